@@ -0,0 +1,132 @@
+//backup_agent:运行在被备份的机器上,把--root允许的目录通过agent协议提供给backup_suite
+//backup_suite的plan使用agent://本机地址:端口/目录 作为source
+//指定--server时向backup_suite注册(第一次运行需要--enroll-token)并定时发送心跳
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use buckyos_backup_lib::*;
+use ::kRPC::kRPC;
+use log::*;
+
+//注册成功后记录backup_suite的地址,之后启动不再注册
+const AGENT_ENROLLED_FILE: &str = "enrolled";
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn get_hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+async fn enroll(client: &kRPC, identity: &AgentIdentity, token: &str, address: &str, tls_dir: &Path, server_url: &str) -> anyhow::Result<()> {
+    let request = identity.create_enroll_request(token, get_hostname().as_str(), env!("CARGO_PKG_VERSION"), address, now_ms());
+    client.call("enroll_agent", serde_json::to_value(&request)?).await
+        .map_err(|e| anyhow::anyhow!("enroll to {} failed: {:?}", server_url, e))?;
+    std::fs::write(tls_dir.join(AGENT_ENROLLED_FILE), server_url)?;
+    info!("enrolled to {} as {}", server_url, identity.did());
+    Ok(())
+}
+
+async fn run_heartbeat(server_url: String, enroll_token: Option<String>, address: String, tls_dir: PathBuf) {
+    let identity = match AgentIdentity::load_or_create(&tls_dir.join(AGENT_IDENTITY_KEY_FILE)) {
+        Ok(identity) => identity,
+        Err(err) => {
+            error!("load agent identity failed: {}", err);
+            return;
+        }
+    };
+    let client = kRPC::new(server_url.as_str(), None);
+    let enrolled = std::fs::read_to_string(tls_dir.join(AGENT_ENROLLED_FILE))
+        .map(|enrolled_url| enrolled_url.trim() == server_url)
+        .unwrap_or(false);
+    if !enrolled {
+        match enroll_token {
+            Some(token) => {
+                if let Err(err) = enroll(&client, &identity, token.as_str(), address.as_str(), &tls_dir, server_url.as_str()).await {
+                    error!("{}", err);
+                    return;
+                }
+            }
+            None => {
+                warn!("agent is not enrolled to {}, run with --enroll-token first", server_url);
+                return;
+            }
+        }
+    }
+
+    loop {
+        let heartbeat = identity.create_heartbeat(env!("CARGO_PKG_VERSION"), address.as_str(), now_ms());
+        match serde_json::to_value(&heartbeat) {
+            Ok(params) => {
+                if let Err(err) = client.call("agent_heartbeat", params).await {
+                    warn!("send heartbeat to {} failed: {:?}", server_url, err);
+                }
+            }
+            Err(err) => warn!("encode heartbeat failed: {}", err),
+        }
+        tokio::time::sleep(Duration::from_secs(AGENT_HEARTBEAT_INTERVAL_SECS)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = clap::Command::new("backup_agent")
+        .about("expose local directories as backup sources for backup_suite")
+        .arg(clap::Arg::new("listen").long("listen").default_value("0.0.0.0:5190")
+            .help("address to listen on"))
+        .arg(clap::Arg::new("root").long("root").required(true).action(clap::ArgAction::Append)
+            .help("directory that backup_suite is allowed to back up and restore to, can be repeated"))
+        .arg(clap::Arg::new("tls_dir").long("tls-dir").required(true)
+            .help("directory with ca.pem, cert.pem and key.pem issued by the backup_suite ca, cert.pem must carry the agent did as subject CN or SAN URI"))
+        .arg(clap::Arg::new("server").long("server")
+            .help("backup_suite control url to enroll and send heartbeats to, e.g. http://192.168.1.2:3000/kapi/backup_control"))
+        .arg(clap::Arg::new("enroll_token").long("enroll-token")
+            .help("one-time token created by backup_suite, required for the first enrollment"))
+        .arg(clap::Arg::new("advertise").long("advertise")
+            .help("host:port that backup_suite uses to connect to this agent, default is <hostname>:<listen port>"))
+        .get_matches();
+
+    simplelog::TermLogger::init(LevelFilter::Info, simplelog::Config::default(),
+        simplelog::TerminalMode::Mixed, simplelog::ColorChoice::Auto).unwrap();
+    let listen_addr = matches.get_one::<String>("listen").unwrap();
+    let allowed_roots: Vec<PathBuf> = matches.get_many::<String>("root").unwrap()
+        .map(|root| std::fs::canonicalize(root).unwrap_or_else(|_| PathBuf::from(root)))
+        .collect();
+    let tls_dir = PathBuf::from(matches.get_one::<String>("tls_dir").unwrap());
+    let did = match AgentIdentity::load_or_create(&tls_dir.join(AGENT_IDENTITY_KEY_FILE)) {
+        Ok(identity) => identity.did(),
+        Err(err) => {
+            error!("load agent identity failed: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = AgentTlsConfig::from_dir(&tls_dir).check_cert_did(did.as_str()) {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+    let tls_config = match AgentTlsConfig::from_dir(&tls_dir).build_server_config() {
+        Ok(tls_config) => tls_config,
+        Err(err) => {
+            error!("load agent tls config from {} failed: {}", tls_dir.to_string_lossy(), err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(server_url) = matches.get_one::<String>("server") {
+        let address = matches.get_one::<String>("advertise").cloned().unwrap_or_else(|| {
+            let listen_port = listen_addr.rsplit(':').next().unwrap_or("5190");
+            format!("{}:{}", get_hostname(), listen_port)
+        });
+        let enroll_token = matches.get_one::<String>("enroll_token").cloned();
+        tokio::spawn(run_heartbeat(server_url.clone(), enroll_token, address, tls_dir.clone()));
+    }
+
+    let server = Arc::new(AgentServer::new(allowed_roots));
+    if let Err(err) = server.run(listen_addr.as_str(), tls_config).await {
+        error!("backup agent exit: {}", err);
+        std::process::exit(1);
+    }
+}
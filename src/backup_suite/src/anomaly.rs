@@ -0,0 +1,198 @@
+#![allow(unused)]
+//勒索软件检测:勒索软件加密文件后,下一次备份会看到大量文件被修改/删除,且新chunk的内容接近随机数据
+//每个plan维护一个基线(历次备份的滑动平均),本次备份严重偏离基线时报警或等待用户确认
+
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use buckyos_backup_lib::*;
+
+pub const ANOMALY_MIN_BASELINE_RUNS: u32 = 3; //基线至少积累这么多次备份才开始检测
+const BASELINE_WEIGHT: f64 = 0.3; //新一次备份在基线中的权重
+const MIN_ENTROPY_SAMPLES: u64 = 10;
+const ENCRYPTED_ENTROPY: f64 = 7.8; //bits/byte,压缩/加密数据接近8
+const NORMAL_ENTROPY: f64 = 7.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyAction {
+    Disabled,
+    Alert,//只记录告警日志,checkpoint正常完成
+    Confirm,//checkpoint进入WaitConfirm,用户确认后才变成Done
+}
+
+impl Default for AnomalyAction {
+    fn default() -> Self {
+        AnomalyAction::Alert
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupRunStat {
+    pub item_count: u64,
+    pub changed_count: u64,
+    pub deleted_count: u64,
+    pub new_chunk_entropy: f64,//被修改的item的平均熵
+    pub entropy_sample_count: u64,
+}
+
+impl BackupRunStat {
+    //和上一个checkpoint的item列表比较
+    pub fn build(prev_items: &Vec<BackupItem>, items: &Vec<BackupItem>, item_entropy: &HashMap<String, f64>) -> Self {
+        let prev_chunks: HashMap<&str, Option<&String>> = prev_items.iter()
+            .map(|item| (item.item_id.as_str(), item.chunk_id.as_ref()))
+            .collect();
+        let mut stat = BackupRunStat {
+            item_count: items.len() as u64,
+            ..Default::default()
+        };
+        let mut entropy_sum = 0.0;
+        let mut current_ids = HashMap::new();
+        for item in items.iter() {
+            current_ids.insert(item.item_id.as_str(), ());
+            let is_changed = match prev_chunks.get(item.item_id.as_str()) {
+                Some(prev_chunk_id) => *prev_chunk_id != item.chunk_id.as_ref(),
+                None => true,
+            };
+            if !is_changed {
+                continue;
+            }
+            stat.changed_count += 1;
+            if let Some(entropy) = item_entropy.get(&item.item_id) {
+                entropy_sum += entropy;
+                stat.entropy_sample_count += 1;
+            }
+        }
+        stat.deleted_count = prev_items.iter()
+            .filter(|item| !current_ids.contains_key(item.item_id.as_str()))
+            .count() as u64;
+        if stat.entropy_sample_count > 0 {
+            stat.new_chunk_entropy = entropy_sum / stat.entropy_sample_count as f64;
+        }
+        stat
+    }
+
+    pub fn changed_rate(&self) -> f64 {
+        if self.item_count == 0 {
+            return 0.0;
+        }
+        self.changed_count as f64 / self.item_count as f64
+    }
+
+    pub fn delete_rate(&self) -> f64 {
+        let total_count = self.item_count + self.deleted_count;
+        if total_count == 0 {
+            return 0.0;
+        }
+        self.deleted_count as f64 / total_count as f64
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlanBaseline {
+    pub run_count: u32,
+    pub changed_rate: f64,
+    pub delete_rate: f64,
+    pub new_chunk_entropy: f64,
+}
+
+impl PlanBaseline {
+    pub fn update(&mut self, stat: &BackupRunStat) {
+        if self.run_count == 0 {
+            self.changed_rate = stat.changed_rate();
+            self.delete_rate = stat.delete_rate();
+            self.new_chunk_entropy = stat.new_chunk_entropy;
+        } else {
+            self.changed_rate = self.changed_rate * (1.0 - BASELINE_WEIGHT) + stat.changed_rate() * BASELINE_WEIGHT;
+            self.delete_rate = self.delete_rate * (1.0 - BASELINE_WEIGHT) + stat.delete_rate() * BASELINE_WEIGHT;
+            if stat.entropy_sample_count > 0 {
+                self.new_chunk_entropy = self.new_chunk_entropy * (1.0 - BASELINE_WEIGHT) + stat.new_chunk_entropy * BASELINE_WEIGHT;
+            }
+        }
+        self.run_count += 1;
+    }
+
+    //返回异常的原因,为空表示正常
+    pub fn check(&self, stat: &BackupRunStat) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.run_count < ANOMALY_MIN_BASELINE_RUNS {
+            return reasons;
+        }
+
+        let changed_rate = stat.changed_rate();
+        if changed_rate >= 0.5 && changed_rate > self.changed_rate * 4.0 + 0.05 {
+            reasons.push(format!("{:.0}% items changed, baseline {:.0}%", changed_rate * 100.0, self.changed_rate * 100.0));
+        }
+        let delete_rate = stat.delete_rate();
+        if delete_rate >= 0.3 && delete_rate > self.delete_rate * 4.0 + 0.05 {
+            reasons.push(format!("{:.0}% items deleted, baseline {:.0}%", delete_rate * 100.0, self.delete_rate * 100.0));
+        }
+        if stat.entropy_sample_count >= MIN_ENTROPY_SAMPLES
+            && stat.new_chunk_entropy > ENCRYPTED_ENTROPY
+            && self.new_chunk_entropy < NORMAL_ENTROPY {
+            reasons.push(format!("changed items look encrypted, entropy {:.2}, baseline {:.2}", stat.new_chunk_entropy, self.new_chunk_entropy));
+        }
+        reasons
+    }
+}
+
+//香农熵,单位bits/byte
+pub fn calc_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for b in data {
+        counts[*b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts.iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_item(item_id: &str, chunk_id: &str) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, 0);
+        item.chunk_id = Some(chunk_id.to_string());
+        item
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(calc_entropy(&[0u8; 1024]), 0.0);
+        let data: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        assert!((calc_entropy(&data) - 8.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_detect_mass_change() {
+        let prev_items: Vec<BackupItem> = (0..100).map(|i| test_item(&format!("{}.doc", i), &format!("c{}", i))).collect();
+        let mut baseline = PlanBaseline::default();
+        let mut normal_items = prev_items.clone();
+        normal_items[0].chunk_id = Some("changed".to_string());
+        let normal_stat = BackupRunStat::build(&prev_items, &normal_items, &HashMap::new());
+        assert_eq!(normal_stat.changed_count, 1);
+        for _ in 0..ANOMALY_MIN_BASELINE_RUNS {
+            assert!(baseline.check(&normal_stat).is_empty());
+            baseline.update(&normal_stat);
+        }
+
+        let mut item_entropy = HashMap::new();
+        let encrypted_items: Vec<BackupItem> = prev_items.iter().take(90).map(|item| {
+            item_entropy.insert(item.item_id.clone(), 7.95);
+            test_item(&item.item_id, "encrypted")
+        }).collect();
+        let stat = BackupRunStat::build(&prev_items, &encrypted_items, &item_entropy);
+        assert_eq!(stat.changed_count, 90);
+        assert_eq!(stat.deleted_count, 10);
+        let reasons = baseline.check(&stat);
+        assert_eq!(reasons.len(), 2);
+    }
+}
@@ -0,0 +1,159 @@
+#![allow(unused)]
+//双人确认:删除plan/清理checkpoint/移除target这样的破坏性操作先创建一个待批准的操作,
+//必须由另一个凭证在有效期内批准后才执行,避免一个被盗的token就能删掉所有备份
+
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use rusqlite::types::{ToSql, FromSql, ValueRef};
+
+pub const PENDING_OPERATION_EXPIRE_MS: u64 = 24 * 3600 * 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DestructiveOperation {
+    DeletePlan { plan_id: String },
+    PruneCheckpoint { checkpoint_id: String },
+    RemoveTarget { target_url: String },//删除所有使用该target的plan
+}
+
+impl DestructiveOperation {
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingOperationState {
+    Pending,
+    Approved,//已经批准并执行
+    Rejected,
+    Expired,
+}
+
+impl ToSql for PendingOperationState {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        let s = match self {
+            PendingOperationState::Pending => "PENDING",
+            PendingOperationState::Approved => "APPROVED",
+            PendingOperationState::Rejected => "REJECTED",
+            PendingOperationState::Expired => "EXPIRED",
+        };
+        Ok(s.into())
+    }
+}
+
+impl FromSql for PendingOperationState {
+    fn column_result(value: ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value.as_str().map(|s| match s {
+            "PENDING" => PendingOperationState::Pending,
+            "APPROVED" => PendingOperationState::Approved,
+            "REJECTED" => PendingOperationState::Rejected,
+            _ => PendingOperationState::Expired,
+        })
+    }
+}
+
+impl PendingOperationState {
+    pub fn to_string(&self) -> &str {
+        match self {
+            PendingOperationState::Pending => "PENDING",
+            PendingOperationState::Approved => "APPROVED",
+            PendingOperationState::Rejected => "REJECTED",
+            PendingOperationState::Expired => "EXPIRED",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingOperation {
+    pub op_id: String,
+    pub operation: DestructiveOperation,
+    pub requester: String,//发起者凭证的指纹,不保存凭证本身
+    pub approver: Option<String>,
+    pub state: PendingOperationState,
+    pub create_time: u64,
+    pub expire_time: u64,
+}
+
+impl PendingOperation {
+    pub fn new(operation: DestructiveOperation, requester: &str, now_ms: u64) -> Self {
+        Self {
+            op_id: format!("op_{}", uuid::Uuid::new_v4()),
+            operation,
+            requester: requester.to_string(),
+            approver: None,
+            state: PendingOperationState::Pending,
+            create_time: now_ms,
+            expire_time: now_ms + PENDING_OPERATION_EXPIRE_MS,
+        }
+    }
+
+    //批准者必须是和发起者不同的凭证,且在有效期内
+    pub fn approve(&mut self, approver: &str, now_ms: u64) -> Result<(), String> {
+        if self.state != PendingOperationState::Pending {
+            return Err(format!("operation {} is {}", self.op_id, self.state.to_string()));
+        }
+        if now_ms > self.expire_time {
+            self.state = PendingOperationState::Expired;
+            return Err(format!("operation {} is expired", self.op_id));
+        }
+        if approver == self.requester {
+            return Err(format!("operation {} must be approved by another credential", self.op_id));
+        }
+        self.approver = Some(approver.to_string());
+        self.state = PendingOperationState::Approved;
+        Ok(())
+    }
+
+    pub fn reject(&mut self, approver: &str) -> Result<(), String> {
+        if self.state != PendingOperationState::Pending {
+            return Err(format!("operation {} is {}", self.op_id, self.state.to_string()));
+        }
+        self.approver = Some(approver.to_string());
+        self.state = PendingOperationState::Rejected;
+        Ok(())
+    }
+
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "op_id": self.op_id,
+            "operation": self.operation,
+            "requester": self.requester,
+            "approver": self.approver,
+            "state": self.state.to_string(),
+            "create_time": self.create_time,
+            "expire_time": self.expire_time,
+        })
+    }
+}
+
+pub fn credential_fingerprint(token: &str) -> String {
+    let hash = Sha256::digest(token.as_bytes());
+    BASE64.encode(&hash[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_person_approve() {
+        let requester = credential_fingerprint("token_a");
+        let approver = credential_fingerprint("token_b");
+        let operation = DestructiveOperation::DeletePlan { plan_id: "plan_1".to_string() };
+        let mut op = PendingOperation::new(operation, requester.as_str(), 1000);
+
+        assert!(op.approve(requester.as_str(), 2000).is_err());
+        assert_eq!(op.state, PendingOperationState::Pending);
+        op.approve(approver.as_str(), 2000).unwrap();
+        assert_eq!(op.state, PendingOperationState::Approved);
+        assert!(op.approve(approver.as_str(), 3000).is_err());
+
+        let operation = DestructiveOperation::PruneCheckpoint { checkpoint_id: "chk_1".to_string() };
+        let mut op = PendingOperation::new(operation, requester.as_str(), 1000);
+        assert!(op.approve(approver.as_str(), 1000 + PENDING_OPERATION_EXPIRE_MS + 1).is_err());
+        assert_eq!(op.state, PendingOperationState::Expired);
+    }
+}
@@ -0,0 +1,143 @@
+#![allow(unused)]
+//备份产物排除:engine自己的数据目录(task db/快照/缓存/ndn store),各plan的快照目录,
+//以及使用本地目录作为target的plan的target目录,如果在本地source中,备份时自动排除,
+//否则下一次备份会把上一次的备份数据再备份一次,checkpoint越来越大
+//source在这些目录里面(或者就是这些目录)时无法排除,只能给出警告
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use buckyos_backup_lib::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupArtifactKind {
+    EngineData,
+    Snapshot,
+    Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupArtifact {
+    pub kind: BackupArtifactKind,
+    pub path: PathBuf,
+    pub plan_id: Option<String>,//快照和target所属的plan,engine的数据目录为None
+}
+
+impl BackupArtifact {
+    pub fn new(kind: BackupArtifactKind, path: &Path, plan_id: Option<&str>) -> Self {
+        Self {
+            kind,
+            path: normalize_artifact_path(path),
+            plan_id: plan_id.map(|plan_id| plan_id.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceOverlapWarning {
+    pub plan_id: String,
+    pub artifact: BackupArtifact,
+    pub excluded: bool,//产物在source里面,备份时会自动排除;false时source在产物里面,无法排除
+    pub message: String,
+}
+
+//目录还不存在时(比如还没有执行过备份的target)按原样比较
+pub fn normalize_artifact_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+//本地source中属于备份产物的item
+pub struct BackupArtifactFilter {
+    source_root: PathBuf,
+    excluded_paths: Vec<PathBuf>,
+}
+
+impl BackupArtifactFilter {
+    //只保留在source里面的产物,source本身在产物里面时不排除任何item(否则整个source都被排除)
+    pub fn new(source_root: &Path, artifacts: &[BackupArtifact]) -> Self {
+        let source_root = normalize_artifact_path(source_root);
+        let excluded_paths = artifacts.iter()
+            .filter(|artifact| artifact.path.starts_with(&source_root) && artifact.path != source_root)
+            .map(|artifact| artifact.path.clone())
+            .collect();
+        Self { source_root, excluded_paths }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excluded_paths.is_empty()
+    }
+
+    pub fn is_artifact_item(&self, item_id: &str) -> bool {
+        let item_path = self.source_root.join(item_id.trim_start_matches('/'));
+        self.excluded_paths.iter().any(|excluded_path| item_path.starts_with(excluded_path))
+    }
+}
+
+//plan的本地source和备份产物重叠时的警告,plan自己的快照和target也要检查
+pub fn check_source_overlap(plan_id: &str, source_url: &str, artifacts: &[BackupArtifact]) -> Vec<SourceOverlapWarning> {
+    let source_root = match translate_local_path_from_url(source_url) {
+        Ok(source_root) => normalize_artifact_path(&source_root),
+        Err(_) => return Vec::new(),
+    };
+    let mut warnings = Vec::new();
+    for artifact in artifacts.iter() {
+        let excluded = artifact.path.starts_with(&source_root) && artifact.path != source_root;
+        if !excluded && !source_root.starts_with(&artifact.path) {
+            continue;
+        }
+        let owner = match artifact.plan_id.as_ref() {
+            Some(owner_plan) if owner_plan == plan_id => "this plan".to_string(),
+            Some(owner_plan) => format!("plan {}", owner_plan),
+            None => "backup engine".to_string(),
+        };
+        let message = if excluded {
+            format!("{:?} directory {} of {} is inside source {}, it will be excluded from backup",
+                artifact.kind, artifact.path.to_string_lossy(), owner, source_root.to_string_lossy())
+        } else {
+            format!("source {} is inside {:?} directory {} of {}, backup will include backup data",
+                source_root.to_string_lossy(), artifact.kind, artifact.path.to_string_lossy(), owner)
+        };
+        warnings.push(SourceOverlapWarning {
+            plan_id: plan_id.to_string(),
+            artifact: artifact.clone(),
+            excluded,
+            message,
+        });
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_artifact_filter() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let source_dir = test_dir.path().join("home");
+        std::fs::create_dir_all(source_dir.join("backup_target")).unwrap();
+        let source_url = format!("file://{}", source_dir.to_string_lossy());
+        let artifacts = vec![
+            BackupArtifact::new(BackupArtifactKind::EngineData, &test_dir.path().join("data"), None),
+            BackupArtifact::new(BackupArtifactKind::Target, &source_dir.join("backup_target"), Some("plan_b")),
+            BackupArtifact::new(BackupArtifactKind::Snapshot, &source_dir.join(".snapshots"), Some("plan_a")),
+        ];
+        let filter = BackupArtifactFilter::new(&source_dir, &artifacts);
+        assert!(!filter.is_empty());
+        assert!(filter.is_artifact_item("backup_target/chunk_1"));
+        assert!(filter.is_artifact_item("/.snapshots"));
+        assert!(!filter.is_artifact_item("backup_target.txt"));
+        assert!(!filter.is_artifact_item("notes.txt"));
+
+        let warnings = check_source_overlap("plan_a", source_url.as_str(), &artifacts);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|warning| warning.excluded));
+
+        //source在engine的数据目录里面,不能排除
+        let data_source_url = format!("file://{}", test_dir.path().join("data").join("cache").to_string_lossy());
+        let warnings = check_source_overlap("plan_c", data_source_url.as_str(), &artifacts);
+        assert_eq!(warnings.len(), 1);
+        assert!(!warnings[0].excluded);
+        assert!(BackupArtifactFilter::new(&test_dir.path().join("data").join("cache"), &artifacts).is_empty());
+        assert!(check_source_overlap("plan_d", "agent://pc1/home", &artifacts).is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+#![allow(unused)]
+//审计日志:记录需要追溯"谁在什么时候做了什么"的操作(比如法律保留的设置和解除),只追加不修改
+//operator是发起者凭证的指纹,不保存凭证本身
+use serde_json::{Value, json};
+
+pub const AUDIT_LEGAL_HOLD_PLACE: &str = "legal_hold.place";
+pub const AUDIT_LEGAL_HOLD_RELEASE: &str = "legal_hold.release";
+pub const AUDIT_LEGAL_HOLD_DENIED: &str = "legal_hold.denied";//没有权限的设置/解除请求
+pub const AUDIT_CHUNK_KEY_EXPORT: &str = "chunk_key.export";//导出target的chunk命名密钥
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub operator: String,
+    pub action: String,
+    pub object_id: String,
+    pub detail: String,
+}
+
+impl AuditRecord {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "seq": self.seq,
+            "timestamp": self.timestamp,
+            "operator": self.operator,
+            "action": self.action,
+            "object_id": self.object_id,
+            "detail": self.detail,
+        })
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(unused)]
+//checkpoint的读锁和删除锁,engine中所有恢复和删除操作共享
+//恢复(restore task/单个文件恢复/直接下载)读取checkpoint期间持有读锁,增量checkpoint依赖的整条链都会被锁住
+//prune/删除plan持有删除锁,和读锁互斥:被读取的checkpoint不能删除,正在删除的checkpoint不能开始恢复
+//两边都不等待,拿不到锁时直接返回错误
+//锁只在内存中,engine重启后没有结束的restore task由engine查task db判断
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+
+#[derive(Default)]
+struct CheckpointLockState {
+    readers: HashMap<String, usize>,//checkpoint_id -> 读取者数量
+    deleting: HashSet<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct CheckpointLockManager {
+    state: Arc<Mutex<CheckpointLockState>>,
+}
+
+//drop时释放读锁
+pub struct CheckpointReadGuard {
+    checkpoint_ids: Vec<String>,
+    state: Arc<Mutex<CheckpointLockState>>,
+}
+
+//drop时释放删除锁
+pub struct CheckpointDeleteGuard {
+    checkpoint_ids: Vec<String>,
+    state: Arc<Mutex<CheckpointLockState>>,
+}
+
+impl CheckpointLockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn try_lock_read(&self, checkpoint_ids: Vec<String>) -> Result<CheckpointReadGuard> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(checkpoint_id) = checkpoint_ids.iter().find(|id| state.deleting.contains(*id)) {
+            return Err(anyhow::anyhow!("checkpoint {} is being deleted", checkpoint_id));
+        }
+        for checkpoint_id in checkpoint_ids.iter() {
+            *state.readers.entry(checkpoint_id.clone()).or_insert(0) += 1;
+        }
+        Ok(CheckpointReadGuard {
+            checkpoint_ids,
+            state: self.state.clone(),
+        })
+    }
+
+    pub fn try_lock_delete(&self, checkpoint_ids: Vec<String>) -> Result<CheckpointDeleteGuard> {
+        let mut state = self.state.lock().unwrap();
+        for checkpoint_id in checkpoint_ids.iter() {
+            if let Some(reader_count) = state.readers.get(checkpoint_id) {
+                return Err(anyhow::anyhow!("checkpoint {} is being read by {} restores, try later", checkpoint_id, reader_count));
+            }
+            if state.deleting.contains(checkpoint_id) {
+                return Err(anyhow::anyhow!("checkpoint {} is being deleted", checkpoint_id));
+            }
+        }
+        state.deleting.extend(checkpoint_ids.iter().cloned());
+        Ok(CheckpointDeleteGuard {
+            checkpoint_ids,
+            state: self.state.clone(),
+        })
+    }
+
+    pub fn get_reader_count(&self, checkpoint_id: &str) -> usize {
+        self.state.lock().unwrap().readers.get(checkpoint_id).cloned().unwrap_or(0)
+    }
+}
+
+impl Drop for CheckpointReadGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        for checkpoint_id in self.checkpoint_ids.iter() {
+            if let Some(reader_count) = state.readers.get_mut(checkpoint_id) {
+                *reader_count -= 1;
+                if *reader_count == 0 {
+                    state.readers.remove(checkpoint_id);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CheckpointDeleteGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        for checkpoint_id in self.checkpoint_ids.iter() {
+            state.deleting.remove(checkpoint_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_read_delete_lock() {
+        let manager = CheckpointLockManager::new();
+        let chain = vec!["chk_3".to_string(), "chk_2".to_string(), "chk_1".to_string()];
+        let read_guard = manager.try_lock_read(chain.clone()).unwrap();
+        let read_guard2 = manager.try_lock_read(vec!["chk_1".to_string()]).unwrap();
+        assert_eq!(manager.get_reader_count("chk_1"), 2);
+        //链上任何一个checkpoint被读取时都不能删除
+        assert!(manager.try_lock_delete(vec!["chk_2".to_string()]).is_err());
+        assert!(manager.try_lock_delete(vec!["chk_4".to_string()]).is_ok());
+
+        drop(read_guard);
+        assert_eq!(manager.get_reader_count("chk_2"), 0);
+        assert!(manager.try_lock_delete(vec!["chk_1".to_string()]).is_err());
+        drop(read_guard2);
+
+        let delete_guard = manager.try_lock_delete(vec!["chk_1".to_string()]).unwrap();
+        assert!(manager.try_lock_read(chain.clone()).is_err());
+        assert!(manager.try_lock_delete(vec!["chk_1".to_string()]).is_err());
+        drop(delete_guard);
+        assert!(manager.try_lock_read(chain).is_ok());
+    }
+}
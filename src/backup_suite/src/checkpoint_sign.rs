@@ -0,0 +1,208 @@
+#![allow(unused)]
+//checkpoint完成时用node的私钥对checkpoint的manifest(item列表hash + merkle root)签名,
+//恢复前重新计算manifest并验证签名,避免被篡改的target/item列表被悄悄的恢复出来
+
+use std::path::PathBuf;
+use anyhow::Result;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use buckyos_backup_lib::*;
+use buckyos_kit::get_buckyos_system_etc_dir;
+use log::*;
+
+use crate::task_db::BackupCheckPoint;
+
+pub const NODE_PRIVATE_KEY_FILE: &str = "node_private_key.pem";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckPointManifest {
+    pub checkpoint_id: String,
+    pub owner_plan: String,
+    pub checkpoint_index: u64,
+    pub item_count: u64,
+    pub total_size: u64,
+    pub item_list_hash: String,
+    pub merkle_root: String,
+}
+
+impl CheckPointManifest {
+    pub fn build(checkpoint: &BackupCheckPoint, items: &Vec<BackupItem>) -> Self {
+        let mut leaves: Vec<(String, [u8; 32])> = items.iter()
+            .map(|item| (item.item_id.clone(), calc_item_leaf_hash(item)))
+            .collect();
+        leaves.sort_by(|a, b| a.0.cmp(&b.0));
+        let leaves: Vec<[u8; 32]> = leaves.into_iter().map(|(_, hash)| hash).collect();
+
+        let mut hasher = Sha256::new();
+        for leaf in leaves.iter() {
+            hasher.update(leaf);
+        }
+        let item_list_hash: [u8; 32] = hasher.finalize().into();
+
+        Self {
+            checkpoint_id: checkpoint.checkpoint_id.clone(),
+            owner_plan: checkpoint.owner_plan.clone(),
+            checkpoint_index: checkpoint.checkpoint_index,
+            item_count: items.len() as u64,
+            total_size: items.iter().map(|item| item.size).sum(),
+            item_list_hash: BASE64.encode(item_list_hash),
+            merkle_root: BASE64.encode(calc_merkle_root(leaves)),
+        }
+    }
+
+    fn to_sign_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+//保存在checkpoints.signature中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckPointSignature {
+    pub manifest: CheckPointManifest,
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl CheckPointSignature {
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json_str(s: &str) -> Result<Self> {
+        serde_json::from_str(s).map_err(|e| anyhow::anyhow!("invalid checkpoint signature: {}", e))
+    }
+}
+
+fn calc_item_leaf_hash(item: &BackupItem) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(item.item_id.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(item.chunk_id.as_deref().unwrap_or("").as_bytes());
+    hasher.update(b"\n");
+    hasher.update(item.size.to_le_bytes());
+    hasher.update(b"\n");
+    hasher.update(item.pack_info.as_deref().unwrap_or("").as_bytes());
+    hasher.update(b"\n");
+    hasher.update(item.file_meta.as_deref().unwrap_or("").as_bytes());
+    hasher.finalize().into()
+}
+
+fn calc_merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return Sha256::digest(b"").into();
+    }
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| {
+            let mut hasher = Sha256::new();
+            hasher.update(pair[0]);
+            //奇数个节点时最后一个和自己配对
+            hasher.update(pair.get(1).unwrap_or(&pair[0]));
+            hasher.finalize().into()
+        }).collect();
+    }
+    level[0]
+}
+
+pub struct CheckPointSigner {
+    signing_key: SigningKey,
+}
+
+impl CheckPointSigner {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self { signing_key }
+    }
+
+    //node还没有激活(没有私钥)时返回None,此时checkpoint不签名
+    pub fn load_node_signer() -> Option<Self> {
+        let key_path: PathBuf = get_buckyos_system_etc_dir().join(NODE_PRIVATE_KEY_FILE);
+        let pem = std::fs::read_to_string(&key_path);
+        if pem.is_err() {
+            debug!("node private key {} not found, checkpoint will not be signed", key_path.to_string_lossy());
+            return None;
+        }
+        match SigningKey::from_pkcs8_pem(pem.unwrap().as_str()) {
+            std::result::Result::Ok(signing_key) => Some(Self::new(signing_key)),
+            Err(e) => {
+                warn!("load node private key {} failed: {}", key_path.to_string_lossy(), e);
+                None
+            }
+        }
+    }
+
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, manifest: CheckPointManifest) -> CheckPointSignature {
+        let signature = self.signing_key.sign(&manifest.to_sign_bytes());
+        CheckPointSignature {
+            manifest,
+            public_key: self.public_key(),
+            signature: BASE64.encode(signature.to_bytes()),
+        }
+    }
+}
+
+//expected_public_key为None时只校验签名本身(比如在新机器上恢复,本机没有原node的私钥)
+pub fn verify_checkpoint_signature(signature: &CheckPointSignature, manifest: &CheckPointManifest, expected_public_key: Option<&str>) -> Result<()> {
+    if let Some(expected_public_key) = expected_public_key {
+        if signature.public_key != expected_public_key {
+            return Err(anyhow::anyhow!("checkpoint {} is signed by unknown key {}", manifest.checkpoint_id, signature.public_key));
+        }
+    }
+    if signature.manifest != *manifest {
+        return Err(anyhow::anyhow!("checkpoint {} manifest mismatch, item list is modified", manifest.checkpoint_id));
+    }
+
+    let public_key: [u8; 32] = BASE64.decode(signature.public_key.as_bytes())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid public key length"))?;
+    let verifying_key = VerifyingKey::from_bytes(&public_key)?;
+    let signature_bytes: [u8; 64] = BASE64.decode(signature.signature.as_bytes())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid signature length"))?;
+    verifying_key.verify(&manifest.to_sign_bytes(), &Signature::from_bytes(&signature_bytes))
+        .map_err(|e| anyhow::anyhow!("checkpoint {} signature verify failed: {}", manifest.checkpoint_id, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_item(item_id: &str, chunk_id: &str, size: u64) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, size);
+        item.chunk_id = Some(chunk_id.to_string());
+        item
+    }
+
+    #[test]
+    fn test_sign_and_verify_checkpoint() {
+        let checkpoint = BackupCheckPoint::new("test_plan", None, 1);
+        let mut items = vec![
+            test_item("a.txt", "sha256:01", 10),
+            test_item("b.txt", "sha256:02", 20),
+            test_item("c.txt", "sha256:03", 30),
+        ];
+        let signer = CheckPointSigner::new(SigningKey::from_bytes(&[7u8; 32]));
+        let manifest = CheckPointManifest::build(&checkpoint, &items);
+        let signature = signer.sign(manifest.clone());
+        let signature = CheckPointSignature::from_json_str(&signature.to_json_string()).unwrap();
+        verify_checkpoint_signature(&signature, &manifest, Some(signer.public_key().as_str())).unwrap();
+
+        //item顺序不影响manifest
+        items.reverse();
+        assert_eq!(CheckPointManifest::build(&checkpoint, &items), manifest);
+
+        items[0].chunk_id = Some("sha256:04".to_string());
+        let modified_manifest = CheckPointManifest::build(&checkpoint, &items);
+        assert!(verify_checkpoint_signature(&signature, &modified_manifest, None).is_err());
+
+        let other_signer = CheckPointSigner::new(SigningKey::from_bytes(&[8u8; 32]));
+        assert!(verify_checkpoint_signature(&signature, &manifest, Some(other_signer.public_key().as_str())).is_err());
+    }
+}
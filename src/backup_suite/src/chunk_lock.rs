@@ -0,0 +1,100 @@
+#![allow(unused)]
+//同一个target上chunk的写入锁,按target_url+chunk_id加锁,engine中所有task共享
+//多个plan备份到同一个target时,相同内容的chunk同一时间只有一个task在写入,
+//其它task等待写入结束后再open_chunk_writer,这时target返回AlreadyDone,item直接完成(和已经存在的chunk一样去重)
+//而不是两个task同时写入同一个chunk,其中一个因为target返回"正在上传"而出错
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+type ChunkLockMap = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+#[derive(Clone, Default)]
+pub struct ChunkLockManager {
+    locks: ChunkLockMap,
+}
+
+//drop时释放锁,没有其它task等待时从map中删除
+pub struct ChunkLockGuard {
+    key: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: ChunkLockMap,
+}
+
+impl ChunkLockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get_lock_key(target_url: &str, chunk_id: &str) -> String {
+        format!("{}#{}", target_url, chunk_id)
+    }
+
+    //返回的bool表示是否等待过其它task释放这个chunk
+    pub async fn lock_chunk(&self, target_url: &str, chunk_id: &str) -> (ChunkLockGuard, bool) {
+        let key = Self::get_lock_key(target_url, chunk_id);
+        let lock = self.locks.lock().unwrap().entry(key.clone()).or_default().clone();
+        let (guard, is_waited) = match lock.clone().try_lock_owned() {
+            Ok(guard) => (guard, false),
+            Err(_) => (lock.clone().lock_owned().await, true),
+        };
+        let chunk_guard = ChunkLockGuard {
+            key,
+            lock,
+            guard: Some(guard),
+            locks: self.locks.clone(),
+        };
+        (chunk_guard, is_waited)
+    }
+
+    pub fn is_chunk_locked(&self, target_url: &str, chunk_id: &str) -> bool {
+        let key = Self::get_lock_key(target_url, chunk_id);
+        self.locks.lock().unwrap().get(&key).map_or(false, |lock| lock.try_lock().is_err())
+    }
+
+    pub fn locked_count(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+}
+
+impl Drop for ChunkLockGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.lock().unwrap();
+        //map和这个guard各持有一个引用,等待中的task也会持有引用
+        if Arc::strong_count(&self.lock) <= 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_chunk_lock_wait_and_release() {
+        let manager = ChunkLockManager::new();
+        let (guard, is_waited) = manager.lock_chunk("s3://bucket", "sha256:01").await;
+        assert!(!is_waited);
+        assert!(manager.is_chunk_locked("s3://bucket", "sha256:01"));
+        //不同target或者不同chunk互不影响
+        let (other_guard, is_waited) = manager.lock_chunk("file:///backup", "sha256:01").await;
+        assert!(!is_waited);
+        drop(other_guard);
+
+        let manager2 = manager.clone();
+        let waiter = tokio::spawn(async move {
+            let (_guard, is_waited) = manager2.lock_chunk("s3://bucket", "sha256:01").await;
+            is_waited
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        drop(guard);
+        assert!(waiter.await.unwrap());
+        assert_eq!(manager.locked_count(), 0);
+        assert!(!manager.is_chunk_locked("s3://bucket", "sha256:01"));
+    }
+}
@@ -0,0 +1,176 @@
+#![allow(unused)]
+//engine级别的小文件内容cache,多次备份之间复用没有变化的小文件内容,减少重复读盘
+//按字节数限制总大小,超过时淘汰最久没有访问的内容;读取时校验文件大小和修改时间,source文件变化后cache失效
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+
+pub const DEFAULT_SMALL_FILE_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentCacheStat {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,//source文件变化导致的失效
+    pub entry_count: u64,
+    pub total_size: u64,
+    pub max_size: u64,
+}
+
+impl ContentCacheStat {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+struct ContentCacheEntry {
+    content: Vec<u8>,
+    size: u64,
+    last_modify_time: u64,
+    access_seq: u64,
+}
+
+pub struct SmallFileContentCache {
+    entries: HashMap<String, ContentCacheEntry>,
+    lru: BTreeMap<u64, String>,//access_seq -> key,最小的最先淘汰
+    next_seq: u64,
+    stat: ContentCacheStat,
+}
+
+impl SmallFileContentCache {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_seq: 0,
+            stat: ContentCacheStat { max_size, ..Default::default() },
+        }
+    }
+
+    //plan之间的item_id可能相同,key包含plan_id
+    pub fn cache_key(plan_id: &str, item_id: &str) -> String {
+        format!("{}:{}", plan_id, item_id)
+    }
+
+    fn touch(&mut self, key: &str) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.access_seq);
+            entry.access_seq = seq;
+            self.lru.insert(seq, key.to_string());
+        }
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<ContentCacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.access_seq);
+        self.stat.total_size -= entry.content.len() as u64;
+        self.stat.entry_count -= 1;
+        Some(entry)
+    }
+
+    //size或修改时间和cache时不一致时删除并返回None
+    pub fn get(&mut self, key: &str, size: u64, last_modify_time: u64) -> Option<Vec<u8>> {
+        let is_valid = match self.entries.get(key) {
+            Some(entry) => entry.size == size && entry.last_modify_time == last_modify_time,
+            None => {
+                self.stat.misses += 1;
+                return None;
+            }
+        };
+        if !is_valid {
+            self.remove_entry(key);
+            self.stat.invalidations += 1;
+            self.stat.misses += 1;
+            return None;
+        }
+        self.touch(key);
+        self.stat.hits += 1;
+        self.entries.get(key).map(|entry| entry.content.clone())
+    }
+
+    pub fn put(&mut self, key: &str, size: u64, last_modify_time: u64, content: Vec<u8>) {
+        let content_size = content.len() as u64;
+        self.remove_entry(key);
+        if content_size > self.stat.max_size {
+            return;
+        }
+        while self.stat.total_size + content_size > self.stat.max_size {
+            let oldest_key = match self.lru.values().next() {
+                Some(oldest_key) => oldest_key.clone(),
+                None => break,
+            };
+            self.remove_entry(oldest_key.as_str());
+            self.stat.evictions += 1;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries.insert(key.to_string(), ContentCacheEntry { content, size, last_modify_time, access_seq: seq });
+        self.lru.insert(seq, key.to_string());
+        self.stat.total_size += content_size;
+        self.stat.entry_count += 1;
+    }
+
+    //备份时发现source文件已经变化
+    pub fn invalidate(&mut self, key: &str) {
+        if self.remove_entry(key).is_some() {
+            self.stat.invalidations += 1;
+        }
+    }
+
+    //删除plan时清除这个plan的所有内容
+    pub fn invalidate_plan(&mut self, plan_id: &str) {
+        let prefix = format!("{}:", plan_id);
+        let keys: Vec<String> = self.entries.keys().filter(|key| key.starts_with(prefix.as_str())).cloned().collect();
+        for key in keys {
+            self.remove_entry(key.as_str());
+        }
+    }
+
+    pub fn get_stat(&self) -> ContentCacheStat {
+        self.stat.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_file_content_cache() {
+        let mut cache = SmallFileContentCache::new(100);
+        let key_a = SmallFileContentCache::cache_key("plan_1", "a.txt");
+        let key_b = SmallFileContentCache::cache_key("plan_1", "b.txt");
+        let key_c = SmallFileContentCache::cache_key("plan_2", "a.txt");
+        assert!(cache.get(key_a.as_str(), 40, 1000).is_none());
+        cache.put(key_a.as_str(), 40, 1000, vec![1u8; 40]);
+        cache.put(key_b.as_str(), 40, 1000, vec![2u8; 40]);
+        assert_eq!(cache.get(key_a.as_str(), 40, 1000), Some(vec![1u8; 40]));
+
+        //超过上限时淘汰最久没有访问的b
+        cache.put(key_c.as_str(), 40, 1000, vec![3u8; 40]);
+        assert!(cache.get(key_b.as_str(), 40, 1000).is_none());
+        assert_eq!(cache.get(key_c.as_str(), 40, 1000), Some(vec![3u8; 40]));
+        let stat = cache.get_stat();
+        assert_eq!((stat.entry_count, stat.total_size, stat.evictions), (2, 80, 1));
+
+        //修改时间变化后失效
+        assert!(cache.get(key_a.as_str(), 40, 2000).is_none());
+        assert!(cache.get(key_a.as_str(), 40, 1000).is_none());
+        cache.put(key_a.as_str(), 40, 2000, vec![4u8; 40]);
+        cache.invalidate_plan("plan_2");
+        assert!(cache.get(key_c.as_str(), 40, 1000).is_none());
+        cache.put(key_b.as_str(), 200, 1000, vec![5u8; 200]);
+
+        let stat = cache.get_stat();
+        assert_eq!((stat.entry_count, stat.total_size, stat.invalidations), (1, 40, 1));
+        assert_eq!((stat.hits, stat.misses), (2, 5));
+        assert!((stat.hit_rate() - 2.0 / 7.0).abs() < 1e-9);
+    }
+}
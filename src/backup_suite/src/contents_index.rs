@@ -0,0 +1,180 @@
+#![allow(unused)]
+//checkpoint内容索引:prepare时把每个item的扩展名,大小分档和修改时间写入contents_index表,
+//恢复时按"扩展名/大小/修改时间/路径前缀"直接在db中查询,不需要客户端扫描所有item
+//目录不进入索引;索引建立之前创建的checkpoint在第一次查询时从backup_items补建
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use buckyos_backup_lib::*;
+
+//选择性恢复的查询条件保存在RestoreConfig.params的这个字段中
+pub const CONTENTS_QUERY_PARAM: &str = "contents_query";
+pub const DEFAULT_CONTENTS_QUERY_LIMIT: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentsIndexEntry {
+    pub item_id: String,
+    pub item_type: BackupItemType,
+    pub extension: String,//小写,不含'.',没有扩展名时为空
+    pub size: u64,
+    pub size_bucket: u32,
+    pub mtime: u64,//同BackupItem::last_modify_time,秒
+}
+
+impl ContentsIndexEntry {
+    //目录不进入索引
+    pub fn from_item(item: &BackupItem) -> Option<Self> {
+        if item.item_type == BackupItemType::Directory {
+            return None;
+        }
+        Some(Self {
+            item_id: item.item_id.clone(),
+            item_type: item.item_type.clone(),
+            extension: get_item_extension(item.item_id.as_str()),
+            size: item.size,
+            size_bucket: get_size_bucket(item.size),
+            mtime: item.last_modify_time,
+        })
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "item_id": self.item_id,
+            "item_type": self.item_type,
+            "extension": self.extension,
+            "size": self.size,
+            "mtime": self.mtime,
+        })
+    }
+}
+
+//最后一段路径中最后一个'.'之后的部分;".bashrc"这样以'.'开头的文件没有扩展名
+pub fn get_item_extension(item_id: &str) -> String {
+    let file_name = item_id.rsplit('/').next().unwrap_or(item_id);
+    match file_name.rfind('.') {
+        Some(pos) if pos > 0 && pos + 1 < file_name.len() => file_name[pos + 1..].to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+//0字节为0档,其它大小在[2^(n-1), 2^n)的为n档
+pub fn get_size_bucket(size: u64) -> u32 {
+    64 - size.leading_zeros()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentsQuery {
+    pub extensions: Vec<String>,//"docx",".docx","*.docx"都可以,不区分大小写
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<u64>,//包含
+    pub modified_before: Option<u64>,//不包含
+    pub path_prefix: Option<String>,//只匹配这个目录下的item
+    pub limit: Option<u64>,//只对查询接口有效,选择性恢复时恢复所有匹配的item
+}
+
+impl ContentsQuery {
+    pub fn get_extensions(&self) -> Vec<String> {
+        let mut extensions: Vec<String> = self.extensions.iter()
+            .map(|ext| ext.trim().trim_start_matches('*').trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        extensions.sort();
+        extensions.dedup();
+        extensions
+    }
+
+    //目录前缀统一成"dir/"的形式,和item_id一样不以'/'开头
+    pub fn get_path_prefix(&self) -> Option<String> {
+        self.path_prefix.as_ref()
+            .map(|prefix| prefix.trim_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| format!("{}/", prefix))
+    }
+
+    pub fn matches(&self, entry: &ContentsIndexEntry) -> bool {
+        let extensions = self.get_extensions();
+        if !extensions.is_empty() && !extensions.contains(&entry.extension) {
+            return false;
+        }
+        if self.min_size.map_or(false, |min_size| entry.size < min_size)
+            || self.max_size.map_or(false, |max_size| entry.size > max_size) {
+            return false;
+        }
+        if self.modified_after.map_or(false, |after| entry.mtime < after)
+            || self.modified_before.map_or(false, |before| entry.mtime >= before) {
+            return false;
+        }
+        match self.get_path_prefix() {
+            Some(prefix) => entry.item_id.starts_with(prefix.as_str()),
+            None => true,
+        }
+    }
+
+    pub fn from_restore_config(restore_config: &RestoreConfig) -> anyhow::Result<Option<Self>> {
+        let query = restore_config.params.as_ref().and_then(|params| params.get(CONTENTS_QUERY_PARAM));
+        match query {
+            Some(query) => Ok(Some(serde_json::from_value(query.clone())
+                .map_err(|e| anyhow::anyhow!("invalid contents query: {}", e))?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_to_restore_config(&self, restore_config: &mut RestoreConfig) {
+        let mut params = restore_config.params.take()
+            .filter(|params| params.is_object())
+            .unwrap_or_else(|| json!({}));
+        params[CONTENTS_QUERY_PARAM] = serde_json::to_value(self).unwrap();
+        restore_config.params = Some(params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contents_query() {
+        assert_eq!(get_item_extension("docs/report.DOCX"), "docx");
+        assert_eq!(get_item_extension("docs.v2/readme"), "");
+        assert_eq!(get_item_extension("home/.bashrc"), "");
+        assert_eq!(get_item_extension("archive.tar.gz"), "gz");
+        assert_eq!(get_size_bucket(0), 0);
+        assert_eq!(get_size_bucket(1), 1);
+        assert_eq!(get_size_bucket(1023), 10);
+        assert_eq!(get_size_bucket(1024), 11);
+
+        let entry = ContentsIndexEntry {
+            item_id: "docs/2024/plan.docx".to_string(),
+            item_type: BackupItemType::File,
+            extension: "docx".to_string(),
+            size: 4096,
+            size_bucket: get_size_bucket(4096),
+            mtime: 1_709_251_200,//2024-03-01
+        };
+        let query = ContentsQuery {
+            extensions: vec!["*.DOCX".to_string(), ".xlsx".to_string()],
+            modified_after: Some(1_709_251_200),
+            modified_before: Some(1_711_929_600),//2024-04-01
+            path_prefix: Some("/docs/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query.get_extensions(), vec!["docx".to_string(), "xlsx".to_string()]);
+        assert!(query.matches(&entry));
+        assert!(!ContentsQuery { min_size: Some(8192), ..query.clone() }.matches(&entry));
+        assert!(!ContentsQuery { path_prefix: Some("doc".to_string()), ..query.clone() }.matches(&entry));
+        assert!(!ContentsQuery { modified_before: Some(1_709_251_200), ..query.clone() }.matches(&entry));
+
+        let mut restore_config = RestoreConfig {
+            restore_location_url: "file:///restore".to_string(),
+            is_clean_restore: false,
+            name_collision_policy: NameCollisionPolicy::default(),
+            conflict_policy: None,
+            restore_in_place: false,
+            params: None,
+        };
+        assert!(ContentsQuery::from_restore_config(&restore_config).unwrap().is_none());
+        query.set_to_restore_config(&mut restore_config);
+        assert_eq!(ContentsQuery::from_restore_config(&restore_config).unwrap(), Some(query));
+    }
+}
@@ -0,0 +1,149 @@
+#![allow(unused)]
+//target的密钥加密后保存在task_db中,plan/target url里只保存credential_id
+//加密key保存在service data目录下单独的文件中(只有owner可读),db文件泄露不会泄露密钥
+
+use std::io::Write;
+use std::path::PathBuf;
+use anyhow::Result;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Sha256, Digest};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use buckyos_backup_lib::*;
+use log::*;
+
+use crate::task_db::BackupTaskDb;
+
+pub const CREDENTIAL_VAULT_KEY_FILE: &str = "credential_vault.key";
+
+pub struct CredentialVault {
+    task_db: BackupTaskDb,
+    key_path: PathBuf,
+}
+
+impl CredentialVault {
+    pub fn new(task_db: BackupTaskDb, key_path: PathBuf) -> Self {
+        Self { task_db, key_path }
+    }
+
+    fn load_or_create_key(&self) -> Result<Key> {
+        if let std::result::Result::Ok(key_str) = std::fs::read_to_string(&self.key_path) {
+            let key_bytes = BASE64.decode(key_str.trim().as_bytes())?;
+            if key_bytes.len() != 32 {
+                return Err(anyhow::anyhow!("invalid credential vault key {}", self.key_path.to_string_lossy()));
+            }
+            return Ok(*Key::from_slice(&key_bytes));
+        }
+
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        if let Some(parent) = self.key_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        //创建时就只有owner可读,不能先用默认权限写入再修改权限
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut key_file = options.open(&self.key_path)?;
+        key_file.write_all(BASE64.encode(key.as_slice()).as_bytes())?;
+        key_file.sync_all()?;
+        info!("create credential vault key {}", self.key_path.to_string_lossy());
+        Ok(key)
+    }
+
+    //同一个target(去掉密钥后的url)只有一个credential_id,重复保存相当于更新密钥,
+    //这样plan的target url(plan_key)是稳定的
+    pub fn get_credential_id(target_url: &str) -> String {
+        let hash = Sha256::digest(target_url.as_bytes());
+        format!("cred_{}", URL_SAFE_NO_PAD.encode(&hash[..12]))
+    }
+
+    //返回credential_id
+    pub fn store_credential(&self, target_url: &str, credential: &TargetCredential) -> Result<String> {
+        let cipher = ChaCha20Poly1305::new(&self.load_or_create_key()?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plain = serde_json::to_vec(&credential.secrets)?;
+        let encrypted = cipher.encrypt(&nonce, plain.as_slice())
+            .map_err(|e| anyhow::anyhow!("encrypt credential failed: {}", e))?;
+
+        let credential_id = Self::get_credential_id(target_url);
+        self.task_db.save_credential(credential_id.as_str(), credential.kind.as_str(),
+            BASE64.encode(nonce.as_slice()).as_str(), BASE64.encode(encrypted).as_str())?;
+        info!("store {} credential {}", credential.kind, credential_id);
+        Ok(credential_id)
+    }
+
+    pub fn remove_credential(&self, credential_id: &str) -> Result<()> {
+        self.task_db.delete_credential(credential_id)?;
+        Ok(())
+    }
+
+    fn load_credential(&self, credential_id: &str) -> Result<TargetCredential> {
+        let record = self.task_db.load_credential(credential_id)?;
+        if record.is_none() {
+            return Err(anyhow::anyhow!("credential {} not found", credential_id));
+        }
+        let (kind, nonce, encrypted) = record.unwrap();
+        let nonce = BASE64.decode(nonce.as_bytes())?;
+        if nonce.len() != 12 {
+            return Err(anyhow::anyhow!("credential {} has invalid nonce", credential_id));
+        }
+        let encrypted = BASE64.decode(encrypted.as_bytes())?;
+        let cipher = ChaCha20Poly1305::new(&self.load_or_create_key()?);
+        let plain = cipher.decrypt(Nonce::from_slice(&nonce), encrypted.as_slice())
+            .map_err(|e| anyhow::anyhow!("decrypt credential {} failed: {}", credential_id, e))?;
+        Ok(TargetCredential {
+            kind,
+            secrets: serde_json::from_slice(&plain)?,
+        })
+    }
+}
+
+impl ICredentialResolver for CredentialVault {
+    fn resolve_credential(&self, credential_id: &str) -> BackupResult<TargetCredential> {
+        self.load_credential(credential_id).map_err(BuckyBackupError::from_anyhow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_store_and_resolve_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        let task_db = BackupTaskDb::new(dir.path().join("test.db").to_str().unwrap());
+        let vault = CredentialVault::new(task_db.clone(), dir.path().join(CREDENTIAL_VAULT_KEY_FILE));
+
+        let mut secrets = HashMap::new();
+        secrets.insert("access_key".to_string(), "ak".to_string());
+        secrets.insert("secret_key".to_string(), "sk".to_string());
+        let credential = TargetCredential { kind: "s3".to_string(), secrets };
+        let credential_id = vault.store_credential("s3://bucket?region=us-east-1", &credential).unwrap();
+        assert_eq!(credential_id, CredentialVault::get_credential_id("s3://bucket?region=us-east-1"));
+
+        let (kind, _, _) = task_db.load_credential(credential_id.as_str()).unwrap().unwrap();
+        assert_eq!(kind, "s3");
+        assert_eq!(vault.resolve_credential(credential_id.as_str()).unwrap(), credential);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.path().join(CREDENTIAL_VAULT_KEY_FILE)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        //key丢失后无法解密
+        let other_vault = CredentialVault::new(task_db.clone(), dir.path().join("other.key"));
+        assert!(other_vault.resolve_credential(credential_id.as_str()).is_err());
+
+        vault.remove_credential(credential_id.as_str()).unwrap();
+        assert!(vault.resolve_credential(credential_id.as_str()).is_err());
+    }
+}
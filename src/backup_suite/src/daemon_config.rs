@@ -0,0 +1,259 @@
+#![allow(unused)]
+//daemon的本机配置,保存在service data目录下的daemon_config.json,只能由本机管理员编辑文件修改,
+//web接口不能修改,凭证/审批这类安全相关的开关不能放在plan的options里(plan可以通过RPC修改)
+
+use std::path::{Component, Path, PathBuf};
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use log::*;
+
+use crate::approval::credential_fingerprint;
+use crate::health::DEFAULT_HEALTH_LISTEN;
+
+pub const DAEMON_CONFIG_FILE: &str = "daemon_config.json";
+pub const DEFAULT_MAX_RUNNING_TASKS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    //管理员凭证的指纹(credential_fingerprint),配置后web接口的管理操作只接受这些凭证
+    pub admin_credentials: Vec<String>,
+    //破坏性操作需要另一个管理员凭证批准,开启时至少需要两个管理员凭证
+    pub two_person_approval: bool,
+    //health检查的监听地址,没有认证,只在容器里需要外部探测时改成0.0.0.0
+    pub health_listen: String,
+    //同时运行的task数量,超过时新启动的task进入队列
+    pub max_running_tasks: usize,
+    //本机管理员放置hook脚本的目录,plan中的hook只能是这个目录下的脚本文件名;为None时不能使用hook
+    //plan可以通过RPC修改,不能让plan直接指定要执行的命令
+    pub hook_dir: Option<String>,
+    //允许通过web接口导出/导入离线种子的目录(比如移动硬盘的挂载点),为空时web接口不能导出导入种子
+    //命令行的seed import由本机管理员运行,不受这个限制
+    pub seed_dirs: Vec<String>,
+    //可以设置/解除法律保留的凭证指纹,和admin_credentials分开配置(法务人员不一定是管理员),为空时不能设置和解除
+    pub legal_hold_officers: Vec<String>,
+    //只读WebDAV目录的监听地址,为None时不启动;只在需要从其它机器浏览时改成0.0.0.0
+    pub dav_listen: Option<String>,
+    //WebDAV的Basic认证,密码的指纹(credential_fingerprint),用户名不检查;开启dav_listen时必须配置
+    pub dav_credentials: Vec<String>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            admin_credentials: Vec::new(),
+            two_person_approval: false,
+            health_listen: DEFAULT_HEALTH_LISTEN.to_string(),
+            max_running_tasks: DEFAULT_MAX_RUNNING_TASKS,
+            hook_dir: None,
+            seed_dirs: Vec::new(),
+            legal_hold_officers: Vec::new(),
+            dav_listen: None,
+            dav_credentials: Vec::new(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    //文件不存在时使用默认配置,文件存在但格式错误时返回错误,不能悄悄退回到没有保护的默认配置
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let config_path = data_dir.join(DAEMON_CONFIG_FILE);
+        let content = match std::fs::read_to_string(&config_path) {
+            std::result::Result::Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("daemon config {} not found, use default", config_path.to_string_lossy());
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(anyhow::anyhow!("read daemon config {} failed: {}", config_path.to_string_lossy(), err)),
+        };
+        let config: Self = serde_json::from_str(content.as_str())
+            .map_err(|e| anyhow::anyhow!("parse daemon config {} failed: {}", config_path.to_string_lossy(), e))?;
+        config.validate()?;
+        info!("daemon config {} loaded, {} admin credentials", config_path.to_string_lossy(), config.admin_credentials.len());
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.two_person_approval && self.admin_credentials.len() < 2 {
+            return Err(anyhow::anyhow!("two_person_approval requires at least 2 admin_credentials"));
+        }
+        if self.max_running_tasks == 0 {
+            return Err(anyhow::anyhow!("max_running_tasks must be greater than 0"));
+        }
+        if self.dav_listen.is_some() && self.dav_credentials.is_empty() {
+            return Err(anyhow::anyhow!("dav_listen requires dav_credentials"));
+        }
+        Ok(())
+    }
+
+    //返回hook_dir下名为name的脚本,name只能是文件名,解析符号链接后也必须还在hook_dir下
+    pub fn resolve_hook_script(&self, name: &str) -> Result<PathBuf> {
+        let hook_dir = self.hook_dir.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("hook {} is not allowed, hook_dir is not configured", name))?;
+        if !is_hook_script_name(name) {
+            return Err(anyhow::anyhow!("invalid hook script name: {}", name));
+        }
+        let hook_dir = Path::new(hook_dir).canonicalize()
+            .map_err(|e| anyhow::anyhow!("hook dir {} is not available: {}", hook_dir, e))?;
+        let script_path = hook_dir.join(name).canonicalize()
+            .map_err(|e| anyhow::anyhow!("hook script {} not found in {}: {}", name, hook_dir.to_string_lossy(), e))?;
+        if !script_path.starts_with(&hook_dir) || !script_path.is_file() {
+            return Err(anyhow::anyhow!("hook script {} is not a file in {}", name, hook_dir.to_string_lossy()));
+        }
+        Ok(script_path)
+    }
+
+    //seed_dir必须是绝对路径,解析已经存在部分的符号链接后还在某个seed_dirs下,返回解析后的路径
+    pub fn resolve_seed_dir(&self, seed_dir: &str) -> Result<PathBuf> {
+        if self.seed_dirs.is_empty() {
+            return Err(anyhow::anyhow!("seed dir {} is not allowed, seed_dirs is not configured in {}", seed_dir, DAEMON_CONFIG_FILE));
+        }
+        let seed_path = Path::new(seed_dir);
+        if !seed_path.is_absolute() || seed_path.components().any(|c| matches!(c, Component::ParentDir)) {
+            return Err(anyhow::anyhow!("seed dir {} must be an absolute path without ..", seed_dir));
+        }
+        //导出时种子目录可能还不存在
+        let mut exist_path = seed_path;
+        while !exist_path.exists() {
+            exist_path = exist_path.parent()
+                .ok_or_else(|| anyhow::anyhow!("seed dir {} is not available", seed_dir))?;
+        }
+        let real_path = exist_path.canonicalize()?.join(seed_path.strip_prefix(exist_path)?);
+        for allowed_dir in self.seed_dirs.iter() {
+            if let std::result::Result::Ok(allowed_dir) = Path::new(allowed_dir).canonicalize() {
+                if real_path.starts_with(&allowed_dir) {
+                    return Ok(real_path);
+                }
+            }
+        }
+        Err(anyhow::anyhow!("seed dir {} is not in seed_dirs", seed_dir))
+    }
+
+    pub fn is_admin_credential(&self, fingerprint: &str) -> bool {
+        self.admin_credentials.iter().any(|admin| admin == fingerprint)
+    }
+
+    //返回凭证的指纹;没有配置管理员凭证时(单用户的本机部署)不校验,以anonymous身份操作
+    pub fn authenticate(&self, token: Option<&str>) -> Result<String> {
+        if self.admin_credentials.is_empty() {
+            return Ok("anonymous".to_string());
+        }
+        let token = token.ok_or_else(|| anyhow::anyhow!("credential is required"))?;
+        let fingerprint = credential_fingerprint(token);
+        if !self.is_admin_credential(fingerprint.as_str()) {
+            return Err(anyhow::anyhow!("credential {} is not an admin credential", fingerprint));
+        }
+        Ok(fingerprint)
+    }
+
+    //法律保留只接受法律保留管理员的凭证,必须提供token,没有配置管理员凭证时也不能以anonymous身份操作
+    //返回token的指纹,不是管理员时也返回指纹,由调用者把被拒绝的请求写入审计日志
+    pub fn authenticate_legal_hold_operator(&self, token: Option<&str>) -> Result<String> {
+        let token = token.ok_or_else(|| anyhow::anyhow!("credential is required"))?;
+        Ok(credential_fingerprint(token))
+    }
+
+    //能让其它机器获得访问权限的操作(如创建agent注册token)必须由配置过的管理员凭证执行,没有配置时不能以anonymous身份操作
+    pub fn authenticate_admin(&self, token: Option<&str>) -> Result<String> {
+        if self.admin_credentials.is_empty() {
+            return Err(anyhow::anyhow!("admin_credentials is not configured in {}", DAEMON_CONFIG_FILE));
+        }
+        self.authenticate(token)
+    }
+}
+
+//hook只能按文件名引用hook_dir下的脚本,不能带路径和参数
+pub fn is_hook_script_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".."
+        && !name.contains(|c: char| c == '/' || c == '\\' || c.is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_daemon_config() {
+        let test_dir = tempfile::tempdir().unwrap();
+        assert_eq!(DaemonConfig::load(test_dir.path()).unwrap(), DaemonConfig::default());
+
+        let admin_a = credential_fingerprint("token_a");
+        let admin_b = credential_fingerprint("token_b");
+        let config_path = test_dir.path().join(DAEMON_CONFIG_FILE);
+        std::fs::write(&config_path, serde_json::json!({
+            "admin_credentials": [admin_a],
+            "two_person_approval": true,
+        }).to_string()).unwrap();
+        assert!(DaemonConfig::load(test_dir.path()).is_err());
+        std::fs::write(&config_path, "{not json").unwrap();
+        assert!(DaemonConfig::load(test_dir.path()).is_err());
+
+        std::fs::write(&config_path, serde_json::json!({
+            "admin_credentials": [admin_a, admin_b],
+            "two_person_approval": true,
+        }).to_string()).unwrap();
+        let config = DaemonConfig::load(test_dir.path()).unwrap();
+        assert!(config.two_person_approval);
+        assert_eq!(config.max_running_tasks, DEFAULT_MAX_RUNNING_TASKS);
+        assert_eq!(config.authenticate(Some("token_b")).unwrap(), admin_b);
+        assert!(config.authenticate(Some("token_c")).is_err());
+        assert!(config.authenticate(None).is_err());
+        assert_eq!(DaemonConfig::default().authenticate(None).unwrap(), "anonymous");
+        assert!(DaemonConfig::default().authenticate_admin(None).is_err());
+        assert_eq!(config.authenticate_admin(Some("token_a")).unwrap(), admin_a);
+        assert!(config.authenticate_admin(None).is_err());
+
+        assert!(config.authenticate_legal_hold_operator(None).is_err());
+        assert_eq!(config.authenticate_legal_hold_operator(Some("token_c")).unwrap(), credential_fingerprint("token_c"));
+
+        std::fs::write(&config_path, serde_json::json!({ "dav_listen": "127.0.0.1:5182" }).to_string()).unwrap();
+        assert!(DaemonConfig::load(test_dir.path()).is_err());
+
+        std::fs::write(&config_path, serde_json::json!({ "max_running_tasks": 0 }).to_string()).unwrap();
+        assert!(DaemonConfig::load(test_dir.path()).is_err());
+        std::fs::write(&config_path, serde_json::json!({ "max_running_tasks": 1 }).to_string()).unwrap();
+        assert_eq!(DaemonConfig::load(test_dir.path()).unwrap().max_running_tasks, 1);
+    }
+
+    #[test]
+    fn test_resolve_hook_script() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let hook_dir = test_dir.path().join("hooks");
+        std::fs::create_dir_all(&hook_dir).unwrap();
+        std::fs::write(hook_dir.join("check.sh"), b"exit 0").unwrap();
+        std::fs::write(test_dir.path().join("outside.sh"), b"exit 0").unwrap();
+
+        assert!(DaemonConfig::default().resolve_hook_script("check.sh").is_err());
+        let config = DaemonConfig { hook_dir: Some(hook_dir.to_string_lossy().to_string()), ..Default::default() };
+        assert_eq!(config.resolve_hook_script("check.sh").unwrap(), hook_dir.canonicalize().unwrap().join("check.sh"));
+        for name in ["", "..", "../outside.sh", "/bin/sh", "check.sh -x", "missing.sh"] {
+            assert!(config.resolve_hook_script(name).is_err(), "{}", name);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(test_dir.path().join("outside.sh"), hook_dir.join("link.sh")).unwrap();
+            assert!(config.resolve_hook_script("link.sh").is_err());
+        }
+    }
+
+    #[test]
+    fn test_resolve_seed_dir() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let allowed_dir = test_dir.path().join("usb");
+        std::fs::create_dir_all(&allowed_dir).unwrap();
+        let seed_dir = allowed_dir.join("seed").to_string_lossy().to_string();
+        assert!(DaemonConfig::default().resolve_seed_dir(&seed_dir).is_err());
+
+        let config = DaemonConfig { seed_dirs: vec![allowed_dir.to_string_lossy().to_string()], ..Default::default() };
+        assert_eq!(config.resolve_seed_dir(&seed_dir).unwrap(), allowed_dir.canonicalize().unwrap().join("seed"));
+        let escape_dir = allowed_dir.join("..").join("other").to_string_lossy().to_string();
+        for seed_dir in ["seed", "/etc", escape_dir.as_str()] {
+            assert!(config.resolve_seed_dir(seed_dir).is_err(), "{}", seed_dir);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", allowed_dir.join("link")).unwrap();
+            assert!(config.resolve_seed_dir(allowed_dir.join("link").join("seed").to_string_lossy().as_ref()).is_err());
+        }
+    }
+}
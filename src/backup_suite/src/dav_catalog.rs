@@ -0,0 +1,714 @@
+#![allow(unused)]
+//只读的WebDAV目录:不用恢复就可以在Finder/资源管理器里浏览checkpoint,把单个文件拖出来
+//目录结构是 /plan_id/checkpoint_id/item路径,目录和文件的元数据来自task db,文件内容在GET时才从target流式读取
+//默认不启动,daemon配置了dav_listen(或者设置了BACKUP_SUITE_DAV_PORT,只监听127.0.0.1)后启动;只支持OPTIONS/PROPFIND/GET/HEAD,写操作返回405
+//所有请求都需要Basic认证,密码的指纹必须在daemon配置的dav_credentials中
+//和health一样,cyfs_warp的路由不支持WebDAV的方法,这里单独起一个很简单的http服务
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use chrono::{TimeZone, Utc};
+use log::*;
+use ndn_lib::ChunkId;
+use buckyos_backup_lib::*;
+
+use crate::approval::credential_fingerprint;
+use crate::daemon_config::DaemonConfig;
+use crate::engine::{BackupEngine, DEFAULT_ENGINE};
+
+pub const DAV_PORT_ENV: &str = "BACKUP_SUITE_DAV_PORT";
+const DAV_ALLOW_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD";
+const DAV_MAX_HEADER_SIZE: usize = 16 * 1024;
+const DAV_MAX_DRAIN_BODY_SIZE: usize = 1024 * 1024;
+const DAV_COPY_BUFFER_SIZE: usize = 256 * 1024;
+const DAV_READ_TIMEOUT_SECS: u64 = 30; //读取请求时每次read的超时,超时后关闭连接
+const DAV_MAX_CONNECTIONS: usize = 64; //同时处理的连接数,超过后等待已有连接结束再accept
+const DAV_UNAUTHORIZED: &str = "401 Unauthorized";
+const DAV_NOT_FOUND: &str = "404 Not Found";
+const DAV_SERVER_ERROR: &str = "500 Internal Server Error";
+
+//daemon配置优先;兼容老版本的环境变量,只监听本机;都没有设置(或者端口为0)时不启动DAV服务
+pub fn get_dav_catalog_listen(daemon_config: &DaemonConfig) -> Option<String> {
+    if let Some(dav_listen) = daemon_config.dav_listen.as_ref() {
+        return Some(dav_listen.clone());
+    }
+    std::env::var(DAV_PORT_ENV).ok()
+        .and_then(|port| port.trim().parse::<u16>().ok())
+        .filter(|port| *port != 0)
+        .map(|port| format!("127.0.0.1:{}", port))
+}
+
+//Authorization: Basic base64(user:password),只检查password的指纹
+pub fn check_dav_authorization(credentials: &[String], authorization: Option<&str>) -> bool {
+    let password = authorization
+        .and_then(|authorization| authorization.trim().strip_prefix("Basic "))
+        .and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| decoded.split_once(':').map(|(_, password)| password.to_string()));
+    match password {
+        Some(password) => {
+            let fingerprint = credential_fingerprint(password.as_str());
+            credentials.iter().any(|credential| *credential == fingerprint)
+        }
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DavPath {
+    Root,
+    Plan(String),
+    Checkpoint(String, String),//plan_id, checkpoint_id
+    Item(String, String, String),//plan_id, checkpoint_id, item路径(和item_id一样不以/开头)
+}
+
+//解析请求中的路径,路径不合法(编码错误,包含..)时返回None
+pub fn parse_dav_path(path: &str) -> Option<DavPath> {
+    //客户端通过代理访问时请求行里是完整的url
+    let path = match path.find("://") {
+        Some(pos) => {
+            let rest = &path[pos + 3..];
+            rest.find('/').map(|host_end| &rest[host_end..]).unwrap_or("/")
+        }
+        None => path,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let mut segments = Vec::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_decode(segment)?;
+        if segment == "." || segment == ".." || segment.contains('/') {
+            return None;
+        }
+        segments.push(segment);
+    }
+    let mut segments = segments.into_iter();
+    let plan_id = match segments.next() {
+        Some(plan_id) => plan_id,
+        None => return Some(DavPath::Root),
+    };
+    let checkpoint_id = match segments.next() {
+        Some(checkpoint_id) => checkpoint_id,
+        None => return Some(DavPath::Plan(plan_id)),
+    };
+    let item_path = segments.collect::<Vec<String>>().join("/");
+    if item_path.is_empty() {
+        Some(DavPath::Checkpoint(plan_id, checkpoint_id))
+    } else {
+        Some(DavPath::Item(plan_id, checkpoint_id, item_path))
+    }
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] == b'%' {
+            let hex = segment.get(pos + 1..pos + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            pos += 3;
+        } else {
+            decoded.push(bytes[pos]);
+            pos += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(format!("%{:02X}", byte).as_str());
+        }
+    }
+    encoded
+}
+
+//rel_path是解码后的相对路径(plan_id/checkpoint_id/item路径),目录的href以/结尾
+pub fn dav_href(rel_path: &str, is_dir: bool) -> String {
+    let segments: Vec<String> = rel_path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_encode)
+        .collect();
+    let mut href = format!("/{}", segments.join("/"));
+    if is_dir && !segments.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DavEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub last_modify_time: u64,//秒,0表示未知
+}
+
+impl DavEntry {
+    pub fn dir(name: &str, last_modify_time: u64) -> Self {
+        Self { name: name.to_string(), is_dir: true, size: 0, last_modify_time }
+    }
+
+    pub fn file(name: &str, size: u64, last_modify_time: u64) -> Self {
+        Self { name: name.to_string(), is_dir: false, size, last_modify_time }
+    }
+}
+
+//checkpoint的item按目录建立的索引,加载checkpoint时建立一次,PROPFIND/GET不再扫描全部item
+pub struct DavCatalogIndex {
+    items: Vec<BackupItem>,
+    item_pos: HashMap<String, usize>,
+    //目录(""为根目录) -> 直接子项,按名字排序;没有单独记录的中间目录由item路径推出
+    children: HashMap<String, BTreeMap<String, DavEntry>>,
+}
+
+impl DavCatalogIndex {
+    pub fn new(items: Vec<BackupItem>) -> Self {
+        let item_pos = items.iter().enumerate()
+            .map(|(pos, item)| (item.item_id.clone(), pos))
+            .collect::<HashMap<String, usize>>();
+        let mut index = Self { items, item_pos, children: HashMap::new() };
+        let mut children: HashMap<String, BTreeMap<String, DavEntry>> = HashMap::new();
+        for item in index.items.iter() {
+            let mut parent = String::new();
+            let mut segments = item.item_id.split('/').peekable();
+            while let Some(name) = segments.next() {
+                let dir_children = children.entry(parent.clone()).or_default();
+                if segments.peek().is_some() {
+                    dir_children.entry(name.to_string()).or_insert_with(|| DavEntry::dir(name, 0));
+                    if !parent.is_empty() {
+                        parent.push('/');
+                    }
+                    parent.push_str(name);
+                } else if let Some(entry) = index.item_to_entry(item) {
+                    dir_children.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        index.children = children;
+        index
+    }
+
+    fn get_item(&self, path: &str) -> Option<&BackupItem> {
+        self.item_pos.get(path).map(|pos| &self.items[*pos])
+    }
+
+    //GET时读取内容的item路径:硬链接读取它指向的文件
+    pub fn resolve_content_path(&self, path: &str) -> Option<String> {
+        let item = self.get_item(path)?;
+        if item.item_type != BackupItemType::HardLink {
+            return Some(path.to_string());
+        }
+        let link_target = item.file_meta.as_ref()
+            .and_then(|s| ItemFileMeta::from_json_str(s))
+            .and_then(|file_meta| file_meta.link_target)?;
+        self.get_item(link_target.as_str()).map(|target_item| target_item.item_id.clone())
+    }
+
+    //符号链接和云盘占位文件不能下载,不显示;硬链接显示为它指向的文件的大小
+    fn item_to_entry(&self, item: &BackupItem) -> Option<DavEntry> {
+        let name = item.item_id.rsplit('/').next().unwrap_or(item.item_id.as_str());
+        match item.item_type {
+            BackupItemType::Directory => Some(DavEntry::dir(name, item.last_modify_time)),
+            BackupItemType::Symlink | BackupItemType::CloudPlaceholder => None,
+            BackupItemType::HardLink => {
+                let target_item = self.get_item(self.resolve_content_path(item.item_id.as_str())?.as_str())?;
+                Some(DavEntry::file(name, target_item.size, item.last_modify_time))
+            }
+            BackupItemType::File | BackupItemType::Chunk => Some(DavEntry::file(name, item.size, item.last_modify_time)),
+        }
+    }
+
+    //checkpoint中路径对应的文件或者目录
+    pub fn find_entry(&self, path: &str) -> Option<DavEntry> {
+        if let Some(item) = self.get_item(path) {
+            return self.item_to_entry(item);
+        }
+        if self.children.contains_key(path) {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            return Some(DavEntry::dir(name, 0));
+        }
+        None
+    }
+
+    //目录dir(""为根目录)的直接子项,按名字排序;目录不存在时返回None
+    pub fn list_dir(&self, dir: &str) -> Option<Vec<DavEntry>> {
+        if let Some(item) = self.get_item(dir) {
+            if item.item_type != BackupItemType::Directory {
+                return None;
+            }
+        }
+        match self.children.get(dir) {
+            Some(children) => Some(children.values().cloned().collect()),
+            None if dir.is_empty() || self.get_item(dir).is_some() => Some(Vec::new()),
+            None => None,
+        }
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn http_date(secs: u64) -> Option<String> {
+    if secs == 0 {
+        return None;
+    }
+    Utc.timestamp_opt(secs as i64, 0).single()
+        .map(|time| time.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+//PROPFIND的响应,entries是(href, entry);不管请求了哪些属性都返回全部属性
+pub fn build_multistatus(entries: &[(String, DavEntry)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for (href, entry) in entries.iter() {
+        xml.push_str("<D:response><D:href>");
+        xml.push_str(xml_escape(href).as_str());
+        xml.push_str("</D:href><D:propstat><D:prop>");
+        xml.push_str(format!("<D:displayname>{}</D:displayname>", xml_escape(entry.name.as_str())).as_str());
+        if entry.is_dir {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            xml.push_str("<D:resourcetype/>");
+            xml.push_str(format!("<D:getcontentlength>{}</D:getcontentlength>", entry.size).as_str());
+            xml.push_str("<D:getcontenttype>application/octet-stream</D:getcontenttype>");
+        }
+        if let Some(last_modified) = http_date(entry.last_modify_time) {
+            xml.push_str(format!("<D:getlastmodified>{}</D:getlastmodified>", last_modified).as_str());
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+struct DavRequest {
+    method: String,
+    path: String,
+    depth: Option<String>,
+    authorization: Option<String>,
+}
+
+//完成的checkpoint不会再变化,Finder浏览时会对同一个checkpoint连续发很多PROPFIND,缓存最近一个checkpoint的索引
+pub struct DavCatalog {
+    credentials: Vec<String>,
+    items_cache: Mutex<Option<(String, Arc<DavCatalogIndex>)>>,
+}
+
+impl DavCatalog {
+    pub fn new(credentials: Vec<String>) -> Self {
+        Self { credentials, items_cache: Mutex::new(None) }
+    }
+
+    //每次都检查checkpoint仍然属于plan且可以浏览,checkpoint被删除后不会再返回缓存的内容
+    fn load_items(&self, engine: &BackupEngine, plan_id: &str, checkpoint_id: &str) -> Result<Arc<DavCatalogIndex>, &'static str> {
+        let checkpoints = engine.list_browsable_checkpoints(plan_id).map_err(|e| {
+            warn!("dav list checkpoints of plan {} error: {}", plan_id, e);
+            DAV_SERVER_ERROR
+        })?;
+        if !checkpoints.iter().any(|checkpoint| checkpoint.checkpoint_id == checkpoint_id) {
+            return Err(DAV_NOT_FOUND);
+        }
+        if let Some((cached_id, items)) = self.items_cache.lock().unwrap().as_ref() {
+            if cached_id == checkpoint_id {
+                return Ok(items.clone());
+            }
+        }
+        let items = engine.load_checkpoint_catalog(checkpoint_id).map_err(|e| {
+            warn!("dav load items of checkpoint {} error: {}", checkpoint_id, e);
+            DAV_SERVER_ERROR
+        })?;
+        let items = Arc::new(DavCatalogIndex::new(items));
+        *self.items_cache.lock().unwrap() = Some((checkpoint_id.to_string(), items.clone()));
+        Ok(items)
+    }
+
+    async fn propfind(&self, path: &str, with_children: bool) -> Result<String, &'static str> {
+        let dav_path = parse_dav_path(path).ok_or(DAV_NOT_FOUND)?;
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let mut entries = Vec::new();
+        match dav_path {
+            DavPath::Root => {
+                entries.push((dav_href("", true), DavEntry::dir("", 0)));
+                if with_children {
+                    let mut plan_ids = engine.list_backup_plans().await.map_err(|_| DAV_SERVER_ERROR)?;
+                    plan_ids.sort();
+                    for plan_id in plan_ids {
+                        entries.push((dav_href(plan_id.as_str(), true), DavEntry::dir(plan_id.as_str(), 0)));
+                    }
+                }
+            }
+            DavPath::Plan(plan_id) => {
+                engine.get_backup_plan(plan_id.as_str()).await.map_err(|_| DAV_NOT_FOUND)?;
+                entries.push((dav_href(plan_id.as_str(), true), DavEntry::dir(plan_id.as_str(), 0)));
+                if with_children {
+                    let checkpoints = engine.list_browsable_checkpoints(plan_id.as_str()).map_err(|_| DAV_SERVER_ERROR)?;
+                    for checkpoint in checkpoints {
+                        let rel_path = format!("{}/{}", plan_id, checkpoint.checkpoint_id);
+                        let entry = DavEntry::dir(checkpoint.checkpoint_id.as_str(), checkpoint.create_time / 1000);
+                        entries.push((dav_href(rel_path.as_str(), true), entry));
+                    }
+                }
+            }
+            DavPath::Checkpoint(plan_id, checkpoint_id) => {
+                self.append_item_entries(&engine, plan_id.as_str(), checkpoint_id.as_str(), "", with_children, &mut entries)?;
+            }
+            DavPath::Item(plan_id, checkpoint_id, item_path) => {
+                self.append_item_entries(&engine, plan_id.as_str(), checkpoint_id.as_str(), item_path.as_str(), with_children, &mut entries)?;
+            }
+        }
+        Ok(build_multistatus(&entries))
+    }
+
+    //item_path为""时是checkpoint的根目录
+    fn append_item_entries(&self, engine: &BackupEngine, plan_id: &str, checkpoint_id: &str, item_path: &str,
+        with_children: bool, entries: &mut Vec<(String, DavEntry)>) -> Result<(), &'static str> {
+        let items = self.load_items(engine, plan_id, checkpoint_id)?;
+        let rel_path = format!("{}/{}/{}", plan_id, checkpoint_id, item_path);
+        let entry = if item_path.is_empty() {
+            DavEntry::dir(checkpoint_id, 0)
+        } else {
+            items.find_entry(item_path).ok_or(DAV_NOT_FOUND)?
+        };
+        entries.push((dav_href(rel_path.as_str(), entry.is_dir), entry.clone()));
+        if entry.is_dir && with_children {
+            for child in items.list_dir(item_path).unwrap_or_default() {
+                let child_path = format!("{}/{}", rel_path, child.name);
+                entries.push((dav_href(child_path.as_str(), child.is_dir), child));
+            }
+        }
+        Ok(())
+    }
+
+    //边发送边校验hash,最后一段内容在校验通过后才发出,校验失败时客户端只会收到不完整的文件
+    async fn serve_item(&self, stream: &mut TcpStream, path: &str, head_only: bool) -> std::io::Result<()> {
+        let (plan_id, checkpoint_id, item_path) = match parse_dav_path(path) {
+            Some(DavPath::Item(plan_id, checkpoint_id, item_path)) => (plan_id, checkpoint_id, item_path),
+            Some(_) => return write_dav_response(stream, "405 Method Not Allowed", &[("Allow", DAV_ALLOW_METHODS.to_string())], b"").await,
+            None => return write_dav_response(stream, DAV_NOT_FOUND, &[], b"").await,
+        };
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let items = match self.load_items(&engine, plan_id.as_str(), checkpoint_id.as_str()) {
+            Ok(items) => items,
+            Err(status) => return write_dav_response(stream, status, &[], b"").await,
+        };
+        let entry = match items.find_entry(item_path.as_str()) {
+            Some(entry) if !entry.is_dir => entry,
+            _ => return write_dav_response(stream, DAV_NOT_FOUND, &[], b"").await,
+        };
+        let content_path = match items.resolve_content_path(item_path.as_str()) {
+            Some(content_path) => content_path,
+            None => return write_dav_response(stream, DAV_NOT_FOUND, &[], b"").await,
+        };
+        let mut headers = vec![("Content-Type", "application/octet-stream".to_string())];
+        if let Some(last_modified) = http_date(entry.last_modify_time) {
+            headers.push(("Last-Modified", last_modified));
+        }
+        if head_only {
+            write_dav_headers(stream, "200 OK", &headers, entry.size).await?;
+            return stream.shutdown().await;
+        }
+
+        let (item, mut reader, _read_guard) = match engine.open_checkpoint_item_reader(checkpoint_id.as_str(), content_path.as_str()).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("dav open item {} of checkpoint {} error: {:#}", item_path, checkpoint_id, e);
+                return write_dav_response(stream, DAV_SERVER_ERROR, &[], b"").await;
+            }
+        };
+        let chunk_id = ChunkId::new(item.chunk_id.as_ref().unwrap())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e)))?;
+        let mut hasher = BackupChunkHasher::for_chunk_id(&chunk_id)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        info!("dav download item {} of checkpoint {}, size {}", item_path, checkpoint_id, item.size);
+        write_dav_headers(stream, "200 OK", &headers, item.size).await?;
+        let mut buf = vec![0u8; DAV_COPY_BUFFER_SIZE];
+        let mut pending: Vec<u8> = Vec::new();
+        let mut read_size = 0u64;
+        loop {
+            let read_len = reader.read(&mut buf).await?;
+            if read_len == 0 {
+                break;
+            }
+            hasher.update_from_bytes(&buf[..read_len]);
+            read_size += read_len as u64;
+            stream.write_all(&pending).await?;
+            pending.clear();
+            pending.extend_from_slice(&buf[..read_len]);
+        }
+        let real_chunk_id = hasher.finalize_chunk_id()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        if read_size != item.size || real_chunk_id != chunk_id {
+            error!("dav download item {} of checkpoint {} verify failed, size {} hash {}", item_path, checkpoint_id, read_size, real_chunk_id.to_string());
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "item content verify failed"));
+        }
+        stream.write_all(&pending).await?;
+        stream.shutdown().await
+    }
+}
+
+pub async fn start_dav_catalog_service(listen: String, credentials: Vec<String>) {
+    if credentials.is_empty() {
+        error!("start dav catalog service at {} failed: dav_credentials is not configured", listen);
+        return;
+    }
+    let listener = TcpListener::bind(listen.as_str()).await;
+    if listener.is_err() {
+        error!("start dav catalog service at {} failed: {}", listen, listener.err().unwrap());
+        return;
+    }
+    let listener = listener.unwrap();
+    info!("dav catalog service listen at {}", listen);
+    let catalog = Arc::new(DavCatalog::new(credentials));
+    let connection_limit = Arc::new(Semaphore::new(DAV_MAX_CONNECTIONS));
+    loop {
+        let permit = connection_limit.clone().acquire_owned().await.unwrap();
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let catalog = catalog.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_dav_request(catalog, stream).await {
+                        debug!("handle dav request error: {}", e);
+                    }
+                    drop(permit);
+                });
+            }
+            Err(e) => {
+                warn!("dav catalog service accept error: {}", e);
+            }
+        }
+    }
+}
+
+//每个连接只处理一个请求
+async fn handle_dav_request(catalog: Arc<DavCatalog>, mut stream: TcpStream) -> std::io::Result<()> {
+    let request = match read_dav_request(&mut stream, Duration::from_secs(DAV_READ_TIMEOUT_SECS)).await? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    debug!("dav request: {} {} depth {:?}", request.method, request.path, request.depth);
+    if !check_dav_authorization(&catalog.credentials, request.authorization.as_deref()) {
+        let headers = [("WWW-Authenticate", "Basic realm=\"backup_suite\"".to_string())];
+        return write_dav_response(&mut stream, DAV_UNAUTHORIZED, &headers, b"").await;
+    }
+    match request.method.as_str() {
+        "OPTIONS" => {
+            let headers = [("DAV", "1".to_string()), ("Allow", DAV_ALLOW_METHODS.to_string())];
+            write_dav_response(&mut stream, "200 OK", &headers, b"").await
+        }
+        "PROPFIND" => {
+            let with_children = request.depth.as_deref().map(|depth| depth.trim() != "0").unwrap_or(true);
+            match catalog.propfind(request.path.as_str(), with_children).await {
+                Ok(body) => {
+                    let headers = [("Content-Type", "application/xml; charset=utf-8".to_string())];
+                    write_dav_response(&mut stream, "207 Multi-Status", &headers, body.as_bytes()).await
+                }
+                Err(status) => write_dav_response(&mut stream, status, &[], b"").await,
+            }
+        }
+        "GET" | "HEAD" => catalog.serve_item(&mut stream, request.path.as_str(), request.method == "HEAD").await,
+        _ => {
+            let headers = [("Allow", DAV_ALLOW_METHODS.to_string())];
+            write_dav_response(&mut stream, "405 Method Not Allowed", &headers, b"").await
+        }
+    }
+}
+
+//客户端一直不发完请求时read超时返回错误,连接随之关闭
+async fn read_dav_request(stream: &mut TcpStream, read_timeout: Duration) -> std::io::Result<Option<DavRequest>> {
+    let mut buf = Vec::new();
+    let mut piece = [0u8; 2048];
+    let header_end = loop {
+        if let Some(pos) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > DAV_MAX_HEADER_SIZE {
+            return Ok(None);
+        }
+        let read_len = read_dav_stream(stream, &mut piece, read_timeout).await?;
+        if read_len == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&piece[..read_len]);
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("").to_uppercase();
+    let path = request_line.next().unwrap_or("").to_string();
+    let mut depth = None;
+    let mut authorization = None;
+    let mut content_length = 0usize;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "depth" => depth = Some(value.trim().to_string()),
+                "authorization" => authorization = Some(value.trim().to_string()),
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+
+    //PROPFIND的body是要查询的属性,这里总是返回全部属性,读掉丢弃
+    let mut remain = content_length.min(DAV_MAX_DRAIN_BODY_SIZE).saturating_sub(buf.len() - header_end - 4);
+    while remain > 0 {
+        let read_len = read_dav_stream(stream, &mut piece[..remain.min(2048)], read_timeout).await?;
+        if read_len == 0 {
+            break;
+        }
+        remain -= read_len;
+    }
+    Ok(Some(DavRequest { method, path, depth, authorization }))
+}
+
+async fn read_dav_stream(stream: &mut TcpStream, buf: &mut [u8], read_timeout: Duration) -> std::io::Result<usize> {
+    match timeout(read_timeout, stream.read(buf)).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "read dav request timeout")),
+    }
+}
+
+async fn write_dav_headers(stream: &mut TcpStream, status: &str, headers: &[(&str, String)], content_length: u64) -> std::io::Result<()> {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers.iter() {
+        response.push_str(format!("{}: {}\r\n", name, value).as_str());
+    }
+    response.push_str(format!("Content-Length: {}\r\nConnection: close\r\n\r\n", content_length).as_str());
+    stream.write_all(response.as_bytes()).await
+}
+
+async fn write_dav_response(stream: &mut TcpStream, status: &str, headers: &[(&str, String)], body: &[u8]) -> std::io::Result<()> {
+    write_dav_headers(stream, status, headers, body.len() as u64).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, item_type: BackupItemType, size: u64) -> BackupItem {
+        let mut item = BackupItem::new(item_id, item_type, size);
+        item.last_modify_time = 1700000000;
+        item
+    }
+
+    #[test]
+    fn test_parse_dav_path() {
+        assert_eq!(parse_dav_path("/"), Some(DavPath::Root));
+        assert_eq!(parse_dav_path("/plan_1/"), Some(DavPath::Plan("plan_1".to_string())));
+        assert_eq!(parse_dav_path("http://127.0.0.1:5182/plan_1/ckpt_1"),
+            Some(DavPath::Checkpoint("plan_1".to_string(), "ckpt_1".to_string())));
+        assert_eq!(parse_dav_path("/plan_1/ckpt_1/docs/my%20file%E4%B8%AD.txt?x=1"),
+            Some(DavPath::Item("plan_1".to_string(), "ckpt_1".to_string(), "docs/my file中.txt".to_string())));
+        assert_eq!(parse_dav_path("/plan_1/ckpt_1/../other"), None);
+        assert_eq!(parse_dav_path("/plan_1/ckpt_1/a%2Fb"), None);
+        assert_eq!(parse_dav_path("/plan_1/ckpt_1/bad%zz"), None);
+        assert_eq!(dav_href("plan_1/ckpt_1/docs/my file中.txt", false), "/plan_1/ckpt_1/docs/my%20file%E4%B8%AD.txt");
+        assert_eq!(dav_href("plan_1", true), "/plan_1/");
+        assert_eq!(dav_href("", true), "/");
+    }
+
+    #[test]
+    fn test_list_catalog_dir() {
+        let mut hard_link = make_item("docs/b_link.txt", BackupItemType::HardLink, 0);
+        hard_link.file_meta = Some(ItemFileMeta { link_target: Some("docs/b.txt".to_string()), ..Default::default() }.to_json_string());
+        let items = vec![
+            make_item("a.txt", BackupItemType::File, 10),
+            make_item("docs", BackupItemType::Directory, 0),
+            make_item("docs/b.txt", BackupItemType::File, 20),
+            hard_link,
+            make_item("docs/link", BackupItemType::Symlink, 0),
+            make_item("photos/2024/c.jpg", BackupItemType::File, 30),
+        ];
+        let index = DavCatalogIndex::new(items);
+        let root = index.list_dir("").unwrap();
+        let names: Vec<&str> = root.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "docs", "photos"]);
+        assert_eq!(root[0], DavEntry::file("a.txt", 10, 1700000000));
+        assert_eq!(root[1], DavEntry::dir("docs", 1700000000));
+        assert_eq!(root[2], DavEntry::dir("photos", 0));
+
+        //符号链接不显示,硬链接显示指向的文件的大小,下载时读取指向的文件
+        let docs = index.list_dir("docs").unwrap();
+        assert_eq!(docs, vec![DavEntry::file("b.txt", 20, 1700000000), DavEntry::file("b_link.txt", 20, 1700000000)]);
+        assert_eq!(index.resolve_content_path("docs/b_link.txt"), Some("docs/b.txt".to_string()));
+        assert_eq!(index.resolve_content_path("a.txt"), Some("a.txt".to_string()));
+        assert_eq!(index.list_dir("photos").unwrap(), vec![DavEntry::dir("2024", 0)]);
+        assert!(index.list_dir("missing").is_none());
+        assert!(index.list_dir("a.txt").is_none());
+
+        assert_eq!(index.find_entry("photos/2024"), Some(DavEntry::dir("2024", 0)));
+        assert_eq!(index.find_entry("photos/2024/c.jpg"), Some(DavEntry::file("c.jpg", 30, 1700000000)));
+        assert!(index.find_entry("docs/link").is_none());
+        assert!(index.find_entry("photos/20").is_none());
+    }
+
+    #[test]
+    fn test_check_dav_authorization() {
+        let credentials = vec![credential_fingerprint("secret")];
+        let basic = |user_password: &str| format!("Basic {}", BASE64.encode(user_password));
+        assert!(check_dav_authorization(&credentials, Some(basic("finder:secret").as_str())));
+        assert!(!check_dav_authorization(&credentials, Some(basic("finder:wrong").as_str())));
+        assert!(!check_dav_authorization(&credentials, Some("Bearer secret")));
+        assert!(!check_dav_authorization(&credentials, None));
+        assert!(!check_dav_authorization(&[], Some(basic("finder:secret").as_str())));
+    }
+
+    #[test]
+    fn test_build_multistatus() {
+        let entries = vec![
+            (dav_href("plan_1/ckpt_1/docs", true), DavEntry::dir("docs", 0)),
+            (dav_href("plan_1/ckpt_1/docs/a&b.txt", false), DavEntry::file("a&b.txt", 42, 1700000000)),
+        ];
+        let xml = build_multistatus(&entries);
+        assert!(xml.contains("<D:href>/plan_1/ckpt_1/docs/</D:href>"));
+        assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(xml.contains("<D:href>/plan_1/ckpt_1/docs/a%26b.txt</D:href>"));
+        assert!(xml.contains("<D:displayname>a&amp;b.txt</D:displayname>"));
+        assert!(xml.contains("<D:getcontentlength>42</D:getcontentlength>"));
+        assert!(xml.contains("<D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>"));
+        assert_eq!(xml.matches("<D:response>").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_dav_request_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        //请求头没有发完
+        client.write_all(b"PROPFIND /plan HTTP/1.1\r\nDepth: 1\r\n").await.unwrap();
+        let err = read_dav_request(&mut server, Duration::from_millis(200)).await.err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        //body没有发完
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(b"PROPFIND /plan HTTP/1.1\r\nContent-Length: 100\r\n\r\n<propfind>").await.unwrap();
+        let err = read_dav_request(&mut server, Duration::from_millis(200)).await.err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(b"GET /plan HTTP/1.1\r\nDepth: 0\r\n\r\n").await.unwrap();
+        let request = read_dav_request(&mut server, Duration::from_millis(200)).await.unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.depth.as_deref(), Some("0"));
+    }
+}
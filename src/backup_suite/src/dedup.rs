@@ -0,0 +1,144 @@
+#![allow(unused)]
+//去重统计:逻辑大小是checkpoint中所有item的大小之和(不去重时需要存储的大小),
+//存储大小是这个checkpoint新写入target的内容(target上已经有的chunk,以及checkpoint内重复的内容不计算)
+//每个checkpoint完成时计算一次并保存,按plan/target汇总
+//目前chunk没有压缩,存储大小只体现去重,打包和增量上传的效果
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use buckyos_backup_lib::*;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DedupStat {
+    pub logical_size: u64,
+    pub stored_size: u64,
+    pub checkpoint_count: u64,
+}
+
+impl DedupStat {
+    //existing_chunks为target上其它checkpoint已经引用的chunk_id/quick_hash/pack_chunk_id
+    pub fn build(items: &[BackupItem], existing_chunks: &HashSet<String>) -> Self {
+        let mut logical_size = 0;
+        //写入target的内容 -> 大小,打包的item按在pack中的位置区分
+        let mut stored: HashMap<String, u64> = HashMap::new();
+        for item in items.iter() {
+            //链接和云盘占位文件没有内容
+            if item.item_type.is_metadata_only() {
+                continue;
+            }
+            logical_size += item.size;
+            if let Some(diff_info) = load_item_diff_info(item) {
+                //增量上传的item按chunk计算,和上一个checkpoint相同的chunk不计算
+                for chunk in diff_info.chunks.into_iter() {
+                    if !existing_chunks.contains(&chunk.chunk_id) {
+                        stored.entry(chunk.chunk_id).or_insert(chunk.length);
+                    }
+                }
+                continue;
+            }
+            let location = item.pack_info.as_ref().and_then(|pack_info| PackItemLocation::from_json_str(pack_info.as_str()));
+            let stored_key = match location {
+                Some(location) => {
+                    if existing_chunks.contains(&location.pack_chunk_id) {
+                        continue;
+                    }
+                    format!("{}:{}", location.pack_chunk_id, location.offset)
+                }
+                None => {
+                    let chunk_ids: Vec<&String> = item.chunk_id.iter().chain(item.quick_hash.iter()).collect();
+                    if chunk_ids.is_empty() || chunk_ids.iter().any(|chunk_id| existing_chunks.contains(*chunk_id)) {
+                        continue;
+                    }
+                    chunk_ids[0].clone()
+                }
+            };
+            stored.entry(stored_key).or_insert(item.size);
+        }
+        Self {
+            logical_size,
+            stored_size: stored.values().sum(),
+            checkpoint_count: 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &DedupStat) {
+        self.logical_size += other.logical_size;
+        self.stored_size += other.stored_size;
+        self.checkpoint_count += other.checkpoint_count;
+    }
+
+    pub fn saved_size(&self) -> u64 {
+        self.logical_size.saturating_sub(self.stored_size)
+    }
+
+    //逻辑大小/存储大小,没有存储任何内容时为0
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.stored_size == 0 {
+            return 0.0;
+        }
+        self.logical_size as f64 / self.stored_size as f64
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "logical_size": self.logical_size,
+            "stored_size": self.stored_size,
+            "saved_size": self.saved_size(),
+            "dedup_ratio": self.dedup_ratio(),
+            "checkpoint_count": self.checkpoint_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, chunk_id: &str, size: u64, pack_info: Option<PackItemLocation>) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, size);
+        item.chunk_id = Some(chunk_id.to_string());
+        item.pack_info = pack_info.map(|location| location.to_json_string());
+        item
+    }
+
+    #[test]
+    fn test_build_dedup_stat() {
+        let location = |pack_chunk_id: &str, offset: u64, size: u64| Some(PackItemLocation {
+            pack_chunk_id: pack_chunk_id.to_string(),
+            offset,
+            size,
+        });
+        let items = vec![
+            make_item("a", "c1", 1000, None),
+            make_item("a_copy", "c1", 1000, None),
+            make_item("b", "c2", 2000, None),
+            make_item("small_1", "c3", 10, location("pack1", 0, 10)),
+            make_item("small_2", "c4", 20, location("pack1", 10, 20)),
+            make_item("small_3", "c5", 30, location("pack2", 0, 30)),
+        ];
+        let stat = DedupStat::build(&items, &HashSet::new());
+        assert_eq!(stat.logical_size, 4060);
+        assert_eq!(stat.stored_size, 3060);
+        assert_eq!(stat.saved_size(), 1000);
+
+        let existing_chunks: HashSet<String> = ["c2".to_string(), "pack1".to_string()].into_iter().collect();
+        let mut stat = DedupStat::build(&items, &existing_chunks);
+        assert_eq!(stat.stored_size, 1030);
+        stat.add(&DedupStat::build(&items, &HashSet::new()));
+        assert_eq!(stat.checkpoint_count, 2);
+        assert_eq!(stat.logical_size, 8120);
+        assert_eq!(stat.stored_size, 4090);
+        assert_eq!(DedupStat::default().dedup_ratio(), 0.0);
+
+        //增量上传的item只计算target上没有的chunk
+        let mut delta_item = make_item("vm.img", "c6", 300, None);
+        let mut diff_info = FileDiffInfo::default();
+        diff_info.add_chunk("d1".to_string(), 100, true);
+        diff_info.add_chunk("d2".to_string(), 200, false);
+        delta_item.diff_info = Some(diff_info.to_json_string());
+        let existing_chunks: HashSet<String> = ["d1".to_string()].into_iter().collect();
+        let stat = DedupStat::build(&[delta_item], &existing_chunks);
+        assert_eq!(stat.logical_size, 300);
+        assert_eq!(stat.stored_size, 200);
+    }
+}
@@ -0,0 +1,90 @@
+#![allow(unused)]
+//运行备份前的预估:只枚举source的item(不计算hash,不创建checkpoint),和plan最后一个完成的checkpoint比较,
+//大小和修改时间都没变的item认为内容已经在target上,不需要上传
+//预估的上传量是上限,变化的文件内容也可能在target上已经存在
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use buckyos_backup_lib::*;
+
+//没有历史上传速度时使用的速度(bytes/s)
+pub const DEFAULT_ESTIMATE_SPEED: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupEstimate {
+    pub item_count: u64,
+    pub total_size: u64,
+    pub changed_item_count: u64,//新增或者变化的item
+    pub upload_size: u64,//去重后预计需要上传的大小
+    pub base_checkpoint_id: Option<String>,//用来比较的checkpoint,None表示第一次备份
+    pub speed: u64,
+    pub is_speed_measured: bool,//speed是否来自这个plan之前的备份
+    pub estimated_duration_secs: u64,
+}
+
+impl BackupEstimate {
+    //speed为None时使用DEFAULT_ESTIMATE_SPEED
+    pub fn build(items: &[BackupItem], base_checkpoint_id: Option<String>, base_items: &[BackupItem], speed: Option<u64>) -> Self {
+        let base_items: HashMap<&str, &BackupItem> = base_items.iter()
+            .map(|item| (item.item_id.as_str(), item))
+            .collect();
+        let mut changed_item_count = 0;
+        let mut upload_size = 0;
+        for item in items.iter() {
+            let is_unchanged = base_items.get(item.item_id.as_str()).map_or(false, |base_item| {
+                base_item.size == item.size && base_item.last_modify_time == item.last_modify_time
+                    && base_item.item_type == item.item_type
+            });
+            if is_unchanged {
+                continue;
+            }
+            changed_item_count += 1;
+            //链接和云盘占位文件没有需要上传的内容
+            if !item.item_type.is_metadata_only() {
+                upload_size += item.size;
+            }
+        }
+
+        let is_speed_measured = speed.map_or(false, |speed| speed > 0);
+        let speed = if is_speed_measured { speed.unwrap() } else { DEFAULT_ESTIMATE_SPEED };
+        Self {
+            item_count: items.len() as u64,
+            total_size: items.iter().map(|item| item.size).sum(),
+            changed_item_count,
+            upload_size,
+            base_checkpoint_id,
+            speed,
+            is_speed_measured,
+            estimated_duration_secs: (upload_size + speed - 1) / speed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, size: u64, last_modify_time: u64) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, size);
+        item.last_modify_time = last_modify_time;
+        item
+    }
+
+    #[test]
+    fn test_build_estimate() {
+        let items = vec![make_item("a", 1000, 1), make_item("b", 2000, 2), make_item("c", 3000, 3)];
+        let estimate = BackupEstimate::build(&items, None, &[], None);
+        assert_eq!(estimate.item_count, 3);
+        assert_eq!(estimate.upload_size, 6000);
+        assert!(!estimate.is_speed_measured);
+        assert_eq!(estimate.estimated_duration_secs, 1);
+
+        //a没有变化,b修改过,c是新增的
+        let base_items = vec![make_item("a", 1000, 1), make_item("b", 2000, 1)];
+        let estimate = BackupEstimate::build(&items, Some("chk_1".to_string()), &base_items, Some(1000));
+        assert_eq!(estimate.total_size, 6000);
+        assert_eq!(estimate.changed_item_count, 2);
+        assert_eq!(estimate.upload_size, 5000);
+        assert!(estimate.is_speed_measured);
+        assert_eq!(estimate.estimated_duration_secs, 5);
+    }
+}
@@ -0,0 +1,32 @@
+#![allow(unused)]
+//EventBus/BackupEvent在backup-lib中定义,这里是从engine的task_db类型构造事件的函数
+pub use buckyos_backup_lib::{BackupEvent, BackupEventRecord, EventBus};
+
+use crate::task_db::*;
+
+pub fn task_created_event(task: &WorkTask) -> BackupEvent {
+    BackupEvent::TaskCreated {
+        task_id: task.taskid.clone(),
+        plan_id: task.owner_plan_id.clone(),
+        checkpoint_id: task.checkpoint_id.clone(),
+        task_type: task.task_type.to_string().to_string(),
+    }
+}
+
+pub fn task_progress_event(task: &WorkTask) -> BackupEvent {
+    BackupEvent::TaskProgress {
+        task_id: task.taskid.clone(),
+        completed_item_count: task.completed_item_count,
+        item_count: task.item_count,
+        completed_size: task.transferred_size(),
+        total_size: task.total_size,
+    }
+}
+
+pub fn checkpoint_state_changed_event(checkpoint: &BackupCheckPoint) -> BackupEvent {
+    BackupEvent::CheckPointStateChanged {
+        checkpoint_id: checkpoint.checkpoint_id.clone(),
+        plan_id: checkpoint.owner_plan.clone(),
+        state: checkpoint.state.to_string().to_string(),
+    }
+}
@@ -0,0 +1,66 @@
+#![allow(unused)]
+//plan的排除规则,配置在BackupPlanOptions.exclude_patterns中
+//规则和item路径的每一级分别匹配,所以"*.tmp"排除任何目录下的临时文件,".thumbnails"排除整个目录
+//只支持*(任意个字符)和?(一个字符),区分大小写
+
+pub struct ExcludeFilter {
+    patterns: Vec<String>,
+}
+
+impl ExcludeFilter {
+    pub fn new(patterns: &[String]) -> Self {
+        Self {
+            patterns: patterns.iter().filter(|pattern| !pattern.is_empty()).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_excluded(&self, item_id: &str) -> bool {
+        item_id.split(|c| c == '/' || c == '\\')
+            .filter(|name| !name.is_empty())
+            .any(|name| self.patterns.iter().any(|pattern| wildcard_match(pattern.as_bytes(), name.as_bytes())))
+    }
+}
+
+//回溯到上一个*的位置重新匹配,不需要递归
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_filter() {
+        let filter = ExcludeFilter::new(&["*.tmp".to_string(), "~$*".to_string(), ".thumbnails".to_string(), "cache?".to_string()]);
+        assert!(filter.is_excluded("report.tmp"));
+        assert!(filter.is_excluded("docs/~$report.docx"));
+        assert!(filter.is_excluded("photos/.thumbnails/a.jpg"));
+        assert!(filter.is_excluded("cache1/data"));
+        assert!(!filter.is_excluded("report.tmp.docx"));
+        assert!(!filter.is_excluded("cache12"));
+        assert!(!filter.is_excluded("photos/a.jpg"));
+        assert!(ExcludeFilter::new(&["".to_string()]).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+#![allow(unused)]
+//agent集群状态:注册过的agent(DID绑定)和它们的心跳,给webui的agent列表使用
+//注册token只保存sha256,使用一次后记录使用它的DID;心跳更新last_seen/version/address,
+//超过3个心跳周期没有心跳的agent视为离线,agent上的plan是source为agent://address/...的plan
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use anyhow::Result;
+use buckyos_backup_lib::*;
+
+pub const DEFAULT_AGENT_ENROLL_TOKEN_TTL_MS: u64 = 24 * 3600 * 1000;
+//注册请求和心跳的时间与本机时间的最大偏差,防止截获的请求被重放
+pub const AGENT_REQUEST_MAX_SKEW_MS: u64 = 5 * 60 * 1000;
+const AGENT_OFFLINE_HEARTBEATS: u64 = 3;
+
+pub fn hash_agent_enroll_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn check_agent_request_time(timestamp: u64, now: u64) -> Result<()> {
+    if timestamp.abs_diff(now) > AGENT_REQUEST_MAX_SKEW_MS {
+        return Err(anyhow::anyhow!("agent request time {} is too far from now {}, check the clock of agent", timestamp, now));
+    }
+    Ok(())
+}
+
+pub fn is_agent_online(last_seen: u64, now: u64) -> bool {
+    now.saturating_sub(last_seen) <= AGENT_OFFLINE_HEARTBEATS * AGENT_HEARTBEAT_INTERVAL_SECS * 1000
+}
+
+//plan的source是否在address(host:port)对应的agent上
+pub fn is_agent_plan_source(address: &str, source_url: &str) -> bool {
+    match parse_agent_url(source_url) {
+        Ok((host, port, _)) => format!("{}:{}", host, port) == address,
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentRecord {
+    pub did: String,
+    pub hostname: String,
+    pub address: String,
+    pub version: String,
+    pub enroll_time: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStatus {
+    #[serde(flatten)]
+    pub agent: AgentRecord,
+    pub online: bool,
+    pub plans: Vec<String>,
+    pub pending_plans: Vec<String>,//从未备份过,最近的备份没有完成,或者定时备份已经到期的plan
+}
+
+//agent_plans为(plan_id, source_url, 是否pending)
+pub fn build_agent_status(agent: AgentRecord, agent_plans: &[(String, String, bool)], now: u64) -> AgentStatus {
+    let mut plans = Vec::new();
+    let mut pending_plans = Vec::new();
+    for (plan_id, source_url, pending) in agent_plans.iter() {
+        if !is_agent_plan_source(agent.address.as_str(), source_url.as_str()) {
+            continue;
+        }
+        plans.push(plan_id.clone());
+        if *pending {
+            pending_plans.push(plan_id.clone());
+        }
+    }
+    AgentStatus {
+        online: is_agent_online(agent.last_seen, now),
+        agent,
+        plans,
+        pending_plans,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_status() {
+        let now = 10 * 3600 * 1000;
+        assert!(check_agent_request_time(now - 1000, now).is_ok());
+        assert!(check_agent_request_time(now + AGENT_REQUEST_MAX_SKEW_MS + 1, now).is_err());
+        assert_ne!(hash_agent_enroll_token("token"), "token");
+
+        let agent = AgentRecord {
+            did: "did:dev:abc".to_string(),
+            hostname: "pc1".to_string(),
+            address: "192.168.1.10:5190".to_string(),
+            version: "0.2.0".to_string(),
+            enroll_time: now - 3600 * 1000,
+            last_seen: now - 60 * 1000,
+        };
+        let agent_plans = vec![
+            ("plan_a".to_string(), "agent://192.168.1.10/home/alice".to_string(), false),
+            ("plan_b".to_string(), "agent://192.168.1.10:5190/data".to_string(), true),
+            ("plan_c".to_string(), "agent://192.168.1.11/data".to_string(), true),
+            ("plan_d".to_string(), "file:///data".to_string(), true),
+        ];
+        let status = build_agent_status(agent.clone(), &agent_plans, now);
+        assert!(status.online);
+        assert_eq!(status.plans, vec!["plan_a".to_string(), "plan_b".to_string()]);
+        assert_eq!(status.pending_plans, vec!["plan_b".to_string()]);
+
+        let status = build_agent_status(agent, &agent_plans, now + 3 * 60 * 1000);
+        assert!(!status.online);
+    }
+}
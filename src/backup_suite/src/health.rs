@@ -0,0 +1,122 @@
+#![allow(unused)]
+//给Kubernetes/systemd用的存活检查: GET http://127.0.0.1:5181/health
+//正常返回200,engine后台循环停止/db不可用/磁盘空间不足时返回503,body是各子系统的检查结果
+//cyfs_warp的路由只支持静态目录和kRPC,所以这里单独起一个很简单的http服务
+//没有认证,默认只监听本机,容器里需要探测时在daemon配置中修改health_listen
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use sysinfo::Disks;
+use log::*;
+
+pub const DEFAULT_HEALTH_LISTEN: &str = "127.0.0.1:5181";
+pub const MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+
+//engine后台循环每次运行后更新检查结果,health请求只读取快照,不去锁DEFAULT_ENGINE
+//(engine锁可能被长时间的操作持有,那时health请求也会卡住)
+#[derive(Clone, Default)]
+pub struct HealthSnapshot {
+    snapshot: Arc<RwLock<Option<(u64, bool, serde_json::Value)>>>,//(更新时间ms, 是否健康, 检查结果)
+}
+
+impl HealthSnapshot {
+    pub fn update(&self, now_ms: u64, is_healthy: bool, report: serde_json::Value) {
+        *self.snapshot.write().unwrap() = Some((now_ms, is_healthy, report));
+    }
+
+    //超过max_age_ms没有更新说明后台循环已经停止
+    pub fn get(&self, now_ms: u64, max_age_ms: u64) -> (bool, serde_json::Value) {
+        let snapshot = self.snapshot.read().unwrap().clone();
+        match snapshot {
+            None => (false, serde_json::json!({ "status": "starting" })),
+            Some((update_time, is_healthy, mut report)) => {
+                if now_ms.saturating_sub(update_time) <= max_age_ms {
+                    return (is_healthy, report);
+                }
+                report["status"] = serde_json::json!("error");
+                report["scheduler"]["ok"] = serde_json::json!(false);
+                (false, report)
+            }
+        }
+    }
+}
+
+//path所在磁盘的可用空间,找不到对应的磁盘时返回None
+pub fn get_free_space(path: &Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let disks = Disks::new_with_refreshed_list();
+    disks.list().iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+pub async fn start_health_service(listen_addr: String, snapshot: HealthSnapshot, max_age_ms: u64) {
+    let listener = TcpListener::bind(listen_addr.as_str()).await;
+    if listener.is_err() {
+        error!("start health service at {} failed: {}", listen_addr, listener.err().unwrap());
+        return;
+    }
+    let listener = listener.unwrap();
+    info!("health service listen at {}", listen_addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let snapshot = snapshot.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_health_request(stream, snapshot, max_age_ms).await {
+                        debug!("handle health request error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("health service accept error: {}", e);
+            }
+        }
+    }
+}
+
+async fn handle_health_request(mut stream: TcpStream, snapshot: HealthSnapshot, max_age_ms: u64) -> std::io::Result<()> {
+    let mut buf = vec![0u8; 2048];
+    let read_len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..read_len]);
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let (status, body) = if method == "GET" && (path == "/health" || path.starts_with("/health?")) {
+        let now_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let (is_healthy, report) = snapshot.get(now_ms, max_age_ms);
+        let status = if is_healthy { "200 OK" } else { "503 Service Unavailable" };
+        (status, report.to_string())
+    } else {
+        ("404 Not Found", "{}".to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_snapshot() {
+        let snapshot = HealthSnapshot::default();
+        assert!(!snapshot.get(1000, 100).0);
+        snapshot.update(1000, true, serde_json::json!({ "status": "ok", "scheduler": { "ok": true } }));
+        assert_eq!(snapshot.get(1050, 100), (true, serde_json::json!({ "status": "ok", "scheduler": { "ok": true } })));
+        //后台循环停止后快照不再更新
+        let (is_healthy, report) = snapshot.get(1200, 100);
+        assert!(!is_healthy);
+        assert_eq!(report["status"], "error");
+        assert_eq!(report["scheduler"]["ok"], false);
+    }
+}
@@ -0,0 +1,149 @@
+#![allow(unused)]
+//按目录统计每个checkpoint相对上一个checkpoint的变化量,汇总多个checkpoint得到plan的变化热度图,
+//用来找出变化最频繁的子目录(调整排除规则和保留策略)
+//变化的判断和anomaly一致:item是新增的,或者chunk_id和上一个checkpoint不同
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use buckyos_backup_lib::*;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirChangeStat {
+    pub dir: String,//item_id中最后一个'/'之前的部分,根目录为""
+    pub changed_size: u64,
+    pub changed_item_count: u64,
+    pub deleted_item_count: u64,
+}
+
+fn get_item_dir(item_id: &str) -> &str {
+    let item_id = item_id.trim_start_matches('/');
+    match item_id.rfind('/') {
+        Some(pos) => &item_id[..pos],
+        None => "",
+    }
+}
+
+//只保留前depth级目录
+fn truncate_dir(dir: &str, depth: usize) -> String {
+    if dir.is_empty() {
+        return String::new();
+    }
+    dir.split('/').take(depth).collect::<Vec<&str>>().join("/")
+}
+
+//和上一个checkpoint的item列表比较,返回有变化的目录
+pub fn calc_dir_changes(prev_items: &[BackupItem], items: &[BackupItem]) -> Vec<DirChangeStat> {
+    let prev_chunks: HashMap<&str, Option<&String>> = prev_items.iter()
+        .map(|item| (item.item_id.as_str(), item.chunk_id.as_ref()))
+        .collect();
+    let mut dir_changes: HashMap<&str, DirChangeStat> = HashMap::new();
+    let mut current_ids = HashMap::new();
+    for item in items.iter() {
+        current_ids.insert(item.item_id.as_str(), ());
+        let is_changed = match prev_chunks.get(item.item_id.as_str()) {
+            Some(prev_chunk_id) => *prev_chunk_id != item.chunk_id.as_ref(),
+            None => true,
+        };
+        if !is_changed {
+            continue;
+        }
+        let dir = get_item_dir(item.item_id.as_str());
+        let stat = dir_changes.entry(dir).or_insert_with(|| DirChangeStat { dir: dir.to_string(), ..Default::default() });
+        stat.changed_size += item.size;
+        stat.changed_item_count += 1;
+    }
+    for item in prev_items.iter().filter(|item| !current_ids.contains_key(item.item_id.as_str())) {
+        let dir = get_item_dir(item.item_id.as_str());
+        let stat = dir_changes.entry(dir).or_insert_with(|| DirChangeStat { dir: dir.to_string(), ..Default::default() });
+        stat.deleted_item_count += 1;
+    }
+    let mut dir_changes: Vec<DirChangeStat> = dir_changes.into_values().collect();
+    dir_changes.sort_by(|a, b| a.dir.cmp(&b.dir));
+    dir_changes
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HeatmapEntry {
+    pub dir: String,
+    pub changed_size: u64,
+    pub changed_item_count: u64,
+    pub deleted_item_count: u64,
+    pub checkpoint_count: u64,//有变化的checkpoint数
+}
+
+//汇总多个checkpoint的目录变化,子目录合并到前depth级目录,按变化量从大到小排列
+//records为(checkpoint_id, 目录变化)
+pub fn build_heatmap(records: &[(String, DirChangeStat)], depth: usize) -> Vec<HeatmapEntry> {
+    let mut entries: HashMap<String, HeatmapEntry> = HashMap::new();
+    let mut counted: HashMap<(String, String), ()> = HashMap::new();
+    for (checkpoint_id, stat) in records.iter() {
+        let dir = truncate_dir(stat.dir.as_str(), depth);
+        let entry = entries.entry(dir.clone()).or_insert_with(|| HeatmapEntry { dir: dir.clone(), ..Default::default() });
+        entry.changed_size += stat.changed_size;
+        entry.changed_item_count += stat.changed_item_count;
+        entry.deleted_item_count += stat.deleted_item_count;
+        if counted.insert((dir, checkpoint_id.clone()), ()).is_none() {
+            entry.checkpoint_count += 1;
+        }
+    }
+    let mut entries: Vec<HeatmapEntry> = entries.into_values().collect();
+    entries.sort_by(|a, b| b.changed_size.cmp(&a.changed_size)
+        .then(b.changed_item_count.cmp(&a.changed_item_count))
+        .then(a.dir.cmp(&b.dir)));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, chunk_id: &str, size: u64) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, size);
+        item.chunk_id = Some(chunk_id.to_string());
+        item
+    }
+
+    #[test]
+    fn test_calc_dir_changes() {
+        let prev_items = vec![
+            make_item("a.txt", "c1", 10),
+            make_item("logs/1.log", "c2", 100),
+            make_item("logs/old/0.log", "c3", 100),
+        ];
+        let items = vec![
+            make_item("a.txt", "c1", 10),
+            make_item("logs/1.log", "c4", 200),
+            make_item("logs/2.log", "c5", 300),
+            make_item("data/db/x.db", "c6", 1000),
+        ];
+        let changes = calc_dir_changes(&prev_items, &items);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0], DirChangeStat { dir: "data/db".to_string(), changed_size: 1000, changed_item_count: 1, deleted_item_count: 0 });
+        assert_eq!(changes[1], DirChangeStat { dir: "logs".to_string(), changed_size: 500, changed_item_count: 2, deleted_item_count: 0 });
+        assert_eq!(changes[2].dir, "logs/old");
+        assert_eq!(changes[2].deleted_item_count, 1);
+        assert!(calc_dir_changes(&items, &items).is_empty());
+    }
+
+    #[test]
+    fn test_build_heatmap() {
+        let stat = |dir: &str, changed_size: u64| DirChangeStat { dir: dir.to_string(), changed_size, changed_item_count: 1, deleted_item_count: 0 };
+        let records = vec![
+            ("chk_1".to_string(), stat("logs", 100)),
+            ("chk_1".to_string(), stat("logs/app", 300)),
+            ("chk_2".to_string(), stat("logs/app/debug", 300)),
+            ("chk_2".to_string(), stat("", 50)),
+            ("chk_2".to_string(), stat("data", 1000)),
+        ];
+        let heatmap = build_heatmap(&records, 1);
+        assert_eq!(heatmap.len(), 3);
+        assert_eq!(heatmap[0].dir, "data");
+        assert_eq!(heatmap[1].dir, "logs");
+        assert_eq!(heatmap[1].changed_size, 700);
+        assert_eq!(heatmap[1].changed_item_count, 3);
+        assert_eq!(heatmap[1].checkpoint_count, 2);
+        assert_eq!(heatmap[2].dir, "");
+
+        let heatmap = build_heatmap(&records, 2);
+        assert_eq!(heatmap.iter().find(|entry| entry.dir == "logs/app").unwrap().changed_size, 600);
+    }
+}
@@ -0,0 +1,106 @@
+#![allow(unused)]
+//运行时检测CPU的hash/加密加速指令,结果在health接口的hw_accel中返回
+//具体实现的选择由各个crate在运行时完成:sha2通过cpufeatures选择SHA-NI/ARMv8扩展,
+//blake3自己按AVX-512/AVX2/SSE4.1/NEON分派;这里用同样的规则检测,报告实际生效的路径
+//加密(chunk名的封装)使用ChaCha20Poly1305,不依赖AES指令,所以不报告AES
+use std::sync::OnceLock;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HwAccelInfo {
+    pub arch: &'static str,
+    pub cpu_features: Vec<&'static str>,//检测到的相关指令集
+    pub sha256: &'static str,
+    pub blake3: &'static str,
+}
+
+impl HwAccelInfo {
+    pub fn from_features(arch: &'static str, cpu_features: Vec<&'static str>) -> Self {
+        let has = |feature: &str| cpu_features.contains(&feature);
+        let (sha256, blake3) = match arch {
+            "x86_64" | "x86" => (
+                if has("sha") && has("sse4.1") { "sha_ni" } else { "software" },
+                if has("avx512f") && has("avx512vl") {
+                    "avx512"
+                } else if has("avx2") {
+                    "avx2"
+                } else if has("sse4.1") {
+                    "sse41"
+                } else {
+                    "portable"
+                },
+            ),
+            "aarch64" => (
+                if has("sha2") { "armv8_sha2" } else { "software" },
+                if has("neon") { "neon" } else { "portable" },
+            ),
+            _ => ("software", "portable"),
+        };
+        Self { arch, cpu_features, sha256, blake3 }
+    }
+
+    pub fn is_sha256_accelerated(&self) -> bool {
+        self.sha256 != "software"
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn detect_cpu_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    macro_rules! check {
+        ($($feature:tt),*) => {
+            $(if std::is_x86_feature_detected!($feature) { features.push($feature); })*
+        };
+    }
+    check!("sse2", "sse4.1", "avx2", "avx512f", "avx512vl", "sha");
+    features
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect_cpu_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    macro_rules! check {
+        ($($feature:tt),*) => {
+            $(if std::arch::is_aarch64_feature_detected!($feature) { features.push($feature); })*
+        };
+    }
+    check!("neon", "sha2");
+    features
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")))]
+fn detect_cpu_features() -> Vec<&'static str> {
+    Vec::new()
+}
+
+//检测结果在进程内不会变化,只检测一次
+pub fn get_hw_accel_info() -> &'static HwAccelInfo {
+    static HW_ACCEL_INFO: OnceLock<HwAccelInfo> = OnceLock::new();
+    HW_ACCEL_INFO.get_or_init(|| HwAccelInfo::from_features(std::env::consts::ARCH, detect_cpu_features()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hw_accel_paths() {
+        let info = HwAccelInfo::from_features("x86_64", vec!["sse2", "sse4.1", "avx2", "sha"]);
+        assert_eq!((info.sha256, info.blake3), ("sha_ni", "avx2"));
+        assert!(info.is_sha256_accelerated());
+        //AVX-512需要同时支持F和VL
+        let info = HwAccelInfo::from_features("x86_64", vec!["sse4.1", "avx2", "avx512f"]);
+        assert_eq!((info.sha256, info.blake3), ("software", "avx2"));
+        let info = HwAccelInfo::from_features("x86_64", vec!["sse4.1", "avx2", "avx512f", "avx512vl"]);
+        assert_eq!(info.blake3, "avx512");
+
+        let info = HwAccelInfo::from_features("aarch64", vec!["neon", "sha2"]);
+        assert_eq!((info.sha256, info.blake3), ("armv8_sha2", "neon"));
+        let info = HwAccelInfo::from_features("riscv64", vec![]);
+        assert!(!info.is_sha256_accelerated());
+
+        let info = get_hw_accel_info();
+        assert_eq!(info.arch, std::env::consts::ARCH);
+        assert!(serde_json::to_value(info).unwrap()["sha256"].is_string());
+    }
+}
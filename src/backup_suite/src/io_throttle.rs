@@ -0,0 +1,110 @@
+#![allow(unused)]
+//source端的读取限制:备份数据库等正在使用的source时,限制读取速度,避免影响业务的延迟
+//读取限速对一个task的hash计算和传输线程共享,只限制读取item内容,枚举目录不限制
+//低优先级也通过读取限速实现,不修改进程/线程的cpu和io优先级:task和web控制,其它plan共用tokio的线程,
+//降低线程优先级会影响整个daemon,恢复原来的优先级还需要root权限
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceIoPolicy {
+    pub max_read_bytes_per_sec: u64,//0为不限制
+    pub low_priority: bool,//没有设置max_read_bytes_per_sec时按BACKGROUND_READ_BYTES_PER_SEC限速
+}
+
+pub const BACKGROUND_READ_BYTES_PER_SEC: u64 = 16 * 1024 * 1024;
+
+impl SourceIoPolicy {
+    //task实际使用的读取限速,0为不限制
+    pub fn read_bytes_per_sec(&self) -> u64 {
+        if self.max_read_bytes_per_sec > 0 {
+            return self.max_read_bytes_per_sec;
+        }
+        if self.low_priority { BACKGROUND_READ_BYTES_PER_SEC } else { 0 }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RateLimitState {
+    tokens: i64,//可以为负数,表示已经预支的字节数
+    last_time_ms: u64,
+}
+
+impl RateLimitState {
+    //令牌桶,最多积攒1秒的配额,返回读取bytes之后需要等待的时间(ms)
+    fn reserve(&mut self, bytes_per_sec: u64, bytes: u64, now_ms: u64) -> u64 {
+        let rate = bytes_per_sec as i64;
+        let elapsed = now_ms.saturating_sub(self.last_time_ms) as i64;
+        self.tokens = (self.tokens + elapsed * rate / 1000).min(rate);
+        self.last_time_ms = now_ms;
+        self.tokens -= bytes as i64;
+        if self.tokens >= 0 {
+            return 0;
+        }
+        (-self.tokens * 1000 / rate) as u64
+    }
+}
+
+pub struct ReadRateLimiter {
+    bytes_per_sec: u64,
+    start_time: Instant,
+    state: Mutex<RateLimitState>,
+}
+
+impl ReadRateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            start_time: Instant::now(),
+            state: Mutex::new(RateLimitState::default()),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.bytes_per_sec > 0
+    }
+
+    //从source读取bytes之后调用,超过限制时等待
+    pub async fn acquire(&self, bytes: u64) {
+        if !self.is_limited() || bytes == 0 {
+            return;
+        }
+        let now_ms = self.start_time.elapsed().as_millis() as u64;
+        let wait_ms = self.state.lock().unwrap().reserve(self.bytes_per_sec, bytes, now_ms);
+        if wait_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_rate_limit() {
+        let rate = 1024 * 1024;
+        let mut state = RateLimitState::default();
+        //开始时没有积攒的配额
+        assert_eq!(state.reserve(rate, 512 * 1024, 0), 500);
+        //等待之后配额补齐
+        assert_eq!(state.reserve(rate, 512 * 1024, 500), 500);
+        assert_eq!(state.reserve(rate, 0, 1000), 0);
+        //空闲很久最多积攒1秒的配额
+        assert_eq!(state.reserve(rate, rate, 60 * 1000), 0);
+        assert_eq!(state.reserve(rate, 2 * rate, 60 * 1000), 2000);
+        assert_eq!(state.reserve(rate, 0, 62 * 1000), 0);
+
+        assert_eq!(SourceIoPolicy::default().read_bytes_per_sec(), 0);
+        let policy = SourceIoPolicy { max_read_bytes_per_sec: 0, low_priority: true };
+        assert_eq!(policy.read_bytes_per_sec(), BACKGROUND_READ_BYTES_PER_SEC);
+        let policy = SourceIoPolicy { max_read_bytes_per_sec: rate, low_priority: true };
+        assert_eq!(policy.read_bytes_per_sec(), rate);
+    }
+}
@@ -0,0 +1,54 @@
+#![allow(unused)]
+//法律保留:诉讼/调查期间checkpoint必须原样保留,和pin不同,只有配置的法律保留管理员(凭证指纹)可以设置和解除,
+//保留期间checkpoint和它依赖的checkpoint不能被prune/保留策略删除,所属的plan和target也不能删除
+//所有设置/解除(包括没有权限被拒绝的)都写入审计日志
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegalHold {
+    pub checkpoint_id: String,
+    pub plan_id: String,
+    pub reason: String,//案件编号等,必须填写
+    pub holder: String,//设置者凭证的指纹
+    pub hold_time: u64,
+}
+
+impl LegalHold {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "checkpoint_id": self.checkpoint_id,
+            "plan_id": self.plan_id,
+            "reason": self.reason,
+            "holder": self.holder,
+            "hold_time": self.hold_time,
+        })
+    }
+}
+
+//没有配置管理员时任何人都不能设置或解除法律保留
+pub fn check_legal_hold_officer(officers: &[String], operator: &str) -> Result<(), String> {
+    if officers.is_empty() {
+        return Err("no legal hold officer is configured".to_string());
+    }
+    if !officers.iter().any(|officer| officer == operator) {
+        return Err(format!("{} is not a legal hold officer", operator));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approval::credential_fingerprint;
+
+    #[test]
+    fn test_check_legal_hold_officer() {
+        let officer = credential_fingerprint("token_officer");
+        let operator = credential_fingerprint("token_operator");
+        assert!(check_legal_hold_officer(&[], officer.as_str()).is_err());
+        let officers = vec![officer.clone()];
+        assert!(check_legal_hold_officer(&officers, officer.as_str()).is_ok());
+        let err = check_legal_hold_officer(&officers, operator.as_str()).unwrap_err();
+        assert!(err.contains(operator.as_str()));
+    }
+}
@@ -0,0 +1,104 @@
+#![allow(unused)]
+//BACKUP_SUITE_LOG_FORMAT=json时日志以json行输出到stdout,方便Loki/ELK收集,本地日志文件仍然保留
+//BACKUP_SUITE_OTLP_ENDPOINT设置时把engine的span(prepare/eval/transfer/restore,以及磁盘读/hash/上传/db)导出到OTLP collector
+//代码里仍然使用log的宏,LogTracer把它们转换成tracing事件,
+//这样在task span里输出的日志都会带上task_id/checkpoint_id/plan_id,可以按一次备份过滤
+use buckyos_kit::{get_buckyos_root_dir, init_logging};
+use opentelemetry::KeyValue;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+pub const LOG_FORMAT_ENV: &str = "BACKUP_SUITE_LOG_FORMAT";
+pub const OTLP_ENDPOINT_ENV: &str = "BACKUP_SUITE_OTLP_ENDPOINT";
+
+pub fn init_backup_logging(service_name: &str) {
+    let is_json = std::env::var(LOG_FORMAT_ENV).unwrap_or_default() == "json";
+    let otlp_endpoint = std::env::var(OTLP_ENDPOINT_ENV).ok().filter(|endpoint| !endpoint.is_empty());
+    if !is_json && otlp_endpoint.is_none() {
+        init_logging(service_name);
+        return;
+    }
+
+    if let Err(e) = tracing_log::LogTracer::init() {
+        eprintln!("init log tracer failed: {}", e);
+    }
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let otel_layer = otlp_endpoint.and_then(|endpoint| {
+        match init_otlp_tracer(service_name, endpoint.as_str()) {
+            Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+            Err(e) => {
+                eprintln!("init otlp exporter {} failed: {}", endpoint, e);
+                None
+            }
+        }
+    });
+
+    //stdout改为json/导出span后仍然写本地日志文件,和init_logging的文件位置一致
+    let file_layer = open_log_file(service_name).map(|log_file| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(log_file))
+    });
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(otel_layer)
+        .with(file_layer);
+    if is_json {
+        registry.with(tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_target(true))
+            .init();
+    } else {
+        registry.with(tracing_subscriber::fmt::layer()).init();
+    }
+}
+
+fn open_log_file(service_name: &str) -> Option<std::fs::File> {
+    let log_dir = get_buckyos_root_dir().join("logs");
+    let log_path = log_dir.join(format!("{}.log", service_name));
+    let open_result = std::fs::create_dir_all(&log_dir).and_then(|_| {
+        std::fs::OpenOptions::new().create(true).append(true).open(&log_path)
+    });
+    match open_result {
+        Ok(log_file) => Some(log_file),
+        Err(e) => {
+            eprintln!("open log file {} failed: {}", log_path.to_string_lossy(), e);
+            None
+        }
+    }
+}
+
+fn init_otlp_tracer(service_name: &str, endpoint: &str) -> anyhow::Result<Tracer> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name.to_string())]))
+        .build();
+    let tracer = provider.tracer(service_name.to_string());
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+//进程退出前调用,把还没有导出的span发送出去
+pub fn shutdown_backup_logging() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+pub fn backup_task_span(task_id: &str, checkpoint_id: &str, plan_id: &str) -> Span {
+    tracing::info_span!("backup_task", task_id = %task_id, checkpoint_id = %checkpoint_id, plan_id = %plan_id)
+}
+
+pub fn restore_task_span(task_id: &str, checkpoint_id: &str, plan_id: &str) -> Span {
+    tracing::info_span!("restore_task", task_id = %task_id, checkpoint_id = %checkpoint_id, plan_id = %plan_id)
+}
@@ -1,5 +1,57 @@
+mod anomaly;
+mod approval;
+mod artifact;
+mod audit;
+mod checkpoint_lock;
+mod checkpoint_sign;
+mod chunk_lock;
+mod content_cache;
+mod contents_index;
+mod credential_vault;
+mod daemon_config;
+mod dav_catalog;
+mod dedup;
 mod engine;
+mod estimate;
+mod event_bus;
+mod exclude;
+mod fleet;
+mod health;
+mod heatmap;
+mod hw_accel;
+mod io_throttle;
+mod legal_hold;
+mod logging;
+mod maintenance;
+#[cfg(test)]
+mod mock_target;
+mod ndn_tunnel;
+mod network;
+mod partial_accept;
+mod plan_import;
+mod plan_template;
+mod plan_validate;
+mod plugin_loader;
+mod power;
+mod quota;
+mod reconcile;
+mod reload;
+mod restore_drill;
+mod restore_parallel;
+mod restore_target;
+mod retention;
+mod schedule;
+mod seed;
+mod service;
+mod sla;
+mod snapshot;
+mod spool;
+mod system_manifest;
 mod task_db;
+mod task_journal;
+mod transfer_stat;
+mod wake;
+mod watchdog;
 mod web_control;
 mod work_task;
 
@@ -8,14 +60,137 @@ use web_control::*;
 use buckyos_kit::*;
 use log::*;
 
+fn main() {
+    let matches = clap::Command::new("backup_suite")
+        .subcommand(clap::Command::new("service")
+            .about("manage the backup_suite system service (systemd unit / windows service)")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("install").about("register and start the service"))
+            .subcommand(clap::Command::new("uninstall").about("stop and remove the service"))
+            .subcommand(clap::Command::new("status").about("show the service state"))
+            .subcommand(clap::Command::new("run").hide(true)))
+        .subcommand(clap::Command::new("seed")
+            .about("offline seed of the first full backup")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("import")
+                .about("write the chunks of a seed directory to the target, run it where the target is reachable")
+                .arg(clap::Arg::new("seed_dir").required(true))
+                .arg(clap::Arg::new("target").long("target").help("target url, default is the target of the exported plan"))))
+        .subcommand(clap::Command::new("target")
+            .about("maintain backup targets")
+            .subcommand_required(true)
+            .subcommand(clap::Command::new("upgrade-layout")
+                .about("migrate the data layout of a target to the current version in place")
+                .arg(clap::Arg::new("target_url").required(true)))
+            .subcommand(clap::Command::new("open-key-escrow")
+                .about("print the chunk naming key in an exported key escrow file, the passphrase is read from stdin")
+                .arg(clap::Arg::new("escrow_file").required(true))))
+        .get_matches();
+    if let Some(import_matches) = matches.subcommand_matches("seed").and_then(|seed_matches| seed_matches.subcommand_matches("import")) {
+        let seed_dir = import_matches.get_one::<String>("seed_dir").unwrap().clone();
+        let target_url = import_matches.get_one::<String>("target").cloned();
+        if let Err(err) = run_seed_import(seed_dir, target_url) {
+            eprintln!("import seed failed: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(upgrade_matches) = matches.subcommand_matches("target").and_then(|target_matches| target_matches.subcommand_matches("upgrade-layout")) {
+        let target_url = upgrade_matches.get_one::<String>("target_url").unwrap().clone();
+        if let Err(err) = run_target_layout_upgrade(target_url) {
+            eprintln!("upgrade target layout failed: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(escrow_matches) = matches.subcommand_matches("target").and_then(|target_matches| target_matches.subcommand_matches("open-key-escrow")) {
+        let escrow_file = escrow_matches.get_one::<String>("escrow_file").unwrap().clone();
+        if let Err(err) = run_open_key_escrow(escrow_file) {
+            eprintln!("open key escrow failed: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let service_command = matches.subcommand_matches("service")
+        .and_then(|service_matches| service_matches.subcommand_name())
+        .and_then(service::ServiceCommand::from_str);
+    match service_command {
+        Some(service::ServiceCommand::Run) => {
+            if let Err(err) = service::run_as_service(run_backup_suite) {
+                eprintln!("run backup_suite service failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Some(command) => {
+            match service::run_service_command(command, &get_buckyos_service_data_dir("backup_suite")) {
+                Ok(result) => println!("{}", result),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => run_backup_suite(),
+    }
+}
+
+#[tokio::main]
+async fn run_seed_import(seed_dir: String, target_url: Option<String>) -> anyhow::Result<()> {
+    logging::init_backup_logging("backup_suite_seed");
+    let engine = BackupEngine::new()?;
+    let report = engine.import_seed(seed_dir.as_str(), target_url.as_deref()).await?;
+    println!("{}", report.to_json_value());
+    Ok(())
+}
+
+//service运行时应该通过web接口升级,由engine检查是否有运行中的task
+#[tokio::main]
+async fn run_target_layout_upgrade(target_url: String) -> anyhow::Result<()> {
+    logging::init_backup_logging("backup_suite_upgrade");
+    let engine = BackupEngine::new()?;
+    let (from_version, to_version) = engine.upgrade_target_layout(target_url.as_str()).await?;
+    println!("target {} layout version: {} -> {}", target_url, from_version, to_version);
+    Ok(())
+}
+
+//凭证库丢失后恢复chunk命名密钥:把输出的密钥作为chunk_naming_key加到target url中重新创建plan
+fn run_open_key_escrow(escrow_file: String) -> anyhow::Result<()> {
+    let escrow = buckyos_backup_lib::ChunkKeyEscrow::from_json_str(std::fs::read_to_string(escrow_file.as_str())?.as_str())?;
+    let mut passphrase = String::new();
+    std::io::stdin().read_line(&mut passphrase)?;
+    let namer = escrow.open(passphrase.trim_end_matches(|c| c == '\r' || c == '\n'))?;
+    println!("key_id: {}", escrow.key_id);
+    println!("{}: {}", buckyos_backup_lib::CHUNK_NAMING_KEY_SECRET, namer.to_hex());
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() {
-    init_logging("backup_suite");
+async fn run_backup_suite() {
+    logging::init_backup_logging("backup_suite");
     info!("backup suite start");
+    info!("hw accel: {:?}", hw_accel::get_hw_accel_info());
+    //配置文件格式错误时不能退回到没有保护的默认配置,在第一次使用DEFAULT_ENGINE之前检查并退出
+    if let Err(err) = daemon_config::DaemonConfig::load(&get_buckyos_service_data_dir("backup_suite")) {
+        error!("load daemon config failed: {:#}", err);
+        eprintln!("load daemon config failed: {:#}", err);
+        std::process::exit(1);
+    }
     let engine = DEFAULT_ENGINE.lock().await;
     engine.start().await.unwrap();
+    let health_listen = engine.get_daemon_config().health_listen.clone();
+    let (health_snapshot, health_max_age_ms) = engine.get_health_snapshot();
+    let dav_listen = dav_catalog::get_dav_catalog_listen(engine.get_daemon_config());
+    let dav_credentials = engine.get_daemon_config().dav_credentials.clone();
     drop(engine);
+    tokio::spawn(health::start_health_service(health_listen, health_snapshot, health_max_age_ms));
+    if let Some(dav_listen) = dav_listen {
+        tokio::spawn(dav_catalog::start_dav_catalog_service(dav_listen, dav_credentials));
+    }
+    tokio::spawn(ndn_tunnel::start_ndn_chunk_server(
+        get_buckyos_service_data_dir("backup_suite").join(buckyos_backup_lib::NDN_STORE_DIR),
+        buckyos_backup_lib::DEFAULT_NDN_CHUNK_PORT));
     info!("backup engine start ok,start web control service");
     start_web_control_service().await;
+    logging::shutdown_backup_logging();
 }
 
@@ -0,0 +1,23 @@
+#![allow(unused)]
+//维护模式:迁移存储或者维护task db时开启,禁止创建备份/恢复task,也不启动task(手动resume,定时备份,失败重试和队列中的task)
+//查询状态的接口不受影响;已经在运行的task不会被暂停,需要时手动暂停,关闭维护模式后队列中的task继续启动
+//状态保存在task db的engine_settings中,重启后仍然有效
+use serde::{Serialize, Deserialize};
+
+pub const MAINTENANCE_SETTING_KEY: &str = "maintenance_mode";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceMode {
+    pub reason: String,
+    pub enable_time: u64,
+}
+
+impl MaintenanceMode {
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json_str(s: &str) -> Option<Self> {
+        serde_json::from_str(s).ok()
+    }
+}
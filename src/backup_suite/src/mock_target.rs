@@ -0,0 +1,323 @@
+#![allow(unused)]
+//测试用的target provider(url为mock:///path),数据保存在path下的LocalChunkTargetProvider中,
+//可以按脚本注入延迟/部分写入/TryLater/读取数据损坏/target崩溃,用于engine的暂停/恢复/失败流程测试
+//写故障按open_chunk_writer的调用顺序(从0开始)触发,读故障按open_chunk_reader_for_restore的调用顺序触发,每个故障只触发一次
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Duration;
+use anyhow::Result;
+use url::Url;
+use ndn_lib::{ChunkId, ChunkReader, ChunkWriter};
+use buckyos_backup_lib::*;
+
+pub const MOCK_TARGET_SCHEME: &str = "mock";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockFault {
+    TryLater,//open_chunk_writer返回TryLater
+    PartialWrite(u64),//writer写入这么多字节后返回io错误
+    StallWrite(u64),//writer写入这么多字节后一直阻塞,模拟卡住的连接
+    CorruptRead,//reader返回的第一个字节被修改
+    Crash,//之后target的所有操作都返回错误,直到recover
+    OutOfSpace(u64),//open_chunk_writer返回OutOfSpace,参数为target剩余的空间
+    DiskFullWrite(u64),//writer写入这么多字节后返回StorageFull(ENOSPC)的io错误
+}
+
+#[derive(Debug, Default)]
+pub struct MockTargetState {
+    latency: Option<Duration>,
+    write_faults: HashMap<u64, MockFault>,
+    read_faults: HashMap<u64, MockFault>,
+    write_count: u64,
+    read_count: u64,
+    exist_batch_count: u64,
+    is_crashed: bool,
+    triggered_faults: Vec<MockFault>,
+    removed_chunks: Vec<String>,
+}
+
+pub type SharedMockTargetState = Arc<Mutex<MockTargetState>>;
+
+impl MockTargetState {
+    pub fn new_shared() -> SharedMockTargetState {
+        Arc::new(Mutex::new(MockTargetState::default()))
+    }
+
+    //每个操作都增加的延迟
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = Some(latency);
+    }
+
+    pub fn inject_write_fault(&mut self, chunk_index: u64, fault: MockFault) {
+        self.write_faults.insert(chunk_index, fault);
+    }
+
+    pub fn inject_read_fault(&mut self, read_index: u64, fault: MockFault) {
+        self.read_faults.insert(read_index, fault);
+    }
+
+    pub fn recover(&mut self) {
+        self.is_crashed = false;
+    }
+
+    pub fn write_count(&self) -> u64 {
+        self.write_count
+    }
+
+    pub fn read_count(&self) -> u64 {
+        self.read_count
+    }
+
+    pub fn exist_batch_count(&self) -> u64 {
+        self.exist_batch_count
+    }
+
+    pub fn triggered_faults(&self) -> Vec<MockFault> {
+        self.triggered_faults.clone()
+    }
+
+    pub fn removed_chunks(&self) -> Vec<String> {
+        self.removed_chunks.clone()
+    }
+
+    fn next_write_fault(&mut self) -> Option<MockFault> {
+        let fault = self.write_faults.remove(&self.write_count);
+        self.write_count += 1;
+        self.on_fault(fault)
+    }
+
+    fn next_read_fault(&mut self) -> Option<MockFault> {
+        let fault = self.read_faults.remove(&self.read_count);
+        self.read_count += 1;
+        self.on_fault(fault)
+    }
+
+    fn on_fault(&mut self, fault: Option<MockFault>) -> Option<MockFault> {
+        if let Some(fault) = fault.as_ref() {
+            self.triggered_faults.push(fault.clone());
+            if *fault == MockFault::Crash {
+                self.is_crashed = true;
+            }
+        }
+        fault
+    }
+}
+
+pub struct MockChunkTarget {
+    url: String,
+    inner: LocalChunkTargetProvider,
+    state: SharedMockTargetState,
+}
+
+impl MockChunkTarget {
+    pub async fn new(url: &str, state: SharedMockTargetState) -> Result<Self> {
+        let dir_path = Url::parse(url)?.path().to_string();
+        let inner = LocalChunkTargetProvider::new(dir_path).await?;
+        Ok(Self {
+            url: url.to_string(),
+            inner,
+            state,
+        })
+    }
+
+    pub fn get_provider_desc() -> BackupProviderDesc {
+        BackupProviderDesc::builtin("mock_chunk_target", MOCK_TARGET_SCHEME, BackupProviderKind::Target)
+    }
+
+    pub fn get_provider_creator(state: SharedMockTargetState) -> TargetProviderCreator {
+        Arc::new(move |url: String, _credential: Option<TargetCredential>| {
+            let state = state.clone();
+            Box::pin(async move {
+                let target = MockChunkTarget::new(url.as_str(), state).await?;
+                Ok(Box::new(target) as BackupChunkTargetProvider)
+            }) as TargetProviderFuture
+        })
+    }
+
+    async fn before_op(&self) -> BackupResult<()> {
+        let (latency, is_crashed) = {
+            let state = self.state.lock().unwrap();
+            (state.latency, state.is_crashed)
+        };
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        if is_crashed {
+            return Err(BuckyBackupError::Failed("mock target crashed".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IBackupChunkTargetProvider for MockChunkTarget {
+    async fn get_target_info(&self) -> Result<String> {
+        self.before_op().await?;
+        Ok(format!("mock target {}", self.url))
+    }
+
+    fn get_target_url(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn get_account_session_info(&self) -> Result<String> {
+        Ok(String::new())
+    }
+
+    async fn set_account_session_info(&self, session_info: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
+        self.before_op().await?;
+        self.inner.is_chunk_exist(chunk_id).await
+    }
+
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId]) -> Result<Vec<(bool, u64)>> {
+        self.before_op().await?;
+        self.state.lock().unwrap().exist_batch_count += 1;
+        self.inner.is_chunk_exist_batch(chunk_ids).await
+    }
+
+    async fn open_chunk_writer(&self, chunk_id: &ChunkId, offset: u64, size: u64) -> BackupResult<(ChunkWriter, u64)> {
+        self.before_op().await?;
+        let fault = self.state.lock().unwrap().next_write_fault();
+        match fault {
+            Some(MockFault::TryLater) => {
+                Err(BuckyBackupError::TryLater(format!("mock try later: {}", chunk_id.to_string())))
+            }
+            Some(MockFault::Crash) => {
+                Err(BuckyBackupError::Failed("mock target crashed".to_string()))
+            }
+            Some(MockFault::OutOfSpace(available)) => {
+                Err(BuckyBackupError::OutOfSpace { required: size.saturating_sub(offset), available })
+            }
+            Some(MockFault::PartialWrite(limit)) => {
+                let (writer, init_offset) = self.inner.open_chunk_writer(chunk_id, offset, size).await?;
+                Ok((Box::pin(PartialChunkWriter { inner: writer, remain: limit, is_stall: false, error_kind: io::ErrorKind::BrokenPipe }), init_offset))
+            }
+            Some(MockFault::StallWrite(limit)) => {
+                let (writer, init_offset) = self.inner.open_chunk_writer(chunk_id, offset, size).await?;
+                Ok((Box::pin(PartialChunkWriter { inner: writer, remain: limit, is_stall: true, error_kind: io::ErrorKind::BrokenPipe }), init_offset))
+            }
+            Some(MockFault::DiskFullWrite(limit)) => {
+                let (writer, init_offset) = self.inner.open_chunk_writer(chunk_id, offset, size).await?;
+                Ok((Box::pin(PartialChunkWriter { inner: writer, remain: limit, is_stall: false, error_kind: io::ErrorKind::StorageFull }), init_offset))
+            }
+            _ => self.inner.open_chunk_writer(chunk_id, offset, size).await,
+        }
+    }
+
+    async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        self.before_op().await?;
+        self.inner.complete_chunk_writer(chunk_id).await
+    }
+
+    async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
+        self.before_op().await?;
+        self.inner.link_chunkid(source_chunk_id, new_chunk_id).await
+    }
+
+    async fn query_link_target(&self, source_chunk_id: &ChunkId) -> BackupResult<Option<ChunkId>> {
+        self.before_op().await?;
+        self.inner.query_link_target(source_chunk_id).await
+    }
+
+    async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset: u64) -> BackupResult<ChunkReader> {
+        self.before_op().await?;
+        let fault = self.state.lock().unwrap().next_read_fault();
+        match fault {
+            Some(MockFault::TryLater) => {
+                Err(BuckyBackupError::TryLater(format!("mock try later: {}", chunk_id.to_string())))
+            }
+            Some(MockFault::Crash) => {
+                Err(BuckyBackupError::Failed("mock target crashed".to_string()))
+            }
+            Some(MockFault::CorruptRead) => {
+                let reader = self.inner.open_chunk_reader_for_restore(chunk_id, offset).await?;
+                Ok(Box::pin(CorruptChunkReader { inner: reader, is_corrupted: false }))
+            }
+            _ => self.inner.open_chunk_reader_for_restore(chunk_id, offset).await,
+        }
+    }
+
+    //底层的LocalChunkTargetProvider不能删除chunk,只记录要删除的chunk
+    async fn remove_chunk(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        self.before_op().await?;
+        self.state.lock().unwrap().removed_chunks.push(chunk_id.to_string());
+        Ok(())
+    }
+
+    async fn read_layout_marker(&self) -> BackupResult<Option<String>> {
+        self.before_op().await?;
+        self.inner.read_layout_marker().await
+    }
+
+    async fn write_layout_marker(&self, marker: &str) -> BackupResult<()> {
+        self.before_op().await?;
+        self.inner.write_layout_marker(marker).await
+    }
+
+    async fn upgrade_layout(&self, from_version: u32) -> BackupResult<()> {
+        self.before_op().await?;
+        self.inner.upgrade_layout(from_version).await
+    }
+}
+
+struct PartialChunkWriter {
+    inner: ChunkWriter,
+    remain: u64,
+    is_stall: bool,//写满remain后一直返回Pending(不会被唤醒),否则返回io错误
+    error_kind: io::ErrorKind,
+}
+
+impl AsyncWrite for PartialChunkWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.remain == 0 {
+            if this.is_stall {
+                return Poll::Pending;
+            }
+            return Poll::Ready(Err(io::Error::new(this.error_kind, "mock partial write")));
+        }
+        let len = buf.len().min(this.remain as usize);
+        let result = this.inner.as_mut().poll_write(cx, &buf[..len]);
+        if let Poll::Ready(Ok(write_len)) = &result {
+            this.remain -= *write_len as u64;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+struct CorruptChunkReader {
+    inner: ChunkReader,
+    is_corrupted: bool,
+}
+
+impl AsyncRead for CorruptChunkReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled_len = buf.filled().len();
+        let result = this.inner.as_mut().poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &result {
+            if !this.is_corrupted && buf.filled().len() > filled_len {
+                buf.filled_mut()[filled_len] ^= 0xff;
+                this.is_corrupted = true;
+            }
+        }
+        result
+    }
+}
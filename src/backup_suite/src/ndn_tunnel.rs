@@ -0,0 +1,74 @@
+#![allow(unused)]
+//ndn:// target通过buckyos zone的rtcp隧道连接存储节点:rtcp://<设备名>/127.0.0.1:<端口>
+//隧道由设备主动连接zone gateway建立,NAT后的设备不需要端口映射,存储节点上的NdnChunkServer只监听本机地址
+//NdnChunkServer没有认证,只能由隧道转发进来(zone内的设备才能建立隧道),node是本机时直接TCP连接
+//局域网内的其它设备也要使用设备名通过隧道连接,ip地址的node会被拒绝
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use url::Url;
+use log::*;
+use buckyos_backup_lib::*;
+
+pub const NDN_CHUNK_SERVER_LISTEN_HOST: &str = "127.0.0.1";
+
+pub struct ZoneTunnelStreamOpener;
+
+impl ZoneTunnelStreamOpener {
+    pub fn is_local_node(node: &str) -> bool {
+        node == "localhost" || node.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+    }
+
+    pub fn is_ip_node(node: &str) -> bool {
+        node.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().is_ok()
+    }
+
+    pub fn get_tunnel_url(node: &str, port: u16) -> String {
+        format!("rtcp://{}/{}:{}", node, NDN_CHUNK_SERVER_LISTEN_HOST, port)
+    }
+}
+
+#[async_trait]
+impl NdnStreamOpener for ZoneTunnelStreamOpener {
+    async fn open_stream(&self, node: &str, port: u16) -> BackupResult<Box<dyn NdnStream>> {
+        if Self::is_local_node(node) {
+            return TcpNdnStreamOpener.open_stream(node, port).await;
+        }
+        if Self::is_ip_node(node) {
+            return Err(BuckyBackupError::Failed(format!("ndn node {} must be a zone device name, chunk server only accepts connections through the zone tunnel", node)));
+        }
+        let tunnel_url = Self::get_tunnel_url(node, port);
+        let url = Url::parse(tunnel_url.as_str())
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid tunnel url {}: {}", tunnel_url, e)))?;
+        debug!("open ndn stream by tunnel {}", tunnel_url);
+        let stream = cyfs_gateway_lib::open_stream_by_url(&url).await
+            .map_err(|e| BuckyBackupError::TryLater(format!("open tunnel {} failed: {}", tunnel_url, e)))?;
+        Ok(Box::new(stream))
+    }
+}
+
+//存储节点上接收其它设备的chunk,store_root下每个store一个目录
+pub async fn start_ndn_chunk_server(store_root: PathBuf, port: u16) {
+    let server = Arc::new(NdnChunkServer::new(store_root));
+    let listen_addr = format!("{}:{}", NDN_CHUNK_SERVER_LISTEN_HOST, port);
+    if let Err(e) = server.run(listen_addr.as_str()).await {
+        error!("ndn chunk server on {} exit: {}", listen_addr, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ndn_tunnel_url() {
+        assert!(ZoneTunnelStreamOpener::is_local_node("localhost"));
+        assert!(ZoneTunnelStreamOpener::is_local_node("[::1]"));
+        assert!(!ZoneTunnelStreamOpener::is_local_node("192.168.1.10"));
+        assert!(ZoneTunnelStreamOpener::is_ip_node("192.168.1.10"));
+        assert!(!ZoneTunnelStreamOpener::is_local_node("nas1"));
+        assert!(!ZoneTunnelStreamOpener::is_ip_node("nas1"));
+        assert_eq!(ZoneTunnelStreamOpener::get_tunnel_url("nas1", DEFAULT_NDN_CHUNK_PORT), "rtcp://nas1/127.0.0.1:5191");
+    }
+}
@@ -0,0 +1,164 @@
+#![allow(unused)]
+//网络状况感知:plan配置了network策略时,离线或者使用按流量计费的网络(手机热点等)时不启动备份,
+//运行中的备份task会被暂停,网络恢复后engine自动resume
+//在线状态通过TCP连接探测地址判断(探测地址可以配置),计费网络目前只在linux上通过NetworkManager判断
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+pub const DEFAULT_NETWORK_PROBE_ADDRS: [&str; 2] = ["1.1.1.1:443", "8.8.8.8:53"];
+const NETWORK_PROBE_TIMEOUT_SECS: u64 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkPolicy {
+    pub pause_on_metered: bool,//计费网络下不备份
+    pub pause_when_offline: bool,//离线时不备份(target在本机时不需要)
+}
+
+impl NetworkPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.pause_on_metered || self.pause_when_offline
+    }
+
+    //当前网络下不能备份时返回原因
+    pub fn check(&self, state: &NetworkState) -> Option<String> {
+        if self.pause_when_offline && !state.is_online {
+            return Some("network is offline".to_string());
+        }
+        if self.pause_on_metered && state.is_metered {
+            return Some("network is metered".to_string());
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkState {
+    pub is_online: bool,
+    pub is_metered: bool,
+}
+
+impl Default for NetworkState {
+    fn default() -> Self {
+        Self {
+            is_online: true,
+            is_metered: false,
+        }
+    }
+}
+
+#[async_trait]
+pub trait NetworkMonitor: Send + Sync {
+    async fn get_network_state(&self) -> NetworkState;
+}
+
+pub struct SystemNetworkMonitor {
+    probe_addrs: Vec<String>,//host:port,任何一个可以连接就认为在线
+}
+
+impl SystemNetworkMonitor {
+    pub fn new(probe_addrs: Vec<String>) -> Self {
+        Self { probe_addrs }
+    }
+}
+
+impl Default for SystemNetworkMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_NETWORK_PROBE_ADDRS.iter().map(|addr| addr.to_string()).collect())
+    }
+}
+
+#[async_trait]
+impl NetworkMonitor for SystemNetworkMonitor {
+    async fn get_network_state(&self) -> NetworkState {
+        let mut is_online = false;
+        for addr in self.probe_addrs.iter() {
+            let result = tokio::time::timeout(Duration::from_secs(NETWORK_PROBE_TIMEOUT_SECS), TcpStream::connect(addr.as_str())).await;
+            if matches!(result, Ok(Ok(_))) {
+                is_online = true;
+                break;
+            }
+        }
+        NetworkState {
+            is_online,
+            is_metered: is_online && is_metered_connection().await,
+        }
+    }
+}
+
+//NetworkManager的Metered属性: 0未知 1是 2否 3猜测是 4猜测否,busctl输出为"u 1"
+#[cfg(target_os = "linux")]
+async fn is_metered_connection() -> bool {
+    let output = tokio::process::Command::new("busctl")
+        .args(["get-property", "org.freedesktop.NetworkManager", "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager", "Metered"])
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => parse_nm_metered(String::from_utf8_lossy(&output.stdout).as_ref()),
+        _ => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn is_metered_connection() -> bool {
+    false
+}
+
+fn parse_nm_metered(output: &str) -> bool {
+    matches!(output.trim(), "u 1" | "u 3")
+}
+
+//测试时模拟网络变化
+pub struct SimulatedNetworkMonitor {
+    state: Mutex<NetworkState>,
+}
+
+impl SimulatedNetworkMonitor {
+    pub fn new(state: NetworkState) -> Self {
+        Self { state: Mutex::new(state) }
+    }
+
+    pub fn set_state(&self, state: NetworkState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+#[async_trait]
+impl NetworkMonitor for SimulatedNetworkMonitor {
+    async fn get_network_state(&self) -> NetworkState {
+        *self.state.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_policy_check() {
+        let offline = NetworkState { is_online: false, is_metered: false };
+        let metered = NetworkState { is_online: true, is_metered: true };
+        let policy = NetworkPolicy::default();
+        assert!(!policy.is_enabled());
+        assert!(policy.check(&offline).is_none());
+        assert!(policy.check(&metered).is_none());
+
+        let policy: NetworkPolicy = serde_json::from_str(r#"{"pause_on_metered":true}"#).unwrap();
+        assert!(policy.is_enabled());
+        assert!(policy.check(&offline).is_none());
+        assert_eq!(policy.check(&metered).unwrap(), "network is metered");
+        assert!(policy.check(&NetworkState::default()).is_none());
+
+        let policy = NetworkPolicy { pause_on_metered: true, pause_when_offline: true };
+        assert_eq!(policy.check(&offline).unwrap(), "network is offline");
+
+        assert!(parse_nm_metered("u 1\n"));
+        assert!(parse_nm_metered("u 3"));
+        assert!(!parse_nm_metered("u 4"));
+        assert!(!parse_nm_metered("u 0"));
+    }
+}
@@ -0,0 +1,63 @@
+#![allow(unused)]
+//部分完成:个别文件一直读取失败(被其它进程锁定/没有权限)时,不让整个备份失败
+//plan开启后读取失败的item标记为Failed并跳过,所有其它item完成后,失败的数量在限制内时checkpoint仍然完成,
+//并记录失败的item数量(done with errors),失败的item不能恢复,可以在修复权限后通过retry_failed_items重新备份
+//失败的比例超过限制时task失败,按plan的重试策略重试
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartialAcceptPolicy {
+    pub enabled: bool,
+    pub max_failed_percent: f64,//失败的item占checkpoint所有item的最大百分比
+    pub max_failed_items: u64,//失败的item的最大数量,0为不限制
+}
+
+impl Default for PartialAcceptPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_failed_percent: 1.0,
+            max_failed_items: 100,
+        }
+    }
+}
+
+impl PartialAcceptPolicy {
+    //checkpoint的total_count个item中有failed_count个失败时,能否完成
+    pub fn accept(&self, failed_count: u64, total_count: u64) -> bool {
+        if failed_count == 0 {
+            return true;
+        }
+        if !self.enabled || total_count == 0 {
+            return false;
+        }
+        if self.max_failed_items > 0 && failed_count > self.max_failed_items {
+            return false;
+        }
+        failed_count as f64 * 100.0 <= self.max_failed_percent * total_count as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_accept() {
+        let policy = PartialAcceptPolicy::default();
+        assert!(policy.accept(0, 100));
+        assert!(!policy.accept(1, 1000));
+
+        let policy = PartialAcceptPolicy { enabled: true, ..Default::default() };
+        assert!(policy.accept(1, 100));
+        assert!(!policy.accept(2, 100));
+        assert!(policy.accept(100, 10000));
+        //超过数量限制
+        assert!(!policy.accept(101, 100000));
+
+        let policy = PartialAcceptPolicy { enabled: true, max_failed_percent: 50.0, max_failed_items: 0 };
+        assert!(policy.accept(5000, 10000));
+        assert!(!policy.accept(5001, 10000));
+    }
+}
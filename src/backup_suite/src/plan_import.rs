@@ -0,0 +1,727 @@
+#![allow(unused)]
+//从其它备份工具的配置导入plan,方便用户迁移:
+//restic: 环境变量文件/脚本/crontab,包含RESTIC_REPOSITORY,restic backup和restic forget命令
+//duplicati: 导出的备份任务json(单个任务或者数组)
+//rclone: rclone.conf中的remote定义,加上crontab/脚本中的rclone sync/copy命令(rclone.conf本身没有source)
+//只导入source,定时,保留策略,排除规则和target的映射,不转换已有的备份数据:
+//本地target使用原目录加上LOCAL_TARGET_SUFFIX的新目录,s3 target使用同一个bucket,原工具的数据保持不变
+//密钥以url参数的形式生成,创建plan时和其它plan一样移到凭证库
+use std::collections::HashMap;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use url::Url;
+use buckyos_backup_lib::TargetUrl;
+
+use crate::retention::RetentionPolicy;
+use crate::schedule::BackupSchedulePolicy;
+use crate::task_db::BackupPlanConfig;
+
+pub const LOCAL_TARGET_SUFFIX: &str = "-bucky_backup";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanImportFormat {
+    Restic,
+    Duplicati,
+    Rclone,
+}
+
+impl PlanImportFormat {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "restic" => Some(Self::Restic),
+            "duplicati" => Some(Self::Duplicati),
+            "rclone" => Some(Self::Rclone),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Restic => "restic",
+            Self::Duplicati => "duplicati",
+            Self::Rclone => "rclone",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedPlan {
+    pub plan: BackupPlanConfig,
+    pub notes: Vec<String>,//没有完全对应的配置,需要用户确认
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlanImportResult {
+    pub plans: Vec<ImportedPlan>,
+    pub warnings: Vec<String>,//无法导入的任务
+}
+
+//crontab中的时间是本机时区,schedule使用UTC时间,按导入时本机的时区偏移转换
+pub fn import_plans(format: PlanImportFormat, content: &str) -> Result<PlanImportResult> {
+    let utc_offset_minutes = chrono::Local::now().offset().local_minus_utc() / 60;
+    import_plans_with_offset(format, content, utc_offset_minutes)
+}
+
+fn import_plans_with_offset(format: PlanImportFormat, content: &str, utc_offset_minutes: i32) -> Result<PlanImportResult> {
+    match format {
+        PlanImportFormat::Restic => Ok(import_restic(content, utc_offset_minutes)),
+        PlanImportFormat::Duplicati => import_duplicati(content),
+        PlanImportFormat::Rclone => Ok(import_rclone(content, utc_offset_minutes)),
+    }
+}
+
+fn new_imported_plan(format: PlanImportFormat, source_path: &str, target_url: &str, title: &str) -> BackupPlanConfig {
+    let source_url = format!("file://{}", source_path.trim_end_matches('/'));
+    BackupPlanConfig::chunk2chunk(source_url.as_str(), target_url, title, format!("imported from {}", format.as_str()).as_str())
+}
+
+fn local_target_url(path: &str, notes: &mut Vec<String>) -> String {
+    let target_path = format!("{}{}", path.trim_end_matches('/'), LOCAL_TARGET_SUFFIX);
+    notes.push(format!("backup data is stored in {}, existing data in {} is not converted", target_path, path));
+    format!("file://{}", target_path)
+}
+
+//目前s3 target把chunk保存在bucket的根目录,不支持自定义endpoint
+fn s3_target_url(bucket: &str, prefix: &str, secrets: &HashMap<&str, String>, notes: &mut Vec<String>) -> Result<String, String> {
+    let mut url = Url::parse(format!("s3://{}", bucket).as_str()).map_err(|e| format!("invalid bucket {}: {}", bucket, e))?;
+    for name in ["region", "access_key", "secret_key", "session_token"] {
+        if let Some(value) = secrets.get(name).filter(|value| !value.is_empty()) {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+    }
+    let prefix = prefix.trim_matches('/');
+    if !prefix.is_empty() {
+        notes.push(format!("prefix {} is ignored, backup data is stored in the root of bucket {}", prefix, bucket));
+    }
+    TargetUrl::parse(url.as_str()).map_err(|e| e.to_string())?;
+    Ok(url.to_string())
+}
+
+//按空白分割,支持单引号和双引号
+fn split_command_line(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut has_arg = false;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                has_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if has_arg || !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            None => current.push(c),
+        }
+    }
+    if has_arg || !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+//crontab的一行分成(时间表达式,命令),不是crontab格式时时间表达式为None
+fn split_cron_line(line: &str) -> (Option<String>, String) {
+    let line = line.trim();
+    if line.starts_with('@') {
+        let (spec, command) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        return (Some(spec.to_string()), command.trim().to_string());
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let is_cron_field = |field: &str| field.chars().all(|c| c.is_ascii_digit() || "*/,-".contains(c));
+    if fields.len() > 5 && fields[..5].iter().all(|field| is_cron_field(field)) {
+        return (Some(fields[..5].join(" ")), fields[5..].join(" "));
+    }
+    (None, line.to_string())
+}
+
+//只支持每天/每周固定时间和每N小时,其它的表达式返回None
+//utc_offset_minutes是cron所在时区相对UTC的偏移(东八区为480),Daily/Weekly的时间转换成UTC
+fn cron_to_schedule(spec: &str, utc_offset_minutes: i32) -> Option<BackupSchedulePolicy> {
+    match spec {
+        "@daily" | "@midnight" => return Some(local_daily_to_utc(0, 0, utc_offset_minutes)),
+        "@weekly" => return Some(local_weekly_to_utc(6, 0, 0, utc_offset_minutes)),
+        "@hourly" => return Some(BackupSchedulePolicy::Period { interval_secs: 3600 }),
+        _ => {}
+    }
+    let fields: Vec<&str> = spec.split_whitespace().collect();
+    if fields.len() != 5 || fields[2] != "*" || fields[3] != "*" {
+        return None;
+    }
+    let minute: u32 = fields[0].parse().ok()?;
+    if minute >= 60 {
+        return None;
+    }
+    if let Some(hours) = fields[1].strip_prefix("*/") {
+        let hours: u64 = hours.parse().ok()?;
+        return if fields[4] == "*" { Some(BackupSchedulePolicy::Period { interval_secs: hours * 3600 }) } else { None };
+    }
+    let hour: u32 = fields[1].parse().ok()?;
+    if hour >= 24 {
+        return None;
+    }
+    if fields[4] == "*" {
+        return Some(local_daily_to_utc(hour, minute, utc_offset_minutes));
+    }
+    //cron中0和7是周日,1是周一;schedule中0是周一
+    let cron_weekday: u32 = fields[4].parse().ok()?;
+    if cron_weekday > 7 {
+        return None;
+    }
+    Some(local_weekly_to_utc((cron_weekday + 6) % 7, hour, minute, utc_offset_minutes))
+}
+
+//返回本地时间对应的UTC(天的偏移,小时,分钟),天的偏移为-1/0/1
+fn local_time_to_utc(hour: u32, minute: u32, utc_offset_minutes: i32) -> (i32, u32, u32) {
+    let utc_minutes = (hour * 60 + minute) as i32 - utc_offset_minutes;
+    let day_shift = utc_minutes.div_euclid(24 * 60);
+    let utc_minutes = utc_minutes.rem_euclid(24 * 60) as u32;
+    (day_shift, utc_minutes / 60, utc_minutes % 60)
+}
+
+fn local_daily_to_utc(hour: u32, minute: u32, utc_offset_minutes: i32) -> BackupSchedulePolicy {
+    let (_, hour, minute) = local_time_to_utc(hour, minute, utc_offset_minutes);
+    BackupSchedulePolicy::Daily { hour, minute }
+}
+
+fn local_weekly_to_utc(weekday: u32, hour: u32, minute: u32, utc_offset_minutes: i32) -> BackupSchedulePolicy {
+    let (day_shift, hour, minute) = local_time_to_utc(hour, minute, utc_offset_minutes);
+    let weekday = (weekday as i32 + day_shift).rem_euclid(7) as u32;
+    BackupSchedulePolicy::Weekly { weekday, hour, minute }
+}
+
+fn apply_cron_schedule(plan: &mut BackupPlanConfig, cron_spec: Option<&String>, utc_offset_minutes: i32, notes: &mut Vec<String>) {
+    if let Some(cron_spec) = cron_spec {
+        match cron_to_schedule(cron_spec.as_str(), utc_offset_minutes) {
+            Some(policy) => {
+                //按导入时的偏移转换,有夏令时的时区切换后会差一个小时
+                if utc_offset_minutes != 0 && !matches!(policy, BackupSchedulePolicy::Period { .. }) {
+                    notes.push(format!("cron schedule '{}' is converted from local time to UTC with offset {} minutes, it may be off by an hour after a daylight saving change",
+                        cron_spec, utc_offset_minutes));
+                }
+                plan.options.schedule = vec![policy];
+            }
+            None => notes.push(format!("cron schedule '{}' is not supported, plan has no schedule", cron_spec)),
+        }
+    }
+}
+
+//"--name value"和"--name=value"两种写法
+fn take_flag_value(args: &[String], i: &mut usize, names: &[&str]) -> Option<String> {
+    let arg = args[*i].as_str();
+    for name in names.iter() {
+        if arg == *name {
+            *i += 1;
+            return Some(args.get(*i).cloned().unwrap_or_default());
+        }
+        if let Some(value) = arg.strip_prefix(format!("{}=", name).as_str()) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+fn parse_env_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim().strip_prefix("export ").unwrap_or(line.trim());
+    let (key, value) = line.split_once('=')?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    let value = split_command_line(value).into_iter().next().unwrap_or_default();
+    Some((key.to_string(), value))
+}
+
+struct ResticBackupJob {
+    repo: Option<String>,
+    paths: Vec<String>,
+    excludes: Vec<String>,
+    cron_spec: Option<String>,
+}
+
+fn import_restic(content: &str, utc_offset_minutes: i32) -> PlanImportResult {
+    let mut result = PlanImportResult::default();
+    let mut env: HashMap<String, String> = HashMap::new();
+    let mut jobs = Vec::new();
+    let mut retention: Option<RetentionPolicy> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = parse_env_line(line) {
+            env.insert(key, value);
+            continue;
+        }
+        let (cron_spec, command) = split_cron_line(line);
+        let args = split_command_line(command.as_str());
+        let restic_pos = match args.iter().position(|arg| arg == "restic" || arg.ends_with("/restic")) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let mut repo = None;
+        let mut sub_command = None;
+        let mut paths = Vec::new();
+        let mut excludes = Vec::new();
+        let mut policy = RetentionPolicy::default();
+        let mut i = restic_pos + 1;
+        while i < args.len() {
+            if let Some(value) = take_flag_value(&args, &mut i, &["-r", "--repo"]) {
+                repo = Some(value);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["-e", "--exclude"]) {
+                excludes.push(value);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["--keep-last"]) {
+                policy.keep_last = value.parse().unwrap_or(0);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["--keep-daily"]) {
+                policy.keep_daily = value.parse().unwrap_or(0);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["--keep-weekly"]) {
+                policy.keep_weekly = value.parse().unwrap_or(0);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["--keep-monthly"]) {
+                policy.keep_monthly = value.parse().unwrap_or(0);
+            } else if let Some(value) = take_flag_value(&args, &mut i, &["--keep-yearly"]) {
+                policy.keep_yearly = value.parse().unwrap_or(0);
+            } else if args[i].starts_with('-') {
+                //其它不影响plan的参数
+            } else if sub_command.is_none() {
+                sub_command = Some(args[i].clone());
+            } else {
+                paths.push(args[i].clone());
+            }
+            i += 1;
+        }
+        match sub_command.as_deref() {
+            Some("backup") => jobs.push(ResticBackupJob { repo, paths, excludes, cron_spec }),
+            Some("forget") if policy.is_enabled() => retention = Some(policy),
+            _ => {}
+        }
+    }
+
+    let mut secrets: HashMap<&str, String> = HashMap::new();
+    for (name, env_name) in [("region", "AWS_DEFAULT_REGION"), ("region", "AWS_REGION"), ("access_key", "AWS_ACCESS_KEY_ID"),
+        ("secret_key", "AWS_SECRET_ACCESS_KEY"), ("session_token", "AWS_SESSION_TOKEN")] {
+        if let Some(value) = env.get(env_name) {
+            secrets.entry(name).or_insert(value.clone());
+        }
+    }
+    for job in jobs.into_iter() {
+        let repo = match job.repo.or_else(|| env.get("RESTIC_REPOSITORY").cloned()) {
+            Some(repo) => repo,
+            None => {
+                result.warnings.push(format!("restic backup of {:?} has no repository", job.paths));
+                continue;
+            }
+        };
+        for path in job.paths.iter() {
+            let mut notes = Vec::new();
+            let target_url = if let Some(s3_repo) = repo.strip_prefix("s3:") {
+                //s3:s3.amazonaws.com/bucket/prefix 或 s3:https://host/bucket/prefix
+                let s3_repo = s3_repo.trim_start_matches("https://").trim_start_matches("http://");
+                let mut parts = s3_repo.splitn(3, '/');
+                let host = parts.next().unwrap_or_default();
+                let bucket = parts.next().unwrap_or_default();
+                if !host.ends_with("amazonaws.com") {
+                    notes.push(format!("s3 endpoint {} is not supported, plan uses aws s3", host));
+                }
+                if bucket.is_empty() {
+                    Err(format!("restic repository {} has no bucket", repo))
+                } else {
+                    s3_target_url(bucket, parts.next().unwrap_or_default(), &secrets, &mut notes)
+                }
+            } else if let Some(local_repo) = repo.strip_prefix("local:").or(Some(repo.as_str()).filter(|repo| repo.starts_with('/'))) {
+                Ok(local_target_url(local_repo, &mut notes))
+            } else {
+                Err(format!("restic repository {} is not supported", repo))
+            };
+            let target_url = match target_url {
+                Ok(target_url) => target_url,
+                Err(e) => {
+                    result.warnings.push(e);
+                    continue;
+                }
+            };
+            let mut plan = new_imported_plan(PlanImportFormat::Restic, path.as_str(), target_url.as_str(), format!("restic {}", path).as_str());
+            apply_cron_schedule(&mut plan, job.cron_spec.as_ref(), utc_offset_minutes, &mut notes);
+            plan.options.exclude_patterns = job.excludes.clone();
+            if let Some(retention) = retention.as_ref() {
+                plan.options.retention = retention.clone();
+            }
+            result.plans.push(ImportedPlan { plan, notes });
+        }
+    }
+    result
+}
+
+//duplicati的时间长度:数字加单位(s/m/h/D/W/M/Y)
+fn parse_duplicati_timespan(value: &str) -> Option<(u64, char)> {
+    let unit = value.chars().last()?;
+    let count: u64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    Some((count, unit))
+}
+
+fn duplicati_timespan_days(count: u64, unit: char) -> Option<u64> {
+    match unit {
+        'D' => Some(count),
+        'W' => Some(count * 7),
+        'M' => Some(count * 30),
+        'Y' => Some(count * 365),
+        _ => None,
+    }
+}
+
+//keep-versions对应keep_last;keep-time和retention-policy按周期换算成keep_daily/weekly/monthly/yearly,是近似的
+fn duplicati_retention(settings: &HashMap<String, String>, notes: &mut Vec<String>) -> RetentionPolicy {
+    let mut policy = RetentionPolicy::default();
+    if let Some(versions) = settings.get("keep-versions").and_then(|value| value.parse().ok()) {
+        policy.keep_last = versions;
+    }
+    if let Some((count, unit)) = settings.get("keep-time").and_then(|value| parse_duplicati_timespan(value)) {
+        match duplicati_timespan_days(count, unit) {
+            Some(days) => policy.keep_daily = days as u32,
+            None => notes.push(format!("keep-time {}{} is not supported", count, unit)),
+        }
+    }
+    if let Some(retention_policy) = settings.get("retention-policy") {
+        //例如"1W:1D,4W:1W,12M:1M":1周内每天保留一个,4周内每周保留一个...
+        for rule in retention_policy.split(',') {
+            let timeframe = rule.split_once(':')
+                .and_then(|(timeframe, interval)| Some((parse_duplicati_timespan(timeframe)?, parse_duplicati_timespan(interval)?)));
+            let ((frame_count, frame_unit), (_, interval_unit)) = match timeframe {
+                Some(timeframe) => timeframe,
+                None => {
+                    notes.push(format!("retention rule {} is not supported", rule));
+                    continue;
+                }
+            };
+            let (frame_days, interval_days) = match (duplicati_timespan_days(frame_count, frame_unit), duplicati_timespan_days(1, interval_unit)) {
+                (Some(frame_days), Some(interval_days)) => (frame_days, interval_days),
+                _ => {
+                    notes.push(format!("retention rule {} is not supported", rule));
+                    continue;
+                }
+            };
+            let keep = (frame_days / interval_days).max(1) as u32;
+            match interval_unit {
+                'D' => policy.keep_daily = policy.keep_daily.max(keep),
+                'W' => policy.keep_weekly = policy.keep_weekly.max(keep),
+                'M' => policy.keep_monthly = policy.keep_monthly.max(keep),
+                _ => policy.keep_yearly = policy.keep_yearly.max(keep),
+            }
+        }
+    }
+    policy
+}
+
+//Repeat为1D/1W时按Time的时间每天/每周备份,其它的按间隔备份
+fn duplicati_schedule(schedule: &Value, notes: &mut Vec<String>) -> Option<BackupSchedulePolicy> {
+    let repeat = schedule.get("Repeat").and_then(|v| v.as_str())?;
+    let time = schedule.get("Time").and_then(|v| v.as_str())
+        .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&chrono::Utc));
+    let (count, unit) = parse_duplicati_timespan(repeat)?;
+    if count == 0 {
+        return None;
+    }
+    if schedule.get("AllowedDays").and_then(|v| v.as_array()).is_some_and(|days| !days.is_empty() && days.len() < 7) {
+        notes.push("allowed days of schedule are ignored".to_string());
+    }
+    use chrono::{Datelike, Timelike};
+    match (count, unit, time) {
+        (1, 'D', Some(time)) => Some(BackupSchedulePolicy::Daily { hour: time.hour(), minute: time.minute() }),
+        (1, 'W', Some(time)) => Some(BackupSchedulePolicy::Weekly {
+            weekday: time.weekday().num_days_from_monday(), hour: time.hour(), minute: time.minute(),
+        }),
+        _ => {
+            let secs = match unit {
+                's' => count,
+                'm' => count * 60,
+                'h' => count * 3600,
+                _ => duplicati_timespan_days(count, unit)? * 24 * 3600,
+            };
+            Some(BackupSchedulePolicy::Period { interval_secs: secs })
+        }
+    }
+}
+
+fn duplicati_target_url(target_url: &str, notes: &mut Vec<String>) -> Result<String, String> {
+    let url = Url::parse(target_url).map_err(|e| format!("invalid duplicati target {}: {}", target_url, e))?;
+    match url.scheme() {
+        "file" => Ok(local_target_url(url.path(), notes)),
+        "s3" => {
+            let query: HashMap<String, String> = url.query_pairs().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let mut secrets: HashMap<&str, String> = HashMap::new();
+            for (name, param) in [("region", "s3-location-constraint"), ("access_key", "auth-username"), ("secret_key", "auth-password")] {
+                if let Some(value) = query.get(param) {
+                    secrets.insert(name, value.clone());
+                }
+            }
+            if query.get("s3-server-name").is_some_and(|server| !server.ends_with("amazonaws.com")) {
+                notes.push(format!("s3 server {} is not supported, plan uses aws s3", query["s3-server-name"]));
+            }
+            s3_target_url(url.host_str().unwrap_or_default(), url.path(), &secrets, notes)
+        }
+        scheme => Err(format!("duplicati target scheme {} is not supported", scheme)),
+    }
+}
+
+fn import_duplicati(content: &str) -> Result<PlanImportResult> {
+    let value: Value = serde_json::from_str(content).map_err(|e| anyhow::anyhow!("invalid duplicati config: {}", e))?;
+    let jobs = match value {
+        Value::Array(jobs) => jobs,
+        job => vec![job],
+    };
+    let mut result = PlanImportResult::default();
+    for job in jobs.iter() {
+        let backup = match job.get("Backup") {
+            Some(backup) => backup,
+            None => {
+                result.warnings.push("duplicati job has no Backup section".to_string());
+                continue;
+            }
+        };
+        let name = backup.get("Name").and_then(|v| v.as_str()).unwrap_or("duplicati");
+        let settings: HashMap<String, String> = backup.get("Settings").and_then(|v| v.as_array())
+            .map(|settings| settings.iter().filter_map(|setting| {
+                Some((setting.get("Name")?.as_str()?.trim_start_matches("--").to_string(), setting.get("Value")?.as_str()?.to_string()))
+            }).collect())
+            .unwrap_or_default();
+        //只导入排除规则,duplicati的包含规则和正则表达式([...])没有对应的配置
+        let mut filter_notes = Vec::new();
+        let mut excludes = Vec::new();
+        for filter in backup.get("Filters").and_then(|v| v.as_array()).into_iter().flatten() {
+            let expression = filter.get("Expression").and_then(|v| v.as_str()).unwrap_or_default();
+            let is_include = filter.get("Include").and_then(|v| v.as_bool()).unwrap_or(false);
+            let pattern = expression.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+            if is_include || expression.starts_with('[') || pattern.is_empty() {
+                filter_notes.push(format!("filter {} is not supported", expression));
+            } else {
+                excludes.push(pattern.to_string());
+            }
+        }
+
+        let target_url = backup.get("TargetURL").and_then(|v| v.as_str()).unwrap_or_default();
+        let sources: Vec<&str> = backup.get("Sources").and_then(|v| v.as_array())
+            .map(|sources| sources.iter().filter_map(|source| source.as_str()).collect())
+            .unwrap_or_default();
+        if sources.is_empty() {
+            result.warnings.push(format!("duplicati job {} has no source", name));
+        }
+        for source in sources.iter() {
+            let mut notes = filter_notes.clone();
+            let target_url = match duplicati_target_url(target_url, &mut notes) {
+                Ok(target_url) => target_url,
+                Err(e) => {
+                    result.warnings.push(format!("duplicati job {}: {}", name, e));
+                    break;
+                }
+            };
+            let title = if sources.len() > 1 { format!("{} {}", name, source) } else { name.to_string() };
+            let mut plan = new_imported_plan(PlanImportFormat::Duplicati, source, target_url.as_str(), title.as_str());
+            if let Some(schedule) = job.get("Schedule").and_then(|schedule| duplicati_schedule(schedule, &mut notes)) {
+                plan.options.schedule = vec![schedule];
+            }
+            plan.options.retention = duplicati_retention(&settings, &mut notes);
+            plan.options.exclude_patterns = excludes.clone();
+            result.plans.push(ImportedPlan { plan, notes });
+        }
+    }
+    Ok(result)
+}
+
+//rclone.conf的remote:支持s3和local,alias会解析到它指向的remote(只解析一层)
+fn rclone_target_url(remotes: &HashMap<String, HashMap<String, String>>, dest: &str, allow_alias: bool, notes: &mut Vec<String>) -> Result<String, String> {
+    let (remote_name, remote_path) = match dest.split_once(':') {
+        Some((remote_name, remote_path)) if !remote_name.contains('/') => (remote_name, remote_path),
+        _ => return Ok(local_target_url(dest, notes)),
+    };
+    let remote = remotes.get(remote_name).ok_or_else(|| format!("rclone remote {} is not defined", remote_name))?;
+    match remote.get("type").map(|t| t.as_str()).unwrap_or_default() {
+        "local" => Ok(local_target_url(remote_path, notes)),
+        "alias" if allow_alias => {
+            let alias_remote = remote.get("remote").ok_or_else(|| format!("rclone alias {} has no remote", remote_name))?;
+            let alias_dest = format!("{}/{}", alias_remote.trim_end_matches('/'), remote_path.trim_start_matches('/'));
+            rclone_target_url(remotes, alias_dest.as_str(), false, notes)
+        }
+        "s3" => {
+            if remote.get("provider").is_some_and(|provider| provider != "AWS") {
+                notes.push(format!("s3 provider {} is not supported, plan uses aws s3", remote["provider"]));
+            }
+            let (bucket, prefix) = remote_path.trim_start_matches('/').split_once('/').unwrap_or((remote_path.trim_start_matches('/'), ""));
+            if bucket.is_empty() {
+                return Err(format!("rclone destination {} has no bucket", dest));
+            }
+            let mut secrets: HashMap<&str, String> = HashMap::new();
+            for (name, key) in [("region", "region"), ("access_key", "access_key_id"), ("secret_key", "secret_access_key"), ("session_token", "session_token")] {
+                if let Some(value) = remote.get(key) {
+                    secrets.insert(name, value.clone());
+                }
+            }
+            s3_target_url(bucket, prefix, &secrets, notes)
+        }
+        remote_type => Err(format!("rclone remote type {} is not supported", remote_type)),
+    }
+}
+
+fn import_rclone(content: &str, utc_offset_minutes: i32) -> PlanImportResult {
+    let mut result = PlanImportResult::default();
+    let mut remotes: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current_remote: Option<String> = None;
+    let mut jobs = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim().to_string();
+            remotes.insert(name.clone(), HashMap::new());
+            current_remote = Some(name);
+            continue;
+        }
+        let (cron_spec, command) = split_cron_line(line);
+        let args = split_command_line(command.as_str());
+        if let Some(rclone_pos) = args.iter().position(|arg| arg == "rclone" || arg.ends_with("/rclone")) {
+            current_remote = None;
+            let mut positional = Vec::new();
+            let mut excludes = Vec::new();
+            let mut i = rclone_pos + 1;
+            while i < args.len() {
+                if let Some(value) = take_flag_value(&args, &mut i, &["--exclude"]) {
+                    excludes.push(value);
+                } else if !args[i].starts_with('-') {
+                    positional.push(args[i].clone());
+                }
+                i += 1;
+            }
+            if positional.len() >= 3 && (positional[0] == "sync" || positional[0] == "copy") {
+                jobs.push((positional[1].clone(), positional[2].clone(), excludes, cron_spec));
+            }
+            continue;
+        }
+        if let (Some(remote_name), Some((key, value))) = (current_remote.as_ref(), line.split_once('=')) {
+            remotes.get_mut(remote_name).unwrap().insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    if jobs.is_empty() {
+        result.warnings.push(format!("found {} rclone remotes but no rclone sync/copy command, add the commands (or crontab) to import plans", remotes.len()));
+    }
+
+    for (source, dest, excludes, cron_spec) in jobs.into_iter() {
+        let mut notes = Vec::new();
+        if source.contains(':') && !source.starts_with('/') {
+            result.warnings.push(format!("rclone source {} is a remote, only local source is supported", source));
+            continue;
+        }
+        let target_url = match rclone_target_url(&remotes, dest.as_str(), true, &mut notes) {
+            Ok(target_url) => target_url,
+            Err(e) => {
+                result.warnings.push(e);
+                continue;
+            }
+        };
+        let mut plan = new_imported_plan(PlanImportFormat::Rclone, source.as_str(), target_url.as_str(), format!("rclone {} -> {}", source, dest).as_str());
+        apply_cron_schedule(&mut plan, cron_spec.as_ref(), utc_offset_minutes, &mut notes);
+        //rclone的排除规则是glob,只保留不包含路径的部分
+        plan.options.exclude_patterns = excludes.iter()
+            .map(|pattern| pattern.trim_end_matches("/**").trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string())
+            .filter(|pattern| !pattern.is_empty() && pattern != "**")
+            .collect();
+        result.plans.push(ImportedPlan { plan, notes });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_plans() {
+        let restic = r#"
+export RESTIC_REPOSITORY="s3:s3.amazonaws.com/my-bucket/restic"
+export AWS_ACCESS_KEY_ID=AKIA123
+export AWS_SECRET_ACCESS_KEY='secret'
+30 2 * * * restic backup /home/user/docs /home/user/photos --exclude "*.tmp"
+0 4 * * 0 restic forget --keep-daily 7 --keep-weekly 4 --prune
+"#;
+        let result = import_plans_with_offset(PlanImportFormat::Restic, restic, 0).unwrap();
+        assert_eq!(result.plans.len(), 2);
+        let plan = &result.plans[0].plan;
+        assert_eq!(plan.source.get_source_url(), "file:///home/user/docs");
+        assert_eq!(plan.target.get_target_url(), "s3://my-bucket?access_key=AKIA123&secret_key=secret");
+        assert_eq!(plan.options.schedule, vec![BackupSchedulePolicy::Daily { hour: 2, minute: 30 }]);
+        assert_eq!(plan.options.retention.keep_daily, 7);
+        assert_eq!(plan.options.retention.keep_weekly, 4);
+        assert_eq!(plan.options.exclude_patterns, vec!["*.tmp".to_string()]);
+        assert_eq!(result.plans[0].notes.len(), 1);
+
+        let duplicati = r#"{
+            "Schedule": {"Repeat": "1W", "Time": "2024-01-07T03:15:00Z", "AllowedDays": []},
+            "Backup": {
+                "Name": "Photos",
+                "TargetURL": "file:///mnt/nas/duplicati",
+                "Sources": ["/home/user/photos/"],
+                "Settings": [{"Name": "retention-policy", "Value": "1W:1D,4W:1W,12M:1M"}],
+                "Filters": [{"Include": false, "Expression": "*/.thumbnails/"}, {"Include": true, "Expression": "*.jpg"}]
+            }
+        }"#;
+        let result = import_plans(PlanImportFormat::Duplicati, duplicati).unwrap();
+        assert_eq!(result.plans.len(), 1);
+        let plan = &result.plans[0].plan;
+        assert_eq!(plan.source.get_source_url(), "file:///home/user/photos");
+        assert_eq!(plan.target.get_target_url(), format!("file:///mnt/nas/duplicati{}", LOCAL_TARGET_SUFFIX));
+        assert_eq!(plan.options.schedule, vec![BackupSchedulePolicy::Weekly { weekday: 6, hour: 3, minute: 15 }]);
+        assert_eq!((plan.options.retention.keep_daily, plan.options.retention.keep_weekly, plan.options.retention.keep_monthly), (7, 4, 12));
+        assert_eq!(plan.options.exclude_patterns, vec![".thumbnails".to_string()]);
+        assert!(import_plans(PlanImportFormat::Duplicati, "not json").is_err());
+
+        let rclone = r#"
+[aws]
+type = s3
+provider = AWS
+region = us-east-1
+access_key_id = AKIA456
+secret_access_key = secret2
+
+[backup]
+type = alias
+remote = aws:team-bucket
+
+[webdav]
+type = webdav
+0 */6 * * * rclone sync /srv/share backup:share --exclude "cache/**"
+@daily rclone copy /srv/www webdav:www
+"#;
+        let result = import_plans_with_offset(PlanImportFormat::Rclone, rclone, 0).unwrap();
+        assert_eq!(result.plans.len(), 1);
+        assert_eq!(result.warnings.len(), 1);
+        let plan = &result.plans[0].plan;
+        assert_eq!(plan.target.get_target_url(), "s3://team-bucket?region=us-east-1&access_key=AKIA456&secret_key=secret2");
+        assert_eq!(plan.options.schedule, vec![BackupSchedulePolicy::Period { interval_secs: 6 * 3600 }]);
+        assert_eq!(plan.options.exclude_patterns, vec!["cache".to_string()]);
+    }
+
+    #[test]
+    fn test_cron_local_time_to_utc() {
+        assert_eq!(cron_to_schedule("30 2 * * *", 480), Some(BackupSchedulePolicy::Daily { hour: 18, minute: 30 }));
+        assert_eq!(cron_to_schedule("30 22 * * *", -300), Some(BackupSchedulePolicy::Daily { hour: 3, minute: 30 }));
+        //周一02:30(东八区)是周日18:30(UTC)
+        assert_eq!(cron_to_schedule("30 2 * * 1", 480), Some(BackupSchedulePolicy::Weekly { weekday: 6, hour: 18, minute: 30 }));
+        assert_eq!(cron_to_schedule("0 22 * * 0", -300), Some(BackupSchedulePolicy::Weekly { weekday: 0, hour: 3, minute: 0 }));
+        assert_eq!(cron_to_schedule("@daily", 330), Some(BackupSchedulePolicy::Daily { hour: 18, minute: 30 }));
+        assert_eq!(cron_to_schedule("0 */6 * * *", 480), Some(BackupSchedulePolicy::Period { interval_secs: 6 * 3600 }));
+        assert_eq!(cron_to_schedule("0 24 * * *", 0), None);
+        assert_eq!(cron_to_schedule("60 1 * * *", 0), None);
+
+        let restic = "30 2 * * * restic -r /mnt/backup backup /home/user/docs\n";
+        let result = import_plans_with_offset(PlanImportFormat::Restic, restic, 480).unwrap();
+        assert_eq!(result.plans[0].plan.options.schedule, vec![BackupSchedulePolicy::Daily { hour: 18, minute: 30 }]);
+        assert!(result.plans[0].notes.iter().any(|note| note.contains("UTC")));
+    }
+}
@@ -0,0 +1,192 @@
+#![allow(unused)]
+//plan模板:设置向导中一键创建常用的plan,模板给出定时/保留/排除等策略,用户只需要填source和target的几个参数
+//模板只用于生成BackupPlanConfig,创建时和普通plan一样经过validate_plan检查,创建后和模板没有关系
+use std::collections::HashMap;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use url::Url;
+use buckyos_backup_lib::TargetUrl;
+
+use crate::network::NetworkPolicy;
+use crate::retention::RetentionPolicy;
+use crate::schedule::BackupSchedulePolicy;
+use crate::snapshot::SnapshotMode;
+use crate::task_db::{BackupPlanConfig, BackupPlanOptions};
+
+//office/libreoffice的锁文件,系统生成的缩略图和目录配置
+const COMMON_EXCLUDE_PATTERNS: [&str; 6] = ["~$*", ".~lock.*#", "*.tmp", "Thumbs.db", ".DS_Store", "desktop.ini"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanTemplateTarget {
+    S3,//参数bucket,region,credential_id
+    LocalDir,//参数target_path,NAS挂载的目录或者移动硬盘
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanTemplateParam {
+    pub name: String,
+    pub description: String,
+    pub required: bool,
+}
+
+impl PlanTemplateParam {
+    fn new(name: &str, description: &str, required: bool) -> Self {
+        Self { name: name.to_string(), description: description.to_string(), required }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanTemplate {
+    pub template_id: String,
+    pub title: String,
+    pub description: String,
+    pub target: PlanTemplateTarget,
+    pub params: Vec<PlanTemplateParam>,
+    pub options: BackupPlanOptions,//生成的plan使用的策略,向导中展示给用户
+}
+
+impl PlanTemplate {
+    fn new(template_id: &str, title: &str, description: &str, target: PlanTemplateTarget, options: BackupPlanOptions) -> Self {
+        let mut params = vec![
+            PlanTemplateParam::new("source", "local directory to backup", true),
+            PlanTemplateParam::new("title", "plan title, use template title if empty", false),
+        ];
+        match target {
+            PlanTemplateTarget::S3 => {
+                params.push(PlanTemplateParam::new("bucket", "s3 bucket name", true));
+                params.push(PlanTemplateParam::new("region", "s3 region", false));
+                params.push(PlanTemplateParam::new("credential_id", "credential of the bucket in credential vault", false));
+            }
+            PlanTemplateTarget::LocalDir => {
+                params.push(PlanTemplateParam::new("target_path", "local directory to store backup data", true));
+            }
+        }
+        Self {
+            template_id: template_id.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            target,
+            params,
+            options,
+        }
+    }
+
+    //用模板和参数生成plan,不检查source/target是否可用
+    pub fn build_plan(&self, params: &HashMap<String, String>) -> Result<BackupPlanConfig> {
+        let get_param = |name: &str| params.get(name).map(|value| value.trim()).filter(|value| !value.is_empty());
+        for param in self.params.iter().filter(|param| param.required) {
+            if get_param(param.name.as_str()).is_none() {
+                return Err(anyhow::anyhow!("template {} requires param {}", self.template_id, param.name));
+            }
+        }
+        let source_url = local_dir_to_url(get_param("source").unwrap())?;
+        let target_url = match self.target {
+            PlanTemplateTarget::S3 => {
+                let mut url = Url::parse(format!("s3://{}", get_param("bucket").unwrap()).as_str())?;
+                for name in ["region", "credential_id"] {
+                    if let Some(value) = get_param(name) {
+                        url.query_pairs_mut().append_pair(name, value);
+                    }
+                }
+                //url格式不合法(空bucket/region等)时在创建plan之前报错
+                TargetUrl::parse(url.as_str())?;
+                url.to_string()
+            }
+            PlanTemplateTarget::LocalDir => local_dir_to_url(get_param("target_path").unwrap())?,
+        };
+        let title = get_param("title").unwrap_or(self.title.as_str());
+        let mut plan = BackupPlanConfig::chunk2chunk(source_url.as_str(), target_url.as_str(), title, self.description.as_str());
+        plan.options = self.options.clone();
+        Ok(plan)
+    }
+}
+
+//参数可以是绝对路径或者file://的url
+fn local_dir_to_url(path: &str) -> Result<String> {
+    if path.starts_with("file://") {
+        return Ok(path.to_string());
+    }
+    if !std::path::Path::new(path).is_absolute() {
+        return Err(anyhow::anyhow!("path must be absolute: {}", path));
+    }
+    Ok(format!("file://{}", path))
+}
+
+fn exclude_patterns(extra_patterns: &[&str]) -> Vec<String> {
+    COMMON_EXCLUDE_PATTERNS.iter().chain(extra_patterns.iter()).map(|pattern| pattern.to_string()).collect()
+}
+
+pub fn list_plan_templates() -> Vec<PlanTemplate> {
+    let mut documents_options = BackupPlanOptions::default();
+    documents_options.schedule = vec![BackupSchedulePolicy::Daily { hour: 2, minute: 0 }];
+    documents_options.retention = RetentionPolicy { keep_daily: 30, ..Default::default() };
+    documents_options.exclude_patterns = exclude_patterns(&[]);
+    documents_options.snapshot.mode = SnapshotMode::Auto;
+    documents_options.network = NetworkPolicy { pause_on_metered: true, pause_when_offline: true };
+
+    //照片很少修改,按周备份,保留更长时间
+    let mut photos_options = BackupPlanOptions::default();
+    photos_options.schedule = vec![BackupSchedulePolicy::Weekly { weekday: 6, hour: 3, minute: 0 }];
+    photos_options.retention = RetentionPolicy { keep_weekly: 8, keep_monthly: 12, ..Default::default() };
+    photos_options.exclude_patterns = exclude_patterns(&[".thumbnails", "@eaDir"]);
+
+    //虚拟机镜像很大,只上传修改的部分
+    let mut vm_options = BackupPlanOptions::default();
+    vm_options.schedule = vec![BackupSchedulePolicy::Daily { hour: 1, minute: 0 }];
+    vm_options.retention = RetentionPolicy { keep_last: 7, ..Default::default() };
+    vm_options.exclude_patterns = vec!["*.lck".to_string(), "*.tmp".to_string()];
+    vm_options.snapshot.mode = SnapshotMode::Auto;
+    vm_options.delta_upload = true;
+
+    vec![
+        PlanTemplate::new("documents_daily_s3", "Documents daily to S3",
+            "Backup documents to S3 every day at 02:00, keep 30 days", PlanTemplateTarget::S3, documents_options),
+        PlanTemplate::new("photos_weekly_nas", "Photos weekly to NAS",
+            "Backup photos to NAS every Sunday at 03:00, keep 8 weeks and 12 months", PlanTemplateTarget::LocalDir, photos_options),
+        PlanTemplate::new("vm_images_nightly", "Virtual machine images nightly",
+            "Backup changed blocks of virtual machine images every day at 01:00, keep last 7", PlanTemplateTarget::LocalDir, vm_options),
+    ]
+}
+
+pub fn build_plan_from_template(template_id: &str, params: &HashMap<String, String>) -> Result<BackupPlanConfig> {
+    let template = list_plan_templates().into_iter()
+        .find(|template| template.template_id == template_id)
+        .ok_or_else(|| anyhow::anyhow!("plan template not found: {}", template_id))?;
+    template.build_plan(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan_validate::check_plan_options;
+
+    #[test]
+    fn test_build_plan_from_template() {
+        for template in list_plan_templates() {
+            assert!(check_plan_options(&template.options, true).is_empty(), "{}", template.template_id);
+        }
+
+        let mut params = HashMap::new();
+        params.insert("source".to_string(), "/home/user/Documents".to_string());
+        params.insert("bucket".to_string(), "my-backup".to_string());
+        params.insert("credential_id".to_string(), "cred_1".to_string());
+        let plan = build_plan_from_template("documents_daily_s3", &params).unwrap();
+        assert_eq!(plan.source.get_source_url(), "file:///home/user/Documents");
+        assert_eq!(plan.target.get_target_url(), "s3://my-backup?credential_id=cred_1");
+        assert_eq!(plan.title, "Documents daily to S3");
+        assert_eq!(plan.options.retention.keep_daily, 30);
+
+        //缺少必须的参数
+        params.remove("bucket");
+        assert!(build_plan_from_template("documents_daily_s3", &params).is_err());
+        params.insert("target_path".to_string(), "nas/photos".to_string());
+        assert!(build_plan_from_template("photos_weekly_nas", &params).is_err());
+        params.insert("target_path".to_string(), "/mnt/nas/photos".to_string());
+        params.insert("title".to_string(), "my photos".to_string());
+        let plan = build_plan_from_template("photos_weekly_nas", &params).unwrap();
+        assert_eq!(plan.target.get_target_url(), "file:///mnt/nas/photos");
+        assert_eq!(plan.title, "my photos");
+        assert!(build_plan_from_template("unknown", &params).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+#![allow(unused)]
+//创建plan前的检查:url和scheme,source是否可读,target是否可写,source和target/engine数据目录是否重叠,策略配置是否有效
+//结果是结构化的诊断列表,有Error时拒绝创建plan,Warning只是提示(比如远端target暂时离线)
+//本地路径的问题总是Error,远端source/target打不开只是Warning,可能是设备在休眠,备份前的hook会唤醒它
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use crate::schedule::*;
+use crate::task_db::BackupPlanOptions;
+use crate::snapshot::SnapshotMode;
+use crate::wake::*;
+use crate::daemon_config::is_hook_script_name;
+
+const MIN_SOURCE_READ_BYTES_PER_SEC: u64 = 1024 * 1024;//低于这个读取限速时提示
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanDiagnosticLevel {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanDiagnostic {
+    pub level: PlanDiagnosticLevel,
+    pub field: String,//出问题的配置项,比如source,target,options.schedule[0]
+    pub code: String,
+    pub message: String,
+}
+
+impl PlanDiagnostic {
+    pub fn error(field: &str, code: &str, message: String) -> Self {
+        Self { level: PlanDiagnosticLevel::Error, field: field.to_string(), code: code.to_string(), message }
+    }
+
+    pub fn warning(field: &str, code: &str, message: String) -> Self {
+        Self { level: PlanDiagnosticLevel::Warning, field: field.to_string(), code: code.to_string(), message }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanValidationReport {
+    pub diagnostics: Vec<PlanDiagnostic>,
+}
+
+impl PlanValidationReport {
+    pub fn push(&mut self, diagnostic: PlanDiagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn extend(&mut self, diagnostics: Vec<PlanDiagnostic>) {
+        self.diagnostics.extend(diagnostics);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.diagnostics.iter().any(|diagnostic| diagnostic.level == PlanDiagnosticLevel::Error)
+    }
+
+    pub fn errors(&self) -> Vec<&PlanDiagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.level == PlanDiagnosticLevel::Error).collect()
+    }
+
+    pub fn warnings(&self) -> Vec<&PlanDiagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.level == PlanDiagnosticLevel::Warning).collect()
+    }
+
+    //用于创建plan失败时的错误信息
+    pub fn error_summary(&self) -> String {
+        self.errors().iter()
+            .map(|diagnostic| format!("{}: {}", diagnostic.field, diagnostic.message))
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+}
+
+//target目录还不存在时(第一次备份时创建)检查最近的已存在的上级目录,检查时不创建目录
+pub fn probe_local_dir_writable(path: &Path) -> std::io::Result<()> {
+    let mut dir = path;
+    while !dir.exists() {
+        dir = dir.parent().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no existing parent directory"))?;
+    }
+    if !dir.is_dir() {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{} is not a directory", dir.to_string_lossy())));
+    }
+    let probe_path = dir.join(format!(".bucky_backup_probe_{}", std::process::id()));
+    std::fs::write(&probe_path, b"probe")?;
+    std::fs::remove_file(&probe_path)
+}
+
+//只检查配置本身,不访问source/target
+pub fn check_plan_options(options: &BackupPlanOptions, is_local_source: bool) -> Vec<PlanDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for (i, policy) in options.schedule.iter().enumerate() {
+        if policy.next_fire_time(0).is_none() {
+            diagnostics.push(PlanDiagnostic::error(format!("options.schedule[{}]", i).as_str(), "invalid_schedule",
+                format!("schedule policy {:?} will never fire", policy)));
+        }
+    }
+
+    let retry = &options.retry;
+    if !retry.backoff_multiplier.is_finite() || retry.backoff_multiplier < 1.0 {
+        diagnostics.push(PlanDiagnostic::error("options.retry.backoff_multiplier", "invalid_retry",
+            format!("backoff multiplier must be >= 1.0, got {}", retry.backoff_multiplier)));
+    }
+    if retry.max_attempts > 0 && retry.max_delay_secs < retry.initial_delay_secs {
+        diagnostics.push(PlanDiagnostic::warning("options.retry.max_delay_secs", "invalid_retry",
+            format!("max delay {}s is less than initial delay {}s", retry.max_delay_secs, retry.initial_delay_secs)));
+    }
+
+    for (i, hook) in options.pre_task_hooks.iter().enumerate() {
+        match hook {
+            PreTaskHook::WakeOnLan(wake_hook) => {
+                if let Err(e) = build_magic_packet(wake_hook.mac_address.as_str()) {
+                    diagnostics.push(PlanDiagnostic::error(format!("options.pre_task_hooks[{}]", i).as_str(), "invalid_hook", e.to_string()));
+                }
+            }
+        }
+    }
+    //hook只能是daemon配置的hook_dir下的脚本文件名,不能是命令
+    if let Some(hook) = options.pre_restore_hook.as_ref() {
+        if !is_hook_script_name(hook.as_str()) {
+            diagnostics.push(PlanDiagnostic::error("options.pre_restore_hook", "invalid_hook",
+                format!("pre restore hook {:?} must be a script file name in the hook dir", hook)));
+        }
+    }
+
+    if options.power.min_battery_percent > 100 {
+        diagnostics.push(PlanDiagnostic::error("options.power.min_battery_percent", "invalid_power",
+            format!("battery percent {} is greater than 100", options.power.min_battery_percent)));
+    }
+    if options.tenant.as_ref().is_some_and(|tenant| tenant.is_empty()) {
+        diagnostics.push(PlanDiagnostic::error("options.tenant", "invalid_tenant", "tenant is empty".to_string()));
+    }
+    //排除规则按路径的每一级匹配,包含路径分隔符的规则不会匹配任何item
+    for (i, pattern) in options.exclude_patterns.iter().enumerate() {
+        if pattern.is_empty() || pattern.contains(|c| c == '/' || c == '\\') {
+            diagnostics.push(PlanDiagnostic::error(format!("options.exclude_patterns[{}]", i).as_str(), "invalid_exclude",
+                format!("exclude pattern '{}' must be a non-empty file or directory name", pattern)));
+        }
+    }
+    let partial_accept = &options.partial_accept;
+    if partial_accept.enabled && !(partial_accept.max_failed_percent >= 0.0 && partial_accept.max_failed_percent <= 100.0) {
+        diagnostics.push(PlanDiagnostic::error("options.partial_accept.max_failed_percent", "invalid_partial_accept",
+            format!("max failed percent must be in [0, 100], got {}", partial_accept.max_failed_percent)));
+    }
+    let timeout = &options.timeout;
+    if timeout.max_runtime_secs > 0 && timeout.stall_timeout_secs >= timeout.max_runtime_secs {
+        diagnostics.push(PlanDiagnostic::warning("options.timeout.stall_timeout_secs", "invalid_timeout",
+            format!("stall timeout {}s is not less than max runtime {}s, stall detection never fires", timeout.stall_timeout_secs, timeout.max_runtime_secs)));
+    }
+    let max_read_bytes_per_sec = options.source_io.max_read_bytes_per_sec;
+    if max_read_bytes_per_sec > 0 && max_read_bytes_per_sec < MIN_SOURCE_READ_BYTES_PER_SEC {
+        diagnostics.push(PlanDiagnostic::warning("options.source_io.max_read_bytes_per_sec", "slow_source_read",
+            format!("source read limit {} bytes/s is very low, backup may never catch up with changes", max_read_bytes_per_sec)));
+    }
+    if options.snapshot.mode != SnapshotMode::None && !is_local_source {
+        diagnostics.push(PlanDiagnostic::warning("options.snapshot.mode", "snapshot_ignored",
+            "snapshot only works for file:// source, it will be ignored".to_string()));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_plan_options() {
+        let options = BackupPlanOptions::default();
+        assert!(check_plan_options(&options, true).is_empty());
+
+        let mut options = BackupPlanOptions::default();
+        options.schedule = vec![
+            BackupSchedulePolicy::Daily { hour: 3, minute: 0 },
+            BackupSchedulePolicy::Weekly { weekday: 7, hour: 3, minute: 0 },
+        ];
+        options.retry.backoff_multiplier = 0.5;
+        options.pre_task_hooks = vec![PreTaskHook::WakeOnLan(WakeOnLanHook {
+            mac_address: "01:23:45".to_string(),
+            broadcast_addr: "255.255.255.255:9".to_string(),
+            probe_addr: None,
+            wake_timeout_secs: 10,
+        })];
+        options.snapshot.mode = SnapshotMode::Auto;
+        let mut report = PlanValidationReport::default();
+        report.extend(check_plan_options(&options, false));
+        assert!(!report.is_valid());
+        assert_eq!(report.errors().len(), 3);
+        assert_eq!(report.errors()[0].field, "options.schedule[1]");
+        assert_eq!(report.warnings().len(), 1);
+        assert!(report.error_summary().contains("options.pre_task_hooks[0]"));
+    }
+}
@@ -0,0 +1,121 @@
+#![allow(unused)]
+//启动时从plugins目录加载第三方的source/target provider动态库(.so/.dylib/.dll),
+//插件使用buckyos_backup_lib::declare_backup_plugin!导出abi版本和注册函数,
+//abi版本和BACKUP_PLUGIN_ABI_VERSION不一致,或者使用不同版本的rustc/buckyos-backup-lib编译的插件会被跳过
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use libloading::{Library, Symbol};
+use buckyos_backup_lib::*;
+use log::*;
+
+pub const BACKUP_PLUGIN_DIR_NAME: &str = "plugins";
+
+type PluginAbiVersionFn = extern "C" fn() -> u32;
+type PluginBuildIdFn = extern "C" fn() -> *const std::os::raw::c_char;
+type PluginRegisterFn = fn(&mut BackupProviderRegistry);
+
+pub struct LoadedPlugin {
+    pub path: PathBuf,
+    pub schemes: Vec<String>,
+    //provider的代码在动态库里,库必须在engine的整个生命周期内保持加载
+    _library: Library,
+}
+
+fn is_plugin_file(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    ext == std::env::consts::DLL_EXTENSION
+}
+
+pub fn load_plugin(path: &Path, registry: &mut BackupProviderRegistry) -> Result<LoadedPlugin> {
+    //加载动态库会执行库的初始化代码,plugins目录只应该放可信的插件
+    let library = unsafe { Library::new(path)? };
+    let abi_version = unsafe {
+        let abi_version_fn: Symbol<PluginAbiVersionFn> = library.get(BACKUP_PLUGIN_ABI_VERSION_SYMBOL)?;
+        abi_version_fn()
+    };
+    if abi_version != BACKUP_PLUGIN_ABI_VERSION {
+        return Err(anyhow::anyhow!("plugin {} abi version {} not match {}",
+            path.to_string_lossy(), abi_version, BACKUP_PLUGIN_ABI_VERSION));
+    }
+    //注册函数的参数是rust类型,编译环境不同时调用是未定义行为,必须在调用前检查
+    let build_id = unsafe {
+        let build_id_fn: Symbol<PluginBuildIdFn> = library.get(BACKUP_PLUGIN_BUILD_ID_SYMBOL)?;
+        let build_id = build_id_fn();
+        if build_id.is_null() {
+            return Err(anyhow::anyhow!("plugin {} build id is null", path.to_string_lossy()));
+        }
+        CStr::from_ptr(build_id).to_string_lossy().to_string()
+    };
+    if build_id != get_backup_plugin_build_id() {
+        return Err(anyhow::anyhow!("plugin {} built with {}, not match {}",
+            path.to_string_lossy(), build_id, get_backup_plugin_build_id()));
+    }
+
+    let mut plugin_registry = BackupProviderRegistry::new();
+    unsafe {
+        let register_fn: Symbol<PluginRegisterFn> = library.get(BACKUP_PLUGIN_REGISTER_SYMBOL)?;
+        register_fn(&mut plugin_registry);
+    }
+    let schemes = plugin_registry.list_schemes();
+    if schemes.is_empty() {
+        return Err(anyhow::anyhow!("plugin {} registered no provider", path.to_string_lossy()));
+    }
+    let plugin_path = path.to_string_lossy().to_string();
+    registry.merge_plugin(plugin_registry, plugin_path.as_str());
+    Ok(LoadedPlugin {
+        path: path.to_path_buf(),
+        schemes,
+        _library: library,
+    })
+}
+
+//单个插件加载失败只记录日志,不影响其它插件和内置provider
+pub fn load_plugins_from_dir(plugin_dir: &Path, registry: &mut BackupProviderRegistry) -> Vec<LoadedPlugin> {
+    let mut loaded_plugins = Vec::new();
+    let read_dir = std::fs::read_dir(plugin_dir);
+    if read_dir.is_err() {
+        info!("plugin dir {} not exist, skip load plugins", plugin_dir.to_string_lossy());
+        return loaded_plugins;
+    }
+
+    let mut plugin_files: Vec<PathBuf> = read_dir.unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_plugin_file(path))
+        .collect();
+    //按文件名排序,多个插件注册同一个scheme时结果是确定的
+    plugin_files.sort();
+    for plugin_file in plugin_files {
+        match load_plugin(&plugin_file, registry) {
+            Ok(plugin) => {
+                info!("load backup plugin {} ok, schemes: {:?}", plugin.path.to_string_lossy(), plugin.schemes);
+                loaded_plugins.push(plugin);
+            }
+            Err(e) => {
+                warn!("load backup plugin {} failed: {}", plugin_file.to_string_lossy(), e);
+            }
+        }
+    }
+    loaded_plugins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_invalid_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake_plugin = dir.path().join(format!("fake.{}", std::env::consts::DLL_EXTENSION));
+        std::fs::write(&fake_plugin, b"not a library").unwrap();
+        std::fs::write(dir.path().join("readme.txt"), b"readme").unwrap();
+
+        let mut registry = BackupProviderRegistry::with_builtin();
+        let plugins = load_plugins_from_dir(dir.path(), &mut registry);
+        assert!(plugins.is_empty());
+        assert_eq!(registry.list_schemes(), vec!["file".to_string()]);
+
+        let plugins = load_plugins_from_dir(&dir.path().join("not_exist"), &mut registry);
+        assert!(plugins.is_empty());
+    }
+}
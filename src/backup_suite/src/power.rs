@@ -0,0 +1,197 @@
+#![allow(unused)]
+//电源感知:笔记本使用电池时可以不启动备份,运行中的备份task会被暂停,接通电源(或电量回到阈值以上)后engine自动resume
+//linux读取/sys/class/power_supply,macos解析pmset的输出,其它平台总是认为使用外部电源
+use std::path::Path;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+const LINUX_POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerPolicy {
+    pub pause_on_battery: bool,//使用电池时总是不备份
+    pub min_battery_percent: u8,//使用电池且电量低于这个值时不备份,0表示不检查
+}
+
+impl PowerPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.pause_on_battery || self.min_battery_percent > 0
+    }
+
+    //当前电源状态下不能备份时返回原因
+    pub fn check(&self, state: &PowerState) -> Option<String> {
+        if !state.on_battery {
+            return None;
+        }
+        if self.pause_on_battery {
+            return Some("running on battery".to_string());
+        }
+        match state.battery_percent {
+            Some(percent) if percent < self.min_battery_percent => {
+                Some(format!("battery {}% is below {}%", percent, self.min_battery_percent))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerState {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,//没有电池或者读取失败时为None
+}
+
+//在engine的调度循环里调用,不能阻塞运行时的线程
+#[async_trait]
+pub trait PowerMonitor: Send + Sync {
+    async fn get_power_state(&self) -> PowerState;
+}
+
+pub struct SystemPowerMonitor;
+
+#[async_trait]
+impl PowerMonitor for SystemPowerMonitor {
+    #[cfg(target_os = "linux")]
+    async fn get_power_state(&self) -> PowerState {
+        tokio::task::spawn_blocking(|| read_linux_power_state(Path::new(LINUX_POWER_SUPPLY_DIR))).await
+            .unwrap_or_default()
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn get_power_state(&self) -> PowerState {
+        let output = tokio::process::Command::new("pmset").args(["-g", "batt"]).output().await;
+        match output {
+            Ok(output) if output.status.success() => parse_pmset_output(String::from_utf8_lossy(&output.stdout).as_ref()),
+            _ => PowerState::default(),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn get_power_state(&self) -> PowerState {
+        PowerState::default()
+    }
+}
+
+fn read_power_supply_attr(supply_dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(supply_dir.join(name)).ok().map(|value| value.trim().to_string())
+}
+
+//有外部电源在线时不算使用电池;没有外部电源信息时以电池是否在放电为准
+fn read_linux_power_state(power_supply_dir: &Path) -> PowerState {
+    let mut is_mains_online = None;
+    let mut is_discharging = false;
+    let mut battery_percent = None;
+    let entries = match std::fs::read_dir(power_supply_dir) {
+        Ok(entries) => entries,
+        Err(_) => return PowerState::default(),
+    };
+    for entry in entries.flatten() {
+        let supply_dir = entry.path();
+        match read_power_supply_attr(&supply_dir, "type").as_deref() {
+            Some("Mains") => {
+                let is_online = read_power_supply_attr(&supply_dir, "online").as_deref() == Some("1");
+                is_mains_online = Some(is_mains_online.unwrap_or(false) || is_online);
+            }
+            Some("Battery") => {
+                if read_power_supply_attr(&supply_dir, "status").as_deref() == Some("Discharging") {
+                    is_discharging = true;
+                }
+                if let Some(capacity) = read_power_supply_attr(&supply_dir, "capacity").and_then(|value| value.parse::<u8>().ok()) {
+                    //多块电池时取最低的
+                    battery_percent = Some(battery_percent.map_or(capacity, |percent: u8| percent.min(capacity)));
+                }
+            }
+            _ => {}
+        }
+    }
+    PowerState {
+        on_battery: battery_percent.is_some() && is_mains_online.map_or(is_discharging, |is_online| !is_online),
+        battery_percent,
+    }
+}
+
+//Now drawing from 'Battery Power'
+// -InternalBattery-0 (id=1234)	85%; discharging; 4:30 remaining present: true
+fn parse_pmset_output(output: &str) -> PowerState {
+    let battery_percent = output.lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .filter_map(|info| info.split('%').next())
+        .find_map(|percent| percent.trim().parse::<u8>().ok());
+    PowerState {
+        on_battery: output.contains("'Battery Power'"),
+        battery_percent,
+    }
+}
+
+//测试时模拟电源变化
+pub struct SimulatedPowerMonitor {
+    state: Mutex<PowerState>,
+}
+
+impl SimulatedPowerMonitor {
+    pub fn new(state: PowerState) -> Self {
+        Self { state: Mutex::new(state) }
+    }
+
+    pub fn set_state(&self, state: PowerState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+#[async_trait]
+impl PowerMonitor for SimulatedPowerMonitor {
+    async fn get_power_state(&self) -> PowerState {
+        *self.state.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_policy_check() {
+        let ac = PowerState { on_battery: false, battery_percent: Some(10) };
+        let battery_low = PowerState { on_battery: true, battery_percent: Some(15) };
+        let battery_high = PowerState { on_battery: true, battery_percent: Some(80) };
+        assert!(!PowerPolicy::default().is_enabled());
+        assert!(PowerPolicy::default().check(&battery_low).is_none());
+
+        let policy: PowerPolicy = serde_json::from_str(r#"{"min_battery_percent":30}"#).unwrap();
+        assert!(policy.is_enabled());
+        assert!(policy.check(&ac).is_none());
+        assert!(policy.check(&battery_high).is_none());
+        assert_eq!(policy.check(&battery_low).unwrap(), "battery 15% is below 30%");
+
+        let policy = PowerPolicy { pause_on_battery: true, min_battery_percent: 0 };
+        assert!(policy.check(&battery_high).is_some());
+        assert!(policy.check(&ac).is_none());
+    }
+
+    #[test]
+    fn test_read_power_state() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let write_supply = |name: &str, attrs: &[(&str, &str)]| {
+            let supply_dir = test_dir.path().join(name);
+            std::fs::create_dir_all(&supply_dir).unwrap();
+            for (attr, value) in attrs {
+                std::fs::write(supply_dir.join(attr), format!("{}\n", value)).unwrap();
+            }
+        };
+        //台式机没有电池
+        assert_eq!(read_linux_power_state(test_dir.path()), PowerState::default());
+        write_supply("BAT0", &[("type", "Battery"), ("status", "Discharging"), ("capacity", "42")]);
+        assert_eq!(read_linux_power_state(test_dir.path()), PowerState { on_battery: true, battery_percent: Some(42) });
+        write_supply("AC", &[("type", "Mains"), ("online", "1")]);
+        assert!(!read_linux_power_state(test_dir.path()).on_battery);
+        write_supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert!(read_linux_power_state(test_dir.path()).on_battery);
+
+        let state = parse_pmset_output("Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t85%; discharging; 4:30 remaining present: true\n");
+        assert_eq!(state, PowerState { on_battery: true, battery_percent: Some(85) });
+        let state = parse_pmset_output("Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t100%; charged; 0:00 remaining present: true\n");
+        assert_eq!(state, PowerState { on_battery: false, battery_percent: Some(100) });
+    }
+}
@@ -0,0 +1,143 @@
+#![allow(unused)]
+//存储配额:限制一个plan/租户/target在target上保存的数据量(max_bytes)
+//已用量是还存在的checkpoint的去重统计中存储大小(新写入target的大小)之和,租户的已用量是所有属于这个租户的plan之和
+//备份开始前用预估的上传量检查,上传过程中每个chunk写入前再按这次task累计的上传量检查,超出时task失败并发布告警事件
+//预估的上传量是上限(变化的文件也可能已经在target上),所以开始前的检查偏严格
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaScope {
+    Plan,//scope_key为plan_id
+    Tenant,//scope_key为plan options中的tenant
+    Target,//scope_key为target_url
+}
+
+impl QuotaScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaScope::Plan => "plan",
+            QuotaScope::Tenant => "tenant",
+            QuotaScope::Target => "target",
+        }
+    }
+
+    pub fn from_str(scope: &str) -> Option<Self> {
+        match scope {
+            "plan" => Some(QuotaScope::Plan),
+            "tenant" => Some(QuotaScope::Tenant),
+            "target" => Some(QuotaScope::Target),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageQuota {
+    pub scope: QuotaScope,
+    pub scope_key: String,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub scope: QuotaScope,
+    pub scope_key: String,
+    pub max_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl QuotaUsage {
+    pub fn remain_bytes(&self) -> u64 {
+        self.max_bytes.saturating_sub(self.used_bytes)
+    }
+}
+
+//按配额汇总已用量,stored_sizes为(plan_id, target_url, 存储大小),plan_tenants为plan_id -> tenant
+pub fn build_quota_usages(quotas: &[StorageQuota], stored_sizes: &[(String, String, u64)],
+    plan_tenants: &HashMap<String, String>) -> Vec<QuotaUsage> {
+    quotas.iter().map(|quota| {
+        let used_bytes = stored_sizes.iter()
+            .filter(|(plan_id, target_url, _)| match quota.scope {
+                QuotaScope::Plan => plan_id == &quota.scope_key,
+                QuotaScope::Tenant => plan_tenants.get(plan_id) == Some(&quota.scope_key),
+                QuotaScope::Target => target_url == &quota.scope_key,
+            })
+            .map(|(_, _, stored_size)| *stored_size)
+            .sum();
+        QuotaUsage {
+            scope: quota.scope,
+            scope_key: quota.scope_key.clone(),
+            max_bytes: quota.max_bytes,
+            used_bytes,
+        }
+    }).collect()
+}
+
+//一次备份需要满足的配额(plan自己的,所属租户的,target的),used_bytes是备份开始时的已用量
+#[derive(Debug, Clone, Default)]
+pub struct QuotaGuard {
+    pub usages: Vec<QuotaUsage>,
+}
+
+impl QuotaGuard {
+    pub fn for_plan(usages: Vec<QuotaUsage>, plan_id: &str, tenant: Option<&str>, target_url: &str) -> Self {
+        let usages = usages.into_iter().filter(|usage| match usage.scope {
+            QuotaScope::Plan => usage.scope_key == plan_id,
+            QuotaScope::Tenant => Some(usage.scope_key.as_str()) == tenant,
+            QuotaScope::Target => usage.scope_key == target_url,
+        }).collect();
+        Self { usages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.usages.is_empty()
+    }
+
+    //再写入required_bytes后会超出的配额,多个配额超出时返回剩余空间最小的
+    pub fn check(&self, required_bytes: u64) -> Option<&QuotaUsage> {
+        self.usages.iter()
+            .filter(|usage| usage.used_bytes.saturating_add(required_bytes) > usage.max_bytes)
+            .min_by_key(|usage| usage.remain_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_guard() {
+        let quotas = vec![
+            StorageQuota { scope: QuotaScope::Plan, scope_key: "plan_a".to_string(), max_bytes: 1000 },
+            StorageQuota { scope: QuotaScope::Tenant, scope_key: "tenant_1".to_string(), max_bytes: 1500 },
+            StorageQuota { scope: QuotaScope::Target, scope_key: "file:///t2".to_string(), max_bytes: 100 },
+        ];
+        let stored_sizes = vec![
+            ("plan_a".to_string(), "file:///t1".to_string(), 300),
+            ("plan_a".to_string(), "file:///t1".to_string(), 200),
+            ("plan_b".to_string(), "file:///t1".to_string(), 700),
+            ("plan_c".to_string(), "file:///t2".to_string(), 50),
+        ];
+        let plan_tenants = HashMap::from([
+            ("plan_a".to_string(), "tenant_1".to_string()),
+            ("plan_b".to_string(), "tenant_1".to_string()),
+        ]);
+        let usages = build_quota_usages(&quotas, &stored_sizes, &plan_tenants);
+        assert_eq!(usages.iter().map(|usage| usage.used_bytes).collect::<Vec<_>>(), vec![500, 1200, 50]);
+
+        let guard = QuotaGuard::for_plan(usages.clone(), "plan_a", Some("tenant_1"), "file:///t1");
+        assert_eq!(guard.usages.len(), 2);
+        assert!(guard.check(300).is_none());
+        //租户剩余300,plan剩余500,先超出的是租户配额
+        let exceeded = guard.check(400).unwrap();
+        assert_eq!((exceeded.scope, exceeded.scope_key.as_str()), (QuotaScope::Tenant, "tenant_1"));
+        assert_eq!(guard.check(600).unwrap().scope, QuotaScope::Tenant);
+
+        let guard = QuotaGuard::for_plan(usages, "plan_c", None, "file:///t2");
+        assert_eq!(guard.check(51).unwrap().scope, QuotaScope::Target);
+        assert!(QuotaGuard::default().check(u64::MAX).is_none());
+        assert_eq!(QuotaScope::from_str("tenant"), Some(QuotaScope::Tenant));
+    }
+}
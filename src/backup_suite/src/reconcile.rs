@@ -0,0 +1,173 @@
+#![allow(unused)]
+//本地记录的checkpoint/item状态和target上实际的chunk不一致时的修复
+//以target上chunk是否存在作为item的远端状态,按固定的规则修正本地状态:
+// - 本地Done但target上没有chunk:重新排队上传(打包和增量上传的item需要重新处理,回到New)
+// - 本地未完成但target上已经有完整的chunk:直接设置为Done
+// - 还没有算出内容hash(chunk_id)的item无法确认target上的chunk是同一个内容,不修正,在报告中列出
+//checkpoint的状态由修正后的item状态推导,结果只取决于item状态,重复执行得到相同的结果
+use serde::{Serialize, Deserialize};
+use buckyos_backup_lib::*;
+
+use crate::task_db::CheckPointState;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemReconcileAction {
+    Requeue,//target上缺少chunk,需要重新上传
+    Complete,//target上已经有chunk,不需要再上传
+}
+
+//item的内容在target上保存的chunk,没有chunk_id的item返回None
+//quick_hash只采样了文件的一部分,target上存在同名的chunk不能说明内容相同,不能代替chunk_id
+pub fn get_item_target_chunk_ids(item: &BackupItem) -> Option<Vec<String>> {
+    item.chunk_id.as_ref()?;
+    //增量上传的item需要所有chunk都存在
+    let chunk_ids = match (item.pack_info.as_ref(), load_item_diff_info(item)) {
+        (Some(pack_info), _) => PackItemLocation::from_json_str(pack_info.as_str())
+            .map(|location| location.pack_chunk_id).into_iter().collect(),
+        (None, Some(diff_info)) => diff_info.chunks.into_iter().map(|chunk| chunk.chunk_id).collect(),
+        (None, None) => item.chunk_id.clone().into_iter().collect(),
+    };
+    Some(chunk_ids)
+}
+
+//item的本地状态和target上chunk是否存在不一致时返回需要的修正
+pub fn reconcile_item_state(item: &BackupItem, remote_exist: bool) -> Option<ItemReconcileAction> {
+    if item.item_type.is_metadata_only() {
+        return None;
+    }
+    match (&item.state, remote_exist) {
+        (BackupItemState::Done, false) => Some(ItemReconcileAction::Requeue),
+        //New的item还没有计算hash,chunk存在也无法确认是同一个内容
+        (BackupItemState::New, _) => None,
+        (BackupItemState::Done, true) => None,
+        (_, true) if item.chunk_id.is_none() => None,
+        (_, true) => Some(ItemReconcileAction::Complete),
+        (_, false) => None,
+    }
+}
+
+pub fn apply_item_reconcile_action(item: &mut BackupItem, action: &ItemReconcileAction) {
+    match action {
+        ItemReconcileAction::Requeue => {
+            if item.pack_info.is_some() {
+                //pack chunk丢失后无法单独上传其中的item,重新计算并打包
+                item.pack_info = None;
+                item.state = BackupItemState::New;
+            } else if load_item_diff_info(item).is_some() {
+                //增量上传的chunk丢失,重新切分上传
+                item.diff_info = None;
+                item.chunk_id = None;
+                item.state = BackupItemState::New;
+            } else {
+                item.state = BackupItemState::LocalDone;
+            }
+            item.progress = "".to_string();
+        }
+        ItemReconcileAction::Complete => {
+            item.state = BackupItemState::Done;
+            item.progress = "".to_string();
+        }
+    }
+}
+
+//根据修正后的item状态推导checkpoint的状态
+//New(还在枚举item)和Failed(比如用户拒绝了异常的checkpoint)不自动改变
+pub fn resolve_checkpoint_state(current: &CheckPointState, items: &[BackupItem]) -> CheckPointState {
+    if *current == CheckPointState::New || *current == CheckPointState::Failed {
+        return current.clone();
+    }
+    let have_new_item = items.iter().any(|item| item.state == BackupItemState::New);
+    let have_pending_item = items.iter().any(|item| item.state != BackupItemState::Done);
+    if have_new_item {
+        return CheckPointState::Prepared;
+    }
+    if have_pending_item {
+        return CheckPointState::Evaluated;
+    }
+    //等待用户确认的checkpoint,数据完整后仍然需要确认;数据还在spool中的checkpoint由spool上传完成后变成Done
+    if *current == CheckPointState::WaitConfirm || *current == CheckPointState::LocalDone {
+        return current.clone();
+    }
+    CheckPointState::Done
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointReconcileReport {
+    pub checkpoint_id: String,
+    pub old_state: String,
+    pub new_state: String,
+    pub checked_chunk_count: u64,
+    pub requeued_items: Vec<String>,
+    pub completed_items: Vec<String>,
+    pub unverified_items: Vec<String>,//没有chunk_id,无法和target比较的item,状态保持不变
+}
+
+impl CheckpointReconcileReport {
+    pub fn is_changed(&self) -> bool {
+        self.old_state != self.new_state || !self.requeued_items.is_empty() || !self.completed_items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, state: BackupItemState, pack_info: Option<&str>) -> BackupItem {
+        let mut item = BackupItem::new(item_id, BackupItemType::Chunk, 0);
+        item.chunk_id = Some("sha256:00".to_string());
+        item.state = state;
+        item.pack_info = pack_info.map(|s| s.to_string());
+        item
+    }
+
+    #[test]
+    fn test_reconcile_item_state() {
+        let mut item = make_item("a", BackupItemState::Done, None);
+        assert_eq!(reconcile_item_state(&item, true), None);
+        let action = reconcile_item_state(&item, false).unwrap();
+        assert_eq!(action, ItemReconcileAction::Requeue);
+        apply_item_reconcile_action(&mut item, &action);
+        assert_eq!(item.state, BackupItemState::LocalDone);
+
+        let mut packed_item = make_item("b", BackupItemState::Done, Some("{}"));
+        apply_item_reconcile_action(&mut packed_item, &ItemReconcileAction::Requeue);
+        assert_eq!(packed_item.state, BackupItemState::New);
+        assert!(packed_item.pack_info.is_none());
+
+        let mut delta_item = make_item("e", BackupItemState::Done, None);
+        delta_item.diff_info = Some(FileDiffInfo::default().to_json_string());
+        apply_item_reconcile_action(&mut delta_item, &ItemReconcileAction::Requeue);
+        assert_eq!(delta_item.state, BackupItemState::New);
+        assert!(delta_item.chunk_id.is_none() && delta_item.diff_info.is_none());
+
+        let mut item = make_item("c", BackupItemState::Transmitting, None);
+        assert_eq!(reconcile_item_state(&item, false), None);
+        let action = reconcile_item_state(&item, true).unwrap();
+        apply_item_reconcile_action(&mut item, &action);
+        assert_eq!(item.state, BackupItemState::Done);
+        assert_eq!(reconcile_item_state(&make_item("d", BackupItemState::New, None), true), None);
+
+        //只有quick_hash的item不能按target上的chunk完成
+        let mut item = make_item("f", BackupItemState::Transmitting, None);
+        item.quick_hash = item.chunk_id.take();
+        assert_eq!(get_item_target_chunk_ids(&item), None);
+        assert_eq!(reconcile_item_state(&item, true), None);
+        assert_eq!(get_item_target_chunk_ids(&make_item("g", BackupItemState::Done, None)), Some(vec!["sha256:00".to_string()]));
+    }
+
+    #[test]
+    fn test_resolve_checkpoint_state() {
+        let done_items = vec![make_item("a", BackupItemState::Done, None), make_item("b", BackupItemState::Done, None)];
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::Evaluated, &done_items), CheckPointState::Done);
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::WaitConfirm, &done_items), CheckPointState::WaitConfirm);
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::LocalDone, &done_items), CheckPointState::LocalDone);
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::Failed, &done_items), CheckPointState::Failed);
+
+        let pending_items = vec![make_item("a", BackupItemState::Done, None), make_item("b", BackupItemState::LocalDone, None)];
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::Done, &pending_items), CheckPointState::Evaluated);
+        let new_items = vec![make_item("a", BackupItemState::New, None), make_item("b", BackupItemState::LocalDone, None)];
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::Done, &new_items), CheckPointState::Prepared);
+        assert_eq!(resolve_checkpoint_state(&CheckPointState::New, &new_items), CheckPointState::New);
+    }
+}
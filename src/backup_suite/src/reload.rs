@@ -0,0 +1,83 @@
+#![allow(unused)]
+//不重启服务重新加载配置:重新读取plugins目录注册provider,从task db重新读取所有plan并同步plan相关的状态
+//provider registry和plan列表分别在一次加锁中整体替换,其它请求不会看到一半新一半旧的状态
+//已经加载的插件动态库不会卸载(运行中的task可能还在使用),用同名文件替换插件需要重启服务
+use std::collections::HashMap;
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanReloadDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub updated: Vec<String>,
+}
+
+//按plan_id比较内存中的plan和db中的plan,plan内容用to_json_value比较
+pub fn diff_plans(current: &HashMap<String, Value>, loaded: &HashMap<String, Value>) -> PlanReloadDiff {
+    let mut diff = PlanReloadDiff::default();
+    for (plan_id, plan) in loaded.iter() {
+        match current.get(plan_id) {
+            None => diff.added.push(plan_id.clone()),
+            Some(current_plan) if current_plan != plan => diff.updated.push(plan_id.clone()),
+            _ => {}
+        }
+    }
+    for plan_id in current.keys() {
+        if !loaded.contains_key(plan_id) {
+            diff.removed.push(plan_id.clone());
+        }
+    }
+    diff.added.sort();
+    diff.removed.sort();
+    diff.updated.sort();
+    diff
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineReloadReport {
+    pub schemes: Vec<String>,
+    pub plugins: Vec<String>,//加载成功的插件文件
+    pub added_plans: Vec<String>,
+    pub removed_plans: Vec<String>,
+    pub updated_plans: Vec<String>,
+    pub kept_plans: Vec<String>,//db中已经删除,但是还有运行中的task,下次reload时再移除
+    pub reload_time: u64,
+}
+
+impl EngineReloadReport {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "schemes": self.schemes,
+            "plugins": self.plugins,
+            "added_plans": self.added_plans,
+            "removed_plans": self.removed_plans,
+            "updated_plans": self.updated_plans,
+            "kept_plans": self.kept_plans,
+            "reload_time": self.reload_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_plans() {
+        let current: HashMap<String, Value> = [
+            ("plan_a".to_string(), json!({"title": "a"})),
+            ("plan_b".to_string(), json!({"title": "b"})),
+            ("plan_c".to_string(), json!({"title": "c"})),
+        ].into_iter().collect();
+        let loaded: HashMap<String, Value> = [
+            ("plan_a".to_string(), json!({"title": "a"})),
+            ("plan_b".to_string(), json!({"title": "b2"})),
+            ("plan_d".to_string(), json!({"title": "d"})),
+        ].into_iter().collect();
+        let diff = diff_plans(&current, &loaded);
+        assert_eq!(diff.added, vec!["plan_d".to_string()]);
+        assert_eq!(diff.removed, vec!["plan_c".to_string()]);
+        assert_eq!(diff.updated, vec!["plan_b".to_string()]);
+        assert_eq!(diff_plans(&current, &current), PlanReloadDiff::default());
+    }
+}
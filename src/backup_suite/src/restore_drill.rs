@@ -0,0 +1,143 @@
+#![allow(unused)]
+//恢复演练:定期从plan最新的可恢复checkpoint中随机抽取一些文件,恢复到临时目录并校验hash,证明备份确实能恢复出来
+//演练不创建restore task,每次的结果记录在task db的restore_drills中,有文件校验失败时发布RestoreDrillFailed事件
+//全部通过时删除临时目录,失败时保留恢复出来的文件用于排查
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use buckyos_backup_lib::*;
+
+pub const RESTORE_DRILL_DIR_NAME: &str = "restore_drill";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestoreDrillPolicy {
+    pub enabled: bool,
+    pub interval_secs: u64,//两次演练的间隔
+    pub sample_count: usize,//每次抽取的文件数
+    pub max_sample_bytes: u64,//抽取的文件总大小上限,避免演练占用太多带宽,0为不限制
+    pub scratch_dir: Option<String>,//恢复的临时目录,为None时使用engine数据目录下的restore_drill目录
+}
+
+impl Default for RestoreDrillPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 7 * 24 * 3600,
+            sample_count: 16,
+            max_sample_bytes: 256 * 1024 * 1024,
+            scratch_dir: None,
+        }
+    }
+}
+
+impl RestoreDrillPolicy {
+    //时间都是ms,last_drill_time为0表示还没有演练过
+    pub fn is_due(&self, last_drill_time: u64, now: u64) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        last_drill_time == 0 || now >= last_drill_time.saturating_add(self.interval_secs.saturating_mul(1000))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreDrillFailure {
+    pub item_id: String,//为空表示checkpoint本身不能读取(比如签名校验失败)
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreDrillRecord {
+    pub drill_id: String,
+    pub plan_id: String,
+    pub checkpoint_id: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub sample_count: u64,
+    pub verified_count: u64,
+    pub verified_size: u64,
+    pub failures: Vec<RestoreDrillFailure>,
+    #[serde(default)]
+    pub checkpoint_size: u64,//演练时checkpoint中文件的总大小,SLA报告用来外推RTO
+}
+
+impl RestoreDrillRecord {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+//随机抽样:按hash(seed + item_id)排序后依次选取,同一个seed的结果可以重现
+//只抽取有内容的文件,超过剩余大小上限的文件跳过,继续尝试后面更小的文件
+pub fn select_drill_samples(items: &[BackupItem], seed: &str, sample_count: usize, max_sample_bytes: u64) -> Vec<BackupItem> {
+    let mut candidates: Vec<(Vec<u8>, &BackupItem)> = items.iter()
+        .filter(|item| matches!(item.item_type, BackupItemType::File | BackupItemType::Chunk))
+        .filter(|item| item.state == BackupItemState::Done && item.chunk_id.is_some())
+        .map(|item| {
+            let mut hasher = Sha256::new();
+            hasher.update(seed.as_bytes());
+            hasher.update(item.item_id.as_bytes());
+            (hasher.finalize().to_vec(), item)
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut samples = Vec::new();
+    let mut total_size = 0u64;
+    for (_, item) in candidates {
+        if samples.len() >= sample_count {
+            break;
+        }
+        if max_sample_bytes > 0 && total_size + item.size > max_sample_bytes {
+            continue;
+        }
+        total_size += item.size;
+        samples.push(item.clone());
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_item(item_id: &str, item_type: BackupItemType, size: u64) -> BackupItem {
+        let mut item = BackupItem::new(item_id, item_type, size);
+        item.chunk_id = Some(format!("sha256:{:064}", size));
+        item.state = BackupItemState::Done;
+        item
+    }
+
+    #[test]
+    fn test_select_drill_samples() {
+        let mut items: Vec<BackupItem> = (0..20).map(|i| make_item(format!("file_{}.bin", i).as_str(), BackupItemType::File, 100)).collect();
+        items.push(make_item("dir", BackupItemType::Directory, 0));
+        items.push(make_item("link", BackupItemType::Symlink, 0));
+        items.push(make_item("big.bin", BackupItemType::File, 10000));
+        let mut failed = make_item("failed.bin", BackupItemType::File, 100);
+        failed.state = BackupItemState::Failed("read error".to_string());
+        failed.chunk_id = None;
+        items.push(failed);
+
+        let samples = select_drill_samples(&items, "drill_1", 5, 1000);
+        assert_eq!(samples.len(), 5);
+        assert!(samples.iter().all(|item| item.item_id.starts_with("file_")));
+        //同一个seed抽样结果相同,不同seed一般不同
+        assert_eq!(samples, select_drill_samples(&items, "drill_1", 5, 1000));
+        let other_ids: Vec<String> = select_drill_samples(&items, "drill_2", 5, 1000).into_iter().map(|item| item.item_id).collect();
+        assert_ne!(samples.iter().map(|item| item.item_id.clone()).collect::<Vec<String>>(), other_ids);
+
+        //大小上限限制抽取的数量,0为不限制
+        assert_eq!(select_drill_samples(&items, "drill_1", 100, 450).len(), 4);
+        assert_eq!(select_drill_samples(&items, "drill_1", 100, 0).len(), 21);
+    }
+
+    #[test]
+    fn test_restore_drill_policy_due() {
+        let policy = RestoreDrillPolicy { enabled: true, interval_secs: 3600, ..Default::default() };
+        assert!(policy.is_due(0, 1000));
+        assert!(!policy.is_due(1000, 1000 + 3599 * 1000));
+        assert!(policy.is_due(1000, 1000 + 3600 * 1000));
+        assert!(!RestoreDrillPolicy::default().is_due(0, 1000));
+    }
+}
@@ -0,0 +1,152 @@
+#![allow(unused)]
+//恢复的并发配置:同时从target获取的item数(窗口),每个item的预读深度,同时写入本地文件的item数
+//配置保存在RestoreConfig.params的parallelism字段中,每个恢复task可以不同
+//adaptive时窗口按target最近的读取延迟调整:延迟越高,需要越多的请求在途才能把写入线程喂饱
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use log::*;
+use ndn_lib::ChunkReader;
+use buckyos_backup_lib::*;
+
+pub const RESTORE_PARALLELISM_PARAM: &str = "parallelism";
+pub const RESTORE_READ_AHEAD_PIECE_SIZE: usize = 1024 * 1024;
+//读取延迟每增加这么多,窗口多一个在途的item
+const RESTORE_LATENCY_PER_EXTRA_FETCH_US: u64 = 20 * 1000;
+const MAX_RESTORE_PARALLEL_ITEMS: usize = 256;
+const MAX_RESTORE_READ_AHEAD_PIECES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestoreParallelism {
+    pub max_parallel_items: usize,//同时从target获取的item数上限
+    pub min_parallel_items: usize,//adaptive时窗口的下限
+    pub read_ahead_pieces: usize,//每个item在等待写入时预读的piece数,0为不预读
+    pub write_concurrency: usize,//同时写入本地文件的item数
+    pub adaptive: bool,
+}
+
+impl Default for RestoreParallelism {
+    fn default() -> Self {
+        Self {
+            max_parallel_items: 16,
+            min_parallel_items: 2,
+            read_ahead_pieces: 4,
+            write_concurrency: 4,
+            adaptive: true,
+        }
+    }
+}
+
+impl RestoreParallelism {
+    pub fn from_restore_config(restore_config: &RestoreConfig) -> anyhow::Result<Self> {
+        let parallelism = restore_config.params.as_ref().and_then(|params| params.get(RESTORE_PARALLELISM_PARAM));
+        let parallelism: Self = match parallelism {
+            Some(parallelism) => serde_json::from_value(parallelism.clone())
+                .map_err(|e| anyhow::anyhow!("invalid restore parallelism: {}", e))?,
+            None => Self::default(),
+        };
+        parallelism.check()?;
+        Ok(parallelism)
+    }
+
+    pub fn set_to_restore_config(&self, restore_config: &mut RestoreConfig) {
+        let mut params = restore_config.params.take()
+            .filter(|params| params.is_object())
+            .unwrap_or_else(|| json!({}));
+        params[RESTORE_PARALLELISM_PARAM] = serde_json::to_value(self).unwrap();
+        restore_config.params = Some(params);
+    }
+
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.min_parallel_items == 0 || self.write_concurrency == 0 {
+            return Err(anyhow::anyhow!("restore parallelism min_parallel_items and write_concurrency must be at least 1"));
+        }
+        if self.max_parallel_items < self.min_parallel_items || self.max_parallel_items > MAX_RESTORE_PARALLEL_ITEMS {
+            return Err(anyhow::anyhow!("restore parallelism max_parallel_items must be between {} and {}",
+                self.min_parallel_items, MAX_RESTORE_PARALLEL_ITEMS));
+        }
+        if self.read_ahead_pieces > MAX_RESTORE_READ_AHEAD_PIECES {
+            return Err(anyhow::anyhow!("restore parallelism read_ahead_pieces must be at most {}", MAX_RESTORE_READ_AHEAD_PIECES));
+        }
+        Ok(())
+    }
+
+    //latency_us是target读取请求的p90延迟,还没有统计数据时为None
+    pub fn window_for_latency(&self, latency_us: Option<u64>) -> usize {
+        if !self.adaptive {
+            return self.max_parallel_items;
+        }
+        let latency_us = match latency_us {
+            Some(latency_us) => latency_us,
+            None => return self.max_parallel_items,
+        };
+        let extra_fetches = (latency_us / RESTORE_LATENCY_PER_EXTRA_FETCH_US) as usize;
+        (self.write_concurrency + extra_fetches).clamp(self.min_parallel_items, self.max_parallel_items)
+    }
+
+    pub fn read_ahead_size(&self) -> usize {
+        self.read_ahead_pieces * RESTORE_READ_AHEAD_PIECE_SIZE
+    }
+}
+
+//后台把reader的内容读到大小为buffer_size的缓冲区中,item等待写入或者写入本地文件时继续从target读取
+//后台读取失败时返回的reader提前结束,由调用方的长度/hash校验发现
+pub fn read_ahead_reader(item_id: &str, reader: ChunkReader, buffer_size: usize) -> ChunkReader {
+    if buffer_size == 0 {
+        return reader;
+    }
+    let (mut buffer_writer, buffer_reader) = tokio::io::duplex(buffer_size);
+    let item_id = item_id.to_string();
+    tokio::spawn(async move {
+        let mut reader = reader;
+        if let Err(e) = tokio::io::copy(&mut reader, &mut buffer_writer).await {
+            warn!("read ahead restore item {} error: {}", item_id, e);
+        }
+    });
+    Box::pin(buffer_reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_restore_parallelism() {
+        let mut restore_config = RestoreConfig {
+            restore_location_url: "file:///tmp/restore".to_string(),
+            is_clean_restore: false,
+            name_collision_policy: NameCollisionPolicy::default(),
+            conflict_policy: None,
+            restore_in_place: false,
+            params: None,
+        };
+        assert_eq!(RestoreParallelism::from_restore_config(&restore_config).unwrap(), RestoreParallelism::default());
+
+        let parallelism = RestoreParallelism { max_parallel_items: 8, write_concurrency: 2, ..Default::default() };
+        parallelism.set_to_restore_config(&mut restore_config);
+        assert_eq!(RestoreParallelism::from_restore_config(&restore_config).unwrap(), parallelism);
+        //低延迟时只需要比写入并发多一点,高延迟时窗口增大到上限
+        assert_eq!(parallelism.window_for_latency(None), 8);
+        assert_eq!(parallelism.window_for_latency(Some(1000)), 2);
+        assert_eq!(parallelism.window_for_latency(Some(65 * 1000)), 5);
+        assert_eq!(parallelism.window_for_latency(Some(2000 * 1000)), 8);
+        let fixed = RestoreParallelism { adaptive: false, ..parallelism.clone() };
+        assert_eq!(fixed.window_for_latency(Some(1000)), 8);
+
+        let invalid = RestoreParallelism { min_parallel_items: 4, max_parallel_items: 2, ..Default::default() };
+        invalid.set_to_restore_config(&mut restore_config);
+        assert!(RestoreParallelism::from_restore_config(&restore_config).is_err());
+        assert!(RestoreParallelism { write_concurrency: 0, ..Default::default() }.check().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead_reader() {
+        let content: Vec<u8> = (0..3 * RESTORE_READ_AHEAD_PIECE_SIZE).map(|i| (i % 251) as u8).collect();
+        let reader: ChunkReader = Box::pin(std::io::Cursor::new(content.clone()));
+        let mut reader = read_ahead_reader("item_1", reader, RESTORE_READ_AHEAD_PIECE_SIZE);
+        let mut restored = Vec::new();
+        reader.read_to_end(&mut restored).await.unwrap();
+        assert_eq!(restored, content);
+    }
+}
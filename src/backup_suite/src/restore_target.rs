@@ -0,0 +1,48 @@
+#![allow(unused)]
+//恢复时指定读取chunk的target,代替plan当前的target:比如从副本,或者从种子导出的本地目录恢复
+//指定的target url保存在RestoreConfig.params的restore_target字段中,创建恢复task时检查checkpoint的chunk都在这个target上
+use serde_json::{Value, json};
+use buckyos_backup_lib::*;
+
+pub const RESTORE_TARGET_PARAM: &str = "restore_target";
+
+//没有指定时返回None,使用plan的target
+pub fn get_restore_target_url(restore_config: &RestoreConfig) -> Option<String> {
+    restore_config.params.as_ref()
+        .and_then(|params| params.get(RESTORE_TARGET_PARAM))
+        .and_then(|target_url| target_url.as_str())
+        .filter(|target_url| !target_url.is_empty())
+        .map(|target_url| target_url.to_string())
+}
+
+pub fn set_restore_target_url(restore_config: &mut RestoreConfig, target_url: &str) {
+    let mut params = restore_config.params.take()
+        .filter(|params| params.is_object())
+        .unwrap_or_else(|| json!({}));
+    params[RESTORE_TARGET_PARAM] = Value::String(target_url.to_string());
+    restore_config.params = Some(params);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_target_param() {
+        let mut restore_config = RestoreConfig {
+            restore_location_url: "file:///restore".to_string(),
+            is_clean_restore: false,
+            name_collision_policy: NameCollisionPolicy::default(),
+            conflict_policy: None,
+            restore_in_place: false,
+            params: Some(json!({ "contents_query": { "extensions": ["jpg"] } })),
+        };
+        assert!(get_restore_target_url(&restore_config).is_none());
+        set_restore_target_url(&mut restore_config, "file:///mnt/seed");
+        assert_eq!(get_restore_target_url(&restore_config), Some("file:///mnt/seed".to_string()));
+        //不影响其它参数
+        assert!(restore_config.params.as_ref().unwrap().get("contents_query").is_some());
+        set_restore_target_url(&mut restore_config, "");
+        assert!(get_restore_target_url(&restore_config).is_none());
+    }
+}
@@ -0,0 +1,209 @@
+#![allow(unused)]
+//plan的checkpoint保留策略(GFS),配置在BackupPlanOptions.retention中
+//备份完成后按策略计算每个完成的checkpoint被哪些规则保留,没有任何规则保留的checkpoint会被删除
+//daily/weekly/monthly/yearly:在最近的N个有checkpoint的天/周/月/年中,各保留该周期内最新的一个
+//被pin的checkpoint,以及被保留的checkpoint依赖的checkpoint也会保留
+//周期使用UTC时间,周从周一开始(和schedule一致)
+use std::collections::HashMap;
+use chrono::Datelike;
+use serde::{Serialize, Deserialize};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+const WEEK_MS: u64 = 7 * DAY_MS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub keep_last: u32,//保留最新的N个
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    pub keep_monthly: u32,
+    pub keep_yearly: u32,
+}
+
+//默认不删除任何checkpoint
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 0,
+            keep_daily: 0,
+            keep_weekly: 0,
+            keep_monthly: 0,
+            keep_yearly: 0,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.keep_last > 0 || self.keep_daily > 0 || self.keep_weekly > 0
+            || self.keep_monthly > 0 || self.keep_yearly > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionRule {
+    Last,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Pinned,
+    Dependency,//被其它保留的checkpoint依赖
+}
+
+impl RetentionRule {
+    //create_time所在周期的编号,同一个周期内的checkpoint编号相同
+    fn period_key(&self, create_time: u64) -> Option<i64> {
+        match self {
+            RetentionRule::Daily => Some((create_time / DAY_MS) as i64),
+            //1970-01-01是周四,往后移3天让周从周一开始
+            RetentionRule::Weekly => Some(((create_time + 3 * DAY_MS) / WEEK_MS) as i64),
+            RetentionRule::Monthly => chrono::DateTime::from_timestamp_millis(create_time as i64)
+                .map(|time| time.year() as i64 * 12 + time.month0() as i64),
+            RetentionRule::Yearly => chrono::DateTime::from_timestamp_millis(create_time as i64)
+                .map(|time| time.year() as i64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionCandidate {
+    pub checkpoint_id: String,
+    pub checkpoint_index: u64,
+    pub create_time: u64,
+    pub depend_checkpoint_id: Option<String>,
+    pub pinned: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionDecision {
+    pub checkpoint_id: String,
+    pub rules: Vec<RetentionRule>,//保留这个checkpoint的规则,为空表示可以删除
+}
+
+impl RetentionDecision {
+    pub fn is_kept(&self) -> bool {
+        !self.rules.is_empty()
+    }
+}
+
+//返回的结果按checkpoint_index从新到旧排列
+pub fn evaluate_retention(policy: &RetentionPolicy, candidates: &[RetentionCandidate]) -> Vec<RetentionDecision> {
+    let mut candidates: Vec<&RetentionCandidate> = candidates.iter().collect();
+    //checkpoint的顺序以checkpoint_index为准,create_time只用来划分周期
+    candidates.sort_by(|a, b| b.checkpoint_index.cmp(&a.checkpoint_index));
+    let mut decisions: Vec<RetentionDecision> = candidates.iter()
+        .map(|candidate| RetentionDecision { checkpoint_id: candidate.checkpoint_id.clone(), rules: Vec::new() })
+        .collect();
+
+    for decision in decisions.iter_mut().take(policy.keep_last as usize) {
+        decision.rules.push(RetentionRule::Last);
+    }
+    let period_rules = [
+        (RetentionRule::Daily, policy.keep_daily),
+        (RetentionRule::Weekly, policy.keep_weekly),
+        (RetentionRule::Monthly, policy.keep_monthly),
+        (RetentionRule::Yearly, policy.keep_yearly),
+    ];
+    for (rule, keep_count) in period_rules.iter() {
+        let mut kept_count = 0;
+        let mut last_period_key = None;
+        for (pos, candidate) in candidates.iter().enumerate() {
+            if kept_count >= *keep_count {
+                break;
+            }
+            let period_key = rule.period_key(candidate.create_time);
+            if period_key.is_none() || period_key == last_period_key {
+                continue;
+            }
+            last_period_key = period_key;
+            decisions[pos].rules.push(rule.clone());
+            kept_count += 1;
+        }
+    }
+    for (pos, candidate) in candidates.iter().enumerate() {
+        if candidate.pinned {
+            decisions[pos].rules.push(RetentionRule::Pinned);
+        }
+    }
+
+    //依赖的checkpoint的index更小,排在后面,按顺序处理一遍就能覆盖整条依赖链
+    let positions: HashMap<&str, usize> = candidates.iter().enumerate()
+        .map(|(pos, candidate)| (candidate.checkpoint_id.as_str(), pos))
+        .collect();
+    for pos in 0..candidates.len() {
+        if !decisions[pos].is_kept() {
+            continue;
+        }
+        let depend_pos = candidates[pos].depend_checkpoint_id.as_ref()
+            .and_then(|depend_checkpoint_id| positions.get(depend_checkpoint_id.as_str()));
+        if let Some(depend_pos) = depend_pos {
+            if !decisions[*depend_pos].rules.contains(&RetentionRule::Dependency) {
+                decisions[*depend_pos].rules.push(RetentionRule::Dependency);
+            }
+        }
+    }
+    decisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //2024-01-01 00:00:00 UTC,周一
+    const MONDAY_MS: u64 = 1704067200000;
+
+    fn make_candidates(create_times: &[u64]) -> Vec<RetentionCandidate> {
+        create_times.iter().enumerate().map(|(index, create_time)| RetentionCandidate {
+            checkpoint_id: format!("chk_{}", index),
+            checkpoint_index: index as u64,
+            create_time: *create_time,
+            depend_checkpoint_id: None,
+            pinned: false,
+        }).collect()
+    }
+
+    fn kept_ids(decisions: &[RetentionDecision]) -> Vec<String> {
+        decisions.iter().filter(|decision| decision.is_kept()).map(|decision| decision.checkpoint_id.clone()).collect()
+    }
+
+    #[test]
+    fn test_gfs_retention() {
+        //连续60天,每天2个checkpoint
+        let create_times: Vec<u64> = (0..120).map(|i| MONDAY_MS + i / 2 * DAY_MS + (i % 2) * 3600 * 1000).collect();
+        let candidates = make_candidates(&create_times);
+
+        let policy = RetentionPolicy { keep_last: 1, keep_daily: 3, ..Default::default() };
+        let decisions = evaluate_retention(&policy, &candidates);
+        assert_eq!(kept_ids(&decisions), vec!["chk_119", "chk_117", "chk_115"]);
+        assert_eq!(decisions[0].rules, vec![RetentionRule::Last, RetentionRule::Daily]);
+
+        //2024-01是第一个月,每周/每月保留的都是周期内最新的checkpoint
+        let policy = RetentionPolicy { keep_weekly: 2, keep_monthly: 3, keep_yearly: 1, ..Default::default() };
+        let decisions = evaluate_retention(&policy, &candidates);
+        assert_eq!(kept_ids(&decisions), vec!["chk_119", "chk_111", "chk_61"]);
+        assert_eq!(decisions[0].rules, vec![RetentionRule::Weekly, RetentionRule::Monthly, RetentionRule::Yearly]);
+        assert_eq!(decisions[8].rules, vec![RetentionRule::Weekly]);
+        assert_eq!(decisions[58].rules, vec![RetentionRule::Monthly]);
+
+        assert!(!RetentionPolicy::default().is_enabled());
+        assert!(kept_ids(&evaluate_retention(&RetentionPolicy::default(), &candidates)).is_empty());
+    }
+
+    #[test]
+    fn test_retention_keep_pinned_and_dependency() {
+        let mut candidates = make_candidates(&[MONDAY_MS, MONDAY_MS + DAY_MS, MONDAY_MS + 2 * DAY_MS, MONDAY_MS + 3 * DAY_MS]);
+        candidates[1].pinned = true;
+        candidates[3].depend_checkpoint_id = Some("chk_2".to_string());
+        candidates[2].depend_checkpoint_id = Some("chk_0".to_string());
+        let policy = RetentionPolicy { keep_last: 1, ..Default::default() };
+        let decisions = evaluate_retention(&policy, &candidates);
+        assert_eq!(decisions[0].rules, vec![RetentionRule::Last]);
+        assert_eq!(decisions[1].rules, vec![RetentionRule::Dependency]);
+        assert_eq!(decisions[2].rules, vec![RetentionRule::Pinned]);
+        assert_eq!(decisions[3].rules, vec![RetentionRule::Dependency]);
+    }
+}
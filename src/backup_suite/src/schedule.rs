@@ -0,0 +1,255 @@
+#![allow(unused)]
+//plan的定时备份策略,配置在BackupPlanOptions.schedule中,多个策略时最早到期的生效
+//策略的计算都是纯函数(输入上次运行时间和当前时间),配合SimulatedClock可以在测试中快进数周,
+//simulate_schedule给出一段时间内会触发备份的所有时间点
+//Daily/Weekly使用UTC时间
+//备份task失败后按plan的BackupRetryPolicy自动重试,重试也由engine的schedule在后台循环中触发
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Serialize, Deserialize};
+
+use crate::task_db::WorkTask;
+
+const MINUTE_MS: u64 = 60 * 1000;
+const DAY_MS: u64 = 24 * 60 * MINUTE_MS;
+const WEEK_MS: u64 = 7 * DAY_MS;
+//防止间隔很小的策略在很长的模拟时间里产生过多结果
+pub const MAX_SIMULATE_FIRE_COUNT: usize = 10000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum BackupSchedulePolicy {
+    Period { interval_secs: u64 },//距离上次备份超过interval_secs
+    Daily { hour: u32, minute: u32 },
+    Weekly { weekday: u32, hour: u32, minute: u32 },//weekday: 0为周一
+}
+
+impl BackupSchedulePolicy {
+    //last_run_time之后下一次触发的时间(ms),策略无效时返回None
+    pub fn next_fire_time(&self, last_run_time: u64) -> Option<u64> {
+        match self {
+            BackupSchedulePolicy::Period { interval_secs } => {
+                if *interval_secs == 0 {
+                    return None;
+                }
+                Some(last_run_time + interval_secs * 1000)
+            }
+            BackupSchedulePolicy::Daily { hour, minute } => {
+                if *hour >= 24 || *minute >= 60 {
+                    return None;
+                }
+                let day_offset = (*hour as u64 * 60 + *minute as u64) * MINUTE_MS;
+                let next = last_run_time / DAY_MS * DAY_MS + day_offset;
+                Some(if next <= last_run_time { next + DAY_MS } else { next })
+            }
+            BackupSchedulePolicy::Weekly { weekday, hour, minute } => {
+                if *weekday >= 7 || *hour >= 24 || *minute >= 60 {
+                    return None;
+                }
+                //1970-01-01是周四,往前3天是周一
+                let monday_offset = 3 * DAY_MS;
+                let week_start = (last_run_time + monday_offset) / WEEK_MS * WEEK_MS;
+                let week_offset = *weekday as u64 * DAY_MS + (*hour as u64 * 60 + *minute as u64) * MINUTE_MS;
+                let next = (week_start + week_offset).saturating_sub(monday_offset);
+                Some(if next <= last_run_time { next + WEEK_MS } else { next })
+            }
+        }
+    }
+}
+
+pub fn next_schedule_fire_time(policies: &[BackupSchedulePolicy], last_run_time: u64) -> Option<u64> {
+    policies.iter()
+        .filter_map(|policy| policy.next_fire_time(last_run_time))
+        .min()
+}
+
+pub fn is_schedule_due(policies: &[BackupSchedulePolicy], last_run_time: u64, now: u64) -> bool {
+    next_schedule_fire_time(policies, last_run_time).map_or(false, |fire_time| fire_time <= now)
+}
+
+//模拟[start_time, end_time)内的调度,假设每次触发的备份都立即完成,返回所有触发时间
+//start_time时已经到期的会在start_time触发(和engine启动时补一次备份的行为一致)
+pub fn simulate_schedule(policies: &[BackupSchedulePolicy], last_run_time: u64, start_time: u64, end_time: u64) -> Vec<u64> {
+    let mut fire_times = Vec::new();
+    let mut last_run_time = last_run_time;
+    while fire_times.len() < MAX_SIMULATE_FIRE_COUNT {
+        let next = next_schedule_fire_time(policies, last_run_time);
+        if next.is_none() {
+            break;
+        }
+        let fire_time = next.unwrap().max(start_time);
+        if fire_time >= end_time {
+            break;
+        }
+        fire_times.push(fire_time);
+        last_run_time = fire_time;
+    }
+    fire_times
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RetryGiveUpAction {
+    WaitNextSchedule,//放弃重试,等下一次定时备份
+    PauseSchedule,//暂停plan的定时备份,直到手动备份成功
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupRetryPolicy {
+    pub max_attempts: u32,//失败后自动重试的最大次数,0为不重试
+    pub initial_delay_secs: u64,//第一次重试距离失败的时间
+    pub backoff_multiplier: f64,//每次重试的间隔是上一次的多少倍
+    pub max_delay_secs: u64,
+    pub give_up_action: RetryGiveUpAction,
+    pub alert_on_give_up: bool,//放弃重试时发布RetryGiveUp事件
+}
+
+impl Default for BackupRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay_secs: 5 * 60,
+            backoff_multiplier: 2.0,
+            max_delay_secs: 6 * 3600,
+            give_up_action: RetryGiveUpAction::WaitNextSchedule,
+            alert_on_give_up: true,
+        }
+    }
+}
+
+impl BackupRetryPolicy {
+    //第attempt次重试(从1开始)距离上次失败的时间(ms)
+    pub fn retry_delay_ms(&self, attempt: u32) -> u64 {
+        let multiplier = self.backoff_multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        let delay_secs = (self.initial_delay_secs as f64 * multiplier).min(self.max_delay_secs as f64);
+        delay_secs as u64 * 1000
+    }
+
+    //task失败后还能自动重试的次数,retry_attempt为这个task是第几次重试
+    pub fn remaining_attempts(&self, retry_attempt: u32) -> u32 {
+        self.max_attempts.saturating_sub(retry_attempt)
+    }
+
+    //retry_attempt次重试的task在fail_time失败后,下一次重试的时间,已经用完重试次数时返回None
+    pub fn next_retry_time(&self, retry_attempt: u32, fail_time: u64) -> Option<u64> {
+        if self.remaining_attempts(retry_attempt) == 0 {
+            return None;
+        }
+        Some(fail_time + self.retry_delay_ms(retry_attempt + 1))
+    }
+}
+
+//engine通过clock获取调度用的当前时间,测试时换成SimulatedClock
+pub trait ScheduleClock: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl ScheduleClock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        WorkTask::now_ms()
+    }
+}
+
+pub struct SimulatedClock {
+    now_ms: AtomicU64,
+}
+
+impl SimulatedClock {
+    pub fn new(start_ms: u64) -> Self {
+        Self { now_ms: AtomicU64::new(start_ms) }
+    }
+
+    pub fn advance(&self, duration_ms: u64) {
+        self.now_ms.fetch_add(duration_ms, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.store(now_ms, Ordering::SeqCst);
+    }
+}
+
+impl ScheduleClock for SimulatedClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //2024-01-01 00:00:00 UTC,周一
+    const MONDAY_MS: u64 = 1704067200000;
+
+    #[test]
+    fn test_policy_next_fire_time() {
+        let period = BackupSchedulePolicy::Period { interval_secs: 3600 };
+        assert_eq!(period.next_fire_time(MONDAY_MS), Some(MONDAY_MS + 3600 * 1000));
+        assert_eq!(BackupSchedulePolicy::Period { interval_secs: 0 }.next_fire_time(MONDAY_MS), None);
+
+        let daily = BackupSchedulePolicy::Daily { hour: 2, minute: 30 };
+        assert_eq!(daily.next_fire_time(MONDAY_MS), Some(MONDAY_MS + 150 * MINUTE_MS));
+        assert_eq!(daily.next_fire_time(MONDAY_MS + 150 * MINUTE_MS), Some(MONDAY_MS + DAY_MS + 150 * MINUTE_MS));
+
+        let weekly = BackupSchedulePolicy::Weekly { weekday: 2, hour: 0, minute: 0 };
+        assert_eq!(weekly.next_fire_time(MONDAY_MS), Some(MONDAY_MS + 2 * DAY_MS));
+        assert_eq!(weekly.next_fire_time(MONDAY_MS + 3 * DAY_MS), Some(MONDAY_MS + 9 * DAY_MS));
+        assert_eq!(BackupSchedulePolicy::Weekly { weekday: 7, hour: 0, minute: 0 }.next_fire_time(MONDAY_MS), None);
+    }
+
+    #[test]
+    fn test_simulate_schedule() {
+        let policies = vec![BackupSchedulePolicy::Daily { hour: 3, minute: 0 }];
+        let fire_times = simulate_schedule(&policies, MONDAY_MS, MONDAY_MS, MONDAY_MS + 4 * WEEK_MS);
+        assert_eq!(fire_times.len(), 28);
+        assert_eq!(fire_times[0], MONDAY_MS + 3 * 60 * MINUTE_MS);
+        assert!(fire_times.windows(2).all(|pair| pair[1] - pair[0] == DAY_MS));
+
+        //多个策略时最早到期的生效,每次触发后重新计算
+        let policies = vec![
+            BackupSchedulePolicy::Weekly { weekday: 6, hour: 12, minute: 0 },
+            BackupSchedulePolicy::Period { interval_secs: 3 * 24 * 3600 },
+        ];
+        let fire_times = simulate_schedule(&policies, MONDAY_MS, MONDAY_MS, MONDAY_MS + WEEK_MS);
+        assert_eq!(fire_times, vec![MONDAY_MS + 3 * DAY_MS, MONDAY_MS + 6 * DAY_MS, MONDAY_MS + 6 * DAY_MS + 12 * 60 * MINUTE_MS]);
+
+        //很久没有运行过的plan在开始时立即触发一次
+        let policies = vec![BackupSchedulePolicy::Period { interval_secs: 3600 }];
+        let fire_times = simulate_schedule(&policies, 0, MONDAY_MS, MONDAY_MS + 2 * 3600 * 1000);
+        assert_eq!(fire_times, vec![MONDAY_MS, MONDAY_MS + 3600 * 1000]);
+        assert!(simulate_schedule(&[], 0, MONDAY_MS, MONDAY_MS + WEEK_MS).is_empty());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = BackupRetryPolicy {
+            max_attempts: 4,
+            initial_delay_secs: 60,
+            backoff_multiplier: 2.0,
+            max_delay_secs: 200,
+            ..Default::default()
+        };
+        assert_eq!(policy.retry_delay_ms(1), 60 * 1000);
+        assert_eq!(policy.retry_delay_ms(2), 120 * 1000);
+        assert_eq!(policy.retry_delay_ms(3), 200 * 1000);
+        assert_eq!(policy.next_retry_time(0, MONDAY_MS), Some(MONDAY_MS + 60 * 1000));
+        assert_eq!(policy.next_retry_time(3, MONDAY_MS), Some(MONDAY_MS + 200 * 1000));
+        assert_eq!(policy.next_retry_time(4, MONDAY_MS), None);
+        assert_eq!(policy.remaining_attempts(1), 3);
+
+        let no_retry = BackupRetryPolicy { max_attempts: 0, ..Default::default() };
+        assert_eq!(no_retry.next_retry_time(0, MONDAY_MS), None);
+    }
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::new(MONDAY_MS);
+        let policies = vec![BackupSchedulePolicy::Daily { hour: 1, minute: 0 }];
+        assert!(!is_schedule_due(&policies, MONDAY_MS, clock.now_ms()));
+        clock.advance(60 * MINUTE_MS);
+        assert!(is_schedule_due(&policies, MONDAY_MS, clock.now_ms()));
+        clock.set(MONDAY_MS);
+        assert_eq!(clock.now_ms(), MONDAY_MS);
+    }
+}
@@ -0,0 +1,214 @@
+#![allow(unused)]
+//离线种子:第一次完整备份的数据量很大(TB级)时,把checkpoint的chunk导出到移动硬盘,寄到target所在的地方再导入,不需要经过WAN上传
+//种子目录就是file:// target的目录结构(NamedDataStore)加上一个清单,file:// target可以直接复制目录,其它target用import导入
+//导出后checkpoint的chunk不再从spool上传,导入完成后在源端确认(检查target上的chunk),之后的增量备份正常上传
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use anyhow::Result;
+use log::*;
+use ndn_lib::ChunkId;
+use buckyos_backup_lib::*;
+
+pub const SEED_MANIFEST_FILE_NAME: &str = "seed_manifest.json";
+pub const SEED_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedChunk {
+    pub chunk_id: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedManifest {
+    pub version: u32,
+    pub plan_id: String,
+    pub checkpoint_id: String,
+    pub source_url: String,
+    pub target_url: String,//导出时plan的target,导入时可以指定其它url(比如同一个bucket的内网地址)
+    pub create_time: u64,
+    pub chunks: Vec<SeedChunk>,
+}
+
+impl SeedManifest {
+    pub fn total_size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.size).sum()
+    }
+
+    pub fn save_to_dir(&self, seed_dir: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(seed_dir.join(SEED_MANIFEST_FILE_NAME), content)?;
+        Ok(())
+    }
+
+    pub fn load_from_dir(seed_dir: &Path) -> Result<Self> {
+        let manifest_path = seed_dir.join(SEED_MANIFEST_FILE_NAME);
+        let content = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| anyhow::anyhow!("read seed manifest {} error: {}", manifest_path.display(), e))?;
+        let manifest: SeedManifest = serde_json::from_str(content.as_str())?;
+        if manifest.version > SEED_FORMAT_VERSION {
+            return Err(anyhow::anyhow!("seed format version {} is not supported, upgrade backup_suite", manifest.version));
+        }
+        Ok(manifest)
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "plan_id": self.plan_id,
+            "checkpoint_id": self.checkpoint_id,
+            "target_url": self.target_url,
+            "create_time": self.create_time,
+            "chunk_count": self.chunks.len(),
+            "total_size": self.total_size(),
+        })
+    }
+}
+
+//源端记录的导出,import_confirm_time为None表示还没有确认target上已经导入
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedExportRecord {
+    pub checkpoint_id: String,
+    pub plan_id: String,
+    pub target_url: String,
+    pub seed_dir: String,
+    pub chunk_count: u64,
+    pub total_size: u64,
+    pub export_time: u64,
+    pub import_confirm_time: Option<u64>,
+}
+
+impl SeedExportRecord {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "checkpoint_id": self.checkpoint_id,
+            "plan_id": self.plan_id,
+            "target_url": self.target_url,
+            "seed_dir": self.seed_dir,
+            "chunk_count": self.chunk_count,
+            "total_size": self.total_size,
+            "export_time": self.export_time,
+            "import_confirm_time": self.import_confirm_time,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeedImportReport {
+    pub imported_count: u64,
+    pub imported_size: u64,
+    pub skipped_count: u64,//target上已经存在的chunk
+}
+
+impl SeedImportReport {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "imported_count": self.imported_count,
+            "imported_size": self.imported_size,
+            "skipped_count": self.skipped_count,
+        })
+    }
+}
+
+//从from读取chunk写入to,to上已经存在时返回false
+//边复制边计算hash,hash不一致时不complete,移动硬盘上损坏的chunk不会进入target;续传时从头读取from,跳过已经写入的部分
+pub async fn copy_chunk_between_targets(from: &BackupChunkTargetProvider, to: &BackupChunkTargetProvider,
+    chunk_id: &ChunkId, size: u64) -> BackupResult<bool> {
+    let (mut writer, offset) = match to.open_chunk_writer(chunk_id, 0, size).await {
+        Ok(result) => result,
+        Err(BuckyBackupError::AlreadyDone(_)) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let mut reader = from.open_chunk_reader_for_restore(chunk_id, 0).await?;
+    let mut hasher = BackupChunkHasher::for_chunk_id(chunk_id)
+        .map_err(|e| BuckyBackupError::Internal(e.to_string()))?;
+    let mut buf = vec![0u8; COPY_CHUNK_BUFFER_SIZE];
+    let mut read_size: u64 = 0;
+    loop {
+        let read_len = reader.read(&mut buf).await?;
+        if read_len == 0 {
+            break;
+        }
+        hasher.update_from_bytes(&buf[..read_len]);
+        let read_end = read_size + read_len as u64;
+        if read_end > offset {
+            let skip_len = offset.saturating_sub(read_size) as usize;
+            writer.write_all(&buf[skip_len..read_len]).await?;
+        }
+        read_size = read_end;
+    }
+    writer.flush().await?;
+    drop(writer);
+    let real_chunk_id = hasher.finalize_chunk_id()
+        .map_err(|e| BuckyBackupError::Internal(e.to_string()))?;
+    if real_chunk_id != *chunk_id || read_size != size {
+        return Err(BuckyBackupError::Failed(format!("chunk {} is corrupted, got {} with {} bytes",
+            chunk_id.to_string(), real_chunk_id.to_string(), read_size)));
+    }
+    to.complete_chunk_writer(chunk_id).await?;
+    Ok(true)
+}
+
+//在target所在的网络中运行,把种子目录中的chunk写入target;中断后重新导入会跳过已经存在的chunk
+pub async fn import_seed_to_target(seed_dir: &Path, target: &BackupChunkTargetProvider) -> Result<SeedImportReport> {
+    let manifest = SeedManifest::load_from_dir(seed_dir)?;
+    let seed_store = LocalChunkTargetProvider::new(seed_dir.to_string_lossy().to_string()).await?;
+    let seed_store: BackupChunkTargetProvider = Box::new(seed_store);
+    read_target_layout_version(&seed_store).await?;
+    info!("import seed of checkpoint {} to {}, {} chunks, {} bytes", manifest.checkpoint_id,
+        target.get_target_url(), manifest.chunks.len(), manifest.total_size());
+    let mut report = SeedImportReport::default();
+    for chunk in manifest.chunks.iter() {
+        let chunk_id = ChunkId::new(chunk.chunk_id.as_str()).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let (is_exist, _) = target.is_chunk_exist(&chunk_id).await?;
+        if is_exist {
+            report.skipped_count += 1;
+            continue;
+        }
+        let (is_in_seed, _) = seed_store.is_chunk_exist(&chunk_id).await?;
+        if !is_in_seed {
+            return Err(anyhow::anyhow!("chunk {} is missing in seed {}", chunk.chunk_id, seed_dir.display()));
+        }
+        if copy_chunk_between_targets(&seed_store, target, &chunk_id, chunk.size).await? {
+            report.imported_count += 1;
+            report.imported_size += chunk.size;
+        } else {
+            report.skipped_count += 1;
+        }
+    }
+    info!("import seed of checkpoint {} done: {:?}", manifest.checkpoint_id, report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_manifest() {
+        let seed_dir = tempfile::tempdir().unwrap();
+        assert!(SeedManifest::load_from_dir(seed_dir.path()).is_err());
+        let mut manifest = SeedManifest {
+            version: SEED_FORMAT_VERSION,
+            plan_id: "plan".to_string(),
+            checkpoint_id: "checkpoint".to_string(),
+            source_url: "file:///data".to_string(),
+            target_url: "s3://bucket/backup".to_string(),
+            create_time: 1000,
+            chunks: vec![
+                SeedChunk { chunk_id: "sha256:01".to_string(), size: 100 },
+                SeedChunk { chunk_id: "sha256:02".to_string(), size: 200 },
+            ],
+        };
+        manifest.save_to_dir(seed_dir.path()).unwrap();
+        let loaded = SeedManifest::load_from_dir(seed_dir.path()).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.total_size(), 300);
+        assert_eq!(loaded.to_json_value()["chunk_count"], 2);
+
+        //新版本导出的种子不能导入
+        manifest.version = SEED_FORMAT_VERSION + 1;
+        manifest.save_to_dir(seed_dir.path()).unwrap();
+        assert!(SeedManifest::load_from_dir(seed_dir.path()).is_err());
+    }
+}
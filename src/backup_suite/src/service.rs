@@ -0,0 +1,248 @@
+#![allow(unused)]
+//backup_suite service install/uninstall/status:把当前可执行文件注册为系统服务
+//linux生成systemd unit(异常退出自动重启),windows通过sc.exe注册服务并设置失败后重启,
+//windows服务由SCM以"service run"参数启动,在windows_service_main中向SCM报告状态后运行daemon
+//安装时的BUCKYOS_ROOT写入服务配置,保证服务使用和安装时相同的数据目录
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::Result;
+
+pub const SERVICE_NAME: &str = "backup_suite";
+pub const SERVICE_DISPLAY_NAME: &str = "Bucky Backup Suite";
+const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+const RESTART_DELAY_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceCommand {
+    Install,
+    Uninstall,
+    Status,
+    Run,//由windows SCM调用
+}
+
+impl ServiceCommand {
+    pub fn from_str(command: &str) -> Option<Self> {
+        match command {
+            "install" => Some(ServiceCommand::Install),
+            "uninstall" => Some(ServiceCommand::Uninstall),
+            "status" => Some(ServiceCommand::Status),
+            "run" => Some(ServiceCommand::Run),
+            _ => None,
+        }
+    }
+}
+
+fn run_command(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program).args(args).output()
+        .map_err(|e| anyhow::anyhow!("run {} failed: {}", program, e))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("{} {} failed: {}{}", program, args.join(" "),
+            String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn build_systemd_unit(exe_path: &Path, data_dir: &Path, buckyos_root: Option<&str>) -> String {
+    let mut unit = format!(
+        "[Unit]\nDescription={}\nAfter=network-online.target\nWants=network-online.target\n\n\
+        [Service]\nType=simple\nExecStart=\"{}\"\nWorkingDirectory={}\nRestart=on-failure\nRestartSec={}\n",
+        SERVICE_DISPLAY_NAME, exe_path.to_string_lossy(), data_dir.to_string_lossy(), RESTART_DELAY_SECS);
+    if let Some(buckyos_root) = buckyos_root {
+        unit.push_str(&format!("Environment=\"BUCKYOS_ROOT={}\"\n", buckyos_root));
+    }
+    unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+    unit
+}
+
+fn get_systemd_unit_path() -> PathBuf {
+    Path::new(SYSTEMD_UNIT_DIR).join(format!("{}.service", SERVICE_NAME))
+}
+
+#[cfg(target_os = "linux")]
+fn install_service(exe_path: &Path, data_dir: &Path) -> Result<()> {
+    let buckyos_root = std::env::var("BUCKYOS_ROOT").ok();
+    let unit = build_systemd_unit(exe_path, data_dir, buckyos_root.as_deref());
+    std::fs::write(get_systemd_unit_path(), unit)
+        .map_err(|e| anyhow::anyhow!("write {} failed: {}, run as root?", get_systemd_unit_path().to_string_lossy(), e))?;
+    run_command("systemctl", &["daemon-reload"])?;
+    run_command("systemctl", &["enable", "--now", SERVICE_NAME])?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn uninstall_service() -> Result<()> {
+    //服务没有运行时disable也可能失败,不影响删除unit
+    let _ = run_command("systemctl", &["disable", "--now", SERVICE_NAME]);
+    let unit_path = get_systemd_unit_path();
+    if unit_path.exists() {
+        std::fs::remove_file(&unit_path)?;
+    }
+    run_command("systemctl", &["daemon-reload"])?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn query_service_status() -> Result<String> {
+    if !get_systemd_unit_path().exists() {
+        return Ok("not installed".to_string());
+    }
+    //is-active在服务没有运行时返回非0
+    let output = Command::new("systemctl").args(["is-active", SERVICE_NAME]).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(windows)]
+fn install_service(exe_path: &Path, data_dir: &Path) -> Result<()> {
+    let bin_path = format!("\"{}\" service run", exe_path.to_string_lossy());
+    run_command("sc.exe", &["create", SERVICE_NAME, "binPath=", bin_path.as_str(), "start=", "auto", "DisplayName=", SERVICE_DISPLAY_NAME])?;
+    //连续失败时5秒,5秒,60秒后重启,一天后重置失败计数
+    run_command("sc.exe", &["failure", SERVICE_NAME, "reset=", "86400", "actions=", "restart/5000/restart/5000/restart/60000"])?;
+    if let Ok(buckyos_root) = std::env::var("BUCKYOS_ROOT") {
+        let env_value = format!("BUCKYOS_ROOT={}", buckyos_root);
+        run_command("reg", &["add", format!("HKLM\\SYSTEM\\CurrentControlSet\\Services\\{}", SERVICE_NAME).as_str(),
+            "/v", "Environment", "/t", "REG_MULTI_SZ", "/d", env_value.as_str(), "/f"])?;
+    }
+    run_command("sc.exe", &["start", SERVICE_NAME])?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn uninstall_service() -> Result<()> {
+    let _ = run_command("sc.exe", &["stop", SERVICE_NAME]);
+    run_command("sc.exe", &["delete", SERVICE_NAME])?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn query_service_status() -> Result<String> {
+    let output = Command::new("sc.exe").args(["query", SERVICE_NAME]).output()?;
+    if !output.status.success() {
+        return Ok("not installed".to_string());
+    }
+    Ok(parse_sc_query_state(String::from_utf8_lossy(&output.stdout).as_ref()).unwrap_or_else(|| "unknown".to_string()))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn install_service(exe_path: &Path, data_dir: &Path) -> Result<()> {
+    Err(anyhow::anyhow!("service install is not supported on this platform"))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn uninstall_service() -> Result<()> {
+    Err(anyhow::anyhow!("service uninstall is not supported on this platform"))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn query_service_status() -> Result<String> {
+    Err(anyhow::anyhow!("service status is not supported on this platform"))
+}
+
+//        STATE              : 4  RUNNING
+pub fn parse_sc_query_state(output: &str) -> Option<String> {
+    output.lines()
+        .find(|line| line.trim_start().starts_with("STATE"))
+        .and_then(|line| line.split_whitespace().last())
+        .map(|state| state.to_lowercase())
+}
+
+//返回给命令行输出的结果
+pub fn run_service_command(command: ServiceCommand, data_dir: &Path) -> Result<String> {
+    match command {
+        ServiceCommand::Install => {
+            let exe_path = std::env::current_exe()?;
+            std::fs::create_dir_all(data_dir)?;
+            install_service(&exe_path, data_dir)?;
+            Ok(format!("service {} installed, exe: {}, data dir: {}", SERVICE_NAME, exe_path.to_string_lossy(), data_dir.to_string_lossy()))
+        }
+        ServiceCommand::Uninstall => {
+            uninstall_service()?;
+            Ok(format!("service {} uninstalled", SERVICE_NAME))
+        }
+        ServiceCommand::Status => query_service_status(),
+        ServiceCommand::Run => Err(anyhow::anyhow!("service run is started by the service manager")),
+    }
+}
+
+#[cfg(windows)]
+mod windows_service_host {
+    use std::ffi::OsString;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use windows_service::{define_windows_service, service_dispatcher};
+    use windows_service::service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType};
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use log::*;
+    use super::SERVICE_NAME;
+
+    static DAEMON_MAIN: OnceLock<fn()> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, windows_service_main);
+
+    fn windows_service_main(_arguments: Vec<OsString>) {
+        let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            //daemon没有优雅退出的流程,和在控制台中Ctrl+C一样直接退出进程
+            ServiceControl::Stop | ServiceControl::Shutdown => std::process::exit(0),
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        });
+        let status_handle = match status_handle {
+            Ok(status_handle) => status_handle,
+            Err(err) => {
+                error!("register service control handler failed: {}", err);
+                return;
+            }
+        };
+        let _ = status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: ServiceState::Running,
+            controls_accepted: ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        });
+        if let Some(daemon_main) = DAEMON_MAIN.get() {
+            daemon_main();
+        }
+    }
+
+    pub fn run(daemon_main: fn()) -> anyhow::Result<()> {
+        let _ = DAEMON_MAIN.set(daemon_main);
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+}
+
+//windows上由SCM启动时调用,阻塞直到服务停止
+#[cfg(windows)]
+pub fn run_as_service(daemon_main: fn()) -> Result<()> {
+    windows_service_host::run(daemon_main)
+}
+
+#[cfg(not(windows))]
+pub fn run_as_service(daemon_main: fn()) -> Result<()> {
+    daemon_main();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_config() {
+        let unit = build_systemd_unit(Path::new("/opt/buckyos/bin/backup_suite/backup_suite"), Path::new("/opt/buckyos/data/backup_suite"), Some("/opt/buckyos"));
+        assert!(unit.contains("ExecStart=\"/opt/buckyos/bin/backup_suite/backup_suite\"\n"));
+        assert!(unit.contains("WorkingDirectory=/opt/buckyos/data/backup_suite\n"));
+        assert!(unit.contains("Restart=on-failure\n"));
+        assert!(unit.contains("Environment=\"BUCKYOS_ROOT=/opt/buckyos\"\n"));
+        assert!(unit.ends_with("WantedBy=multi-user.target\n"));
+        assert!(!build_systemd_unit(Path::new("/usr/bin/backup_suite"), Path::new("/var/lib/backup_suite"), None).contains("Environment"));
+
+        let output = "SERVICE_NAME: backup_suite\n        TYPE               : 10  WIN32_OWN_PROCESS\n        STATE              : 4  RUNNING\n";
+        assert_eq!(parse_sc_query_state(output).as_deref(), Some("running"));
+        assert_eq!(parse_sc_query_state("error"), None);
+        assert_eq!(ServiceCommand::from_str("install"), Some(ServiceCommand::Install));
+        assert_eq!(ServiceCommand::from_str("start"), None);
+    }
+}
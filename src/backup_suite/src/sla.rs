@@ -0,0 +1,205 @@
+#![allow(unused)]
+//备份SLA:plan可以配置RPO(最多丢失多长时间的数据)和RTO(恢复需要的最长时间)目标
+//RPO按最新的可恢复checkpoint的创建时间到现在的时间计算;RTO按最近一次恢复演练的速度估算恢复整个checkpoint需要的时间
+//调度循环定期检查,进入违约状态时发布SlaBreached事件,恢复达标后才会再次发布
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use crate::restore_drill::RestoreDrillRecord;
+
+pub const SLA_OBJECTIVE_RPO: &str = "rpo";
+pub const SLA_OBJECTIVE_RTO: &str = "rto";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlaPolicy {
+    pub rpo_secs: u64,//0为不检查
+    pub rto_secs: u64,//0为不检查,需要开启恢复演练才能测量
+}
+
+impl SlaPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.rpo_secs > 0 || self.rto_secs > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlaObjectiveStatus {
+    pub target_secs: u64,
+    pub actual_secs: Option<u64>,//无法测量时为None
+    pub compliant: bool,
+    pub reason: Option<String>,//不达标的原因
+}
+
+impl SlaObjectiveStatus {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "target_secs": self.target_secs,
+            "actual_secs": self.actual_secs,
+            "compliant": self.compliant,
+            "reason": self.reason,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlaReport {
+    pub plan_id: String,
+    pub report_time: u64,
+    pub last_checkpoint_id: Option<String>,
+    pub last_checkpoint_time: Option<u64>,
+    pub last_drill_id: Option<String>,
+    pub rpo: Option<SlaObjectiveStatus>,//没有配置时为None
+    pub rto: Option<SlaObjectiveStatus>,
+}
+
+impl SlaReport {
+    pub fn is_compliant(&self) -> bool {
+        self.rpo.as_ref().map(|status| status.compliant).unwrap_or(true)
+            && self.rto.as_ref().map(|status| status.compliant).unwrap_or(true)
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "plan_id": self.plan_id,
+            "report_time": self.report_time,
+            "last_checkpoint_id": self.last_checkpoint_id,
+            "last_checkpoint_time": self.last_checkpoint_time,
+            "last_drill_id": self.last_drill_id,
+            "rpo": self.rpo.as_ref().map(|status| status.to_json_value()),
+            "rto": self.rto.as_ref().map(|status| status.to_json_value()),
+            "compliant": self.is_compliant(),
+        })
+    }
+}
+
+//last_checkpoint:(checkpoint_id, create_time);RTO按last_drill记录的checkpoint大小外推;时间都是ms
+pub fn evaluate_sla(plan_id: &str, policy: &SlaPolicy, now: u64, last_checkpoint: Option<(&str, u64)>,
+    last_drill: Option<&RestoreDrillRecord>) -> SlaReport {
+    let rpo = if policy.rpo_secs > 0 {
+        Some(match last_checkpoint {
+            Some((_, create_time)) => {
+                let actual_secs = now.saturating_sub(create_time) / 1000;
+                let compliant = actual_secs <= policy.rpo_secs;
+                SlaObjectiveStatus {
+                    target_secs: policy.rpo_secs,
+                    actual_secs: Some(actual_secs),
+                    compliant,
+                    reason: if compliant { None } else { Some(format!("last recoverable checkpoint is {}s old", actual_secs)) },
+                }
+            }
+            None => SlaObjectiveStatus {
+                target_secs: policy.rpo_secs,
+                actual_secs: None,
+                compliant: false,
+                reason: Some("no recoverable checkpoint".to_string()),
+            },
+        })
+    } else {
+        None
+    };
+
+    let rto = if policy.rto_secs > 0 {
+        Some(match last_drill {
+            None => SlaObjectiveStatus {
+                target_secs: policy.rto_secs,
+                actual_secs: None,
+                compliant: false,
+                reason: Some("no restore drill measured".to_string()),
+            },
+            Some(drill) if !drill.is_success() => SlaObjectiveStatus {
+                target_secs: policy.rto_secs,
+                actual_secs: None,
+                compliant: false,
+                reason: Some(format!("last restore drill {} failed", drill.drill_id)),
+            },
+            Some(drill) => {
+                //按演练的速度外推到整个checkpoint,演练没有恢复任何数据(或者老版本没有记录checkpoint大小)时只用演练时间
+                let drill_ms = drill.end_time.saturating_sub(drill.start_time);
+                let restore_ms = if drill.verified_size > 0 && drill.checkpoint_size > drill.verified_size {
+                    (drill_ms as u128 * drill.checkpoint_size as u128 / drill.verified_size as u128) as u64
+                } else {
+                    drill_ms
+                };
+                let actual_secs = restore_ms.div_ceil(1000);
+                let compliant = actual_secs <= policy.rto_secs;
+                SlaObjectiveStatus {
+                    target_secs: policy.rto_secs,
+                    actual_secs: Some(actual_secs),
+                    compliant,
+                    reason: if compliant { None } else { Some(format!("estimated restore time is {}s", actual_secs)) },
+                }
+            }
+        })
+    } else {
+        None
+    };
+
+    SlaReport {
+        plan_id: plan_id.to_string(),
+        report_time: now,
+        last_checkpoint_id: last_checkpoint.map(|(checkpoint_id, _)| checkpoint_id.to_string()),
+        last_checkpoint_time: last_checkpoint.map(|(_, create_time)| create_time),
+        last_drill_id: last_drill.map(|drill| drill.drill_id.clone()),
+        rpo,
+        rto,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restore_drill::RestoreDrillFailure;
+
+    fn make_drill(start_time: u64, end_time: u64, verified_size: u64, checkpoint_size: u64, failed: bool) -> RestoreDrillRecord {
+        RestoreDrillRecord {
+            drill_id: "drill_1".to_string(),
+            plan_id: "plan_1".to_string(),
+            checkpoint_id: "chk_1".to_string(),
+            start_time,
+            end_time,
+            sample_count: 4,
+            verified_count: if failed { 3 } else { 4 },
+            verified_size,
+            failures: if failed {
+                vec![RestoreDrillFailure { item_id: "a.bin".to_string(), reason: "hash mismatch".to_string() }]
+            } else {
+                Vec::new()
+            },
+            checkpoint_size,
+        }
+    }
+
+    #[test]
+    fn test_evaluate_sla() {
+        let policy = SlaPolicy { rpo_secs: 3600, rto_secs: 600 };
+        let now = 10_000_000;
+        //没有配置的目标不检查
+        let report = evaluate_sla("plan_1", &SlaPolicy::default(), now, None, None);
+        assert!(report.rpo.is_none() && report.rto.is_none() && report.is_compliant());
+
+        //没有checkpoint和演练时不达标
+        let report = evaluate_sla("plan_1", &policy, now, None, None);
+        assert!(!report.rpo.as_ref().unwrap().compliant);
+        assert!(!report.rto.as_ref().unwrap().compliant);
+
+        //演练10s恢复了1/50的数据,估计恢复整个checkpoint需要500s
+        let drill = make_drill(1000, 11000, 100, 5000, false);
+        let report = evaluate_sla("plan_1", &policy, now, Some(("chk_1", now - 1800 * 1000)), Some(&drill));
+        assert_eq!(report.rpo.as_ref().unwrap().actual_secs, Some(1800));
+        assert_eq!(report.rto.as_ref().unwrap().actual_secs, Some(500));
+        assert!(report.is_compliant());
+        assert_eq!(report.last_checkpoint_id, Some("chk_1".to_string()));
+
+        let drill = make_drill(1000, 11000, 100, 10000, false);
+        let report = evaluate_sla("plan_1", &policy, now, Some(("chk_1", now - 7200 * 1000)), Some(&drill));
+        assert_eq!(report.rpo.as_ref().unwrap().actual_secs, Some(7200));
+        assert_eq!(report.rto.as_ref().unwrap().actual_secs, Some(1000));
+        assert!(!report.rpo.as_ref().unwrap().compliant && !report.rto.as_ref().unwrap().compliant);
+
+        //演练失败时RTO不达标
+        let drill = make_drill(1000, 2000, 100, 100, true);
+        let report = evaluate_sla("plan_1", &policy, now, Some(("chk_1", now)), Some(&drill));
+        let rto = report.rto.unwrap();
+        assert!(!rto.compliant && rto.actual_secs.is_none());
+    }
+}
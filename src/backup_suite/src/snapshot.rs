@@ -0,0 +1,598 @@
+#![allow(unused)]
+//备份前为本地source目录创建快照,备份从快照读取,避免备份过程中文件被修改导致的不一致
+//快照方式由plan的SnapshotMode决定:
+//  none: 不创建快照,直接读source
+//  native: 文件系统原生快照(目前只支持btrfs子卷),不占用额外空间
+//  reflink: 逐个文件reflink(CoW)复制,要求快照目录和source在同一个支持reflink的文件系统上
+//  hardlink_farm: 逐个文件创建硬链接,只占用目录元数据的空间,
+//                 能防止备份过程中文件被删除/改名/原子替换,但不能防止文件被原地修改
+//  copy: 完整复制,文件系统支持时使用reflink,否则占用和source一样多的空间
+//  auto: 依次尝试native/reflink/hardlink_farm/copy,都不可用(例如空间不够)时退回直接读source
+//创建前检查快照所在卷的可用空间,复制过程中可用空间低于min_free_space时中止并删除快照,不能让快照把磁盘写满
+//reflink/copy方式复制文件时同时复制owner/xattrs/权限,source中的硬链接在快照中也是硬链接,
+//任何一项不能保留时快照失败(auto会尝试下一种方式),不能让备份记录和source不一致的属性
+//快照目录: <location>/<task_id>/data,开始创建时把使用的方式写入<location>/<task_id>/mode,
+//创建完成后写入<location>/<task_id>/done,resume时直接复用完整的快照
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use log::*;
+
+use crate::health::{get_free_space, MIN_FREE_SPACE};
+
+pub const SNAPSHOT_DIR_NAME: &str = "snapshots";
+const SNAPSHOT_DATA_DIR_NAME: &str = "data";
+const SNAPSHOT_MODE_FILE_NAME: &str = "mode";
+const SNAPSHOT_DONE_FILE_NAME: &str = "done";
+//每复制这么多字节检查一次快照卷的可用空间
+const FREE_SPACE_CHECK_INTERVAL: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    None,
+    Auto,
+    Native,
+    Reflink,
+    HardlinkFarm,
+    Copy,
+}
+
+impl Default for SnapshotMode {
+    fn default() -> Self {
+        SnapshotMode::None
+    }
+}
+
+impl SnapshotMode {
+    //按顺序尝试的快照方式,占用空间少的优先
+    pub fn candidates(&self) -> Vec<SnapshotMode> {
+        match self {
+            SnapshotMode::None => Vec::new(),
+            SnapshotMode::Auto => vec![SnapshotMode::Native, SnapshotMode::Reflink, SnapshotMode::HardlinkFarm, SnapshotMode::Copy],
+            mode => vec![*mode],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotMode::None => "none",
+            SnapshotMode::Auto => "auto",
+            SnapshotMode::Native => "native",
+            SnapshotMode::Reflink => "reflink",
+            SnapshotMode::HardlinkFarm => "hardlink_farm",
+            SnapshotMode::Copy => "copy",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<SnapshotMode> {
+        match s {
+            "none" => Some(SnapshotMode::None),
+            "auto" => Some(SnapshotMode::Auto),
+            "native" => Some(SnapshotMode::Native),
+            "reflink" => Some(SnapshotMode::Reflink),
+            "hardlink_farm" => Some(SnapshotMode::HardlinkFarm),
+            "copy" => Some(SnapshotMode::Copy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SnapshotOptionsConfig")]
+pub struct SnapshotOptions {
+    pub mode: SnapshotMode,//只对file://的source生效
+    pub location: Option<String>,//快照目录,为None时使用engine的data_dir/snapshots
+    pub min_free_space: u64,//快照所在卷至少保留的空间
+}
+
+//老版本的plan只有enabled开关,打开时是完整复制(能reflink时reflink),和现在的copy方式相同
+#[derive(Deserialize)]
+#[serde(default)]
+struct SnapshotOptionsConfig {
+    mode: Option<SnapshotMode>,
+    enabled: Option<bool>,
+    location: Option<String>,
+    min_free_space: u64,
+}
+
+impl Default for SnapshotOptionsConfig {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            enabled: None,
+            location: None,
+            min_free_space: MIN_FREE_SPACE,
+        }
+    }
+}
+
+impl From<SnapshotOptionsConfig> for SnapshotOptions {
+    fn from(config: SnapshotOptionsConfig) -> Self {
+        let mode = config.mode.unwrap_or(if config.enabled == Some(true) { SnapshotMode::Copy } else { SnapshotMode::None });
+        Self {
+            mode,
+            location: config.location,
+            min_free_space: config.min_free_space,
+        }
+    }
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            mode: SnapshotMode::None,
+            location: None,
+            min_free_space: MIN_FREE_SPACE,
+        }
+    }
+}
+
+impl SnapshotOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.mode != SnapshotMode::None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotPreflight {
+    pub mode: SnapshotMode,
+    pub source_size: u64,//只有copy方式会统计
+    pub free_space: Option<u64>,//找不到快照所在的磁盘时为None,不做空间检查
+    pub required_space: u64,//不包括min_free_space
+}
+
+pub struct LocalSnapshot {
+    task_dir: PathBuf,
+}
+
+impl LocalSnapshot {
+    pub fn new(snapshot_root: &Path, task_id: &str) -> Self {
+        Self { task_dir: snapshot_root.join(task_id) }
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.task_dir.join(SNAPSHOT_DATA_DIR_NAME)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.task_dir.join(SNAPSHOT_DONE_FILE_NAME).exists()
+    }
+
+    //快照使用的方式,还没有开始创建时返回None
+    pub fn get_mode(&self) -> Option<SnapshotMode> {
+        let mode = fs::read_to_string(self.task_dir.join(SNAPSHOT_MODE_FILE_NAME)).ok()?;
+        SnapshotMode::from_str(mode.trim())
+    }
+
+    //检查mode在source和快照目录上是否可用,以及快照卷的空间是否足够
+    pub fn preflight(&self, source_dir: &Path, mode: SnapshotMode, options: &SnapshotOptions) -> Result<SnapshotPreflight> {
+        fs::create_dir_all(&self.task_dir)?;
+        let is_supported = match mode {
+            SnapshotMode::Native => is_native_snapshot_supported(source_dir),
+            SnapshotMode::Reflink => probe_reflink(source_dir, &self.task_dir),
+            SnapshotMode::HardlinkFarm => probe_hardlink(source_dir, &self.task_dir),
+            SnapshotMode::Copy => true,
+            SnapshotMode::None | SnapshotMode::Auto => false,
+        };
+        if !is_supported {
+            return Err(anyhow::anyhow!("snapshot mode {} is not supported for {} at {}",
+                mode.as_str(), source_dir.to_string_lossy(), self.task_dir.to_string_lossy()));
+        }
+
+        let source_size = if mode == SnapshotMode::Copy { get_dir_size(source_dir)? } else { 0 };
+        //copy时如果能reflink也不需要为数据预留空间
+        let required_space = if mode == SnapshotMode::Copy && !probe_reflink(source_dir, &self.task_dir) {
+            source_size
+        } else {
+            0
+        };
+        let free_space = get_free_space(&self.task_dir);
+        if let Some(free_space) = free_space {
+            if free_space < required_space.saturating_add(options.min_free_space) {
+                return Err(anyhow::anyhow!("not enough space for {} snapshot at {}: need {} bytes and reserve {} bytes, free {} bytes",
+                    mode.as_str(), self.task_dir.to_string_lossy(), required_space, options.min_free_space, free_space));
+            }
+        }
+        Ok(SnapshotPreflight {
+            mode,
+            source_size,
+            free_space,
+            required_space,
+        })
+    }
+
+    //按options.mode创建快照,返回快照的数据目录;auto下所有方式都不可用时返回None,备份直接读source
+    //已经有完整的快照时直接返回,否则删除不完整的快照重新创建
+    pub fn create(&self, source_dir: &Path, options: &SnapshotOptions) -> Result<Option<PathBuf>> {
+        if !options.is_enabled() {
+            return Ok(None);
+        }
+        let data_dir = self.data_dir();
+        if self.is_complete() {
+            info!("reuse snapshot {}", data_dir.to_string_lossy());
+            return Ok(Some(data_dir));
+        }
+        self.remove();
+
+        let mut last_err = None;
+        for mode in options.mode.candidates() {
+            match self.create_with_mode(source_dir, mode, options) {
+                Ok(()) => {
+                    fs::write(self.task_dir.join(SNAPSHOT_DONE_FILE_NAME), b"")?;
+                    return Ok(Some(data_dir));
+                }
+                Err(err) => {
+                    info!("create {} snapshot of {} failed: {}", mode.as_str(), source_dir.to_string_lossy(), err);
+                    self.remove();
+                    last_err = Some(err);
+                }
+            }
+        }
+        if options.mode == SnapshotMode::Auto {
+            warn!("no snapshot mode is available for {}, backup from source directly", source_dir.to_string_lossy());
+            return Ok(None);
+        }
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no snapshot mode")))
+    }
+
+    fn create_with_mode(&self, source_dir: &Path, mode: SnapshotMode, options: &SnapshotOptions) -> Result<()> {
+        let preflight = self.preflight(source_dir, mode, options)?;
+        fs::write(self.task_dir.join(SNAPSHOT_MODE_FILE_NAME), mode.as_str())?;
+        let data_dir = self.data_dir();
+        info!("create {} snapshot of {} at {}, required space: {}", mode.as_str(), source_dir.to_string_lossy(),
+            data_dir.to_string_lossy(), preflight.required_space);
+        if mode == SnapshotMode::Native {
+            return create_native_snapshot(source_dir, &data_dir);
+        }
+        let mut copier = SnapshotCopier {
+            mode,
+            min_free_space: options.min_free_space,
+            check_dir: self.task_dir.clone(),
+            copied_since_check: 0,
+            hard_links: HashMap::new(),
+        };
+        copier.copy_dir(source_dir, &data_dir)
+    }
+
+    pub fn remove(&self) {
+        if !self.task_dir.exists() {
+            return;
+        }
+        //原生快照是只读的子卷,不能直接删除目录
+        if self.get_mode() == Some(SnapshotMode::Native) && self.data_dir().exists() {
+            if let Err(err) = remove_native_snapshot(&self.data_dir()) {
+                warn!("remove native snapshot {} failed: {}", self.data_dir().to_string_lossy(), err);
+            }
+        }
+        if let Err(err) = fs::remove_dir_all(&self.task_dir) {
+            warn!("remove snapshot {} failed: {}", self.task_dir.to_string_lossy(), err);
+        }
+    }
+}
+
+struct SnapshotCopier {
+    mode: SnapshotMode,
+    min_free_space: u64,
+    check_dir: PathBuf,
+    copied_since_check: u64,
+    hard_links: HashMap<(u64, u64), PathBuf>,//source中有多个链接的文件(dev, ino) -> 快照中第一次复制的路径
+}
+
+impl SnapshotCopier {
+    fn copy_dir(&mut self, source_dir: &Path, dest_dir: &Path) -> Result<()> {
+        fs::create_dir_all(dest_dir)?;
+        for entry in fs::read_dir(source_dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let dest_path = dest_dir.join(entry.file_name());
+            if file_type.is_dir() {
+                self.copy_dir(&entry.path(), &dest_path)?;
+            } else if file_type.is_file() {
+                self.copy_file(&entry.path(), &dest_path)?;
+            } else if file_type.is_symlink() {
+                copy_symlink(&entry.path(), &dest_path)?;
+                if self.mode != SnapshotMode::HardlinkFarm {
+                    copy_attributes(&entry.path(), &dest_path)?;
+                }
+            }
+        }
+        //目录的属性在内容复制完之后设置,只读目录也能先写入内容
+        if self.mode != SnapshotMode::HardlinkFarm {
+            copy_attributes(source_dir, dest_dir)?;
+        }
+        Ok(())
+    }
+
+    fn copy_file(&mut self, source_path: &Path, dest_path: &Path) -> Result<()> {
+        if self.mode == SnapshotMode::HardlinkFarm {
+            //硬链接和source是同一个文件,不能修改它的属性
+            return Ok(fs::hard_link(source_path, dest_path)?);
+        }
+        let metadata = fs::symlink_metadata(source_path)?;
+        let link_key = get_hard_link_key(&metadata);
+        if let Some(first_dest) = link_key.and_then(|key| self.hard_links.get(&key)) {
+            fs::hard_link(first_dest, dest_path)?;
+            return Ok(());
+        }
+        if self.mode == SnapshotMode::Reflink {
+            reflink_copy::reflink(source_path, dest_path)?;
+        } else if reflink_copy::reflink(source_path, dest_path).is_err() {
+            self.copied_since_check += fs::copy(source_path, dest_path)?;
+        }
+        //快照中的文件保留原来的修改时间,备份记录的文件属性和source一致;先设置时间,之后的chmod可能让文件变成只读
+        let modified = metadata.modified()?;
+        fs::File::options().write(true).open(dest_path)?.set_modified(modified)?;
+        copy_attributes(source_path, dest_path)?;
+        if let Some(link_key) = link_key {
+            self.hard_links.insert(link_key, dest_path.to_path_buf());
+        }
+        if self.copied_since_check >= FREE_SPACE_CHECK_INTERVAL {
+            self.copied_since_check = 0;
+            if let Some(free_space) = get_free_space(&self.check_dir) {
+                if free_space < self.min_free_space {
+                    return Err(anyhow::anyhow!("snapshot volume free space {} is below {}, abort snapshot", free_space, self.min_free_space));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+//btrfs上source是子卷时可以创建只读的子卷快照,快照目录需要和source在同一个btrfs文件系统上
+#[cfg(target_os = "linux")]
+fn is_native_snapshot_supported(source_dir: &Path) -> bool {
+    std::process::Command::new("btrfs")
+        .args(["subvolume", "show"])
+        .arg(source_dir)
+        .output()
+        .map_or(false, |output| output.status.success())
+}
+
+#[cfg(target_os = "linux")]
+fn create_native_snapshot(source_dir: &Path, data_dir: &Path) -> Result<()> {
+    let output = std::process::Command::new("btrfs")
+        .args(["subvolume", "snapshot", "-r"])
+        .arg(source_dir)
+        .arg(data_dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("btrfs snapshot failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn remove_native_snapshot(data_dir: &Path) -> Result<()> {
+    let output = std::process::Command::new("btrfs")
+        .args(["subvolume", "delete"])
+        .arg(data_dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("btrfs subvolume delete failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_native_snapshot_supported(source_dir: &Path) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_native_snapshot(source_dir: &Path, data_dir: &Path) -> Result<()> {
+    Err(anyhow::anyhow!("native snapshot is not supported on this platform"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn remove_native_snapshot(data_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+//有多个硬链接的文件返回(dev, ino)
+#[cfg(unix)]
+fn get_hard_link_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn get_hard_link_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn copy_attributes(source_path: &Path, dest_path: &Path) -> Result<()> {
+    buckyos_backup_lib::copy_path_attributes(source_path, dest_path).map_err(|e| {
+        anyhow::anyhow!("snapshot can not keep attributes of {}: {}", source_path.to_string_lossy(), e)
+    })
+}
+
+//非unix平台不能保留ACL/所有者/硬链接,复制方式的快照会丢失这些属性,拒绝创建
+#[cfg(not(unix))]
+fn copy_attributes(source_path: &Path, dest_path: &Path) -> Result<()> {
+    Err(anyhow::anyhow!("copy snapshot can not keep attributes of {} on this platform", source_path.to_string_lossy()))
+}
+
+#[cfg(unix)]
+fn copy_symlink(source_path: &Path, dest_path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(source_path)?, dest_path)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source_path: &Path, dest_path: &Path) -> io::Result<()> {
+    warn!("skip symlink {} in snapshot", source_path.to_string_lossy());
+    Ok(())
+}
+
+fn get_dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total_size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            total_size += get_dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            total_size += entry.metadata()?.len();
+        }
+    }
+    Ok(total_size)
+}
+
+fn find_first_file(dir: &Path) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let file_type = entry.file_type().ok()?;
+        if file_type.is_file() {
+            return Some(entry.path());
+        }
+        if file_type.is_dir() {
+            if let Some(file) = find_first_file(&entry.path()) {
+                return Some(file);
+            }
+        }
+    }
+    None
+}
+
+//用source中的一个文件试探快照目录是否和source在同一个支持reflink的文件系统上
+fn probe_reflink(source_dir: &Path, snapshot_dir: &Path) -> bool {
+    let probe_source = find_first_file(source_dir);
+    if probe_source.is_none() {
+        return false;
+    }
+    let probe_path = snapshot_dir.join(".reflink_probe");
+    let is_supported = reflink_copy::reflink(probe_source.unwrap(), &probe_path).is_ok();
+    let _ = fs::remove_file(&probe_path);
+    is_supported
+}
+
+//硬链接要求快照目录和source在同一个文件系统上;source为空时也可以用硬链接
+fn probe_hardlink(source_dir: &Path, snapshot_dir: &Path) -> bool {
+    let probe_source = find_first_file(source_dir);
+    if probe_source.is_none() {
+        return true;
+    }
+    let probe_path = snapshot_dir.join(".hardlink_probe");
+    let is_supported = fs::hard_link(probe_source.unwrap(), &probe_path).is_ok();
+    let _ = fs::remove_file(&probe_path);
+    is_supported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_source_dir(test_dir: &Path) -> PathBuf {
+        let source_dir = test_dir.join("source");
+        fs::create_dir_all(source_dir.join("sub")).unwrap();
+        fs::write(source_dir.join("a.txt"), b"hello").unwrap();
+        fs::write(source_dir.join("sub").join("b.txt"), vec![7u8; 4096]).unwrap();
+        source_dir
+    }
+
+    #[test]
+    fn test_create_snapshot() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let source_dir = create_source_dir(test_dir.path());
+
+        let snapshot = LocalSnapshot::new(&test_dir.path().join(SNAPSHOT_DIR_NAME), "task_1");
+        let options = SnapshotOptions { mode: SnapshotMode::Copy, min_free_space: 0, ..Default::default() };
+        let preflight = snapshot.preflight(&source_dir, SnapshotMode::Copy, &options).unwrap();
+        assert_eq!(preflight.source_size, 5 + 4096);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::hard_link(source_dir.join("sub").join("b.txt"), source_dir.join("b_link.txt")).unwrap();
+            fs::set_permissions(source_dir.join("a.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let data_dir = snapshot.create(&source_dir, &options).unwrap().unwrap();
+        assert!(snapshot.is_complete());
+        assert_eq!(snapshot.get_mode(), Some(SnapshotMode::Copy));
+        assert_eq!(fs::read(data_dir.join("sub").join("b.txt")).unwrap(), vec![7u8; 4096]);
+        //硬链接在快照中还是硬链接,文件权限和source一致
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let link_meta = fs::metadata(data_dir.join("b_link.txt")).unwrap();
+            assert_eq!(link_meta.ino(), fs::metadata(data_dir.join("sub").join("b.txt")).unwrap().ino());
+            assert_eq!(fs::metadata(data_dir.join("a.txt")).unwrap().mode() & 0o7777, 0o600);
+        }
+        //快照和source相互独立
+        fs::write(source_dir.join("a.txt"), b"changed").unwrap();
+        assert_eq!(fs::read(data_dir.join("a.txt")).unwrap(), b"hello");
+        //完整的快照会被复用
+        assert_eq!(snapshot.create(&source_dir, &options).unwrap(), Some(data_dir.clone()));
+        assert_eq!(fs::read(data_dir.join("a.txt")).unwrap(), b"hello");
+
+        //可用空间不够时不创建快照
+        let options = SnapshotOptions { mode: SnapshotMode::Copy, min_free_space: u64::MAX, ..Default::default() };
+        let snapshot2 = LocalSnapshot::new(&test_dir.path().join(SNAPSHOT_DIR_NAME), "task_2");
+        if get_free_space(test_dir.path()).is_some() {
+            assert!(snapshot2.create(&source_dir, &options).is_err());
+            assert!(!snapshot2.data_dir().exists());
+        }
+
+        snapshot.remove();
+        assert!(!data_dir.exists());
+    }
+
+    #[test]
+    fn test_legacy_snapshot_options() {
+        let options: SnapshotOptions = serde_json::from_value(serde_json::json!({ "enabled": true, "min_free_space": 0 })).unwrap();
+        assert_eq!(options, SnapshotOptions { mode: SnapshotMode::Copy, min_free_space: 0, ..Default::default() });
+        let options: SnapshotOptions = serde_json::from_value(serde_json::json!({ "enabled": false })).unwrap();
+        assert_eq!(options, SnapshotOptions::default());
+        let options: SnapshotOptions = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(options, SnapshotOptions::default());
+        //新格式的mode优先
+        let options: SnapshotOptions = serde_json::from_value(serde_json::json!({ "enabled": true, "mode": "hardlink_farm" })).unwrap();
+        assert_eq!(options.mode, SnapshotMode::HardlinkFarm);
+        let value = serde_json::to_value(SnapshotOptions { mode: SnapshotMode::Reflink, ..Default::default() }).unwrap();
+        assert_eq!(serde_json::from_value::<SnapshotOptions>(value).unwrap().mode, SnapshotMode::Reflink);
+    }
+
+    #[test]
+    fn test_snapshot_mode() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let source_dir = create_source_dir(test_dir.path());
+        let snapshot_root = test_dir.path().join(SNAPSHOT_DIR_NAME);
+
+        let options = SnapshotOptions { mode: SnapshotMode::None, ..Default::default() };
+        assert_eq!(LocalSnapshot::new(&snapshot_root, "task_none").create(&source_dir, &options).unwrap(), None);
+
+        //硬链接快照和source是同一个文件,source被删除后快照中的文件还在
+        let options = SnapshotOptions { mode: SnapshotMode::HardlinkFarm, min_free_space: 0, ..Default::default() };
+        let snapshot = LocalSnapshot::new(&snapshot_root, "task_hardlink");
+        let data_dir = snapshot.create(&source_dir, &options).unwrap().unwrap();
+        assert_eq!(snapshot.get_mode(), Some(SnapshotMode::HardlinkFarm));
+        fs::remove_file(source_dir.join("a.txt")).unwrap();
+        assert_eq!(fs::read(data_dir.join("a.txt")).unwrap(), b"hello");
+        snapshot.remove();
+        assert!(source_dir.join("sub").join("b.txt").exists());
+
+        //auto按顺序选择第一个可用的方式
+        let options = SnapshotOptions { mode: SnapshotMode::Auto, min_free_space: 0, ..Default::default() };
+        let snapshot = LocalSnapshot::new(&snapshot_root, "task_auto");
+        let data_dir = snapshot.create(&source_dir, &options).unwrap().unwrap();
+        assert!(snapshot.get_mode().is_some());
+        assert_eq!(fs::read(data_dir.join("sub").join("b.txt")).unwrap(), vec![7u8; 4096]);
+        snapshot.remove();
+
+        //auto下所有方式都不可用时直接读source,指定的方式不可用时失败
+        if get_free_space(test_dir.path()).is_some() {
+            let options = SnapshotOptions { mode: SnapshotMode::Auto, min_free_space: u64::MAX, ..Default::default() };
+            assert_eq!(LocalSnapshot::new(&snapshot_root, "task_auto2").create(&source_dir, &options).unwrap(), None);
+            let options = SnapshotOptions { mode: SnapshotMode::HardlinkFarm, min_free_space: u64::MAX, ..Default::default() };
+            assert!(LocalSnapshot::new(&snapshot_root, "task_hardlink2").create(&source_dir, &options).is_err());
+        }
+    }
+}
@@ -0,0 +1,362 @@
+#![allow(unused)]
+//本地spool:target很慢(远端WAN)时,plan开启spool后备份的chunk先写入本地spool目录,task在数据进入spool后就完成,
+//engine的uploader在后台把spool中的chunk上传到target,失败时按spool自己的退避策略重试,备份窗口不再受带宽限制
+//spool按target内容寻址:target或spool中已经有的chunk不会再写入;恢复和校验时优先从spool读取还没有上传的chunk
+//待上传的chunk和link记录在task db的spool_entries中,上传成功后删除记录和spool中的文件
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use anyhow::Result;
+use log::*;
+use ndn_lib::{ChunkId, ChunkReader, ChunkWriter};
+use buckyos_backup_lib::*;
+use crate::task_db::{BackupTaskDb, WorkTask};
+
+pub const SPOOL_DIR_NAME: &str = "spool";
+//导出为离线种子的记录不再上传,等种子导入确认后删除
+pub const SPOOL_HOLD_RETRY_TIME: u64 = i64::MAX as u64;
+const SPOOL_PARTIAL_SUFFIX: &str = ".partial";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoolPolicy {
+    pub enabled: bool,
+    pub spool_dir: Option<String>,//为None时使用engine数据目录下的spool目录
+    pub max_spool_bytes: u64,//spool中待上传的数据超过这个大小时直接写target,0为不限制
+    pub retry_interval_secs: u64,//上传失败后第一次重试的间隔,之后每次翻倍
+    pub max_retry_interval_secs: u64,
+}
+
+impl Default for SpoolPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spool_dir: None,
+            max_spool_bytes: 0,
+            retry_interval_secs: 60,
+            max_retry_interval_secs: 3600,
+        }
+    }
+}
+
+impl SpoolPolicy {
+    //第retry_count次失败后到下次重试的间隔
+    pub fn retry_delay_secs(&self, retry_count: u32) -> u64 {
+        let delay = self.retry_interval_secs.saturating_mul(1u64 << retry_count.saturating_sub(1).min(32));
+        delay.min(self.max_retry_interval_secs.max(self.retry_interval_secs))
+    }
+}
+
+//待上传的chunk(link_chunk_id为None)或者chunk上的link(link_chunk_id -> chunk_id)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpoolEntry {
+    pub entry_id: i64,
+    pub target_url: String,
+    pub spool_dir: String,//写入时target的spool目录,plan修改spool目录后之前的记录仍然从原来的目录上传
+    pub chunk_id: String,
+    pub link_chunk_id: Option<String>,
+    pub checkpoint_id: String,
+    pub size: u64,
+    pub retry_count: u32,
+    pub next_retry_time: u64,
+    pub last_error: Option<String>,
+    pub create_time: u64,
+}
+
+impl SpoolEntry {
+    pub fn new(target_url: &str, spool_dir: &Path, chunk_id: &str, link_chunk_id: Option<&str>, checkpoint_id: &str, size: u64) -> Self {
+        let now = WorkTask::now_ms();
+        Self {
+            entry_id: 0,
+            target_url: target_url.to_string(),
+            spool_dir: spool_dir.to_string_lossy().to_string(),
+            chunk_id: chunk_id.to_string(),
+            link_chunk_id: link_chunk_id.map(|s| s.to_string()),
+            checkpoint_id: checkpoint_id.to_string(),
+            size,
+            retry_count: 0,
+            next_retry_time: now,
+            last_error: None,
+            create_time: now,
+        }
+    }
+}
+
+//每个target一个spool目录,用target_url的hash命名
+pub fn get_target_spool_dir(spool_root: &Path, target_url: &str) -> PathBuf {
+    let digest = Sha256::digest(target_url.as_bytes());
+    let name: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    spool_root.join(name)
+}
+
+//chunk_id中的':'在windows上不能用于文件名
+pub fn get_spool_chunk_path(spool_dir: &Path, chunk_id: &str) -> PathBuf {
+    spool_dir.join(chunk_id.replace(':', "_"))
+}
+
+pub async fn open_spool_chunk_reader(spool_dir: &Path, chunk_id: &str, offset: u64) -> std::io::Result<(ChunkReader, u64)> {
+    let mut file = File::open(get_spool_chunk_path(spool_dir, chunk_id)).await?;
+    let size = file.metadata().await?.len();
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok((Box::pin(file), size))
+}
+
+//上传前重新计算spool中chunk的hash,本地磁盘损坏的数据不能上传到target
+pub async fn verify_spool_chunk(spool_dir: &Path, chunk_id: &ChunkId) -> Result<u64> {
+    let chunk_id_str = chunk_id.to_string();
+    let (mut reader, _) = open_spool_chunk_reader(spool_dir, chunk_id_str.as_str(), 0).await?;
+    let mut hasher = BackupChunkHasher::for_chunk_id(chunk_id)?;
+    let mut buf = vec![0u8; COPY_CHUNK_BUFFER_SIZE];
+    let mut read_size: u64 = 0;
+    loop {
+        let read_len = reader.read(&mut buf).await?;
+        if read_len == 0 {
+            break;
+        }
+        hasher.update_from_bytes(&buf[..read_len]);
+        read_size += read_len as u64;
+    }
+    let real_chunk_id = hasher.finalize_chunk_id()?;
+    if real_chunk_id != *chunk_id {
+        return Err(anyhow::anyhow!("spool chunk {} is corrupted, hash is {}", chunk_id_str, real_chunk_id.to_string()));
+    }
+    Ok(read_size)
+}
+
+//spool中的chunk已经没有待上传的记录(数据和link)时删除
+pub async fn remove_spool_chunk(spool_dir: &Path, chunk_id: &str) {
+    let _ = fs::remove_file(get_spool_chunk_path(spool_dir, chunk_id)).await;
+}
+
+//包装target:写入进入spool,查询和读取先查spool再查target
+pub struct SpoolChunkTarget {
+    remote: BackupChunkTargetProvider,
+    spool_dir: PathBuf,
+    task_db: BackupTaskDb,
+    checkpoint_id: String,
+    max_spool_bytes: u64,
+    accept_writes: bool,//plan关闭spool后仍然需要从spool读取还没有上传的chunk,但新的chunk直接写target
+}
+
+impl SpoolChunkTarget {
+    pub fn new(remote: BackupChunkTargetProvider, spool_root: &Path, task_db: BackupTaskDb, checkpoint_id: &str, max_spool_bytes: u64, accept_writes: bool) -> Self {
+        let spool_dir = get_target_spool_dir(spool_root, remote.get_target_url().as_str());
+        Self {
+            remote,
+            spool_dir,
+            task_db,
+            checkpoint_id: checkpoint_id.to_string(),
+            max_spool_bytes,
+            accept_writes,
+        }
+    }
+
+    async fn get_spool_chunk_size(&self, chunk_id: &str) -> Option<u64> {
+        fs::metadata(get_spool_chunk_path(&self.spool_dir, chunk_id)).await.ok().map(|meta| meta.len())
+    }
+
+    //chunk自己或者它link到的chunk在spool中时返回大小
+    async fn get_resolved_spool_chunk_size(&self, chunk_id: &str) -> BackupResult<Option<u64>> {
+        if let Some(size) = self.get_spool_chunk_size(chunk_id).await {
+            return Ok(Some(size));
+        }
+        if let Some(source_chunk_id) = self.resolve_spool_chunk(chunk_id)? {
+            return Ok(self.get_spool_chunk_size(source_chunk_id.as_str()).await);
+        }
+        Ok(None)
+    }
+
+    //spool中的link: chunk_id -> 实际保存数据的chunk
+    fn resolve_spool_chunk(&self, chunk_id: &str) -> BackupResult<Option<String>> {
+        self.task_db.load_spool_link_source(self.remote.get_target_url().as_str(), chunk_id)
+    }
+
+    fn is_spool_full(&self) -> BackupResult<bool> {
+        if self.max_spool_bytes == 0 {
+            return Ok(false);
+        }
+        let used_size = self.task_db.get_spool_used_size(self.remote.get_target_url().as_str())?;
+        Ok(used_size >= self.max_spool_bytes)
+    }
+}
+
+#[async_trait]
+impl IBackupChunkTargetProvider for SpoolChunkTarget {
+    async fn get_target_info(&self) -> Result<String> {
+        self.remote.get_target_info().await
+    }
+
+    fn get_target_url(&self) -> String {
+        self.remote.get_target_url()
+    }
+
+    async fn get_account_session_info(&self) -> Result<String> {
+        self.remote.get_account_session_info().await
+    }
+
+    async fn set_account_session_info(&self, session_info: &str) -> Result<()> {
+        self.remote.set_account_session_info(session_info).await
+    }
+
+    //target不可用时当作不存在,chunk写入spool,上传时target已经有的chunk会返回AlreadyDone
+    async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
+        let chunk_id_str = chunk_id.to_string();
+        if let Some(size) = self.get_resolved_spool_chunk_size(chunk_id_str.as_str()).await? {
+            return Ok((true, size));
+        }
+        match self.remote.is_chunk_exist(chunk_id).await {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                warn!("query chunk {} on target {} error: {}, spool it", chunk_id_str, self.remote.get_target_url(), e);
+                Ok((false, 0))
+            }
+        }
+    }
+
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId]) -> Result<Vec<(bool, u64)>> {
+        let mut results = Vec::with_capacity(chunk_ids.len());
+        let mut remote_indexes = Vec::new();
+        let mut remote_chunk_ids = Vec::new();
+        for (index, chunk_id) in chunk_ids.iter().enumerate() {
+            match self.get_resolved_spool_chunk_size(chunk_id.to_string().as_str()).await? {
+                Some(size) => results.push((true, size)),
+                None => {
+                    results.push((false, 0));
+                    remote_indexes.push(index);
+                    remote_chunk_ids.push(chunk_id.clone());
+                }
+            }
+        }
+        if remote_chunk_ids.is_empty() {
+            return Ok(results);
+        }
+        match self.remote.is_chunk_exist_batch(&remote_chunk_ids).await {
+            Ok(remote_results) => {
+                for (index, result) in remote_indexes.into_iter().zip(remote_results.into_iter()) {
+                    results[index] = result;
+                }
+            }
+            Err(e) => {
+                warn!("query {} chunks on target {} error: {}, spool them", remote_chunk_ids.len(), self.remote.get_target_url(), e);
+            }
+        }
+        Ok(results)
+    }
+
+    //spool是本地的,中断后从头写入
+    async fn open_chunk_writer(&self, chunk_id: &ChunkId, offset: u64, size: u64) -> BackupResult<(ChunkWriter, u64)> {
+        let chunk_id_str = chunk_id.to_string();
+        if self.get_spool_chunk_size(chunk_id_str.as_str()).await.is_some() {
+            return Err(BuckyBackupError::AlreadyDone(format!("chunk {} already in spool", chunk_id_str)));
+        }
+        if !self.accept_writes || self.is_spool_full()? {
+            debug!("spool of target {} is full or disabled, write chunk {} to target directly", self.remote.get_target_url(), chunk_id_str);
+            return self.remote.open_chunk_writer(chunk_id, offset, size).await;
+        }
+        fs::create_dir_all(&self.spool_dir).await?;
+        let mut partial_path = get_spool_chunk_path(&self.spool_dir, chunk_id_str.as_str()).into_os_string();
+        partial_path.push(SPOOL_PARTIAL_SUFFIX);
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&partial_path).await?;
+        Ok((Box::pin(file), 0))
+    }
+
+    async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let chunk_id_str = chunk_id.to_string();
+        let chunk_path = get_spool_chunk_path(&self.spool_dir, chunk_id_str.as_str());
+        let mut partial_path = chunk_path.clone().into_os_string();
+        partial_path.push(SPOOL_PARTIAL_SUFFIX);
+        let partial_path = PathBuf::from(partial_path);
+        if !partial_path.exists() {
+            //spool满时直接写入了target
+            return self.remote.complete_chunk_writer(chunk_id).await;
+        }
+        let size = fs::metadata(&partial_path).await?.len();
+        fs::rename(&partial_path, &chunk_path).await?;
+        let entry = SpoolEntry::new(self.remote.get_target_url().as_str(), &self.spool_dir, chunk_id_str.as_str(), None, self.checkpoint_id.as_str(), size);
+        self.task_db.add_spool_entry(&entry)?;
+        debug!("chunk {} is spooled, size: {}", chunk_id_str, size);
+        Ok(())
+    }
+
+    async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
+        //link可能在chunk数据写入前建立,spool开启时都先记录下来,由uploader按记录顺序上传
+        if !self.accept_writes {
+            return self.remote.link_chunkid(source_chunk_id, new_chunk_id).await;
+        }
+        let source_chunk_id_str = source_chunk_id.to_string();
+        let entry = SpoolEntry::new(self.remote.get_target_url().as_str(), &self.spool_dir, source_chunk_id_str.as_str(),
+            Some(new_chunk_id.to_string().as_str()), self.checkpoint_id.as_str(), 0);
+        self.task_db.add_spool_entry(&entry)?;
+        Ok(())
+    }
+
+    async fn query_link_target(&self, source_chunk_id: &ChunkId) -> BackupResult<Option<ChunkId>> {
+        let link_chunk_id = self.task_db.load_spool_link_target(self.remote.get_target_url().as_str(), source_chunk_id.to_string().as_str())?;
+        if let Some(link_chunk_id) = link_chunk_id {
+            let chunk_id = ChunkId::new(link_chunk_id.as_str()).map_err(|e| BuckyBackupError::Internal(e.to_string()))?;
+            return Ok(Some(chunk_id));
+        }
+        self.remote.query_link_target(source_chunk_id).await
+    }
+
+    async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset: u64) -> BackupResult<ChunkReader> {
+        let chunk_id_str = chunk_id.to_string();
+        let spool_chunk_id = self.resolve_spool_chunk(chunk_id_str.as_str())?.unwrap_or(chunk_id_str);
+        if let Ok((reader, _)) = open_spool_chunk_reader(&self.spool_dir, spool_chunk_id.as_str(), offset).await {
+            return Ok(reader);
+        }
+        self.remote.open_chunk_reader_for_restore(chunk_id, offset).await
+    }
+
+    async fn remove_chunk(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        self.remote.remove_chunk(chunk_id).await
+    }
+
+    async fn read_layout_marker(&self) -> BackupResult<Option<String>> {
+        self.remote.read_layout_marker().await
+    }
+
+    async fn write_layout_marker(&self, marker: &str) -> BackupResult<()> {
+        self.remote.write_layout_marker(marker).await
+    }
+
+    async fn upgrade_layout(&self, from_version: u32) -> BackupResult<()> {
+        self.remote.upgrade_layout(from_version).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool_retry_delay() {
+        let policy = SpoolPolicy::default();
+        assert_eq!(policy.retry_delay_secs(1), 60);
+        assert_eq!(policy.retry_delay_secs(2), 120);
+        assert_eq!(policy.retry_delay_secs(6), 1920);
+        assert_eq!(policy.retry_delay_secs(7), 3600);
+        assert_eq!(policy.retry_delay_secs(100), 3600);
+
+        let spool_root = Path::new("/var/spool");
+        let dir1 = get_target_spool_dir(spool_root, "s3://bucket/a");
+        assert_eq!(dir1, get_target_spool_dir(spool_root, "s3://bucket/a"));
+        assert_ne!(dir1, get_target_spool_dir(spool_root, "s3://bucket/b"));
+        assert_eq!(get_spool_chunk_path(&dir1, "sha256:abcd"), dir1.join("sha256_abcd"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_spool_chunk() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let content = vec![3u8; 4096];
+        let chunk_id = calc_content_chunk_id(&content).unwrap();
+        let chunk_path = get_spool_chunk_path(spool_dir.path(), chunk_id.to_string().as_str());
+        std::fs::write(&chunk_path, &content).unwrap();
+        assert_eq!(verify_spool_chunk(spool_dir.path(), &chunk_id).await.unwrap(), 4096);
+        std::fs::write(&chunk_path, &content[..4000]).unwrap();
+        assert!(verify_spool_chunk(spool_dir.path(), &chunk_id).await.is_err());
+    }
+}
@@ -0,0 +1,365 @@
+#![allow(unused)]
+//裸机恢复清单:plan配置了system_manifest(整机备份)时,每次备份记录磁盘分区布局/fstab/存在的系统路径,保存在task_db中,随checkpoint删除
+//恢复时build_recovery_script根据清单生成在救援系统中执行的脚本:用sfdisk重建分区表,按原来的uuid/label格式化,
+//挂载到恢复根目录,然后把checkpoint恢复到这个目录。脚本会清空磁盘,engine只生成不执行,由用户确认磁盘后手动运行
+//脚本只重建根目录所在的系统盘,清空前要求用户逐个输入设备名确认;系统盘上有LVM/LUKS/RAID时不能生成脚本
+//目前只支持linux(lsblk/sfdisk)
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::Result;
+use log::*;
+
+pub const DEFAULT_RECOVERY_ROOT: &str = "/mnt/recovery";
+//整机恢复后需要检查的系统路径,备份时存在的才记录
+const SYSTEM_PATHS: [&str; 6] = ["/etc", "/boot", "/boot/efi", "/usr", "/var", "/home"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiskLayout {
+    pub path: String,
+    pub size: u64,
+    pub partition_table: Option<String>,//sfdisk --dump的输出,可以直接交给sfdisk重建分区表
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    pub path: String,
+    pub disk: String,
+    //lsblk的type,分区是part;分区上的lvm/crypt/raid设备也记录下来,恢复时需要拒绝
+    #[serde(default = "default_device_type")]
+    pub device_type: String,
+    pub size: u64,
+    pub fstype: Option<String>,
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+    pub mountpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FstabEntry {
+    pub device: String,
+    pub mountpoint: String,
+    pub fstype: String,
+    pub options: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemRestoreManifest {
+    pub hostname: String,
+    pub os: String,
+    pub create_time: u64,
+    pub disks: Vec<DiskLayout>,
+    pub partitions: Vec<PartitionInfo>,
+    pub fstab: Vec<FstabEntry>,
+    pub system_paths: Vec<String>,
+}
+
+#[cfg(target_os = "linux")]
+pub async fn collect_system_manifest() -> Result<SystemRestoreManifest> {
+    let output = tokio::process::Command::new("lsblk")
+        .args(["-J", "-b", "-o", "NAME,PATH,SIZE,TYPE,FSTYPE,UUID,LABEL,MOUNTPOINT"])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("lsblk failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    let (mut disks, partitions) = parse_lsblk_output(String::from_utf8_lossy(&output.stdout).as_ref())?;
+    for disk in disks.iter_mut() {
+        let output = tokio::process::Command::new("sfdisk").args(["--dump", disk.path.as_str()]).output().await;
+        match output {
+            Ok(output) if output.status.success() => {
+                disk.partition_table = Some(String::from_utf8_lossy(&output.stdout).to_string());
+            }
+            _ => warn!("dump partition table of {} failed", disk.path),
+        }
+    }
+    let fstab = tokio::fs::read_to_string("/etc/fstab").await.map(|content| parse_fstab(&content)).unwrap_or_default();
+    Ok(SystemRestoreManifest {
+        hostname: sysinfo::System::host_name().unwrap_or_default(),
+        os: sysinfo::System::long_os_version().unwrap_or_default(),
+        create_time: chrono::Utc::now().timestamp_millis() as u64,
+        disks,
+        partitions,
+        fstab,
+        system_paths: SYSTEM_PATHS.iter().filter(|path| std::path::Path::new(path).exists()).map(|path| path.to_string()).collect(),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn collect_system_manifest() -> Result<SystemRestoreManifest> {
+    Err(anyhow::anyhow!("system manifest is only supported on linux"))
+}
+
+fn default_device_type() -> String {
+    "part".to_string()
+}
+
+//这些设备或文件系统需要先手动重建(pvcreate/cryptsetup/mdadm),脚本不能按分区直接格式化
+fn is_stacked_device(partition: &PartitionInfo) -> bool {
+    partition.device_type != "part"
+        || matches!(partition.fstype.as_deref(), Some("LVM2_member") | Some("crypto_LUKS") | Some("linux_raid_member"))
+}
+
+fn json_str(device: &Value, key: &str) -> Option<String> {
+    device.get(key).and_then(|value| value.as_str()).filter(|value| !value.is_empty()).map(|value| value.to_string())
+}
+
+//旧版本lsblk的-J输出中size是字符串
+fn json_size(device: &Value) -> u64 {
+    match device.get("size") {
+        Some(Value::Number(size)) => size.as_u64().unwrap_or(0),
+        Some(Value::String(size)) => size.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn collect_lsblk_devices(devices: &[Value], disk: Option<&str>, disks: &mut Vec<DiskLayout>, partitions: &mut Vec<PartitionInfo>) {
+    for device in devices {
+        let path = json_str(device, "path").unwrap_or_else(|| format!("/dev/{}", json_str(device, "name").unwrap_or_default()));
+        let mut current_disk = disk.map(|disk| disk.to_string());
+        match device.get("type").and_then(|value| value.as_str()) {
+            Some("disk") => {
+                disks.push(DiskLayout { path: path.clone(), size: json_size(device), partition_table: None });
+                current_disk = Some(path.clone());
+            }
+            Some(device_type) if device_type == "part" || device_type == "lvm" || device_type == "crypt" || device_type.starts_with("raid") => {
+                partitions.push(PartitionInfo {
+                    path: path.clone(),
+                    disk: current_disk.clone().unwrap_or_default(),
+                    device_type: device_type.to_string(),
+                    size: json_size(device),
+                    fstype: json_str(device, "fstype"),
+                    uuid: json_str(device, "uuid"),
+                    label: json_str(device, "label"),
+                    mountpoint: json_str(device, "mountpoint"),
+                });
+            }
+            _ => {}
+        }
+        if let Some(children) = device.get("children").and_then(|value| value.as_array()) {
+            collect_lsblk_devices(children, current_disk.as_deref(), disks, partitions);
+        }
+    }
+}
+
+pub fn parse_lsblk_output(output: &str) -> Result<(Vec<DiskLayout>, Vec<PartitionInfo>)> {
+    let value: Value = serde_json::from_str(output)?;
+    let devices = value.get("blockdevices").and_then(|value| value.as_array())
+        .ok_or_else(|| anyhow::anyhow!("invalid lsblk output"))?;
+    let mut disks = Vec::new();
+    let mut partitions = Vec::new();
+    collect_lsblk_devices(devices, None, &mut disks, &mut partitions);
+    Ok((disks, partitions))
+}
+
+pub fn parse_fstab(content: &str) -> Vec<FstabEntry> {
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 3 {
+                return None;
+            }
+            Some(FstabEntry {
+                device: fields[0].to_string(),
+                mountpoint: fields[1].to_string(),
+                fstype: fields[2].to_string(),
+                options: fields.get(3).unwrap_or(&"defaults").to_string(),
+            })
+        })
+        .collect()
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+//按原来的uuid/label格式化,保证恢复出来的fstab/引导配置还能找到分区
+fn build_format_command(partition: &PartitionInfo) -> Option<String> {
+    let fstype = partition.fstype.as_deref()?;
+    let uuid = partition.uuid.as_deref();
+    let label = partition.label.as_deref().map(shell_quote);
+    let mut command = match fstype {
+        "ext2" | "ext3" | "ext4" | "btrfs" => {
+            let mut command = if fstype == "btrfs" { "mkfs.btrfs -f".to_string() } else { format!("mkfs.{} -F", fstype) };
+            if let Some(uuid) = uuid {
+                command.push_str(&format!(" -U {}", uuid));
+            }
+            if let Some(label) = label {
+                command.push_str(&format!(" -L {}", label));
+            }
+            command
+        }
+        "xfs" => {
+            let mut command = "mkfs.xfs -f".to_string();
+            if let Some(uuid) = uuid {
+                command.push_str(&format!(" -m uuid={}", uuid));
+            }
+            if let Some(label) = label {
+                command.push_str(&format!(" -L {}", label));
+            }
+            command
+        }
+        "vfat" => {
+            let mut command = "mkfs.vfat".to_string();
+            if let Some(uuid) = uuid {
+                command.push_str(&format!(" -i {}", uuid.replace('-', "")));
+            }
+            if let Some(label) = label {
+                command.push_str(&format!(" -n {}", label));
+            }
+            command
+        }
+        "swap" => {
+            let mut command = "mkswap".to_string();
+            if let Some(uuid) = uuid {
+                command.push_str(&format!(" -U {}", uuid));
+            }
+            if let Some(label) = label {
+                command.push_str(&format!(" -L {}", label));
+            }
+            command
+        }
+        _ => return Some(format!("# unsupported filesystem {} on {}, format it manually", fstype, partition.path)),
+    };
+    command.push_str(&format!(" {}", partition.path));
+    Some(command)
+}
+
+//根目录所在的磁盘,备份的是这个磁盘上的系统,其它数据盘不能由恢复脚本清空
+pub fn get_system_disk(manifest: &SystemRestoreManifest) -> Result<&DiskLayout> {
+    let root_partition = manifest.partitions.iter()
+        .find(|partition| partition.mountpoint.as_deref() == Some("/"))
+        .ok_or_else(|| anyhow::anyhow!("root filesystem is not recorded in the system manifest"))?;
+    manifest.disks.iter()
+        .find(|disk| disk.path == root_partition.disk)
+        .ok_or_else(|| anyhow::anyhow!("disk of root filesystem {} is not recorded in the system manifest", root_partition.path))
+}
+
+//生成在救援系统中运行的恢复脚本,恢复checkpoint前执行
+pub fn build_recovery_script(manifest: &SystemRestoreManifest, recovery_root: &str) -> Result<String> {
+    let system_disk = get_system_disk(manifest)?;
+    let partition_table = system_disk.partition_table.as_ref()
+        .ok_or_else(|| anyhow::anyhow!("partition table of system disk {} was not recorded", system_disk.path))?;
+    let partitions: Vec<&PartitionInfo> = manifest.partitions.iter().filter(|partition| partition.disk == system_disk.path).collect();
+    let stacked_devices: Vec<String> = partitions.iter()
+        .filter(|partition| is_stacked_device(partition))
+        .map(|partition| format!("{} ({})", partition.path, partition.fstype.as_deref().unwrap_or(partition.device_type.as_str())))
+        .collect();
+    if !stacked_devices.is_empty() {
+        return Err(anyhow::anyhow!("system disk {} uses LVM/LUKS/RAID, which the recovery script can't rebuild: {}",
+            system_disk.path, stacked_devices.join(", ")));
+    }
+
+    let recovery_root = recovery_root.trim_end_matches('/');
+    let mut lines = vec![
+        "#!/bin/sh".to_string(),
+        format!("# bare-metal recovery script for {} ({})", manifest.hostname, manifest.os),
+        format!("# WARNING: ALL DATA ON {} WILL BE ERASED", system_disk.path),
+        "set -e".to_string(),
+        //设备名在救援系统中可能变化,清空前显示设备信息并要求输入设备名确认
+        "confirm_device() {".to_string(),
+        "    lsblk -o NAME,SIZE,TYPE,FSTYPE,LABEL,MODEL \"$1\"".to_string(),
+        "    size=$(blockdev --getsize64 \"$1\")".to_string(),
+        "    if [ \"$size\" -lt \"$2\" ]; then echo \"$1 is smaller than the original disk ($2 bytes)\"; exit 1; fi".to_string(),
+        "    printf 'type %s to erase it: ' \"$1\"".to_string(),
+        "    read answer".to_string(),
+        "    if [ \"$answer\" != \"$1\" ]; then echo \"$1 is not confirmed, abort\"; exit 1; fi".to_string(),
+        "}".to_string(),
+    ];
+    for disk in manifest.disks.iter().filter(|disk| disk.path != system_disk.path) {
+        lines.push(format!("# {} is not the system disk, it is not touched", disk.path));
+    }
+    lines.push(format!("confirm_device {} {}", system_disk.path, system_disk.size));
+    lines.push(format!("sfdisk --wipe always {} <<'BUCKY_PARTITION_TABLE'", system_disk.path));
+    lines.push(partition_table.trim_end().to_string());
+    lines.push("BUCKY_PARTITION_TABLE".to_string());
+    lines.extend(partitions.iter().filter_map(|partition| build_format_command(partition)));
+
+    //先挂载上级目录
+    let mut mount_partitions: Vec<(&str, &PartitionInfo)> = partitions.iter().copied()
+        .filter_map(|partition| partition.mountpoint.as_deref().filter(|mountpoint| mountpoint.starts_with('/')).map(|mountpoint| (mountpoint, partition)))
+        .collect();
+    mount_partitions.sort_by_key(|(mountpoint, _)| mountpoint.trim_end_matches('/').matches('/').count());
+    for (mountpoint, partition) in mount_partitions {
+        let mount_dir = format!("{}{}", recovery_root, mountpoint.trim_end_matches('/'));
+        let mount_dir = if mount_dir.is_empty() { "/".to_string() } else { mount_dir };
+        lines.push(format!("mkdir -p {} && mount {} {}", shell_quote(&mount_dir), partition.path, shell_quote(&mount_dir)));
+    }
+    lines.push(format!("# now restore the checkpoint to file://{} , then reinstall the boot loader", recovery_root));
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LSBLK_OUTPUT: &str = r#"{"blockdevices": [
+        {"name":"sda", "path":"/dev/sda", "size":512110190592, "type":"disk", "fstype":null, "uuid":null, "label":null, "mountpoint":null,
+         "children": [
+            {"name":"sda1", "path":"/dev/sda1", "size":536870912, "type":"part", "fstype":"vfat", "uuid":"ABCD-1234", "label":null, "mountpoint":"/boot/efi"},
+            {"name":"sda2", "path":"/dev/sda2", "size":"500000000000", "type":"part", "fstype":"ext4", "uuid":"0b6c9a3e-1f7a-4c7e-9a55-3d1a4d2b7c11", "label":"root", "mountpoint":"/"},
+            {"name":"sda3", "path":"/dev/sda3", "size":8589934592, "type":"part", "fstype":"swap", "uuid":"5e1f0c2a-8d4b-4a57-b2a8-0c9b3f6e1d22", "label":null, "mountpoint":"[SWAP]"}
+         ]},
+        {"name":"loop0", "path":"/dev/loop0", "size":4096, "type":"loop", "fstype":"squashfs", "uuid":null, "label":null, "mountpoint":"/snap/core"}
+    ]}"#;
+
+    #[test]
+    fn test_parse_system_layout() {
+        let (disks, partitions) = parse_lsblk_output(LSBLK_OUTPUT).unwrap();
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].path, "/dev/sda");
+        assert_eq!(partitions.len(), 3);
+        assert_eq!(partitions[1].disk, "/dev/sda");
+        assert_eq!(partitions[1].size, 500000000000);
+        assert_eq!(partitions[1].label.as_deref(), Some("root"));
+        assert!(parse_lsblk_output("{}").is_err());
+
+        let fstab = parse_fstab("# /etc/fstab\nUUID=0b6c9a3e / ext4 errors=remount-ro 0 1\n\nUUID=ABCD-1234 /boot/efi vfat umask=0077 0 1\n/swapfile none swap\n");
+        assert_eq!(fstab.len(), 3);
+        assert_eq!(fstab[1].mountpoint, "/boot/efi");
+        assert_eq!(fstab[2].options, "defaults");
+    }
+
+    #[test]
+    fn test_build_recovery_script() {
+        let (mut disks, partitions) = parse_lsblk_output(LSBLK_OUTPUT).unwrap();
+        disks[0].partition_table = Some("label: gpt\ndevice: /dev/sda\n/dev/sda1 : start=2048, size=1048576\n".to_string());
+        let manifest = SystemRestoreManifest { hostname: "nas".to_string(), disks, partitions, ..Default::default() };
+        let script = build_recovery_script(&manifest, "/mnt/recovery/").unwrap();
+        assert!(script.contains("confirm_device /dev/sda 512110190592\nsfdisk --wipe always /dev/sda <<'BUCKY_PARTITION_TABLE'\nlabel: gpt\n"));
+        assert!(script.contains("mkfs.vfat -i ABCD1234 /dev/sda1"));
+        assert!(script.contains("mkfs.ext4 -F -U 0b6c9a3e-1f7a-4c7e-9a55-3d1a4d2b7c11 -L 'root' /dev/sda2"));
+        assert!(script.contains("mkswap -U 5e1f0c2a-8d4b-4a57-b2a8-0c9b3f6e1d22 /dev/sda3"));
+        //根目录先于/boot/efi挂载,swap不挂载
+        let root_mount = script.find("mount /dev/sda2 '/mnt/recovery'").unwrap();
+        let efi_mount = script.find("mount /dev/sda1 '/mnt/recovery/boot/efi'").unwrap();
+        assert!(root_mount < efi_mount);
+        assert!(!script.contains("[SWAP]"));
+
+        //只清空系统盘,数据盘不动
+        let mut data_manifest = manifest.clone();
+        data_manifest.disks.push(DiskLayout { path: "/dev/sdb".to_string(), size: 4096, partition_table: Some("label: gpt\n".to_string()) });
+        data_manifest.partitions.push(PartitionInfo { path: "/dev/sdb1".to_string(), disk: "/dev/sdb".to_string(), device_type: "part".to_string(),
+            fstype: Some("ext4".to_string()), mountpoint: Some("/data".to_string()), ..Default::default() });
+        let script = build_recovery_script(&data_manifest, "/mnt/recovery").unwrap();
+        assert!(!script.contains("sfdisk --wipe always /dev/sdb"));
+        assert!(!script.contains("mkfs.ext4 -F /dev/sdb1"));
+        assert!(!script.contains("/mnt/recovery/data"));
+
+        //系统盘上有LVM时拒绝生成
+        let lvm_output = r#"{"blockdevices": [
+            {"name":"sda", "path":"/dev/sda", "size":4096, "type":"disk",
+             "children": [
+                {"name":"sda1", "path":"/dev/sda1", "size":2048, "type":"part", "fstype":"LVM2_member",
+                 "children": [{"name":"vg-root", "path":"/dev/mapper/vg-root", "size":2048, "type":"lvm", "fstype":"ext4", "mountpoint":"/"}]}
+             ]}
+        ]}"#;
+        let (mut disks, partitions) = parse_lsblk_output(lvm_output).unwrap();
+        disks[0].partition_table = Some("label: gpt\n".to_string());
+        assert_eq!(partitions[1].disk, "/dev/sda");
+        let manifest = SystemRestoreManifest { disks, partitions, ..Default::default() };
+        assert!(build_recovery_script(&manifest, "/mnt/recovery").is_err());
+        assert!(build_recovery_script(&SystemRestoreManifest::default(), "/mnt/recovery").is_err());
+    }
+}
@@ -1,13 +1,40 @@
 #![allow(dead_code)]
 #![allow(unused)]
-use thiserror::Error;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 use serde_json::{Value, json};
+use serde::{Serialize, Deserialize};
 use rusqlite::{Connection, params, Result as SqlResult};
 use rusqlite::types::{ToSql, FromSql, ValueRef};
 use buckyos_backup_lib::*;
 use log::*;
 use buckyos_backup_lib::RestoreConfig;
+use crate::work_task::TaskRuntimeStat;
+use crate::anomaly::{AnomalyAction, PlanBaseline};
+use crate::approval::{DestructiveOperation, PendingOperation};
+use crate::audit::AuditRecord;
+use crate::contents_index::{ContentsIndexEntry, ContentsQuery, get_size_bucket};
+use crate::dedup::DedupStat;
+use crate::fleet::AgentRecord;
+use crate::heatmap::DirChangeStat;
+use crate::io_throttle::SourceIoPolicy;
+use crate::legal_hold::LegalHold;
+use crate::network::NetworkPolicy;
+use crate::partial_accept::PartialAcceptPolicy;
+use crate::power::PowerPolicy;
+use crate::quota::{QuotaScope, StorageQuota};
+use crate::restore_drill::{RestoreDrillPolicy, RestoreDrillRecord};
+use crate::sla::SlaPolicy;
+use crate::retention::RetentionPolicy;
+use crate::schedule::{BackupSchedulePolicy, BackupRetryPolicy};
+use crate::seed::SeedExportRecord;
+use crate::snapshot::SnapshotOptions;
+use crate::spool::{SpoolEntry, SpoolPolicy, SPOOL_HOLD_RETRY_TIME};
+use crate::system_manifest::SystemRestoreManifest;
+use crate::task_journal::TaskJournalEntry;
+use crate::transfer_stat::CheckpointTransferStat;
+use crate::wake::PreTaskHook;
+use crate::watchdog::TaskTimeoutPolicy;
 
 
 // impl From<ChunkItem> for BackupItem {
@@ -25,17 +52,8 @@ use buckyos_backup_lib::RestoreConfig;
 //     }
 // }
 
-#[derive(Error, Debug)]
-pub enum BackupTaskError {
-    #[error("task not found")]
-    TaskNotFound,
-    #[error("invalid checkpoint id")]
-    InvalidCheckpointId,
-    #[error("database error: {0}")]
-    DatabaseError(#[from] rusqlite::Error),
-}
-
-pub type Result<T> = std::result::Result<T, BackupTaskError>;
+//task_db和engine、provider使用同一个错误类型,调用方可以通过kind()/is_retryable()区分
+pub type Result<T> = BackupResult<T>;
 
 #[derive(Debug, Clone)]
 pub enum BackupSource {
@@ -74,20 +92,29 @@ pub enum CheckPointState {
     New,
     Prepared,//所有的backup item确认了
     Evaluated,//所有的backup item都计算了hash和diff(如有需要)
+    WaitConfirm,//数据已经传输完成,但和plan的基线相比有异常(疑似勒索软件),需要用户确认后才能变成Done
+    LocalDone,//数据完整并已签名,但还有chunk只在本地spool中,全部上传到target后才变成Done
     Done,
     Failed,
 }
 
-impl ToSql for CheckPointState {
-    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
-        let s = match self {
+impl CheckPointState {
+    pub fn to_string(&self) -> &str {
+        match self {
             CheckPointState::New => "NEW",
             CheckPointState::Prepared => "PREPARED",
             CheckPointState::Evaluated => "EVALUATED",
+            CheckPointState::WaitConfirm => "WAIT_CONFIRM",
+            CheckPointState::LocalDone => "LOCAL_DONE",
             CheckPointState::Done => "DONE",
             CheckPointState::Failed => "FAILED",
-        };
-        Ok(s.into())
+        }
+    }
+}
+
+impl ToSql for CheckPointState {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
     }
 }
 
@@ -97,6 +124,8 @@ impl FromSql for CheckPointState {
             "NEW" => CheckPointState::New,
             "PREPARED" => CheckPointState::Prepared,
             "EVALUATED" => CheckPointState::Evaluated,
+            "WAIT_CONFIRM" => CheckPointState::WaitConfirm,
+            "LOCAL_DONE" => CheckPointState::LocalDone,
             "DONE" => CheckPointState::Done,
             "FAILED" => CheckPointState::Failed,
             _ => CheckPointState::Failed, // 默认失败状态
@@ -111,7 +140,14 @@ pub struct BackupCheckPoint {
     pub state:CheckPointState,
     pub owner_plan:String,
     pub checkpoint_hash:Option<String>,
+    pub signature:Option<String>,//CheckPointSignature的json,checkpoint完成时用node私钥签名
+    pub pinned: bool,//被pin的checkpoint(以及它依赖的checkpoint)不会被prune/保留策略删除
+    pub pin_reason: Option<String>,
     pub checkpoint_index:u64,
+    pub chunk_hash: ChunkHashType,//创建时从plan配置复制,checkpoint里的chunk_id都用这个算法计算
+    pub inventory_only: bool,//清单checkpoint:只有文件清单,数据没有上传,不能恢复
+    pub failed_item_count: u64,//部分完成的checkpoint中读取失败的item数量,大于0时为done with errors
+    pub retry_of: Option<String>,//重新备份这个checkpoint中读取失败的item时创建,item从它复制,不重新枚举source
     pub create_time: u64, //checkpoint的顺序很重要，因此不能用时间来排序（这可能会因为时间错误带来严重的BUG）
 
     //pub small_content_cache:HashMap<String, Vec<u8>>,
@@ -127,13 +163,139 @@ impl BackupCheckPoint {
             depend_checkpoint_id: parent_checkpoint_id.map(|s| s.to_string()),
             state: CheckPointState::New,
             checkpoint_hash: None,
+            signature: None,
+            pinned: false,
+            pin_reason: None,
             checkpoint_index,
+            chunk_hash: ChunkHashType::default(),
+            inventory_only: false,
+            failed_item_count: 0,
+            retry_of: None,
             create_time: (chrono::Utc::now().timestamp_millis() as u64),
         }
     }
 }
 
 
+//plan级别的可选配置,以json保存在backup_plans.options中,新增字段必须有默认值以兼容老数据
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPlanOptions {
+    pub preserve_ownership: bool,//是否记录uid/gid
+    pub preserve_xattrs: bool,//是否记录扩展属性
+    //严格模式:文件在备份过程中反复被修改时让任务失败(否则只标记为fuzzy),
+    //并且备份完成后要校验target上的所有chunk才把checkpoint设置为Done
+    pub strict_mode: bool,
+    pub anomaly_action: AnomalyAction,//备份结果严重偏离基线时的处理方式
+    pub schedule: Vec<BackupSchedulePolicy>,//定时备份策略,为空时只能手动备份
+    pub retry: BackupRetryPolicy,//备份task失败后的自动重试策略
+    pub snapshot: SnapshotOptions,//备份前创建本地source的快照,备份从快照读取
+    //原地恢复前执行的脚本,只能是daemon配置的hook_dir下的文件名,返回非0表示使用source的服务还在运行,拒绝恢复
+    pub pre_restore_hook: Option<String>,
+    pub retention: RetentionPolicy,//checkpoint的保留策略,备份完成后删除不再保留的checkpoint
+    pub pre_task_hooks: Vec<PreTaskHook>,//备份/恢复task开始传输前执行,比如唤醒休眠的target
+    pub network: NetworkPolicy,//离线/计费网络下暂停备份
+    pub power: PowerPolicy,//使用电池时暂停备份
+    pub chunk_hash: ChunkHashType,//chunk_id的hash算法,只对之后创建的checkpoint生效
+    //清单模式:记录完整的文件清单(路径/大小/hash/属性)但不上传数据,用于审计,或者在开启完整备份前快速建立基线
+    pub inventory_only: bool,
+    pub system_manifest: bool,//整机备份:每次备份记录分区布局/fstab等裸机恢复清单
+    pub tenant: Option<String>,//plan所属的租户,同一个租户的plan共享租户的存储配额
+    //大文件(虚拟机镜像/邮箱)按内容切分上传,修改后只上传和上一个checkpoint不同的chunk
+    pub delta_upload: bool,
+    pub exclude_patterns: Vec<String>,//不备份的文件/目录名,支持*和?通配符
+    pub timeout: TaskTimeoutPolicy,//task最长运行时间和卡住检测,超时的task标记为Failed
+    pub partial_accept: PartialAcceptPolicy,//少量item读取失败时checkpoint仍然完成
+    pub source_io: SourceIoPolicy,//读取source的限速(低优先级),减少备份对业务的影响
+    pub spool: SpoolPolicy,//先写入本地spool,后台再上传到target,用于很慢的远端target
+    pub restore_drill: RestoreDrillPolicy,//定期抽取文件做恢复演练
+    pub sla: SlaPolicy,//RPO/RTO目标,不达标时发布SlaBreached事件
+    pub parallel_hash: ParallelHashConfig,//prepare时多线程计算大文件的chunk_id
+    pub reparse_points: ReparsePointPolicy,//本地source遇到符号链接/junction时记录链接、跟随还是跳过
+    //本地source是否备份子目录,老版本创建的plan只备份source目录下的文件,需要时手动开启
+    pub recursive: bool,
+    pub cloud_placeholder: CloudPlaceholderPolicy,//OneDrive/iCloud等云盘占位文件跳过、只记录属性还是限速下载
+    //允许恢复/导出没有签名(node未激活时创建)或者无法确认签名者的checkpoint
+    pub allow_unsigned_checkpoint: bool,
+}
+
+impl Default for BackupPlanOptions {
+    fn default() -> Self {
+        Self {
+            preserve_ownership: true,
+            preserve_xattrs: true,
+            strict_mode: false,
+            anomaly_action: AnomalyAction::default(),
+            schedule: Vec::new(),
+            retry: BackupRetryPolicy::default(),
+            snapshot: SnapshotOptions::default(),
+            pre_restore_hook: None,
+            retention: RetentionPolicy::default(),
+            pre_task_hooks: Vec::new(),
+            network: NetworkPolicy::default(),
+            power: PowerPolicy::default(),
+            chunk_hash: ChunkHashType::default(),
+            inventory_only: false,
+            system_manifest: false,
+            tenant: None,
+            delta_upload: false,
+            exclude_patterns: Vec::new(),
+            timeout: TaskTimeoutPolicy::default(),
+            partial_accept: PartialAcceptPolicy::default(),
+            source_io: SourceIoPolicy::default(),
+            spool: SpoolPolicy::default(),
+            restore_drill: RestoreDrillPolicy::default(),
+            sla: SlaPolicy::default(),
+            parallel_hash: ParallelHashConfig::default(),
+            reparse_points: ReparsePointPolicy::default(),
+            recursive: false,
+            cloud_placeholder: CloudPlaceholderPolicy::default(),
+            allow_unsigned_checkpoint: false,
+        }
+    }
+}
+
+impl BackupPlanOptions {
+    //按plan的配置裁剪source记录的文件属性
+    pub fn filter_file_meta(&self, item: &mut BackupItem) {
+        if self.preserve_ownership && self.preserve_xattrs {
+            return;
+        }
+        let file_meta = item.file_meta.as_ref().and_then(|s| ItemFileMeta::from_json_str(s));
+        if let Some(mut file_meta) = file_meta {
+            if !self.preserve_ownership {
+                file_meta.strip_ownership();
+            }
+            if !self.preserve_xattrs {
+                file_meta.strip_xattrs();
+            }
+            item.file_meta = Some(file_meta.to_json_string());
+        }
+    }
+}
+
+impl ToSql for BackupPlanOptions {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        let s = serde_json::to_string(self).map_err(|e| 
+            rusqlite::Error::ToSqlConversionFailure(Box::new(e))
+        )?;
+        Ok(s.into())
+    }
+}
+
+impl FromSql for BackupPlanOptions {
+    fn column_result(value: ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        //老版本的plan没有options列
+        if matches!(value, ValueRef::Null) {
+            return Ok(BackupPlanOptions::default());
+        }
+        let s = value.as_str()?;
+        let options: BackupPlanOptions = serde_json::from_str(s)
+            .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))?;
+        Ok(options)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BackupPlanConfig {
     pub source: BackupSource,
@@ -142,6 +304,7 @@ pub struct BackupPlanConfig {
     pub description: String,
     pub type_str: String,
     pub last_checkpoint_index: u64,
+    pub options: BackupPlanOptions,
 }
 
 impl BackupPlanConfig {
@@ -153,6 +316,7 @@ impl BackupPlanConfig {
             "description": self.description,
             "type_str": self.type_str,
             "last_checkpoint_index": self.last_checkpoint_index,
+            "options": self.options,
         });
         result
     }
@@ -167,6 +331,7 @@ impl BackupPlanConfig {
             description: description.to_string() ,
             type_str: "c2c".to_string(),
             last_checkpoint_index: 1024,
+            options: BackupPlanOptions::default(),
         }
     }
 
@@ -185,37 +350,49 @@ impl BackupPlanConfig {
 
 }
 
+//状态转换:
+//Paused(新建) -> Running/Queued, Queued -> Running/Paused/Cancelled,
+//Running -> Paused/Done/Failed/Cancelled, Paused -> Running/Queued/Cancelled
+//Done/Failed/Cancelled是结束状态,Failed的task由调度重新创建task重试,不会被resume
 #[derive(Debug, Clone, PartialEq)]
 pub enum TaskState {
     Running,
-    Pending,
+    Queued,//运行的task数量达到上限,在队列中等待运行
     Paused,
     Done,
     Failed,
+    Cancelled,//被用户取消,和运行出错的Failed区分
 }
 
 impl TaskState {
     pub fn to_string(&self) -> &str {
         match self {
             TaskState::Running => "RUNNING",
-            TaskState::Pending => "PENDING",
+            TaskState::Queued => "QUEUED",
             TaskState::Paused => "PAUSED",
             TaskState::Done => "DONE",
             TaskState::Failed => "FAILED",
+            TaskState::Cancelled => "CANCELLED",
         }
     }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskState::Done | TaskState::Failed | TaskState::Cancelled)
+    }
+
+    //可以被resume/force_run启动的状态
+    pub fn is_resumable(&self) -> bool {
+        matches!(self, TaskState::Paused | TaskState::Queued)
+    }
+
+    pub fn can_cancel(&self) -> bool {
+        !self.is_finished()
+    }
 }
 
 impl ToSql for TaskState {
     fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
-        let s = match self {
-            TaskState::Running => "RUNNING",
-            TaskState::Pending => "PENDING",
-            TaskState::Paused => "PAUSED",
-            TaskState::Done => "DONE",
-            TaskState::Failed => "FAILED",
-        };
-        Ok(s.into())
+        Ok(self.to_string().to_string().into())
     }
 }
 
@@ -223,10 +400,12 @@ impl FromSql for TaskState {
     fn column_result(value: ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
         value.as_str().map(|s| match s {
             "RUNNING" => TaskState::Running,
-            "PENDING" => TaskState::Pending,
+            //旧版本的PENDING表示等待运行,init_database时会迁移为QUEUED
+            "QUEUED" | "PENDING" => TaskState::Queued,
             "PAUSED" => TaskState::Paused,
             "DONE" => TaskState::Done,
             "FAILED" => TaskState::Failed,
+            "CANCELLED" => TaskState::Cancelled,
             _ => TaskState::Failed, // 默认失败状态
         })
     }
@@ -283,6 +462,8 @@ pub struct WorkTask {
     pub completed_item_count: u64,
     pub wait_transfer_item_count: u64,
     pub restore_config: Option<RestoreConfig>,
+    pub retry_attempt: u32,//失败后自动重试创建的task为第几次重试,手动/定时创建的task为0
+    pub runtime_stat: TaskRuntimeStat,
 }
 
 
@@ -304,6 +485,8 @@ impl WorkTask {
             completed_item_count: 0,
             wait_transfer_item_count: 0,
             restore_config: None,
+            retry_attempt: 0,
+            runtime_stat: TaskRuntimeStat::default(),
         }
     }
 
@@ -312,45 +495,57 @@ impl WorkTask {
     }
 
     pub fn to_json_value(&self) -> Value {
+        let mut result = json!({
+            "taskid": self.taskid,
+            "task_type": self.task_type.to_string(),
+            "owner_plan_id": self.owner_plan_id,
+            "checkpoint_id": self.checkpoint_id,
+            "total_size": self.total_size,
+            "completed_size": self.transferred_size(),
+            "state": self.state.to_string(),
+            "create_time": self.create_time,
+            "update_time": self.update_time,
+            "item_count": self.item_count,
+            "completed_item_count": self.completed_item_count,
+            "wait_transfer_item_count": self.wait_transfer_item_count,
+            "speed": self.runtime_stat.speed,
+            "eta": self.runtime_stat.eta_secs,
+            "prepare_time_ms": self.runtime_stat.prepare_time_ms,
+            "transfer_time_ms": self.runtime_stat.transfer_time_ms,
+            "last_item_id": self.runtime_stat.last_item_id,
+            "retry_attempt": self.retry_attempt,
+        });
+        if !self.runtime_stat.transferring_items.is_empty() {
+            let transferring_items: Vec<Value> = self.runtime_stat.transferring_items.iter()
+                .map(|(item_id, (offset, size))| json!({
+                    "item_id": item_id,
+                    "offset": offset,
+                    "size": size,
+                }))
+                .collect();
+            result["transferring_items"] = json!(transferring_items);
+        }
         if self.restore_config.is_some() {
             let restore_config = self.restore_config.as_ref().unwrap();
-            let restore_config_json = json!({
+            result["restore_config"] = json!({
                 "restore_location_url": restore_config.restore_location_url,
                 "is_clean_restore": restore_config.is_clean_restore,
             });
-            let result = json!({
-                "taskid": self.taskid,
-                "task_type": self.task_type.to_string(),
-                "owner_plan_id": self.owner_plan_id,
-                "checkpoint_id": self.checkpoint_id,
-                "total_size": self.total_size,
-                "completed_size": self.completed_size,
-                "state": self.state.to_string(),
-                "create_time": self.create_time,
-                "update_time": self.update_time,
-                "item_count": self.item_count,
-                "completed_item_count": self.completed_item_count,
-                "wait_transfer_item_count": self.wait_transfer_item_count,
-                "restore_config": restore_config_json,
-            });
-            return result;
-        } else {
-            let result = json!({
-                "taskid": self.taskid,
-                "task_type": self.task_type.to_string(),
-                "owner_plan_id": self.owner_plan_id,
-                "checkpoint_id": self.checkpoint_id,
-                "total_size": self.total_size,
-                "completed_size": self.completed_size,
-                "state": self.state.to_string(),
-                "create_time": self.create_time,
-                "update_time": self.update_time,
-                "item_count": self.item_count,
-                "completed_item_count": self.completed_item_count,
-                "wait_transfer_item_count": self.wait_transfer_item_count,
-            });
-            return result;
         }
+        result
+    }
+
+    pub fn now_ms() -> u64 {
+        chrono::Utc::now().timestamp_millis() as u64
+    }
+
+    //completed_size只包括已经完成的item,加上正在传输的item已经写入的部分,给UI显示进度
+    pub fn transferred_size(&self) -> u64 {
+        self.completed_size + self.runtime_stat.transferring_size()
+    }
+
+    pub fn update_progress_stat(&mut self) {
+        self.runtime_stat.on_progress(Self::now_ms(), self.transferred_size(), self.total_size);
     }
 }
 
@@ -371,11 +566,10 @@ impl BackupTaskDb {
 
     fn init_database(&self) -> Result<()> {
         let dir = std::path::Path::new(&self.db_path).parent()
-            .ok_or(BackupTaskError::DatabaseError(rusqlite::Error::InvalidPath(std::path::PathBuf::from(self.db_path.clone()))))?;
-        std::fs::create_dir_all(dir)
-            .map_err(|_| BackupTaskError::DatabaseError(rusqlite::Error::InvalidPath(std::path::PathBuf::from(self.db_path.clone()))))?;
+            .ok_or(BuckyBackupError::Failed(format!("invalid db path: {}", self.db_path)))?;
+        std::fs::create_dir_all(dir)?;
         
-        let conn = Connection::open(&self.db_path).map_err(BackupTaskError::DatabaseError)?;
+        let conn = Connection::open(&self.db_path)?;
         
         conn.execute(
             "CREATE TABLE IF NOT EXISTS work_tasks (
@@ -391,7 +585,8 @@ impl BackupTaskDb {
                 item_count INTEGER NOT NULL,
                 completed_item_count INTEGER NOT NULL,
                 wait_transfer_item_count INTEGER NOT NULL,
-                restore_config TEXT
+                restore_config TEXT,
+                retry_attempt INTEGER NOT NULL DEFAULT 0
             )",
             [],
         )?;
@@ -405,7 +600,14 @@ impl BackupTaskDb {
                 owner_plan TEXT NOT NULL,
                 checkpoint_hash TEXT,
                 checkpoint_index INTEGER NOT NULL,
-                create_time INTEGER NOT NULL
+                create_time INTEGER NOT NULL,
+                signature TEXT,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                pin_reason TEXT,
+                chunk_hash TEXT NOT NULL DEFAULT 'sha256',
+                inventory_only INTEGER NOT NULL DEFAULT 0,
+                failed_item_count INTEGER NOT NULL DEFAULT 0,
+                retry_of TEXT
             )",
             [],
         )?;
@@ -420,7 +622,8 @@ impl BackupTaskDb {
                 title TEXT NOT NULL,
                 description TEXT NOT NULL,
                 type_str TEXT NOT NULL,
-                last_checkpoint_index INTEGER NOT NULL
+                last_checkpoint_index INTEGER NOT NULL,
+                options TEXT
             )",
             [],
         )?;
@@ -438,6 +641,9 @@ impl BackupTaskDb {
                 create_time INTEGER NOT NULL,
                 progress TEXT,
                 diff_info TEXT,
+                pack_info TEXT,
+                file_meta TEXT,
+                fuzzy INTEGER NOT NULL DEFAULT 0,
                 PRIMARY KEY (item_id, checkpoint_id)
             )",
             [],
@@ -466,250 +672,1887 @@ impl BackupTaskDb {
                 size INTEGER NOT NULL,
                 last_modify_time INTEGER NOT NULL,
                 create_time INTEGER NOT NULL,
+                progress TEXT,
+                diff_info TEXT,
+                pack_info TEXT,
+                file_meta TEXT,
+                fuzzy INTEGER NOT NULL DEFAULT 0,
                 PRIMARY KEY (item_id, owner_taskid)
             )",
             [],
         )?;
 
-        Ok(())
-    }
+        //恢复时每个冲突文件的处理结果
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS restore_conflicts (
+                item_id TEXT NOT NULL,
+                owner_taskid TEXT NOT NULL,
+                restore_item_id TEXT,
+                action TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                PRIMARY KEY (item_id, owner_taskid)
+            )",
+            [],
+        )?;
 
-    pub fn load_task_by_id(&self, taskid: &str) -> Result<WorkTask> {
-        let conn = Connection::open(&self.db_path)?;
-        let mut stmt = conn.prepare(
-            "SELECT * FROM work_tasks WHERE taskid = ?"
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS pack_chunks (
+                pack_chunk_id TEXT NOT NULL,
+                checkpoint_id TEXT NOT NULL,
+                total_size INTEGER NOT NULL,
+                item_count INTEGER NOT NULL,
+                pack_index TEXT NOT NULL,
+                PRIMARY KEY (pack_chunk_id, checkpoint_id)
+            )",
+            [],
         )?;
-        
-        let task = stmt.query_row(params![taskid], |row| {
-            Ok(WorkTask {
-                taskid: row.get(0)?,
-                task_type: row.get(1)?,
-                owner_plan_id: row.get(2)?,
-                checkpoint_id: row.get(3)?,
-                total_size: row.get(4)?,
-                completed_size: row.get(5)?,
-                state: row.get(6)?,
-                create_time: row.get(7)?,
-                update_time: row.get(8)?,
-                item_count: row.get(9)?,
-                completed_item_count: row.get(10)?,
-                wait_transfer_item_count: row.get(11)?,
-                restore_config: row.get(12)?,
-            })
-        }).map_err(|_| BackupTaskError::TaskNotFound)?;
 
-        Ok(task)
-    }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS plan_baselines (
+                plan_id TEXT PRIMARY KEY,
+                baseline TEXT NOT NULL,
+                update_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn create_task(&self, task: &WorkTask) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
         conn.execute(
-            "INSERT INTO work_tasks VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
-            params![
-                task.taskid,
-                task.task_type,
-                task.owner_plan_id,
-                task.checkpoint_id,
-                task.total_size,
-                task.completed_size,
-                task.state,
-                task.create_time,
-                task.update_time,
-                task.item_count,
-                task.completed_item_count,
-                task.wait_transfer_item_count,
-                task.restore_config,
-            ],
+            "CREATE TABLE IF NOT EXISTS pending_operations (
+                op_id TEXT PRIMARY KEY,
+                operation TEXT NOT NULL,
+                requester TEXT NOT NULL,
+                approver TEXT,
+                state TEXT NOT NULL,
+                create_time INTEGER NOT NULL,
+                expire_time INTEGER NOT NULL
+            )",
+            [],
         )?;
-        Ok(())
-    }
 
-    pub fn update_task(&self, task: &WorkTask) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
-        let new_task_state;
-        if task.state == TaskState::Done || task.state == TaskState::Failed || task.state == TaskState::Pending {
-            new_task_state = task.state.clone();
-        } else {
-            new_task_state = TaskState::Paused;
-        }
-        let rows_affected = conn.execute(
-            "UPDATE work_tasks SET 
-                task_type = ?2,
-                owner_plan_id = ?3,
-                checkpoint_id = ?4,
-                total_size = ?5,
-                completed_size = ?6,
-                state = ?7,
-                update_time = ?8,
-                item_count = ?9,
-                completed_item_count = ?10,
-                wait_transfer_item_count = ?11
-            WHERE taskid = ?1",
-            params![
-                task.taskid,
-                task.task_type,
-                task.owner_plan_id,
-                task.checkpoint_id,
-                task.total_size,
-                task.completed_size,
-                new_task_state,
-                chrono::Utc::now().timestamp_millis() as u64,
-                task.item_count,
-                task.completed_item_count,
-                task.wait_transfer_item_count,
-            ],
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS credentials (
+                credential_id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                nonce TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                create_time INTEGER NOT NULL
+            )",
+            [],
         )?;
 
-        if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
-        }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS target_stats (
+                target_url TEXT PRIMARY KEY,
+                stats TEXT NOT NULL,
+                update_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dir_changes (
+                checkpoint_id TEXT NOT NULL,
+                plan_id TEXT NOT NULL,
+                dir TEXT NOT NULL,
+                changed_size INTEGER NOT NULL,
+                changed_item_count INTEGER NOT NULL,
+                deleted_item_count INTEGER NOT NULL,
+                create_time INTEGER NOT NULL,
+                PRIMARY KEY (checkpoint_id, dir)
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dedup_stats (
+                checkpoint_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                target_url TEXT NOT NULL,
+                logical_size INTEGER NOT NULL,
+                stored_size INTEGER NOT NULL,
+                create_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        //target上完成的checkpoint写入的chunk,计算去重统计时只需要查询这次checkpoint的chunk,不用扫描所有历史checkpoint
+        //老版本的数据库第一次创建这个表时从已有的checkpoint生成
+        let need_build_chunk_index = !Self::is_table_exist(&conn, "target_chunks")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS target_chunks (
+                target_url TEXT NOT NULL,
+                chunk_id TEXT NOT NULL,
+                PRIMARY KEY (target_url, chunk_id)
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS checkpoint_transfer_stats (
+                checkpoint_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                wall_time_ms INTEGER NOT NULL,
+                transfer_time_ms INTEGER NOT NULL,
+                transferred_size INTEGER NOT NULL,
+                avg_speed INTEGER NOT NULL,
+                peak_speed INTEGER NOT NULL,
+                retry_count INTEGER NOT NULL,
+                dedup_ratio REAL NOT NULL,
+                create_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        //link_chunk_id为空表示chunk数据,否则为chunk上的link
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS spool_entries (
+                entry_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                target_url TEXT NOT NULL,
+                spool_dir TEXT NOT NULL,
+                chunk_id TEXT NOT NULL,
+                link_chunk_id TEXT NOT NULL DEFAULT '',
+                checkpoint_id TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                retry_count INTEGER NOT NULL DEFAULT 0,
+                next_retry_time INTEGER NOT NULL,
+                last_error TEXT,
+                create_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS seed_exports (
+                checkpoint_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                target_url TEXT NOT NULL,
+                seed_dir TEXT NOT NULL,
+                chunk_count INTEGER NOT NULL,
+                total_size INTEGER NOT NULL,
+                export_time INTEGER NOT NULL,
+                import_confirm_time INTEGER
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS legal_holds (
+                checkpoint_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                holder TEXT NOT NULL,
+                hold_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                operator TEXT NOT NULL,
+                action TEXT NOT NULL,
+                object_id TEXT NOT NULL,
+                detail TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        //contents_index_state中有记录表示checkpoint的索引已经完整
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS contents_index (
+                checkpoint_id TEXT NOT NULL,
+                item_id TEXT NOT NULL,
+                item_type TEXT NOT NULL,
+                extension TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                size_bucket INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                PRIMARY KEY (checkpoint_id, item_id)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS contents_index_extension ON contents_index (checkpoint_id, extension)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS contents_index_mtime ON contents_index (checkpoint_id, mtime)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS contents_index_state (
+                checkpoint_id TEXT PRIMARY KEY,
+                item_count INTEGER NOT NULL,
+                complete_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS system_manifests (
+                checkpoint_id TEXT PRIMARY KEY,
+                manifest TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS storage_quotas (
+                scope TEXT NOT NULL,
+                scope_key TEXT NOT NULL,
+                max_bytes INTEGER NOT NULL,
+                PRIMARY KEY (scope, scope_key)
+            )",
+            [],
+        )?;
+
+        //used_by为使用这个token注册的agent DID,没有使用时为NULL
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS agent_enroll_tokens (
+                token_hash TEXT PRIMARY KEY,
+                create_time INTEGER NOT NULL,
+                expire_time INTEGER NOT NULL,
+                used_by TEXT
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS agents (
+                did TEXT PRIMARY KEY,
+                hostname TEXT NOT NULL,
+                address TEXT NOT NULL,
+                version TEXT NOT NULL,
+                enroll_time INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        //engine级别的配置,比如维护模式,value为json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS engine_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        //客户端提供的幂等key,重试的创建请求返回第一次创建的task
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS idempotency_keys (
+                idempotency_key TEXT PRIMARY KEY,
+                request TEXT NOT NULL,
+                task_id TEXT NOT NULL,
+                create_time INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        //task事件日志只追加:触发器拒绝修改和删除,删除task/plan后日志仍然保留
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS task_journal (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                task_id TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                from_state TEXT,
+                to_state TEXT NOT NULL,
+                cause TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_task_journal_task_id ON task_journal (task_id)", [])?;
+        conn.execute(
+            "CREATE TRIGGER IF NOT EXISTS task_journal_no_update BEFORE UPDATE ON task_journal
+             BEGIN SELECT RAISE(ABORT, 'task journal is append-only'); END",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TRIGGER IF NOT EXISTS task_journal_no_delete BEFORE DELETE ON task_journal
+             BEGIN SELECT RAISE(ABORT, 'task journal is append-only'); END",
+            [],
+        )?;
+
+        //恢复演练的结果,failures为RestoreDrillFailure数组的json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS restore_drills (
+                drill_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                checkpoint_id TEXT NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER NOT NULL,
+                sample_count INTEGER NOT NULL,
+                verified_count INTEGER NOT NULL,
+                verified_size INTEGER NOT NULL,
+                failures TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        //已经发布过SlaBreached事件的plan/目标,重新达标后删除;daemon重启后不会重复告警
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sla_breaches (
+                plan_id TEXT NOT NULL,
+                objective TEXT NOT NULL,
+                breach_time INTEGER NOT NULL,
+                PRIMARY KEY (plan_id, objective)
+            )",
+            [],
+        )?;
+
+        //老版本创建的数据库缺少的列
+        Self::ensure_column(&conn, "backup_items", "pack_info", "TEXT")?;
+        Self::ensure_column(&conn, "restore_items", "progress", "TEXT")?;
+        Self::ensure_column(&conn, "restore_items", "diff_info", "TEXT")?;
+        Self::ensure_column(&conn, "restore_items", "pack_info", "TEXT")?;
+        Self::ensure_column(&conn, "backup_items", "file_meta", "TEXT")?;
+        Self::ensure_column(&conn, "restore_items", "file_meta", "TEXT")?;
+        Self::ensure_column(&conn, "backup_items", "fuzzy", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "restore_items", "fuzzy", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "backup_plans", "options", "TEXT")?;
+        Self::ensure_column(&conn, "checkpoints", "signature", "TEXT")?;
+        Self::ensure_column(&conn, "checkpoints", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "checkpoints", "pin_reason", "TEXT")?;
+        Self::ensure_column(&conn, "checkpoints", "chunk_hash", "TEXT NOT NULL DEFAULT 'sha256'")?;
+        Self::ensure_column(&conn, "checkpoints", "inventory_only", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "checkpoints", "failed_item_count", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "checkpoints", "retry_of", "TEXT")?;
+        Self::ensure_column(&conn, "work_tasks", "retry_attempt", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "restore_drills", "checkpoint_size", "INTEGER NOT NULL DEFAULT 0")?;
+        //旧版本中取消的task保存为FAILED,无法区分,只迁移PENDING
+        conn.execute("UPDATE work_tasks SET state = 'QUEUED' WHERE state = 'PENDING'", [])?;
+        if need_build_chunk_index {
+            self.build_target_chunk_index()?;
+        }
+
+        Ok(())
+    }
+
+    fn is_table_exist(conn: &Connection, table: &str) -> Result<bool> {
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![table],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    fn build_target_chunk_index(&self) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT DISTINCT target_url FROM backup_plans")?;
+        let target_urls = stmt.query_map([], |row| row.get::<_, String>(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        for target_url in target_urls.iter() {
+            let chunk_ids = self.list_target_chunk_ids(target_url.as_str(), "")?;
+            info!("taskdb: build chunk index of target {}, {} chunks", target_url, chunk_ids.len());
+            self.add_target_chunks(target_url.as_str(), &chunk_ids)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_connection(&self) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+
+    fn ensure_column(conn: &Connection, table: &str, column: &str, column_def: &str) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let columns = stmt.query_map([], |row| row.get::<_, String>(1))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        if !columns.iter().any(|name| name == column) {
+            info!("taskdb: add column {}.{}", table, column);
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, column_def), [])?;
+        }
+        Ok(())
+    }
+
+    const WORK_TASK_COLUMNS: &'static str = "taskid, task_type, owner_plan_id, checkpoint_id, total_size, completed_size, state,
+        create_time, update_time, item_count, completed_item_count, wait_transfer_item_count, restore_config, retry_attempt";
+
+    fn work_task_from_row(row: &rusqlite::Row) -> SqlResult<WorkTask> {
+        Ok(WorkTask {
+            taskid: row.get(0)?,
+            task_type: row.get(1)?,
+            owner_plan_id: row.get(2)?,
+            checkpoint_id: row.get(3)?,
+            total_size: row.get(4)?,
+            completed_size: row.get(5)?,
+            state: row.get(6)?,
+            create_time: row.get(7)?,
+            update_time: row.get(8)?,
+            item_count: row.get(9)?,
+            completed_item_count: row.get(10)?,
+            wait_transfer_item_count: row.get(11)?,
+            restore_config: row.get(12)?,
+            retry_attempt: row.get(13)?,
+            runtime_stat: TaskRuntimeStat::default(),
+        })
+    }
+
+    pub fn load_task_by_id(&self, taskid: &str) -> Result<WorkTask> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            format!("SELECT {} FROM work_tasks WHERE taskid = ?", Self::WORK_TASK_COLUMNS).as_str()
+        )?;
+        
+        let task = stmt.query_row(params![taskid], Self::work_task_from_row).map_err(|err| match err {
+            rusqlite::Error::QueryReturnedNoRows => BuckyBackupError::NotFound(format!("task {}", taskid)),
+            err => BuckyBackupError::Database(err),
+        })?;
+
+        Ok(task)
+    }
+
+    pub fn create_task(&self, task: &WorkTask) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO work_tasks (taskid, task_type, owner_plan_id, checkpoint_id, total_size, completed_size, state,
+                create_time, update_time, item_count, completed_item_count, wait_transfer_item_count, restore_config, retry_attempt)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                task.taskid,
+                task.task_type,
+                task.owner_plan_id,
+                task.checkpoint_id,
+                task.total_size,
+                task.completed_size,
+                task.state,
+                task.create_time,
+                task.update_time,
+                task.item_count,
+                task.completed_item_count,
+                task.wait_transfer_item_count,
+                task.restore_config,
+                task.retry_attempt,
+            ],
+        )?;
+        Ok(())
+    }
+
+    //plan最近创建的备份task
+    pub fn load_last_backup_task(&self, plan_id: &str) -> Result<Option<WorkTask>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            format!("SELECT {} FROM work_tasks WHERE owner_plan_id = ?1 AND task_type = ?2 ORDER BY create_time DESC LIMIT 1",
+                Self::WORK_TASK_COLUMNS).as_str()
+        )?;
+        let mut tasks = stmt.query_map(params![plan_id, TaskType::Backup], Self::work_task_from_row)?
+            .collect::<SqlResult<Vec<WorkTask>>>()?;
+        Ok(tasks.pop())
+    }
+
+    pub fn update_task(&self, task: &WorkTask) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let new_task_state;
+        //Running的task在进程退出后不会继续运行,保存为Paused
+        if task.state == TaskState::Running {
+            new_task_state = TaskState::Paused;
+        } else {
+            new_task_state = task.state.clone();
+        }
+        let rows_affected = conn.execute(
+            "UPDATE work_tasks SET 
+                task_type = ?2,
+                owner_plan_id = ?3,
+                checkpoint_id = ?4,
+                total_size = ?5,
+                completed_size = ?6,
+                state = ?7,
+                update_time = ?8,
+                item_count = ?9,
+                completed_item_count = ?10,
+                wait_transfer_item_count = ?11
+            WHERE taskid = ?1",
+            params![
+                task.taskid,
+                task.task_type,
+                task.owner_plan_id,
+                task.checkpoint_id,
+                task.total_size,
+                task.completed_size,
+                new_task_state,
+                chrono::Utc::now().timestamp_millis() as u64,
+                task.item_count,
+                task.completed_item_count,
+                task.wait_transfer_item_count,
+            ],
+        )?;
+
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("task {}", task.taskid)));
+        }
+        Ok(())
+    }
+
+
+    //checkpoints表的列顺序和CREATE TABLE一致(新增的列都追加在最后)
+    fn checkpoint_from_row(row: &rusqlite::Row) -> SqlResult<BackupCheckPoint> {
+        Ok(BackupCheckPoint {
+            checkpoint_id: row.get(0)?,
+            depend_checkpoint_id: row.get(1)?,
+            prev_checkpoint_id: row.get(2)?,
+            state: row.get(3)?,
+            owner_plan: row.get(4)?,
+            checkpoint_hash: row.get(5)?,
+            checkpoint_index: row.get(6)?,
+            create_time: row.get(7)?,
+            signature: row.get(8)?,
+            pinned: row.get(9)?,
+            pin_reason: row.get(10)?,
+            chunk_hash: ChunkHashType::from_str(row.get::<_, String>(11)?.as_str()),
+            inventory_only: row.get(12)?,
+            failed_item_count: row.get(13)?,
+            retry_of: row.get(14)?,
+        })
+    }
+
+    pub fn load_last_checkpoint(&self, taskid: &str, count:Option<u32>) -> Result<BackupCheckPoint> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT * FROM checkpoints WHERE taskid = ?1 ORDER BY create_time DESC LIMIT ?2")?;
+        let mut rows = stmt.query(params![taskid, count.unwrap_or(1)])?;
+
+        if let Some(row) = rows.next()? {
+            let checkpoint = Self::checkpoint_from_row(row)?;
+            Ok(checkpoint)
+        } else {
+            Err(BuckyBackupError::NotFound(format!("checkpoint of task {}", taskid)))
+        }
+    }
+
+    //同一个plan中在checkpoint_index之前最近一个完成的checkpoint,数据还在spool中的也可以作为比较的基准
+    pub fn load_prev_done_checkpoint(&self, plan_id: &str, checkpoint_index: u64) -> Result<Option<BackupCheckPoint>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE owner_plan = ?1 AND checkpoint_index < ?2 AND state IN ('DONE', 'LOCAL_DONE') 
+             ORDER BY checkpoint_index DESC LIMIT 1"
+        )?;
+        let mut rows = stmt.query(params![plan_id, checkpoint_index])?;
+        if let Some(row) = rows.next()? {
+            let checkpoint = Self::checkpoint_from_row(row)?;
+            Ok(Some(checkpoint))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //plan中所有完成的checkpoint,按checkpoint_index从旧到新排列
+    pub fn list_done_checkpoints(&self, plan_id: &str) -> Result<Vec<BackupCheckPoint>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE owner_plan = ?1 AND state = 'DONE' ORDER BY checkpoint_index"
+        )?;
+        let checkpoints = stmt.query_map(params![plan_id], Self::checkpoint_from_row)?
+            .collect::<SqlResult<Vec<BackupCheckPoint>>>()?;
+        Ok(checkpoints)
+    }
+
+    //plan中所有的checkpoint(包括未完成的),按checkpoint_index从旧到新排列
+    pub fn list_checkpoints(&self, plan_id: &str) -> Result<Vec<BackupCheckPoint>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE owner_plan = ?1 ORDER BY checkpoint_index"
+        )?;
+        let checkpoints = stmt.query_map(params![plan_id], Self::checkpoint_from_row)?
+            .collect::<SqlResult<Vec<BackupCheckPoint>>>()?;
+        Ok(checkpoints)
+    }
+
+    pub fn load_checkpoint_by_id(&self, checkpoint_id: &str) -> Result<BackupCheckPoint> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE checkpoint_id = ?"
+        )?;
+        
+        let checkpoint = stmt.query_row(params![checkpoint_id], |row| {
+            Self::checkpoint_from_row(row)
+        }).map_err(|err| match err {
+            rusqlite::Error::QueryReturnedNoRows => BuckyBackupError::NotFound(format!("checkpoint {}", checkpoint_id)),
+            err => BuckyBackupError::Database(err),
+        })?;
+
+        Ok(checkpoint)
+    }
+
+
+    pub fn cancel_task(&self, taskid: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute(
+            "UPDATE work_tasks SET state = ? WHERE taskid = ?",
+            params![
+                TaskState::Cancelled,
+                taskid
+            ],
+        )?;
+
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("task {}", taskid)));
+        }
+        Ok(())
+    }
+
+    pub fn save_backup_item(&self, checkpoint_id: &str, item: &BackupItem) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO backup_items (
+                item_id,
+                checkpoint_id,
+                item_type,
+                chunk_id,
+                quick_hash,
+                state,
+                size,
+                last_modify_time,
+                create_time,
+                progress,
+                diff_info,
+                pack_info,
+                file_meta,
+                fuzzy
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                item.item_id,
+                checkpoint_id,
+                item.item_type,
+                item.chunk_id,
+                item.quick_hash,
+                item.state,
+                item.size,
+                item.last_modify_time,
+                item.create_time,
+                item.progress,
+                item.diff_info.clone().unwrap_or("".to_string()),
+                item.pack_info,
+                item.file_meta,
+                item.is_fuzzy,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_item_list_to_checkpoint(&self, checkpoint_id: &str, item_list: &Vec<BackupItem>) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+
+        // optimize: per checkpoint per table?
+        // tx.execute(
+        //     "CREATE TABLE IF NOT EXISTS {}_backup_items (
+        //         item_id TEXT NOT NULL,
+        //         checkpoint_id TEXT NOT NULL,
+        //         item_type TEXT NOT NULL,
+        //         chunk_id TEXT,
+        //         quick_hash TEXT,
+        //         state TEXT NOT NULL,
+        //         size INTEGER NOT NULL,
+        //         last_modify_time INTEGER NOT NULL,
+        //         create_time INTEGER NOT NULL,
+        //         PRIMARY KEY (item_id, checkpoint_id)
+        //     )",
+        //     [],
+        // )?;
+
+        for item in item_list {
+            tx.execute(
+                "INSERT INTO backup_items (
+                    item_id,
+                    checkpoint_id,
+                    item_type,
+                    chunk_id,
+                    quick_hash,
+                    state,
+                    size,
+                    last_modify_time,
+                    create_time,
+                    progress,
+                    diff_info,
+                    pack_info,
+                    file_meta,
+                    fuzzy
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    item.item_id,
+                    checkpoint_id,
+                    item.item_type,
+                    item.chunk_id,
+                    item.quick_hash,
+                    item.state,
+                    item.size,
+                    item.last_modify_time,
+                    item.create_time,
+                    item.progress,
+                    item.diff_info.clone().unwrap_or("".to_string()),
+                    item.pack_info,
+                    item.file_meta,
+                    item.is_fuzzy,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        info!("taskdb.save_item_list_to_checkpoint: {} {} items", checkpoint_id, item_list.len());
+        Ok(())
+    }
+
+    pub fn save_pack_index(&self, checkpoint_id: &str, pack_index: &PackIndex) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let pack_index_str = serde_json::to_string(pack_index).unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO pack_chunks (pack_chunk_id, checkpoint_id, total_size, item_count, pack_index) 
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pack_index.pack_chunk_id,
+                checkpoint_id,
+                pack_index.total_size,
+                pack_index.items.len() as u64,
+                pack_index_str,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_pack_index(&self, checkpoint_id: &str, pack_chunk_id: &str) -> Result<Option<PackIndex>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT pack_index FROM pack_chunks WHERE checkpoint_id = ? AND pack_chunk_id = ?"
+        )?;
+        let mut rows = stmt.query(params![checkpoint_id, pack_chunk_id])?;
+        if let Some(row) = rows.next()? {
+            let pack_index_str: String = row.get(0)?;
+            let pack_index = serde_json::from_str::<PackIndex>(pack_index_str.as_str()).ok();
+            Ok(pack_index)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save_system_manifest(&self, checkpoint_id: &str, manifest: &SystemRestoreManifest) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO system_manifests (checkpoint_id, manifest) VALUES (?1, ?2)",
+            params![checkpoint_id, serde_json::to_string(manifest).unwrap()],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_system_manifest(&self, checkpoint_id: &str) -> Result<Option<SystemRestoreManifest>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT manifest FROM system_manifests WHERE checkpoint_id = ?"
+        )?;
+        let mut rows = stmt.query(params![checkpoint_id])?;
+        if let Some(row) = rows.next()? {
+            let manifest_str: String = row.get(0)?;
+            Ok(serde_json::from_str::<SystemRestoreManifest>(manifest_str.as_str()).ok())
+        } else {
+            Ok(None)
+        }
+    }
+
+    //value为None时删除配置
+    pub fn set_engine_setting(&self, key: &str, value: Option<&str>) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        match value {
+            Some(value) => conn.execute(
+                "INSERT OR REPLACE INTO engine_settings (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?,
+            None => conn.execute(
+                "DELETE FROM engine_settings WHERE key = ?1",
+                params![key],
+            )?,
+        };
+        Ok(())
+    }
+
+    pub fn get_engine_setting(&self, key: &str) -> Result<Option<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT value FROM engine_settings WHERE key = ?"
+        )?;
+        let mut rows = stmt.query(params![key])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //返回(request, task_id)
+    pub fn load_idempotency_key(&self, idempotency_key: &str) -> Result<Option<(String, String)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT request, task_id FROM idempotency_keys WHERE idempotency_key = ?"
+        )?;
+        let mut rows = stmt.query(params![idempotency_key])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some((row.get(0)?, row.get(1)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save_idempotency_key(&self, idempotency_key: &str, request: &str, task_id: &str, create_time: u64) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO idempotency_keys (idempotency_key, request, task_id, create_time) VALUES (?1, ?2, ?3, ?4)",
+            params![idempotency_key, request, task_id, create_time],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_expired_idempotency_keys(&self, before_time: u64) -> Result<usize> {
+        let conn = Connection::open(&self.db_path)?;
+        let count = conn.execute(
+            "DELETE FROM idempotency_keys WHERE create_time < ?1",
+            params![before_time],
+        )?;
+        Ok(count)
+    }
+
+    //max_bytes为None时删除配额
+    pub fn set_storage_quota(&self, scope: QuotaScope, scope_key: &str, max_bytes: Option<u64>) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        match max_bytes {
+            Some(max_bytes) => conn.execute(
+                "INSERT OR REPLACE INTO storage_quotas (scope, scope_key, max_bytes) VALUES (?1, ?2, ?3)",
+                params![scope.as_str(), scope_key, max_bytes],
+            )?,
+            None => conn.execute(
+                "DELETE FROM storage_quotas WHERE scope = ?1 AND scope_key = ?2",
+                params![scope.as_str(), scope_key],
+            )?,
+        };
+        Ok(())
+    }
+
+    pub fn load_storage_quotas(&self) -> Result<Vec<StorageQuota>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT scope, scope_key, max_bytes FROM storage_quotas"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, u64>(2)?))
+        })?
+        .collect::<SqlResult<Vec<(String, String, u64)>>>()?;
+        Ok(rows.into_iter()
+            .filter_map(|(scope, scope_key, max_bytes)| {
+                QuotaScope::from_str(scope.as_str()).map(|scope| StorageQuota { scope, scope_key, max_bytes })
+            })
+            .collect())
+    }
+
+    //还存在的checkpoint在target上的存储大小,返回(plan_id, target_url, stored_size)
+    pub fn load_checkpoint_stored_sizes(&self) -> Result<Vec<(String, String, u64)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT d.plan_id, d.target_url, d.stored_size FROM dedup_stats d
+             JOIN checkpoints c ON d.checkpoint_id = c.checkpoint_id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, u64>(2)?))
+        })?
+        .collect::<SqlResult<Vec<(String, String, u64)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn create_agent_enroll_token(&self, token_hash: &str, create_time: u64, expire_time: u64) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO agent_enroll_tokens (token_hash, create_time, expire_time) VALUES (?1, ?2, ?3)",
+            params![token_hash, create_time, expire_time],
+        )?;
+        Ok(())
+    }
+
+    //token没有使用过并且没有过期时标记为被did使用,返回是否成功,保证一个token只能注册一次
+    pub fn consume_agent_enroll_token(&self, token_hash: &str, did: &str, now: u64) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let changed = conn.execute(
+            "UPDATE agent_enroll_tokens SET used_by = ?1 WHERE token_hash = ?2 AND used_by IS NULL AND expire_time > ?3",
+            params![did, token_hash, now],
+        )?;
+        Ok(changed == 1)
+    }
+
+    //同一个DID重新注册时覆盖之前的记录
+    pub fn save_agent(&self, agent: &AgentRecord) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO agents (did, hostname, address, version, enroll_time, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![agent.did, agent.hostname, agent.address, agent.version, agent.enroll_time, agent.last_seen],
+        )?;
+        Ok(())
+    }
+
+    //返回false表示did没有注册
+    pub fn update_agent_heartbeat(&self, did: &str, version: &str, address: &str, last_seen: u64) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let changed = conn.execute(
+            "UPDATE agents SET version = ?1, address = ?2, last_seen = ?3 WHERE did = ?4",
+            params![version, address, last_seen, did],
+        )?;
+        Ok(changed == 1)
+    }
+
+    pub fn list_agents(&self) -> Result<Vec<AgentRecord>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT did, hostname, address, version, enroll_time, last_seen FROM agents ORDER BY enroll_time"
+        )?;
+        let agents = stmt.query_map([], |row| {
+            Ok(AgentRecord {
+                did: row.get(0)?,
+                hostname: row.get(1)?,
+                address: row.get(2)?,
+                version: row.get(3)?,
+                enroll_time: row.get(4)?,
+                last_seen: row.get(5)?,
+            })
+        })?
+        .collect::<SqlResult<Vec<AgentRecord>>>()?;
+        Ok(agents)
+    }
+
+    pub fn load_plan_baseline(&self, plan_id: &str) -> Result<Option<PlanBaseline>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT baseline FROM plan_baselines WHERE plan_id = ?"
+        )?;
+        let mut rows = stmt.query(params![plan_id])?;
+        if let Some(row) = rows.next()? {
+            let baseline_str: String = row.get(0)?;
+            Ok(serde_json::from_str::<PlanBaseline>(baseline_str.as_str()).ok())
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save_plan_baseline(&self, plan_id: &str, baseline: &PlanBaseline) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let baseline_str = serde_json::to_string(baseline).unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO plan_baselines (plan_id, baseline, update_time) VALUES (?1, ?2, ?3)",
+            params![plan_id, baseline_str, WorkTask::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    //target_key是去掉密钥和参数的target url(get_target_stats_key),列名沿用老版本的target_url
+    pub fn save_target_stats(&self, target_key: &str, stats: &TargetStats) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let stats_str = serde_json::to_string(stats).unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO target_stats (target_url, stats, update_time) VALUES (?1, ?2, ?3)",
+            params![target_key, stats_str, WorkTask::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_target_stats(&self, target_key: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM target_stats WHERE target_url = ?1", params![target_key])?;
+        Ok(())
+    }
+
+    pub fn load_all_target_stats(&self) -> Result<Vec<(String, TargetStats)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT target_url, stats FROM target_stats")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<SqlResult<Vec<(String, String)>>>()?;
+        let result = rows.into_iter()
+            .filter_map(|(target_url, stats_str)| {
+                serde_json::from_str::<TargetStats>(stats_str.as_str()).ok().map(|stats| (target_url, stats))
+            })
+            .collect();
+        Ok(result)
+    }
+
+    //checkpoint删除后仍然保留变化记录,热度图需要覆盖更长的时间
+    pub fn save_dir_changes(&self, plan_id: &str, checkpoint_id: &str, create_time: u64, changes: &[DirChangeStat]) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM dir_changes WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        for change in changes.iter() {
+            tx.execute(
+                "INSERT INTO dir_changes (checkpoint_id, plan_id, dir, changed_size, changed_item_count, deleted_item_count, create_time)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![checkpoint_id, plan_id, change.dir, change.changed_size, change.changed_item_count,
+                    change.deleted_item_count, create_time],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    //返回(checkpoint_id, 目录变化),只包含create_time >= since_time的记录
+    pub fn load_dir_changes(&self, plan_id: &str, since_time: u64) -> Result<Vec<(String, DirChangeStat)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, dir, changed_size, changed_item_count, deleted_item_count FROM dir_changes
+             WHERE plan_id = ?1 AND create_time >= ?2"
+        )?;
+        let rows = stmt.query_map(params![plan_id, since_time], |row| {
+            Ok((row.get::<_, String>(0)?, DirChangeStat {
+                dir: row.get(1)?,
+                changed_size: row.get(2)?,
+                changed_item_count: row.get(3)?,
+                deleted_item_count: row.get(4)?,
+            }))
+        })?
+        .collect::<SqlResult<Vec<(String, DirChangeStat)>>>()?;
+        Ok(rows)
+    }
+
+    //target上除exclude_checkpoint_id之外的完成的checkpoint引用的chunk_id/quick_hash/pack_chunk_id
+    pub fn list_target_chunk_ids(&self, target_url: &str, exclude_checkpoint_id: &str) -> Result<HashSet<String>> {
+        self.query_chunk_ids(
+            "p.target_url = ?1 AND c.checkpoint_id != ?2 AND c.state IN ('DONE', 'LOCAL_DONE', 'WAIT_CONFIRM')",
+            params![target_url, exclude_checkpoint_id])
+    }
+
+    //target上所有checkpoint(包括没有完成的)引用的chunk,删除target上的chunk前用它判断是否还有引用
+    pub fn list_target_referenced_chunk_ids(&self, target_url: &str) -> Result<HashSet<String>> {
+        self.query_chunk_ids("p.target_url = ?1", params![target_url])
+    }
+
+    //checkpoint自己在target上保存的chunk(打包的item只算pack chunk)
+    pub fn list_checkpoint_chunk_ids(&self, checkpoint_id: &str) -> Result<HashSet<String>> {
+        self.query_chunk_ids("c.checkpoint_id = ?1", params![checkpoint_id])
+    }
+
+    //chunk_ids中已经在target的chunk索引中的chunk
+    pub fn filter_indexed_target_chunks(&self, target_url: &str, chunk_ids: &HashSet<String>) -> Result<HashSet<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT 1 FROM target_chunks WHERE target_url = ?1 AND chunk_id = ?2")?;
+        let mut indexed_chunk_ids = HashSet::new();
+        for chunk_id in chunk_ids.iter() {
+            if stmt.exists(params![target_url, chunk_id])? {
+                indexed_chunk_ids.insert(chunk_id.clone());
+            }
+        }
+        Ok(indexed_chunk_ids)
+    }
+
+    pub fn add_target_chunks(&self, target_url: &str, chunk_ids: &HashSet<String>) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT OR IGNORE INTO target_chunks (target_url, chunk_id) VALUES (?1, ?2)")?;
+            for chunk_id in chunk_ids.iter() {
+                stmt.execute(params![target_url, chunk_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    //chunk已经从target上删除
+    pub fn remove_target_chunks(&self, target_url: &str, chunk_ids: &HashSet<String>) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("DELETE FROM target_chunks WHERE target_url = ?1 AND chunk_id = ?2")?;
+            for chunk_id in chunk_ids.iter() {
+                stmt.execute(params![target_url, chunk_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    //condition中checkpoints表的别名为c,backup_plans表的别名为p
+    fn query_chunk_ids(&self, condition: &str, query_params: &[&dyn ToSql]) -> Result<HashSet<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(format!(
+            "SELECT bi.chunk_id, bi.quick_hash, bi.diff_info FROM backup_items bi
+             JOIN checkpoints c ON bi.checkpoint_id = c.checkpoint_id
+             JOIN backup_plans p ON c.owner_plan = p.plan_id
+             WHERE {} AND (bi.pack_info IS NULL OR bi.pack_info = '')", condition).as_str()
+        )?;
+        let rows = stmt.query_map(query_params, |row| {
+            Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?))
+        })?
+        .collect::<SqlResult<Vec<(Option<String>, Option<String>, Option<String>)>>>()?;
+        let mut chunk_ids: HashSet<String> = HashSet::new();
+        for (chunk_id, quick_hash, diff_info) in rows.into_iter() {
+            //增量上传的item在target上保存的是chunk表中的chunk,不是整个文件
+            match diff_info.as_deref().and_then(FileDiffInfo::from_json_str) {
+                Some(diff_info) => chunk_ids.extend(diff_info.chunk_ids()),
+                None => chunk_ids.extend(chunk_id.into_iter().chain(quick_hash.into_iter())),
+            }
+        }
+
+        let mut stmt = conn.prepare(format!(
+            "SELECT pc.pack_chunk_id FROM pack_chunks pc
+             JOIN checkpoints c ON pc.checkpoint_id = c.checkpoint_id
+             JOIN backup_plans p ON c.owner_plan = p.plan_id
+             WHERE {}", condition).as_str()
+        )?;
+        let pack_chunk_ids = stmt.query_map(query_params, |row| row.get::<_, String>(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        chunk_ids.extend(pack_chunk_ids);
+        Ok(chunk_ids)
+    }
+
+    //checkpoint删除后保留统计记录,汇总的是这个plan/target历史上节省的空间
+    pub fn save_dedup_stat(&self, checkpoint_id: &str, plan_id: &str, target_url: &str, create_time: u64, stat: &DedupStat) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO dedup_stats (checkpoint_id, plan_id, target_url, logical_size, stored_size, create_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![checkpoint_id, plan_id, target_url, stat.logical_size, stat.stored_size, create_time],
+        )?;
+        Ok(())
+    }
+
+    //返回(plan_id, target_url, 单个checkpoint的统计)
+    pub fn load_all_dedup_stats(&self) -> Result<Vec<(String, String, DedupStat)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT plan_id, target_url, logical_size, stored_size FROM dedup_stats"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, DedupStat {
+                logical_size: row.get(2)?,
+                stored_size: row.get(3)?,
+                checkpoint_count: 1,
+            }))
+        })?
+        .collect::<SqlResult<Vec<(String, String, DedupStat)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn save_checkpoint_transfer_stat(&self, checkpoint_id: &str, plan_id: &str, create_time: u64, stat: &CheckpointTransferStat) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO checkpoint_transfer_stats (checkpoint_id, plan_id, wall_time_ms, transfer_time_ms, transferred_size,
+                avg_speed, peak_speed, retry_count, dedup_ratio, create_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![checkpoint_id, plan_id, stat.wall_time_ms, stat.transfer_time_ms, stat.transferred_size,
+                stat.avg_speed, stat.peak_speed, stat.retry_count, stat.dedup_ratio, create_time],
+        )?;
+        Ok(())
+    }
+
+    //返回checkpoint_id -> 传输统计
+    pub fn load_checkpoint_transfer_stats(&self, plan_id: &str) -> Result<HashMap<String, CheckpointTransferStat>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, wall_time_ms, transfer_time_ms, transferred_size, avg_speed, peak_speed, retry_count, dedup_ratio
+             FROM checkpoint_transfer_stats WHERE plan_id = ?1"
+        )?;
+        let rows = stmt.query_map(params![plan_id], |row| {
+            Ok((row.get::<_, String>(0)?, CheckpointTransferStat {
+                wall_time_ms: row.get(1)?,
+                transfer_time_ms: row.get(2)?,
+                transferred_size: row.get(3)?,
+                avg_speed: row.get(4)?,
+                peak_speed: row.get(5)?,
+                retry_count: row.get(6)?,
+                dedup_ratio: row.get(7)?,
+            }))
+        })?
+        .collect::<SqlResult<HashMap<String, CheckpointTransferStat>>>()?;
+        Ok(rows)
+    }
+
+    pub fn add_spool_entry(&self, entry: &SpoolEntry) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO spool_entries (target_url, spool_dir, chunk_id, link_chunk_id, checkpoint_id, size, retry_count, next_retry_time, last_error, create_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![entry.target_url, entry.spool_dir, entry.chunk_id, entry.link_chunk_id.as_deref().unwrap_or(""), entry.checkpoint_id,
+                entry.size, entry.retry_count, entry.next_retry_time, entry.last_error, entry.create_time],
+        )?;
+        Ok(())
+    }
+
+    //按写入顺序返回已经到重试时间的记录,数据总是在它的link之前
+    pub fn load_due_spool_entries(&self, now_ms: u64, limit: u32) -> Result<Vec<SpoolEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT entry_id, target_url, spool_dir, chunk_id, link_chunk_id, checkpoint_id, size, retry_count, next_retry_time, last_error, create_time
+             FROM spool_entries WHERE next_retry_time <= ?1 ORDER BY entry_id LIMIT ?2"
+        )?;
+        let rows = stmt.query_map(params![now_ms, limit], Self::spool_entry_from_row)?
+            .collect::<SqlResult<Vec<SpoolEntry>>>()?;
+        Ok(rows)
+    }
+
+    fn spool_entry_from_row(row: &rusqlite::Row) -> SqlResult<SpoolEntry> {
+        let link_chunk_id: String = row.get(4)?;
+        Ok(SpoolEntry {
+            entry_id: row.get(0)?,
+            target_url: row.get(1)?,
+            spool_dir: row.get(2)?,
+            chunk_id: row.get(3)?,
+            link_chunk_id: if link_chunk_id.is_empty() { None } else { Some(link_chunk_id) },
+            checkpoint_id: row.get(5)?,
+            size: row.get(6)?,
+            retry_count: row.get(7)?,
+            next_retry_time: row.get(8)?,
+            last_error: row.get(9)?,
+            create_time: row.get(10)?,
+        })
+    }
+
+    pub fn remove_spool_entry(&self, entry_id: i64) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM spool_entries WHERE entry_id = ?", params![entry_id])?;
+        Ok(())
+    }
+
+    pub fn update_spool_entry_retry(&self, entry_id: i64, retry_count: u32, next_retry_time: u64, last_error: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "UPDATE spool_entries SET retry_count = ?1, next_retry_time = ?2, last_error = ?3 WHERE entry_id = ?4",
+            params![retry_count, next_retry_time, last_error, entry_id],
+        )?;
+        Ok(())
+    }
+
+    //chunk还有多少待上传的记录(数据和link),为0时可以删除spool中的文件
+    pub fn count_spool_entries_by_chunk(&self, target_url: &str, chunk_id: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let count: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM spool_entries WHERE target_url = ?1 AND chunk_id = ?2",
+            params![target_url, chunk_id],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    //link_chunk_id -> spool中保存数据的chunk
+    pub fn load_spool_link_source(&self, target_url: &str, link_chunk_id: &str) -> Result<Option<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT chunk_id FROM spool_entries WHERE target_url = ?1 AND link_chunk_id = ?2 LIMIT 1"
+        )?;
+        let mut rows = stmt.query(params![target_url, link_chunk_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //spool中的chunk -> 还没有上传的link
+    pub fn load_spool_link_target(&self, target_url: &str, chunk_id: &str) -> Result<Option<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT link_chunk_id FROM spool_entries WHERE target_url = ?1 AND chunk_id = ?2 AND link_chunk_id != '' LIMIT 1"
+        )?;
+        let mut rows = stmt.query(params![target_url, chunk_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn count_spool_entries(&self, target_url: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let count: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM spool_entries WHERE target_url = ?1",
+            params![target_url],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    //checkpoint的数据已经导出为离线种子,uploader不再上传这些记录
+    pub fn hold_checkpoint_spool_entries(&self, checkpoint_id: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let count = conn.execute(
+            "UPDATE spool_entries SET next_retry_time = ?1, last_error = 'seeded' WHERE checkpoint_id = ?2",
+            params![SPOOL_HOLD_RETRY_TIME, checkpoint_id],
+        )?;
+        Ok(count as u64)
+    }
+
+    //取消种子导出后,被hold的记录重新由uploader上传
+    pub fn release_checkpoint_spool_entries(&self, checkpoint_id: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let count = conn.execute(
+            "UPDATE spool_entries SET next_retry_time = 0, last_error = NULL WHERE checkpoint_id = ?1 AND next_retry_time = ?2",
+            params![checkpoint_id, SPOOL_HOLD_RETRY_TIME],
+        )?;
+        Ok(count as u64)
+    }
+
+    pub fn load_checkpoint_spool_entries(&self, checkpoint_id: &str) -> Result<Vec<SpoolEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT entry_id, target_url, spool_dir, chunk_id, link_chunk_id, checkpoint_id, size, retry_count, next_retry_time, last_error, create_time
+             FROM spool_entries WHERE checkpoint_id = ?1 ORDER BY entry_id"
+        )?;
+        let rows = stmt.query_map(params![checkpoint_id], Self::spool_entry_from_row)?
+            .collect::<SqlResult<Vec<SpoolEntry>>>()?;
+        Ok(rows)
+    }
+
+    //checkpoint的数据还有多少条记录只在spool中:它自己写入的,以及它去重时引用的其它checkpoint写入的chunk
+    pub fn count_checkpoint_pending_spool_entries(&self, checkpoint_id: &str, target_url: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let count: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM spool_entries WHERE checkpoint_id = ?1
+                OR (target_url = ?2 AND chunk_id IN (
+                    SELECT chunk_id FROM backup_items WHERE checkpoint_id = ?1 AND chunk_id IS NOT NULL
+                    UNION SELECT pack_chunk_id FROM pack_chunks WHERE checkpoint_id = ?1))",
+            params![checkpoint_id, target_url],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    pub fn list_local_done_checkpoints(&self) -> Result<Vec<BackupCheckPoint>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE state = 'LOCAL_DONE' ORDER BY owner_plan, checkpoint_index"
+        )?;
+        let checkpoints = stmt.query_map([], Self::checkpoint_from_row)?
+            .collect::<SqlResult<Vec<BackupCheckPoint>>>()?;
+        Ok(checkpoints)
+    }
+
+    //返回checkpoint还没有上传的(记录数, 大小)
+    pub fn get_checkpoint_spool_stat(&self, checkpoint_id: &str) -> Result<(u64, u64)> {
+        let conn = Connection::open(&self.db_path)?;
+        let stat = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM spool_entries WHERE checkpoint_id = ?1",
+            params![checkpoint_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(stat)
+    }
+
+    pub fn get_spool_used_size(&self, target_url: &str) -> Result<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let size: u64 = conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM spool_entries WHERE target_url = ?1",
+            params![target_url],
+            |row| row.get(0),
+        )?;
+        Ok(size)
+    }
+
+    //返回每个target的(待上传记录数, 待上传大小, 失败中的记录数)
+    pub fn load_spool_stats(&self) -> Result<Vec<(String, u64, u64, u64)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT target_url, COUNT(*), COALESCE(SUM(size), 0), SUM(CASE WHEN retry_count > 0 THEN 1 ELSE 0 END)
+             FROM spool_entries GROUP BY target_url"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<SqlResult<Vec<(String, u64, u64, u64)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn save_seed_export(&self, record: &SeedExportRecord) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO seed_exports (checkpoint_id, plan_id, target_url, seed_dir, chunk_count, total_size, export_time, import_confirm_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![record.checkpoint_id, record.plan_id, record.target_url, record.seed_dir,
+                record.chunk_count, record.total_size, record.export_time, record.import_confirm_time],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_seed_export(&self, checkpoint_id: &str) -> Result<Option<SeedExportRecord>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, plan_id, target_url, seed_dir, chunk_count, total_size, export_time, import_confirm_time
+             FROM seed_exports WHERE checkpoint_id = ?"
+        )?;
+        let mut rows = stmt.query(params![checkpoint_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(Self::seed_export_from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //plan_id为None时返回所有plan的导出
+    pub fn delete_seed_export(&self, checkpoint_id: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM seed_exports WHERE checkpoint_id = ?1", params![checkpoint_id])?;
+        Ok(())
+    }
+
+    pub fn list_seed_exports(&self, plan_id: Option<&str>) -> Result<Vec<SeedExportRecord>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, plan_id, target_url, seed_dir, chunk_count, total_size, export_time, import_confirm_time
+             FROM seed_exports WHERE ?1 IS NULL OR plan_id = ?1 ORDER BY export_time"
+        )?;
+        let rows = stmt.query_map(params![plan_id], Self::seed_export_from_row)?
+            .collect::<SqlResult<Vec<SeedExportRecord>>>()?;
+        Ok(rows)
+    }
+
+    fn seed_export_from_row(row: &rusqlite::Row) -> SqlResult<SeedExportRecord> {
+        Ok(SeedExportRecord {
+            checkpoint_id: row.get(0)?,
+            plan_id: row.get(1)?,
+            target_url: row.get(2)?,
+            seed_dir: row.get(3)?,
+            chunk_count: row.get(4)?,
+            total_size: row.get(5)?,
+            export_time: row.get(6)?,
+            import_confirm_time: row.get(7)?,
+        })
+    }
+
+    pub fn save_restore_drill(&self, record: &RestoreDrillRecord) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let failures = serde_json::to_string(&record.failures).unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO restore_drills (drill_id, plan_id, checkpoint_id, start_time, end_time, sample_count, verified_count, verified_size, failures, checkpoint_size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![record.drill_id, record.plan_id, record.checkpoint_id, record.start_time, record.end_time,
+                record.sample_count, record.verified_count, record.verified_size, failures, record.checkpoint_size],
+        )?;
+        Ok(())
+    }
+
+    //plan_id为None时返回所有plan的演练,按开始时间从新到旧,最多limit条
+    pub fn list_restore_drills(&self, plan_id: Option<&str>, limit: u32) -> Result<Vec<RestoreDrillRecord>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT drill_id, plan_id, checkpoint_id, start_time, end_time, sample_count, verified_count, verified_size, failures, checkpoint_size
+             FROM restore_drills WHERE ?1 IS NULL OR plan_id = ?1 ORDER BY start_time DESC LIMIT ?2"
+        )?;
+        let rows = stmt.query_map(params![plan_id, limit], Self::restore_drill_from_row)?
+            .collect::<SqlResult<Vec<RestoreDrillRecord>>>()?;
+        Ok(rows)
+    }
+
+    pub fn load_last_restore_drill(&self, plan_id: &str) -> Result<Option<RestoreDrillRecord>> {
+        Ok(self.list_restore_drills(Some(plan_id), 1)?.pop())
+    }
+
+    pub fn delete_restore_drills(&self, plan_id: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM restore_drills WHERE plan_id = ?1", params![plan_id])?;
+        Ok(())
+    }
+
+    fn restore_drill_from_row(row: &rusqlite::Row) -> SqlResult<RestoreDrillRecord> {
+        let failures: String = row.get(8)?;
+        Ok(RestoreDrillRecord {
+            drill_id: row.get(0)?,
+            plan_id: row.get(1)?,
+            checkpoint_id: row.get(2)?,
+            start_time: row.get(3)?,
+            end_time: row.get(4)?,
+            sample_count: row.get(5)?,
+            verified_count: row.get(6)?,
+            verified_size: row.get(7)?,
+            failures: serde_json::from_str(failures.as_str()).unwrap_or_default(),
+            checkpoint_size: row.get(9)?,
+        })
+    }
+
+    //返回false表示已经记录过这个告警
+    pub fn add_sla_breach(&self, plan_id: &str, objective: &str, breach_time: u64) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute(
+            "INSERT OR IGNORE INTO sla_breaches (plan_id, objective, breach_time) VALUES (?1, ?2, ?3)",
+            params![plan_id, objective, breach_time],
+        )?;
+        Ok(rows_affected > 0)
+    }
+
+    //返回false表示没有记录这个告警
+    pub fn remove_sla_breach(&self, plan_id: &str, objective: &str) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute(
+            "DELETE FROM sla_breaches WHERE plan_id = ?1 AND objective = ?2",
+            params![plan_id, objective],
+        )?;
+        Ok(rows_affected > 0)
+    }
+
+    //返回(objective, breach_time)
+    pub fn list_sla_breaches(&self, plan_id: &str) -> Result<Vec<(String, u64)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT objective, breach_time FROM sla_breaches WHERE plan_id = ?1 ORDER BY objective")?;
+        let rows = stmt.query_map(params![plan_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<Vec<(String, u64)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn delete_sla_breaches(&self, plan_id: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM sla_breaches WHERE plan_id = ?1", params![plan_id])?;
+        Ok(())
+    }
+
+    //prepare时按批写入,resume后重新枚举的item覆盖之前的记录
+    pub fn save_contents_index(&self, checkpoint_id: &str, entries: &[ContentsIndexEntry]) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        for entry in entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO contents_index (checkpoint_id, item_id, item_type, extension, size, size_bucket, mtime)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![checkpoint_id, entry.item_id, entry.item_type, entry.extension, entry.size, entry.size_bucket, entry.mtime],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    //rebuild为true时先删除已有的索引(从backup_items补建)
+    pub fn complete_contents_index(&self, checkpoint_id: &str, entries: &[ContentsIndexEntry], rebuild: bool) -> Result<()> {
+        if rebuild {
+            self.delete_contents_index(checkpoint_id)?;
+        }
+        self.save_contents_index(checkpoint_id, entries)?;
+        let conn = Connection::open(&self.db_path)?;
+        let item_count: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM contents_index WHERE checkpoint_id = ?1",
+            params![checkpoint_id],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO contents_index_state (checkpoint_id, item_count, complete_time) VALUES (?1, ?2, ?3)",
+            params![checkpoint_id, item_count, WorkTask::now_ms()],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_contents_index(&self, checkpoint_id: &str) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM contents_index WHERE checkpoint_id = ?1", params![checkpoint_id])?;
+        tx.execute("DELETE FROM contents_index_state WHERE checkpoint_id = ?1", params![checkpoint_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn is_contents_index_complete(&self, checkpoint_id: &str) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let count: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM contents_index_state WHERE checkpoint_id = ?1",
+            params![checkpoint_id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    //按item_id排序,limit为None时返回所有匹配的item
+    pub fn query_contents_index(&self, checkpoint_id: &str, query: &ContentsQuery, limit: Option<u64>) -> Result<Vec<ContentsIndexEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut sql = "SELECT item_id, item_type, extension, size, size_bucket, mtime FROM contents_index WHERE checkpoint_id = ?".to_string();
+        let mut values: Vec<rusqlite::types::Value> = vec![checkpoint_id.to_string().into()];
+        let extensions = query.get_extensions();
+        if !extensions.is_empty() {
+            sql.push_str(&format!(" AND extension IN ({})", vec!["?"; extensions.len()].join(", ")));
+            values.extend(extensions.into_iter().map(|ext| ext.into()));
+        }
+        //size_bucket的条件用于走索引之外的快速过滤,精确比较还是用size
+        if let Some(min_size) = query.min_size {
+            sql.push_str(" AND size_bucket >= ? AND size >= ?");
+            values.push((get_size_bucket(min_size) as i64).into());
+            values.push((min_size as i64).into());
+        }
+        if let Some(max_size) = query.max_size {
+            sql.push_str(" AND size_bucket <= ? AND size <= ?");
+            values.push((get_size_bucket(max_size) as i64).into());
+            values.push((max_size as i64).into());
+        }
+        if let Some(modified_after) = query.modified_after {
+            sql.push_str(" AND mtime >= ?");
+            values.push((modified_after as i64).into());
+        }
+        if let Some(modified_before) = query.modified_before {
+            sql.push_str(" AND mtime < ?");
+            values.push((modified_before as i64).into());
+        }
+        //前缀以'/'结尾,按字节序"dir/" <= item_id < "dir0"就是dir下的所有item
+        if let Some(path_prefix) = query.get_path_prefix() {
+            let upper_bound = format!("{}0", path_prefix.trim_end_matches('/'));
+            sql.push_str(" AND item_id >= ? AND item_id < ?");
+            values.push(path_prefix.into());
+            values.push(upper_bound.into());
+        }
+        sql.push_str(" ORDER BY item_id");
+        if let Some(limit) = limit {
+            sql.push_str(" LIMIT ?");
+            values.push((limit as i64).into());
+        }
+        let mut stmt = conn.prepare(sql.as_str())?;
+        let entries = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| {
+            Ok(ContentsIndexEntry {
+                item_id: row.get(0)?,
+                item_type: row.get(1)?,
+                extension: row.get(2)?,
+                size: row.get(3)?,
+                size_bucket: row.get(4)?,
+                mtime: row.get(5)?,
+            })
+        })?.collect::<SqlResult<Vec<ContentsIndexEntry>>>()?;
+        Ok(entries)
+    }
+
+    //按扩展名统计(扩展名, item数, 总大小),按总大小从大到小
+    pub fn load_contents_summary(&self, checkpoint_id: &str) -> Result<Vec<(String, u64, u64)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT extension, COUNT(*), COALESCE(SUM(size), 0) FROM contents_index WHERE checkpoint_id = ?1
+             GROUP BY extension ORDER BY SUM(size) DESC"
+        )?;
+        let rows = stmt.query_map(params![checkpoint_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<SqlResult<Vec<(String, u64, u64)>>>()?;
+        Ok(rows)
+    }
+
+    pub fn save_legal_hold(&self, hold: &LegalHold) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO legal_holds (checkpoint_id, plan_id, reason, holder, hold_time) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![hold.checkpoint_id, hold.plan_id, hold.reason, hold.holder, hold.hold_time],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_legal_hold(&self, checkpoint_id: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute("DELETE FROM legal_holds WHERE checkpoint_id = ?1", params![checkpoint_id])?;
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("legal hold of checkpoint {}", checkpoint_id)));
+        }
+        Ok(())
+    }
+
+    pub fn load_legal_hold(&self, checkpoint_id: &str) -> Result<Option<LegalHold>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, plan_id, reason, holder, hold_time FROM legal_holds WHERE checkpoint_id = ?1"
+        )?;
+        let mut rows = stmt.query(params![checkpoint_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(Self::legal_hold_from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //plan_id为None时返回所有plan的法律保留
+    pub fn list_legal_holds(&self, plan_id: Option<&str>) -> Result<Vec<LegalHold>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id, plan_id, reason, holder, hold_time FROM legal_holds
+             WHERE ?1 IS NULL OR plan_id = ?1 ORDER BY hold_time"
+        )?;
+        let holds = stmt.query_map(params![plan_id], Self::legal_hold_from_row)?
+            .collect::<SqlResult<Vec<LegalHold>>>()?;
+        Ok(holds)
+    }
+
+    fn legal_hold_from_row(row: &rusqlite::Row) -> SqlResult<LegalHold> {
+        Ok(LegalHold {
+            checkpoint_id: row.get(0)?,
+            plan_id: row.get(1)?,
+            reason: row.get(2)?,
+            holder: row.get(3)?,
+            hold_time: row.get(4)?,
+        })
+    }
+
+    pub fn add_audit_log(&self, timestamp: u64, operator: &str, action: &str, object_id: &str, detail: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO audit_log (timestamp, operator, action, object_id, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![timestamp, operator, action, object_id, detail],
+        )?;
         Ok(())
     }
 
-
-    pub fn load_last_checkpoint(&self, taskid: &str, count:Option<u32>) -> Result<BackupCheckPoint> {
+    pub fn append_task_journal(&self, task_id: &str, time: u64, from_state: Option<&TaskState>, to_state: &TaskState, cause: &str) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
-        let mut stmt = conn.prepare("SELECT * FROM checkpoints WHERE taskid = ?1 ORDER BY create_time DESC LIMIT ?2")?;
-        let mut rows = stmt.query(params![taskid, count.unwrap_or(1)])?;
-
-        if let Some(row) = rows.next()? {
-            let checkpoint = BackupCheckPoint {
-                checkpoint_id: row.get(0)?,
-                depend_checkpoint_id: row.get(1)?,
-                prev_checkpoint_id: row.get(2)?,
-                state: row.get(3)?,
-                owner_plan: row.get(4)?,
-                checkpoint_hash: row.get(5)?,
-                checkpoint_index: row.get(6)?,
-                create_time: row.get(7)?,
-            };
-            Ok(checkpoint)
-        } else {
-            Err(BackupTaskError::InvalidCheckpointId)
-        }
+        conn.execute(
+            "INSERT INTO task_journal (task_id, time, from_state, to_state, cause) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![task_id, time, from_state, to_state, cause],
+        )?;
+        Ok(())
     }
 
-    pub fn load_checkpoint_by_id(&self, checkpoint_id: &str) -> Result<BackupCheckPoint> {
+    //按seq从小到大
+    pub fn load_task_journal(&self, task_id: &str) -> Result<Vec<TaskJournalEntry>> {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
-            "SELECT * FROM checkpoints WHERE checkpoint_id = ?"
+            "SELECT seq, task_id, time, from_state, to_state, cause FROM task_journal WHERE task_id = ?1 ORDER BY seq"
         )?;
-        
-        let checkpoint = stmt.query_row(params![checkpoint_id], |row| {
-            Ok(BackupCheckPoint {
-                checkpoint_id: row.get(0)?,
-                depend_checkpoint_id: row.get(1)?,
-                prev_checkpoint_id: row.get(2)?,
-                state: row.get(3)?,
-                owner_plan: row.get(4)?,
-                checkpoint_hash: row.get(5)?,
-                checkpoint_index: row.get(6)?,
-                create_time: row.get(7)?,
+        let entries = stmt.query_map(params![task_id], |row| {
+            Ok(TaskJournalEntry {
+                seq: row.get(0)?,
+                task_id: row.get(1)?,
+                time: row.get(2)?,
+                from_state: row.get(3)?,
+                to_state: row.get(4)?,
+                cause: row.get(5)?,
             })
-        }).map_err(|_| BackupTaskError::InvalidCheckpointId)?;
+        })?.collect::<SqlResult<Vec<TaskJournalEntry>>>()?;
+        Ok(entries)
+    }
 
-        Ok(checkpoint)
+    //日志中最后的状态还是Running的task:进程在task运行时退出,没有记录之后的状态变化
+    pub fn list_journal_running_tasks(&self) -> Result<Vec<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT task_id FROM task_journal WHERE seq IN (SELECT MAX(seq) FROM task_journal GROUP BY task_id)
+             AND to_state = 'RUNNING'"
+        )?;
+        let task_ids = stmt.query_map([], |row| row.get(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        Ok(task_ids)
     }
 
+    //按seq从小到大,object_id为None时返回所有记录
+    pub fn list_audit_logs(&self, object_id: Option<&str>, since_seq: u64) -> Result<Vec<AuditRecord>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT seq, timestamp, operator, action, object_id, detail FROM audit_log
+             WHERE seq > ?1 AND (?2 IS NULL OR object_id = ?2) ORDER BY seq"
+        )?;
+        let records = stmt.query_map(params![since_seq, object_id], |row| {
+            Ok(AuditRecord {
+                seq: row.get(0)?,
+                timestamp: row.get(1)?,
+                operator: row.get(2)?,
+                action: row.get(3)?,
+                object_id: row.get(4)?,
+                detail: row.get(5)?,
+            })
+        })?.collect::<SqlResult<Vec<AuditRecord>>>()?;
+        Ok(records)
+    }
 
-    pub fn cancel_task(&self, taskid: &str) -> Result<()> {
+    //secret是加密后的密钥,加解密由CredentialVault负责
+    pub fn save_credential(&self, credential_id: &str, kind: &str, nonce: &str, secret: &str) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
-        let rows_affected = conn.execute(
-            "UPDATE work_tasks SET state = ? WHERE taskid = ?",
-            params![
-                TaskState::Failed,
-                taskid
-            ],
+        conn.execute(
+            "INSERT OR REPLACE INTO credentials (credential_id, kind, nonce, secret, create_time) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![credential_id, kind, nonce, secret, WorkTask::now_ms()],
         )?;
+        Ok(())
+    }
 
-        if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+    //返回(kind, nonce, secret)
+    pub fn load_credential(&self, credential_id: &str) -> Result<Option<(String, String, String)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT kind, nonce, secret FROM credentials WHERE credential_id = ?"
+        )?;
+        let mut rows = stmt.query(params![credential_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some((row.get(0)?, row.get(1)?, row.get(2)?)))
+        } else {
+            Ok(None)
         }
+    }
+
+    pub fn delete_credential(&self, credential_id: &str) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM credentials WHERE credential_id = ?", params![credential_id])?;
         Ok(())
     }
 
-    pub fn save_backup_item(&self, checkpoint_id: &str, item: &BackupItem) -> Result<()> {
+    pub fn create_pending_operation(&self, op: &PendingOperation) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         conn.execute(
-            "INSERT INTO backup_items VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            "INSERT INTO pending_operations (op_id, operation, requester, approver, state, create_time, expire_time) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![
-                item.item_id,
-                checkpoint_id,
-                item.item_type,
-                item.chunk_id,
-                item.quick_hash,
-                item.state,
-                item.size,
-                item.last_modify_time,
-                item.create_time,
-                item.progress,
-                item.diff_info.clone().unwrap_or("".to_string()),
+                op.op_id,
+                op.operation.to_json_string(),
+                op.requester,
+                op.approver,
+                op.state,
+                op.create_time,
+                op.expire_time,
             ],
         )?;
         Ok(())
     }
 
-    pub fn save_item_list_to_checkpoint(&self, checkpoint_id: &str, item_list: &Vec<BackupItem>) -> Result<()> {
-        let mut conn = Connection::open(&self.db_path)?;
-        let tx = conn.transaction()?;
+    pub fn update_pending_operation(&self, op: &PendingOperation) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute(
+            "UPDATE pending_operations SET approver = ?1, state = ?2 WHERE op_id = ?3",
+            params![op.approver, op.state, op.op_id],
+        )?;
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("operation {}", op.op_id)));
+        }
+        Ok(())
+    }
 
-        // optimize: per checkpoint per table?
-        // tx.execute(
-        //     "CREATE TABLE IF NOT EXISTS {}_backup_items (
-        //         item_id TEXT NOT NULL,
-        //         checkpoint_id TEXT NOT NULL,
-        //         item_type TEXT NOT NULL,
-        //         chunk_id TEXT,
-        //         quick_hash TEXT,
-        //         state TEXT NOT NULL,
-        //         size INTEGER NOT NULL,
-        //         last_modify_time INTEGER NOT NULL,
-        //         create_time INTEGER NOT NULL,
-        //         PRIMARY KEY (item_id, checkpoint_id)
-        //     )",
-        //     [],
-        // )?;
+    pub fn load_pending_operation(&self, op_id: &str) -> Result<PendingOperation> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT op_id, operation, requester, approver, state, create_time, expire_time 
+             FROM pending_operations WHERE op_id = ?"
+        )?;
+        let mut ops = stmt.query_map(params![op_id], Self::pending_operation_from_row)?
+            .collect::<SqlResult<Vec<PendingOperation>>>()?;
+        ops.pop().ok_or(BuckyBackupError::NotFound(format!("operation {}", op_id)))
+    }
 
-        for item in item_list {
-            tx.execute(
-                "INSERT INTO backup_items (
-                    item_id,
-                    checkpoint_id,
-                    item_type,
-                    chunk_id,
-                    quick_hash,
-                    state,
-                    size,
-                    last_modify_time,
-                    create_time,
-                    progress,
-                    diff_info
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-                params![
-                    item.item_id,
-                    checkpoint_id,
-                    item.item_type,
-                    item.chunk_id,
-                    item.quick_hash,
-                    item.state,
-                    item.size,
-                    item.last_modify_time,
-                    item.create_time,
-                    item.progress,
-                    item.diff_info.clone().unwrap_or("".to_string()),
-                ],
-            )?;
-        }
+    pub fn list_pending_operations(&self) -> Result<Vec<PendingOperation>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT op_id, operation, requester, approver, state, create_time, expire_time 
+             FROM pending_operations WHERE state = 'PENDING' ORDER BY create_time"
+        )?;
+        let ops = stmt.query_map([], Self::pending_operation_from_row)?
+            .collect::<SqlResult<Vec<PendingOperation>>>()?;
+        Ok(ops)
+    }
 
-        tx.commit()?;
-        info!("taskdb.save_item_list_to_checkpoint: {} {} items", checkpoint_id, item_list.len());
-        Ok(())
+    fn pending_operation_from_row(row: &rusqlite::Row) -> SqlResult<PendingOperation> {
+        let operation_str: String = row.get(1)?;
+        let operation = serde_json::from_str::<DestructiveOperation>(operation_str.as_str())
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e)))?;
+        Ok(PendingOperation {
+            op_id: row.get(0)?,
+            operation,
+            requester: row.get(2)?,
+            approver: row.get(3)?,
+            state: row.get(4)?,
+            create_time: row.get(5)?,
+            expire_time: row.get(6)?,
+        })
     }
 
     pub fn create_checkpoint(&self, checkpoint: &BackupCheckPoint) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         conn.execute(
-            "INSERT INTO checkpoints VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            "INSERT INTO checkpoints (
+                checkpoint_id,
+                depend_checkpoint_id,
+                prev_checkpoint_id,
+                state,
+                owner_plan,
+                checkpoint_hash,
+                checkpoint_index,
+                create_time,
+                signature,
+                pinned,
+                pin_reason,
+                chunk_hash,
+                inventory_only,
+                failed_item_count,
+                retry_of
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
             params![
                 checkpoint.checkpoint_id,
                 checkpoint.depend_checkpoint_id,
@@ -719,6 +2562,13 @@ impl BackupTaskDb {
                 checkpoint.checkpoint_hash,
                 checkpoint.checkpoint_index,
                 checkpoint.create_time,
+                checkpoint.signature,
+                checkpoint.pinned,
+                checkpoint.pin_reason,
+                checkpoint.chunk_hash.as_str(),
+                checkpoint.inventory_only,
+                checkpoint.failed_item_count,
+                checkpoint.retry_of,
             ],
         )?;
         Ok(())
@@ -734,7 +2584,9 @@ impl BackupTaskDb {
                 owner_plan = ?5,
                 checkpoint_hash = ?6,
                 checkpoint_index = ?7,
-                create_time = ?8
+                create_time = ?8,
+                signature = ?9,
+                failed_item_count = ?10
             WHERE checkpoint_id = ?1",
             params![
                 checkpoint.checkpoint_id,
@@ -745,34 +2597,82 @@ impl BackupTaskDb {
                 checkpoint.checkpoint_hash,
                 checkpoint.checkpoint_index,
                 checkpoint.create_time,
+                checkpoint.signature,
+                checkpoint.failed_item_count,
             ],
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::InvalidCheckpointId);
+            return Err(BuckyBackupError::NotFound(format!("checkpoint {}", checkpoint.checkpoint_id)));
         }
         Ok(())
     }
 
+    //checkpoint和它的item/pack索引在一个事务中删除,中途崩溃不会留下没有checkpoint的item记录
     pub fn delete_checkpoint(&self, checkpoint_id: &str) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
-        let rows_affected = conn.execute(
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        let rows_affected = tx.execute(
             "DELETE FROM checkpoints WHERE checkpoint_id = ?",
             params![checkpoint_id],
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::InvalidCheckpointId);
+            return Err(BuckyBackupError::NotFound(format!("checkpoint {}", checkpoint_id)));
+        }
+        tx.execute("DELETE FROM backup_items WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM pack_chunks WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM system_manifests WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM checkpoint_transfer_stats WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM seed_exports WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM contents_index WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.execute("DELETE FROM contents_index_state WHERE checkpoint_id = ?", params![checkpoint_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    //直接依赖checkpoint_id的checkpoint
+    pub fn list_dependent_checkpoint_ids(&self, checkpoint_id: &str) -> Result<Vec<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT checkpoint_id FROM checkpoints WHERE depend_checkpoint_id = ?"
+        )?;
+        let checkpoint_ids = stmt.query_map(params![checkpoint_id], |row| row.get::<_, String>(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        Ok(checkpoint_ids)
+    }
+
+    //pin状态单独更新,update_checkpoint不会覆盖它(运行中的task持有的checkpoint可能是pin之前加载的)
+    pub fn set_checkpoint_pinned(&self, checkpoint_id: &str, pinned: bool, pin_reason: Option<&str>) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let rows_affected = conn.execute(
+            "UPDATE checkpoints SET pinned = ?2, pin_reason = ?3 WHERE checkpoint_id = ?1",
+            params![checkpoint_id, pinned, pin_reason],
+        )?;
+
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("checkpoint {}", checkpoint_id)));
         }
         Ok(())
     }
 
+    pub fn list_pinned_checkpoints(&self) -> Result<Vec<BackupCheckPoint>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM checkpoints WHERE pinned != 0 ORDER BY owner_plan, checkpoint_index"
+        )?;
+        let checkpoints = stmt.query_map([], Self::checkpoint_from_row)?
+            .collect::<SqlResult<Vec<BackupCheckPoint>>>()?;
+        Ok(checkpoints)
+    }
+
+    //读取失败的item没有数据,不属于checkpoint的内容(恢复/签名/下次备份的比较都不包含),用load_failed_backup_items查询
     pub fn load_backup_items_by_checkpoint(&self, checkpoint_id: &str) -> Result<Vec<BackupItem>> {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
             "SELECT item_id, item_type, chunk_id, quick_hash, state, size, 
-                    last_modify_time, create_time, progress, diff_info
-             FROM backup_items WHERE checkpoint_id = ?"
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
+             FROM backup_items WHERE checkpoint_id = ? AND state NOT LIKE 'FAILED:%'"
         )?;
         
         
@@ -800,6 +2700,9 @@ impl BackupTaskDb {
                 have_cache: false,
                 progress: row.get(8)?,
                 diff_info,
+                pack_info: row.get(10)?,
+                file_meta: row.get(11)?,
+                is_fuzzy: row.get(12)?,
             })
         })?
         .collect::<SqlResult<Vec<BackupItem>>>()?;
@@ -811,7 +2714,7 @@ impl BackupTaskDb {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
             "SELECT item_id, item_type, chunk_id, quick_hash, state, size, 
-                    last_modify_time, create_time, progress, diff_info
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
              FROM backup_items 
              WHERE checkpoint_id = ? AND state = ?"
         )?;
@@ -831,6 +2734,9 @@ impl BackupTaskDb {
                     have_cache: false,
                     progress: row.get(8)?,
                     diff_info: Some(row.get(9)?),
+                    pack_info: row.get(10)?,
+                    file_meta: row.get(11)?,
+                    is_fuzzy: row.get(12)?,
                 })
             }
         )?
@@ -843,7 +2749,7 @@ impl BackupTaskDb {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
             "SELECT item_id, item_type, chunk_id, quick_hash, state,size, 
-                    last_modify_time, create_time, progress, diff_info
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
              FROM backup_items 
              WHERE checkpoint_id = ? AND state = ?"
         )?;
@@ -866,6 +2772,44 @@ impl BackupTaskDb {
                     have_cache: false,
                     progress: row.get(8)?,
                     diff_info: Some(row.get(9)?),
+                    pack_info: row.get(10)?,
+                    file_meta: row.get(11)?,
+                    is_fuzzy: row.get(12)?,
+                })
+            }
+        )?
+        .collect::<SqlResult<Vec<BackupItem>>>()?;
+
+        Ok(items)
+    }
+
+    pub fn load_failed_backup_items(&self, checkpoint_id: &str) -> Result<Vec<BackupItem>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT item_id, item_type, chunk_id, quick_hash, state, size, 
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
+             FROM backup_items 
+             WHERE checkpoint_id = ? AND state LIKE 'FAILED:%'"
+        )?;
+
+        let items = stmt.query_map(
+            params![checkpoint_id],
+            |row| {
+                Ok(BackupItem {
+                    item_id: row.get(0)?,
+                    item_type: row.get(1)?,
+                    chunk_id: row.get(2)?,
+                    quick_hash: row.get(3)?,
+                    state: row.get(4)?,
+                    size: row.get(5)?,
+                    last_modify_time: row.get(6)?,
+                    create_time: row.get(7)?,
+                    have_cache: false,
+                    progress: row.get(8)?,
+                    diff_info: row.get(9)?,
+                    pack_info: row.get(10)?,
+                    file_meta: row.get(11)?,
+                    is_fuzzy: row.get(12)?,
                 })
             }
         )?
@@ -874,6 +2818,54 @@ impl BackupTaskDb {
         Ok(items)
     }
 
+    //把checkpoint的item和pack索引复制到重新备份失败item的新checkpoint,读取失败的item重置为NEW,
+    //返回(item总数,总大小,已完成的item数量,已完成的大小)
+    pub fn copy_checkpoint_items_for_retry(&self, from_checkpoint_id: &str, to_checkpoint_id: &str) -> Result<(u64, u64, u64, u64)> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO backup_items (
+                item_id, checkpoint_id, item_type, chunk_id, quick_hash, state, size,
+                last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
+            )
+            SELECT item_id, ?2, item_type, chunk_id, quick_hash,
+                   CASE WHEN state LIKE 'FAILED:%' THEN 'NEW' ELSE state END, size,
+                   last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
+            FROM backup_items WHERE checkpoint_id = ?1",
+            params![from_checkpoint_id, to_checkpoint_id],
+        )?;
+        tx.execute(
+            "INSERT INTO pack_chunks (pack_chunk_id, checkpoint_id, total_size, item_count, pack_index)
+             SELECT pack_chunk_id, ?2, total_size, item_count, pack_index FROM pack_chunks WHERE checkpoint_id = ?1",
+            params![from_checkpoint_id, to_checkpoint_id],
+        )?;
+        let stat = tx.query_row(
+            "SELECT COUNT(*), IFNULL(SUM(size), 0),
+                    IFNULL(SUM(CASE WHEN state = 'DONE' THEN 1 ELSE 0 END), 0),
+                    IFNULL(SUM(CASE WHEN state = 'DONE' THEN size ELSE 0 END), 0)
+             FROM backup_items WHERE checkpoint_id = ?1",
+            params![to_checkpoint_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        tx.commit()?;
+        Ok(stat)
+    }
+
+    //返回(读取失败的item数量,没有完成的item数量(包括失败的),item总数)
+    pub fn count_checkpoint_unfinished_items(&self, checkpoint_id: &str) -> Result<(u64, u64, u64)> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT IFNULL(SUM(CASE WHEN state LIKE 'FAILED:%' THEN 1 ELSE 0 END), 0),
+                    IFNULL(SUM(CASE WHEN state != 'DONE' THEN 1 ELSE 0 END), 0),
+                    COUNT(*)
+             FROM backup_items WHERE checkpoint_id = ?"
+        )?;
+        let counts = stmt.query_row(params![checkpoint_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(counts)
+    }
+
     pub fn check_is_checkpoint_items_all_done(&self, checkpoint_id: &str) -> Result<bool> {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
@@ -898,7 +2890,10 @@ impl BackupTaskDb {
                 last_modify_time = ?6,
                 create_time = ?7,
                 progress = ?8,
-                diff_info = ?9
+                diff_info = ?9,
+                pack_info = ?12,
+                file_meta = ?13,
+                fuzzy = ?14
             WHERE checkpoint_id = ?10 AND item_id = ?11",
             params![
                 item.item_type,
@@ -912,11 +2907,14 @@ impl BackupTaskDb {
                 item.diff_info.clone().unwrap_or("".to_string()),
                 checkpoint_id,
                 item.item_id,
+                item.pack_info,
+                item.file_meta,
+                item.is_fuzzy,
             ],
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("item {} in checkpoint {}", item.item_id, checkpoint_id)));
         }
 
         Ok(())
@@ -936,7 +2934,7 @@ impl BackupTaskDb {
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("item {} in checkpoint {}", item_id, checkpoint_id)));
         }
 
         Ok(())
@@ -945,7 +2943,18 @@ impl BackupTaskDb {
     pub fn create_backup_plan(&self, plan: &BackupPlanConfig) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         conn.execute(
-            "INSERT INTO backup_plans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO backup_plans (
+                plan_id,
+                source_type,
+                source_url,
+                target_type,
+                target_url,
+                title,
+                description,
+                type_str,
+                last_checkpoint_index,
+                options
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             params![
                 plan.get_plan_key(),
                 match &plan.source {
@@ -962,6 +2971,7 @@ impl BackupTaskDb {
                 plan.description,
                 plan.type_str,
                 plan.last_checkpoint_index,
+                plan.options,
             ],
         )?;
         Ok(())
@@ -978,7 +2988,8 @@ impl BackupTaskDb {
                 title = ?6,
                 description = ?7,
                 type_str = ?8,
-                last_checkpoint_index = ?9
+                last_checkpoint_index = ?9,
+                options = ?10
             WHERE plan_id = ?1",
             params![
                 plan.get_plan_key(),
@@ -996,11 +3007,12 @@ impl BackupTaskDb {
                 plan.description,
                 plan.type_str,
                 plan.last_checkpoint_index,
+                plan.options,
             ],
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("plan {}", plan.get_plan_key())));
         }
         Ok(())
     }
@@ -1013,14 +3025,49 @@ impl BackupTaskDb {
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("plan {}", plan_id)));
+        }
+        Ok(())
+    }
+
+    //target url变化(比如把url中的密钥移到凭证库)会改变plan_id,在一个事务中更新plan和所有按plan_id/target_url记录的数据
+    pub fn migrate_plan_target(&self, old_plan_id: &str, old_target_url: &str, plan: &BackupPlanConfig) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        let new_plan_id = plan.get_plan_key();
+        let new_target_url = plan.target.get_target_url();
+        let rows_affected = tx.execute(
+            "UPDATE backup_plans SET plan_id = ?2, target_url = ?3 WHERE plan_id = ?1",
+            params![old_plan_id, new_plan_id, new_target_url],
+        )?;
+        if rows_affected == 0 {
+            return Err(BuckyBackupError::NotFound(format!("plan {}", old_plan_id)));
+        }
+        tx.execute("UPDATE work_tasks SET owner_plan_id = ?2 WHERE owner_plan_id = ?1", params![old_plan_id, new_plan_id])?;
+        tx.execute("UPDATE checkpoints SET owner_plan = ?2 WHERE owner_plan = ?1", params![old_plan_id, new_plan_id])?;
+        for table in ["plan_baselines", "dir_changes", "dedup_stats", "checkpoint_transfer_stats", "seed_exports", "legal_holds", "restore_drills", "sla_breaches"] {
+            tx.execute(format!("UPDATE {} SET plan_id = ?2 WHERE plan_id = ?1", table).as_str(), params![old_plan_id, new_plan_id])?;
         }
+        tx.execute("UPDATE storage_quotas SET scope_key = ?2 WHERE scope = 'plan' AND scope_key = ?1", params![old_plan_id, new_plan_id])?;
+        tx.execute("UPDATE storage_quotas SET scope_key = ?2 WHERE scope = 'target' AND scope_key = ?1", params![old_target_url, new_target_url])?;
+        for table in ["spool_entries", "seed_exports"] {
+            tx.execute(format!("UPDATE {} SET target_url = ?2 WHERE target_url = ?1", table).as_str(), params![old_target_url, new_target_url])?;
+        }
+        tx.execute("DELETE FROM target_stats WHERE target_url = ?1", params![old_target_url])?;
+        //新的url可能已经有其它plan写入的相同chunk
+        tx.execute("UPDATE OR IGNORE target_chunks SET target_url = ?2 WHERE target_url = ?1", params![old_target_url, new_target_url])?;
+        tx.execute("DELETE FROM target_chunks WHERE target_url = ?1", params![old_target_url])?;
+        tx.commit()?;
         Ok(())
     }
 
     pub fn list_backup_plans(&self) -> Result<Vec<BackupPlanConfig>> {
         let conn = Connection::open(&self.db_path)?;
-        let mut stmt = conn.prepare("SELECT * FROM backup_plans")?;
+        let mut stmt = conn.prepare(
+            "SELECT plan_id, source_type, source_url, target_type, target_url, title,
+                    description, type_str, last_checkpoint_index, options
+             FROM backup_plans"
+        )?;
         
         let plans = stmt.query_map([], |row| {
             let source_type: String = row.get(1)?;
@@ -1043,6 +3090,7 @@ impl BackupTaskDb {
                 description: row.get(6)?,
                 type_str: row.get(7)?,
                 last_checkpoint_index: row.get(8)?,
+                options: row.get(9)?,
             })
         })?
         .collect::<SqlResult<Vec<BackupPlanConfig>>>()?;
@@ -1058,7 +3106,8 @@ impl BackupTaskDb {
             "running" => sql = "SELECT taskid FROM work_tasks WHERE state = 'RUNNING'",
             "paused" => sql = "SELECT taskid FROM work_tasks WHERE state = 'PAUSED'",
             "failed" => sql = "SELECT taskid FROM work_tasks WHERE state = 'FAILED'",
-            "pending" => sql = "SELECT taskid FROM work_tasks WHERE state = 'PENDING'",
+            "queued" => sql = "SELECT taskid FROM work_tasks WHERE state = 'QUEUED'",
+            "cancelled" => sql = "SELECT taskid FROM work_tasks WHERE state = 'CANCELLED'",
             "done" => sql = "SELECT taskid FROM work_tasks WHERE state = 'DONE'",
             _ => sql = "SELECT taskid FROM work_tasks",
         }
@@ -1070,6 +3119,26 @@ impl BackupTaskDb {
         Ok(tasks)
     }
 
+    //没有结束的restore task(包括暂停和排队中的),返回(task_id, checkpoint_id)
+    pub fn list_unfinished_restore_tasks(&self) -> Result<Vec<(String, String)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT taskid, checkpoint_id FROM work_tasks
+             WHERE task_type = 'RESTORE' AND state IN ('RUNNING', 'QUEUED', 'PAUSED')"
+        )?;
+        let tasks = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<SqlResult<Vec<(String, String)>>>()?;
+        Ok(tasks)
+    }
+
+    pub fn list_task_ids_by_checkpoint(&self, checkpoint_id: &str) -> Result<Vec<String>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT taskid FROM work_tasks WHERE checkpoint_id = ?")?;
+        let tasks = stmt.query_map(params![checkpoint_id], |row| row.get::<_, String>(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        Ok(tasks)
+    }
+
     pub fn add_worktask_log(&self, timestamp: u64, level: &str, owner_task: &str, log_content: &str, log_event_type: &str) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         conn.execute(
@@ -1114,8 +3183,12 @@ impl BackupTaskDb {
                     state,
                     size,
                     last_modify_time,
-                    create_time
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    create_time,
+                    progress,
+                    pack_info,
+                    file_meta,
+                    fuzzy
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
                 params![
                     item.item_id,
                     owner_taskid,
@@ -1126,6 +3199,10 @@ impl BackupTaskDb {
                     item.size,
                     item.last_modify_time,
                     item.create_time,
+                    item.progress,
+                    item.pack_info,
+                    item.file_meta,
+                    item.is_fuzzy,
                 ],
             )?;
         }
@@ -1135,11 +3212,49 @@ impl BackupTaskDb {
         Ok(())
     }
 
+    pub fn save_restore_conflicts(&self, owner_taskid: &str, decisions: &Vec<RestoreConflictDecision>) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        for decision in decisions {
+            tx.execute(
+                "INSERT OR REPLACE INTO restore_conflicts (item_id, owner_taskid, restore_item_id, action, reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    decision.item_id,
+                    owner_taskid,
+                    decision.restore_item_id,
+                    decision.action.as_str(),
+                    decision.reason,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_restore_conflicts(&self, owner_taskid: &str) -> Result<Vec<RestoreConflictDecision>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT item_id, restore_item_id, action, reason FROM restore_conflicts WHERE owner_taskid = ? ORDER BY item_id"
+        )?;
+        let decisions = stmt.query_map(params![owner_taskid], |row| {
+            let action: String = row.get(2)?;
+            Ok(RestoreConflictDecision {
+                item_id: row.get(0)?,
+                restore_item_id: row.get(1)?,
+                action: RestoreConflictAction::from_str(&action).unwrap_or(RestoreConflictAction::Skip),
+                reason: row.get(3)?,
+            })
+        })?
+        .collect::<SqlResult<Vec<RestoreConflictDecision>>>()?;
+        Ok(decisions)
+    }
+
     pub fn load_restore_items_by_task(&self, owner_taskid: &str,state: &BackupItemState) -> Result<Vec<BackupItem>> {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
             "SELECT item_id, item_type, chunk_id, quick_hash, state, size, 
-                    last_modify_time, create_time, progress, diff_info
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
              FROM restore_items WHERE owner_taskid = ? AND state = ?"
         )?;
         
@@ -1155,7 +3270,10 @@ impl BackupTaskDb {
                 create_time: row.get(7)?,
                 have_cache: false,
                 progress: row.get(8)?,
-                diff_info: Some(row.get(9)?),
+                diff_info: row.get(9)?,
+                pack_info: row.get(10)?,
+                file_meta: row.get(11)?,
+                is_fuzzy: row.get(12)?,
             })
         })?
         .collect::<SqlResult<Vec<BackupItem>>>()?;
@@ -1190,7 +3308,7 @@ impl BackupTaskDb {
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("restore item {} of task {}", item.item_id, owner_taskid)));
         }
 
         Ok(())
@@ -1210,7 +3328,7 @@ impl BackupTaskDb {
         )?;
 
         if rows_affected == 0 {
-            return Err(BackupTaskError::TaskNotFound);
+            return Err(BuckyBackupError::NotFound(format!("restore item {} of task {}", item_id, owner_taskid)));
         }
 
         Ok(())
@@ -1219,8 +3337,8 @@ impl BackupTaskDb {
     pub fn load_wait_transfer_restore_items(&self, owner_taskid: &str) -> Result<Vec<BackupItem>> {
         let conn = Connection::open(&self.db_path)?;
         let mut stmt = conn.prepare(
-            "SELECT item_id, item_type, chunk_id, quick_hash, size, 
-                    last_modify_time, create_time, progress, diff_info
+            "SELECT item_id, item_type, chunk_id, quick_hash, state, size, 
+                    last_modify_time, create_time, progress, diff_info, pack_info, file_meta, fuzzy
              FROM restore_items 
              WHERE owner_taskid = ? AND state = ?"
         )?;
@@ -1243,6 +3361,9 @@ impl BackupTaskDb {
                     have_cache: false,
                     progress: row.get(8)?,
                     diff_info: Some(row.get(9)?),
+                    pack_info: row.get(10)?,
+                    file_meta: row.get(11)?,
+                    is_fuzzy: row.get(12)?,
                 })
             }
         )?
@@ -1322,7 +3443,7 @@ mod tests {
         // Test cancel
         db.cancel_task(&task_id).unwrap();
         let cancelled_task = db.load_task_by_id(&task_id).unwrap();
-        assert_eq!(cancelled_task.state, TaskState::Failed);
+        assert_eq!(cancelled_task.state, TaskState::Cancelled);
         //db.delete_task(&task_id).unwrap();
     }
 
@@ -1348,6 +3469,18 @@ mod tests {
         // Verify update
         let loaded_cp = db.load_checkpoint_by_id(&checkpoint_id).unwrap();
         assert_eq!(loaded_cp.state, CheckPointState::Prepared);
+
+        // Delete checkpoint with its items
+        let item = BackupItem::new("a.txt", BackupItemType::Chunk, 10);
+        db.save_item_list_to_checkpoint(&checkpoint_id, &vec![item]).unwrap();
+        let manifest = SystemRestoreManifest { hostname: "nas".to_string(), ..Default::default() };
+        db.save_system_manifest(&checkpoint_id, &manifest).unwrap();
+        assert_eq!(db.load_system_manifest(&checkpoint_id).unwrap(), Some(manifest));
+        db.delete_checkpoint(&checkpoint_id).unwrap();
+        assert!(db.load_checkpoint_by_id(&checkpoint_id).is_err());
+        assert!(db.load_backup_items_by_checkpoint(&checkpoint_id).unwrap().is_empty());
+        assert!(db.load_system_manifest(&checkpoint_id).unwrap().is_none());
+        assert!(db.delete_checkpoint(&checkpoint_id).is_err());
     }
 
     #[test]
@@ -1356,11 +3489,71 @@ mod tests {
         
         // Test loading non-existent task
         let result = db.load_task_by_id("non_existent_task");
-        assert!(matches!(result, Err(BackupTaskError::TaskNotFound)));
+        assert!(matches!(result, Err(BuckyBackupError::NotFound(_))));
 
         // Test loading non-existent checkpoint
         let result = db.load_checkpoint_by_id("non_existent_checkpoint");
-        assert!(matches!(result, Err(BackupTaskError::InvalidCheckpointId)));
+        assert!(matches!(result, Err(BuckyBackupError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_plan_options() {
+        let (db, _) = setup_test_db();
+
+        let source_url = format!("file:///tmp/test_plan_options_{}", Uuid::new_v4());
+        let mut plan = BackupPlanConfig::chunk2chunk(source_url.as_str(), "file:///tmp/test_plan_options_target", "title", "desc");
+        plan.options.preserve_xattrs = false;
+        db.create_backup_plan(&plan).unwrap();
+
+        let plans = db.list_backup_plans().unwrap();
+        let loaded = plans.iter().find(|p| p.get_plan_key() == plan.get_plan_key()).unwrap();
+        assert_eq!(loaded.options, plan.options);
+
+        let mut file_meta = ItemFileMeta::default();
+        file_meta.uid = Some(1000);
+        file_meta.xattrs.insert("user.test".to_string(), "76616c7565".to_string());
+        let mut item = BackupItem::new("a.txt", BackupItemType::Chunk, 0);
+        item.file_meta = Some(file_meta.to_json_string());
+        loaded.options.filter_file_meta(&mut item);
+        let filtered = ItemFileMeta::from_json_str(item.file_meta.as_ref().unwrap()).unwrap();
+        assert_eq!(filtered.uid, Some(1000));
+        assert!(filtered.xattrs.is_empty());
+
+        db.delete_backup_plan(plan.get_plan_key().as_str()).unwrap();
+    }
+
+    #[test]
+    fn test_task_journal() {
+        let test_dir = tempdir().unwrap();
+        let db = BackupTaskDb::new(test_dir.path().join("journal.db").to_str().unwrap());
+        db.append_task_journal("task_1", 1000, None, &TaskState::Paused, "created").unwrap();
+        db.append_task_journal("task_1", 2000, Some(&TaskState::Paused), &TaskState::Running, "start").unwrap();
+        db.append_task_journal("task_2", 2500, None, &TaskState::Paused, "created").unwrap();
+        let entries = db.load_task_journal("task_1").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].from_state, None);
+        assert_eq!((entries[1].from_state.clone(), entries[1].to_state.clone()), (Some(TaskState::Paused), TaskState::Running));
+        assert_eq!(db.list_journal_running_tasks().unwrap(), vec!["task_1".to_string()]);
+
+        //日志不能修改和删除
+        let conn = Connection::open(test_dir.path().join("journal.db")).unwrap();
+        assert!(conn.execute("UPDATE task_journal SET cause = 'x'", []).is_err());
+        assert!(conn.execute("DELETE FROM task_journal", []).is_err());
+        assert_eq!(db.load_task_journal("task_1").unwrap()[1].cause, "start");
+    }
+
+    #[test]
+    fn test_target_chunk_index() {
+        let test_dir = tempdir().unwrap();
+        let db = BackupTaskDb::new(test_dir.path().join("chunks.db").to_str().unwrap());
+        let chunk_ids: HashSet<String> = ["chunk_a", "chunk_b"].iter().map(|s| s.to_string()).collect();
+        db.add_target_chunks("file:///target_1", &chunk_ids).unwrap();
+        db.add_target_chunks("file:///target_1", &chunk_ids).unwrap();
+        let candidates: HashSet<String> = ["chunk_b", "chunk_c"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(db.filter_indexed_target_chunks("file:///target_1", &candidates).unwrap(), ["chunk_b".to_string()].into_iter().collect());
+        assert!(db.filter_indexed_target_chunks("file:///target_2", &candidates).unwrap().is_empty());
+        db.remove_target_chunks("file:///target_1", &["chunk_b".to_string()].into_iter().collect()).unwrap();
+        assert!(db.filter_indexed_target_chunks("file:///target_1", &candidates).unwrap().is_empty());
     }
 }
 
@@ -0,0 +1,143 @@
+#![allow(unused)]
+//task事件日志:task的每次状态变化(时间,之前/之后的状态,原因)追加到task db的task_journal中,只追加不修改
+//work_tasks表只保存当前状态(运行中的task保存为Paused),通过日志可以重建task完整的时间线
+//(queued -> running -> paused -> running -> done),用于排查问题和统计SLA
+use serde_json::{Value, json};
+use crate::task_db::TaskState;
+
+pub const JOURNAL_CAUSE_ENGINE_RESTART: &str = "engine restarted while task running";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskJournalEntry {
+    pub seq: u64,
+    pub task_id: String,
+    pub time: u64,
+    pub from_state: Option<TaskState>,//task创建时为None
+    pub to_state: TaskState,
+    pub cause: String,
+}
+
+impl TaskJournalEntry {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "seq": self.seq,
+            "task_id": self.task_id,
+            "time": self.time,
+            "from_state": self.from_state.as_ref().map(|state| state.to_string()),
+            "to_state": self.to_state.to_string(),
+            "cause": self.cause,
+        })
+    }
+}
+
+//由日志计算的task时间线汇总,时间都是ms
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskTimelineSummary {
+    pub create_time: u64,
+    pub first_run_time: Option<u64>,
+    pub finish_time: Option<u64>,//进入Done/Failed/Cancelled的时间
+    pub final_state: Option<TaskState>,
+    pub queued_ms: u64,
+    pub running_ms: u64,
+    pub paused_ms: u64,
+    pub pause_count: u32,//Running -> Paused的次数
+    pub resume_count: u32,//Paused -> Running的次数
+}
+
+impl TaskTimelineSummary {
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "create_time": self.create_time,
+            "first_run_time": self.first_run_time,
+            "finish_time": self.finish_time,
+            "final_state": self.final_state.as_ref().map(|state| state.to_string()),
+            "queued_ms": self.queued_ms,
+            "running_ms": self.running_ms,
+            "paused_ms": self.paused_ms,
+            "pause_count": self.pause_count,
+            "resume_count": self.resume_count,
+        })
+    }
+}
+
+//entries按seq排序;没有结束的task最后一个状态计算到now
+//结束的task可能被重新打开(比如reconcile把Done改为Paused,Failed的task被resume),统计整个日志,结束时间取最后一次结束
+pub fn summarize_task_journal(entries: &[TaskJournalEntry], now: u64) -> Option<TaskTimelineSummary> {
+    let first = entries.first()?;
+    let mut summary = TaskTimelineSummary { create_time: first.time, ..Default::default() };
+    for (index, entry) in entries.iter().enumerate() {
+        match (&entry.from_state, &entry.to_state) {
+            (Some(TaskState::Running), TaskState::Paused) => summary.pause_count += 1,
+            (Some(TaskState::Paused), TaskState::Running) => summary.resume_count += 1,
+            _ => {}
+        }
+        if entry.to_state == TaskState::Running && summary.first_run_time.is_none() {
+            summary.first_run_time = Some(entry.time);
+        }
+        if entry.to_state.is_finished() {
+            summary.finish_time = Some(entry.time);
+            summary.final_state = Some(entry.to_state.clone());
+            continue;
+        }
+        summary.finish_time = None;
+        summary.final_state = None;
+        let end_time = entries.get(index + 1).map(|next| next.time).unwrap_or(now);
+        let duration = end_time.saturating_sub(entry.time);
+        match entry.to_state {
+            TaskState::Queued => summary.queued_ms += duration,
+            TaskState::Running => summary.running_ms += duration,
+            TaskState::Paused => summary.paused_ms += duration,
+            _ => {}
+        }
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u64, time: u64, from_state: Option<TaskState>, to_state: TaskState) -> TaskJournalEntry {
+        TaskJournalEntry { seq, task_id: "task_1".to_string(), time, from_state, to_state, cause: "".to_string() }
+    }
+
+    #[test]
+    fn test_summarize_task_journal() {
+        assert!(summarize_task_journal(&[], 0).is_none());
+        //created(paused) -> queued -> running -> paused -> running -> done
+        let entries = vec![
+            entry(1, 1000, None, TaskState::Paused),
+            entry(2, 1100, Some(TaskState::Paused), TaskState::Queued),
+            entry(3, 1500, Some(TaskState::Queued), TaskState::Running),
+            entry(4, 3500, Some(TaskState::Running), TaskState::Paused),
+            entry(5, 4500, Some(TaskState::Paused), TaskState::Running),
+            entry(6, 5500, Some(TaskState::Running), TaskState::Done),
+        ];
+        let summary = summarize_task_journal(&entries, 100000).unwrap();
+        assert_eq!(summary.create_time, 1000);
+        assert_eq!(summary.first_run_time, Some(1500));
+        assert_eq!(summary.finish_time, Some(5500));
+        assert_eq!(summary.final_state, Some(TaskState::Done));
+        assert_eq!((summary.queued_ms, summary.running_ms, summary.paused_ms), (400, 3000, 1100));
+        assert_eq!((summary.pause_count, summary.resume_count), (1, 1));
+
+        //运行中的task计算到now
+        let summary = summarize_task_journal(&entries[..3], 2000).unwrap();
+        assert_eq!(summary.finish_time, None);
+        assert_eq!(summary.running_ms, 500);
+
+        //done之后被重新打开,继续运行后再次结束
+        let mut entries = entries;
+        entries.push(entry(7, 8000, Some(TaskState::Done), TaskState::Paused));
+        entries.push(entry(8, 9000, Some(TaskState::Paused), TaskState::Running));
+        let summary = summarize_task_journal(&entries, 9500).unwrap();
+        assert_eq!((summary.finish_time, summary.final_state), (None, None));
+        assert_eq!((summary.running_ms, summary.paused_ms), (3500, 2100));
+        entries.push(entry(9, 10000, Some(TaskState::Running), TaskState::Done));
+        let summary = summarize_task_journal(&entries, 100000).unwrap();
+        assert_eq!(summary.finish_time, Some(10000));
+        assert_eq!(summary.final_state, Some(TaskState::Done));
+        assert_eq!((summary.queued_ms, summary.running_ms, summary.paused_ms), (400, 4000, 2100));
+        assert_eq!((summary.pause_count, summary.resume_count), (1, 2));
+    }
+}
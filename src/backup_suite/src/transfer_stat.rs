@@ -0,0 +1,75 @@
+#![allow(unused)]
+//checkpoint的传输统计:checkpoint完成时根据task的运行时统计计算并保存,用于比较不同次备份的速度
+//wall time从checkpoint创建算起,包括暂停和失败重试的时间;传输时间只包括传输线程运行的时间
+//速度和重试次数只在内存中统计,服务重启前的部分不计算在内
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use crate::work_task::TaskRuntimeStat;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointTransferStat {
+    pub wall_time_ms: u64,
+    pub transfer_time_ms: u64,
+    pub transferred_size: u64,//传输线程写入target的大小
+    pub avg_speed: u64,//bytes/s,transferred_size/transfer_time
+    pub peak_speed: u64,//bytes/s,滑动平均速度的最大值
+    pub retry_count: u64,//item读写的重试次数加上task失败后自动重试的次数
+    pub dedup_ratio: f64,//同DedupStat::dedup_ratio,没有统计时为0
+}
+
+impl CheckpointTransferStat {
+    pub fn build(wall_time_ms: u64, runtime_stat: &TaskRuntimeStat, retry_attempt: u32, dedup_ratio: f64) -> Self {
+        let avg_speed = if runtime_stat.transfer_time_ms > 0 {
+            runtime_stat.transferred_size * 1000 / runtime_stat.transfer_time_ms
+        } else {
+            0
+        };
+        Self {
+            wall_time_ms,
+            transfer_time_ms: runtime_stat.transfer_time_ms,
+            transferred_size: runtime_stat.transferred_size,
+            avg_speed,
+            //传输时间小于采样间隔时没有滑动平均速度
+            peak_speed: runtime_stat.peak_speed.max(avg_speed),
+            retry_count: runtime_stat.retry_count + retry_attempt as u64,
+            dedup_ratio,
+        }
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        json!({
+            "wall_time_ms": self.wall_time_ms,
+            "transfer_time_ms": self.transfer_time_ms,
+            "transferred_size": self.transferred_size,
+            "avg_speed": self.avg_speed,
+            "peak_speed": self.peak_speed,
+            "retry_count": self.retry_count,
+            "dedup_ratio": self.dedup_ratio,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_transfer_stat() {
+        let mut runtime_stat = TaskRuntimeStat::default();
+        runtime_stat.transfer_time_ms = 4000;
+        runtime_stat.transferred_size = 8 * 1024 * 1024;
+        runtime_stat.peak_speed = 3 * 1024 * 1024;
+        runtime_stat.retry_count = 2;
+        let stat = CheckpointTransferStat::build(10_000, &runtime_stat, 1, 1.5);
+        assert_eq!(stat.wall_time_ms, 10_000);
+        assert_eq!(stat.avg_speed, 2 * 1024 * 1024);
+        assert_eq!(stat.peak_speed, 3 * 1024 * 1024);
+        assert_eq!(stat.retry_count, 3);
+        assert_eq!(stat.to_json_value()["dedup_ratio"], 1.5);
+
+        //没有传输数据(所有chunk都已经在target上)
+        let stat = CheckpointTransferStat::build(500, &TaskRuntimeStat::default(), 0, 0.0);
+        assert_eq!(stat.avg_speed, 0);
+        assert_eq!(stat.peak_speed, 0);
+    }
+}
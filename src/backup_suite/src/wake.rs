@@ -0,0 +1,139 @@
+#![allow(unused)]
+//task开始传输前执行的hook,配置在BackupPlanOptions.pre_task_hooks中,按顺序执行,任何一个失败task就失败
+//wake_on_lan: target所在的机器(比如家里的NAS)可能在休眠,先发送WoL魔术包,
+//  再每隔一段时间探测target是否可以访问(同时重发魔术包),直到可以访问或者超过wake_timeout_secs
+//  配置了probe_addr时用TCP连接探测,否则通过target provider探测
+//plan可以通过RPC修改,这里的hook只能是内置的类型,不能执行命令;需要执行脚本的hook使用daemon配置的hook_dir
+use std::future::Future;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use log::*;
+use tokio::net::{TcpStream, UdpSocket};
+
+pub const DEFAULT_WOL_BROADCAST_ADDR: &str = "255.255.255.255:9";
+pub const DEFAULT_WAKE_TIMEOUT_SECS: u64 = 120;
+//每次探测的超时
+const WAKE_PROBE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PreTaskHook {
+    WakeOnLan(WakeOnLanHook),
+}
+
+fn default_broadcast_addr() -> String {
+    DEFAULT_WOL_BROADCAST_ADDR.to_string()
+}
+
+fn default_wake_timeout_secs() -> u64 {
+    DEFAULT_WAKE_TIMEOUT_SECS
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WakeOnLanHook {
+    pub mac_address: String,//aa:bb:cc:dd:ee:ff 或 aa-bb-cc-dd-ee-ff
+    #[serde(default = "default_broadcast_addr")]
+    pub broadcast_addr: String,
+    #[serde(default)]
+    pub probe_addr: Option<String>,//host:port
+    #[serde(default = "default_wake_timeout_secs")]
+    pub wake_timeout_secs: u64,
+}
+
+//魔术包:6个0xff,然后是重复16次的mac地址
+pub fn build_magic_packet(mac_address: &str) -> Result<Vec<u8>> {
+    let mac_bytes = mac_address.split(|c| c == ':' || c == '-')
+        .map(|part| u8::from_str_radix(part, 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| anyhow::anyhow!("invalid mac address: {}", mac_address))?;
+    if mac_bytes.len() != 6 {
+        return Err(anyhow::anyhow!("invalid mac address: {}", mac_address));
+    }
+    let mut packet = vec![0xffu8; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac_bytes);
+    }
+    Ok(packet)
+}
+
+pub async fn send_magic_packet(mac_address: &str, broadcast_addr: &str) -> Result<()> {
+    let packet = build_magic_packet(mac_address)?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&packet, broadcast_addr).await?;
+    Ok(())
+}
+
+pub async fn probe_tcp_addr(addr: &str) -> bool {
+    let result = tokio::time::timeout(Duration::from_secs(WAKE_PROBE_TIMEOUT_SECS), TcpStream::connect(addr)).await;
+    matches!(result, Ok(Ok(_)))
+}
+
+impl WakeOnLanHook {
+    //target已经可以访问时不发送魔术包
+    pub async fn wake_and_wait<F, Fut>(&self, poll_interval: Duration, probe: F) -> Result<()>
+        where F: Fn() -> Fut, Fut: Future<Output = bool> {
+        if probe().await {
+            return Ok(());
+        }
+        let start = tokio::time::Instant::now();
+        let wake_timeout = Duration::from_secs(self.wake_timeout_secs);
+        loop {
+            info!("send wake on lan packet to {} via {}", self.mac_address, self.broadcast_addr);
+            if let Err(err) = send_magic_packet(self.mac_address.as_str(), self.broadcast_addr.as_str()).await {
+                //mac地址错误时重试没有意义
+                build_magic_packet(self.mac_address.as_str())?;
+                warn!("send wake on lan packet to {} failed: {}", self.mac_address, err);
+            }
+            tokio::time::sleep(poll_interval).await;
+            if probe().await {
+                info!("target of {} is awake after {:?}", self.mac_address, start.elapsed());
+                return Ok(());
+            }
+            if start.elapsed() >= wake_timeout {
+                return Err(anyhow::anyhow!("target of {} is not reachable after wake {} secs", self.mac_address, self.wake_timeout_secs));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_build_magic_packet() {
+        let packet = build_magic_packet("01:23:45:67:89:ab").unwrap();
+        assert_eq!(packet.len(), 102);
+        assert_eq!(&packet[..6], &[0xff; 6]);
+        assert_eq!(&packet[6..12], &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab]);
+        assert_eq!(&packet[96..], &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab]);
+        assert_eq!(build_magic_packet("01-23-45-67-89-AB").unwrap(), packet);
+        assert!(build_magic_packet("01:23:45:67:89").is_err());
+        assert!(build_magic_packet("01:23:45:67:89:zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wake_and_wait() {
+        let hook: WakeOnLanHook = serde_json::from_str(r#"{"mac_address":"01:23:45:67:89:ab","broadcast_addr":"127.0.0.1:9","wake_timeout_secs":1}"#).unwrap();
+        //第3次探测时target醒来
+        let probe_count = Arc::new(AtomicU32::new(0));
+        let counter = probe_count.clone();
+        hook.wake_and_wait(Duration::from_millis(10), move || {
+            let counter = counter.clone();
+            async move { counter.fetch_add(1, Ordering::SeqCst) >= 2 }
+        }).await.unwrap();
+        assert_eq!(probe_count.load(Ordering::SeqCst), 3);
+
+        let result = hook.wake_and_wait(Duration::from_millis(100), || async { false }).await;
+        assert!(result.is_err());
+
+        let hook = PreTaskHook::WakeOnLan(hook);
+        let value = serde_json::to_value(&hook).unwrap();
+        assert_eq!(value["type"], "wake_on_lan");
+        assert_eq!(serde_json::from_value::<PreTaskHook>(value).unwrap(), hook);
+    }
+}
@@ -0,0 +1,143 @@
+#![allow(unused)]
+//task超时检测:target不再响应时正在进行的读写可能永远不返回,task会一直停在Running
+//engine的后台循环定期检查运行中的task,超过最长运行时间,或者很长时间没有进度(没有传输数据,也没有处理新的item)时,
+//把task标记为Failed并中断正在进行的读写,失败的备份task之后按plan的重试策略重试
+//运行时间从task这次开始运行时算起,暂停的时间不计算在内
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaskTimeoutPolicy {
+    pub max_runtime_secs: u64,//task一次运行的最长时间,0为不限制
+    pub stall_timeout_secs: u64,//没有任何进度超过这个时间认为task卡住了,0为不检测
+}
+
+//默认不检测:有些target写一个大chunk时不报告进度,一直在正常传输的task也会被误判为卡住,需要时在plan中开启
+impl Default for TaskTimeoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_runtime_secs: 0,
+            stall_timeout_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskTimeoutReason {
+    MaxRuntimeExceeded { runtime_secs: u64 },
+    Stalled { idle_secs: u64 },
+}
+
+impl TaskTimeoutReason {
+    pub fn log_event_type(&self) -> &'static str {
+        match self {
+            TaskTimeoutReason::MaxRuntimeExceeded { .. } => "TASK_TIMEOUT",
+            TaskTimeoutReason::Stalled { .. } => "TASK_STALLED",
+        }
+    }
+}
+
+impl std::fmt::Display for TaskTimeoutReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskTimeoutReason::MaxRuntimeExceeded { runtime_secs } => write!(f, "task exceeded max runtime, has run {} secs", runtime_secs),
+            TaskTimeoutReason::Stalled { idle_secs } => write!(f, "task stalled, no progress for {} secs", idle_secs),
+        }
+    }
+}
+
+//task的进度:已传输的大小,item数量(枚举阶段),已完成的item数量,任何一个变化都认为有进度
+pub type TaskProgress = (u64, u64, u64);
+
+#[derive(Debug, Clone, Copy)]
+struct TaskProgressMark {
+    progress: TaskProgress,
+    run_start_time: u64,
+    last_progress_time: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct TaskWatchdog {
+    marks: HashMap<String, TaskProgressMark>,//运行中的task
+    timeout_tasks: HashSet<String>,//已经因为超时被标记为Failed,work thread还没有退出的task
+}
+
+impl TaskWatchdog {
+    //删除已经不在运行的task,下次运行时重新计时
+    pub fn retain_running(&mut self, running_task_ids: &HashSet<String>) {
+        self.marks.retain(|task_id, _| running_task_ids.contains(task_id));
+    }
+
+    //检查运行中的task,超时的task记录到timeout_tasks中并返回原因
+    pub fn check(&mut self, task_id: &str, progress: TaskProgress, policy: &TaskTimeoutPolicy, now: u64) -> Option<TaskTimeoutReason> {
+        let mark = self.marks.entry(task_id.to_string()).or_insert(TaskProgressMark {
+            progress,
+            run_start_time: now,
+            last_progress_time: now,
+        });
+        if mark.progress != progress {
+            mark.progress = progress;
+            mark.last_progress_time = now;
+        }
+
+        let runtime_secs = now.saturating_sub(mark.run_start_time) / 1000;
+        let idle_secs = now.saturating_sub(mark.last_progress_time) / 1000;
+        let reason = if policy.max_runtime_secs > 0 && runtime_secs >= policy.max_runtime_secs {
+            TaskTimeoutReason::MaxRuntimeExceeded { runtime_secs }
+        } else if policy.stall_timeout_secs > 0 && idle_secs >= policy.stall_timeout_secs {
+            TaskTimeoutReason::Stalled { idle_secs }
+        } else {
+            return None;
+        };
+        self.marks.remove(task_id);
+        self.timeout_tasks.insert(task_id.to_string());
+        Some(reason)
+    }
+
+    //work thread退出时调用,返回task是否因为超时失败
+    pub fn take_timeout_task(&mut self, task_id: &str) -> bool {
+        self.timeout_tasks.remove(task_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_watchdog() {
+        let policy = TaskTimeoutPolicy { max_runtime_secs: 3600, stall_timeout_secs: 600 };
+        let mut watchdog = TaskWatchdog::default();
+        let start = 1_000_000;
+        assert!(watchdog.check("task_1", (0, 10, 0), &policy, start).is_none());
+        //有进度时重新计算卡住的时间
+        assert!(watchdog.check("task_1", (100, 10, 1), &policy, start + 500 * 1000).is_none());
+        assert!(watchdog.check("task_1", (100, 10, 1), &policy, start + 1000 * 1000).is_none());
+        assert_eq!(watchdog.check("task_1", (100, 10, 1), &policy, start + 1100 * 1000),
+            Some(TaskTimeoutReason::Stalled { idle_secs: 600 }));
+        assert!(watchdog.take_timeout_task("task_1"));
+        assert!(!watchdog.take_timeout_task("task_1"));
+
+        //一直有进度但是运行时间太长
+        for i in 0..=6 {
+            let reason = watchdog.check("task_2", (i, 0, 0), &policy, start + i * 600 * 1000);
+            if i < 6 {
+                assert!(reason.is_none());
+            } else {
+                assert_eq!(reason, Some(TaskTimeoutReason::MaxRuntimeExceeded { runtime_secs: 3600 }));
+            }
+        }
+
+        //暂停后重新运行,重新计时
+        assert!(watchdog.check("task_3", (0, 0, 0), &policy, start).is_none());
+        watchdog.retain_running(&HashSet::new());
+        assert!(watchdog.check("task_3", (0, 0, 0), &policy, start + 3000 * 1000).is_none());
+        assert!(watchdog.check("task_3", (0, 0, 0), &policy, start + 3500 * 1000).is_none());
+
+        let disabled = TaskTimeoutPolicy { max_runtime_secs: 0, stall_timeout_secs: 0 };
+        assert_eq!(TaskTimeoutPolicy::default(), disabled);
+        assert!(watchdog.check("task_4", (0, 0, 0), &disabled, start).is_none());
+        assert!(watchdog.check("task_4", (0, 0, 0), &disabled, start + 100 * 3600 * 1000).is_none());
+    }
+}
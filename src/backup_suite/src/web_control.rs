@@ -1,14 +1,23 @@
 #![allow(unused)]
 use crate::engine::*;
-use crate::task_db::BackupPlanConfig;
+use crate::task_db::{BackupPlanConfig, BackupPlanOptions};
+use crate::approval::DestructiveOperation;
+use crate::contents_index::ContentsQuery;
+use crate::restore_target::set_restore_target_url;
+use crate::plan_import::PlanImportFormat;
+use crate::plan_template::list_plan_templates;
+use crate::quota::QuotaScope;
 use ::kRPC::*;
 use async_trait::async_trait;
-use buckyos_backup_lib::RestoreConfig;
-use buckyos_kit::get_buckyos_system_bin_dir;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use buckyos_backup_lib::{AgentEnrollRequest, AgentHeartbeat, RestoreConfig, TARGET_LAYOUT_VERSION};
+use buckyos_kit::{get_buckyos_system_bin_dir, buckyos_get_unix_timestamp};
 use cyfs_gateway_lib::*;
 use cyfs_warp::*;
 use log::*;
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::Path;
 use std::result::Result;
@@ -21,7 +30,8 @@ impl WebControlServer {
         Self {}
     }
 
-    async fn create_backup_plan(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+    //create_backup_plan和validate_plan使用相同的参数
+    fn parse_backup_plan_config(req: &RPCRequest) -> Result<BackupPlanConfig, RPCErrors> {
         let source_type = req.params.get("source_type");
         let source_url = req.params.get("source");
         let target_type = req.params.get("target_type");
@@ -56,24 +66,36 @@ impl WebControlServer {
 
         let title = title.unwrap().as_str().unwrap();
         let description = description.unwrap().as_str().unwrap();
-        let plan_id: String;
-        let engine = DEFAULT_ENGINE.lock().await;
+        let options = match req.params.get("options") {
+            Some(options) => Some(
+                serde_json::from_value::<BackupPlanOptions>(options.clone())
+                    .map_err(|e| RPCErrors::ParseRequestError(format!("invalid options: {}", e)))?,
+            ),
+            None => None,
+        };
         match type_str {
             "c2c" => {
-                let new_plan =
+                let mut new_plan =
                     BackupPlanConfig::chunk2chunk(source_url, target_url, title, description);
-                plan_id = engine
-                    .create_backup_plan(new_plan)
-                    .await
-                    .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
-            }
-            _ => {
-                return Err(RPCErrors::ParseRequestError(format!(
-                    "unknown type_str: {}",
-                    type_str
-                )));
+                if let Some(options) = options {
+                    new_plan.options = options;
+                }
+                Ok(new_plan)
             }
+            _ => Err(RPCErrors::ParseRequestError(format!(
+                "unknown type_str: {}",
+                type_str
+            ))),
         }
+    }
+
+    async fn create_backup_plan(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let new_plan = Self::parse_backup_plan_config(&req)?;
+        let engine = DEFAULT_ENGINE.lock().await;
+        let plan_id = engine
+            .create_backup_plan(new_plan)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let result = json!({
             "plan_id": plan_id
@@ -81,12 +103,121 @@ impl WebControlServer {
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
     }
 
+    //创建plan前的检查,返回所有诊断,valid为false时create_backup_plan会失败
+    async fn validate_plan(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let new_plan = Self::parse_backup_plan_config(&req)?;
+        let engine = DEFAULT_ENGINE.lock().await;
+        let report = engine.validate_plan(&new_plan).await;
+        let result = json!({
+            "valid": report.is_valid(),
+            "diagnostics": report.diagnostics
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_plan_templates(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let result = json!({
+            "templates": list_plan_templates()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //params为模板需要的参数(字符串),dry_run时只返回生成的plan和检查结果
+    async fn create_plan_from_template(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let template_id = req.params.get("template_id").and_then(|v| v.as_str());
+        if template_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "template_id is required".to_string(),
+            ));
+        }
+        let params: HashMap<String, String> = match req.params.get("params") {
+            Some(params) => serde_json::from_value(params.clone())
+                .map_err(|e| RPCErrors::ParseRequestError(format!("invalid params: {}", e)))?,
+            None => HashMap::new(),
+        };
+        let dry_run = req.params.get("dry_run").and_then(|v| v.as_bool()).unwrap_or(false);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let (plan_id, plan, report) = engine
+            .create_plan_from_template(template_id.unwrap(), &params, dry_run)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "plan_id": plan_id,
+            "plan": plan.to_json_value(),
+            "valid": report.is_valid(),
+            "diagnostics": report.diagnostics
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //format为restic/duplicati/rclone,content为配置文件(以及crontab)的内容
+    async fn import_plans(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let format = req.params.get("format").and_then(|v| v.as_str()).and_then(PlanImportFormat::from_str);
+        if format.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "format must be restic, duplicati or rclone".to_string(),
+            ));
+        }
+        let content = req.params.get("content").and_then(|v| v.as_str());
+        if content.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "content is required".to_string(),
+            ));
+        }
+        let dry_run = req.params.get("dry_run").and_then(|v| v.as_bool()).unwrap_or(false);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let (plans, warnings) = engine
+            .import_plans(format.unwrap(), content.unwrap(), dry_run)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let plans: Vec<Value> = plans.into_iter().map(|(imported_plan, plan_id, report)| json!({
+            "plan_id": plan_id,
+            "plan": imported_plan.plan.to_json_value(),
+            "notes": imported_plan.notes,
+            "valid": report.is_valid(),
+            "diagnostics": report.diagnostics,
+        })).collect();
+        let result = json!({
+            "plans": plans,
+            "warnings": warnings
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //enabled为true时进入维护模式,reason说明原因(比如迁移存储)
+    async fn set_maintenance_mode(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let enabled = req.params.get("enabled").and_then(|v| v.as_bool());
+        if enabled.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "enabled is required".to_string(),
+            ));
+        }
+        let reason = req.params.get("reason").and_then(|v| v.as_str()).unwrap_or("maintenance");
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .set_maintenance_mode(if enabled.unwrap() { Some(reason) } else { None })
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "maintenance": engine.get_maintenance_mode().await
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn get_maintenance_mode(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let result = json!({
+            "maintenance": engine.get_maintenance_mode().await
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
     async fn list_backup_plan(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
         let engine = DEFAULT_ENGINE.lock().await;
         let plans = engine
             .list_backup_plans()
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let result = json!({
             "backup_plans": plans
@@ -106,7 +237,7 @@ impl WebControlServer {
         let plan = engine
             .get_backup_plan(plan_id)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
         let mut result = plan.to_json_value();
         let is_running = engine.is_plan_have_running_backup_task(plan_id).await;
         result["is_running"] = json!(is_running);
@@ -128,16 +259,18 @@ impl WebControlServer {
         } else {
             None
         };
+        //客户端重试时带上相同的key,返回第一次创建的task
+        let idempotency_key = req.params.get("idempotency_key").and_then(|v| v.as_str());
         let engine = DEFAULT_ENGINE.lock().await;
         let task_id = engine
-            .create_backup_task(plan_id, real_parent_checkpoint_id)
+            .create_backup_task_idempotent(plan_id, real_parent_checkpoint_id, idempotency_key)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let task_info = engine
             .get_task_info(&task_id)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let result = task_info.to_json_value();
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
@@ -162,19 +295,24 @@ impl WebControlServer {
         }
         let plan_id = plan_id.unwrap().as_str().unwrap();
         let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap();
-        let restore_config = serde_json::from_value(restore_config.unwrap().clone())
+        let mut restore_config: RestoreConfig = serde_json::from_value(restore_config.unwrap().clone())
             .map_err(|err| RPCErrors::ParseRequestError("cfg format error".to_string()))?;
+        //从plan当前target以外的地方(副本,种子目录)恢复
+        if let Some(target_url) = req.params.get("target_url").and_then(|v| v.as_str()) {
+            set_restore_target_url(&mut restore_config, target_url);
+        }
 
+        let idempotency_key = req.params.get("idempotency_key").and_then(|v| v.as_str());
         let engine = DEFAULT_ENGINE.lock().await;
         let task_id = engine
-            .create_restore_task(plan_id, checkpoint_id, restore_config)
+            .create_restore_task_idempotent(plan_id, checkpoint_id, restore_config, idempotency_key)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let task_info = engine
             .get_task_info(&task_id)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let result = task_info.to_json_value();
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
@@ -194,7 +332,7 @@ impl WebControlServer {
         result_task_list = engine
             .list_backup_tasks(filter_str)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
 
         let result = json!({
             "task_list": result_task_list
@@ -214,8 +352,32 @@ impl WebControlServer {
         let task_info = engine
             .get_task_info(task_id)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
-        let result = task_info.to_json_value();
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let mut result = task_info.to_json_value();
+        result["queue_position"] = json!(engine.get_task_queue_position(task_id).await);
+        result["remaining_retry_attempts"] = json!(engine.get_task_remaining_retry_attempts(&task_info).await);
+        result["session"] = json!(engine.get_task_session_stat(task_id).await);
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn get_task_timeline(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let task_id = req.params.get("taskid");
+        if task_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "taskid is required".to_string(),
+            ));
+        }
+        let task_id = task_id.unwrap().as_str().unwrap();
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let (entries, summary) = engine
+            .get_task_timeline(task_id)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let journal: Vec<Value> = entries.iter().map(|entry| entry.to_json_value()).collect();
+        let result = json!({
+            "task_id": task_id,
+            "journal": journal,
+            "summary": summary.map(|summary| summary.to_json_value()),
+        });
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
     }
 
@@ -227,13 +389,16 @@ impl WebControlServer {
             ));
         }
         let task_id = task_id.unwrap().as_str().unwrap();
+        //force_run为true时不受同时运行task数量的限制
+        let force_run = req.params.get("force_run").and_then(|v| v.as_bool()).unwrap_or(false);
         let engine = DEFAULT_ENGINE.lock().await;
-        engine
-            .resume_work_task(task_id)
+        let queue_position = engine
+            .start_work_task(task_id, force_run)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
         let result = json!({
-            "result": "success"
+            "result": "success",
+            "queue_position": queue_position
         });
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
     }
@@ -250,13 +415,101 @@ impl WebControlServer {
         engine
             .pause_work_task(task_id)
             .await
-            .map_err(|e| RPCErrors::ReasonError(e.to_string()))?;
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn cancel_backup_task(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let task_id = req.params.get("taskid");
+        if task_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "taskid is required".to_string(),
+            ));
+        }
+        let task_id = task_id.unwrap().as_str().unwrap();
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .cancel_backup_task(task_id)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
         let result = json!({
             "result": "success"
         });
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
     }
 
+    //恢复task中每个冲突文件的处理结果
+    async fn get_restore_conflicts(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let task_id = req.params.get("taskid");
+        if task_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "taskid is required".to_string(),
+            ));
+        }
+        let task_id = task_id.unwrap().as_str().unwrap();
+        let engine = DEFAULT_ENGINE.lock().await;
+        let conflicts = engine
+            .get_restore_conflicts(task_id)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "conflicts": conflicts
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //恢复单个文件,不创建task:指定dest时恢复到dest目录,download为true时直接返回文件内容(base64)
+    async fn restore_single_item(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id");
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let item_path = req.params.get("item_path");
+        if item_path.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "item_path is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap();
+        let item_path = item_path.unwrap().as_str().unwrap();
+        let is_download = req.params.get("download").and_then(|v| v.as_bool()).unwrap_or(false);
+        //读取/恢复文件需要从target传输数据,不能在传输期间持有DEFAULT_ENGINE的锁
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        if is_download {
+            let (file_name, content) = engine
+                .read_single_item(checkpoint_id, item_path)
+                .await
+                .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+            let result = json!({
+                "file_name": file_name,
+                "size": content.len(),
+                "content": BASE64.encode(&content)
+            });
+            return Ok(RPCResponse::new(RPCResult::Success(result), req.seq));
+        }
+
+        let dest = req.params.get("dest");
+        if dest.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "dest is required".to_string(),
+            ));
+        }
+        let dest = dest.unwrap().as_str().unwrap();
+        let restore_path = engine
+            .restore_single_item(checkpoint_id, item_path, dest)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success",
+            "path": restore_path.to_string_lossy()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
     async fn validate_path(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
         let path = req.params.get("path");
         if path.is_none() {
@@ -287,6 +540,873 @@ impl WebControlServer {
         });
         Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
     }
+
+    //发起/批准破坏性操作的凭证,token必须是daemon配置中的管理员凭证,返回token的指纹
+    fn authenticate(engine: &BackupEngine, req: &RPCRequest) -> Result<String, RPCErrors> {
+        engine.authenticate(req.token.as_deref())
+            .map_err(|e| RPCErrors::ReasonError(format!("authenticate failed: {:#}", e)))
+    }
+
+    async fn request_destructive_operation(&self, req: &RPCRequest, operation: DestructiveOperation) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let requester = Self::authenticate(&engine, req)?;
+        let op_id = engine
+            .request_destructive_operation(operation, requester.as_str())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = match op_id {
+            Some(op_id) => json!({
+                "result": "pending",
+                "op_id": op_id
+            }),
+            None => json!({
+                "result": "success"
+            }),
+        };
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn delete_backup_plan(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id");
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let plan_id = plan_id.unwrap().as_str().unwrap().to_string();
+        self.request_destructive_operation(&req, DestructiveOperation::DeletePlan { plan_id }).await
+    }
+
+    async fn prune_checkpoint(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id");
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap().to_string();
+        self.request_destructive_operation(&req, DestructiveOperation::PruneCheckpoint { checkpoint_id }).await
+    }
+
+    //以target上的chunk为准修正checkpoint和item的状态
+    async fn reconcile_checkpoint(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id");
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap();
+        let engine = DEFAULT_ENGINE.lock().await;
+        let report = engine
+            .reconcile_checkpoint(checkpoint_id)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "report": report
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //裸机恢复向导,recovery_root默认/mnt/recovery,返回的脚本需要用户在救援系统中执行
+    async fn get_recovery_guide(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let recovery_root = req.params.get("recovery_root").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let result = engine
+            .get_recovery_guide(checkpoint_id.unwrap(), recovery_root)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //pinned为false时取消pin
+    async fn pin_checkpoint(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id");
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap();
+        let pinned = req.params.get("pinned").and_then(|v| v.as_bool()).unwrap_or(true);
+        let reason = req.params.get("reason").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .pin_checkpoint(checkpoint_id, pinned, reason)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_pinned_checkpoints(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let checkpoints = engine
+            .list_pinned_checkpoints()
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "checkpoints": checkpoints.iter().map(|checkpoint| json!({
+                "checkpoint_id": checkpoint.checkpoint_id,
+                "plan_id": checkpoint.owner_plan,
+                "checkpoint_index": checkpoint.checkpoint_index,
+                "create_time": checkpoint.create_time,
+                "pin_reason": checkpoint.pin_reason,
+            })).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //设置/解除法律保留,发起者的token必须是配置的法律保留管理员
+    async fn set_legal_hold(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let reason = req.params.get("reason").and_then(|v| v.as_str());
+        if reason.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "reason is required".to_string(),
+            ));
+        }
+        let hold = req.params.get("hold").and_then(|v| v.as_bool()).unwrap_or(true);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let operator = engine.authenticate_legal_hold_operator(req.token.as_deref())
+            .map_err(|e| RPCErrors::ReasonError(format!("authenticate failed: {:#}", e)))?;
+        let set_result = if hold {
+            engine.place_legal_hold(checkpoint_id.unwrap(), operator.as_str(), reason.unwrap()).await
+        } else {
+            engine.release_legal_hold(checkpoint_id.unwrap(), operator.as_str(), reason.unwrap()).await
+        };
+        set_result.map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_legal_holds(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let holds = engine
+            .list_legal_holds(plan_id)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "legal_holds": holds.iter().map(|hold| hold.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //object_id为空时返回所有记录,since_seq为上次收到的最后一条记录的seq
+    async fn list_audit_logs(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let object_id = req.params.get("object_id").and_then(|v| v.as_str());
+        let since_seq = req.params.get("since_seq").and_then(|v| v.as_u64()).unwrap_or(0);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let records = engine
+            .list_audit_logs(object_id, since_seq)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "records": records.iter().map(|record| record.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_checkpoints(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let checkpoints = engine
+            .list_checkpoints(plan_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let held_checkpoint_ids = engine
+            .list_legal_holds(plan_id)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?
+            .into_iter()
+            .map(|hold| hold.checkpoint_id)
+            .collect::<Vec<String>>();
+        let result = json!({
+            "checkpoints": checkpoints.iter().map(|(checkpoint, transfer_stat)| json!({
+                "checkpoint_id": checkpoint.checkpoint_id,
+                "plan_id": checkpoint.owner_plan,
+                "prev_checkpoint_id": checkpoint.prev_checkpoint_id,
+                "checkpoint_index": checkpoint.checkpoint_index,
+                "state": checkpoint.state.to_string(),
+                "create_time": checkpoint.create_time,
+                "pinned": checkpoint.pinned,
+                "legal_hold": held_checkpoint_ids.contains(&checkpoint.checkpoint_id),
+                "inventory_only": checkpoint.inventory_only,
+                "failed_item_count": checkpoint.failed_item_count,
+                "transfer_stat": transfer_stat.as_ref().map(|stat| stat.to_json_value()),
+            })).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //query的格式同ContentsQuery,没有query时返回所有item(最多limit个)
+    async fn query_checkpoint_contents(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let query: ContentsQuery = match req.params.get("query") {
+            Some(query) => serde_json::from_value(query.clone())
+                .map_err(|e| RPCErrors::ParseRequestError(format!("invalid query: {}", e)))?,
+            None => ContentsQuery::default(),
+        };
+        let engine = DEFAULT_ENGINE.lock().await;
+        let entries = engine
+            .query_checkpoint_contents(checkpoint_id.unwrap(), &query)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "items": entries.iter().map(|entry| entry.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn get_checkpoint_contents_summary(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let summary = engine
+            .get_checkpoint_contents_summary(checkpoint_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "extensions": summary.iter().map(|(extension, item_count, total_size)| json!({
+                "extension": extension,
+                "item_count": item_count,
+                "total_size": total_size,
+            })).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //种子目录只能是daemon配置的seed_dirs下的目录,导出/导入会读写本机文件和target,需要管理员凭证
+    fn resolve_seed_dir(engine: &BackupEngine, req: &RPCRequest, seed_dir: &str) -> Result<String, RPCErrors> {
+        engine.authenticate_admin(req.token.as_deref())
+            .map_err(|e| RPCErrors::ReasonError(format!("authenticate failed: {:#}", e)))?;
+        let seed_dir = engine.get_daemon_config().resolve_seed_dir(seed_dir)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        Ok(seed_dir.to_string_lossy().to_string())
+    }
+
+    //导出可能需要很长时间(TB级),不持有engine的锁
+    async fn export_seed(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let seed_dir = req.params.get("seed_dir").and_then(|v| v.as_str());
+        if seed_dir.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "seed_dir is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let seed_dir = Self::resolve_seed_dir(&engine, &req, seed_dir.unwrap())?;
+        let manifest = engine
+            .export_seed(checkpoint_id.unwrap(), seed_dir.as_str())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        Ok(RPCResponse::new(RPCResult::Success(manifest.to_json_value()), req.seq))
+    }
+
+    //target为空时导入到导出时plan的target
+    async fn import_seed(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let seed_dir = req.params.get("seed_dir").and_then(|v| v.as_str());
+        if seed_dir.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "seed_dir is required".to_string(),
+            ));
+        }
+        let target_url = req.params.get("target").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let seed_dir = Self::resolve_seed_dir(&engine, &req, seed_dir.unwrap())?;
+        let report = engine
+            .import_seed(seed_dir.as_str(), target_url)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        Ok(RPCResponse::new(RPCResult::Success(report.to_json_value()), req.seq))
+    }
+
+    async fn confirm_seed_import(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        Self::authenticate(&engine, &req)?;
+        engine
+            .confirm_seed_import(checkpoint_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn cancel_seed_export(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        Self::authenticate(&engine, &req)?;
+        let released_count = engine
+            .cancel_seed_export(checkpoint_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "released_count": released_count
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_seed_exports(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let records = engine
+            .list_seed_exports(plan_id)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "seed_exports": records.iter().map(|record| record.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //手动触发一次恢复演练,等演练结束后返回结果
+    async fn run_restore_drill(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let record = engine
+            .run_restore_drill(plan_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let mut result = serde_json::to_value(&record).unwrap();
+        result["success"] = json!(record.is_success());
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_restore_drills(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let limit = req.params.get("limit").and_then(|v| v.as_u64()).unwrap_or(20) as u32;
+        let engine = DEFAULT_ENGINE.lock().await;
+        let records = engine
+            .list_restore_drills(plan_id, limit)
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "restore_drills": records.iter().map(|record| {
+                let mut value = serde_json::to_value(record).unwrap();
+                value["success"] = json!(record.is_success());
+                value
+            }).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //指定plan_id时只返回这个plan的报告,否则返回所有配置了SLA的plan
+    async fn get_sla_report(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let reports = match plan_id {
+            Some(plan_id) => engine.get_sla_report(plan_id).await.map(|report| vec![report]),
+            None => engine.list_sla_reports().await,
+        }.map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "reports": reports.iter().map(|report| report.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_failed_items(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap();
+        let engine = DEFAULT_ENGINE.lock().await;
+        let items = engine
+            .list_failed_backup_items(checkpoint_id)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "checkpoint_id": checkpoint_id,
+            "items": items.iter().map(|(item_id, reason)| json!({
+                "item_id": item_id,
+                "reason": reason,
+            })).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn retry_failed_items(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id").and_then(|v| v.as_str());
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let (task_id, item_count) = engine
+            .retry_failed_items(checkpoint_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "task_id": task_id,
+            "item_count": item_count,
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn remove_target(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let target_url = req.params.get("target");
+        if target_url.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "target is required".to_string(),
+            ));
+        }
+        let target_url = target_url.unwrap().as_str().unwrap().to_string();
+        self.request_destructive_operation(&req, DestructiveOperation::RemoveTarget { target_url }).await
+    }
+
+    async fn approve_operation(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let op_id = req.params.get("op_id");
+        if op_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "op_id is required".to_string(),
+            ));
+        }
+        let op_id = op_id.unwrap().as_str().unwrap();
+        let approve = req.params.get("approve").and_then(|v| v.as_bool()).unwrap_or(true);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let approver = Self::authenticate(&engine, &req)?;
+        let op_result = if approve {
+            engine.approve_pending_operation(op_id, approver.as_str()).await
+        } else {
+            engine.reject_pending_operation(op_id, approver.as_str()).await
+        };
+        op_result.map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_pending_operations(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let ops = engine
+            .list_pending_operations()
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "pending_operations": ops.iter().map(|op| op.to_json_value()).collect::<Vec<Value>>()
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //webui轮询task/checkpoint事件,since_seq为上次收到的最后一个事件的seq
+    async fn get_events(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let since_seq = req.params.get("since_seq").and_then(|v| v.as_u64()).unwrap_or(0);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let events = engine.get_event_bus().get_recent_events(since_seq);
+        let result = json!({
+            "events": events
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //列出已注册的source/target provider及其能力
+    async fn list_providers(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let providers = engine.list_providers();
+        let result = json!({
+            "providers": providers
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //重新加载插件和plan,修改插件目录或者直接修改db中的plan后调用,不需要重启服务
+    async fn reload_engine(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let report = engine.reload().await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = report.to_json_value();
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //模拟plan的定时备份,返回[start_time, end_time)内会触发备份的时间(ms)
+    async fn simulate_plan_schedule(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let start_time = req.params.get("start_time").and_then(|v| v.as_u64()).unwrap_or(buckyos_get_unix_timestamp() * 1000);
+        let end_time = req.params.get("end_time").and_then(|v| v.as_u64()).unwrap_or(start_time + 7 * 24 * 3600 * 1000);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let fire_times = engine
+            .simulate_plan_schedule(plan_id.unwrap(), start_time, end_time)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "fire_times": fire_times
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //预估plan下一次备份需要上传的大小和时间,不创建task
+    async fn estimate_backup(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let estimate = engine
+            .estimate_backup(plan_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "estimate": estimate
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //plan的目录变化热度,since_time默认30天前,depth为合并到的目录层级(默认1)
+    async fn get_change_heatmap(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let since_time = req.params.get("since_time").and_then(|v| v.as_u64())
+            .unwrap_or((buckyos_get_unix_timestamp() * 1000).saturating_sub(30 * 24 * 3600 * 1000));
+        let depth = req.params.get("depth").and_then(|v| v.as_u64()).unwrap_or(1);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let heatmap = engine
+            .get_plan_change_heatmap(plan_id.unwrap(), since_time, depth as usize)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "heatmap": heatmap
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //本地source和engine数据目录,快照目录,本地target重叠的警告,没有plan_id时检查所有plan
+    async fn check_source_overlap(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let warnings = engine
+            .check_source_overlap(plan_id)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "warnings": warnings
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //plan中每个完成的checkpoint被哪些保留规则保留,rules为空的会在下次备份完成后删除
+    async fn get_plan_retention(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        if plan_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "plan_id is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let decisions = engine
+            .evaluate_plan_retention(plan_id.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "checkpoints": decisions
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn health(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let (_, report) = engine.check_health().await;
+        Ok(RPCResponse::new(RPCResult::Success(report), req.seq))
+    }
+
+    async fn get_target_stats(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let target_url = req.params.get("target").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let stats = engine
+            .get_target_stats(target_url)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "target_stats": stats
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn get_target_layout(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let target_url = req.params.get("target").and_then(|v| v.as_str());
+        if target_url.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "target is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let marker = engine
+            .get_target_layout(target_url.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "layout": marker,
+            "supported_version": TARGET_LAYOUT_VERSION,
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //迁移数据可能需要很长时间,不持有engine的锁
+    async fn upgrade_target_layout(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let target_url = req.params.get("target").and_then(|v| v.as_str());
+        if target_url.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "target is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let (from_version, to_version) = engine
+            .upgrade_target_layout(target_url.unwrap())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "from_version": from_version,
+            "to_version": to_version,
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //每个target在spool中等待上传的记录数/大小/失败中的记录数
+    async fn get_spool_status(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let status = engine
+            .get_spool_status()
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "spool_status": status
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //立即上传到期的spool记录,不等后台循环
+    async fn drain_spool(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await.clone();
+        let uploaded_count = engine
+            .drain_spool()
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "uploaded_count": uploaded_count
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //按plan和target汇总的逻辑大小/实际存储大小,plan_id和target都是可选的过滤条件
+    async fn get_dedup_stats(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let plan_id = req.params.get("plan_id").and_then(|v| v.as_str());
+        let target_url = req.params.get("target").and_then(|v| v.as_str());
+        let engine = DEFAULT_ENGINE.lock().await;
+        let stats = engine
+            .get_dedup_stats(plan_id, target_url)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "dedup_stats": stats
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //scope为plan/tenant/target,key为plan_id/租户/target url,没有max_bytes时取消配额
+    async fn set_storage_quota(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let scope = req.params.get("scope").and_then(|v| v.as_str()).and_then(QuotaScope::from_str);
+        if scope.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "scope must be plan, tenant or target".to_string(),
+            ));
+        }
+        let scope_key = req.params.get("key").and_then(|v| v.as_str());
+        if scope_key.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "key is required".to_string(),
+            ));
+        }
+        let max_bytes = req.params.get("max_bytes").and_then(|v| v.as_u64());
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .set_storage_quota(scope.unwrap(), scope_key.unwrap(), max_bytes)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn get_storage_quotas(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let quotas = engine
+            .get_storage_quotas()
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "quotas": quotas
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //返回用口令加密的chunk命名密钥,由管理员另外保存,只有配置过的管理员凭证能导出
+    async fn export_chunk_naming_key(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let target_url = req.params.get("target_url").and_then(|v| v.as_str());
+        if target_url.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "target_url is required".to_string(),
+            ));
+        }
+        let passphrase = req.params.get("passphrase").and_then(|v| v.as_str());
+        if passphrase.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "passphrase is required".to_string(),
+            ));
+        }
+        let engine = DEFAULT_ENGINE.lock().await;
+        let operator = engine.authenticate_admin(req.token.as_deref())
+            .map_err(|e| RPCErrors::ReasonError(format!("authenticate failed: {:#}", e)))?;
+        let escrow = engine
+            .export_chunk_naming_key(target_url.unwrap(), passphrase.unwrap(), operator.as_str())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "key_id": escrow.key_id,
+            "escrow": escrow.to_json_string(),
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //ttl_secs为token的有效期,默认24小时;token可以让任何机器注册为agent,只有管理员能创建
+    async fn create_agent_enroll_token(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let ttl_ms = req.params.get("ttl_secs").and_then(|v| v.as_u64()).map(|ttl_secs| ttl_secs * 1000);
+        let engine = DEFAULT_ENGINE.lock().await;
+        let operator = engine.authenticate_admin(req.token.as_deref())
+            .map_err(|e| RPCErrors::ReasonError(format!("authenticate failed: {:#}", e)))?;
+        let (token, expire_time) = engine
+            .create_agent_enroll_token(ttl_ms, operator.as_str())
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "token": token,
+            "expire_time": expire_time,
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //由backup_agent调用,参数为AgentEnrollRequest
+    async fn enroll_agent(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let request = serde_json::from_value::<AgentEnrollRequest>(req.params.clone())
+            .map_err(|e| RPCErrors::ParseRequestError(format!("invalid enroll request: {}", e)))?;
+        let engine = DEFAULT_ENGINE.lock().await;
+        let agent = engine
+            .enroll_agent(&request)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "agent": agent
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //由backup_agent定时调用,参数为AgentHeartbeat
+    async fn agent_heartbeat(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let heartbeat = serde_json::from_value::<AgentHeartbeat>(req.params.clone())
+            .map_err(|e| RPCErrors::ParseRequestError(format!("invalid heartbeat: {}", e)))?;
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .agent_heartbeat(&heartbeat)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    async fn list_agents(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let engine = DEFAULT_ENGINE.lock().await;
+        let agents = engine
+            .list_agents()
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "agents": agents
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
+
+    //确认因为异常检测而处于WAIT_CONFIRM的checkpoint
+    async fn confirm_checkpoint(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let checkpoint_id = req.params.get("checkpoint_id");
+        if checkpoint_id.is_none() {
+            return Err(RPCErrors::ParseRequestError(
+                "checkpoint_id is required".to_string(),
+            ));
+        }
+        let checkpoint_id = checkpoint_id.unwrap().as_str().unwrap();
+        let accept = req.params.get("accept").and_then(|v| v.as_bool()).unwrap_or(true);
+        let engine = DEFAULT_ENGINE.lock().await;
+        engine
+            .confirm_checkpoint(checkpoint_id, accept)
+            .await
+            .map_err(|e| RPCErrors::ReasonError(format!("{:#}", e)))?;
+        let result = json!({
+            "result": "success"
+        });
+        Ok(RPCResponse::new(RPCResult::Success(result), req.seq))
+    }
 }
 
 #[async_trait]
@@ -298,16 +1418,74 @@ impl kRPCHandler for WebControlServer {
     ) -> Result<RPCResponse, RPCErrors> {
         match req.method.as_str() {
             "create_backup_plan" => self.create_backup_plan(req).await,
+            "validate_plan" => self.validate_plan(req).await,
+            "list_plan_templates" => self.list_plan_templates(req).await,
+            "create_plan_from_template" => self.create_plan_from_template(req).await,
+            "import_plans" => self.import_plans(req).await,
+            "set_maintenance_mode" => self.set_maintenance_mode(req).await,
+            "get_maintenance_mode" => self.get_maintenance_mode(req).await,
             "list_backup_plan" => self.list_backup_plan(req).await,
             "get_backup_plan" => self.get_backup_plan(req).await,
             "create_backup_task" => self.create_backup_task(req).await,
             "create_restore_task" => self.create_restore_task(req).await,
             "get_task_info" => self.get_task_info(req).await,
+            "get_task_timeline" => self.get_task_timeline(req).await,
             "resume_backup_task" => self.resume_backup_task(req).await,
             "pause_backup_task" => self.pause_backup_task(req).await,
+            "cancel_backup_task" => self.cancel_backup_task(req).await,
+            "get_restore_conflicts" => self.get_restore_conflicts(req).await,
+            "restore_single_item" => self.restore_single_item(req).await,
             "list_backup_task" => self.list_backup_task(req).await,
             "validate_path" => self.validate_path(req).await,
             "is_plan_running" => self.is_plan_running(req).await,
+            "confirm_checkpoint" => self.confirm_checkpoint(req).await,
+            "delete_backup_plan" => self.delete_backup_plan(req).await,
+            "prune_checkpoint" => self.prune_checkpoint(req).await,
+            "reconcile_checkpoint" => self.reconcile_checkpoint(req).await,
+            "get_recovery_guide" => self.get_recovery_guide(req).await,
+            "pin_checkpoint" => self.pin_checkpoint(req).await,
+            "list_pinned_checkpoints" => self.list_pinned_checkpoints(req).await,
+            "set_legal_hold" => self.set_legal_hold(req).await,
+            "list_legal_holds" => self.list_legal_holds(req).await,
+            "list_audit_logs" => self.list_audit_logs(req).await,
+            "list_checkpoints" => self.list_checkpoints(req).await,
+            "query_checkpoint_contents" => self.query_checkpoint_contents(req).await,
+            "get_checkpoint_contents_summary" => self.get_checkpoint_contents_summary(req).await,
+            "export_seed" => self.export_seed(req).await,
+            "import_seed" => self.import_seed(req).await,
+            "confirm_seed_import" => self.confirm_seed_import(req).await,
+            "cancel_seed_export" => self.cancel_seed_export(req).await,
+            "list_seed_exports" => self.list_seed_exports(req).await,
+            "run_restore_drill" => self.run_restore_drill(req).await,
+            "list_restore_drills" => self.list_restore_drills(req).await,
+            "get_sla_report" => self.get_sla_report(req).await,
+            "list_failed_items" => self.list_failed_items(req).await,
+            "retry_failed_items" => self.retry_failed_items(req).await,
+            "remove_target" => self.remove_target(req).await,
+            "approve_operation" => self.approve_operation(req).await,
+            "list_pending_operations" => self.list_pending_operations(req).await,
+            "get_target_stats" => self.get_target_stats(req).await,
+            "get_target_layout" => self.get_target_layout(req).await,
+            "upgrade_target_layout" => self.upgrade_target_layout(req).await,
+            "get_spool_status" => self.get_spool_status(req).await,
+            "drain_spool" => self.drain_spool(req).await,
+            "get_dedup_stats" => self.get_dedup_stats(req).await,
+            "set_storage_quota" => self.set_storage_quota(req).await,
+            "get_storage_quotas" => self.get_storage_quotas(req).await,
+            "export_chunk_naming_key" => self.export_chunk_naming_key(req).await,
+            "create_agent_enroll_token" => self.create_agent_enroll_token(req).await,
+            "enroll_agent" => self.enroll_agent(req).await,
+            "agent_heartbeat" => self.agent_heartbeat(req).await,
+            "list_agents" => self.list_agents(req).await,
+            "health" => self.health(req).await,
+            "get_events" => self.get_events(req).await,
+            "list_providers" => self.list_providers(req).await,
+            "reload_engine" => self.reload_engine(req).await,
+            "simulate_plan_schedule" => self.simulate_plan_schedule(req).await,
+            "get_plan_retention" => self.get_plan_retention(req).await,
+            "estimate_backup" => self.estimate_backup(req).await,
+            "get_change_heatmap" => self.get_change_heatmap(req).await,
+            "check_source_overlap" => self.check_source_overlap(req).await,
             _ => Err(RPCErrors::UnknownMethod(req.method)),
         }
     }
@@ -5,11 +5,127 @@ use std::collections::HashMap;
 use crossbeam::queue::SegQueue;
 
 use anyhow::Result;
+use std::future::Future;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use buckyos_backup_lib::*;
 use log::*;
+use serde::{Serialize, Deserialize};
+use crate::io_throttle::ReadRateLimiter;
 
 const MAX_CACHE_SIZE:u64 = 1024*1024*512;
+const MAX_TRANSFER_CACHE_SIZE:u64 = 1024*1024*64;
+const SPEED_SAMPLE_INTERVAL_MS:u64 = 1000;
+const PROGRESS_EVENT_INTERVAL_MS:u64 = 1000;//传输大文件时发布TaskProgress事件的最小间隔
+
+//WorkTask的运行时统计,只在内存里维护,由work thread更新,给UI展示进度用
+#[derive(Debug, Clone, Default)]
+pub struct TaskRuntimeStat {
+    pub speed: u64,//bytes/s,滑动平均
+    pub eta_secs: Option<u64>,
+    pub prepare_time_ms: u64,
+    pub transfer_time_ms: u64,
+    pub last_item_id: Option<String>,
+    pub peak_speed: u64,
+    pub transferred_size: u64,//传输线程写入target的大小
+    pub retry_count: u64,//item读写失败稍后重试,以及读取期间被修改重新读取的次数
+    //正在传输的item: item_id -> (已经写入target的大小, item大小),item完成后才计入completed_size
+    //大文件一个item要传输很久,用这个显示item内部的进度
+    pub transferring_items: HashMap<String,(u64,u64)>,
+    last_progress_event_time: u64,
+    prepare_start_time: u64,
+    transfer_start_time: u64,
+    last_sample_time: u64,
+    last_sample_size: u64,
+}
+
+impl TaskRuntimeStat {
+    pub fn on_prepare_start(&mut self, now_ms: u64) {
+        self.prepare_start_time = now_ms;
+    }
+
+    pub fn on_prepare_end(&mut self, now_ms: u64) {
+        if self.prepare_start_time > 0 && now_ms > self.prepare_start_time {
+            self.prepare_time_ms += now_ms - self.prepare_start_time;
+        }
+        self.prepare_start_time = 0;
+    }
+
+    pub fn on_transfer_start(&mut self, now_ms: u64, completed_size: u64) {
+        self.transfer_start_time = now_ms;
+        self.last_sample_time = now_ms;
+        self.last_sample_size = completed_size;
+        self.transferring_items.clear();
+    }
+
+    pub fn on_transfer_end(&mut self, now_ms: u64) {
+        self.update_transfer_time(now_ms);
+        self.transfer_start_time = 0;
+        self.speed = 0;
+        self.eta_secs = None;
+        self.transferring_items.clear();
+    }
+
+    //copy loop每写入一段数据后调用,offset为item已经写入target的大小(包括断点续传之前写入的部分)
+    pub fn on_item_progress(&mut self, item_id: &str, offset: u64, size: u64) {
+        self.transferring_items.insert(item_id.to_string(), (offset.min(size), size));
+    }
+
+    //item完成或者中断时调用
+    pub fn on_item_end(&mut self, item_id: &str) {
+        self.transferring_items.remove(item_id);
+    }
+
+    pub fn transferring_size(&self) -> u64 {
+        self.transferring_items.values().map(|(offset, _)| *offset).sum()
+    }
+
+    //传输大文件时限制TaskProgress事件的频率
+    pub fn should_publish_progress(&mut self, now_ms: u64) -> bool {
+        if now_ms.saturating_sub(self.last_progress_event_time) < PROGRESS_EVENT_INTERVAL_MS {
+            return false;
+        }
+        self.last_progress_event_time = now_ms;
+        true
+    }
+
+    fn update_transfer_time(&mut self, now_ms: u64) {
+        if self.transfer_start_time > 0 && now_ms > self.transfer_start_time {
+            self.transfer_time_ms += now_ms - self.transfer_start_time;
+            self.transfer_start_time = now_ms;
+        }
+    }
+
+    //completed_size/total_size是task当前的累计值
+    pub fn on_progress(&mut self, now_ms: u64, completed_size: u64, total_size: u64) {
+        if self.last_sample_time == 0 {
+            self.last_sample_time = now_ms;
+            self.last_sample_size = completed_size;
+            return;
+        }
+        let elapsed = now_ms.saturating_sub(self.last_sample_time);
+        if elapsed < SPEED_SAMPLE_INTERVAL_MS {
+            return;
+        }
+        self.update_transfer_time(now_ms);
+        let delta = completed_size.saturating_sub(self.last_sample_size);
+        let current_speed = delta * 1000 / elapsed;
+        if self.speed == 0 {
+            self.speed = current_speed;
+        } else {
+            self.speed = (self.speed * 7 + current_speed * 3) / 10;
+        }
+        self.peak_speed = self.peak_speed.max(self.speed);
+        self.last_sample_time = now_ms;
+        self.last_sample_size = completed_size;
+
+        if self.speed > 0 {
+            self.eta_secs = Some(total_size.saturating_sub(completed_size) / self.speed);
+        } else {
+            self.eta_secs = None;
+        }
+    }
+}
 
 pub struct ChunkCacheNode {
     pub start_offset: u64,
@@ -242,6 +358,108 @@ lazy_static::lazy_static!{
 // //由于 CachedReader 没有使用 Pin 字段，可以安全地实现 Unpin
 // impl<R: AsyncRead + Unpin> Unpin for CachedReader<R> {}
 
+//一次备份运行中prepare/eval/transfer三个线程共享的状态,线程之间只通过session协调,不再轮询WorkTask的状态
+//暂停/取消/超时时cancel,各线程在循环中检查is_cancelled后退出,正在进行的chunk读写由cancel_token中断
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TaskSessionThread {
+    Prepare,
+    Eval,
+    Transfer,
+}
+
+impl TaskSessionThread {
+    pub const ALL: [TaskSessionThread; 3] = [TaskSessionThread::Prepare, TaskSessionThread::Eval, TaskSessionThread::Transfer];
+
+    fn index(&self) -> usize {
+        match self {
+            TaskSessionThread::Prepare => 0,
+            TaskSessionThread::Eval => 1,
+            TaskSessionThread::Transfer => 2,
+        }
+    }
+}
+
+//各线程更新的进度计数,不需要锁住WorkTask就能读取
+#[derive(Debug, Default)]
+pub struct TaskSessionProgress {
+    pub prepared_items: AtomicU64,
+    pub prepared_size: AtomicU64,
+    pub evaluated_items: AtomicU64,
+    pub transferred_items: AtomicU64,
+    pub transferred_size: AtomicU64,
+}
+
+//session状态的快照,给UI和watchdog用
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskSessionStat {
+    pub prepared_items: u64,
+    pub prepared_size: u64,
+    pub evaluated_items: u64,
+    pub transferred_items: u64,
+    pub transferred_size: u64,
+    pub heartbeats: HashMap<TaskSessionThread,u64>,//线程最后一次心跳的时间(ms),线程还没有启动时没有记录
+    pub is_cancelled: bool,
+}
+
+//prepare线程枚举时读出的小文件内容,交给eval线程直接打包上传,不需要再读一次磁盘
+pub struct TransferCacheNode {
+    pub item_id: String,
+    pub total_size: u64,
+    pub content: Vec<u8>,
+}
+
+impl TransferCacheNode {
+    pub fn new(item_id:String,content:Vec<u8>) -> Self {
+        Self {
+            item_id,
+            total_size: content.len() as u64,
+            content,
+        }
+    }
+}
+
+//prepare(生产者)和eval(消费者)之间有大小上限的cache,满了以后prepare不再缓存,由eval自己读取
+pub struct TransferCache {
+    nodes: std::sync::Mutex<HashMap<String,TransferCacheNode>>,
+    total_size: AtomicU64,
+    max_size: u64,
+}
+
+impl TransferCache {
+    pub fn new(max_size:u64) -> Self {
+        Self {
+            nodes: std::sync::Mutex::new(HashMap::new()),
+            total_size: AtomicU64::new(0),
+            max_size,
+        }
+    }
+
+    pub fn can_put(&self, size:u64) -> bool {
+        self.total_size.load(Ordering::Relaxed) + size <= self.max_size
+    }
+
+    //cache满时返回false,node被丢弃
+    pub fn try_put(&self, node:TransferCacheNode) -> bool {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !self.can_put(node.total_size) || nodes.contains_key(&node.item_id) {
+            return false;
+        }
+        self.total_size.fetch_add(node.total_size, Ordering::Relaxed);
+        nodes.insert(node.item_id.clone(), node);
+        true
+    }
+
+    pub fn take(&self, item_id:&str) -> Option<TransferCacheNode> {
+        let node = self.nodes.lock().unwrap().remove(item_id)?;
+        self.total_size.fetch_sub(node.total_size, Ordering::Relaxed);
+        Some(node)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.total_size.load(Ordering::Relaxed)
+    }
+}
+
 pub struct BackupTaskSession {
     pub task_id: String,
     pub eval_cache_queue:Arc<SegQueue<BackupItem>>,
@@ -249,6 +467,13 @@ pub struct BackupTaskSession {
     pub transfer_cache_queue:Arc<SegQueue<BackupItem>>,
     pub transfer_queue:Arc<SegQueue<BackupItem>>,
     pub done_items:Arc<Mutex<HashMap<String,u64>>>,
+    pub item_entropy:Arc<Mutex<HashMap<String,f64>>>,//item_id -> 内容采样的熵,用于异常检测
+    pub cancel_token:CancellationToken,//暂停task时cancel,中断正在进行的chunk读写
+    pub read_limiter:Arc<ReadRateLimiter>,//hash计算和传输线程共享的source读取限速
+    pub transfer_cache:TransferCache,
+    pub progress:TaskSessionProgress,
+    uploaded_size:AtomicU64,//这次运行实际写入target的大小,各线程上传时共享,用于配额检查
+    heartbeats:[AtomicU64; 3],
 }
 
 impl BackupTaskSession {
@@ -260,6 +485,169 @@ impl BackupTaskSession {
             transfer_cache_queue:Arc::new(SegQueue::new()),
             transfer_queue:Arc::new(SegQueue::new()),
             done_items:Arc::new(Mutex::new(HashMap::new())),
+            item_entropy:Arc::new(Mutex::new(HashMap::new())),
+            cancel_token:CancellationToken::new(),
+            read_limiter:Arc::new(ReadRateLimiter::unlimited()),
+            transfer_cache:TransferCache::new(MAX_TRANSFER_CACHE_SIZE),
+            progress:TaskSessionProgress::default(),
+            uploaded_size:AtomicU64::new(0),
+            heartbeats:[AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
         }
     }
-}
\ No newline at end of file
+
+    pub fn cancel(&self) {
+        self.cancel_token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_token.is_cancelled()
+    }
+
+    //线程每处理一轮调用一次
+    pub fn beat(&self, thread: TaskSessionThread, now: u64) {
+        self.heartbeats[thread.index()].store(now, Ordering::Relaxed);
+    }
+
+    pub fn last_heartbeat(&self, thread: TaskSessionThread) -> Option<u64> {
+        match self.heartbeats[thread.index()].load(Ordering::Relaxed) {
+            0 => None,
+            beat => Some(beat),
+        }
+    }
+
+    //已经启动但超过timeout_ms没有心跳的线程,可能卡在了source/target的读写上
+    pub fn stalled_threads(&self, now: u64, timeout_ms: u64) -> Vec<TaskSessionThread> {
+        TaskSessionThread::ALL.iter()
+            .filter(|thread| self.last_heartbeat(**thread).map_or(false, |beat| now.saturating_sub(beat) >= timeout_ms))
+            .cloned()
+            .collect()
+    }
+
+    pub fn on_item_prepared(&self, size: u64) {
+        self.progress.prepared_items.fetch_add(1, Ordering::Relaxed);
+        self.progress.prepared_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn on_item_evaluated(&self) {
+        self.progress.evaluated_items.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_transfer_progress(&self, size: u64) {
+        self.progress.transferred_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn on_item_transferred(&self) {
+        self.progress.transferred_items.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_uploaded(&self, size: u64) {
+        self.uploaded_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn uploaded_size(&self) -> u64 {
+        self.uploaded_size.load(Ordering::Relaxed)
+    }
+
+    pub fn stat(&self) -> TaskSessionStat {
+        TaskSessionStat {
+            prepared_items: self.progress.prepared_items.load(Ordering::Relaxed),
+            prepared_size: self.progress.prepared_size.load(Ordering::Relaxed),
+            evaluated_items: self.progress.evaluated_items.load(Ordering::Relaxed),
+            transferred_items: self.progress.transferred_items.load(Ordering::Relaxed),
+            transferred_size: self.progress.transferred_size.load(Ordering::Relaxed),
+            heartbeats: TaskSessionThread::ALL.iter()
+                .filter_map(|thread| self.last_heartbeat(*thread).map(|beat| (*thread, beat)))
+                .collect(),
+            is_cancelled: self.is_cancelled(),
+        }
+    }
+}
+
+//fut完成前cancel_token被cancel时返回None,fut会被drop
+pub async fn run_until_cancelled<F: Future>(cancel_token: &CancellationToken, fut: F) -> Option<F::Output> {
+    tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => None,
+        output = fut => Some(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_progress() {
+        let mut stat = TaskRuntimeStat::default();
+        let start = 1_000_000;
+        stat.on_transfer_start(start, 0);
+        stat.on_item_progress("item_1", 1024, 200 * 1024);
+        stat.on_item_progress("item_2", 4096, 2048);
+        assert_eq!(stat.transferring_size(), 1024 + 2048);
+        stat.on_item_progress("item_1", 8192, 200 * 1024);
+        assert_eq!(stat.transferring_size(), 8192 + 2048);
+        stat.on_item_end("item_2");
+        assert_eq!(stat.transferring_size(), 8192);
+
+        //item内部的进度也计算速度
+        stat.on_progress(start + 2000, stat.transferring_size(), 200 * 1024);
+        assert_eq!(stat.speed, 4096);
+        assert!(stat.eta_secs.is_some());
+
+        assert!(stat.should_publish_progress(start));
+        assert!(!stat.should_publish_progress(start + 500));
+        assert!(stat.should_publish_progress(start + 1000));
+
+        stat.on_transfer_end(start + 3000);
+        assert_eq!(stat.transferring_size(), 0);
+    }
+
+    #[test]
+    fn test_task_session_coordination() {
+        let session = BackupTaskSession::new("task_1".to_string());
+        let start = 1_000_000;
+        assert!(session.stalled_threads(start, 1000).is_empty());
+        session.beat(TaskSessionThread::Prepare, start);
+        session.beat(TaskSessionThread::Transfer, start);
+        session.beat(TaskSessionThread::Transfer, start + 5000);
+        //eval线程还没有启动,不认为卡住
+        assert_eq!(session.stalled_threads(start + 5500, 1000), vec![TaskSessionThread::Prepare]);
+
+        session.on_item_prepared(100);
+        session.on_item_prepared(200);
+        session.on_item_evaluated();
+        session.on_transfer_progress(150);
+        session.on_item_transferred();
+        let stat = session.stat();
+        assert_eq!((stat.prepared_items, stat.prepared_size), (2, 300));
+        assert_eq!((stat.evaluated_items, stat.transferred_items, stat.transferred_size), (1, 1, 150));
+        assert_eq!(stat.heartbeats.get(&TaskSessionThread::Transfer), Some(&(start + 5000)));
+        assert!(stat.heartbeats.get(&TaskSessionThread::Eval).is_none());
+
+        //cancel后各线程和clone出去的cancel_token都能看到
+        let cancel_token = session.cancel_token.clone();
+        assert!(!session.is_cancelled());
+        session.cancel();
+        assert!(session.is_cancelled() && cancel_token.is_cancelled());
+        assert!(session.stat().is_cancelled);
+    }
+
+    #[test]
+    fn test_transfer_cache() {
+        let cache = TransferCache::new(100);
+        assert!(cache.try_put(TransferCacheNode::new("item_1".to_string(), vec![1u8; 60])));
+        //同一个item只缓存一次,超过上限的不缓存
+        assert!(!cache.try_put(TransferCacheNode::new("item_1".to_string(), vec![1u8; 10])));
+        assert!(!cache.try_put(TransferCacheNode::new("item_2".to_string(), vec![2u8; 50])));
+        assert!(cache.can_put(40));
+        assert!(cache.try_put(TransferCacheNode::new("item_3".to_string(), vec![3u8; 40])));
+        assert_eq!(cache.total_size(), 100);
+
+        let node = cache.take("item_1").unwrap();
+        assert_eq!((node.total_size, node.content[0]), (60, 1));
+        assert!(cache.take("item_1").is_none());
+        assert_eq!(cache.total_size(), 40);
+        assert!(cache.try_put(TransferCacheNode::new("item_2".to_string(), vec![2u8; 50])));
+        assert_eq!(cache.take("item_2").unwrap().content, vec![2u8; 50]);
+    }
+}
@@ -0,0 +1,16 @@
+use std::process::Command;
+
+//插件和backup_suite通过rust的trait object交互,rust的abi只在相同版本的rustc下稳定,
+//把编译用的rustc版本写进BACKUP_PLUGIN_BUILD_ID,加载插件时检查
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("-V")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BACKUP_LIB_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -0,0 +1,947 @@
+#![allow(unused)]
+//remote agent:在被备份的机器上运行backup_agent,把本机目录作为source通过TLS(双向证书认证)提供给backup_suite,
+//backup_suite使用agent://host:port/path的source url拉取备份,恢复时也通过agent写回
+//协议:每个请求使用一个连接,client发送一行AgentRequest的json,agent回复一行AgentResponse的json,
+//open_item在回复后跟item从offset开始的全部内容,open_writer_for_restore在回复后由client发送写入的内容直到关闭连接
+//agent上的source状态(prepare_items的进度等)按session_id保存,每个AgentChunkSourceProvider对应一个session
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use ndn_lib::{ChunkReader, ChunkWriter, ChunkReadSeek};
+use anyhow::Result;
+use log::*;
+
+use crate::provider::*;
+use crate::local_chunk_provider::LocalDirChunkProvider;
+use crate::local_path::translate_local_path_from_url;
+use crate::provider_registry::*;
+
+pub const AGENT_SCHEME: &str = "agent";
+pub const DEFAULT_AGENT_PORT: u16 = 5190;
+//backup_suite数据目录下保存连接agent使用的证书的目录:ca.pem,cert.pem,key.pem
+pub const AGENT_TLS_DIR: &str = "agent_tls";
+const AGENT_CONNECT_TIMEOUT_SECS: u64 = 10;
+//超过这个时间没有请求的session被清理
+const AGENT_SESSION_IDLE_SECS: u64 = 3600;
+const AGENT_COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+//ca用来校验对端证书,cert/key是自己的证书;backup_suite和agent的证书由同一个ca签发
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentTlsConfig {
+    pub ca_cert: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl AgentTlsConfig {
+    pub fn from_dir(dir: &Path) -> Self {
+        Self {
+            ca_cert: dir.join("ca.pem"),
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        }
+    }
+
+    fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+        let content = std::fs::read(path).map_err(|e| anyhow::anyhow!("read {} failed: {}", path.to_string_lossy(), e))?;
+        let certs = rustls_pemfile::certs(&mut content.as_slice()).collect::<std::result::Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(anyhow::anyhow!("no certificate in {}", path.to_string_lossy()));
+        }
+        Ok(certs)
+    }
+
+    fn load_key(&self) -> Result<PrivateKeyDer<'static>> {
+        let content = std::fs::read(&self.key).map_err(|e| anyhow::anyhow!("read {} failed: {}", self.key.to_string_lossy(), e))?;
+        rustls_pemfile::private_key(&mut content.as_slice())?
+            .ok_or_else(|| anyhow::anyhow!("no private key in {}", self.key.to_string_lossy()))
+    }
+
+    fn load_roots(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in Self::load_certs(&self.ca_cert)? {
+            roots.add(cert)?;
+        }
+        Ok(roots)
+    }
+
+    //显式指定crypto provider,不依赖依赖树中rustls开启了哪些feature
+    fn crypto_provider() -> Arc<CryptoProvider> {
+        Arc::new(ring::default_provider())
+    }
+
+    pub fn build_client_config(&self) -> Result<Arc<ClientConfig>> {
+        let config = ClientConfig::builder_with_provider(Self::crypto_provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(self.load_roots()?)
+            .with_client_auth_cert(Self::load_certs(&self.cert)?, self.load_key()?)?;
+        Ok(Arc::new(config))
+    }
+
+    //连接注册过的agent:证书除了由ca签发,还必须属于注册时的DID,同一个ca签发的其它agent的证书不能冒充
+    pub fn build_client_config_for_did(&self, did: &str) -> Result<Arc<ClientConfig>> {
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(self.load_roots()?), Self::crypto_provider()).build()?;
+        let verifier = Arc::new(AgentDidVerifier { inner, did: did.to_string() });
+        let config = ClientConfig::builder_with_provider(Self::crypto_provider())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_client_auth_cert(Self::load_certs(&self.cert)?, self.load_key()?)?;
+        Ok(Arc::new(config))
+    }
+
+    //agent启动时检查自己的证书,backup_suite只接受属于注册时DID的证书
+    pub fn check_cert_did(&self, did: &str) -> Result<()> {
+        let certs = Self::load_certs(&self.cert)?;
+        if !is_agent_cert_for_did(&certs[0], did) {
+            return Err(anyhow::anyhow!("certificate {} does not belong to {}, issue it with the did as subject CN or SAN URI",
+                self.cert.to_string_lossy(), did));
+        }
+        Ok(())
+    }
+
+    //没有ca签发的证书的client在握手时被拒绝
+    pub fn build_server_config(&self) -> Result<Arc<ServerConfig>> {
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(self.load_roots()?), Self::crypto_provider()).build()?;
+        let config = ServerConfig::builder_with_provider(Self::crypto_provider())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(Self::load_certs(&self.cert)?, self.load_key()?)?;
+        Ok(Arc::new(config))
+    }
+}
+
+//agent的证书的subject CN或SAN中的URI是agent的DID
+pub fn is_agent_cert_for_did(cert: &CertificateDer<'_>, did: &str) -> bool {
+    let cert = match x509_parser::parse_x509_certificate(cert.as_ref()) {
+        Ok((_, cert)) => cert,
+        Err(_) => return false,
+    };
+    if cert.subject().iter_common_name().any(|common_name| common_name.as_str().ok() == Some(did)) {
+        return true;
+    }
+    match cert.subject_alternative_name() {
+        Ok(Some(san)) => san.value.general_names.iter()
+            .any(|name| matches!(name, x509_parser::extensions::GeneralName::URI(uri) if *uri == did)),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+struct AgentDidVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    did: String,
+}
+
+impl ServerCertVerifier for AgentDidVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>,
+        ocsp_response: &[u8], now: UnixTime) -> std::result::Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        if !is_agent_cert_for_did(end_entity, self.did.as_str()) {
+            return Err(tokio_rustls::rustls::Error::General(format!("agent certificate does not belong to {}", self.did)));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+        -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+        -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+//agent://host[:port]/path,返回(host, port, agent上的本地路径)
+pub fn parse_agent_url(url: &str) -> BackupResult<(String, u16, PathBuf)> {
+    let parsed = url::Url::parse(url).map_err(|e| BuckyBackupError::Failed(format!("invalid agent url {}: {}", url, e)))?;
+    if parsed.scheme() != AGENT_SCHEME {
+        return Err(BuckyBackupError::Failed(format!("{} is not an agent url", url)));
+    }
+    let host = parsed.host_str()
+        .ok_or_else(|| BuckyBackupError::Failed(format!("agent url {} has no host", url)))?
+        .to_string();
+    let port = parsed.port().unwrap_or(DEFAULT_AGENT_PORT);
+    let path = translate_local_path_from_url(format!("file://{}", parsed.path()).as_str())?;
+    Ok((host, port, path))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum AgentMethod {
+    GetSourceInfo,
+    PrepareItems,
+    OpenItem { item_id: String, offset: u64 },
+    OnItemBackuped { item_id: String },
+    CheckItemChanged { item: BackupItem },
+    InitForRestore { restore_config: RestoreConfig },
+    OpenWriterForRestore { item: BackupItem, restore_config: RestoreConfig, offset: u64 },
+    CompleteRestoreItem { item: BackupItem, restore_config: RestoreConfig },
+    RestoreLinkItem { item: BackupItem, restore_config: RestoreConfig },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRequest {
+    pub session_id: String,
+    pub source_path: PathBuf,
+    #[serde(flatten)]
+    pub method: AgentMethod,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentResponse {
+    #[serde(default)]
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<(BackupErrorKind, String)>,
+}
+
+impl AgentResponse {
+    pub(crate) fn from_result(result: BackupResult<Value>) -> Self {
+        match result {
+            Ok(result) => Self { result, error: None },
+            Err(err) => Self { result: Value::Null, error: Some((err.kind(), err.to_string())) },
+        }
+    }
+}
+
+//agent返回的错误按类型还原,需要engine区别处理的是TryLater/AlreadyDone/NotFound
+pub(crate) fn error_from_agent(kind: BackupErrorKind, message: String) -> BuckyBackupError {
+    match kind {
+        BackupErrorKind::TryLater => BuckyBackupError::TryLater(message),
+        BackupErrorKind::AlreadyDone => BuckyBackupError::AlreadyDone(message),
+        BackupErrorKind::NotFound => BuckyBackupError::NotFound(message),
+        BackupErrorKind::NeedProcess => BuckyBackupError::NeedProcess(message),
+        BackupErrorKind::Internal => BuckyBackupError::Internal(message),
+        _ => BuckyBackupError::Failed(message),
+    }
+}
+
+pub(crate) fn parse_result<T: serde::de::DeserializeOwned>(result: Value) -> BackupResult<T> {
+    serde_json::from_value(result).map_err(|e| BuckyBackupError::Failed(format!("invalid agent response: {}", e)))
+}
+
+pub(crate) async fn write_json_line<S: AsyncWrite + Unpin, T: Serialize>(stream: &mut S, value: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(value).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    stream.flush().await
+}
+
+pub(crate) async fn read_json_line<S: AsyncBufReadExt + Unpin, T: serde::de::DeserializeOwned>(stream: &mut S) -> std::io::Result<T> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    serde_json::from_str(line.as_str()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+type AgentStream = BufReader<tokio_rustls::client::TlsStream<TcpStream>>;
+
+fn new_session_id() -> String {
+    static SESSION_SEQ: AtomicU64 = AtomicU64::new(0);
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+    format!("{:x}-{:x}-{:x}", now, std::process::id(), SESSION_SEQ.fetch_add(1, Ordering::Relaxed))
+}
+
+#[derive(Clone)]
+struct AgentConnector {
+    host: String,
+    port: u16,
+    source_path: PathBuf,
+    session_id: String,
+    tls_config: Arc<ClientConfig>,
+}
+
+impl AgentConnector {
+    //agent不在线时返回TryLater
+    async fn request(&self, method: AgentMethod) -> BackupResult<(Value, AgentStream)> {
+        let server_name = ServerName::try_from(self.host.clone())
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid agent host {}: {}", self.host, e)))?;
+        let connect = async {
+            let tcp_stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            TlsConnector::from(self.tls_config.clone()).connect(server_name, tcp_stream).await
+        };
+        let stream = tokio::time::timeout(Duration::from_secs(AGENT_CONNECT_TIMEOUT_SECS), connect).await
+            .map_err(|_| BuckyBackupError::TryLater(format!("connect agent {}:{} timeout", self.host, self.port)))?
+            .map_err(|e| BuckyBackupError::TryLater(format!("connect agent {}:{} failed: {}", self.host, self.port, e)))?;
+        let mut stream = BufReader::new(stream);
+        let request = AgentRequest {
+            session_id: self.session_id.clone(),
+            source_path: self.source_path.clone(),
+            method,
+        };
+        write_json_line(&mut stream, &request).await?;
+        let response: AgentResponse = read_json_line(&mut stream).await?;
+        if let Some((kind, message)) = response.error {
+            return Err(error_from_agent(kind, message));
+        }
+        Ok((response.result, stream))
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(&self, method: AgentMethod) -> BackupResult<T> {
+        let (result, _) = self.request(method).await?;
+        parse_result(result)
+    }
+}
+
+type OpenItemFuture = Pin<Box<dyn Future<Output = BackupResult<(Value, AgentStream)>> + Send>>;
+
+enum AgentReaderState {
+    Idle,
+    Connecting(OpenItemFuture),
+    Reading(AgentStream),
+}
+
+//从agent读取item内容,seek到其它位置时重新发起open_item请求
+//state放在std Mutex中只是为了满足Sync,poll时通过get_mut访问不需要加锁
+pub struct AgentItemReader {
+    connector: AgentConnector,
+    item_id: String,
+    size: u64,
+    pos: u64,
+    state: std::sync::Mutex<AgentReaderState>,
+}
+
+impl AgentItemReader {
+    async fn open(connector: AgentConnector, item_id: &str, offset: u64) -> BackupResult<Self> {
+        let (result, stream) = connector.request(AgentMethod::OpenItem { item_id: item_id.to_string(), offset }).await?;
+        let size: u64 = parse_result(result)?;
+        Ok(Self {
+            connector,
+            item_id: item_id.to_string(),
+            size,
+            pos: offset,
+            state: std::sync::Mutex::new(AgentReaderState::Reading(stream)),
+        })
+    }
+}
+
+impl AsyncRead for AgentItemReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let state = this.state.get_mut().unwrap();
+        loop {
+            match state {
+                AgentReaderState::Idle => {
+                    let connector = this.connector.clone();
+                    let method = AgentMethod::OpenItem { item_id: this.item_id.clone(), offset: this.pos };
+                    *state = AgentReaderState::Connecting(Box::pin(async move { connector.request(method).await }));
+                }
+                AgentReaderState::Connecting(open_future) => {
+                    match open_future.as_mut().poll(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok((_, stream))) => *state = AgentReaderState::Reading(stream),
+                        Poll::Ready(Err(err)) => {
+                            *state = AgentReaderState::Idle;
+                            return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, err.to_string())));
+                        }
+                    }
+                }
+                AgentReaderState::Reading(stream) => {
+                    let filled = buf.filled().len();
+                    let result = Pin::new(stream).poll_read(cx, buf);
+                    if let Poll::Ready(Ok(())) = &result {
+                        this.pos += (buf.filled().len() - filled) as u64;
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for AgentItemReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let this = self.get_mut();
+        let new_pos = match position {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => this.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => this.pos.checked_add_signed(delta),
+        };
+        let new_pos = new_pos.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek position"))?;
+        if new_pos != this.pos {
+            this.pos = new_pos;
+            *this.state.get_mut().unwrap() = AgentReaderState::Idle;
+        }
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+//恢复时写入agent的内容,连接关闭表示写入结束
+pub struct AgentItemWriter {
+    stream: std::sync::Mutex<AgentStream>,
+}
+
+impl AsyncWrite for AgentItemWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_shutdown(cx)
+    }
+}
+
+pub struct AgentChunkSourceProvider {
+    url: String,
+    connector: AgentConnector,
+}
+
+impl AgentChunkSourceProvider {
+    pub fn new(url: &str, tls_config: Arc<ClientConfig>) -> BackupResult<Self> {
+        let (host, port, source_path) = parse_agent_url(url)?;
+        Ok(Self {
+            url: url.to_string(),
+            connector: AgentConnector {
+                host,
+                port,
+                source_path,
+                session_id: new_session_id(),
+                tls_config,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl IBackupChunkSourceProvider for AgentChunkSourceProvider {
+    async fn get_source_info(&self) -> Result<Value> {
+        let info: Value = self.connector.call(AgentMethod::GetSourceInfo).await?;
+        Ok(json!({
+            "type": "agent_source",
+            "agent": format!("{}:{}", self.connector.host, self.connector.port),
+            "source": info,
+        }))
+    }
+
+    fn get_source_url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    async fn prepare_items(&self) -> BackupResult<(Vec<BackupItem>, bool)> {
+        self.connector.call(AgentMethod::PrepareItems).await
+    }
+
+    async fn open_item(&self, item_id: &str) -> BackupResult<Pin<Box<dyn ChunkReadSeek + Send + Sync + Unpin>>> {
+        Ok(Box::pin(AgentItemReader::open(self.connector.clone(), item_id, 0).await?))
+    }
+
+    async fn open_item_chunk_reader(&self, item_id: &str, offset: u64) -> BackupResult<ChunkReader> {
+        Ok(Box::pin(AgentItemReader::open(self.connector.clone(), item_id, offset).await?))
+    }
+
+    async fn on_item_backuped(&self, item_id: &str) -> Result<()> {
+        self.connector.call::<Value>(AgentMethod::OnItemBackuped { item_id: item_id.to_string() }).await?;
+        Ok(())
+    }
+
+    async fn init_for_restore(&self, restore_config: &RestoreConfig) -> Result<()> {
+        self.connector.call::<Value>(AgentMethod::InitForRestore { restore_config: restore_config.clone() }).await?;
+        Ok(())
+    }
+
+    async fn open_writer_for_restore(&self, item: &BackupItem, restore_config: &RestoreConfig, offset: u64) -> BackupResult<(ChunkWriter, u64)> {
+        let (result, stream) = self.connector.request(AgentMethod::OpenWriterForRestore {
+            item: item.clone(),
+            restore_config: restore_config.clone(),
+            offset,
+        }).await?;
+        let real_offset: u64 = parse_result(result)?;
+        Ok((Box::pin(AgentItemWriter { stream: std::sync::Mutex::new(stream) }), real_offset))
+    }
+
+    async fn complete_restore_item(&self, item: &BackupItem, restore_config: &RestoreConfig) -> BackupResult<()> {
+        self.connector.call::<Value>(AgentMethod::CompleteRestoreItem { item: item.clone(), restore_config: restore_config.clone() }).await?;
+        Ok(())
+    }
+
+    async fn check_item_changed(&self, item: &mut BackupItem) -> BackupResult<bool> {
+        let (is_changed, new_item): (bool, BackupItem) = self.connector.call(AgentMethod::CheckItemChanged { item: item.clone() }).await?;
+        *item = new_item;
+        Ok(is_changed)
+    }
+
+    async fn restore_link_item(&self, item: &BackupItem, restore_config: &RestoreConfig) -> BackupResult<()> {
+        self.connector.call::<Value>(AgentMethod::RestoreLinkItem { item: item.clone(), restore_config: restore_config.clone() }).await?;
+        Ok(())
+    }
+}
+
+struct AgentSession {
+    source: LocalDirChunkProvider,
+    last_active: AtomicU64,
+    //恢复写入中的item -> 写入结束后的错误;complete_restore_item等写入结束后再完成
+    restore_writes: Mutex<HashMap<String, Arc<Mutex<Option<String>>>>>,
+}
+
+//恢复时路径可能还不存在,解析已经存在的最深一级目录,再接上不存在的部分
+fn canonicalize_existing_prefix(path: &Path) -> Option<PathBuf> {
+    let mut existing_path = path;
+    let mut missing_names = Vec::new();
+    loop {
+        match existing_path.canonicalize() {
+            Ok(mut real_path) => {
+                for name in missing_names.iter().rev() {
+                    real_path.push(name);
+                }
+                return Some(real_path);
+            }
+            Err(_) => {
+                missing_names.push(existing_path.file_name()?.to_os_string());
+                existing_path = existing_path.parent()?;
+            }
+        }
+    }
+}
+
+//agent端:只允许访问allowed_roots下的目录
+pub struct AgentServer {
+    allowed_roots: Vec<PathBuf>,
+    sessions: Mutex<HashMap<String, Arc<AgentSession>>>,
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl AgentServer {
+    pub fn new(allowed_roots: Vec<PathBuf>) -> Self {
+        Self {
+            allowed_roots,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //item_id是source目录下的相对路径
+    fn check_item_id(item_id: &str) -> BackupResult<()> {
+        let path = Path::new(item_id);
+        if path.is_absolute() || path.components().any(|component| !matches!(component, Component::Normal(_))) {
+            return Err(BuckyBackupError::Failed(format!("invalid item id {}", item_id)));
+        }
+        Ok(())
+    }
+
+    //按解析符号链接后的真实路径检查,allowed_roots下指向外部的链接不能用来读写外部的文件
+    pub fn is_path_allowed(&self, path: &Path) -> bool {
+        if !path.is_absolute() || path.components().any(|component| component == Component::ParentDir) {
+            return false;
+        }
+        let real_path = match canonicalize_existing_prefix(path) {
+            Some(real_path) => real_path,
+            None => return false,
+        };
+        self.allowed_roots.iter().any(|root| {
+            let real_root = root.canonicalize().unwrap_or_else(|_| root.clone());
+            real_path.starts_with(&real_root)
+        })
+    }
+
+    //item为None时只检查恢复目录
+    fn check_restore_config(&self, restore_config: &RestoreConfig, item: Option<&BackupItem>) -> BackupResult<()> {
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        if !self.is_path_allowed(&restore_path) {
+            return Err(BuckyBackupError::Failed(format!("restore path {} is not allowed by agent", restore_path.to_string_lossy())));
+        }
+        if let Some(item) = item {
+            if !self.is_path_allowed(&restore_path.join(item.item_id.as_str())) {
+                return Err(BuckyBackupError::Failed(format!("restore item {} is not allowed by agent", item.item_id)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str, source_path: &Path) -> BackupResult<Arc<AgentSession>> {
+        if !self.is_path_allowed(source_path) {
+            return Err(BuckyBackupError::Failed(format!("source path {} is not allowed by agent", source_path.to_string_lossy())));
+        }
+        let now = now_secs();
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = sessions.get(session_id) {
+            if session.source.dir_path != source_path.to_string_lossy() {
+                return Err(BuckyBackupError::Failed(format!("session {} belongs to other source", session_id)));
+            }
+            session.last_active.store(now, Ordering::Relaxed);
+            return Ok(session.clone());
+        }
+        sessions.retain(|_, session| session.last_active.load(Ordering::Relaxed) + AGENT_SESSION_IDLE_SECS > now);
+        let source = LocalDirChunkProvider::new(source_path.to_string_lossy().to_string()).await
+            .map_err(|e| BuckyBackupError::Failed(e.to_string()))?;
+        let session = Arc::new(AgentSession {
+            source,
+            last_active: AtomicU64::new(now),
+            restore_writes: Mutex::new(HashMap::new()),
+        });
+        sessions.insert(session_id.to_string(), session.clone());
+        Ok(session)
+    }
+
+    //监听并处理请求,直到出错
+    pub async fn run(self: Arc<Self>, listen_addr: &str, tls_config: Arc<ServerConfig>) -> Result<()> {
+        let listener = TcpListener::bind(listen_addr).await?;
+        info!("backup agent listen on {}, allowed roots: {:?}", listen_addr, self.allowed_roots);
+        self.serve(listener, tls_config).await
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, tls_config: Arc<ServerConfig>) -> Result<()> {
+        let acceptor = TlsAcceptor::from(tls_config);
+        loop {
+            let (tcp_stream, remote_addr) = listener.accept().await?;
+            let acceptor = acceptor.clone();
+            let server = self.clone();
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(tcp_stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("agent tls handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                if let Err(e) = server.handle_connection(stream).await {
+                    warn!("agent handle request from {} failed: {}", remote_addr, e);
+                }
+            });
+        }
+    }
+
+    pub async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, stream: S) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        let request: AgentRequest = read_json_line(&mut stream).await?;
+        debug!("agent request: {:?}", request.method);
+        let item_id = match &request.method {
+            AgentMethod::OpenItem { item_id, .. } | AgentMethod::OnItemBackuped { item_id } => Some(item_id.as_str()),
+            AgentMethod::CheckItemChanged { item } | AgentMethod::OpenWriterForRestore { item, .. }
+                | AgentMethod::CompleteRestoreItem { item, .. } | AgentMethod::RestoreLinkItem { item, .. } => Some(item.item_id.as_str()),
+            _ => None,
+        };
+        let session = match item_id.map_or(Ok(()), Self::check_item_id) {
+            Ok(()) => self.get_session(request.session_id.as_str(), &request.source_path).await,
+            Err(err) => Err(err),
+        };
+        //source目录下的符号链接可能指向外部
+        let is_backup_item = matches!(&request.method, AgentMethod::OpenItem { .. } | AgentMethod::OnItemBackuped { .. } | AgentMethod::CheckItemChanged { .. });
+        let session = match (session, item_id) {
+            (Ok(_), Some(item_id)) if is_backup_item && !self.is_path_allowed(&request.source_path.join(item_id)) => {
+                Err(BuckyBackupError::Failed(format!("item {} is not allowed by agent", item_id)))
+            }
+            (session, _) => session,
+        };
+        let session = match session {
+            Ok(session) => session,
+            Err(err) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+        };
+        let source = &session.source;
+        let result = match request.method {
+            AgentMethod::GetSourceInfo => source.get_source_info().await.map_err(|e| BuckyBackupError::Failed(e.to_string())),
+            AgentMethod::PrepareItems => source.prepare_items().await.map(|result| json!(result)),
+            AgentMethod::OnItemBackuped { item_id } => source.on_item_backuped(item_id.as_str()).await
+                .map(|_| Value::Null)
+                .map_err(|e| BuckyBackupError::Failed(e.to_string())),
+            AgentMethod::CheckItemChanged { mut item } => source.check_item_changed(&mut item).await
+                .map(|is_changed| json!((is_changed, item))),
+            AgentMethod::InitForRestore { restore_config } => match self.check_restore_config(&restore_config, None) {
+                Ok(()) => source.init_for_restore(&restore_config).await
+                    .map(|_| Value::Null)
+                    .map_err(|e| BuckyBackupError::Failed(e.to_string())),
+                Err(err) => Err(err),
+            },
+            AgentMethod::RestoreLinkItem { item, restore_config } => match self.check_restore_config(&restore_config, Some(&item)) {
+                Ok(()) => source.restore_link_item(&item, &restore_config).await.map(|_| Value::Null),
+                Err(err) => Err(err),
+            },
+            AgentMethod::CompleteRestoreItem { item, restore_config } => {
+                self.complete_restore_item(&session, &item, &restore_config).await.map(|_| Value::Null)
+            }
+            AgentMethod::OpenItem { item_id, offset } => {
+                return self.send_item_content(&session, stream, item_id.as_str(), offset).await;
+            }
+            AgentMethod::OpenWriterForRestore { item, restore_config, offset } => {
+                return self.receive_restore_content(&session, stream, &item, &restore_config, offset).await;
+            }
+        };
+        write_json_line(&mut stream, &AgentResponse::from_result(result)).await?;
+        Ok(())
+    }
+
+    async fn send_item_content<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, session: &AgentSession, mut stream: BufReader<S>,
+        item_id: &str, offset: u64) -> Result<()> {
+        let reader = session.source.open_item_chunk_reader(item_id, offset).await;
+        let size = std::fs::metadata(Path::new(&session.source.dir_path).join(item_id)).map(|meta| meta.len());
+        let (mut reader, size) = match (reader, size) {
+            (Ok(reader), Ok(size)) => (reader, size),
+            (Err(err), _) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+            (_, Err(err)) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(BuckyBackupError::TryLater(err.to_string())))).await?;
+                return Ok(());
+            }
+        };
+        write_json_line(&mut stream, &AgentResponse::from_result(Ok(json!(size)))).await?;
+        let stream = stream.get_mut();
+        tokio::io::copy(&mut reader, stream).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    async fn receive_restore_content<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, session: &AgentSession, mut stream: BufReader<S>,
+        item: &BackupItem, restore_config: &RestoreConfig, offset: u64) -> Result<()> {
+        let open_result = match self.check_restore_config(restore_config, Some(item)) {
+            Ok(()) => session.source.open_writer_for_restore(item, restore_config, offset).await,
+            Err(err) => Err(err),
+        };
+        let (mut writer, real_offset) = match open_result {
+            Ok(result) => result,
+            Err(err) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+        };
+        //回复之前登记,保证之后的complete_restore_item一定能等到这次写入结束
+        let write_state = Arc::new(Mutex::new(Some(format!("restore write of {} is interrupted", item.item_id))));
+        let mut write_guard = write_state.clone().lock_owned().await;
+        session.restore_writes.lock().await.insert(item.item_id.clone(), write_state);
+        write_json_line(&mut stream, &AgentResponse::from_result(Ok(json!(real_offset)))).await?;
+
+        //client写完后直接关闭连接,没有close_notify时读取会返回UnexpectedEof,按写入结束处理,由大小判断是否完整
+        let mut written = 0u64;
+        let mut buf = vec![0u8; AGENT_COPY_BUFFER_SIZE];
+        let mut write_error = None;
+        loop {
+            let read_len = match stream.read(&mut buf).await {
+                Ok(read_len) => read_len,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+                Err(e) => {
+                    write_error = Some(format!("read restore content failed: {}", e));
+                    break;
+                }
+            };
+            if read_len == 0 {
+                break;
+            }
+            if let Err(e) = writer.write_all(&buf[..read_len]).await {
+                write_error = Some(format!("write restore content failed: {}", e));
+                break;
+            }
+            written += read_len as u64;
+        }
+        if write_error.is_none() {
+            if let Err(e) = writer.flush().await {
+                write_error = Some(format!("flush restore content failed: {}", e));
+            }
+        }
+        let expected = item.size.saturating_sub(real_offset);
+        if write_error.is_none() && written != expected {
+            write_error = Some(format!("restore content of {} is incomplete, {} of {} bytes", item.item_id, written, expected));
+        }
+        *write_guard = write_error;
+        Ok(())
+    }
+
+    async fn complete_restore_item(&self, session: &AgentSession, item: &BackupItem, restore_config: &RestoreConfig) -> BackupResult<()> {
+        self.check_restore_config(restore_config, Some(item))?;
+        let write_state = session.restore_writes.lock().await.remove(&item.item_id);
+        if let Some(write_state) = write_state {
+            if let Some(err) = write_state.lock().await.take() {
+                return Err(BuckyBackupError::TryLater(err));
+            }
+        }
+        session.source.complete_restore_item(item, restore_config).await
+    }
+}
+
+//agent的地址(host:port) -> 在这个地址注册的agent的DID
+pub type AgentDidResolver = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+//注册agent://的source,证书在连接时从tls_dir读取(可以在不重启backup_suite的情况下更新证书)
+//只能连接注册过的agent,agent的证书必须属于注册时的DID
+pub fn register_agent_source_provider(registry: &mut BackupProviderRegistry, tls_dir: PathBuf, did_resolver: AgentDidResolver) {
+    registry.register_source_provider(
+        BackupProviderDesc::builtin("agent_source", AGENT_SCHEME, BackupProviderKind::Source),
+        Arc::new(move |url: String| {
+            let tls_config = AgentTlsConfig::from_dir(&tls_dir);
+            let did_resolver = did_resolver.clone();
+            Box::pin(async move {
+                let (host, port, _) = parse_agent_url(url.as_str())?;
+                let address = format!("{}:{}", host, port);
+                let did = did_resolver(address.as_str())
+                    .ok_or_else(|| BuckyBackupError::Failed(format!("agent {} is not enrolled", address)))?;
+                let source = AgentChunkSourceProvider::new(url.as_str(), tls_config.build_client_config_for_did(did.as_str())?)?;
+                Ok(Box::new(source) as BackupChunkSourceProvider)
+            }) as SourceProviderFuture
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncSeekExt;
+
+    struct TestCerts {
+        dir: tempfile::TempDir,
+    }
+
+    const TEST_AGENT_DID: &str = "did:dev:test_agent";
+
+    //生成测试用的ca,agent(localhost,DID为TEST_AGENT_DID)和client证书
+    fn create_test_certs() -> TestCerts {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        for name in ["agent", "client"] {
+            let key = rcgen::KeyPair::generate().unwrap();
+            let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            if name == "agent" {
+                params.subject_alt_names.push(rcgen::SanType::URI(rcgen::Ia5String::try_from(TEST_AGENT_DID).unwrap()));
+            }
+            let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+            let cert_dir = dir.path().join(name);
+            std::fs::create_dir_all(&cert_dir).unwrap();
+            std::fs::write(cert_dir.join("ca.pem"), ca_cert.pem()).unwrap();
+            std::fs::write(cert_dir.join("cert.pem"), cert.pem()).unwrap();
+            std::fs::write(cert_dir.join("key.pem"), key.serialize_pem()).unwrap();
+        }
+        TestCerts { dir }
+    }
+
+    #[test]
+    fn test_parse_agent_url() {
+        let (host, port, path) = parse_agent_url("agent://192.168.1.10/home/alice/docs").unwrap();
+        assert_eq!((host.as_str(), port), ("192.168.1.10", DEFAULT_AGENT_PORT));
+        assert!(path.ends_with("docs"));
+        assert_eq!(parse_agent_url("agent://pc.local:6000/data").unwrap().1, 6000);
+        assert!(parse_agent_url("file:///data").is_err());
+
+        let server = AgentServer::new(vec![PathBuf::from("/home/alice")]);
+        assert!(server.is_path_allowed(Path::new("/home/alice/docs")));
+        assert!(!server.is_path_allowed(Path::new("/home/alice/../bob")));
+        assert!(!server.is_path_allowed(Path::new("/home/bob")));
+
+        #[cfg(unix)]
+        {
+            let test_dir = tempfile::tempdir().unwrap();
+            let root = test_dir.path().join("root");
+            std::fs::create_dir_all(&root).unwrap();
+            std::fs::create_dir_all(test_dir.path().join("outside")).unwrap();
+            std::os::unix::fs::symlink(test_dir.path().join("outside"), root.join("link")).unwrap();
+            let server = AgentServer::new(vec![root.clone()]);
+            assert!(server.is_path_allowed(&root.join("new_dir/a.bin")));
+            assert!(!server.is_path_allowed(&root.join("link")));
+            assert!(!server.is_path_allowed(&root.join("link/new_dir/a.bin")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_source() {
+        let certs = create_test_certs();
+        let test_dir = tempfile::tempdir().unwrap();
+        let source_dir = test_dir.path().join("source");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        let content: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(source_dir.join("a.bin"), &content).unwrap();
+
+        let server = Arc::new(AgentServer::new(vec![test_dir.path().to_path_buf()]));
+        let server_config = AgentTlsConfig::from_dir(&certs.dir.path().join("agent")).build_server_config().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(server.serve(listener, server_config));
+
+        let client_config = AgentTlsConfig::from_dir(&certs.dir.path().join("client")).build_client_config().unwrap();
+        let url = format!("agent://localhost:{}{}", port, source_dir.to_string_lossy());
+        let source = AgentChunkSourceProvider::new(url.as_str(), client_config.clone()).unwrap();
+        let (items, is_done) = source.prepare_items().await.unwrap();
+        assert!(is_done);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].size, content.len() as u64);
+
+        let mut reader = source.open_item("a.bin").await.unwrap();
+        let mut read_content = Vec::new();
+        reader.read_to_end(&mut read_content).await.unwrap();
+        assert_eq!(read_content, content);
+        //seek后重新从agent读取
+        reader.seek(SeekFrom::Start(1000)).await.unwrap();
+        let mut buf = vec![0u8; 10];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, content[1000..1010]);
+
+        let restore_dir = test_dir.path().join("restore");
+        let restore_config = RestoreConfig {
+            restore_location_url: format!("file://{}", restore_dir.to_string_lossy()),
+            is_clean_restore: false,
+            name_collision_policy: Default::default(),
+            conflict_policy: None,
+            restore_in_place: false,
+            params: None,
+        };
+        source.init_for_restore(&restore_config).await.unwrap();
+        let (mut writer, offset) = source.open_writer_for_restore(&items[0], &restore_config, 0).await.unwrap();
+        assert_eq!(offset, 0);
+        writer.write_all(&content).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        source.complete_restore_item(&items[0], &restore_config).await.unwrap();
+        assert_eq!(std::fs::read(restore_dir.join("a.bin")).unwrap(), content);
+
+        //不在allowed_roots下的目录被拒绝
+        let other_url = format!("agent://localhost:{}/other_dir", port);
+        let other_source = AgentChunkSourceProvider::new(other_url.as_str(), client_config).unwrap();
+        assert!(other_source.prepare_items().await.is_err());
+
+        //证书不是同一个ca签发的client不能连接
+        let other_certs = create_test_certs();
+        let other_config = AgentTlsConfig::from_dir(&other_certs.dir.path().join("client")).build_client_config().unwrap();
+        let untrusted_source = AgentChunkSourceProvider::new(url.as_str(), other_config).unwrap();
+        assert!(untrusted_source.prepare_items().await.is_err());
+
+        //连接注册过的agent时校验证书的DID
+        let tls_config = AgentTlsConfig::from_dir(&certs.dir.path().join("client"));
+        let did_source = AgentChunkSourceProvider::new(url.as_str(), tls_config.build_client_config_for_did(TEST_AGENT_DID).unwrap()).unwrap();
+        assert!(did_source.prepare_items().await.is_ok());
+        let other_did_source = AgentChunkSourceProvider::new(url.as_str(), tls_config.build_client_config_for_did("did:dev:other_agent").unwrap()).unwrap();
+        assert!(other_did_source.prepare_items().await.is_err());
+    }
+}
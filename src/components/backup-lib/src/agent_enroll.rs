@@ -0,0 +1,169 @@
+#![allow(unused)]
+//agent注册:backup_suite生成一次性的注册token,agent用token和自己的DID(did:dev:<ed25519公钥>)注册,
+//注册后backup_suite只接受这个DID签名的心跳,心跳中带上agent的版本和对外地址
+//私钥保存在agent的tls目录中,DID由公钥得到,所以backup_suite不需要单独保存公钥
+use std::path::Path;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+pub const AGENT_DID_PREFIX: &str = "did:dev:";
+pub const AGENT_IDENTITY_KEY_FILE: &str = "agent_key.pem";
+pub const AGENT_HEARTBEAT_INTERVAL_SECS: u64 = 60;
+
+pub fn agent_did_from_public_key(public_key: &VerifyingKey) -> String {
+    format!("{}{}", AGENT_DID_PREFIX, URL_SAFE_NO_PAD.encode(public_key.as_bytes()))
+}
+
+pub fn agent_public_key_from_did(did: &str) -> Result<VerifyingKey> {
+    let encoded = did.strip_prefix(AGENT_DID_PREFIX)
+        .ok_or_else(|| anyhow::anyhow!("{} is not an agent did", did))?;
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD.decode(encoded)?.try_into()
+        .map_err(|_| anyhow::anyhow!("invalid public key length in did {}", did))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+fn verify_agent_signature(did: &str, sign_bytes: &[u8], signature: &str) -> Result<()> {
+    let public_key = agent_public_key_from_did(did)?;
+    let signature_bytes: [u8; 64] = URL_SAFE_NO_PAD.decode(signature)?.try_into()
+        .map_err(|_| anyhow::anyhow!("invalid signature length"))?;
+    public_key.verify(sign_bytes, &Signature::from_bytes(&signature_bytes))
+        .map_err(|e| anyhow::anyhow!("verify signature of {} failed: {}", did, e))
+}
+
+pub struct AgentIdentity {
+    signing_key: SigningKey,
+}
+
+impl AgentIdentity {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self { signing_key }
+    }
+
+    //第一次运行时生成私钥并保存
+    pub fn load_or_create(key_path: &Path) -> Result<Self> {
+        if key_path.exists() {
+            let pem = std::fs::read_to_string(key_path)?;
+            let signing_key = SigningKey::from_pkcs8_pem(pem.as_str())
+                .map_err(|e| anyhow::anyhow!("load agent key {} failed: {}", key_path.to_string_lossy(), e))?;
+            return Ok(Self::new(signing_key));
+        }
+        let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let pem = signing_key.to_pkcs8_pem(LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("encode agent key failed: {}", e))?;
+        if let Some(parent) = key_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(key_path, pem.as_bytes())?;
+        Ok(Self::new(signing_key))
+    }
+
+    pub fn did(&self) -> String {
+        agent_did_from_public_key(&self.signing_key.verifying_key())
+    }
+
+    fn sign(&self, sign_bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.sign(sign_bytes).to_bytes())
+    }
+
+    pub fn create_enroll_request(&self, token: &str, hostname: &str, version: &str, address: &str, timestamp: u64) -> AgentEnrollRequest {
+        let mut request = AgentEnrollRequest {
+            token: token.to_string(),
+            did: self.did(),
+            hostname: hostname.to_string(),
+            version: version.to_string(),
+            address: address.to_string(),
+            timestamp,
+            signature: String::new(),
+        };
+        request.signature = self.sign(&request.to_sign_bytes());
+        request
+    }
+
+    pub fn create_heartbeat(&self, version: &str, address: &str, timestamp: u64) -> AgentHeartbeat {
+        let mut heartbeat = AgentHeartbeat {
+            did: self.did(),
+            version: version.to_string(),
+            address: address.to_string(),
+            timestamp,
+            signature: String::new(),
+        };
+        heartbeat.signature = self.sign(&heartbeat.to_sign_bytes());
+        heartbeat
+    }
+}
+
+//address为backup_suite连接agent使用的host:port,和plan的agent:// source对应
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentEnrollRequest {
+    pub token: String,
+    pub did: String,
+    pub hostname: String,
+    pub version: String,
+    pub address: String,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl AgentEnrollRequest {
+    fn to_sign_bytes(&self) -> Vec<u8> {
+        format!("enroll\n{}\n{}\n{}\n{}\n{}\n{}", self.token, self.did, self.hostname, self.version, self.address, self.timestamp).into_bytes()
+    }
+
+    //签名证明注册者持有DID对应的私钥
+    pub fn verify(&self) -> Result<()> {
+        verify_agent_signature(self.did.as_str(), &self.to_sign_bytes(), self.signature.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentHeartbeat {
+    pub did: String,
+    pub version: String,
+    pub address: String,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl AgentHeartbeat {
+    fn to_sign_bytes(&self) -> Vec<u8> {
+        format!("heartbeat\n{}\n{}\n{}\n{}", self.did, self.version, self.address, self.timestamp).into_bytes()
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        verify_agent_signature(self.did.as_str(), &self.to_sign_bytes(), self.signature.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_identity() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let key_path = test_dir.path().join(AGENT_IDENTITY_KEY_FILE);
+        let identity = AgentIdentity::load_or_create(&key_path).unwrap();
+        let did = identity.did();
+        assert!(did.starts_with(AGENT_DID_PREFIX));
+        //再次加载得到同一个DID
+        assert_eq!(AgentIdentity::load_or_create(&key_path).unwrap().did(), did);
+
+        let request = identity.create_enroll_request("token", "pc1", "0.2.0", "192.168.1.10:5190", 1000);
+        assert!(request.verify().is_ok());
+        let mut forged = request.clone();
+        forged.token = "other_token".to_string();
+        assert!(forged.verify().is_err());
+
+        let heartbeat = identity.create_heartbeat("0.2.0", "192.168.1.10:5190", 2000);
+        assert!(heartbeat.verify().is_ok());
+        //别的agent不能冒用这个DID发送心跳
+        let other = AgentIdentity::new(SigningKey::from_bytes(&[9u8; 32]));
+        let mut forged = other.create_heartbeat("0.2.0", "192.168.1.10:5190", 2000);
+        forged.did = did;
+        assert!(forged.verify().is_err());
+        assert!(agent_public_key_from_did("did:bns:alice").is_err());
+    }
+}
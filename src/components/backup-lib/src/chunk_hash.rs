@@ -0,0 +1,119 @@
+#![allow(unused)]
+
+use serde::{Serialize, Deserialize};
+use ndn_lib::{ChunkHasher, ChunkId};
+use anyhow::Result;
+
+//chunk_id使用的hash算法,按plan配置,记录在checkpoint上(同一个checkpoint里的chunk都用同一种算法)
+//sha256是ndn_lib的默认算法;blake3快很多,而且大块数据可以多线程计算,适合NVMe这类hash是瓶颈的source
+//注意:不同算法的chunk_id不同,切换算法后新checkpoint无法和之前的checkpoint去重
+pub const BLAKE3_HASH_TYPE: &str = "blake3";
+//一次update的数据超过这个大小时blake3使用多线程计算
+pub const BLAKE3_PARALLEL_MIN_SIZE: usize = 1024*1024; //1MB
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkHashType {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl ChunkHashType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChunkHashType::Sha256 => "sha256",
+            ChunkHashType::Blake3 => BLAKE3_HASH_TYPE,
+        }
+    }
+
+    //未知的算法按sha256处理(兼容旧数据)
+    pub fn from_str(hash_type: &str) -> Self {
+        match hash_type {
+            BLAKE3_HASH_TYPE => ChunkHashType::Blake3,
+            _ => ChunkHashType::Sha256,
+        }
+    }
+
+    //校验时按chunk_id自身的算法计算
+    pub fn from_chunk_id(chunk_id: &ChunkId) -> Self {
+        Self::from_str(chunk_id.to_obj_id().obj_type.as_str())
+    }
+}
+
+pub enum BackupChunkHasher {
+    Sha256(ChunkHasher),
+    Blake3(blake3::Hasher),
+}
+
+impl BackupChunkHasher {
+    pub fn new(hash_type: ChunkHashType) -> Result<Self> {
+        match hash_type {
+            ChunkHashType::Sha256 => {
+                let hasher = ChunkHasher::new(None).map_err(|e| anyhow::anyhow!("{}",e))?;
+                Ok(BackupChunkHasher::Sha256(hasher))
+            }
+            ChunkHashType::Blake3 => Ok(BackupChunkHasher::Blake3(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn for_chunk_id(chunk_id: &ChunkId) -> Result<Self> {
+        Self::new(ChunkHashType::from_chunk_id(chunk_id))
+    }
+
+    pub fn update_from_bytes(&mut self, content: &[u8]) {
+        match self {
+            BackupChunkHasher::Sha256(hasher) => hasher.update_from_bytes(content),
+            BackupChunkHasher::Blake3(hasher) => {
+                if content.len() >= BLAKE3_PARALLEL_MIN_SIZE {
+                    hasher.update_rayon(content);
+                } else {
+                    hasher.update(content);
+                }
+            }
+        }
+    }
+
+    pub fn finalize_chunk_id(self) -> Result<ChunkId> {
+        match self {
+            BackupChunkHasher::Sha256(hasher) => Ok(hasher.finalize_chunk_id()),
+            BackupChunkHasher::Blake3(hasher) => {
+                let chunk_id_str = format!("{}:{}", BLAKE3_HASH_TYPE, hasher.finalize().to_hex());
+                ChunkId::new(chunk_id_str.as_str()).map_err(|e| anyhow::anyhow!("{}",e))
+            }
+        }
+    }
+}
+
+pub fn calc_chunk_id(content: &[u8], hash_type: ChunkHashType) -> Result<ChunkId> {
+    let mut hasher = BackupChunkHasher::new(hash_type)?;
+    hasher.update_from_bytes(content);
+    hasher.finalize_chunk_id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc_content_chunk_id;
+
+    #[test]
+    fn test_chunk_hash_type() {
+        let content = vec![7u8; BLAKE3_PARALLEL_MIN_SIZE * 3 + 5];
+        let sha256_id = calc_chunk_id(&content, ChunkHashType::Sha256).unwrap();
+        assert_eq!(sha256_id, calc_content_chunk_id(&content).unwrap());
+        assert_eq!(ChunkHashType::from_chunk_id(&sha256_id), ChunkHashType::Sha256);
+
+        let blake3_id = calc_chunk_id(&content, ChunkHashType::Blake3).unwrap();
+        assert_eq!(blake3_id.to_string(), format!("blake3:{}", blake3::hash(&content).to_hex()));
+        assert_eq!(ChunkHashType::from_chunk_id(&blake3_id), ChunkHashType::Blake3);
+
+        //分段计算(大段走多线程)和一次计算的结果一致
+        let mut hasher = BackupChunkHasher::for_chunk_id(&blake3_id).unwrap();
+        hasher.update_from_bytes(&content[..100]);
+        hasher.update_from_bytes(&content[100..]);
+        assert_eq!(hasher.finalize_chunk_id().unwrap(), blake3_id);
+
+        assert_eq!(serde_json::to_string(&ChunkHashType::Blake3).unwrap(), "\"blake3\"");
+        assert_eq!(ChunkHashType::from_str("unknown"), ChunkHashType::Sha256);
+    }
+}
@@ -0,0 +1,316 @@
+#![allow(unused)]
+//target上chunk对象的命名方式:默认直接用chunk_id,url中指定chunk_naming=hmac时用每个target自己的密钥对chunk_id做HMAC,
+//bucket中只能看到HMAC后的对象名,无法和已知内容的hash比对;quick_hash的link目标也加密保存
+//密钥(chunk_naming_key)和其它密钥一样保存在凭证库中,target根目录的key map清单只记录命名方式和密钥的指纹,
+//用于发现用错了密钥或者同一个target混用了两种命名方式,清单中不包含密钥和chunk_id
+//凭证库只在本机,丢失后target上的数据无法恢复,密钥可以用口令加密导出(ChunkKeyEscrow)另外保存
+use serde::{Serialize, Deserialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use rand::RngCore;
+use url::Url;
+use ndn_lib::ChunkId;
+use crate::provider::{BuckyBackupError, BackupResult};
+use crate::credential::TargetCredential;
+
+pub const CHUNK_NAMING_PARAM: &str = "chunk_naming";
+pub const CHUNK_NAMING_PLAIN: &str = "chunk_id";
+pub const CHUNK_NAMING_HMAC: &str = "hmac";
+pub const CHUNK_NAMING_KEY_SECRET: &str = "chunk_naming_key";//hex编码的32字节密钥
+pub const CHUNK_KEY_MAP_NAME: &str = "bucky_backup_keymap.json";
+pub const CHUNK_KEY_MAP_VERSION: u32 = 1;
+const CHUNK_NAMING_KEY_LEN: usize = 32;
+const SEAL_NONCE_LEN: usize = 12;
+pub const CHUNK_KEY_ESCROW_VERSION: u32 = 1;
+pub const CHUNK_KEY_ESCROW_MIN_PASSPHRASE_LEN: usize = 12;
+const ESCROW_SALT_LEN: usize = 16;
+const ESCROW_KDF_ROUNDS: u32 = 200_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkNaming {
+    Plain,
+    Hmac,
+}
+
+//没有chunk_naming参数时是Plain,未知的命名方式返回错误
+pub fn get_chunk_naming(url: &str) -> BackupResult<ChunkNaming> {
+    let parsed = Url::parse(url).map_err(|e| BuckyBackupError::Failed(format!("invalid url: {}", e)))?;
+    let naming = parsed.query_pairs().find(|(k, _)| k == CHUNK_NAMING_PARAM).map(|(_, v)| v.to_string());
+    match naming.as_deref() {
+        None | Some(CHUNK_NAMING_PLAIN) => Ok(ChunkNaming::Plain),
+        Some(CHUNK_NAMING_HMAC) => Ok(ChunkNaming::Hmac),
+        Some(naming) => Err(BuckyBackupError::Failed(format!("unknown {} {}", CHUNK_NAMING_PARAM, naming))),
+    }
+}
+
+pub fn generate_chunk_naming_key() -> String {
+    let mut key = [0u8; CHUNK_NAMING_KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    hex::encode(key)
+}
+
+#[derive(Clone)]
+pub struct ChunkKeyNamer {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for ChunkKeyNamer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ChunkKeyNamer {{ key_id: {} }}", self.key_id())
+    }
+}
+
+impl ChunkKeyNamer {
+    pub fn from_hex(key: &str) -> BackupResult<Self> {
+        let key = hex::decode(key.trim())
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid {}: {}", CHUNK_NAMING_KEY_SECRET, e)))?;
+        if key.len() != CHUNK_NAMING_KEY_LEN {
+            return Err(BuckyBackupError::Failed(format!("{} must be {} bytes", CHUNK_NAMING_KEY_SECRET, CHUNK_NAMING_KEY_LEN)));
+        }
+        Ok(Self { key })
+    }
+
+    //url没有开启hmac命名时返回None;开启后密钥从凭证中读取,兼容还没有移到凭证库的url参数
+    pub fn from_url(url: &str, credential: Option<&TargetCredential>) -> BackupResult<Option<Self>> {
+        if get_chunk_naming(url)? == ChunkNaming::Plain {
+            return Ok(None);
+        }
+        let key = credential.and_then(|credential| credential.get_secret(CHUNK_NAMING_KEY_SECRET).cloned())
+            .or_else(|| Url::parse(url).ok()?.query_pairs()
+                .find(|(k, _)| k == CHUNK_NAMING_KEY_SECRET)
+                .map(|(_, v)| v.to_string()));
+        match key {
+            Some(key) => Ok(Some(Self::from_hex(key.as_str())?)),
+            None => Err(BuckyBackupError::Failed(format!("{}={} requires {}", CHUNK_NAMING_PARAM, CHUNK_NAMING_HMAC, CHUNK_NAMING_KEY_SECRET))),
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.key.as_slice())
+    }
+
+    fn hmac(&self, domain: &str, data: &[u8]) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(self.key.as_slice()).unwrap();
+        mac.update(domain.as_bytes());
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    //chunk对象在target上的名字
+    pub fn object_key(&self, chunk_id: &ChunkId) -> String {
+        hex::encode(self.hmac("chunk:", chunk_id.to_string().as_bytes()))
+    }
+
+    //密钥的指纹,保存在key map清单中
+    pub fn key_id(&self) -> String {
+        hex::encode(&self.hmac("key_id:", &[])[..8])
+    }
+
+    fn seal_cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(self.hmac("seal:", &[]).as_slice()))
+    }
+
+    //加密保存在target上的chunk_id(比如link的目标),返回hex(nonce + 密文)
+    pub fn seal_chunk_id(&self, chunk_id: &ChunkId) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self.seal_cipher().encrypt(&nonce, chunk_id.to_string().as_bytes()).unwrap();
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(encrypted.as_slice());
+        hex::encode(sealed)
+    }
+
+    pub fn open_chunk_id(&self, sealed: &str) -> BackupResult<ChunkId> {
+        let sealed = hex::decode(sealed)
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid sealed chunk id: {}", e)))?;
+        if sealed.len() <= SEAL_NONCE_LEN {
+            return Err(BuckyBackupError::Failed("sealed chunk id is too short".to_string()));
+        }
+        let plain = self.seal_cipher().decrypt(Nonce::from_slice(&sealed[..SEAL_NONCE_LEN]), &sealed[SEAL_NONCE_LEN..])
+            .map_err(|_| BuckyBackupError::Failed("open sealed chunk id failed, wrong chunk naming key?".to_string()))?;
+        let chunk_id = String::from_utf8(plain)
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid sealed chunk id: {}", e)))?;
+        ChunkId::new(chunk_id.as_str()).map_err(|e| BuckyBackupError::Failed(format!("invalid sealed chunk id: {}", e)))
+    }
+}
+
+//保存在target根目录的CHUNK_KEY_MAP_NAME对象中
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkKeyMapManifest {
+    pub version: u32,
+    pub naming: String,
+    pub key_id: Option<String>,
+    pub create_time: u64,
+}
+
+impl ChunkKeyMapManifest {
+    pub fn new(namer: Option<&ChunkKeyNamer>, create_time: u64) -> Self {
+        Self {
+            version: CHUNK_KEY_MAP_VERSION,
+            naming: if namer.is_some() { CHUNK_NAMING_HMAC.to_string() } else { CHUNK_NAMING_PLAIN.to_string() },
+            key_id: namer.map(|namer| namer.key_id()),
+            create_time,
+        }
+    }
+
+    pub fn from_json_str(manifest: &str) -> BackupResult<Self> {
+        serde_json::from_str(manifest)
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid chunk key map: {}", e)))
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    //target上已有的清单必须和当前的命名方式/密钥一致,否则写入的chunk会和已有的chunk对不上
+    pub fn check(&self, target_url: &str, namer: Option<&ChunkKeyNamer>) -> BackupResult<()> {
+        if self.version > CHUNK_KEY_MAP_VERSION {
+            return Err(BuckyBackupError::Failed(format!("target {} chunk key map version {} is not supported, upgrade backup_suite",
+                target_url, self.version)));
+        }
+        let expected = Self::new(namer, self.create_time);
+        if self.naming != expected.naming {
+            return Err(BuckyBackupError::Failed(format!("target {} uses chunk naming {}, but {} is configured",
+                target_url, self.naming, expected.naming)));
+        }
+        if self.key_id != expected.key_id {
+            return Err(BuckyBackupError::Failed(format!("target {} chunk naming key {:?} does not match the configured key {:?}",
+                target_url, self.key_id, expected.key_id)));
+        }
+        Ok(())
+    }
+}
+
+//用口令加密导出的chunk_naming_key,换机器/凭证库丢失后用它恢复密钥
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkKeyEscrow {
+    pub version: u32,
+    pub key_id: String,//恢复后用来和target上key map清单的key_id核对
+    pub salt: String,
+    pub kdf_rounds: u32,
+    pub sealed_key: String,//hex(nonce + 密文)
+}
+
+//PBKDF2-HMAC-SHA256,输出正好一个block
+fn derive_escrow_key(passphrase: &str, salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(passphrase.as_bytes()).unwrap();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block = mac.finalize().into_bytes().to_vec();
+    let mut derived = block.clone();
+    for _ in 1..rounds {
+        let mut mac = HmacSha256::new_from_slice(passphrase.as_bytes()).unwrap();
+        mac.update(block.as_slice());
+        block = mac.finalize().into_bytes().to_vec();
+        derived.iter_mut().zip(block.iter()).for_each(|(d, b)| *d ^= b);
+    }
+    derived
+}
+
+impl ChunkKeyEscrow {
+    pub fn seal(namer: &ChunkKeyNamer, passphrase: &str) -> BackupResult<Self> {
+        if passphrase.chars().count() < CHUNK_KEY_ESCROW_MIN_PASSPHRASE_LEN {
+            return Err(BuckyBackupError::Failed(format!("escrow passphrase must be at least {} characters", CHUNK_KEY_ESCROW_MIN_PASSPHRASE_LEN)));
+        }
+        let mut salt = [0u8; ESCROW_SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(derive_escrow_key(passphrase, &salt, ESCROW_KDF_ROUNDS).as_slice()));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = cipher.encrypt(&nonce, namer.key.as_slice()).unwrap();
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(encrypted.as_slice());
+        Ok(Self {
+            version: CHUNK_KEY_ESCROW_VERSION,
+            key_id: namer.key_id(),
+            salt: hex::encode(salt),
+            kdf_rounds: ESCROW_KDF_ROUNDS,
+            sealed_key: hex::encode(sealed),
+        })
+    }
+
+    pub fn open(&self, passphrase: &str) -> BackupResult<ChunkKeyNamer> {
+        if self.version > CHUNK_KEY_ESCROW_VERSION {
+            return Err(BuckyBackupError::Failed(format!("chunk key escrow version {} is not supported, upgrade backup_suite", self.version)));
+        }
+        let salt = hex::decode(self.salt.as_str())
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid chunk key escrow salt: {}", e)))?;
+        let sealed = hex::decode(self.sealed_key.as_str())
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid chunk key escrow: {}", e)))?;
+        if sealed.len() <= SEAL_NONCE_LEN || self.kdf_rounds == 0 {
+            return Err(BuckyBackupError::Failed("invalid chunk key escrow".to_string()));
+        }
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(derive_escrow_key(passphrase, &salt, self.kdf_rounds).as_slice()));
+        let key = cipher.decrypt(Nonce::from_slice(&sealed[..SEAL_NONCE_LEN]), &sealed[SEAL_NONCE_LEN..])
+            .map_err(|_| BuckyBackupError::Failed("open chunk key escrow failed, wrong passphrase?".to_string()))?;
+        let namer = ChunkKeyNamer::from_hex(hex::encode(key).as_str())?;
+        if namer.key_id() != self.key_id {
+            return Err(BuckyBackupError::Failed(format!("chunk key escrow key id {} does not match", self.key_id)));
+        }
+        Ok(namer)
+    }
+
+    pub fn from_json_str(escrow: &str) -> BackupResult<Self> {
+        serde_json::from_str(escrow)
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid chunk key escrow: {}", e)))
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_chunk_key_namer() {
+        let chunk_id = ChunkId::new("sha256:0203040506070809000102030405060708090001020304050607080900010203").unwrap();
+        let link_id = ChunkId::new("qcid:0203040506070809000102030405060708090001020304050607080900010204").unwrap();
+        assert!(ChunkKeyNamer::from_url("s3://bucket?region=us-east-1", None).unwrap().is_none());
+        assert!(ChunkKeyNamer::from_url("s3://bucket?chunk_naming=hmac", None).is_err());
+        assert!(ChunkKeyNamer::from_url("s3://bucket?chunk_naming=rot13", None).is_err());
+        assert!(ChunkKeyNamer::from_hex("0102").is_err());
+
+        let credential = TargetCredential {
+            kind: "s3".to_string(),
+            secrets: HashMap::from([(CHUNK_NAMING_KEY_SECRET.to_string(), generate_chunk_naming_key())]),
+        };
+        let namer = ChunkKeyNamer::from_url("s3://bucket?chunk_naming=hmac", Some(&credential)).unwrap().unwrap();
+        let other = ChunkKeyNamer::from_hex(generate_chunk_naming_key().as_str()).unwrap();
+        //同一个密钥得到同样的名字,名字中不包含chunk_id
+        let object_key = namer.object_key(&chunk_id);
+        assert_eq!(object_key, namer.object_key(&chunk_id));
+        assert_ne!(object_key, other.object_key(&chunk_id));
+        assert!(!object_key.contains("0203040506070809"));
+        assert!(!format!("{:?}", namer).contains(credential.get_secret(CHUNK_NAMING_KEY_SECRET).unwrap().as_str()));
+
+        let sealed = namer.seal_chunk_id(&link_id);
+        assert!(!sealed.contains("0203040506070809"));
+        assert_eq!(namer.open_chunk_id(sealed.as_str()).unwrap(), link_id);
+        assert!(other.open_chunk_id(sealed.as_str()).is_err());
+
+        let manifest = ChunkKeyMapManifest::new(Some(&namer), 1000);
+        let manifest = ChunkKeyMapManifest::from_json_str(manifest.to_json_string().as_str()).unwrap();
+        manifest.check("s3://bucket", Some(&namer)).unwrap();
+        assert!(manifest.check("s3://bucket", Some(&other)).is_err());
+        assert!(manifest.check("s3://bucket", None).is_err());
+        assert!(ChunkKeyMapManifest::new(None, 1000).check("s3://bucket", Some(&namer)).is_err());
+    }
+
+    #[test]
+    fn test_chunk_key_escrow() {
+        let namer = ChunkKeyNamer::from_hex(generate_chunk_naming_key().as_str()).unwrap();
+        assert!(ChunkKeyEscrow::seal(&namer, "short").is_err());
+        let escrow = ChunkKeyEscrow::seal(&namer, "correct horse battery").unwrap();
+        assert!(!escrow.to_json_string().contains(namer.to_hex().as_str()));
+        let escrow = ChunkKeyEscrow::from_json_str(escrow.to_json_string().as_str()).unwrap();
+        assert_eq!(escrow.key_id, namer.key_id());
+        assert_eq!(escrow.open("correct horse battery").unwrap().to_hex(), namer.to_hex());
+        assert!(escrow.open("wrong horse battery").is_err());
+    }
+}
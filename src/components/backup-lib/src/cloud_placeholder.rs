@@ -0,0 +1,201 @@
+#![allow(unused)]
+
+use serde::{Serialize, Deserialize};
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::time::Sleep;
+
+//OneDrive/Dropbox(Windows云文件API)和iCloud的占位文件:目录里能看到文件,内容在云端,第一次读取时才下载(recall on read)
+//按普通文件备份会把用户整个云盘下载到本地,LocalDir source扫描时按plan的策略处理
+//Windows: 文件属性RECALL_ON_DATA_ACCESS/RECALL_ON_OPEN/OFFLINE
+//macOS:   st_flags的SF_DATALESS(File Provider),老版本iCloud的.文件名.icloud占位文件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudPlaceholderAction {
+    Skip,//不备份
+    #[default]
+    MetadataOnly,//只记录文件属性(CloudPlaceholder item),不读取内容,恢复时跳过
+    Hydrate,//读取内容(触发下载),按hydrate_max_bytes_per_sec限速
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloudPlaceholderPolicy {
+    pub action: CloudPlaceholderAction,
+    pub hydrate_max_bytes_per_sec: u64,//0为不限速
+}
+
+const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+const SF_DATALESS: u32 = 0x40000000;
+const ICLOUD_STUB_EXT: &str = ".icloud";
+
+//metadata是symlink_metadata的结果,读取metadata不会触发下载
+pub fn is_cloud_placeholder(path: &Path, metadata: &Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        if is_placeholder_attributes(metadata.file_attributes()) {
+            return true;
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::macos::fs::MetadataExt;
+        if metadata.st_flags() & SF_DATALESS != 0 {
+            return true;
+        }
+        if let Some(file_name) = path.file_name() {
+            if is_icloud_stub_name(file_name.to_string_lossy().as_ref()) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn is_placeholder_attributes(attributes: u32) -> bool {
+    attributes & (FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS) != 0
+}
+
+//.report.pdf.icloud是report.pdf的占位文件
+fn is_icloud_stub_name(file_name: &str) -> bool {
+    file_name.starts_with('.') && file_name.ends_with(ICLOUD_STUB_EXT) && file_name.len() > ICLOUD_STUB_EXT.len() + 1
+}
+
+//老版本iCloud占位文件对应的真实文件名,不是占位文件名时返回None
+pub fn icloud_stub_real_name(file_name: &str) -> Option<&str> {
+    if !is_icloud_stub_name(file_name) {
+        return None;
+    }
+    Some(&file_name[1..file_name.len() - ICLOUD_STUB_EXT.len()])
+}
+
+//下载占位文件内容的限速,令牌桶最多积攒1秒的配额;一个task的所有source和reader共享
+pub struct HydrateThrottle {
+    bytes_per_sec: u64,
+    start_time: Instant,
+    state: Mutex<(i64, u64)>,//(tokens, last_time_ms),tokens为负数表示已经预支的字节数
+}
+
+impl HydrateThrottle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self { bytes_per_sec, start_time: Instant::now(), state: Mutex::new((0, 0)) }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.bytes_per_sec > 0
+    }
+
+    //读取了bytes之后需要等待的时间
+    fn reserve(&self, bytes: u64) -> Duration {
+        if !self.is_limited() || bytes == 0 {
+            return Duration::ZERO;
+        }
+        let now_ms = self.start_time.elapsed().as_millis() as u64;
+        let rate = self.bytes_per_sec as i64;
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now_ms.saturating_sub(state.1) as i64;
+        state.0 = (state.0 + elapsed * rate / 1000).min(rate);
+        state.1 = now_ms;
+        state.0 -= bytes as i64;
+        if state.0 >= 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis((-state.0 * 1000 / rate) as u64)
+    }
+}
+
+//读取占位文件的reader:每次读取后按限速等待,等待结束前不再读取下一段(下一段才会触发下载)
+pub struct HydrateReader<R> {
+    inner: R,
+    throttle: Arc<HydrateThrottle>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> HydrateReader<R> {
+    pub fn new(inner: R, throttle: Arc<HydrateThrottle>) -> Self {
+        Self { inner, throttle, delay: None }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HydrateReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(delay) = this.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+        }
+        let filled_len = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &result {
+            let wait = this.throttle.reserve((buf.filled().len() - filled_len) as u64);
+            if !wait.is_zero() {
+                this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+        result
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for HydrateReader<R> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.get_mut().inner).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.get_mut().inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_detect_cloud_placeholder() {
+        assert!(is_placeholder_attributes(FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS | 0x20));
+        assert!(is_placeholder_attributes(FILE_ATTRIBUTE_RECALL_ON_OPEN));
+        assert!(is_placeholder_attributes(FILE_ATTRIBUTE_OFFLINE));
+        //普通文件和只有重解析点属性的文件(比如已经下载到本地的OneDrive文件)不是占位文件
+        assert!(!is_placeholder_attributes(0x20 | 0x400));
+
+        assert!(is_icloud_stub_name(".report.pdf.icloud"));
+        assert!(!is_icloud_stub_name("report.pdf.icloud"));
+        assert!(!is_icloud_stub_name(".icloud"));
+        assert_eq!(icloud_stub_real_name(".report.pdf.icloud"), Some("report.pdf"));
+        assert_eq!(icloud_stub_real_name("report.pdf"), None);
+        assert_eq!(serde_json::to_string(&CloudPlaceholderAction::MetadataOnly).unwrap(), "\"metadata_only\"");
+    }
+
+    #[tokio::test]
+    async fn test_hydrate_reader_throttle() {
+        //每秒10000字节,开始时没有积攒的配额,每读1000字节等待100ms
+        let throttle = Arc::new(HydrateThrottle::new(10000));
+        let content = vec![7u8; 3000];
+        let mut reader = HydrateReader::new(std::io::Cursor::new(content.clone()), throttle);
+        let start_time = Instant::now();
+        let mut buf = [0u8; 1000];
+        let mut result = Vec::new();
+        loop {
+            let read_len = reader.read(&mut buf).await.unwrap();
+            if read_len == 0 {
+                break;
+            }
+            result.extend_from_slice(&buf[..read_len]);
+        }
+        assert_eq!(result, content);
+        assert!(start_time.elapsed() >= Duration::from_millis(250));
+    }
+}
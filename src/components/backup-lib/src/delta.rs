@@ -0,0 +1,188 @@
+#![allow(unused)]
+
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+use crate::provider::BackupItem;
+
+//大文件增量上传:按内容切分(CDC)文件,每段内容是一个独立的chunk,
+//文件小范围修改后只有附近的一两个chunk改变,和上一个checkpoint的chunk表比较后只上传新的chunk
+//item的chunk_id仍然是整个文件的hash(用于校验和manifest),chunk表保存在backup_items.diff_info中
+pub const DELTA_MIN_FILE_SIZE:u64 = 1024*1024*16; //16MB,更小的文件切分后chunk太少,增量上传意义不大
+pub const CDC_MIN_CHUNK_SIZE:usize = 1024*256; //256KB
+pub const CDC_AVG_CHUNK_SIZE:usize = 1024*1024; //1MB
+pub const CDC_MAX_CHUNK_SIZE:usize = 1024*1024*4; //4MB
+
+//gear hash使用的随机表,用splitmix64生成,修改后所有文件的切分点都会改变,无法和之前的checkpoint复用chunk
+const fn build_gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed: u64 = 0x6275636b795f6264;
+    let mut i = 0;
+    while i < 256 {
+        seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR_TABLE: [u64; 256] = build_gear_table();
+
+//流式切分:数据可以分多次传入,切分点只由内容决定,插入/删除数据不会影响后面的切分点
+pub struct CdcChunker {
+    hash: u64,
+    chunk_len: usize,
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+}
+
+impl CdcChunker {
+    pub fn new() -> Self {
+        Self::with_sizes(CDC_MIN_CHUNK_SIZE, CDC_AVG_CHUNK_SIZE, CDC_MAX_CHUNK_SIZE)
+    }
+
+    //chunk的平均大小约为min_size+avg_size
+    pub fn with_sizes(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let mask_bits = avg_size.next_power_of_two().trailing_zeros();
+        //使用hash的高位,高位受到更多字节的影响
+        let mask = if mask_bits == 0 { 0 } else { !0u64 << (64 - mask_bits) };
+        Self {
+            hash: 0,
+            chunk_len: 0,
+            min_size,
+            max_size,
+            mask,
+        }
+    }
+
+    //返回当前chunk在data中的结束位置,None表示data全部属于当前chunk
+    pub fn next_cut(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, byte) in data.iter().enumerate() {
+            self.chunk_len += 1;
+            //不足min_size的部分不计算hash
+            if self.chunk_len < self.min_size {
+                continue;
+            }
+            self.hash = (self.hash << 1).wrapping_add(GEAR_TABLE[*byte as usize]);
+            if self.hash & self.mask == 0 || self.chunk_len >= self.max_size {
+                self.hash = 0;
+                self.chunk_len = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDiffChunk {
+    pub pos: u64,//在文件中的位置
+    pub length: u64,
+    pub chunk_id: String,
+    pub reused: bool,//上一个checkpoint已经有这个chunk,本次没有上传
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileDiffInfo {
+    pub base_checkpoint_id: Option<String>,//比较的上一个checkpoint,第一次备份时为None
+    pub chunks: Vec<FileDiffChunk>,
+}
+
+impl FileDiffInfo {
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json_str(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        serde_json::from_str(s).ok()
+    }
+
+    pub fn chunk_ids(&self) -> HashSet<String> {
+        self.chunks.iter().map(|chunk| chunk.chunk_id.clone()).collect()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.length).sum()
+    }
+
+    pub fn reused_size(&self) -> u64 {
+        self.chunks.iter().filter(|chunk| chunk.reused).map(|chunk| chunk.length).sum()
+    }
+
+    pub fn add_chunk(&mut self, chunk_id: String, length: u64, reused: bool) {
+        let pos = self.total_size();
+        self.chunks.push(FileDiffChunk { pos, length, chunk_id, reused });
+    }
+}
+
+//从db加载的item中diff_info可能是空字符串
+pub fn load_item_diff_info(item: &BackupItem) -> Option<FileDiffInfo> {
+    item.diff_info.as_ref().and_then(|diff_info| FileDiffInfo::from_json_str(diff_info.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cut_all(chunker: &mut CdcChunker, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        let mut current = Vec::new();
+        //分成不规则的小段传入,结果和一次传入相同
+        for piece in data.chunks(1000) {
+            let mut pos = 0;
+            while pos < piece.len() {
+                match chunker.next_cut(&piece[pos..]) {
+                    Some(cut) => {
+                        current.extend_from_slice(&piece[pos..pos + cut]);
+                        chunks.push(std::mem::take(&mut current));
+                        pos += cut;
+                    }
+                    None => {
+                        current.extend_from_slice(&piece[pos..]);
+                        pos = piece.len();
+                    }
+                }
+            }
+        }
+        if !current.is_empty() {
+            chunks.push(current);
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_cdc_chunker() {
+        let mut seed: u64 = 7;
+        let data: Vec<u8> = (0..1024 * 1024).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect();
+        let chunks = cut_all(&mut CdcChunker::with_sizes(4096, 16384, 65536), &data);
+        assert!(chunks.len() > 10);
+        assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.len() >= 4096 && chunk.len() <= 65536));
+        assert_eq!(chunks.concat(), data);
+
+        //在中间插入数据,只有插入位置附近的chunk改变
+        let mut modified = data.clone();
+        modified.splice(500 * 1024..500 * 1024, vec![1u8; 100]);
+        let modified_chunks = cut_all(&mut CdcChunker::with_sizes(4096, 16384, 65536), &modified);
+        let changed = modified_chunks.iter().filter(|chunk| !chunks.contains(chunk)).count();
+        assert!(changed <= 2);
+
+        let mut diff_info = FileDiffInfo::default();
+        diff_info.add_chunk("sha256:a".to_string(), 100, false);
+        diff_info.add_chunk("sha256:b".to_string(), 50, true);
+        assert_eq!(diff_info.chunks[1].pos, 100);
+        assert_eq!(diff_info.reused_size(), 50);
+        assert_eq!(FileDiffInfo::from_json_str(diff_info.to_json_string().as_str()).unwrap(), diff_info);
+        assert!(FileDiffInfo::from_json_str("").is_none());
+    }
+}
@@ -0,0 +1,180 @@
+#![allow(unused)]
+//engine把task/checkpoint的状态变化发布到EventBus,通知/统计/webui等子系统订阅后各自处理,
+//不需要再轮询task_db或者在engine里直接调用这些子系统
+//订阅者处理太慢时会丢失最早的事件(broadcast::error::RecvError::Lagged),订阅者可以通过get_recent_events补齐
+//放在backup-lib里,agent和插件也可以直接订阅,不需要依赖backup_suite的task_db
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+//保留最近的事件,给没有长连接的订阅者(webui的轮询)使用
+const MAX_RECENT_EVENTS: usize = 256;
+//同一个task的进度事件最短间隔,传输线程每完成一个chunk都会发布进度,
+//不限制时大量小文件会把最近事件和订阅者的channel都挤满
+pub const PROGRESS_EVENT_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum BackupEvent {
+    TaskCreated { task_id: String, plan_id: String, checkpoint_id: String, task_type: String },
+    TaskStarted { task_id: String },
+    TaskProgress { task_id: String, completed_item_count: u64, item_count: u64, completed_size: u64, total_size: u64 },
+    TaskPaused { task_id: String },
+    TaskFailed { task_id: String, reason: String },
+    TaskDone { task_id: String },
+    TaskCancelled { task_id: String },
+    TaskRetryGiveUp { task_id: String, plan_id: String, retry_attempt: u32 },//失败的task用完了重试次数
+    //备份超出存储配额,task失败
+    QuotaExceeded { task_id: String, plan_id: String, scope: String, scope_key: String, max_bytes: u64, used_bytes: u64, required_bytes: u64 },
+    CheckPointStateChanged { checkpoint_id: String, plan_id: String, state: String },
+    //恢复演练中有文件不能恢复或者校验失败
+    RestoreDrillFailed { drill_id: String, plan_id: String, checkpoint_id: String, failed_count: u64 },
+    //plan的RPO/RTO进入不达标状态,objective为rpo或rto
+    SlaBreached { plan_id: String, objective: String, target_secs: u64, actual_secs: Option<u64>, reason: String },
+    MaintenanceModeChanged { enabled: bool, reason: String },
+}
+
+impl BackupEvent {
+    //task结束后不会再有进度事件,可以清理进度限流的记录
+    fn finished_task_id(&self) -> Option<&str> {
+        match self {
+            BackupEvent::TaskPaused { task_id }
+            | BackupEvent::TaskFailed { task_id, .. }
+            | BackupEvent::TaskDone { task_id }
+            | BackupEvent::TaskCancelled { task_id } => Some(task_id.as_str()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEventRecord {
+    pub seq: u64,
+    pub time: u64,
+    pub event: BackupEvent,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<BackupEventRecord>,
+    recent_events: Mutex<(u64, VecDeque<BackupEventRecord>)>,//(最后一个事件的seq, 最近的事件)
+    last_progress_time: Mutex<HashMap<String, u64>>,//task_id -> 最近一次发布进度事件的时间(ms)
+    progress_interval_ms: u64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::new_with_progress_interval(PROGRESS_EVENT_INTERVAL_MS)
+    }
+
+    pub fn new_with_progress_interval(progress_interval_ms: u64) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            recent_events: Mutex::new((0, VecDeque::new())),
+            last_progress_time: Mutex::new(HashMap::new()),
+            progress_interval_ms,
+        }
+    }
+
+    fn now_ms() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+
+    pub fn publish(&self, event: BackupEvent) {
+        let now = Self::now_ms();
+        {
+            let mut last_progress_time = self.last_progress_time.lock().unwrap();
+            if let BackupEvent::TaskProgress { task_id, .. } = &event {
+                if let Some(last_time) = last_progress_time.get(task_id) {
+                    if now.saturating_sub(*last_time) < self.progress_interval_ms {
+                        return;
+                    }
+                }
+                last_progress_time.insert(task_id.clone(), now);
+            } else if let Some(task_id) = event.finished_task_id() {
+                last_progress_time.remove(task_id);
+            }
+        }
+        let mut recent_events = self.recent_events.lock().unwrap();
+        recent_events.0 += 1;
+        let record = BackupEventRecord {
+            seq: recent_events.0,
+            time: now,
+            event,
+        };
+        if recent_events.1.len() >= MAX_RECENT_EVENTS {
+            recent_events.1.pop_front();
+        }
+        recent_events.1.push_back(record.clone());
+        drop(recent_events);
+        //没有订阅者时send返回错误,忽略
+        let _ = self.sender.send(record);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BackupEventRecord> {
+        self.sender.subscribe()
+    }
+
+    //返回seq大于since_seq的事件
+    pub fn get_recent_events(&self, since_seq: u64) -> Vec<BackupEventRecord> {
+        let recent_events = self.recent_events.lock().unwrap();
+        recent_events.1.iter()
+            .filter(|record| record.seq > since_seq)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_and_subscribe() {
+        let bus = EventBus::new();
+        //还没有订阅者时发布也不会失败
+        bus.publish(BackupEvent::TaskStarted { task_id: "task_1".to_string() });
+
+        let mut receiver = bus.subscribe();
+        bus.publish(BackupEvent::TaskPaused { task_id: "task_1".to_string() });
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.seq, 2);
+        assert_eq!(record.event, BackupEvent::TaskPaused { task_id: "task_1".to_string() });
+
+        for _ in 0..MAX_RECENT_EVENTS {
+            bus.publish(BackupEvent::TaskDone { task_id: "task_1".to_string() });
+        }
+        assert_eq!(bus.get_recent_events(0).len(), MAX_RECENT_EVENTS);
+        let events = bus.get_recent_events(MAX_RECENT_EVENTS as u64);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].seq, MAX_RECENT_EVENTS as u64 + 1);
+
+        let value = serde_json::to_value(&events[0].event).unwrap();
+        assert_eq!(value["type"], "TaskDone");
+    }
+
+    #[test]
+    fn test_progress_rate_limit() {
+        let bus = EventBus::new_with_progress_interval(60 * 1000);
+        let progress = |completed_item_count: u64| BackupEvent::TaskProgress {
+            task_id: "task_1".to_string(),
+            completed_item_count,
+            item_count: 10,
+            completed_size: completed_item_count,
+            total_size: 10,
+        };
+        bus.publish(progress(1));
+        bus.publish(progress(2));
+        bus.publish(BackupEvent::TaskProgress { task_id: "task_2".to_string(), completed_item_count: 1, item_count: 1, completed_size: 1, total_size: 1 });
+        assert_eq!(bus.get_recent_events(0).len(), 2);
+        //task结束后重新开始,第一个进度事件不受之前的限制
+        bus.publish(BackupEvent::TaskPaused { task_id: "task_1".to_string() });
+        bus.publish(progress(3));
+        let events = bus.get_recent_events(0);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[3].event, progress(3));
+    }
+}
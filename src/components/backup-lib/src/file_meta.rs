@@ -0,0 +1,220 @@
+#![allow(unused)]
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use log::*;
+
+//备份时记录的文件属性,恢复完成后再写回文件
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemFileMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atime_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub xattrs: HashMap<String, String>,//value是hex编码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,//Symlink:链接指向的路径,HardLink:共享内容的item_id
+}
+
+fn system_time_to_ns(time: std::io::Result<std::time::SystemTime>) -> Option<u64> {
+    let duration = time.ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(duration.as_nanos() as u64)
+}
+
+impl ItemFileMeta {
+    pub fn from_metadata(path: &Path, metadata: &std::fs::Metadata) -> Self {
+        let mut meta = ItemFileMeta {
+            mtime_ns: system_time_to_ns(metadata.modified()),
+            atime_ns: system_time_to_ns(metadata.accessed()),
+            ..Default::default()
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            meta.mode = Some(metadata.mode());
+            meta.uid = Some(metadata.uid());
+            meta.gid = Some(metadata.gid());
+            match xattr::list(path) {
+                Ok(names) => {
+                    for name in names {
+                        if let Ok(Some(value)) = xattr::get(path, &name) {
+                            meta.xattrs.insert(name.to_string_lossy().to_string(), hex::encode(value));
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!("list xattrs of {} failed: {}", path.to_string_lossy(), e);
+                }
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            if metadata.permissions().readonly() {
+                meta.mode = Some(0o444);
+            }
+        }
+
+        meta
+    }
+
+    pub fn strip_ownership(&mut self) {
+        self.uid = None;
+        self.gid = None;
+    }
+
+    pub fn strip_xattrs(&mut self) {
+        self.xattrs.clear();
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json_str(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        serde_json::from_str(s).ok()
+    }
+
+    //先设置时间(之后的chmod可能让文件变成只读),再chown(会清掉setuid位),最后chmod
+    //ownership和xattrs失败只记录日志,不影响恢复结果
+    pub fn apply_to_path(&self, path: &Path) -> std::io::Result<()> {
+        if self.mtime_ns.is_some() || self.atime_ns.is_some() {
+            let file = std::fs::OpenOptions::new().write(true).open(path)?;
+            let mut times = std::fs::FileTimes::new();
+            if let Some(mtime_ns) = self.mtime_ns {
+                times = times.set_modified(UNIX_EPOCH + Duration::from_nanos(mtime_ns));
+            }
+            if let Some(atime_ns) = self.atime_ns {
+                times = times.set_accessed(UNIX_EPOCH + Duration::from_nanos(atime_ns));
+            }
+            file.set_times(times)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if self.uid.is_some() || self.gid.is_some() {
+                if let Err(e) = std::os::unix::fs::chown(path, self.uid, self.gid) {
+                    warn!("chown {} failed: {}", path.to_string_lossy(), e);
+                }
+            }
+            for (name, value) in self.xattrs.iter() {
+                match hex::decode(value) {
+                    Ok(value) => {
+                        if let Err(e) = xattr::set(path, name, &value) {
+                            warn!("set xattr {} of {} failed: {}", name, path.to_string_lossy(), e);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("invalid xattr {} value: {}", name, e);
+                    }
+                }
+            }
+            if let Some(mode) = self.mode {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            if let Some(mode) = self.mode {
+                let mut permissions = std::fs::metadata(path)?.permissions();
+                permissions.set_readonly(mode & 0o222 == 0);
+                std::fs::set_permissions(path, permissions)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+//把source的xattrs/owner/权限复制到dest,不跟随符号链接,任何一项失败都返回错误
+//和apply_to_path不同,调用者(例如本地快照)要求副本的属性和source完全一致,不能只记录日志
+#[cfg(unix)]
+pub fn copy_path_attributes(source: &Path, dest: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let metadata = std::fs::symlink_metadata(source)?;
+    //source所在的文件系统不支持xattr时没有需要复制的属性
+    let names = match xattr::list(source) {
+        Ok(names) => names.collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        if let Some(value) = xattr::get(source, &name)? {
+            xattr::set(dest, &name, &value).map_err(|e| std::io::Error::new(e.kind(),
+                format!("set xattr {} of {} failed: {}", name.to_string_lossy(), dest.to_string_lossy(), e)))?;
+        }
+    }
+    std::os::unix::fs::lchown(dest, Some(metadata.uid()), Some(metadata.gid())).map_err(|e| std::io::Error::new(e.kind(),
+        format!("chown {} to {}:{} failed: {}", dest.to_string_lossy(), metadata.uid(), metadata.gid(), e)))?;
+    //符号链接本身没有权限位,chown之后再chmod,chown会清掉setuid位
+    if !metadata.file_type().is_symlink() {
+        std::fs::set_permissions(dest, std::fs::Permissions::from_mode(metadata.mode() & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_meta_capture_and_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        std::fs::write(&src, b"hello").unwrap();
+        std::fs::write(&dst, b"hello").unwrap();
+
+        let file = std::fs::OpenOptions::new().write(true).open(&src).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+        drop(file);
+
+        let meta = ItemFileMeta::from_metadata(&src, &std::fs::metadata(&src).unwrap());
+        let meta = ItemFileMeta::from_json_str(meta.to_json_string().as_str()).unwrap();
+        meta.apply_to_path(&dst).unwrap();
+
+        let dst_meta = std::fs::metadata(&dst).unwrap();
+        assert_eq!(system_time_to_ns(dst_meta.modified()), meta.mtime_ns);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(dst_meta.mode() & 0o7777, meta.mode.unwrap() & 0o7777);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_path_attributes() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        std::fs::write(&src, b"hello").unwrap();
+        std::fs::write(&dst, b"hello").unwrap();
+        std::fs::set_permissions(&src, std::fs::Permissions::from_mode(0o640)).unwrap();
+        //有的文件系统(例如tmpfs的老版本)不支持user xattr
+        let has_xattr = xattr::set(&src, "user.backup_test", b"value").is_ok();
+
+        copy_path_attributes(&src, &dst).unwrap();
+        let (src_meta, dst_meta) = (std::fs::metadata(&src).unwrap(), std::fs::metadata(&dst).unwrap());
+        assert_eq!(dst_meta.mode() & 0o7777, 0o640);
+        assert_eq!((dst_meta.uid(), dst_meta.gid()), (src_meta.uid(), src_meta.gid()));
+        if has_xattr {
+            assert_eq!(xattr::get(&dst, "user.backup_test").unwrap(), Some(b"value".to_vec()));
+        }
+    }
+}
@@ -1,7 +1,49 @@
 mod provider;
 mod local_chunk_provider;
+mod pack;
+mod chunk_hash;
+mod file_meta;
+mod name_collision;
+mod restore_conflict;
+mod local_path;
+mod credential;
+mod backup_url;
+mod target_stats;
+mod target_limit;
+mod target_layout;
+mod provider_registry;
+mod agent;
+mod agent_enroll;
+mod ndn_transfer;
+mod delta;
+mod chunk_naming;
+mod parallel_hash;
+mod reparse_point;
+mod cloud_placeholder;
+mod event_bus;
 pub use provider::*;
 pub use local_chunk_provider::*;
+pub use pack::*;
+pub use chunk_hash::*;
+pub use file_meta::*;
+pub use name_collision::*;
+pub use restore_conflict::*;
+pub use local_path::*;
+pub use credential::*;
+pub use backup_url::*;
+pub use target_stats::*;
+pub use target_limit::*;
+pub use target_layout::*;
+pub use provider_registry::*;
+pub use agent::*;
+pub use agent_enroll::*;
+pub use ndn_transfer::*;
+pub use delta::*;
+pub use chunk_naming::*;
+pub use parallel_hash::*;
+pub use reparse_point::*;
+pub use cloud_placeholder::*;
+pub use event_bus::*;
 
 
 pub struct DiffObject {
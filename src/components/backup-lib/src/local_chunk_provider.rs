@@ -7,33 +7,102 @@ use tokio::{
     fs::{self, File,OpenOptions}, 
     io::{self, AsyncRead,AsyncWrite, AsyncReadExt, AsyncWriteExt, AsyncSeek, AsyncSeekExt}, 
 };
-use std::{collections::HashMap};
+use std::collections::{HashMap, HashSet};
 use std::io::SeekFrom;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::pin::Pin;
+use std::task::{Context, Poll};
 use tokio::sync::Mutex;
 use serde_json::json;
+use serde::{Serialize, Deserialize};
 use url::{form_urlencoded::Target, Url};
 use ndn_lib::{ChunkId, ChunkReader, ChunkWriter, NamedDataStore, NdnError};
 use ndn_lib::{ChunkHasher, ChunkReadSeek};
 use log::*;
 
 use crate::provider::*;
+use crate::chunk_hash::*;
+use crate::file_meta::ItemFileMeta;
+use crate::local_path::*;
+use crate::target_layout::TARGET_LAYOUT_MARKER_NAME;
+use crate::parallel_hash::*;
+use crate::reparse_point::*;
+use crate::cloud_placeholder::*;
 
 //待备份的chunk都以文件的形式平摊的保存目录下
 pub struct LocalDirChunkProvider {
     pub dir_path: String,
-
+    prepare_hash: Option<(ChunkHashType, ParallelHashConfig)>,//开启后prepare时多线程计算大文件的chunk_id
+    reparse_point_policy: ReparsePointPolicy,
+    recursive: bool,//是否扫描子目录,默认和老版本一样只备份dir_path下的文件
+    cloud_placeholder_policy: CloudPlaceholderPolicy,
+    hydrate_throttle: Arc<HydrateThrottle>,//读取占位文件的限速,和同一个task的其它source共享
 }
 
 impl LocalDirChunkProvider {
     pub async fn new(dir_path: String)->Result<Self>{
         info!("new local dir chunk provider, dir_path: {}", dir_path);
         Ok(LocalDirChunkProvider {
-            dir_path
+            dir_path,
+            prepare_hash: None,
+            reparse_point_policy: ReparsePointPolicy::default(),
+            recursive: false,
+            cloud_placeholder_policy: CloudPlaceholderPolicy::default(),
+            hydrate_throttle: Arc::new(HydrateThrottle::new(0)),
         })
     }
+
+    //Windows上加\\?\前缀,支持超过MAX_PATH的路径
+    fn get_item_path(&self, item_id: &str) -> PathBuf {
+        join_item_path(&to_long_path(Path::new(&self.dir_path)), item_id)
+    }
+
+    //打开时再判断是否是占位文件:已经下载过的文件不再是占位文件,不需要限速
+    async fn need_hydrate_throttle(&self, file_path: &Path) -> bool {
+        if self.cloud_placeholder_policy.action != CloudPlaceholderAction::Hydrate || !self.hydrate_throttle.is_limited() {
+            return false;
+        }
+        match fs::symlink_metadata(file_path).await {
+            Ok(metadata) => is_cloud_placeholder(file_path, &metadata),
+            Err(_) => false,
+        }
+    }
+}
+
+impl LocalDirChunkProvider {
+    //计算失败的item保留chunk_id为None,由engine重新读取并处理错误;计算之后文件被修改时engine上传前会重新计算
+    //限速下载的占位文件(throttled_items)不在这里读取,由engine通过open_item限速读取
+    async fn hash_prepared_items(&self, backup_items: &mut Vec<BackupItem>, hash_type: ChunkHashType, config: &ParallelHashConfig,
+        throttled_items: &HashSet<String>) {
+        let indexes: Vec<usize> = backup_items.iter().enumerate()
+            .filter(|(_, item)| item.item_type == BackupItemType::Chunk && item.size >= config.min_file_size)
+            .filter(|(_, item)| !throttled_items.contains(&item.item_id))
+            .map(|(index, _)| index)
+            .collect();
+        if indexes.is_empty() {
+            return;
+        }
+        let files: Vec<(PathBuf, u64)> = indexes.iter()
+            .map(|index| (self.get_item_path(&backup_items[*index].item_id), backup_items[*index].size))
+            .collect();
+        let config = config.clone();
+        let start_time = std::time::Instant::now();
+        let results = match tokio::task::spawn_blocking(move || hash_files_parallel(&files, hash_type, &config)).await {
+            Ok(results) => results,
+            Err(e) => {
+                warn!("prepare_items parallel hash error:{}",e.to_string());
+                return;
+            }
+        };
+        for (index, result) in indexes.into_iter().zip(results) {
+            match result {
+                Ok(chunk_id) => backup_items[index].chunk_id = Some(chunk_id.to_string()),
+                Err(e) => warn!("prepare_items hash item {} error:{}", backup_items[index].item_id, e.to_string()),
+            }
+        }
+        info!("prepare_items parallel hash done, cost {:?}", start_time.elapsed());
+    }
 }
 
 #[async_trait]
@@ -48,11 +117,12 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
     }
 
     fn get_source_url(&self)->String {
-        format!("file:///{}",self.dir_path)
+        translate_local_url_from_path(Path::new(&self.dir_path))
+            .unwrap_or_else(|_| format!("file:///{}",self.dir_path))
     }
 
     async fn open_item(&self, item_id: &str)->BackupResult<Pin<Box<dyn ChunkReadSeek + Send + Sync + Unpin>>> {
-        let file_path = Path::new(&self.dir_path).join(item_id);
+        let file_path = self.get_item_path(item_id);
         let file = OpenOptions::new()
             .read(true)
             .open(&file_path)
@@ -62,11 +132,14 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
                 BuckyBackupError::TryLater(e.to_string())
             })?;
 
+        if self.need_hydrate_throttle(&file_path).await {
+            return Ok(Box::pin(HydrateReader::new(file, self.hydrate_throttle.clone())));
+        }
         Ok(Box::pin(file))
     }
 
     async fn open_item_chunk_reader(&self, item_id: &str,offset:u64)->BackupResult<ChunkReader> {
-        let file_path = Path::new(&self.dir_path).join(item_id);
+        let file_path = self.get_item_path(item_id);
         let mut file = OpenOptions::new()
             .read(true)
             .open(&file_path)
@@ -82,6 +155,9 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
                 BuckyBackupError::TryLater(e.to_string())
             })?;
         }
+        if self.need_hydrate_throttle(&file_path).await {
+            return Ok(Box::pin(HydrateReader::new(file, self.hydrate_throttle.clone())));
+        }
         Ok(Box::pin(file))
     }
     //async fn close_item(&self, item_id: &str)->Result<()>;
@@ -93,116 +169,275 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
         true
     }
 
-    async fn prepare_items(&self)->BackupResult<(Vec<BackupItem>,bool)> {
-        //遍历dir_path目录下的所有文件，生成BackupItem列表
+    async fn check_item_changed(&self, item: &mut BackupItem)->BackupResult<bool> {
+        let file_path = self.get_item_path(&item.item_id);
+        let metadata = fs::symlink_metadata(&file_path).await.map_err(|e| {
+            warn!("check_item_changed: get metadata failed! {}", e.to_string());
+            BuckyBackupError::TryLater(e.to_string())
+        })?;
+        let modified = metadata.modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let mut file_meta = item.file_meta.as_ref()
+            .and_then(|s| ItemFileMeta::from_json_str(s))
+            .unwrap_or_default();
 
-        let mut backup_items = Vec::new();
+        let mtime_ns = modified.as_nanos() as u64;
+        let is_changed = if file_meta.mtime_ns.is_some() {
+            file_meta.mtime_ns != Some(mtime_ns)
+        } else {
+            item.last_modify_time != modified.as_secs()
+        };
+        if !is_changed && metadata.len() == item.size {
+            return Ok(false);
+        }
 
-        // Read the directory
-        let mut entries = fs::read_dir(&self.dir_path).await
-            .map_err(|e| {
-                warn!("prepare_items error:{}",e.to_string());
-                BuckyBackupError::Internal(e.to_string())
-            })?;
+        info!("item {} changed, size: {} -> {}, mtime: {:?} -> {}", item.item_id, item.size, metadata.len(), file_meta.mtime_ns, mtime_ns);
+        item.size = metadata.len();
+        item.last_modify_time = modified.as_secs();
+        file_meta.mtime_ns = Some(mtime_ns);
+        item.file_meta = Some(file_meta.to_json_string());
+        Ok(true)
+    }
+
+    fn enable_prepare_hash(&mut self, hash_type: ChunkHashType, config: ParallelHashConfig) {
+        self.prepare_hash = Some((hash_type, config));
+    }
+
+    fn set_reparse_point_policy(&mut self, policy: ReparsePointPolicy) {
+        self.reparse_point_policy = policy;
+    }
+
+    fn set_recursive_scan(&mut self, recursive: bool) {
+        self.recursive = recursive;
+    }
+
+    fn set_cloud_placeholder_policy(&mut self, policy: CloudPlaceholderPolicy, throttle: Arc<HydrateThrottle>) {
+        self.hydrate_throttle = throttle;
+        self.cloud_placeholder_policy = policy;
+    }
 
+    async fn prepare_items(&self)->BackupResult<(Vec<BackupItem>,bool)> {
+        //遍历dir_path目录下的所有文件(recursive时包括子目录)，生成BackupItem列表,item_id是用/分隔的相对路径
+
+        let mut backup_items = Vec::new();
         let now = std::time::SystemTime::now()
             .duration_since(std::time::SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        let mut hardlinks:HashMap<(u64,u64),String> = HashMap::new();
+        let mut throttled_items:HashSet<String> = HashSet::new();
+        //跟随目录链接时可能出现循环,已经扫描过的目录(真实路径)不再扫描
+        let root_path = to_long_path(Path::new(&self.dir_path));
+        let mut visited_dirs:HashSet<PathBuf> = HashSet::new();
+        visited_dirs.insert(fs::canonicalize(&root_path).await.unwrap_or(root_path.clone()));
+        let mut pending_dirs = vec![(root_path, String::new())];
 
-        loop {
-            let entry = entries.next_entry().await
+        while let Some((dir_path, dir_item_id)) = pending_dirs.pop() {
+            let mut entries = fs::read_dir(&dir_path).await
                 .map_err(|e| {
-                    warn!("prepare_items error:{}",e.to_string());
+                    warn!("prepare_items read dir {:?} error:{}", dir_path, e.to_string());
                     BuckyBackupError::Internal(e.to_string())
                 })?;
 
-            if entry.is_none() {
-                break;
-            }
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_file() {
-                // Create a BackupItem for each file
-                let metadata = fs::metadata(&path).await
+            loop {
+                let entry = entries.next_entry().await
                     .map_err(|e| {
-                        warn!("prepare_items error:{}",e.to_string());
+                        warn!("prepare_items read dir {:?} error:{}", dir_path, e.to_string());
                         BuckyBackupError::Internal(e.to_string())
                     })?;
-                
+
+                if entry.is_none() {
+                    break;
+                }
+                let entry = entry.unwrap();
+                let path = entry.path();
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let item_id = if dir_item_id.is_empty() {
+                    file_name
+                } else {
+                    format!("{}/{}", dir_item_id, file_name)
+                };
+                //先不跟随符号链接,按reparse_point_policy决定怎么处理链接
+                let mut metadata = fs::symlink_metadata(&path).await
+                    .map_err(|e| {
+                        warn!("prepare_items get metadata of {:?} error:{}", path, e.to_string());
+                        BuckyBackupError::Internal(e.to_string())
+                    })?;
+                let mut is_link = false;
+                match get_reparse_point_kind(&metadata) {
+                    Some(kind) if self.reparse_point_policy == ReparsePointPolicy::Skip => {
+                        info!("prepare_items skip reparse point {:?}, kind: {:?}", path, kind);
+                        continue;
+                    }
+                    Some(ReparsePointKind::Link) if self.reparse_point_policy == ReparsePointPolicy::Follow => {
+                        match fs::metadata(&path).await {
+                            Ok(target_metadata) => metadata = target_metadata,
+                            Err(e) => {
+                                //链接指向的目标不存在,退回到备份链接本身
+                                warn!("prepare_items follow link {:?} error:{}, backup the link itself", path, e.to_string());
+                                is_link = true;
+                            }
+                        }
+                    }
+                    Some(ReparsePointKind::Link) => is_link = true,
+                    _ => {}
+                }
+
+                if !is_link && metadata.is_dir() {
+                    if !self.recursive {
+                        debug!("prepare_items skip sub dir {:?}, recursive scan is disabled", path);
+                        continue;
+                    }
+                    let real_path = fs::canonicalize(&path).await.unwrap_or(path.clone());
+                    if !visited_dirs.insert(real_path) {
+                        warn!("prepare_items dir {:?} is already scanned (link loop?), skip", path);
+                        continue;
+                    }
+                    pending_dirs.push((path, item_id));
+                    continue;
+                }
+                if !is_link && !metadata.is_file() {
+                    continue;
+                }
+                //云盘占位文件:读取内容会从云端下载,按cloud_placeholder_policy处理
+                let is_placeholder = !is_link && is_cloud_placeholder(&path, &metadata);
+                //老版本iCloud的.report.pdf.icloud:本地只有占位文件,读取它不会下载report.pdf,按真实的文件名只记录属性
+                let mut item_id = item_id;
+                let mut is_icloud_stub = false;
+                if is_placeholder {
+                    if let Some(real_name) = icloud_stub_real_name(entry.file_name().to_string_lossy().as_ref()) {
+                        if fs::symlink_metadata(path.with_file_name(real_name)).await.is_ok() {
+                            //真实文件已经下载到本地,由真实文件的item备份
+                            continue;
+                        }
+                        item_id = if dir_item_id.is_empty() { real_name.to_string() } else { format!("{}/{}", dir_item_id, real_name) };
+                        is_icloud_stub = true;
+                    }
+                }
+                if is_placeholder {
+                    match self.cloud_placeholder_policy.action {
+                        CloudPlaceholderAction::Skip => {
+                            info!("prepare_items skip cloud placeholder {:?}", path);
+                            continue;
+                        }
+                        CloudPlaceholderAction::Hydrate if is_icloud_stub => {
+                            warn!("prepare_items can't hydrate legacy icloud stub {:?}, record metadata only", path);
+                        }
+                        CloudPlaceholderAction::Hydrate if self.hydrate_throttle.is_limited() => {
+                            throttled_items.insert(item_id.clone());
+                        }
+                        _ => {}
+                    }
+                }
+
                 let last_modify_time = metadata.modified()
                     .map_err(|e| {
                         warn!("prepare_items error:{}",e.to_string());
                         BuckyBackupError::Internal(e.to_string())
                     })?
-                    .elapsed()
+                    .duration_since(std::time::UNIX_EPOCH)
                     .map_err(|e| {
                         warn!("prepare_items error:{}",e.to_string());
                         BuckyBackupError::Internal(e.to_string())
                     })?
                     .as_secs();
 
-                info!("prepare item: {:?}, size: {}", path, metadata.len());
+                let mut file_meta = ItemFileMeta::from_metadata(&path, &metadata);
+                let mut item_type = BackupItemType::Chunk;
+                let mut size = metadata.len();
+                if is_link {
+                    let link_target = fs::read_link(&path).await
+                        .map_err(|e| {
+                            warn!("prepare_items read link error:{}",e.to_string());
+                            BuckyBackupError::Internal(e.to_string())
+                        })?;
+                    file_meta.link_target = Some(link_target.to_string_lossy().to_string());
+                    item_type = BackupItemType::Symlink;
+                    size = 0;
+                } else if is_placeholder && (is_icloud_stub || self.cloud_placeholder_policy.action == CloudPlaceholderAction::MetadataOnly) {
+                    //size保留云端文件的大小,只用于显示;老版本iCloud占位文件的大小是占位文件本身的,不记录
+                    item_type = BackupItemType::CloudPlaceholder;
+                    if is_icloud_stub {
+                        size = 0;
+                    }
+                } else if let Some(inode_key) = get_hardlink_key(&metadata) {
+                    //同一个inode的内容只备份一次,后续出现的都记为指向第一个item的HardLink
+                    if let Some(first_item_id) = hardlinks.get(&inode_key) {
+                        file_meta.link_target = Some(first_item_id.clone());
+                        item_type = BackupItemType::HardLink;
+                        size = 0;
+                    } else {
+                        hardlinks.insert(inode_key, item_id.clone());
+                    }
+                }
+
+                info!("prepare item: {:?}, type: {:?}, size: {}", path, item_type, size);
                 let backup_item = BackupItem {
-                    item_id: path.file_name().unwrap().to_string_lossy().to_string(),
-                    item_type:BackupItemType::Chunk,
+                    item_id,
+                    item_type,
                     chunk_id: None,
                     quick_hash: None,
                     state: BackupItemState::New,
-                    size: metadata.len(),
+                    size,
                     last_modify_time,
                     create_time: now,
                     have_cache: false,
                     progress: "".to_string(),
                     diff_info:None,
+                    pack_info:None,
+                    file_meta:Some(file_meta.to_json_string()),
+                    is_fuzzy:false,
                 };
                 backup_items.push(backup_item);
             }
-           
         }
 
+        if let Some((hash_type, config)) = self.prepare_hash.as_ref() {
+            self.hash_prepared_items(&mut backup_items, *hash_type, config, &throttled_items).await;
+        }
         Ok((backup_items,true))
     }
 
     async fn init_for_restore(&self, restore_config:&RestoreConfig)->Result<()> {
-        let restore_url:Url = Url::parse(restore_config.restore_location_url.as_str())
-            .map_err(|e| anyhow::anyhow!("{}",e))?;
-
-        if restore_url.scheme() != "file" {
-            return Err(anyhow::anyhow!("restore_url scheme must be file"));
-        }
-
-        let restore_path = restore_url.path();  
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        fs::create_dir_all(&restore_path).await?;
+        //上次被中断的恢复留下的临时文件
+        remove_stale_restore_partials(&restore_path).await?;
         //TODO : clean up restore_path
         Ok(())
     }
 
     async fn open_writer_for_restore(&self, item: &BackupItem,restore_config:&RestoreConfig,offset:u64)->BackupResult<(ChunkWriter,u64)> {
-        let restore_url:Url = Url::parse(restore_config.restore_location_url.as_str())
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())
            .map_err(|e| {
             warn!("open_writer_for_restore error:{}",e.to_string());
-            BuckyBackupError::Failed(e.to_string())
+            e
            })?;
-
-        if restore_url.scheme() != "file" {
-            return Err(BuckyBackupError::Failed("restore_url scheme must be file".to_string()));
-        }
-
-        let restore_path = restore_url.path();
-        let file_path = Path::new(&restore_path).join(&item.item_id);
+        let file_path = join_item_path(&to_long_path(&restore_path), &item.item_id);
+        //先写到临时文件,complete_restore_item时再rename,中断的恢复不会留下看起来完整的文件
+        let partial_path = get_restore_partial_path(&file_path);
         let mut real_offset = offset;
 
         //先判断文件是否存在
-        if !file_path.exists() {
+        if !partial_path.exists() {
             if offset > 0 {
-                return Err(BuckyBackupError::Failed(format!("file not found: {}", file_path.to_string_lossy())));
+                return Err(BuckyBackupError::Failed(format!("file not found: {}", partial_path.to_string_lossy())));
+            }
+
+            if let Some(parent) = partial_path.parent() {
+                fs::create_dir_all(parent).await.map_err(|e| {
+                    warn!("open_writer_for_restore error:{}", e.to_string());
+                    BuckyBackupError::TryLater(e.to_string())
+                })?;
             }
 
             return Ok((Box::pin(OpenOptions::new()
                 .write(true)
                 .create(true)
                 .truncate(true)
-                .open(&file_path)
+                .open(&partial_path)
                 .await
                 .map_err(|e| {
                     warn!("open_writer_for_restore error:{}", e.to_string());
@@ -210,7 +445,7 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
                 })?), 0));
         }
 
-        let file_meta = fs::metadata(&file_path).await.map_err(|e| {
+        let file_meta = fs::metadata(&partial_path).await.map_err(|e| {
             warn!("restore_item_by_reader: get metadata failed! {}", e.to_string());
             BuckyBackupError::TryLater(e.to_string())
         })?;
@@ -221,7 +456,7 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
         }
         let mut file = OpenOptions::new()
             .write(true)
-            .open(&file_path)
+            .open(&partial_path)
             .await
             .map_err(|e| {
                 warn!("open file failed! {}", e.to_string());
@@ -236,6 +471,286 @@ impl IBackupChunkSourceProvider for LocalDirChunkProvider {
         }
         Ok((Box::pin(file),real_offset))
     }
+
+    async fn complete_restore_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()> {
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        let file_path = join_item_path(&to_long_path(&restore_path), &item.item_id);
+        let partial_path = get_restore_partial_path(&file_path);
+
+        if cfg!(windows) && file_path.exists() {
+            //windows上rename不能覆盖已存在的文件
+            fs::remove_file(&file_path).await.map_err(|e| {
+                warn!("complete_restore_item: remove old file failed! {}", e.to_string());
+                BuckyBackupError::TryLater(e.to_string())
+            })?;
+        }
+        fs::rename(&partial_path, &file_path).await.map_err(|e| {
+            warn!("complete_restore_item: rename {} failed! {}", partial_path.to_string_lossy(), e.to_string());
+            BuckyBackupError::TryLater(e.to_string())
+        })?;
+        debug!("restore item {} complete, rename to {}", item.item_id, file_path.to_string_lossy());
+        if let Some(file_meta) = item.file_meta.as_ref().and_then(|s| ItemFileMeta::from_json_str(s)) {
+            if let Err(e) = file_meta.apply_to_path(&file_path) {
+                warn!("complete_restore_item: apply file meta to {} failed! {}", file_path.to_string_lossy(), e.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    async fn open_item_for_block_restore(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<(Pin<Box<dyn BlockRestoreFile>>,u64)> {
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        let file_path = join_item_path(&to_long_path(&restore_path), &item.item_id);
+        //只改写普通文件,链接和目录按完整恢复处理
+        let is_file = fs::symlink_metadata(&file_path).await.map(|meta| meta.is_file()).unwrap_or(false);
+        if !is_file {
+            return Err(BuckyBackupError::NotFound(format!("file not found: {}", file_path.to_string_lossy())));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&file_path)
+            .await
+            .map_err(|e| {
+                warn!("open_item_for_block_restore: open {} failed! {}", file_path.to_string_lossy(), e.to_string());
+                BuckyBackupError::TryLater(e.to_string())
+            })?;
+        let file_size = file.metadata().await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?.len();
+        Ok((Box::pin(file), file_size))
+    }
+
+    async fn complete_block_restore_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()> {
+        let restore_path = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        let file_path = join_item_path(&to_long_path(&restore_path), &item.item_id);
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&file_path)
+            .await
+            .map_err(|e| {
+                warn!("complete_block_restore_item: open {} failed! {}", file_path.to_string_lossy(), e.to_string());
+                BuckyBackupError::TryLater(e.to_string())
+            })?;
+        file.set_len(item.size).await.map_err(|e| {
+            warn!("complete_block_restore_item: truncate {} failed! {}", file_path.to_string_lossy(), e.to_string());
+            BuckyBackupError::TryLater(e.to_string())
+        })?;
+        file.sync_all().await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?;
+        drop(file);
+        if let Some(file_meta) = item.file_meta.as_ref().and_then(|s| ItemFileMeta::from_json_str(s)) {
+            if let Err(e) = file_meta.apply_to_path(&file_path) {
+                warn!("complete_block_restore_item: apply file meta to {} failed! {}", file_path.to_string_lossy(), e.to_string());
+            }
+        }
+        debug!("block restore item {} complete", item.item_id);
+        Ok(())
+    }
+
+    async fn restore_link_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()> {
+        if item.item_type == BackupItemType::CloudPlaceholder {
+            //没有备份内容,不能覆盖恢复位置已经存在的文件
+            warn!("restore_link_item: item {} is a cloud placeholder without content, skip", item.item_id);
+            return Ok(());
+        }
+        let restore_root = translate_local_path_from_url(restore_config.restore_location_url.as_str())?;
+        let file_path = join_item_path(&to_long_path(&restore_root), &item.item_id);
+        let link_target = item.file_meta.as_ref()
+            .and_then(|s| ItemFileMeta::from_json_str(s))
+            .and_then(|file_meta| file_meta.link_target);
+        if link_target.is_none() {
+            return Err(BuckyBackupError::Failed(format!("link item {} has no link target", item.item_id)));
+        }
+        let link_target = link_target.unwrap();
+
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).await.map_err(|e| {
+                warn!("restore_link_item error:{}", e.to_string());
+                BuckyBackupError::TryLater(e.to_string())
+            })?;
+        }
+        if fs::symlink_metadata(&file_path).await.is_ok() {
+            fs::remove_file(&file_path).await.map_err(|e| {
+                warn!("restore_link_item: remove old file failed! {}", e.to_string());
+                BuckyBackupError::TryLater(e.to_string())
+            })?;
+        }
+
+        match item.item_type {
+            BackupItemType::Symlink => {
+                create_symlink(Path::new(&link_target), &file_path).await.map_err(|e| {
+                    warn!("restore_link_item: create symlink {} failed! {}", file_path.to_string_lossy(), e.to_string());
+                    BuckyBackupError::Failed(e.to_string())
+                })?;
+            }
+            BackupItemType::HardLink => {
+                let target_path = join_item_path(&to_long_path(&restore_root), &link_target);
+                if let Err(e) = fs::hard_link(&target_path, &file_path).await {
+                    //文件系统不支持硬链接时退化成复制
+                    warn!("restore_link_item: hard link {} failed! {}, copy instead", file_path.to_string_lossy(), e.to_string());
+                    fs::copy(&target_path, &file_path).await.map_err(|e| {
+                        warn!("restore_link_item: copy {} failed! {}", target_path.to_string_lossy(), e.to_string());
+                        BuckyBackupError::Failed(e.to_string())
+                    })?;
+                }
+            }
+            _ => {
+                return Err(BuckyBackupError::Failed(format!("item {} is not a link", item.item_id)));
+            }
+        }
+        debug!("restore link item {} -> {} done", item.item_id, link_target);
+        Ok(())
+    }
+}
+
+//返回(dev,inode),只有存在多个硬链接的文件才需要记录
+#[cfg(unix)]
+fn get_hardlink_key(metadata: &std::fs::Metadata) -> Option<(u64,u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.nlink() > 1 {
+        return Some((metadata.dev(), metadata.ino()));
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn get_hardlink_key(metadata: &std::fs::Metadata) -> Option<(u64,u64)> {
+    None
+}
+
+#[cfg(unix)]
+async fn create_symlink(link_target: &Path, link_path: &Path) -> std::io::Result<()> {
+    fs::symlink(link_target, link_path).await
+}
+
+#[cfg(windows)]
+async fn create_symlink(link_target: &Path, link_path: &Path) -> std::io::Result<()> {
+    fs::symlink_file(link_target, link_path).await
+}
+
+pub const RESTORE_PARTIAL_SUFFIX:&str = ".bucky.partial";
+
+pub fn get_restore_partial_path(file_path: &Path) -> PathBuf {
+    let mut partial_path = file_path.as_os_str().to_os_string();
+    partial_path.push(RESTORE_PARTIAL_SUFFIX);
+    PathBuf::from(partial_path)
+}
+
+async fn remove_stale_restore_partials(restore_path: &Path) -> Result<()> {
+    if !restore_path.exists() {
+        return Ok(());
+    }
+    let mut dirs = vec![restore_path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if path.to_string_lossy().ends_with(RESTORE_PARTIAL_SUFFIX) {
+                info!("remove stale restore partial file: {}", path.to_string_lossy());
+                fs::remove_file(&path).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+//未完成的chunk先写到.partial目录下,complete时校验后再导入NamedDataStore
+//按chunk_id算法的hash在写入时同时计算,writer释放时保存到WRITTEN_CHUNK_HASHERS,complete时不需要再读一遍partial文件
+const LOCAL_PARTIAL_DIR:&str = ".partial";
+const PARTIAL_STATE_SAVE_INTERVAL:u64 = 1024*1024*16; //16MB
+const PARTIAL_READ_BUFFER_SIZE:usize = 1024*1024;
+
+#[derive(Serialize, Deserialize)]
+struct PartialChunkState {
+    pos: u64,
+    hash_state: Value,
+}
+
+//partial文件路径 -> (写入的长度, 按chunk_id算法计算的hasher);writer和complete_chunk_writer可能来自不同的provider实例
+static WRITTEN_CHUNK_HASHERS: std::sync::OnceLock<std::sync::Mutex<HashMap<PathBuf, (u64, BackupChunkHasher)>>> = std::sync::OnceLock::new();
+
+fn written_chunk_hashers() -> &'static std::sync::Mutex<HashMap<PathBuf, (u64, BackupChunkHasher)>> {
+    WRITTEN_CHUNK_HASHERS.get_or_init(|| std::sync::Mutex::new(HashMap::new()))
+}
+
+//写入partial文件的同时计算hash,定期把(pos,hash_state)保存下来,用于崩溃后校验已写入的前缀
+//前缀校验总是用sha256的hash_state,和chunk_id的算法无关,完整性在complete_chunk_writer中按chunk_id的算法校验
+struct LocalPartialChunkWriter {
+    file: File,
+    hasher: ChunkHasher,
+    content_hasher: Option<BackupChunkHasher>,//qcid不是完整内容的hash,为None
+    pos: u64,
+    last_saved_pos: u64,
+    partial_path: PathBuf,
+    state_path: PathBuf,
+}
+
+impl Drop for LocalPartialChunkWriter {
+    fn drop(&mut self) {
+        if let Some(content_hasher) = self.content_hasher.take() {
+            written_chunk_hashers().lock().unwrap().insert(self.partial_path.clone(), (self.pos, content_hasher));
+        }
+    }
+}
+
+impl LocalPartialChunkWriter {
+    fn save_state(&mut self) {
+        if self.pos == self.last_saved_pos {
+            return;
+        }
+        let state = PartialChunkState {
+            pos: self.pos,
+            hash_state: serde_json::to_value(&self.hasher.save_state()).unwrap_or(Value::Null),
+        };
+        let state_str = serde_json::to_string(&state).unwrap();
+        if let Err(e) = std::fs::write(&self.state_path, state_str) {
+            warn!("save partial chunk state to {:?} failed! {}", self.state_path, e);
+            return;
+        }
+        self.last_saved_pos = self.pos;
+    }
+}
+
+impl AsyncWrite for LocalPartialChunkWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.file).poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                this.hasher.update_from_bytes(&buf[..n]);
+                if let Some(content_hasher) = this.content_hasher.as_mut() {
+                    content_hasher.update_from_bytes(&buf[..n]);
+                }
+                this.pos += n as u64;
+                if this.pos - this.last_saved_pos >= PARTIAL_STATE_SAVE_INTERVAL {
+                    this.save_state();
+                }
+                Poll::Ready(Ok(n))
+            }
+            other => other,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.file).poll_flush(cx) {
+            Poll::Ready(Ok(())) => {
+                this.save_state();
+                Poll::Ready(Ok(()))
+            }
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.file).poll_shutdown(cx) {
+            Poll::Ready(Ok(())) => {
+                this.save_state();
+                Poll::Ready(Ok(()))
+            }
+            other => other,
+        }
+    }
 }
 
 pub struct LocalChunkTargetProvider {
@@ -252,6 +767,72 @@ impl LocalChunkTargetProvider {
             chunk_store 
         })
     }
+
+    fn get_partial_chunk_path(&self, chunk_id: &ChunkId) -> (PathBuf, PathBuf) {
+        let partial_dir = Path::new(&self.dir_path).join(LOCAL_PARTIAL_DIR);
+        let chunk_id_str = chunk_id.to_string();
+        (partial_dir.join(&chunk_id_str), partial_dir.join(format!("{}.state", chunk_id_str)))
+    }
+
+    async fn remove_partial_chunk(&self, chunk_id: &ChunkId) {
+        let (partial_path, state_path) = self.get_partial_chunk_path(chunk_id);
+        written_chunk_hashers().lock().unwrap().remove(&partial_path);
+        let _ = fs::remove_file(&partial_path).await;
+        let _ = fs::remove_file(&state_path).await;
+    }
+
+    fn new_content_hasher(chunk_id: &ChunkId) -> BackupResult<Option<BackupChunkHasher>> {
+        if chunk_id.to_obj_id().obj_type.as_str() == "qcid" {
+            return Ok(None);
+        }
+        BackupChunkHasher::for_chunk_id(chunk_id).map(Some).map_err(|e| BuckyBackupError::Internal(e.to_string()))
+    }
+
+    //用保存的hash_state校验partial文件的前缀,校验通过返回可以继续写入的位置;读取前缀时同时恢复content_hasher
+    async fn verify_partial_chunk(partial_path: &Path, state_path: &Path, content_hasher: &mut Option<BackupChunkHasher>) -> Option<(u64, ChunkHasher)> {
+        let state_str = fs::read_to_string(state_path).await.ok()?;
+        let state: PartialChunkState = serde_json::from_str(&state_str).ok()?;
+        let file_size = fs::metadata(partial_path).await.ok()?.len();
+        if file_size < state.pos {
+            warn!("partial chunk {:?} is shorter than saved pos {}, restart", partial_path, state.pos);
+            return None;
+        }
+
+        let mut file = File::open(partial_path).await.ok()?;
+        let mut hasher = ChunkHasher::new(None).ok()?;
+        let mut buf = vec![0u8; PARTIAL_READ_BUFFER_SIZE];
+        let mut remain = state.pos;
+        while remain > 0 {
+            let read_len = usize::min(buf.len(), remain as usize);
+            file.read_exact(&mut buf[..read_len]).await.ok()?;
+            hasher.update_from_bytes(&buf[..read_len]);
+            if let Some(content_hasher) = content_hasher.as_mut() {
+                content_hasher.update_from_bytes(&buf[..read_len]);
+            }
+            remain -= read_len as u64;
+        }
+
+        let hash_state = serde_json::to_value(&hasher.save_state()).ok()?;
+        if hash_state != state.hash_state {
+            warn!("partial chunk {:?} prefix hash mismatch, restart", partial_path);
+            return None;
+        }
+
+        if file_size > state.pos {
+            //丢掉没有被hash_state确认过的尾部
+            let file = OpenOptions::new().write(true).open(partial_path).await.ok()?;
+            file.set_len(state.pos).await.ok()?;
+        }
+        Some((state.pos, hasher))
+    }
+
+    //磁盘空间不足时返回OutOfSpace(不查询剩余空间,available为0),其它io错误稍后重试
+    fn write_error(err: std::io::Error, required: u64) -> BuckyBackupError {
+        if is_out_of_space_io_error(&err) {
+            return BuckyBackupError::OutOfSpace { required, available: 0 };
+        }
+        BuckyBackupError::TryLater(err.to_string())
+    }
 }
 
 #[async_trait]
@@ -265,7 +846,8 @@ impl IBackupChunkTargetProvider for LocalChunkTargetProvider {
     }
 
     fn get_target_url(&self)->String{
-        format!("file:///{}",self.dir_path)
+        translate_local_url_from_path(Path::new(&self.dir_path))
+            .unwrap_or_else(|_| format!("file:///{}",self.dir_path))
     }
 
     async fn get_account_session_info(&self)->Result<String>{
@@ -289,35 +871,131 @@ impl IBackupChunkTargetProvider for LocalChunkTargetProvider {
     }
 
     async fn open_chunk_writer(&self, chunk_id: &ChunkId,offset:u64,size:u64)->BackupResult<(ChunkWriter,u64)> {
-        let (mut writer,process) = self.chunk_store.open_chunk_writer(chunk_id,size,offset)
-            .await.map_err(|e| {
-                match e {
-                    NdnError::AlreadyExists(msg) => {
-                        BuckyBackupError::AlreadyDone(msg)
-                    },
-                    _ => {
-                        warn!("open_chunk_writer error:{}",e.to_string());
-                        BuckyBackupError::TryLater(e.to_string())
-                    }
-                }
-            })?;
+        let (is_exist,_) = self.chunk_store.is_chunk_exist(chunk_id,None).await.map_err(|e| {
+            warn!("open_chunk_writer error:{}",e.to_string());
+            BuckyBackupError::TryLater(e.to_string())
+        })?;
+        if is_exist {
+            return Err(BuckyBackupError::AlreadyDone(format!("chunk {} already exists", chunk_id.to_string())));
+        }
 
-        let mut offset = offset;
-        if process.len() > 2 {
-            let json_value : serde_json::Value = serde_json::from_str(&process).map_err(|e| {
-                warn!("can't load process info:{}",e.to_string());
-                BuckyBackupError::Failed(e.to_string())
-            })?;
-            offset = json_value.get("pos").unwrap().as_u64().unwrap();
-        } 
-        Ok((writer,offset))
+        let (partial_path, state_path) = self.get_partial_chunk_path(chunk_id);
+        fs::create_dir_all(partial_path.parent().unwrap()).await.map_err(|e| {
+            warn!("open_chunk_writer: create partial dir failed! {}", e.to_string());
+            Self::write_error(e, size.saturating_sub(offset))
+        })?;
+
+        let mut init_offset = 0;
+        let mut hasher = None;
+        let mut content_hasher = None;
+        written_chunk_hashers().lock().unwrap().remove(&partial_path);
+        if partial_path.exists() {
+            let mut resume_content_hasher = Self::new_content_hasher(chunk_id)?;
+            if let Some((pos, verified_hasher)) = Self::verify_partial_chunk(&partial_path, &state_path, &mut resume_content_hasher).await {
+                info!("resume partial chunk {}, verified offset: {}", chunk_id.to_string(), pos);
+                init_offset = pos;
+                hasher = Some(verified_hasher);
+                content_hasher = resume_content_hasher;
+            }
+        }
+        if init_offset == 0 {
+            content_hasher = Self::new_content_hasher(chunk_id)?;
+        }
+
+        let file = if init_offset > 0 {
+            let mut file = OpenOptions::new().write(true).open(&partial_path).await;
+            if let Ok(file) = file.as_mut() {
+                file.seek(SeekFrom::Start(init_offset)).await.map_err(|e| {
+                    warn!("open_chunk_writer: seek partial chunk failed! {}", e.to_string());
+                    BuckyBackupError::TryLater(e.to_string())
+                })?;
+            }
+            file
+        } else {
+            let _ = fs::remove_file(&state_path).await;
+            OpenOptions::new().write(true).create(true).truncate(true).open(&partial_path).await
+        };
+        let file = file.map_err(|e| {
+            warn!("open_chunk_writer: open partial chunk failed! {}", e.to_string());
+            Self::write_error(e, size.saturating_sub(init_offset))
+        })?;
+
+        let hasher = match hasher {
+            Some(hasher) => hasher,
+            None => ChunkHasher::new(None).map_err(|e| BuckyBackupError::Internal(e.to_string()))?,
+        };
+        let writer = LocalPartialChunkWriter {
+            file,
+            hasher,
+            content_hasher,
+            pos: init_offset,
+            last_saved_pos: init_offset,
+            partial_path,
+            state_path,
+        };
+        Ok((Box::pin(writer),init_offset))
     }
 
     async fn complete_chunk_writer(&self, chunk_id: &ChunkId)->BackupResult<()> {
-        self.chunk_store.complete_chunk_writer(chunk_id).await.map_err(|e| {
-            warn!("complete_chunk_writer error:{}",e.to_string());
-            BuckyBackupError::TryLater(e.to_string())
-        })
+        let (partial_path, _) = self.get_partial_chunk_path(chunk_id);
+        let mut file = File::open(&partial_path).await.map_err(|e| {
+            warn!("complete_chunk_writer: open partial chunk failed! {}", e.to_string());
+            BuckyBackupError::Failed(e.to_string())
+        })?;
+        let size = file.metadata().await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?.len();
+
+        //quick hash(qcid)不是内容的完整hash,无法在这里校验
+        if let Some(mut hasher) = Self::new_content_hasher(chunk_id)? {
+            //writer写入时已经算好hash;没有记录(进程重启)或者长度不一致时才重新读取partial文件
+            let written = written_chunk_hashers().lock().unwrap().remove(&partial_path);
+            let hasher = match written {
+                Some((pos, written_hasher)) if pos == size => written_hasher,
+                _ => {
+                    let mut buf = vec![0u8; PARTIAL_READ_BUFFER_SIZE];
+                    loop {
+                        let read_len = file.read(&mut buf).await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?;
+                        if read_len == 0 {
+                            break;
+                        }
+                        hasher.update_from_bytes(&buf[..read_len]);
+                    }
+                    file.seek(SeekFrom::Start(0)).await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?;
+                    hasher
+                }
+            };
+            let real_chunk_id = hasher.finalize_chunk_id().map_err(|e| BuckyBackupError::Internal(e.to_string()))?;
+            if real_chunk_id != *chunk_id {
+                warn!("complete_chunk_writer: chunk {} hash mismatch, got {}", chunk_id.to_string(), real_chunk_id.to_string());
+                self.remove_partial_chunk(chunk_id).await;
+                return Err(BuckyBackupError::Failed(format!("chunk {} hash mismatch", chunk_id.to_string())));
+            }
+        }
+
+        let open_result = self.chunk_store.open_chunk_writer(chunk_id,size,0).await;
+        match open_result {
+            Ok((mut writer,_)) => {
+                tokio::io::copy(&mut file, &mut writer).await.map_err(|e| {
+                    warn!("complete_chunk_writer: import chunk failed! {}", e.to_string());
+                    Self::write_error(e, size)
+                })?;
+                writer.flush().await.map_err(|e| Self::write_error(e, size))?;
+                self.chunk_store.complete_chunk_writer(chunk_id).await.map_err(|e| {
+                    warn!("complete_chunk_writer error:{}",e.to_string());
+                    BuckyBackupError::TryLater(e.to_string())
+                })?;
+            }
+            Err(NdnError::AlreadyExists(_)) => {
+                info!("chunk {} already exists in store", chunk_id.to_string());
+            }
+            Err(e) => {
+                warn!("complete_chunk_writer error:{}",e.to_string());
+                return Err(BuckyBackupError::TryLater(e.to_string()));
+            }
+        }
+
+        drop(file);
+        self.remove_partial_chunk(chunk_id).await;
+        Ok(())
     }
 
 
@@ -359,6 +1037,175 @@ impl IBackupChunkTargetProvider for LocalChunkTargetProvider {
         Err(BuckyBackupError::Failed(format!("no chunk found for chunk_id: {}", chunk_id.to_string())))
     }
 
+    async fn read_layout_marker(&self)->BackupResult<Option<String>> {
+        let marker_path = Path::new(&self.dir_path).join(TARGET_LAYOUT_MARKER_NAME);
+        match fs::read_to_string(&marker_path).await {
+            Ok(marker) => Ok(Some(marker)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    //先写临时文件再替换,避免中断后留下不完整的标记
+    async fn write_layout_marker(&self, marker: &str)->BackupResult<()> {
+        let marker_path = Path::new(&self.dir_path).join(TARGET_LAYOUT_MARKER_NAME);
+        let tmp_path = marker_path.with_extension("tmp");
+        fs::write(&tmp_path, marker).await?;
+        fs::rename(&tmp_path, &marker_path).await?;
+        Ok(())
+    }
 }
 
 
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::name_collision::NameCollisionPolicy;
+
+    #[tokio::test]
+    async fn test_prepare_and_restore_links() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let restore_dir = tempfile::tempdir().unwrap();
+        std::fs::write(source_dir.path().join("a.txt"), b"hello").unwrap();
+        std::fs::hard_link(source_dir.path().join("a.txt"), source_dir.path().join("b.txt")).unwrap();
+        std::os::unix::fs::symlink("a.txt", source_dir.path().join("c.txt")).unwrap();
+
+        let source = LocalDirChunkProvider::new(source_dir.path().to_string_lossy().to_string()).await.unwrap();
+        let (items, is_done) = source.prepare_items().await.unwrap();
+        assert!(is_done);
+        assert_eq!(items.len(), 3);
+        let symlink_item = items.iter().find(|item| item.item_id == "c.txt").unwrap();
+        assert_eq!(symlink_item.item_type, BackupItemType::Symlink);
+        let hardlink_items: Vec<&BackupItem> = items.iter().filter(|item| item.item_type == BackupItemType::HardLink).collect();
+        assert_eq!(hardlink_items.len(), 1);
+        let hardlink_item = hardlink_items[0];
+        let hardlink_target = ItemFileMeta::from_json_str(hardlink_item.file_meta.as_ref().unwrap()).unwrap().link_target.unwrap();
+
+        let restore_config = RestoreConfig {
+            restore_location_url: format!("file://{}", restore_dir.path().to_string_lossy()),
+            is_clean_restore: true,
+            name_collision_policy: NameCollisionPolicy::Rename,
+            conflict_policy: None,
+            restore_in_place: false,
+            params: None,
+        };
+        std::fs::write(restore_dir.path().join(&hardlink_target), b"hello").unwrap();
+        source.restore_link_item(symlink_item, &restore_config).await.unwrap();
+        source.restore_link_item(hardlink_item, &restore_config).await.unwrap();
+
+        let restored_link = std::fs::read_link(restore_dir.path().join("c.txt")).unwrap();
+        assert_eq!(restored_link, Path::new("a.txt"));
+        let restored_meta = std::fs::metadata(restore_dir.path().join(&hardlink_item.item_id)).unwrap();
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(restored_meta.nlink(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_prepare_reparse_point_policy() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source_dir.path().join("sub").join("deep")).unwrap();
+        std::fs::write(source_dir.path().join("sub").join("deep").join("x.txt"), b"x").unwrap();
+        std::fs::write(other_dir.path().join("y.txt"), b"y").unwrap();
+        std::os::unix::fs::symlink(other_dir.path(), source_dir.path().join("ext")).unwrap();
+        //指回根目录的链接,跟随时不能死循环
+        std::os::unix::fs::symlink("..", source_dir.path().join("sub").join("loop")).unwrap();
+
+        let mut source = LocalDirChunkProvider::new(source_dir.path().to_string_lossy().to_string()).await.unwrap();
+        let prepare_item_ids = |items: Vec<BackupItem>| {
+            let mut item_ids: Vec<(String, BackupItemType)> = items.into_iter().map(|item| (item.item_id, item.item_type)).collect();
+            item_ids.sort_by(|a, b| a.0.cmp(&b.0));
+            item_ids
+        };
+        //默认不进入子目录
+        let (items, _) = source.prepare_items().await.unwrap();
+        assert_eq!(prepare_item_ids(items), vec![("ext".to_string(), BackupItemType::Symlink)]);
+
+        source.set_recursive_scan(true);
+        let (items, _) = source.prepare_items().await.unwrap();
+        assert_eq!(prepare_item_ids(items), vec![
+            ("ext".to_string(), BackupItemType::Symlink),
+            ("sub/deep/x.txt".to_string(), BackupItemType::Chunk),
+            ("sub/loop".to_string(), BackupItemType::Symlink),
+        ]);
+
+        source.set_reparse_point_policy(ReparsePointPolicy::Follow);
+        let (items, _) = source.prepare_items().await.unwrap();
+        assert_eq!(prepare_item_ids(items), vec![
+            ("ext/y.txt".to_string(), BackupItemType::Chunk),
+            ("sub/deep/x.txt".to_string(), BackupItemType::Chunk),
+        ]);
+        let mut reader = source.open_item("ext/y.txt").await.unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"y");
+
+        source.set_reparse_point_policy(ReparsePointPolicy::Skip);
+        let (items, _) = source.prepare_items().await.unwrap();
+        assert_eq!(prepare_item_ids(items), vec![("sub/deep/x.txt".to_string(), BackupItemType::Chunk)]);
+    }
+    #[tokio::test]
+    async fn test_complete_chunk_writer_hash_on_write() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = LocalChunkTargetProvider::new(target_dir.path().to_string_lossy().to_string()).await.unwrap();
+        let content: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        for hash_type in [ChunkHashType::Sha256, ChunkHashType::Blake3] {
+            let chunk_id = calc_chunk_id(&content, hash_type).unwrap();
+            //写一半后中断,再从保存的位置继续写
+            let (mut writer, offset) = target.open_chunk_writer(&chunk_id, 0, content.len() as u64).await.unwrap();
+            assert_eq!(offset, 0);
+            writer.write_all(&content[..PARTIAL_STATE_SAVE_INTERVAL as usize / 8]).await.unwrap();
+            writer.flush().await.unwrap();
+            drop(writer);
+            let (mut writer, offset) = target.open_chunk_writer(&chunk_id, 0, content.len() as u64).await.unwrap();
+            writer.write_all(&content[offset as usize..]).await.unwrap();
+            writer.flush().await.unwrap();
+            drop(writer);
+            target.complete_chunk_writer(&chunk_id).await.unwrap();
+            assert!(target.is_chunk_exist(&chunk_id).await.unwrap().0);
+        }
+
+        //内容和chunk_id不一致时不能导入
+        let chunk_id = calc_chunk_id(b"other content", ChunkHashType::Blake3).unwrap();
+        let (mut writer, _) = target.open_chunk_writer(&chunk_id, 0, 4).await.unwrap();
+        writer.write_all(b"evil").await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        assert!(target.complete_chunk_writer(&chunk_id).await.is_err());
+        assert!(!target.is_chunk_exist(&chunk_id).await.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn test_restore_cloud_placeholder_item() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let restore_dir = tempfile::tempdir().unwrap();
+        std::fs::write(source_dir.path().join("a.txt"), b"hello").unwrap();
+        let mut source = LocalDirChunkProvider::new(source_dir.path().to_string_lossy().to_string()).await.unwrap();
+        //linux上没有占位文件,开启限速下载时普通文件不受影响
+        source.set_cloud_placeholder_policy(CloudPlaceholderPolicy {
+            action: CloudPlaceholderAction::Hydrate,
+            hydrate_max_bytes_per_sec: 1,
+        }, Arc::new(HydrateThrottle::new(1)));
+        let (items, _) = source.prepare_items().await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].item_type, BackupItemType::Chunk);
+        assert!(!source.need_hydrate_throttle(&source.get_item_path("a.txt")).await);
+
+        //只有属性的占位文件item恢复时跳过,不覆盖已经存在的文件
+        let mut placeholder_item = items[0].clone();
+        placeholder_item.item_type = BackupItemType::CloudPlaceholder;
+        assert!(placeholder_item.item_type.is_metadata_only());
+        let restore_config = RestoreConfig {
+            restore_location_url: format!("file://{}", restore_dir.path().to_string_lossy()),
+            is_clean_restore: true,
+            name_collision_policy: NameCollisionPolicy::Rename,
+            conflict_policy: None,
+            restore_in_place: false,
+            params: None,
+        };
+        std::fs::write(restore_dir.path().join("a.txt"), b"local").unwrap();
+        source.restore_link_item(&placeholder_item, &restore_config).await.unwrap();
+        assert_eq!(std::fs::read(restore_dir.path().join("a.txt")).unwrap(), b"local");
+    }
+}
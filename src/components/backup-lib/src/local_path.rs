@@ -0,0 +1,224 @@
+#![allow(unused)]
+
+use std::path::{Path, PathBuf};
+use crate::provider::{BuckyBackupError, BackupResult};
+
+//file:// url和本地路径的互相转换,engine/provider都应该通过这里转换,不要直接用Url::path()
+//Windows:  file:///C:/dir <-> C:\dir
+//          file://server/share/dir (或file:////server/share/dir) <-> \\server\share\dir
+//          \\?\C:\dir 和 \\?\UNC\server\share\dir 这样的verbatim路径转换时去掉前缀
+//          C:dir 这样的驱动器相对路径无法表示成url,返回错误
+//Unix:     file:///dir <-> /dir
+//Windows上访问文件时用to_long_path加上\\?\前缀,超过MAX_PATH(260)的深层路径(比如node_modules)才能打开
+
+pub fn translate_local_path_from_url(url: &str) -> BackupResult<PathBuf> {
+    let path = url_to_path_string(url, cfg!(windows)).map_err(BuckyBackupError::Failed)?;
+    Ok(PathBuf::from(path))
+}
+
+pub fn translate_local_url_from_path(path: &Path) -> BackupResult<String> {
+    path_string_to_url(path.to_string_lossy().as_ref(), cfg!(windows)).map_err(BuckyBackupError::Failed)
+}
+
+//只用于访问文件系统,不要用于生成url或者item_id;非Windows和相对路径原样返回
+pub fn to_long_path(path: &Path) -> PathBuf {
+    PathBuf::from(long_path_string(path.to_string_lossy().as_ref(), cfg!(windows)))
+}
+
+//item_id总是用/分隔,逐段拼接;\\?\路径不会把/当作分隔符
+pub fn join_item_path(root: &Path, item_id: &str) -> PathBuf {
+    let mut path = root.to_path_buf();
+    for name in item_id.split('/').filter(|name| !name.is_empty()) {
+        path.push(name);
+    }
+    path
+}
+
+fn long_path_string(path: &str, is_windows: bool) -> String {
+    if !is_windows || path.starts_with("\\\\?\\") {
+        return path.to_string();
+    }
+    //\\?\路径不做任何规范化,需要先统一分隔符
+    let path = path.replace('/', "\\");
+    if let Some(unc_path) = path.strip_prefix("\\\\") {
+        return format!("\\\\?\\UNC\\{}", unc_path);
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\' {
+        return format!("\\\\?\\{}", path);
+    }
+    path
+}
+
+fn url_to_path_string(url: &str, is_windows: bool) -> Result<String, String> {
+    if !url.get(..5).map_or(false, |scheme| scheme.eq_ignore_ascii_case("file:")) {
+        return Err(format!("{} is not a file url", url));
+    }
+    let rest = &url[5..];
+    let (host, path) = if let Some(rest) = rest.strip_prefix("//") {
+        match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        }
+    } else {
+        ("", rest)
+    };
+    let host = if host.eq_ignore_ascii_case("localhost") { "" } else { host };
+    let path = percent_decode(path)?;
+
+    if is_windows {
+        if !host.is_empty() {
+            return Ok(format!("\\\\{}{}", host, path.replace('/', "\\")));
+        }
+        //file:////server/share的写法
+        if let Some(unc_path) = path.strip_prefix("//") {
+            return Ok(format!("\\\\{}", unc_path.replace('/', "\\")));
+        }
+        let path = path.trim_start_matches('/');
+        let bytes = path.as_bytes();
+        if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && (bytes[1] == b':' || bytes[1] == b'|') {
+            let drive = &path[..1];
+            let remain = path[2..].replace('/', "\\");
+            if remain.is_empty() {
+                return Ok(format!("{}:\\", drive));
+            }
+            if !remain.starts_with('\\') {
+                return Err(format!("{} is a drive relative path", url));
+            }
+            return Ok(format!("{}:{}", drive, remain));
+        }
+        Err(format!("{} has no drive letter", url))
+    } else {
+        if !host.is_empty() {
+            return Err(format!("{} points to remote host {}", url, host));
+        }
+        //兼容老版本生成的file:////dir
+        let path = format!("/{}", path.trim_start_matches('/'));
+        Ok(path)
+    }
+}
+
+fn path_string_to_url(path: &str, is_windows: bool) -> Result<String, String> {
+    if is_windows {
+        let path = if let Some(unc_path) = path.strip_prefix("\\\\?\\UNC\\") {
+            format!("\\\\{}", unc_path)
+        } else if let Some(verbatim_path) = path.strip_prefix("\\\\?\\") {
+            verbatim_path.to_string()
+        } else {
+            path.to_string()
+        };
+
+        if let Some(unc_path) = path.strip_prefix("\\\\") {
+            let unc_path = unc_path.replace('\\', "/");
+            let (host, share_path) = match unc_path.find('/') {
+                Some(pos) => (&unc_path[..pos], &unc_path[pos..]),
+                None => return Err(format!("{} is not a valid unc path", path)),
+            };
+            return Ok(format!("file://{}{}", host, percent_encode(share_path)));
+        }
+
+        let bytes = path.as_bytes();
+        if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+            let remain = &path[2..];
+            if !remain.is_empty() && !remain.starts_with('\\') && !remain.starts_with('/') {
+                return Err(format!("{} is a drive relative path", path));
+            }
+            let full_path = format!("/{}:{}", &path[..1], remain.replace('\\', "/"));
+            return Ok(format!("file://{}", percent_encode(&full_path)));
+        }
+        Err(format!("{} is not an absolute path", path))
+    } else {
+        if !path.starts_with('/') {
+            return Err(format!("{} is not an absolute path", path));
+        }
+        Ok(format!("file://{}", percent_encode(path)))
+    }
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/:-._~!$&'()*+,;=@".contains(&b) {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+fn percent_decode(path: &str) -> Result<String, String> {
+    let bytes = path.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).map_err(|e| e.to_string())?;
+            let value = u8::from_str_radix(hex, 16).map_err(|_| format!("invalid percent encoding in {}", path))?;
+            result.push(value);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(result).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_path() {
+        assert_eq!(url_to_path_string("file:///tmp/a b", false).unwrap(), "/tmp/a b");
+        assert_eq!(url_to_path_string("file:///tmp/a%20b", false).unwrap(), "/tmp/a b");
+        assert_eq!(url_to_path_string("file:////tmp/test", false).unwrap(), "/tmp/test");
+        assert_eq!(url_to_path_string("file://localhost/tmp", false).unwrap(), "/tmp");
+        assert!(url_to_path_string("file://server/tmp", false).is_err());
+        assert!(url_to_path_string("s3://bucket/tmp", false).is_err());
+
+        assert_eq!(path_string_to_url("/tmp/a b", false).unwrap(), "file:///tmp/a%20b");
+        assert!(path_string_to_url("tmp/a", false).is_err());
+        let url = path_string_to_url("/tmp/中文#1", false).unwrap();
+        assert_eq!(url_to_path_string(&url, false).unwrap(), "/tmp/中文#1");
+    }
+
+    #[test]
+    fn test_windows_drive_path() {
+        assert_eq!(url_to_path_string("file:///C:/Users/test", true).unwrap(), "C:\\Users\\test");
+        assert_eq!(url_to_path_string("file:///c:", true).unwrap(), "c:\\");
+        assert_eq!(url_to_path_string("file:///D|/data", true).unwrap(), "D:\\data");
+        assert!(url_to_path_string("file:///data", true).is_err());
+
+        assert_eq!(path_string_to_url("C:\\Users\\test", true).unwrap(), "file:///C:/Users/test");
+        assert_eq!(path_string_to_url("C:\\", true).unwrap(), "file:///C:/");
+        assert!(path_string_to_url("C:test", true).is_err());
+        assert!(path_string_to_url("\\test", true).is_err());
+    }
+
+    #[test]
+    fn test_windows_unc_and_verbatim_path() {
+        assert_eq!(url_to_path_string("file://server/share/dir", true).unwrap(), "\\\\server\\share\\dir");
+        assert_eq!(url_to_path_string("file:////server/share/dir", true).unwrap(), "\\\\server\\share\\dir");
+        assert_eq!(path_string_to_url("\\\\server\\share\\dir", true).unwrap(), "file://server/share/dir");
+
+        assert_eq!(path_string_to_url("\\\\?\\C:\\dir", true).unwrap(), "file:///C:/dir");
+        assert_eq!(path_string_to_url("\\\\?\\UNC\\server\\share\\dir", true).unwrap(), "file://server/share/dir");
+        assert!(path_string_to_url("\\\\server", true).is_err());
+    }
+
+    #[test]
+    fn test_long_path() {
+        assert_eq!(long_path_string("C:\\Users\\test", true), "\\\\?\\C:\\Users\\test");
+        assert_eq!(long_path_string("C:/Users/test", true), "\\\\?\\C:\\Users\\test");
+        assert_eq!(long_path_string("\\\\server\\share\\dir", true), "\\\\?\\UNC\\server\\share\\dir");
+        assert_eq!(long_path_string("\\\\?\\C:\\dir", true), "\\\\?\\C:\\dir");
+        //相对路径不能加前缀
+        assert_eq!(long_path_string("dir\\file", true), "dir\\file");
+        assert_eq!(long_path_string("/tmp/dir", false), "/tmp/dir");
+
+        let path = join_item_path(Path::new("/tmp/root"), "node_modules/a/b.js");
+        assert_eq!(path, Path::new("/tmp/root").join("node_modules").join("a").join("b.js"));
+    }
+}
@@ -0,0 +1,211 @@
+#![allow(unused)]
+
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+//在Linux上备份的文件名恢复到Windows/macOS时可能冲突:大小写不敏感,保留名,路径过长
+//RestoreNameMapper在生成restore item时检测这些问题,并按策略改名/跳过/失败
+pub const MAX_RESTORE_PATH_LEN: usize = 260;
+
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const WINDOWS_INVALID_CHARS: [char; 9] = ['<', '>', ':', '"', '\\', '|', '?', '*', '\0'];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameCollisionPolicy {
+    Rename,//加后缀改名后恢复
+    Skip,//跳过冲突的item
+    Fail,//直接让恢复任务失败
+}
+
+impl Default for NameCollisionPolicy {
+    fn default() -> Self {
+        NameCollisionPolicy::Rename
+    }
+}
+
+pub struct RestoreNameMapper {
+    policy: NameCollisionPolicy,
+    restore_root_len: usize,
+    case_insensitive: bool,
+    check_windows_names: bool,
+    used_names: HashSet<String>,
+    mapped_names: HashMap<String, Option<String>>,//原item_id -> 恢复时使用的item_id,None表示被跳过
+}
+
+impl RestoreNameMapper {
+    //按当前平台的文件系统规则检测
+    pub fn new(policy: NameCollisionPolicy, restore_root: &str) -> Self {
+        let case_insensitive = cfg!(windows) || cfg!(target_os = "macos");
+        Self::with_platform(policy, restore_root, case_insensitive, cfg!(windows))
+    }
+
+    pub fn with_platform(policy: NameCollisionPolicy, restore_root: &str, case_insensitive: bool, check_windows_names: bool) -> Self {
+        Self {
+            policy,
+            restore_root_len: restore_root.trim_end_matches('/').len(),
+            case_insensitive,
+            check_windows_names,
+            used_names: HashSet::new(),
+            mapped_names: HashMap::new(),
+        }
+    }
+
+    //返回Ok(Some(恢复时使用的item_id)),Ok(None)表示跳过,Err表示策略为Fail时遇到的冲突
+    pub fn map_item_id(&mut self, item_id: &str) -> Result<Option<String>, String> {
+        let problem = self.check_problem(item_id);
+        if problem.is_none() {
+            self.used_names.insert(self.name_key(item_id));
+            self.mapped_names.insert(item_id.to_string(), Some(item_id.to_string()));
+            return Ok(Some(item_id.to_string()));
+        }
+
+        let problem = problem.unwrap();
+        match self.policy {
+            NameCollisionPolicy::Fail => {
+                Err(format!("restore item {}: {}", item_id, problem))
+            }
+            NameCollisionPolicy::Skip => {
+                self.mapped_names.insert(item_id.to_string(), None);
+                Ok(None)
+            }
+            NameCollisionPolicy::Rename => {
+                let new_name = self.make_new_name(item_id);
+                self.used_names.insert(self.name_key(&new_name));
+                self.mapped_names.insert(item_id.to_string(), Some(new_name.clone()));
+                Ok(Some(new_name))
+            }
+        }
+    }
+
+    //已经处理过的item_id在恢复时使用的名字,被跳过或者未处理时返回None
+    pub fn get_mapped_name(&self, item_id: &str) -> Option<&String> {
+        self.mapped_names.get(item_id).and_then(|name| name.as_ref())
+    }
+
+    fn name_key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn check_problem(&self, item_id: &str) -> Option<String> {
+        if self.used_names.contains(&self.name_key(item_id)) {
+            return Some("name collision".to_string());
+        }
+        if self.check_windows_names {
+            for component in item_id.split('/') {
+                if is_windows_reserved_name(component) {
+                    return Some(format!("reserved name {}", component));
+                }
+                if component.chars().any(|c| WINDOWS_INVALID_CHARS.contains(&c) || c.is_control()) {
+                    return Some(format!("invalid char in {}", component));
+                }
+                if component.ends_with('.') || component.ends_with(' ') {
+                    return Some(format!("trailing dot or space in {}", component));
+                }
+            }
+            if self.restore_root_len + 1 + item_id.len() > MAX_RESTORE_PATH_LEN {
+                return Some("path too long".to_string());
+            }
+        }
+        None
+    }
+
+    fn make_new_name(&self, item_id: &str) -> String {
+        let (parent, file_name) = match item_id.rfind('/') {
+            Some(pos) => (&item_id[..pos + 1], &item_id[pos + 1..]),
+            None => ("", item_id),
+        };
+        let mut parent = parent.to_string();
+        let mut file_name = file_name.to_string();
+        if self.check_windows_names {
+            parent = parent.split('/').map(|c| sanitize_windows_name(c)).collect::<Vec<String>>().join("/");
+            file_name = sanitize_windows_name(&file_name);
+        }
+        let (stem, ext) = match file_name.rfind('.') {
+            Some(pos) if pos > 0 => (file_name[..pos].to_string(), file_name[pos..].to_string()),
+            _ => (file_name.clone(), String::new()),
+        };
+
+        let mut index = 0;
+        loop {
+            let suffix = if index == 0 { String::new() } else { format!("~{}", index) };
+            let mut stem = stem.clone();
+            if self.check_windows_names {
+                //路径过长时截断文件名主体,保留扩展名和后缀
+                let fixed_len = self.restore_root_len + 1 + parent.len() + ext.len() + suffix.len();
+                let max_stem_len = MAX_RESTORE_PATH_LEN.saturating_sub(fixed_len).max(1);
+                while stem.len() > max_stem_len {
+                    stem.pop();
+                }
+            }
+            let new_name = format!("{}{}{}{}", parent, stem, suffix, ext);
+            if new_name != item_id && self.check_problem(&new_name).is_none() {
+                return new_name;
+            }
+            index += 1;
+        }
+    }
+}
+
+fn is_windows_reserved_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or("").trim_end();
+    WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base))
+}
+
+fn sanitize_windows_name(name: &str) -> String {
+    let mut result: String = name.chars()
+        .map(|c| if WINDOWS_INVALID_CHARS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+    while result.ends_with('.') || result.ends_with(' ') {
+        result.pop();
+        result.push('_');
+    }
+    if is_windows_reserved_name(&result) {
+        result = format!("_{}", result);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_collision() {
+        let mut mapper = RestoreNameMapper::with_platform(NameCollisionPolicy::Rename, "/restore", true, false);
+        assert_eq!(mapper.map_item_id("Readme.md").unwrap(), Some("Readme.md".to_string()));
+        assert_eq!(mapper.map_item_id("README.md").unwrap(), Some("README~1.md".to_string()));
+        assert_eq!(mapper.map_item_id("readme.md").unwrap(), Some("readme~2.md".to_string()));
+        assert_eq!(mapper.get_mapped_name("README.md"), Some(&"README~1.md".to_string()));
+
+        let mut mapper = RestoreNameMapper::with_platform(NameCollisionPolicy::Skip, "/restore", true, false);
+        mapper.map_item_id("a.txt").unwrap();
+        assert_eq!(mapper.map_item_id("A.TXT").unwrap(), None);
+        assert_eq!(mapper.get_mapped_name("A.TXT"), None);
+
+        let mut mapper = RestoreNameMapper::with_platform(NameCollisionPolicy::Fail, "/restore", true, false);
+        mapper.map_item_id("a.txt").unwrap();
+        assert!(mapper.map_item_id("A.txt").is_err());
+    }
+
+    #[test]
+    fn test_windows_names() {
+        let mut mapper = RestoreNameMapper::with_platform(NameCollisionPolicy::Rename, "C:/restore", true, true);
+        assert_eq!(mapper.map_item_id("con.txt").unwrap(), Some("_con.txt".to_string()));
+        assert_eq!(mapper.map_item_id("a:b?.txt").unwrap(), Some("a_b_.txt".to_string()));
+        assert_eq!(mapper.map_item_id("trailing.").unwrap(), Some("trailing_".to_string()));
+
+        let long_name = format!("{}.dat", "x".repeat(300));
+        let new_name = mapper.map_item_id(&long_name).unwrap().unwrap();
+        assert!("C:/restore".len() + 1 + new_name.len() <= MAX_RESTORE_PATH_LEN);
+        assert!(new_name.ends_with(".dat"));
+    }
+}
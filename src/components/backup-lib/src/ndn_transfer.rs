@@ -0,0 +1,489 @@
+#![allow(unused)]
+//ndn://的chunk target:按chunk_id在节点之间交换chunk,请求(interest)是chunk_id和要做的操作,回复(data)是结果和chunk内容
+//连接通过NdnStreamOpener打开,backup_suite使用buckyos zone的隧道(rtcp)连接其它设备,
+//NAT后的设备不需要端口映射;本机和测试中直接用TCP连接
+//存储节点上由NdnChunkServer把store_root/<store>作为LocalChunkTargetProvider提供出去,只监听本机地址,由隧道转发进来
+//协议和agent相同:一个连接一个请求,先是json行,GetChunk/PutChunk之后是chunk内容,写入方关闭连接表示内容结束
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use ndn_lib::{ChunkId, ChunkReader, ChunkWriter};
+use anyhow::Result;
+use log::*;
+
+use crate::provider::*;
+use crate::local_chunk_provider::LocalChunkTargetProvider;
+use crate::provider_registry::*;
+use crate::credential::TargetCredential;
+use crate::agent::{AgentResponse, error_from_agent, parse_result, read_json_line, write_json_line};
+
+pub const NDN_SCHEME: &str = "ndn";
+pub const DEFAULT_NDN_CHUNK_PORT: u16 = 5191;
+//存储节点上保存其它节点chunk的目录(在backup_suite的数据目录下)
+pub const NDN_STORE_DIR: &str = "ndn_store";
+const NDN_CONNECT_TIMEOUT_SECS: u64 = 30;
+const NDN_COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+//ndn://node[:port]/store,node是zone内的设备名或者host,store是存储节点上的chunk仓库名
+pub fn parse_ndn_url(url: &str) -> BackupResult<(String, u16, String)> {
+    let parsed = url::Url::parse(url).map_err(|e| BuckyBackupError::Failed(format!("invalid ndn url {}: {}", url, e)))?;
+    if parsed.scheme() != NDN_SCHEME {
+        return Err(BuckyBackupError::Failed(format!("{} is not an ndn url", url)));
+    }
+    let node = parsed.host_str()
+        .ok_or_else(|| BuckyBackupError::Failed(format!("ndn url {} has no node", url)))?
+        .to_string();
+    let port = parsed.port().unwrap_or(DEFAULT_NDN_CHUNK_PORT);
+    let store = parsed.path().trim_matches('/').to_string();
+    check_store_name(store.as_str())?;
+    Ok((node, port, store))
+}
+
+fn check_store_name(store: &str) -> BackupResult<()> {
+    if store.is_empty() || !store.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(BuckyBackupError::Failed(format!("invalid ndn store name {}", store)));
+    }
+    Ok(())
+}
+
+fn parse_chunk_id(chunk_id: &str) -> BackupResult<ChunkId> {
+    ChunkId::new(chunk_id).map_err(|e| BuckyBackupError::Failed(format!("invalid chunk id {}: {}", chunk_id, e)))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "interest", rename_all = "snake_case")]
+pub enum NdnInterest {
+    ChunkState { chunk_ids: Vec<String> },
+    GetChunk { chunk_id: String, offset: u64 },
+    PutChunk { chunk_id: String, offset: u64, size: u64 },
+    CompleteChunk { chunk_id: String },
+    LinkChunk { source_chunk_id: String, new_chunk_id: String },
+    QueryLink { source_chunk_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NdnInterestPacket {
+    pub store: String,
+    #[serde(flatten)]
+    pub interest: NdnInterest,
+}
+
+pub trait NdnStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> NdnStream for T {}
+
+//打开到node上chunk服务的连接,port是url中的端口
+#[async_trait]
+pub trait NdnStreamOpener: Send + Sync {
+    async fn open_stream(&self, node: &str, port: u16) -> BackupResult<Box<dyn NdnStream>>;
+}
+
+//直接TCP连接,NdnChunkServer没有认证,只用于连接本机的节点
+pub struct TcpNdnStreamOpener;
+
+#[async_trait]
+impl NdnStreamOpener for TcpNdnStreamOpener {
+    async fn open_stream(&self, node: &str, port: u16) -> BackupResult<Box<dyn NdnStream>> {
+        //ipv6的host_str带有[]
+        let stream = TcpStream::connect((node.trim_matches(|c| c == '[' || c == ']'), port)).await
+            .map_err(|e| BuckyBackupError::TryLater(format!("connect ndn node {}:{} failed: {}", node, port, e)))?;
+        Ok(Box::new(stream))
+    }
+}
+
+type NdnConnection = BufReader<Box<dyn NdnStream>>;
+
+//GetChunk/PutChunk之后的chunk内容,stream放在std Mutex中只是为了满足Sync,poll时通过get_mut访问不需要加锁
+struct NdnChunkStream {
+    stream: std::sync::Mutex<NdnConnection>,
+}
+
+impl AsyncRead for NdnChunkStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for NdnChunkStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(self.get_mut().stream.get_mut().unwrap()).poll_shutdown(cx)
+    }
+}
+
+pub struct NdnChunkTargetProvider {
+    url: String,
+    node: String,
+    port: u16,
+    store: String,
+    opener: Arc<dyn NdnStreamOpener>,
+}
+
+impl NdnChunkTargetProvider {
+    pub fn new(url: &str, opener: Arc<dyn NdnStreamOpener>) -> BackupResult<Self> {
+        let (node, port, store) = parse_ndn_url(url)?;
+        Ok(Self {
+            url: url.to_string(),
+            node,
+            port,
+            store,
+            opener,
+        })
+    }
+
+    //节点不在线时返回TryLater
+    async fn request(&self, interest: NdnInterest) -> BackupResult<(Value, NdnConnection)> {
+        let stream = tokio::time::timeout(Duration::from_secs(NDN_CONNECT_TIMEOUT_SECS), self.opener.open_stream(self.node.as_str(), self.port)).await
+            .map_err(|_| BuckyBackupError::TryLater(format!("connect ndn node {}:{} timeout", self.node, self.port)))??;
+        let mut stream = BufReader::new(stream);
+        let packet = NdnInterestPacket {
+            store: self.store.clone(),
+            interest,
+        };
+        write_json_line(&mut stream, &packet).await
+            .map_err(|e| BuckyBackupError::TryLater(format!("send interest to {} failed: {}", self.node, e)))?;
+        let data: AgentResponse = read_json_line(&mut stream).await
+            .map_err(|e| BuckyBackupError::TryLater(format!("read data from {} failed: {}", self.node, e)))?;
+        if let Some((kind, message)) = data.error {
+            return Err(error_from_agent(kind, message));
+        }
+        Ok((data.result, stream))
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(&self, interest: NdnInterest) -> BackupResult<T> {
+        let (result, _) = self.request(interest).await?;
+        parse_result(result)
+    }
+}
+
+#[async_trait]
+impl IBackupChunkTargetProvider for NdnChunkTargetProvider {
+    async fn get_target_info(&self) -> Result<String> {
+        let result = json!({
+            "type": "ndn_chunk_target",
+            "node": self.node,
+            "port": self.port,
+            "store": self.store,
+        });
+        Ok(result.to_string())
+    }
+
+    fn get_target_url(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn get_account_session_info(&self) -> Result<String> {
+        Ok(String::new())
+    }
+
+    async fn set_account_session_info(&self, session_info: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
+        let mut states = self.is_chunk_exist_batch(std::slice::from_ref(chunk_id)).await?;
+        states.pop().ok_or_else(|| anyhow::anyhow!("ndn node {} returns no chunk state", self.node))
+    }
+
+    //一次interest查询所有chunk,减少经过隧道的往返
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId]) -> Result<Vec<(bool, u64)>> {
+        let chunk_ids = chunk_ids.iter().map(|chunk_id| chunk_id.to_string()).collect();
+        let states: Vec<(bool, u64)> = self.call(NdnInterest::ChunkState { chunk_ids }).await?;
+        Ok(states)
+    }
+
+    async fn open_chunk_writer(&self, chunk_id: &ChunkId, offset: u64, size: u64) -> BackupResult<(ChunkWriter, u64)> {
+        let (result, stream) = self.request(NdnInterest::PutChunk { chunk_id: chunk_id.to_string(), offset, size }).await?;
+        let real_offset: u64 = parse_result(result)?;
+        Ok((Box::pin(NdnChunkStream { stream: std::sync::Mutex::new(stream) }), real_offset))
+    }
+
+    async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        self.call::<Value>(NdnInterest::CompleteChunk { chunk_id: chunk_id.to_string() }).await?;
+        Ok(())
+    }
+
+    async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
+        self.call::<Value>(NdnInterest::LinkChunk {
+            source_chunk_id: source_chunk_id.to_string(),
+            new_chunk_id: new_chunk_id.to_string(),
+        }).await?;
+        Ok(())
+    }
+
+    async fn query_link_target(&self, source_chunk_id: &ChunkId) -> BackupResult<Option<ChunkId>> {
+        let target: Option<String> = self.call(NdnInterest::QueryLink { source_chunk_id: source_chunk_id.to_string() }).await?;
+        target.map(|target| parse_chunk_id(target.as_str())).transpose()
+    }
+
+    async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset: u64) -> BackupResult<ChunkReader> {
+        let (_, stream) = self.request(NdnInterest::GetChunk { chunk_id: chunk_id.to_string(), offset }).await?;
+        Ok(Box::pin(NdnChunkStream { stream: std::sync::Mutex::new(stream) }))
+    }
+}
+
+struct NdnStore {
+    target: LocalChunkTargetProvider,
+    //写入中的chunk -> 写入结束后的错误;CompleteChunk等写入结束后再完成
+    chunk_writes: Mutex<HashMap<String, Arc<Mutex<Option<String>>>>>,
+}
+
+//存储节点端:store_root下每个store是一个本地chunk仓库
+pub struct NdnChunkServer {
+    store_root: PathBuf,
+    stores: Mutex<HashMap<String, Arc<NdnStore>>>,
+}
+
+impl NdnChunkServer {
+    pub fn new(store_root: PathBuf) -> Self {
+        Self {
+            store_root,
+            stores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get_store(&self, store: &str) -> BackupResult<Arc<NdnStore>> {
+        check_store_name(store)?;
+        let mut stores = self.stores.lock().await;
+        if let Some(ndn_store) = stores.get(store) {
+            return Ok(ndn_store.clone());
+        }
+        let store_path = self.store_root.join(store);
+        tokio::fs::create_dir_all(&store_path).await.map_err(|e| BuckyBackupError::TryLater(e.to_string()))?;
+        let target = LocalChunkTargetProvider::new(store_path.to_string_lossy().to_string()).await
+            .map_err(|e| BuckyBackupError::TryLater(e.to_string()))?;
+        let ndn_store = Arc::new(NdnStore {
+            target,
+            chunk_writes: Mutex::new(HashMap::new()),
+        });
+        stores.insert(store.to_string(), ndn_store.clone());
+        Ok(ndn_store)
+    }
+
+    //监听并处理请求,直到出错
+    pub async fn run(self: Arc<Self>, listen_addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(listen_addr).await?;
+        info!("ndn chunk server listen on {}, store root: {}", listen_addr, self.store_root.to_string_lossy());
+        self.serve(listener).await
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, remote_addr) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_connection(stream).await {
+                    warn!("ndn chunk server handle interest from {} failed: {}", remote_addr, e);
+                }
+            });
+        }
+    }
+
+    pub async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, stream: S) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        let packet: NdnInterestPacket = read_json_line(&mut stream).await?;
+        debug!("ndn interest of store {}: {:?}", packet.store, packet.interest);
+        let ndn_store = match self.get_store(packet.store.as_str()).await {
+            Ok(ndn_store) => ndn_store,
+            Err(err) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+        };
+        let target = &ndn_store.target;
+        let result = match packet.interest {
+            NdnInterest::ChunkState { chunk_ids } => {
+                let chunk_ids = chunk_ids.iter().map(|chunk_id| parse_chunk_id(chunk_id)).collect::<BackupResult<Vec<_>>>();
+                match chunk_ids {
+                    Ok(chunk_ids) => target.is_chunk_exist_batch(&chunk_ids).await
+                        .map(|states| json!(states))
+                        .map_err(|e| BuckyBackupError::TryLater(e.to_string())),
+                    Err(err) => Err(err),
+                }
+            }
+            NdnInterest::CompleteChunk { chunk_id } => self.complete_chunk(&ndn_store, chunk_id.as_str()).await.map(|_| Value::Null),
+            NdnInterest::LinkChunk { source_chunk_id, new_chunk_id } => {
+                match (parse_chunk_id(source_chunk_id.as_str()), parse_chunk_id(new_chunk_id.as_str())) {
+                    (Ok(source_chunk_id), Ok(new_chunk_id)) => target.link_chunkid(&source_chunk_id, &new_chunk_id).await.map(|_| Value::Null),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                }
+            }
+            NdnInterest::QueryLink { source_chunk_id } => match parse_chunk_id(source_chunk_id.as_str()) {
+                Ok(source_chunk_id) => target.query_link_target(&source_chunk_id).await
+                    .map(|link_target| json!(link_target.map(|chunk_id| chunk_id.to_string()))),
+                Err(err) => Err(err),
+            },
+            NdnInterest::GetChunk { chunk_id, offset } => {
+                return self.send_chunk(&ndn_store, stream, chunk_id.as_str(), offset).await;
+            }
+            NdnInterest::PutChunk { chunk_id, offset, size } => {
+                return self.receive_chunk(&ndn_store, stream, chunk_id.as_str(), offset, size).await;
+            }
+        };
+        write_json_line(&mut stream, &AgentResponse::from_result(result)).await?;
+        Ok(())
+    }
+
+    async fn send_chunk<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, ndn_store: &NdnStore, mut stream: BufReader<S>,
+        chunk_id: &str, offset: u64) -> Result<()> {
+        let reader = match parse_chunk_id(chunk_id) {
+            Ok(chunk_id) => ndn_store.target.open_chunk_reader_for_restore(&chunk_id, offset).await,
+            Err(err) => Err(err),
+        };
+        let mut reader = match reader {
+            Ok(reader) => reader,
+            Err(err) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+        };
+        write_json_line(&mut stream, &AgentResponse::from_result(Ok(Value::Null))).await?;
+        let stream = stream.get_mut();
+        tokio::io::copy(&mut reader, stream).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    async fn receive_chunk<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, ndn_store: &NdnStore, mut stream: BufReader<S>,
+        chunk_id: &str, offset: u64, size: u64) -> Result<()> {
+        let open_result = match parse_chunk_id(chunk_id) {
+            Ok(parsed_chunk_id) => ndn_store.target.open_chunk_writer(&parsed_chunk_id, offset, size).await,
+            Err(err) => Err(err),
+        };
+        let (mut writer, real_offset) = match open_result {
+            Ok(result) => result,
+            Err(err) => {
+                write_json_line(&mut stream, &AgentResponse::from_result(Err(err))).await?;
+                return Ok(());
+            }
+        };
+        //回复之前登记,保证之后的CompleteChunk一定能等到这次写入结束
+        let write_state = Arc::new(Mutex::new(Some(format!("write of chunk {} is interrupted", chunk_id))));
+        let mut write_guard = write_state.clone().lock_owned().await;
+        ndn_store.chunk_writes.lock().await.insert(chunk_id.to_string(), write_state);
+        write_json_line(&mut stream, &AgentResponse::from_result(Ok(json!(real_offset)))).await?;
+
+        //写入方关闭连接表示chunk内容结束,由大小判断是否完整
+        let mut written = 0u64;
+        let mut buf = vec![0u8; NDN_COPY_BUFFER_SIZE];
+        let mut write_error = None;
+        loop {
+            let read_len = match stream.read(&mut buf).await {
+                Ok(read_len) => read_len,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+                Err(e) => {
+                    write_error = Some(format!("read chunk content failed: {}", e));
+                    break;
+                }
+            };
+            if read_len == 0 {
+                break;
+            }
+            if let Err(e) = writer.write_all(&buf[..read_len]).await {
+                write_error = Some(format!("write chunk content failed: {}", e));
+                break;
+            }
+            written += read_len as u64;
+        }
+        if write_error.is_none() {
+            if let Err(e) = writer.flush().await {
+                write_error = Some(format!("flush chunk content failed: {}", e));
+            }
+        }
+        let expected = size.saturating_sub(real_offset);
+        if write_error.is_none() && written != expected {
+            write_error = Some(format!("content of chunk {} is incomplete, {} of {} bytes", chunk_id, written, expected));
+        }
+        *write_guard = write_error;
+        Ok(())
+    }
+
+    async fn complete_chunk(&self, ndn_store: &NdnStore, chunk_id: &str) -> BackupResult<()> {
+        let parsed_chunk_id = parse_chunk_id(chunk_id)?;
+        let write_state = ndn_store.chunk_writes.lock().await.remove(chunk_id);
+        if let Some(write_state) = write_state {
+            if let Some(err) = write_state.lock().await.take() {
+                return Err(BuckyBackupError::TryLater(err));
+            }
+        }
+        ndn_store.target.complete_chunk_writer(&parsed_chunk_id).await
+    }
+}
+
+//注册ndn://的target,opener决定怎样连接到存储节点
+pub fn register_ndn_target_provider(registry: &mut BackupProviderRegistry, opener: Arc<dyn NdnStreamOpener>) {
+    registry.register_target_provider(
+        BackupProviderDesc::builtin("ndn_chunk_target", NDN_SCHEME, BackupProviderKind::Target),
+        Arc::new(move |url: String, _credential: Option<TargetCredential>| {
+            let opener = opener.clone();
+            Box::pin(async move {
+                let target = NdnChunkTargetProvider::new(url.as_str(), opener)?;
+                Ok(Box::new(target) as BackupChunkTargetProvider)
+            }) as TargetProviderFuture
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_hash::*;
+
+    #[tokio::test]
+    async fn test_ndn_chunk_transfer() {
+        assert_eq!(parse_ndn_url("ndn://nas1/home_backup").unwrap(), ("nas1".to_string(), DEFAULT_NDN_CHUNK_PORT, "home_backup".to_string()));
+        assert!(parse_ndn_url("ndn://nas1/a/b").is_err());
+        assert!(parse_ndn_url("ndn://nas1").is_err());
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let server = Arc::new(NdnChunkServer::new(test_dir.path().join(NDN_STORE_DIR)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(server.serve(listener));
+
+        let url = format!("ndn://127.0.0.1:{}/store1", port);
+        let target = NdnChunkTargetProvider::new(url.as_str(), Arc::new(TcpNdnStreamOpener)).unwrap();
+        let content: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let chunk_id = calc_chunk_id(&content, ChunkHashType::Sha256).unwrap();
+        assert_eq!(target.is_chunk_exist(&chunk_id).await.unwrap().0, false);
+
+        //中断的写入不能完成
+        let (mut writer, offset) = target.open_chunk_writer(&chunk_id, 0, content.len() as u64).await.unwrap();
+        assert_eq!(offset, 0);
+        writer.write_all(&content[..1000]).await.unwrap();
+        drop(writer);
+        assert!(target.complete_chunk_writer(&chunk_id).await.is_err());
+
+        let (mut writer, offset) = target.open_chunk_writer(&chunk_id, 0, content.len() as u64).await.unwrap();
+        writer.write_all(&content[offset as usize..]).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        target.complete_chunk_writer(&chunk_id).await.unwrap();
+        assert_eq!(target.is_chunk_exist(&chunk_id).await.unwrap(), (true, content.len() as u64));
+        assert!(matches!(target.open_chunk_writer(&chunk_id, 0, content.len() as u64).await, Err(BuckyBackupError::AlreadyDone(_))));
+
+        let mut reader = target.open_chunk_reader_for_restore(&chunk_id, 1000).await.unwrap();
+        let mut read_content = Vec::new();
+        reader.read_to_end(&mut read_content).await.unwrap();
+        assert_eq!(read_content, content[1000..]);
+
+        //节点不在线时稍后重试
+        let offline_target = NdnChunkTargetProvider::new("ndn://127.0.0.1:1/store1", Arc::new(TcpNdnStreamOpener)).unwrap();
+        assert!(matches!(offline_target.open_chunk_reader_for_restore(&chunk_id, 0).await, Err(BuckyBackupError::TryLater(_))));
+    }
+}
@@ -0,0 +1,165 @@
+#![allow(unused)]
+
+use serde::{Serialize, Deserialize};
+use ndn_lib::{ChunkHasher, ChunkId};
+use anyhow::Result;
+use crate::chunk_hash::*;
+
+//小文件打包:把大量小文件的内容首尾相接拼成一个pack chunk,只占用一个target对象,
+//每个item在pack里的位置记录在index里(保存在task_db的backup_items.pack_info中)
+pub const PACK_CHUNK_MAX_SIZE:u64 = 1024*1024*16; //16MB
+pub const PACK_ITEM_MAX_SIZE:u64 = 1024*1024; //1MB,超过这个大小的item单独成chunk
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackItemLocation {
+    pub pack_chunk_id: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl PackItemLocation {
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json_str(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        serde_json::from_str(s).ok()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackIndexEntry {
+    pub item_id: String,
+    pub chunk_id: String,//item自身内容的chunk_id,用于去重和恢复时校验
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackIndex {
+    pub pack_chunk_id: String,
+    pub total_size: u64,
+    pub items: Vec<PackIndexEntry>,
+}
+
+impl PackIndex {
+    pub fn get_item_location(&self, item_id: &str) -> Option<PackItemLocation> {
+        self.items.iter().find(|entry| entry.item_id == item_id).map(|entry| PackItemLocation {
+            pack_chunk_id: self.pack_chunk_id.clone(),
+            offset: entry.offset,
+            size: entry.size,
+        })
+    }
+}
+
+pub struct PackChunkBuilder {
+    buffer: Vec<u8>,
+    items: Vec<PackIndexEntry>,
+    max_size: u64,
+}
+
+impl PackChunkBuilder {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            buffer: Vec::new(),
+            items: Vec::new(),
+            max_size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size() >= self.max_size
+    }
+
+    pub fn contains_item(&self, item_id: &str) -> bool {
+        self.items.iter().any(|entry| entry.item_id == item_id)
+    }
+
+    //返回false说明pack已经放不下了,调用者应该先finish当前pack
+    pub fn can_add(&self, size: u64) -> bool {
+        self.items.is_empty() || self.size() + size <= self.max_size
+    }
+
+    //content的所有权直接转移给pack,不再保留额外的拷贝
+    pub fn add_item(&mut self, item_id: &str, chunk_id: &ChunkId, mut content: Vec<u8>) {
+        let content_len = content.len() as u64;
+        let offset = self.size();
+        if self.buffer.is_empty() {
+            self.buffer = content;
+        } else {
+            self.buffer.append(&mut content);
+        }
+        self.items.push(PackIndexEntry {
+            item_id: item_id.to_string(),
+            chunk_id: chunk_id.to_string(),
+            offset,
+            size: content_len,
+        });
+    }
+
+    //计算pack chunk的chunk_id,返回pack内容和index,builder被重置
+    pub fn finish(&mut self) -> Result<(ChunkId, Vec<u8>, PackIndex)> {
+        let buffer = std::mem::take(&mut self.buffer);
+        let items = std::mem::take(&mut self.items);
+        let mut hasher = ChunkHasher::new(None).map_err(|e| anyhow::anyhow!("{}",e))?;
+        hasher.update_from_bytes(&buffer);
+        let pack_chunk_id = hasher.finalize_chunk_id();
+        let index = PackIndex {
+            pack_chunk_id: pack_chunk_id.to_string(),
+            total_size: buffer.len() as u64,
+            items,
+        };
+        Ok((pack_chunk_id, buffer, index))
+    }
+}
+
+pub fn calc_content_chunk_id(content: &[u8]) -> Result<ChunkId> {
+    calc_chunk_id(content, ChunkHashType::Sha256)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_builder() {
+        let mut builder = PackChunkBuilder::new(10);
+        let a = vec![1u8; 4];
+        let b = vec![2u8; 4];
+        let c = vec![3u8; 4];
+        let a_id = calc_content_chunk_id(&a).unwrap();
+        let b_id = calc_content_chunk_id(&b).unwrap();
+        builder.add_item("a", &a_id, a);
+        assert!(builder.can_add(4));
+        builder.add_item("b", &b_id, b);
+        assert!(!builder.can_add(c.len() as u64));
+        assert_eq!(builder.item_count(), 2);
+
+        let (pack_id, content, index) = builder.finish().unwrap();
+        assert!(builder.is_empty());
+        assert_eq!(content.len(), 8);
+        assert_eq!(index.pack_chunk_id, pack_id.to_string());
+        let loc = index.get_item_location("b").unwrap();
+        assert_eq!(loc.offset, 4);
+        assert_eq!(loc.size, 4);
+        assert_eq!(&content[loc.offset as usize..(loc.offset + loc.size) as usize], &[2u8; 4]);
+
+        let loc2 = PackItemLocation::from_json_str(loc.to_json_string().as_str()).unwrap();
+        assert_eq!(loc, loc2);
+    }
+}
@@ -0,0 +1,232 @@
+#![allow(unused)]
+
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use blake3::hazmat::{ChainingValue, HasherExt, Mode};
+use ndn_lib::ChunkId;
+use anyhow::Result;
+use log::*;
+
+use crate::chunk_hash::*;
+use crate::pack::PACK_ITEM_MAX_SIZE;
+
+//prepare阶段多线程计算本地文件的chunk_id
+//任务按文件大小从大到小分给各个worker,worker做完自己的任务后从其它worker的队尾偷任务,大文件不会让小文件排队等待
+//blake3的大文件按range_size切成多段并发计算,再按blake3的树结构合并;sha256只能顺序计算,一个文件一个任务
+pub const PARALLEL_HASH_RANGE_SIZE: u64 = 64*1024*1024; //64MB
+const PARALLEL_HASH_BUFFER_SIZE: usize = 1024*1024;
+const BLAKE3_CHUNK_LEN: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParallelHashConfig {
+    pub enabled: bool,
+    pub worker_count: usize,//0为cpu核数
+    pub range_size: u64,//blake3大文件切分的大小,调整为2的幂
+    pub min_file_size: u64,//小于这个大小的文件还是由engine计算(可以打包)
+}
+
+impl Default for ParallelHashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            worker_count: 0,
+            range_size: PARALLEL_HASH_RANGE_SIZE,
+            min_file_size: PACK_ITEM_MAX_SIZE + 1,
+        }
+    }
+}
+
+impl ParallelHashConfig {
+    pub fn get_worker_count(&self) -> usize {
+        if self.worker_count > 0 {
+            return self.worker_count;
+        }
+        std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1)
+    }
+
+    //blake3的子树必须是2的幂个chunk
+    fn get_range_size(&self) -> u64 {
+        self.range_size.max(BLAKE3_CHUNK_LEN).next_power_of_two()
+    }
+}
+
+struct HashJob {
+    file_index: usize,
+    offset: u64,
+    len: u64,
+    is_range: bool,//blake3切分出的一段,结果是子树的chaining value
+}
+
+enum HashJobResult {
+    ChunkId(ChunkId),
+    Range(ChainingValue),
+}
+
+//返回结果和files的顺序一致;阻塞调用,在async中通过spawn_blocking使用
+pub fn hash_files_parallel(files: &[(PathBuf, u64)], hash_type: ChunkHashType, config: &ParallelHashConfig) -> Vec<Result<ChunkId>> {
+    let range_size = config.get_range_size();
+    let mut jobs = Vec::new();
+    for (file_index, (_, size)) in files.iter().enumerate() {
+        if hash_type == ChunkHashType::Blake3 && *size > range_size {
+            let mut offset = 0;
+            while offset < *size {
+                let len = range_size.min(*size - offset);
+                jobs.push(HashJob { file_index, offset, len, is_range: true });
+                offset += len;
+            }
+        } else {
+            jobs.push(HashJob { file_index, offset: 0, len: *size, is_range: false });
+        }
+    }
+    jobs.sort_by(|a, b| b.len.cmp(&a.len));
+
+    let worker_count = config.get_worker_count().min(jobs.len()).max(1);
+    let job_count = jobs.len();
+    let mut queues: Vec<VecDeque<(usize, HashJob)>> = (0..worker_count).map(|_| VecDeque::new()).collect();
+    for (job_index, job) in jobs.into_iter().enumerate() {
+        queues[job_index % worker_count].push_back((job_index, job));
+    }
+    let queues: Vec<Mutex<VecDeque<(usize, HashJob)>>> = queues.into_iter().map(Mutex::new).collect();
+    let results: Mutex<Vec<Option<(HashJob, Result<HashJobResult>)>>> = Mutex::new((0..job_count).map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for worker_index in 0..worker_count {
+            let queues = &queues;
+            let results = &results;
+            scope.spawn(move || {
+                while let Some((job_index, job)) = next_hash_job(queues, worker_index) {
+                    let (path, _) = &files[job.file_index];
+                    let result = hash_file_job(path, &job, hash_type);
+                    results.lock().unwrap()[job_index] = Some((job, result));
+                }
+            });
+        }
+    });
+
+    let mut file_results: Vec<Option<Result<ChunkId>>> = (0..files.len()).map(|_| None).collect();
+    let mut file_ranges: Vec<Vec<(u64, ChainingValue)>> = (0..files.len()).map(|_| Vec::new()).collect();
+    for (job, result) in results.into_inner().unwrap().into_iter().flatten() {
+        match result {
+            Ok(HashJobResult::ChunkId(chunk_id)) => file_results[job.file_index] = Some(Ok(chunk_id)),
+            Ok(HashJobResult::Range(cv)) => file_ranges[job.file_index].push((job.offset, cv)),
+            Err(err) => {
+                if file_results[job.file_index].is_none() {
+                    file_results[job.file_index] = Some(Err(err));
+                }
+            }
+        }
+    }
+    file_results.into_iter().enumerate().map(|(file_index, result)| {
+        if let Some(result) = result {
+            return result;
+        }
+        let ranges = &mut file_ranges[file_index];
+        ranges.sort_by_key(|(offset, _)| *offset);
+        let cvs: Vec<ChainingValue> = ranges.iter().map(|(_, cv)| *cv).collect();
+        let hash = merge_blake3_root(&cvs, files[file_index].1, range_size);
+        let chunk_id_str = format!("{}:{}", BLAKE3_HASH_TYPE, hash.to_hex());
+        ChunkId::new(chunk_id_str.as_str()).map_err(|e| anyhow::anyhow!("{}", e))
+    }).collect()
+}
+
+//自己的队列从头取(大任务先做),偷其它队列时从尾部取(小任务);同一时间只持有一个队列的锁
+fn next_hash_job(queues: &[Mutex<VecDeque<(usize, HashJob)>>], worker_index: usize) -> Option<(usize, HashJob)> {
+    let own_job = queues[worker_index].lock().unwrap().pop_front();
+    if own_job.is_some() {
+        return own_job;
+    }
+    (1..queues.len()).find_map(|step| queues[(worker_index + step) % queues.len()].lock().unwrap().pop_back())
+}
+
+fn hash_file_job(path: &PathBuf, job: &HashJob, hash_type: ChunkHashType) -> Result<HashJobResult> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(job.offset))?;
+    let mut reader = file.take(job.len);
+    let mut buf = vec![0u8; PARALLEL_HASH_BUFFER_SIZE];
+    if job.is_range {
+        let mut hasher = blake3::Hasher::new();
+        hasher.set_input_offset(job.offset);
+        let read_len = read_into(&mut reader, &mut buf, |content| { hasher.update(content); })?;
+        if read_len != job.len {
+            return Err(anyhow::anyhow!("file {:?} is truncated while hashing", path));
+        }
+        return Ok(HashJobResult::Range(hasher.finalize_non_root()));
+    }
+    //单个文件的hash在一个worker里计算,不再使用blake3的多线程
+    let mut hasher = BackupChunkHasher::new(hash_type)?;
+    let read_len = match &mut hasher {
+        BackupChunkHasher::Blake3(hasher) => read_into(&mut reader, &mut buf, |content| { hasher.update(content); })?,
+        hasher => read_into(&mut reader, &mut buf, |content| hasher.update_from_bytes(content))?,
+    };
+    if read_len != job.len {
+        return Err(anyhow::anyhow!("file {:?} is truncated while hashing", path));
+    }
+    Ok(HashJobResult::ChunkId(hasher.finalize_chunk_id()?))
+}
+
+fn read_into<R: Read>(reader: &mut R, buf: &mut [u8], mut on_content: impl FnMut(&[u8])) -> Result<u64> {
+    let mut total_len = 0;
+    loop {
+        let read_len = reader.read(buf)?;
+        if read_len == 0 {
+            break;
+        }
+        on_content(&buf[..read_len]);
+        total_len += read_len as u64;
+    }
+    Ok(total_len)
+}
+
+//cvs是从0开始每range_size一段的子树;按blake3的规则(左子树是小于总长度的最大2的幂个chunk)递归合并
+fn merge_blake3_root(cvs: &[ChainingValue], total_len: u64, range_size: u64) -> blake3::Hash {
+    let left_len = blake3::hazmat::left_subtree_len(total_len);
+    let left_cv = merge_blake3_subtree(cvs, 0, left_len, range_size);
+    let right_cv = merge_blake3_subtree(cvs, left_len, total_len - left_len, range_size);
+    blake3::hazmat::merge_subtrees_root(&left_cv, &right_cv, Mode::Hash)
+}
+
+fn merge_blake3_subtree(cvs: &[ChainingValue], offset: u64, len: u64, range_size: u64) -> ChainingValue {
+    if len <= range_size {
+        return cvs[(offset / range_size) as usize];
+    }
+    let left_len = blake3::hazmat::left_subtree_len(len);
+    let left_cv = merge_blake3_subtree(cvs, offset, left_len, range_size);
+    let right_cv = merge_blake3_subtree(cvs, offset + left_len, len - left_len, range_size);
+    blake3::hazmat::merge_subtrees_non_root(&left_cv, &right_cv, Mode::Hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_files_parallel() {
+        let test_dir = tempfile::tempdir().unwrap();
+        //切分点前后和不是整段的大小都要和一次计算的结果一致
+        let sizes = [0u64, 100, 4096, 4097, 3 * 4096 + 17, 16 * 4096, 37 * 4096 + 1000];
+        let mut files = Vec::new();
+        for (index, size) in sizes.iter().enumerate() {
+            let path = test_dir.path().join(format!("file_{}.bin", index));
+            let content: Vec<u8> = (0..*size).map(|i| (i * 31 % 251) as u8).collect();
+            std::fs::write(&path, &content).unwrap();
+            files.push((path, *size));
+        }
+        files.push((test_dir.path().join("not_exist.bin"), 4096 * 3));
+
+        let config = ParallelHashConfig { enabled: true, worker_count: 3, range_size: 4000, ..Default::default() };
+        for hash_type in [ChunkHashType::Blake3, ChunkHashType::Sha256] {
+            let results = hash_files_parallel(&files, hash_type, &config);
+            assert_eq!(results.len(), files.len());
+            for (index, (path, _)) in files.iter().enumerate().take(sizes.len()) {
+                let content = std::fs::read(path).unwrap();
+                assert_eq!(results[index].as_ref().unwrap(), &calc_chunk_id(&content, hash_type).unwrap());
+            }
+            assert!(results.last().unwrap().is_err());
+        }
+    }
+}
@@ -4,9 +4,17 @@ use async_trait::async_trait;
 use serde_json::Value;
 use ndn_lib::{ChunkReader,ChunkWriter,ChunkReadSeek,ChunkId};
 use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncSeek};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 use anyhow::Result;
+use crate::name_collision::NameCollisionPolicy;
+use crate::restore_conflict::RestoreConflictPolicy;
+use crate::chunk_hash::ChunkHashType;
+use crate::parallel_hash::ParallelHashConfig;
+use crate::reparse_point::ReparsePointPolicy;
+use crate::cloud_placeholder::{CloudPlaceholderPolicy, HydrateThrottle};
 
 #[derive(Error, Debug)]
 pub enum BuckyBackupError {
@@ -20,18 +28,114 @@ pub enum BuckyBackupError {
     NeedProcess(String),
     #[error("Failed: {0}")]
     Failed(String),
+    #[error("NotFound: {0}")]
+    NotFound(String),
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    //target空间不足,required为还需要写入的字节数,available为0表示target无法查询剩余空间
+    #[error("OutOfSpace: required {required} bytes, available {available} bytes")]
+    OutOfSpace { required: u64, available: u64 },
+    //超出plan/租户/target的存储配额,used为备份开始前的已用量,required为这次备份需要写入的字节数
+    #[error("QuotaExceeded: {scope} {key} quota {max_bytes} bytes, used {used} bytes, required {required} bytes")]
+    QuotaExceeded { scope: String, key: String, max_bytes: u64, used: u64, required: u64 },
 }
 
 pub type BackupResult<T> = std::result::Result<T, BuckyBackupError>;
 
+//写入时磁盘空间或者配额不足(ENOSPC/EDQUOT,windows的ERROR_DISK_FULL),重试没有意义
+pub fn is_out_of_space_io_error(err: &std::io::Error) -> bool {
+    matches!(err.kind(), std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackupErrorKind {
+    Internal,
+    AlreadyDone,
+    TryLater,
+    NeedProcess,
+    Failed,
+    NotFound,
+    Database,
+    Io,
+    OutOfSpace,
+    QuotaExceeded,
+}
+
+impl BuckyBackupError {
+    pub fn kind(&self) -> BackupErrorKind {
+        match self {
+            BuckyBackupError::Internal(_) => BackupErrorKind::Internal,
+            BuckyBackupError::AlreadyDone(_) => BackupErrorKind::AlreadyDone,
+            BuckyBackupError::TryLater(_) => BackupErrorKind::TryLater,
+            BuckyBackupError::NeedProcess(_) => BackupErrorKind::NeedProcess,
+            BuckyBackupError::Failed(_) => BackupErrorKind::Failed,
+            BuckyBackupError::NotFound(_) => BackupErrorKind::NotFound,
+            BuckyBackupError::Database(_) => BackupErrorKind::Database,
+            BuckyBackupError::Io(_) => BackupErrorKind::Io,
+            BuckyBackupError::OutOfSpace { .. } => BackupErrorKind::OutOfSpace,
+            BuckyBackupError::QuotaExceeded { .. } => BackupErrorKind::QuotaExceeded,
+        }
+    }
+
+    //稍后重试可能成功的错误:TryLater,数据库忙,以及网络/超时类的io错误
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BuckyBackupError::TryLater(_) => true,
+            BuckyBackupError::Database(rusqlite::Error::SqliteFailure(err, _)) => {
+                matches!(err.code, rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+            }
+            BuckyBackupError::Io(err) => matches!(err.kind(),
+                std::io::ErrorKind::Interrupted | std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe),
+            _ => false,
+        }
+    }
+
+    //engine中使用anyhow::Error传递错误,这里找回错误链中的BuckyBackupError,
+    //io/数据库错误保留原始错误,其它错误保留完整的错误链描述
+    pub fn from_anyhow(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<BuckyBackupError>() {
+            Ok(backup_err) => return backup_err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<std::io::Error>() {
+            Ok(io_err) => return BuckyBackupError::Io(io_err),
+            Err(err) => err,
+        };
+        match err.downcast::<rusqlite::Error>() {
+            Ok(db_err) => BuckyBackupError::Database(db_err),
+            Err(err) => BuckyBackupError::Failed(format!("{:#}", err)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RestoreConfig {
     pub restore_location_url: String,
     pub is_clean_restore: bool, // 为true时,恢复后只包含恢复的文件,不包含其他文件
+    #[serde(default)]
+    pub name_collision_policy: NameCollisionPolicy,//恢复到大小写不敏感或Windows文件系统时,文件名冲突的处理策略
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict_policy: Option<RestoreConflictPolicy>,//恢复路径上已经存在同名文件时的处理策略,None时见get_conflict_policy
+    #[serde(default)]
+    pub restore_in_place: bool,//恢复到plan的source目录,创建task时由engine填写restore_location_url并做安全检查
     #[serde(skip_serializing_if = "Option::is_none")]
     pub params:Option<serde_json::Value>,
 }
 
+impl RestoreConfig {
+    //没有指定冲突策略时,原地恢复保留已有的文件,恢复到其他目录时覆盖
+    pub fn get_conflict_policy(&self) -> RestoreConflictPolicy {
+        match self.conflict_policy.as_ref() {
+            Some(policy) => policy.clone(),
+            None if self.restore_in_place => RestoreConflictPolicy::KeepBoth,
+            None => RestoreConflictPolicy::Overwrite,
+        }
+    }
+}
+
 impl ToSql for RestoreConfig {
     fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
         let s = serde_json::to_string(self).map_err(|e| 
@@ -51,7 +155,7 @@ impl FromSql for RestoreConfig {
 }
 
 //use tokio::fs::AsyncReadExt;
-#[derive(Debug,Clone,PartialEq)]
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
 pub enum BackupItemState {
     New,
     LocalDone,
@@ -92,11 +196,25 @@ impl FromSql for BackupItemState {
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
 pub enum BackupItemType {
     Chunk,
     File,
     Directory,
+    Symlink,//符号链接,只保存链接目标(file_meta.link_target)
+    HardLink,//硬链接,内容只保存一次,file_meta.link_target是第一个出现的item_id
+    CloudPlaceholder,//云盘占位文件,内容在云端没有下载,只保存文件属性
+}
+
+impl BackupItemType {
+    pub fn is_link(&self) -> bool {
+        matches!(self, BackupItemType::Symlink | BackupItemType::HardLink)
+    }
+
+    //没有需要传输的内容,由source的restore_link_item恢复
+    pub fn is_metadata_only(&self) -> bool {
+        self.is_link() || *self == BackupItemType::CloudPlaceholder
+    }
 }
 
 impl ToSql for BackupItemType {
@@ -105,6 +223,9 @@ impl ToSql for BackupItemType {
             BackupItemType::Chunk => "CHUNK".to_string(),
             BackupItemType::File => "FILE".to_string(),
             BackupItemType::Directory => "DIRECTORY".to_string(),
+            BackupItemType::Symlink => "SYMLINK".to_string(),
+            BackupItemType::HardLink => "HARDLINK".to_string(),
+            BackupItemType::CloudPlaceholder => "CLOUDPLACEHOLDER".to_string(),
         };
         Ok(s.into())
     }
@@ -116,6 +237,9 @@ impl FromSql for BackupItemType {
             "CHUNK" => BackupItemType::Chunk,
             "FILE" => BackupItemType::File,
             "DIRECTORY" => BackupItemType::Directory,
+            "SYMLINK" => BackupItemType::Symlink,
+            "HARDLINK" => BackupItemType::HardLink,
+            "CLOUDPLACEHOLDER" => BackupItemType::CloudPlaceholder,
             _ => BackupItemType::File, // 默认文件类型
         })
     }
@@ -123,7 +247,8 @@ impl FromSql for BackupItemType {
 
 
 
-#[derive(Debug,Clone)]
+//remote agent通过json传输BackupItem
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct BackupItem {
     pub item_id: String,//对source来说，可以用item_id来唯一的标示一个待备份的item,一般是文件的相对路径
     pub item_type:BackupItemType,//文件，目录, Piece?
@@ -136,6 +261,31 @@ pub struct BackupItem {
     pub progress:String,
     pub have_cache:bool,//是否已经缓存到本地
     pub diff_info:Option<String>,//diff信息
+    pub pack_info:Option<String>,//item被打包到pack chunk时,记录其在pack中的位置(PackItemLocation的json)
+    pub file_meta:Option<String>,//文件属性(ItemFileMeta的json),恢复完成后写回
+    pub is_fuzzy:bool,//备份过程中文件被修改且重试后仍不稳定,保存的内容可能不是某个时刻的一致快照
+}
+
+impl BackupItem {
+    //还没有准备的item:没有chunk_id,状态为New,其它字段由调用者按需设置
+    pub fn new(item_id: &str, item_type: BackupItemType, size: u64) -> Self {
+        Self {
+            item_id: item_id.to_string(),
+            item_type,
+            chunk_id: None,
+            quick_hash: None,
+            state: BackupItemState::New,
+            size,
+            last_modify_time: 0,
+            create_time: 0,
+            progress: "".to_string(),
+            have_cache: false,
+            diff_info: None,
+            pack_info: None,
+            file_meta: None,
+            is_fuzzy: false,
+        }
+    }
 }
 
 #[async_trait]
@@ -147,14 +297,41 @@ pub trait IBackupChunkSourceProvider {
     //async fn lock_for_backup(&self,source_url: &str)->BackupResult<()>;
     //async fn unlock_for_backup(&self,source_url: &str)->BackupResult<()>;
     async fn prepare_items(&self)->BackupResult<(Vec<BackupItem>,bool)>;
+    //prepare_items时由source计算大文件的chunk_id(按hash_type),不支持的source忽略
+    fn enable_prepare_hash(&mut self, hash_type: ChunkHashType, config: ParallelHashConfig) {}
+    //扫描时遇到符号链接/junction等重解析点的处理方式,不支持的source忽略
+    fn set_reparse_point_policy(&mut self, policy: ReparsePointPolicy) {}
+    //扫描时是否进入子目录,不支持的source忽略
+    fn set_recursive_scan(&mut self, recursive: bool) {}
+    //扫描时遇到云盘占位文件的处理方式,不支持的source忽略
+    //throttle是读取占位文件的限速,同一个task的多个source实例传入同一个,总的下载速度不超过限制
+    fn set_cloud_placeholder_policy(&mut self, policy: CloudPlaceholderPolicy, throttle: Arc<HydrateThrottle>) {}
     async fn open_item(&self, item_id: &str)->BackupResult<Pin<Box<dyn ChunkReadSeek + Send + Sync + Unpin>>>;
     async fn open_item_chunk_reader(&self, item_id: &str,offset:u64)->BackupResult<ChunkReader>;
     async fn on_item_backuped(&self, item_id: &str)->Result<()>;
     //restore
     async fn init_for_restore(&self, restore_config:&RestoreConfig)->Result<()>;
     async fn open_writer_for_restore(&self, item: &BackupItem,restore_config:&RestoreConfig,offset:u64)->BackupResult<(ChunkWriter,u64)>;
+    //item的内容全部写入后调用,source在这里把临时文件原子的替换成最终文件
+    async fn complete_restore_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()>;
+    //比较item当前的size/修改时间和prepare时记录的是否一致,不一致时更新item并返回true
+    async fn check_item_changed(&self, item: &mut BackupItem)->BackupResult<bool>;
+    //Symlink/HardLink类型的item没有内容,直接按file_meta.link_target重建链接
+    async fn restore_link_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()>;
+    //块级恢复:打开恢复位置上已经存在的同名文件直接改写(不经过临时文件),返回文件和它当前的大小
+    //文件不存在或者source不支持时返回NotFound,engine退回到完整恢复
+    async fn open_item_for_block_restore(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<(Pin<Box<dyn BlockRestoreFile>>,u64)> {
+        Err(BuckyBackupError::NotFound(format!("source not support block restore of {}", item.item_id)))
+    }
+    //块级恢复改写完成后调用,把文件截断到item的大小并应用文件属性
+    async fn complete_block_restore_item(&self, item: &BackupItem,restore_config:&RestoreConfig)->BackupResult<()> {
+        Err(BuckyBackupError::Failed(format!("source not support block restore of {}", item.item_id)))
+    }
 }
 
+pub trait BlockRestoreFile: AsyncRead + AsyncWrite + AsyncSeek + Send + Sync + Unpin {}
+impl<T: AsyncRead + AsyncWrite + AsyncSeek + Send + Sync + Unpin> BlockRestoreFile for T {}
+
 
 //TODO ChunkTarget目前只依赖Chunk和Chunklist的语义，是否需要理解CheckPoint的概念?
 #[async_trait]
@@ -171,6 +348,14 @@ pub trait IBackupChunkTargetProvider {
     //async fn get_support_chunkid_types(&self)->Result<Vec<String>>;
     
     async fn is_chunk_exist(&self, chunk_id: &ChunkId)->Result<(bool,u64)>;
+    //批量查询,结果和chunk_ids一一对应;默认逐个调用is_chunk_exist,有批量查询接口的target应该覆盖它
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId])->Result<Vec<(bool,u64)>> {
+        let mut results = Vec::with_capacity(chunk_ids.len());
+        for chunk_id in chunk_ids.iter() {
+            results.push(self.is_chunk_exist(chunk_id).await?);
+        }
+        Ok(results)
+    }
     async fn open_chunk_writer(&self, chunk_id: &ChunkId,offset:u64,size:u64)->BackupResult<(ChunkWriter,u64)>;
     async fn complete_chunk_writer(&self, chunk_id: &ChunkId)->BackupResult<()>;
     async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId)->BackupResult<()>;
@@ -180,7 +365,25 @@ pub trait IBackupChunkTargetProvider {
     //async fn put_chunklist(&self, chunk_list: HashMap<ChunkId, Vec<u8>>)->Result<()>;
     // restore
     async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId,offset:u64)->BackupResult<ChunkReader>;
-    
+    //删除chunk,只用于清理已经不被任何checkpoint引用的chunk;chunk不存在时返回NotFound
+    async fn remove_chunk(&self, chunk_id: &ChunkId)->BackupResult<()> {
+        Err(BuckyBackupError::Failed(format!("target {} not support remove chunk", self.get_target_url())))
+    }
+
+    //布局版本标记(TargetLayoutMarker的json),没有标记时返回None;不支持标记的target按v0处理
+    async fn read_layout_marker(&self)->BackupResult<Option<String>> {
+        Ok(None)
+    }
+    async fn write_layout_marker(&self, _marker: &str)->BackupResult<()> {
+        Err(BuckyBackupError::Failed(format!("target {} not support layout marker", self.get_target_url())))
+    }
+    //把数据从from_version迁移到from_version+1,标记由调用者写入;v0到v1没有数据变化
+    async fn upgrade_layout(&self, from_version: u32)->BackupResult<()> {
+        match from_version {
+            0 => Ok(()),
+            _ => Err(BuckyBackupError::Failed(format!("target {} not support upgrade layout from version {}", self.get_target_url(), from_version))),
+        }
+    }
 }
 
 #[async_trait]
@@ -198,3 +401,34 @@ pub type BackupDirSourceProvider = Box<dyn IBackupDirSourceProvider + Send + Syn
 pub type BackupDirTargetProvider = Box<dyn IBackupDirTargetProvider + Send + Sync>;
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind_and_retryable() {
+        assert!(BuckyBackupError::TryLater("busy".to_string()).is_retryable());
+        assert!(!BuckyBackupError::Failed("bad".to_string()).is_retryable());
+        let io_err = BuckyBackupError::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout"));
+        assert_eq!(io_err.kind(), BackupErrorKind::Io);
+        assert!(io_err.is_retryable());
+
+        //经过anyhow后仍然能找回原始错误
+        let err: anyhow::Error = BuckyBackupError::NotFound("task_1".to_string()).into();
+        assert_eq!(BuckyBackupError::from_anyhow(err).kind(), BackupErrorKind::NotFound);
+        let err: anyhow::Error = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset").into();
+        assert!(BuckyBackupError::from_anyhow(err).is_retryable());
+        let err = anyhow::anyhow!("inner").context("outer");
+        let backup_err = BuckyBackupError::from_anyhow(err);
+        assert_eq!(backup_err.kind(), BackupErrorKind::Failed);
+        assert_eq!(backup_err.to_string(), "Failed: outer: inner");
+
+        assert!(is_out_of_space_io_error(&std::io::Error::new(std::io::ErrorKind::StorageFull, "disk full")));
+        assert!(!is_out_of_space_io_error(&std::io::Error::new(std::io::ErrorKind::BrokenPipe, "broken")));
+        let err: anyhow::Error = BuckyBackupError::OutOfSpace { required: 100, available: 10 }.into();
+        let backup_err = BuckyBackupError::from_anyhow(err);
+        assert_eq!(backup_err.kind(), BackupErrorKind::OutOfSpace);
+        assert!(!backup_err.is_retryable());
+    }
+}
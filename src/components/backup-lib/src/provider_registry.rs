@@ -0,0 +1,259 @@
+#![allow(unused)]
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use crate::provider::*;
+use crate::credential::TargetCredential;
+use crate::local_chunk_provider::*;
+use crate::local_path::translate_local_path_from_url;
+
+//插件导出的函数签名变化时增加这个版本号,版本不一致的插件不会被加载
+pub const BACKUP_PLUGIN_ABI_VERSION: u32 = 1;
+//插件动态库需要导出的符号,使用declare_backup_plugin!宏生成
+pub const BACKUP_PLUGIN_ABI_VERSION_SYMBOL: &[u8] = b"bucky_backup_plugin_abi_version";
+pub const BACKUP_PLUGIN_REGISTER_SYMBOL: &[u8] = b"bucky_backup_plugin_register";
+pub const BACKUP_PLUGIN_BUILD_ID_SYMBOL: &[u8] = b"bucky_backup_plugin_build_id";
+//注册函数传递的是rust类型,只有abi版本号相同不够,还要求buckyos-backup-lib和rustc的版本都相同,
+//这个值在插件编译时由插件依赖的buckyos-backup-lib生成,以\0结尾
+pub const BACKUP_PLUGIN_BUILD_ID: &str = concat!(
+    "buckyos-backup-lib ", env!("CARGO_PKG_VERSION"), "; ", env!("BACKUP_LIB_RUSTC_VERSION"), "\0");
+
+pub fn get_backup_plugin_build_id() -> &'static str {
+    BACKUP_PLUGIN_BUILD_ID.trim_end_matches('\0')
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BackupProviderKind {
+    Source,
+    Target,
+}
+
+//provider支持的能力,engine根据target的能力调整备份/恢复流程
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupProviderAbilities {
+    pub supports_resume: bool,//open_chunk_writer能从offset继续写入未完成的chunk
+    pub supports_ranged_read: bool,//open_chunk_reader_for_restore能从offset开始读,不支持时中断的恢复要从头读取item
+    pub supports_link: bool,//支持link_chunkid/query_link_target,不支持时不使用quick_hash边算边传
+    pub supports_checkpoint_status: bool,//target上能保存/查询checkpoint状态,目前checkpoint状态只保存在本地db
+    pub max_chunk_size: Option<u64>,//单个chunk的最大长度,超过的item无法备份到这个target
+    pub supports_chunk_naming: bool,//支持url中的chunk_naming=hmac,对象名不暴露chunk_id
+}
+
+impl Default for BackupProviderAbilities {
+    fn default() -> Self {
+        Self {
+            supports_resume: true,
+            supports_ranged_read: true,
+            supports_link: true,
+            supports_checkpoint_status: false,
+            max_chunk_size: None,
+            supports_chunk_naming: false,
+        }
+    }
+}
+
+impl BackupProviderAbilities {
+    pub fn check_chunk_size(&self, size: u64) -> bool {
+        self.max_chunk_size.map_or(true, |max_chunk_size| size <= max_chunk_size)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupProviderDesc {
+    pub name: String,
+    pub scheme: String,//provider负责的url scheme
+    pub kind: BackupProviderKind,
+    pub version: String,
+    pub plugin: Option<String>,//从插件加载时为插件文件路径,内置的provider为None
+    #[serde(default)]
+    pub abilities: BackupProviderAbilities,
+}
+
+pub type SourceProviderFuture = Pin<Box<dyn Future<Output = Result<BackupChunkSourceProvider>> + Send>>;
+pub type TargetProviderFuture = Pin<Box<dyn Future<Output = Result<BackupChunkTargetProvider>> + Send>>;
+pub type SourceProviderCreator = Arc<dyn Fn(String) -> SourceProviderFuture + Send + Sync>;
+//参数为target_url和engine从凭证库中解析出的密钥(url里有credential_id时)
+pub type TargetProviderCreator = Arc<dyn Fn(String, Option<TargetCredential>) -> TargetProviderFuture + Send + Sync>;
+
+//按url scheme查找source/target provider,内置的provider和插件中的provider都注册在这里
+#[derive(Clone, Default)]
+pub struct BackupProviderRegistry {
+    sources: HashMap<String, (BackupProviderDesc, SourceProviderCreator)>,
+    targets: HashMap<String, (BackupProviderDesc, TargetProviderCreator)>,
+}
+
+impl BackupProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //注册file://的source和target
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register_source_provider(
+            BackupProviderDesc::builtin("local_dir_source", "file", BackupProviderKind::Source),
+            Arc::new(|url: String| Box::pin(async move {
+                let source_path = translate_local_path_from_url(url.as_str())?;
+                let source = LocalDirChunkProvider::new(source_path.to_string_lossy().to_string()).await?;
+                Ok(Box::new(source) as BackupChunkSourceProvider)
+            }) as SourceProviderFuture),
+        );
+        registry.register_target_provider(
+            BackupProviderDesc::builtin("local_chunk_target", "file", BackupProviderKind::Target),
+            Arc::new(|url: String, _credential: Option<TargetCredential>| Box::pin(async move {
+                let target_path = translate_local_path_from_url(url.as_str())?;
+                let target = LocalChunkTargetProvider::new(target_path.to_string_lossy().to_string()).await?;
+                Ok(Box::new(target) as BackupChunkTargetProvider)
+            }) as TargetProviderFuture),
+        );
+        registry
+    }
+
+    //同一个scheme后注册的会覆盖先注册的
+    pub fn register_source_provider(&mut self, desc: BackupProviderDesc, creator: SourceProviderCreator) {
+        self.sources.insert(desc.scheme.clone(), (desc, creator));
+    }
+
+    pub fn register_target_provider(&mut self, desc: BackupProviderDesc, creator: TargetProviderCreator) {
+        self.targets.insert(desc.scheme.clone(), (desc, creator));
+    }
+
+    pub fn get_source_creator(&self, scheme: &str) -> Option<SourceProviderCreator> {
+        self.sources.get(scheme).map(|(_, creator)| creator.clone())
+    }
+
+    pub fn get_target_creator(&self, scheme: &str) -> Option<TargetProviderCreator> {
+        self.targets.get(scheme).map(|(_, creator)| creator.clone())
+    }
+
+    pub fn get_target_abilities(&self, scheme: &str) -> Option<BackupProviderAbilities> {
+        self.targets.get(scheme).map(|(desc, _)| desc.abilities.clone())
+    }
+
+    pub fn list_providers(&self) -> Vec<BackupProviderDesc> {
+        let mut descs: Vec<BackupProviderDesc> = self.sources.values()
+            .chain(self.targets.values())
+            .map(|(desc, _)| desc.clone())
+            .collect();
+        descs.sort_by(|a, b| a.name.cmp(&b.name));
+        descs
+    }
+
+    //把插件注册到独立registry中的provider合并进来,并记录插件文件路径
+    pub fn merge_plugin(&mut self, plugin_registry: BackupProviderRegistry, plugin_path: &str) {
+        for (scheme, (mut desc, creator)) in plugin_registry.sources {
+            desc.plugin = Some(plugin_path.to_string());
+            self.sources.insert(scheme, (desc, creator));
+        }
+        for (scheme, (mut desc, creator)) in plugin_registry.targets {
+            desc.plugin = Some(plugin_path.to_string());
+            self.targets.insert(scheme, (desc, creator));
+        }
+    }
+
+    //把other中的provider加入,同scheme的覆盖,desc不变
+    pub fn merge(&mut self, other: &BackupProviderRegistry) {
+        for (scheme, entry) in other.sources.iter() {
+            self.sources.insert(scheme.clone(), entry.clone());
+        }
+        for (scheme, entry) in other.targets.iter() {
+            self.targets.insert(scheme.clone(), entry.clone());
+        }
+    }
+
+    pub fn list_schemes(&self) -> Vec<String> {
+        let mut schemes: Vec<String> = self.sources.keys().chain(self.targets.keys()).cloned().collect();
+        schemes.sort();
+        schemes.dedup();
+        schemes
+    }
+}
+
+impl BackupProviderDesc {
+    pub fn builtin(name: &str, scheme: &str, kind: BackupProviderKind) -> Self {
+        Self {
+            name: name.to_string(),
+            scheme: scheme.to_string(),
+            kind,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            plugin: None,
+            abilities: BackupProviderAbilities::default(),
+        }
+    }
+
+    pub fn with_abilities(mut self, abilities: BackupProviderAbilities) -> Self {
+        self.abilities = abilities;
+        self
+    }
+}
+
+//插件crate(crate-type = ["cdylib"])中调用,导出engine加载插件需要的符号:
+//declare_backup_plugin!(register_provider);
+//fn register_provider(registry: &mut BackupProviderRegistry) { ... }
+//插件和backup_suite通过rust的trait object交互,必须使用相同版本的rustc和buckyos-backup-lib编译,
+//加载时通过bucky_backup_plugin_build_id检查
+#[macro_export]
+macro_rules! declare_backup_plugin {
+    ($register_fn:path) => {
+        #[no_mangle]
+        pub extern "C" fn bucky_backup_plugin_abi_version() -> u32 {
+            $crate::BACKUP_PLUGIN_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn bucky_backup_plugin_build_id() -> *const std::os::raw::c_char {
+            $crate::BACKUP_PLUGIN_BUILD_ID.as_ptr() as *const std::os::raw::c_char
+        }
+
+        #[no_mangle]
+        pub fn bucky_backup_plugin_register(registry: &mut $crate::BackupProviderRegistry) {
+            $register_fn(registry);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_build_id() {
+        let build_id = get_backup_plugin_build_id();
+        assert!(build_id.contains(env!("CARGO_PKG_VERSION")));
+        assert!(build_id.contains("rustc"));
+        assert!(!build_id.contains('\0'));
+        assert!(BACKUP_PLUGIN_BUILD_ID.ends_with('\0'));
+    }
+
+    #[test]
+    fn test_builtin_registry() {
+        let mut registry = BackupProviderRegistry::with_builtin();
+        assert!(registry.get_source_creator("file").is_some());
+        assert!(registry.get_target_creator("file").is_some());
+        assert!(registry.get_target_creator("s3").is_none());
+        assert_eq!(registry.list_providers().len(), 2);
+
+        let mut plugin_registry = BackupProviderRegistry::new();
+        plugin_registry.register_target_provider(
+            BackupProviderDesc::builtin("dummy_target", "dummy", BackupProviderKind::Target),
+            Arc::new(|url: String, _credential: Option<TargetCredential>| Box::pin(async move {
+                Err(anyhow::anyhow!("dummy target {}", url))
+            }) as TargetProviderFuture),
+        );
+        registry.merge_plugin(plugin_registry, "/plugins/libdummy.so");
+        assert_eq!(registry.list_schemes(), vec!["dummy".to_string(), "file".to_string()]);
+        let dummy = registry.list_providers().into_iter().find(|desc| desc.scheme == "dummy").unwrap();
+        assert_eq!(dummy.plugin, Some("/plugins/libdummy.so".to_string()));
+        assert_eq!(registry.get_target_abilities("file"), Some(BackupProviderAbilities::default()));
+
+        let abilities = BackupProviderAbilities { max_chunk_size: Some(1024), ..Default::default() };
+        assert!(abilities.check_chunk_size(1024));
+        assert!(!abilities.check_chunk_size(1025));
+        assert!(BackupProviderAbilities::default().check_chunk_size(u64::MAX));
+    }
+}
@@ -0,0 +1,57 @@
+#![allow(unused)]
+
+use serde::{Serialize, Deserialize};
+use std::fs::Metadata;
+
+//符号链接/junction等重解析点的处理方式,LocalDir source扫描时使用
+//Windows上junction和目录符号链接都是名称代理(name surrogate)的重解析点,std的is_symlink()对两者都返回true;
+//OneDrive占位文件/重复数据删除等不是名称代理的重解析点按普通文件读取,Skip时跳过
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReparsePointPolicy {
+    #[default]
+    Record,//链接本身作为Symlink item备份,不跟随
+    Follow,//备份链接指向的文件,指向目录时继续扫描目录(检测循环)
+    Skip,//不备份
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReparsePointKind {
+    Link,//符号链接/junction
+    Other,//其它重解析点,只在Windows上出现
+}
+
+#[cfg(windows)]
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+
+//metadata必须是symlink_metadata(不跟随链接)的结果;普通文件和目录返回None
+pub fn get_reparse_point_kind(metadata: &Metadata) -> Option<ReparsePointKind> {
+    if metadata.file_type().is_symlink() {
+        return Some(ReparsePointKind::Link);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        if metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            return Some(ReparsePointKind::Other);
+        }
+    }
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_reparse_point_kind() {
+        let test_dir = tempfile::tempdir().unwrap();
+        std::fs::write(test_dir.path().join("a.txt"), b"hello").unwrap();
+        std::os::unix::fs::symlink("a.txt", test_dir.path().join("link.txt")).unwrap();
+        let metadata = std::fs::symlink_metadata(test_dir.path().join("a.txt")).unwrap();
+        assert_eq!(get_reparse_point_kind(&metadata), None);
+        let metadata = std::fs::symlink_metadata(test_dir.path().join("link.txt")).unwrap();
+        assert_eq!(get_reparse_point_kind(&metadata), Some(ReparsePointKind::Link));
+        assert_eq!(serde_json::to_string(&ReparsePointPolicy::Follow).unwrap(), "\"follow\"");
+    }
+}
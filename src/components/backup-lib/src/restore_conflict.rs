@@ -0,0 +1,220 @@
+#![allow(unused)]
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde::{Serialize, Deserialize};
+use crate::file_meta::ItemFileMeta;
+
+//恢复到已有文件的目录时,目标路径上已经存在文件的处理策略
+//RestoreConflictResolver在生成restore item时检查目标路径,按策略决定覆盖/跳过/另存,每个冲突的决定都会记录下来
+const KEEP_BOTH_SUFFIX: &str = ".restored";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreConflictPolicy {
+    Overwrite,//用备份中的文件覆盖
+    Skip,//保留已有的文件
+    KeepBoth,//保留已有的文件,备份中的文件加后缀另存
+    OnlyNewer,//备份中的文件比已有的文件新时才覆盖
+}
+
+impl Default for RestoreConflictPolicy {
+    fn default() -> Self {
+        RestoreConflictPolicy::Overwrite
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreConflictAction {
+    Overwrite,
+    Skip,
+    KeepBoth,
+}
+
+impl RestoreConflictAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestoreConflictAction::Overwrite => "overwrite",
+            RestoreConflictAction::Skip => "skip",
+            RestoreConflictAction::KeepBoth => "keep_both",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<RestoreConflictAction> {
+        match s {
+            "overwrite" => Some(RestoreConflictAction::Overwrite),
+            "skip" => Some(RestoreConflictAction::Skip),
+            "keep_both" => Some(RestoreConflictAction::KeepBoth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestoreConflictDecision {
+    pub item_id: String,
+    pub restore_item_id: Option<String>,//跳过时为None
+    pub action: RestoreConflictAction,
+    pub reason: String,
+}
+
+pub struct RestoreConflictResolver {
+    policy: RestoreConflictPolicy,
+    restore_root: PathBuf,
+    used_names: HashSet<String>,
+    mapped_names: HashMap<String, Option<String>>,//item_id -> 恢复时使用的item_id,None表示被跳过
+    decisions: Vec<RestoreConflictDecision>,
+}
+
+impl RestoreConflictResolver {
+    pub fn new(policy: RestoreConflictPolicy, restore_root: &Path) -> Self {
+        Self {
+            policy,
+            restore_root: restore_root.to_path_buf(),
+            used_names: HashSet::new(),
+            mapped_names: HashMap::new(),
+            decisions: Vec::new(),
+        }
+    }
+
+    //返回恢复时使用的item_id,None表示跳过;size/file_meta为备份时记录的文件大小和属性
+    pub fn resolve(&mut self, item_id: &str, size: u64, file_meta: Option<&ItemFileMeta>) -> Option<String> {
+        let result = self.resolve_inner(item_id, size, file_meta);
+        if let Some(restore_item_id) = result.as_ref() {
+            self.used_names.insert(restore_item_id.clone());
+        }
+        self.mapped_names.insert(item_id.to_string(), result.clone());
+        result
+    }
+
+    fn resolve_inner(&mut self, item_id: &str, size: u64, file_meta: Option<&ItemFileMeta>) -> Option<String> {
+        let existing = std::fs::symlink_metadata(self.restore_root.join(item_id));
+        if existing.is_err() {
+            return Some(item_id.to_string());
+        }
+        let existing = existing.unwrap();
+        let backup_mtime_ns = file_meta.and_then(|meta| meta.mtime_ns);
+        let existing_mtime_ns = existing.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos() as u64);
+        let is_unchanged = existing.is_file() && existing.len() == size
+            && backup_mtime_ns.is_some() && backup_mtime_ns == existing_mtime_ns;
+        let (action, reason) = if is_unchanged {
+            //大小和修改时间都和备份时一致,不需要恢复
+            (RestoreConflictAction::Skip, "unchanged".to_string())
+        } else if existing.is_dir() && self.policy != RestoreConflictPolicy::KeepBoth {
+            (RestoreConflictAction::Skip, "destination is a directory".to_string())
+        } else {
+            match self.policy {
+                RestoreConflictPolicy::Overwrite => (RestoreConflictAction::Overwrite, "policy overwrite".to_string()),
+                RestoreConflictPolicy::Skip => (RestoreConflictAction::Skip, "policy skip".to_string()),
+                RestoreConflictPolicy::KeepBoth => (RestoreConflictAction::KeepBoth, "policy keep_both".to_string()),
+                RestoreConflictPolicy::OnlyNewer => {
+                    match (backup_mtime_ns, existing_mtime_ns) {
+                        (Some(backup_mtime_ns), Some(existing_mtime_ns)) if backup_mtime_ns > existing_mtime_ns => {
+                            (RestoreConflictAction::Overwrite, format!("backup mtime {} is newer than {}", backup_mtime_ns, existing_mtime_ns))
+                        }
+                        (Some(backup_mtime_ns), Some(existing_mtime_ns)) => {
+                            (RestoreConflictAction::Skip, format!("backup mtime {} is not newer than {}", backup_mtime_ns, existing_mtime_ns))
+                        }
+                        //无法比较时不覆盖已有的文件
+                        _ => (RestoreConflictAction::Skip, "mtime unknown".to_string()),
+                    }
+                }
+            }
+        };
+
+        let restore_item_id = match action {
+            RestoreConflictAction::Overwrite => Some(item_id.to_string()),
+            RestoreConflictAction::Skip => None,
+            RestoreConflictAction::KeepBoth => Some(self.make_keep_both_name(item_id)),
+        };
+        self.decisions.push(RestoreConflictDecision {
+            item_id: item_id.to_string(),
+            restore_item_id: restore_item_id.clone(),
+            action,
+            reason,
+        });
+        restore_item_id
+    }
+
+    //已经处理过的item_id在恢复时使用的名字,被跳过或者未处理时返回None
+    pub fn get_mapped_name(&self, item_id: &str) -> Option<&String> {
+        self.mapped_names.get(item_id).and_then(|name| name.as_ref())
+    }
+
+    pub fn decisions(&self) -> &Vec<RestoreConflictDecision> {
+        &self.decisions
+    }
+
+    pub fn take_decisions(&mut self) -> Vec<RestoreConflictDecision> {
+        std::mem::take(&mut self.decisions)
+    }
+
+    //a/b.txt -> a/b.restored.txt, a/b.restored-2.txt ...
+    fn make_keep_both_name(&self, item_id: &str) -> String {
+        let (parent, file_name) = match item_id.rfind('/') {
+            Some(pos) => (&item_id[..pos + 1], &item_id[pos + 1..]),
+            None => ("", item_id),
+        };
+        let (stem, ext) = match file_name.rfind('.') {
+            Some(pos) if pos > 0 => (&file_name[..pos], &file_name[pos..]),
+            _ => (file_name, ""),
+        };
+        let mut index = 1;
+        loop {
+            let suffix = if index == 1 { KEEP_BOTH_SUFFIX.to_string() } else { format!("{}-{}", KEEP_BOTH_SUFFIX, index) };
+            let new_name = format!("{}{}{}{}", parent, stem, suffix, ext);
+            if !self.used_names.contains(&new_name) && std::fs::symlink_metadata(self.restore_root.join(&new_name)).is_err() {
+                return new_name;
+            }
+            index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_meta_with_mtime(mtime_ns: u64) -> ItemFileMeta {
+        ItemFileMeta { mtime_ns: Some(mtime_ns), ..Default::default() }
+    }
+
+    #[test]
+    fn test_conflict_policy() {
+        let restore_dir = tempfile::tempdir().unwrap();
+        std::fs::write(restore_dir.path().join("a.txt"), b"live").unwrap();
+        std::fs::write(restore_dir.path().join("a.restored.txt"), b"live").unwrap();
+
+        let mut resolver = RestoreConflictResolver::new(RestoreConflictPolicy::Overwrite, restore_dir.path());
+        assert_eq!(resolver.resolve("a.txt", 0, None), Some("a.txt".to_string()));
+        assert_eq!(resolver.resolve("new.txt", 0, None), Some("new.txt".to_string()));
+        assert_eq!(resolver.decisions().len(), 1);
+        assert_eq!(resolver.decisions()[0].action, RestoreConflictAction::Overwrite);
+
+        let mut resolver = RestoreConflictResolver::new(RestoreConflictPolicy::Skip, restore_dir.path());
+        assert_eq!(resolver.resolve("a.txt", 0, None), None);
+        assert_eq!(resolver.get_mapped_name("a.txt"), None);
+
+        let mut resolver = RestoreConflictResolver::new(RestoreConflictPolicy::KeepBoth, restore_dir.path());
+        assert_eq!(resolver.resolve("a.txt", 0, None), Some("a.restored-2.txt".to_string()));
+        assert_eq!(resolver.get_mapped_name("a.txt"), Some(&"a.restored-2.txt".to_string()));
+
+        let existing_mtime_ns = std::fs::metadata(restore_dir.path().join("a.txt")).unwrap().modified().unwrap()
+            .duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        let mut resolver = RestoreConflictResolver::new(RestoreConflictPolicy::OnlyNewer, restore_dir.path());
+        assert_eq!(resolver.resolve("a.txt", 0, Some(&file_meta_with_mtime(existing_mtime_ns + 1))), Some("a.txt".to_string()));
+        assert_eq!(resolver.resolve("a.restored.txt", 0, Some(&file_meta_with_mtime(existing_mtime_ns - 1))), None);
+        let decisions = resolver.take_decisions();
+        assert_eq!(decisions.len(), 2);
+        assert_eq!(decisions[1].action, RestoreConflictAction::Skip);
+
+        //没有变化的文件任何策略下都跳过
+        let mut resolver = RestoreConflictResolver::new(RestoreConflictPolicy::KeepBoth, restore_dir.path());
+        assert_eq!(resolver.resolve("a.txt", 4, Some(&file_meta_with_mtime(existing_mtime_ns))), None);
+        assert_eq!(resolver.decisions()[0].reason, "unchanged");
+    }
+}
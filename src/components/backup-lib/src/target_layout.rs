@@ -0,0 +1,95 @@
+//target上数据布局的版本,保存在target根目录的标记对象中(TargetLayoutMarker的json)
+//v0: 没有标记的旧target;v1: chunk对象以chunk_id命名,link使用provider自己的机制,checkpoint清单保存在engine的task db中
+//打开target时拒绝比当前程序新的版本;旧版本由upgrade逐级迁移,每升级一级写一次标记,中断后可以继续
+use serde::{Serialize, Deserialize};
+use log::*;
+use crate::provider::*;
+
+pub const TARGET_LAYOUT_VERSION: u32 = 1;
+pub const LEGACY_TARGET_LAYOUT_VERSION: u32 = 0;
+pub const TARGET_LAYOUT_MARKER_NAME: &str = "bucky_backup_layout.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetLayoutMarker {
+    pub version: u32,
+    pub chunk_naming: String,//chunk对象的命名方式
+    pub link_scheme: String,//quick_hash到chunk_id的link的保存方式
+    pub manifest_placement: String,//checkpoint清单保存的位置
+    pub update_time: u64,
+}
+
+impl TargetLayoutMarker {
+    pub fn new(version: u32, update_time: u64) -> Self {
+        Self {
+            version,
+            chunk_naming: "chunk_id".to_string(),
+            link_scheme: "provider".to_string(),
+            manifest_placement: "local_db".to_string(),
+            update_time,
+        }
+    }
+
+    pub fn from_json_str(marker: &str) -> BackupResult<Self> {
+        serde_json::from_str(marker)
+            .map_err(|e| BuckyBackupError::Failed(format!("invalid target layout marker: {}", e)))
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+//比当前程序新的布局不能读写,需要升级程序
+pub fn check_layout_version_supported(target_url: &str, version: u32) -> BackupResult<()> {
+    if version > TARGET_LAYOUT_VERSION {
+        return Err(BuckyBackupError::Failed(format!("target {} layout version {} is newer than supported version {}, upgrade backup_suite",
+            target_url, version, TARGET_LAYOUT_VERSION)));
+    }
+    Ok(())
+}
+
+pub async fn read_target_layout(target: &BackupChunkTargetProvider) -> BackupResult<Option<TargetLayoutMarker>> {
+    match target.read_layout_marker().await? {
+        Some(marker) => Ok(Some(TargetLayoutMarker::from_json_str(marker.as_str())?)),
+        None => Ok(None),
+    }
+}
+
+//没有标记的target按v0处理
+pub async fn read_target_layout_version(target: &BackupChunkTargetProvider) -> BackupResult<u32> {
+    let version = read_target_layout(target).await?
+        .map(|marker| marker.version)
+        .unwrap_or(LEGACY_TARGET_LAYOUT_VERSION);
+    check_layout_version_supported(target.get_target_url().as_str(), version)?;
+    Ok(version)
+}
+
+//把target逐级迁移到当前版本,返回(升级前的版本, 升级后的版本)
+pub async fn upgrade_target_layout(target: &BackupChunkTargetProvider, now: u64) -> BackupResult<(u32, u32)> {
+    let from_version = read_target_layout_version(target).await?;
+    let mut version = from_version;
+    while version < TARGET_LAYOUT_VERSION {
+        info!("upgrade target {} layout from version {}", target.get_target_url(), version);
+        target.upgrade_layout(version).await?;
+        version += 1;
+        target.write_layout_marker(TargetLayoutMarker::new(version, now).to_json_string().as_str()).await?;
+    }
+    Ok((from_version, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_marker() {
+        let marker = TargetLayoutMarker::new(TARGET_LAYOUT_VERSION, 1000);
+        let loaded = TargetLayoutMarker::from_json_str(marker.to_json_string().as_str()).unwrap();
+        assert_eq!(loaded, marker);
+        assert!(TargetLayoutMarker::from_json_str("{}").is_err());
+
+        assert!(check_layout_version_supported("file:///backup", LEGACY_TARGET_LAYOUT_VERSION).is_ok());
+        assert!(check_layout_version_supported("file:///backup", TARGET_LAYOUT_VERSION).is_ok());
+        assert!(check_layout_version_supported("file:///backup", TARGET_LAYOUT_VERSION + 1).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+#![allow(unused)]
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Sleep;
+use url::Url;
+use ndn_lib::{ChunkReader, ChunkWriter, ChunkId};
+use anyhow::Result;
+use crate::provider::*;
+
+//target url中指定同时请求数上限的参数,比如 s3://bucket?region=xx&max_connections=4
+pub const TARGET_MAX_CONNECTIONS_PARAM: &str = "max_connections";
+pub const DEFAULT_TARGET_MAX_CONNECTIONS: usize = 8;
+
+pub fn get_max_connections_from_url(url: &str) -> Option<usize> {
+    let url = Url::parse(url).ok()?;
+    let max_connections = url.query_pairs()
+        .find(|(k, _)| k == TARGET_MAX_CONNECTIONS_PARAM)
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .filter(|max_connections| *max_connections > 0);
+    max_connections
+}
+
+//同一个target的url可能带有不同的参数(比如凭证),并发限制只按scheme/host/port/path区分
+pub fn get_target_limit_key(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.set_query(None);
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+//target上的请求分两个优先级:恢复(读取chunk)优先于备份(其它请求)
+//有恢复请求在等待时,释放的连接只分配给恢复请求;有恢复请求在读取时,备份的写入每次写之前让出一段时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetIoLane {
+    Restore,
+    Backup,
+}
+
+//恢复正在读取时,备份写入每次写之前等待的时间
+const BACKUP_YIELD_DURATION: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+struct LimiterState {
+    available: usize,
+    restore_waiting: usize,
+    restore_active: usize,
+}
+
+//带优先级的连接数限制,由调用方按target共享(所有task/plan共用)
+#[derive(Debug)]
+pub struct TargetConnectionLimiter {
+    max_connections: usize,
+    state: Mutex<LimiterState>,
+    notify: Notify,
+}
+
+impl TargetConnectionLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            max_connections,
+            state: Mutex::new(LimiterState { available: max_connections, restore_waiting: 0, restore_active: 0 }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn available(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    pub fn is_restore_active(&self) -> bool {
+        self.state.lock().unwrap().restore_active > 0
+    }
+
+    pub async fn acquire(self: &Arc<Self>, lane: TargetIoLane) -> TargetConnectionPermit {
+        let mut restore_wait_guard: Option<RestoreWaitGuard> = None;
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.available > 0 && (lane == TargetIoLane::Restore || state.restore_waiting == 0) {
+                    state.available -= 1;
+                    if lane == TargetIoLane::Restore {
+                        state.restore_active += 1;
+                    }
+                    if let Some(mut guard) = restore_wait_guard.take() {
+                        state.restore_waiting -= 1;
+                        guard.is_counted = false;
+                    }
+                    return TargetConnectionPermit { limiter: self.clone(), lane };
+                }
+                if lane == TargetIoLane::Restore && restore_wait_guard.is_none() {
+                    state.restore_waiting += 1;
+                    restore_wait_guard = Some(RestoreWaitGuard { limiter: self.clone(), is_counted: true });
+                }
+                //在释放锁之前注册,避免错过释放时的通知
+                notified.as_mut().enable();
+            }
+            notified.await;
+        }
+    }
+}
+
+//等待中的恢复请求被取消时,不能一直阻止备份请求获取连接
+struct RestoreWaitGuard {
+    limiter: Arc<TargetConnectionLimiter>,
+    is_counted: bool,
+}
+
+impl Drop for RestoreWaitGuard {
+    fn drop(&mut self) {
+        if self.is_counted {
+            self.limiter.state.lock().unwrap().restore_waiting -= 1;
+            self.limiter.notify.notify_waiters();
+        }
+    }
+}
+
+pub struct TargetConnectionPermit {
+    limiter: Arc<TargetConnectionLimiter>,
+    lane: TargetIoLane,
+}
+
+impl Drop for TargetConnectionPermit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.available += 1;
+        if self.lane == TargetIoLane::Restore {
+            state.restore_active -= 1;
+        }
+        drop(state);
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+//包装一个target provider,限制对target的同时请求数
+//reader/writer在整个生命周期内占用一个连接(对应一个上传/下载连接),其它操作只在请求期间占用
+pub struct LimitedChunkTarget {
+    inner: BackupChunkTargetProvider,
+    limiter: Arc<TargetConnectionLimiter>,
+}
+
+impl LimitedChunkTarget {
+    pub fn new(inner: BackupChunkTargetProvider, limiter: Arc<TargetConnectionLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl IBackupChunkTargetProvider for LimitedChunkTarget {
+    async fn get_target_info(&self) -> Result<String> {
+        self.inner.get_target_info().await
+    }
+
+    fn get_target_url(&self) -> String {
+        self.inner.get_target_url()
+    }
+
+    async fn get_account_session_info(&self) -> Result<String> {
+        self.inner.get_account_session_info().await
+    }
+
+    async fn set_account_session_info(&self, session_info: &str) -> Result<()> {
+        self.inner.set_account_session_info(session_info).await
+    }
+
+    async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.is_chunk_exist(chunk_id).await
+    }
+
+    //一次批量查询只占用一个连接
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId]) -> Result<Vec<(bool, u64)>> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.is_chunk_exist_batch(chunk_ids).await
+    }
+
+    async fn open_chunk_writer(&self, chunk_id: &ChunkId, offset: u64, size: u64) -> BackupResult<(ChunkWriter, u64)> {
+        let permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        let (writer, init_offset) = self.inner.open_chunk_writer(chunk_id, offset, size).await?;
+        Ok((Box::pin(LimitedChunkWriter {
+            inner: writer,
+            limiter: self.limiter.clone(),
+            yield_sleep: None,
+            _permit: permit,
+        }), init_offset))
+    }
+
+    async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.complete_chunk_writer(chunk_id).await
+    }
+
+    async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.link_chunkid(source_chunk_id, new_chunk_id).await
+    }
+
+    async fn query_link_target(&self, source_chunk_id: &ChunkId) -> BackupResult<Option<ChunkId>> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.query_link_target(source_chunk_id).await
+    }
+
+    async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset: u64) -> BackupResult<ChunkReader> {
+        let permit = self.limiter.acquire(TargetIoLane::Restore).await;
+        let reader = self.inner.open_chunk_reader_for_restore(chunk_id, offset).await?;
+        Ok(Box::pin(LimitedChunkReader { inner: reader, _permit: permit }))
+    }
+
+    async fn remove_chunk(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let _permit = self.limiter.acquire(TargetIoLane::Backup).await;
+        self.inner.remove_chunk(chunk_id).await
+    }
+
+    async fn read_layout_marker(&self) -> BackupResult<Option<String>> {
+        self.inner.read_layout_marker().await
+    }
+
+    async fn write_layout_marker(&self, marker: &str) -> BackupResult<()> {
+        self.inner.write_layout_marker(marker).await
+    }
+
+    async fn upgrade_layout(&self, from_version: u32) -> BackupResult<()> {
+        self.inner.upgrade_layout(from_version).await
+    }
+}
+
+struct LimitedChunkWriter {
+    inner: ChunkWriter,
+    limiter: Arc<TargetConnectionLimiter>,
+    yield_sleep: Option<Pin<Box<Sleep>>>,
+    _permit: TargetConnectionPermit,
+}
+
+impl AsyncWrite for LimitedChunkWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.yield_sleep.is_none() && this.limiter.is_restore_active() {
+            this.yield_sleep = Some(Box::pin(tokio::time::sleep(BACKUP_YIELD_DURATION)));
+        }
+        if let Some(yield_sleep) = this.yield_sleep.as_mut() {
+            if yield_sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.yield_sleep = None;
+        }
+        this.inner.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+struct LimitedChunkReader {
+    inner: ChunkReader,
+    _permit: TargetConnectionPermit,
+}
+
+impl AsyncRead for LimitedChunkReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_limit_url() {
+        assert_eq!(get_max_connections_from_url("s3://bucket?region=us-east-1&max_connections=4"), Some(4));
+        assert_eq!(get_max_connections_from_url("s3://bucket?max_connections=0"), None);
+        assert_eq!(get_max_connections_from_url("file:///backup"), None);
+        assert_eq!(get_target_limit_key("s3://bucket?credential_id=a&max_connections=4"),
+            get_target_limit_key("s3://bucket?credential_id=b"));
+        assert_ne!(get_target_limit_key("file:///backup/a"), get_target_limit_key("file:///backup/b"));
+    }
+
+    #[tokio::test]
+    async fn test_restore_lane_first() {
+        let limiter = Arc::new(TargetConnectionLimiter::new(1));
+        let backup_permit = limiter.acquire(TargetIoLane::Backup).await;
+        assert_eq!(limiter.available(), 0);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let backup_waiter = {
+            let (limiter, sender) = (limiter.clone(), sender.clone());
+            tokio::spawn(async move {
+                let permit = limiter.acquire(TargetIoLane::Backup).await;
+                sender.send(TargetIoLane::Backup).unwrap();
+                drop(permit);
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let restore_waiter = {
+            let (limiter, sender) = (limiter.clone(), sender.clone());
+            tokio::spawn(async move {
+                let permit = limiter.acquire(TargetIoLane::Restore).await;
+                sender.send(TargetIoLane::Restore).unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                drop(permit);
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        //先等待的备份请求排在后面
+        drop(backup_permit);
+        assert_eq!(receiver.recv().await, Some(TargetIoLane::Restore));
+        assert!(limiter.is_restore_active());
+        assert_eq!(receiver.recv().await, Some(TargetIoLane::Backup));
+        restore_waiter.await.unwrap();
+        backup_waiter.await.unwrap();
+        assert_eq!(limiter.available(), 1);
+        assert!(!limiter.is_restore_active());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_restore_waiter() {
+        let limiter = Arc::new(TargetConnectionLimiter::new(1));
+        let permit = limiter.acquire(TargetIoLane::Backup).await;
+        let result = tokio::time::timeout(Duration::from_millis(20), limiter.acquire(TargetIoLane::Restore)).await;
+        assert!(result.is_err());
+        drop(permit);
+        //取消的恢复请求不再阻止备份请求
+        let result = tokio::time::timeout(Duration::from_millis(100), limiter.acquire(TargetIoLane::Backup)).await;
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+#![allow(unused)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use ndn_lib::{ChunkReader, ChunkWriter, ChunkId};
+use anyhow::Result;
+use crate::provider::*;
+
+//每种操作只保留最近的这么多次延迟用于计算分位数
+const MAX_LATENCY_SAMPLES: usize = 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetOpStats {
+    pub count: u64,
+    pub error_count: u64,
+    pub total_latency_us: u64,
+    pub latencies_us: VecDeque<u64>,
+}
+
+impl TargetOpStats {
+    pub fn record(&mut self, latency_us: u64, is_error: bool) {
+        self.count += 1;
+        if is_error {
+            self.error_count += 1;
+        }
+        self.total_latency_us += latency_us;
+        if self.latencies_us.len() >= MAX_LATENCY_SAMPLES {
+            self.latencies_us.pop_front();
+        }
+        self.latencies_us.push_back(latency_us);
+    }
+
+    //percent取值0-100
+    pub fn percentile_us(&self, percent: u64) -> u64 {
+        if self.latencies_us.is_empty() {
+            return 0;
+        }
+        let mut sorted: Vec<u64> = self.latencies_us.iter().cloned().collect();
+        sorted.sort();
+        let index = ((sorted.len() - 1) as u64 * percent.min(100) / 100) as usize;
+        sorted[index]
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let avg_us = if self.count > 0 { self.total_latency_us / self.count } else { 0 };
+        let error_rate = if self.count > 0 { self.error_count as f64 / self.count as f64 } else { 0.0 };
+        json!({
+            "count": self.count,
+            "error_count": self.error_count,
+            "error_rate": error_rate,
+            "avg_us": avg_us,
+            "p50_us": self.percentile_us(50),
+            "p90_us": self.percentile_us(90),
+            "p99_us": self.percentile_us(99),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetStats {
+    pub ops: HashMap<String, TargetOpStats>,
+}
+
+impl TargetStats {
+    pub fn record(&mut self, op: &str, latency_us: u64, is_error: bool) {
+        self.ops.entry(op.to_string()).or_default().record(latency_us, is_error);
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let ops: serde_json::Map<String, Value> = self.ops.iter()
+            .map(|(op, stats)| (op.clone(), stats.to_json_value()))
+            .collect();
+        Value::Object(ops)
+    }
+}
+
+pub type SharedTargetStats = Arc<Mutex<TargetStats>>;
+
+//包装一个target provider,记录每种操作的请求数/错误数/延迟
+//同一个target会被多次创建provider,所以统计数据由调用方按target url共享
+pub struct StatsChunkTarget {
+    inner: BackupChunkTargetProvider,
+    stats: SharedTargetStats,
+}
+
+impl StatsChunkTarget {
+    pub fn new(inner: BackupChunkTargetProvider, stats: SharedTargetStats) -> Self {
+        Self { inner, stats }
+    }
+
+    fn record<T, E>(&self, op: &str, start: Instant, result: &std::result::Result<T, E>) {
+        let latency_us = start.elapsed().as_micros() as u64;
+        self.stats.lock().unwrap().record(op, latency_us, result.is_err());
+    }
+}
+
+#[async_trait]
+impl IBackupChunkTargetProvider for StatsChunkTarget {
+    async fn get_target_info(&self) -> Result<String> {
+        self.inner.get_target_info().await
+    }
+
+    fn get_target_url(&self) -> String {
+        self.inner.get_target_url()
+    }
+
+    async fn get_account_session_info(&self) -> Result<String> {
+        self.inner.get_account_session_info().await
+    }
+
+    async fn set_account_session_info(&self, session_info: &str) -> Result<()> {
+        self.inner.set_account_session_info(session_info).await
+    }
+
+    async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
+        let start = Instant::now();
+        let result = self.inner.is_chunk_exist(chunk_id).await;
+        self.record("is_chunk_exist", start, &result);
+        result
+    }
+
+    async fn is_chunk_exist_batch(&self, chunk_ids: &[ChunkId]) -> Result<Vec<(bool, u64)>> {
+        let start = Instant::now();
+        let result = self.inner.is_chunk_exist_batch(chunk_ids).await;
+        self.record("is_chunk_exist_batch", start, &result);
+        result
+    }
+
+    async fn open_chunk_writer(&self, chunk_id: &ChunkId, offset: u64, size: u64) -> BackupResult<(ChunkWriter, u64)> {
+        let start = Instant::now();
+        let result = self.inner.open_chunk_writer(chunk_id, offset, size).await;
+        //AlreadyDone不是错误
+        let is_error = matches!(result, Err(ref e) if !matches!(e, BuckyBackupError::AlreadyDone(_)));
+        self.stats.lock().unwrap().record("open_chunk_writer", start.elapsed().as_micros() as u64, is_error);
+        result
+    }
+
+    async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let start = Instant::now();
+        let result = self.inner.complete_chunk_writer(chunk_id).await;
+        self.record("complete_chunk_writer", start, &result);
+        result
+    }
+
+    async fn link_chunkid(&self, source_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
+        let start = Instant::now();
+        let result = self.inner.link_chunkid(source_chunk_id, new_chunk_id).await;
+        self.record("link_chunkid", start, &result);
+        result
+    }
+
+    async fn query_link_target(&self, source_chunk_id: &ChunkId) -> BackupResult<Option<ChunkId>> {
+        let start = Instant::now();
+        let result = self.inner.query_link_target(source_chunk_id).await;
+        self.record("query_link_target", start, &result);
+        result
+    }
+
+    async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset: u64) -> BackupResult<ChunkReader> {
+        let start = Instant::now();
+        let result = self.inner.open_chunk_reader_for_restore(chunk_id, offset).await;
+        self.record("open_chunk_reader", start, &result);
+        result
+    }
+
+    async fn remove_chunk(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let start = Instant::now();
+        let result = self.inner.remove_chunk(chunk_id).await;
+        self.record("remove_chunk", start, &result);
+        result
+    }
+
+    async fn read_layout_marker(&self) -> BackupResult<Option<String>> {
+        self.inner.read_layout_marker().await
+    }
+
+    async fn write_layout_marker(&self, marker: &str) -> BackupResult<()> {
+        self.inner.write_layout_marker(marker).await
+    }
+
+    async fn upgrade_layout(&self, from_version: u32) -> BackupResult<()> {
+        self.inner.upgrade_layout(from_version).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_op_stats() {
+        let mut stats = TargetStats::default();
+        for i in 1..=100 {
+            stats.record("is_chunk_exist", i, i % 10 == 0);
+        }
+        let op_stats = stats.ops.get("is_chunk_exist").unwrap();
+        assert_eq!(op_stats.count, 100);
+        assert_eq!(op_stats.error_count, 10);
+        assert_eq!(op_stats.percentile_us(50), 50);
+        assert_eq!(op_stats.percentile_us(99), 99);
+        assert_eq!(op_stats.percentile_us(100), 100);
+
+        for _ in 0..MAX_LATENCY_SAMPLES {
+            stats.record("is_chunk_exist", 1, false);
+        }
+        let op_stats = stats.ops.get("is_chunk_exist").unwrap();
+        assert_eq!(op_stats.latencies_us.len(), MAX_LATENCY_SAMPLES);
+        assert_eq!(op_stats.percentile_us(99), 1);
+        assert_eq!(stats.to_json_value()["is_chunk_exist"]["count"], 100 + MAX_LATENCY_SAMPLES as u64);
+    }
+}
@@ -34,6 +34,8 @@ pub trait DmcChainClient: 'static + Clone + Send + Sync {
     type EventListener: DmcEventListener;
     async fn get_bill_by_id(&self, bill_id: u64) -> DmcResult<Option<DmcBill>>;
     async fn get_order_by_id(&self, order_id: u64) -> DmcResult<Option<DmcOrder>>;
+    // 订单报价查询, 返回满足filter的有效bill
+    async fn get_bills(&self, filter: DmcBillFilter) -> DmcResult<Vec<DmcBill>>;
 
     async fn get_apply_method(&self, account: &str) -> DmcResult<Option<String>>;
     async fn event_listener(&self, start_block: Option<u64>) -> Self::EventListener;
@@ -1,5 +1,7 @@
 mod types;
 mod client; 
+mod pricing;
 
 pub use types::*;
-pub use client::*;
\ No newline at end of file
+pub use client::*;
+pub use pricing::*;
\ No newline at end of file
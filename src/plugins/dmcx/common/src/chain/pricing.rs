@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::error::*;
+use super::types::*;
+
+// 没有挑战记录的miner的信誉分
+pub const NEW_MINER_REPUTATION: f64 = 0.5;
+
+// 查询链上的bill(miner的报价), asset以GB为单位
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DmcBillFilter {
+    pub min_asset: u64,
+    pub max_price: Option<u64>,
+}
+
+impl DmcBillFilter {
+    pub fn matches(&self, bill: &DmcBill) -> bool {
+        bill.asset >= self.min_asset && self.max_price.map_or(true, |max_price| bill.price <= max_price)
+    }
+}
+
+// 每个plan的下单策略: 单价上限, 一个订单的总价上限和miner的最低信誉分
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct DmcOrderPolicy {
+    pub max_price: Option<u64>,
+    pub budget: Option<u64>,
+    pub min_reputation: Option<f64>,
+}
+
+// miner的链上挑战记录, 由调用者从journal/链上事件中统计
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DmcMinerReputation {
+    pub miner: String,
+    pub prooved_challenges: u64,
+    pub failed_challenges: u64,
+}
+
+impl DmcMinerReputation {
+    pub fn score(&self) -> f64 {
+        let total = self.prooved_challenges + self.failed_challenges;
+        if total == 0 {
+            NEW_MINER_REPUTATION
+        } else {
+            self.prooved_challenges as f64 / total as f64
+        }
+    }
+}
+
+// 选中的订单条款, 下单前记录到source(checkpoint的数据)上
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DmcOrderTerms {
+    pub bill_id: u64,
+    pub miner: String,
+    pub asset: u64,
+    pub duration: u32,
+    pub price: u64,
+    pub pledge_rate: u32,
+    pub total_cost: u64,
+    pub reputation: f64,
+}
+
+impl DmcOrderTerms {
+    pub fn order_options(&self) -> DmcOrderOptions {
+        DmcOrderOptions {
+            bill_id: self.bill_id,
+            asset: self.asset,
+            duration: self.duration
+        }
+    }
+}
+
+// 存放length字节需要的asset, 按bill的min_asset向上取整
+pub fn dmc_asset_of_length(length: u64, min_asset: u64) -> u64 {
+    let gb = 1024 * 1024 * 1024;
+    let asset = u64::max((length + gb - 1) / gb, 1);
+    if min_asset > 1 {
+        (asset + min_asset - 1) / min_asset * min_asset
+    } else {
+        asset
+    }
+}
+
+pub fn dmc_order_cost(price: u64, asset: u64, duration: u32) -> u64 {
+    price.saturating_mul(asset).saturating_mul(duration as u64)
+}
+
+// 在满足容量, 单价, 预算和信誉要求的bill中选择: 按单价除以信誉分排序, 信誉低的miner需要更低的价格才会被选中
+pub fn select_dmc_bill(bills: &[DmcBill], length: u64, duration: u32, policy: &DmcOrderPolicy, reputations: &HashMap<String, DmcMinerReputation>) -> DmcResult<DmcOrderTerms> {
+    let mut candidates = vec![];
+    for bill in bills {
+        let asset = dmc_asset_of_length(length, bill.min_asset);
+        if bill.asset < asset || policy.max_price.map_or(false, |max_price| bill.price > max_price) {
+            continue;
+        }
+        let total_cost = dmc_order_cost(bill.price, asset, duration);
+        if policy.budget.map_or(false, |budget| total_cost > budget) {
+            continue;
+        }
+        let reputation = reputations.get(&bill.miner).map_or(NEW_MINER_REPUTATION, |reputation| reputation.score());
+        if policy.min_reputation.map_or(false, |min_reputation| reputation < min_reputation) {
+            continue;
+        }
+        candidates.push(DmcOrderTerms {
+            bill_id: bill.bill_id,
+            miner: bill.miner.clone(),
+            asset,
+            duration,
+            price: bill.price,
+            pledge_rate: bill.pledge_rate,
+            total_cost,
+            reputation
+        });
+    }
+
+    let effective_price = |terms: &DmcOrderTerms| terms.price as f64 / f64::max(terms.reputation, 0.01);
+    candidates.into_iter()
+        .min_by(|l, r| effective_price(l).partial_cmp(&effective_price(r)).unwrap()
+            .then(l.total_cost.cmp(&r.total_cost))
+            .then(l.bill_id.cmp(&r.bill_id)))
+        .ok_or_else(|| DmcError::new(DmcErrorCode::NotFound, format!("no bill matches {} bytes for {} with policy {:?}", length, duration, policy)))
+}
+
+#[test]
+fn test_select_dmc_bill() {
+    let bill = |bill_id: u64, miner: &str, asset: u64, price: u64| DmcBill {
+        bill_id,
+        miner: miner.to_owned(),
+        asset,
+        price,
+        pledge_rate: 0,
+        min_asset: 1,
+        expire_at: 0
+    };
+    let bills = vec![bill(1, "cheap", 100, 10), bill(2, "small", 1, 5), bill(3, "trusted", 100, 12), bill(4, "pricy", 100, 50)];
+    let mut reputations = HashMap::new();
+    reputations.insert("cheap".to_owned(), DmcMinerReputation { miner: "cheap".to_owned(), prooved_challenges: 1, failed_challenges: 3 });
+    reputations.insert("trusted".to_owned(), DmcMinerReputation { miner: "trusted".to_owned(), prooved_challenges: 10, failed_challenges: 0 });
+
+    let length = 3 * 1024 * 1024 * 1024 - 1;
+    assert_eq!(dmc_asset_of_length(length, 1), 3);
+    assert_eq!(dmc_asset_of_length(length, 2), 4);
+    assert_eq!(dmc_asset_of_length(0, 1), 1);
+
+    // small容量不够, cheap信誉太低, 选择trusted
+    let terms = select_dmc_bill(&bills, length, 4, &DmcOrderPolicy::default(), &reputations).unwrap();
+    assert_eq!(terms.bill_id, 3);
+    assert_eq!(terms.total_cost, 12 * 3 * 4);
+    assert_eq!(terms.order_options().asset, 3);
+
+    let policy = DmcOrderPolicy { max_price: Some(11), ..Default::default() };
+    assert_eq!(select_dmc_bill(&bills, length, 4, &policy, &reputations).unwrap().bill_id, 1);
+    let policy = DmcOrderPolicy { max_price: Some(11), min_reputation: Some(0.6), ..Default::default() };
+    assert!(select_dmc_bill(&bills, length, 4, &policy, &reputations).is_err());
+    let policy = DmcOrderPolicy { budget: Some(12 * 3 * 4 - 1), ..Default::default() };
+    assert_eq!(select_dmc_bill(&bills, length, 4, &policy, &reputations).unwrap().bill_id, 1);
+}
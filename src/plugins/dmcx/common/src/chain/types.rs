@@ -88,10 +88,17 @@ pub struct DmcOrder {
     pub start_at: u128
 }
 
+// order的duration以周为单位, start_at为秒
+pub const DMC_ORDER_DURATION_UNIT_SECS: u64 = 7 * 24 * 3600;
+
 impl DmcOrder {
     pub fn capacity(&self) -> u64 {
         self.asset * 1024 * 1024 * 1024
     }
+
+    pub fn expire_at(&self) -> u64 {
+        self.start_at as u64 + self.duration as u64 * DMC_ORDER_DURATION_UNIT_SECS
+    }
 }
 
 
@@ -1,12 +1,15 @@
 use std::{
     pin::Pin,
+    ops::Range,
     task::{Poll, Context}
 };
 use async_std::io::prelude::*;
+use serde::{Serialize, Deserialize};
 use rs_merkle::*;
 use crate::{
     error::*,
     utils::*, 
+    chain::*,
 };
 
 // peddging to full tree
@@ -98,6 +101,15 @@ pub struct MerkleProc {
 }
 
 
+// piece到root的merkle路径: block内的路径在前, block root到root的路径在后;
+// pedding之后leaves和pieces_per_block都是2的幂, 拼接后就是整棵树上的路径, 只用链上的DmcMerkleStub就可以验证
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MerklePieceProof {
+    pub piece_index: u64, 
+    pub piece_content: DmcData, 
+    pub pathes: Vec<HashValue>
+}
+
 // #[derive(Clone)]
 // pub struct MerkleStubChallenge<H: Hasher> {
 //     pub piece_index: u64, 
@@ -256,6 +268,60 @@ impl MerkleProc {
         assert!(piece_index < self.leaves);
         (piece_index / self.pieces_per_block as u64) as usize
     }
+
+    // 连续piece的证明, 同一个block中的piece只计算一次block的树; block_roots是prepare时保存的所有block root
+    pub async fn proof_of_pieces<R: async_std::io::Read + async_std::io::Seek + Unpin, H: Hasher<Hash = HashValue>>(&self, pieces: Range<u64>, block_roots: &[HashValue], reader: &mut R) -> DmcResult<Vec<MerklePieceProof>> {
+        if pieces.start >= pieces.end || pieces.end > self.leaves {
+            return Err(DmcError::new(DmcErrorCode::InvalidParam, format!("invalid pieces {:?}, leaves {}", pieces, self.leaves)));
+        }
+        if self.blocks() == 0 || block_roots.len() != self.blocks() {
+            return Err(DmcError::new(DmcErrorCode::InvalidData, format!("expect {} block roots, got {}", self.blocks(), block_roots.len())));
+        }
+
+        let mut roots_tree = MerkleTree::<H>::new();
+        roots_tree.append(&mut block_roots.to_vec()).commit();
+
+        use async_std::io::SeekFrom;
+        let mut proofs = vec![];
+        let mut piece_index = pieces.start;
+        while piece_index < pieces.end {
+            let block_index = self.block_index_of_piece(piece_index);
+            let mut block_tree = self.block_path_tree::<_, H>(block_index, reader).await?;
+            block_tree.commit();
+            if block_tree.root() != Some(block_roots[block_index]) {
+                return Err(DmcError::new(DmcErrorCode::InvalidData, format!("block {} root mismatch, source changed after prepare", block_index)));
+            }
+            let upper_part = roots_tree.proof(&[block_index]);
+
+            let block_end = u64::min((block_index as u64 + 1) * self.pieces_per_block as u64, pieces.end);
+            for index in piece_index..block_end {
+                let index_in_block = (index - block_index as u64 * self.pieces_per_block as u64) as usize;
+                let lower_part = block_tree.proof(&[index_in_block]);
+
+                let mut piece_content = vec![0u8; self.piece_size as usize];
+                reader.seek(SeekFrom::Start(index * self.piece_size as u64)).await?;
+                reader.read_exact(piece_content.as_mut_slice()).await?;
+
+                proofs.push(MerklePieceProof {
+                    piece_index: index, 
+                    piece_content: DmcData::from(piece_content), 
+                    pathes: [lower_part.proof_hashes(), upper_part.proof_hashes()].concat()
+                });
+            }
+            piece_index = block_end;
+        }
+        Ok(proofs)
+    }
+
+    // 验证方用verifier(stub.leaves, stub.piece_size, ..)创建, root来自链上的order
+    pub fn verify_piece_proof<H: Hasher<Hash = HashValue>>(&self, root: &HashValue, proof: &MerklePieceProof) -> bool {
+        if proof.piece_index >= self.leaves || proof.piece_content.as_slice().len() != self.piece_size as usize {
+            return false;
+        }
+        let leaf = H::hash(proof.piece_content.as_slice());
+        MerkleProof::<H>::new(proof.pathes.clone())
+            .verify(root.clone(), &[proof.piece_index as usize], &[leaf], self.leaves as usize)
+    }
 }
 
 //     pub fn challenge_of_piece<H: Hasher>(&self, piece_index: u64, block_roots: Vec<H::Hash>) -> MerkleStubChallenge<H> {
@@ -385,6 +451,46 @@ impl Hasher for MerkleStubSha256 {
     }
 }
 
+#[async_std::test]
+async fn test_merkle_piece_proof() {
+    use async_std::io::Cursor;
+    let buffer: Vec<u8> = (0..1000 * 1024 + 100).map(|i| (i % 251) as u8).collect();
+    let proc = MerkleProc::new(buffer.len() as u64, 1024, 16, true);
+    assert_eq!(proc.leaves(), 1024);
+    assert_eq!(proc.blocks(), 64);
+
+    let mut block_roots = vec![];
+    let mut reader = proc.wrap_reader(Cursor::new(buffer.as_slice()));
+    for i in 0..proc.blocks() {
+        block_roots.push(proc.calc_block_path::<_, MerkleStubSha256>(i, &mut reader).await.unwrap());
+    }
+    let root = proc.calc_root_from_block_path::<MerkleStubSha256>(block_roots.clone()).unwrap();
+    let full_root = proc.calc_root_from_pieces::<_, MerkleStubSha256>(&mut proc.wrap_reader(Cursor::new(buffer.as_slice()))).await.unwrap();
+    assert_eq!(root, full_root);
+
+    let verifier = MerkleProc::verifier(proc.leaves(), proc.piece_size(), 16);
+    // 跨block的piece和pedding的piece
+    for pieces in [30..34, 999..1003] {
+        let proofs = proc.proof_of_pieces::<_, MerkleStubSha256>(pieces.clone(), &block_roots, &mut reader).await.unwrap();
+        assert_eq!(proofs.len(), (pieces.end - pieces.start) as usize);
+        for proof in proofs.iter() {
+            assert_eq!(proof.pathes.len(), 10);
+            assert!(verifier.verify_piece_proof::<MerkleStubSha256>(&root, proof));
+        }
+    }
+
+    let mut proof = proc.proof_of_pieces::<_, MerkleStubSha256>(5..6, &block_roots, &mut reader).await.unwrap().remove(0);
+    assert_eq!(proof.piece_content.as_slice(), &buffer[5 * 1024..6 * 1024]);
+    proof.piece_index = 6;
+    assert!(!verifier.verify_piece_proof::<MerkleStubSha256>(&root, &proof));
+    proof.piece_index = 5;
+    let mut content: Vec<u8> = proof.piece_content.clone().into();
+    content[0] ^= 1;
+    proof.piece_content = DmcData::from(content);
+    assert!(!verifier.verify_piece_proof::<MerkleStubSha256>(&root, &proof));
+    assert!(proc.proof_of_pieces::<_, MerkleStubSha256>(1020..1025, &block_roots, &mut reader).await.is_err());
+}
+
 // #[async_std::test]
 // async fn test_merkle_path_stub() {
 //     fn random_mem(piece: usize, count: usize) -> (usize, Vec<u8>) {
@@ -14,6 +14,9 @@ pub enum JournalEventType {
     OrderPrepared = 32,
     OrderStored = 33, 
     OrderCanceled = 34, 
+    OrderTermsSelected = 35, 
+    OrderRenewed = 36, 
+    OrderRenewFailed = 37, 
     OrderRefused = 39, 
 
     OffchainChallengeOk = 50, 
@@ -34,6 +37,9 @@ impl Display for JournalEventType {
             JournalEventType::OrderPrepared => write!(f, "OrderPrepared"),
             JournalEventType::OrderStored => write!(f, "OrderStored"),
             JournalEventType::OrderCanceled => write!(f, "OrderCanceled"),
+            JournalEventType::OrderTermsSelected => write!(f, "OrderTermsSelected"),
+            JournalEventType::OrderRenewed => write!(f, "OrderRenewed"),
+            JournalEventType::OrderRenewFailed => write!(f, "OrderRenewFailed"),
             JournalEventType::OrderRefused => write!(f, "OrderRefused"),
             JournalEventType::OffchainChallengeOk => write!(f, "OffchainChallengeOk"),
             JournalEventType::OffchainChallengeFailed => write!(f, "OffchainChallengeFailed"),
@@ -87,6 +93,9 @@ impl TryFrom<u16> for JournalEventType {
             32 => Ok(Self::OrderPrepared),
             33 => Ok(Self::OrderStored), 
             34 => Ok(Self::OrderCanceled), 
+            35 => Ok(Self::OrderTermsSelected), 
+            36 => Ok(Self::OrderRenewed), 
+            37 => Ok(Self::OrderRenewFailed), 
             39 => Ok(Self::OrderRefused),  
 
             50 => Ok(Self::OffchainChallengeOk),
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 mod journal;
 mod source;
+mod order;
 
 pub use journal::*;
 pub use source::*;
+pub use order::*;
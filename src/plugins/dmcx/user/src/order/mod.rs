@@ -0,0 +1,5 @@
+mod terms;
+mod renewal;
+
+pub use terms::*;
+pub use renewal::*;
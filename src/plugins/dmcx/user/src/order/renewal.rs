@@ -0,0 +1,271 @@
+use log::*;
+use std::{
+    collections::{HashMap, HashSet}, sync::Arc, time::Duration
+};
+use async_std::{sync::RwLock, task};
+use serde::{Serialize, Deserialize};
+use dmc_tools_common::*;
+use crate::{
+    journal::*,
+    source::*
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OrderRenewalOptions {
+    pub lead_time: u64,             // 到期前多少秒开始续约
+    pub check_interval: u64,        // 检查间隔, 秒
+    pub duration: u32,              // 续约的时长, 同DmcOrder::duration
+    pub policy: DmcOrderPolicy,
+}
+
+impl Default for OrderRenewalOptions {
+    fn default() -> Self {
+        Self {
+            lead_time: 7 * 24 * 3600,
+            check_interval: 3600,
+            duration: 24,
+            policy: DmcOrderPolicy::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderRenewalState {
+    Live,
+    Due,
+    Expired,
+}
+
+pub fn order_renewal_state(expire_at: u64, now: u64, lead_time: u64) -> OrderRenewalState {
+    if now >= expire_at {
+        OrderRenewalState::Expired
+    } else if now + lead_time >= expire_at {
+        OrderRenewalState::Due
+    } else {
+        OrderRenewalState::Live
+    }
+}
+
+// OrderRenewed的参数, 事件的order_id是新的order; 只在原来的miner续约, 数据已经在miner上, 不需要重新上传
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OrderRenewedParams {
+    pub old_order_id: u64,
+    pub terms: DmcOrderTerms,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OrderRenewFailedParams {
+    pub expire_at: u64,
+    pub error: String,
+}
+
+pub trait OrderRenewalSourceFilter: Send + Sync {
+    // source对应的checkpoint还存在时才续约, 已经删除的checkpoint的order到期后自然释放
+    fn is_source_live(&self, source_id: u64) -> bool;
+}
+
+struct JobImpl<C: DmcChainAccountClient> {
+    client: C,
+    journal: JournalServer,
+    source_server: SourceServer,
+    source_filter: Box<dyn OrderRenewalSourceFilter>,
+    options: OrderRenewalOptions,
+    reputations: RwLock<HashMap<String, DmcMinerReputation>>,
+}
+
+// 后台检查所有live source的当前order, 到期前lead_time内在原来的miner续约;
+// 原来的miner没有合适的报价时需要把数据重新上传到其它miner, 这里不支持, 记录OrderRenewFailed由用户处理;
+// 续约失败时记录OrderRenewFailed并报错, 下次检查时重试, 直到order过期
+#[derive(Clone)]
+pub struct OrderRenewalJob<C: DmcChainAccountClient>(Arc<JobImpl<C>>);
+
+impl<C: DmcChainAccountClient> std::fmt::Display for OrderRenewalJob<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OrderRenewalJob {{account={}}}", self.0.client.account())
+    }
+}
+
+impl<C: DmcChainAccountClient> OrderRenewalJob<C> {
+    pub fn new(client: C, journal: JournalServer, source_server: SourceServer, source_filter: Box<dyn OrderRenewalSourceFilter>, options: OrderRenewalOptions) -> Self {
+        Self(Arc::new(JobImpl {
+            client,
+            journal,
+            source_server,
+            source_filter,
+            options,
+            reputations: RwLock::new(HashMap::new())
+        }))
+    }
+
+    pub async fn update_reputations(&self, reputations: HashMap<String, DmcMinerReputation>) {
+        *self.0.reputations.write().await = reputations;
+    }
+
+    pub fn start(&self) {
+        let job = self.clone();
+        task::spawn(async move {
+            loop {
+                let now = chrono::Utc::now().timestamp() as u64;
+                if let Err(err) = job.check_once(now).await {
+                    error!("{} check failed, err={}", job, err);
+                }
+                task::sleep(Duration::from_secs(job.0.options.check_interval)).await;
+            }
+        });
+    }
+
+    // 每个source的所有order(新的在前), 以及已经记录过OrderStored的order;
+    // 下单流程只保证记录OrderCreated/OrderPrepared, OrderStored在检查时看到order进入Storing后补记
+    async fn source_orders(&self) -> DmcResult<(HashMap<u64, Vec<u64>>, HashSet<u64>)> {
+        let filter = JournalFilter {
+            event_type: Some(vec![JournalEventType::OrderCreated, JournalEventType::OrderPrepared, JournalEventType::OrderStored, JournalEventType::OrderRenewed]),
+            source_id: None,
+            order_id: None
+        };
+        let mut orders: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut stored_orders = HashSet::new();
+        for log in load_journal_logs(&self.0.journal, filter).await? {
+            if let Some(order_id) = log.event.order_id {
+                if log.event.event_type == JournalEventType::OrderStored {
+                    stored_orders.insert(order_id);
+                }
+                let source_orders = orders.entry(log.event.source_id).or_default();
+                if !source_orders.contains(&order_id) {
+                    source_orders.push(order_id);
+                }
+            }
+        }
+        for source_orders in orders.values_mut() {
+            source_orders.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        Ok((orders, stored_orders))
+    }
+
+    // 一个source检查失败(比如链上查询出错)只记录日志, 继续检查其它source
+    pub async fn check_once(&self, now: u64) -> DmcResult<()> {
+        let (orders, stored_orders) = self.source_orders().await?;
+        debug!("{} check, now={}, sources={}", self, now, orders.len());
+        for (source_id, order_ids) in orders {
+            if !self.0.source_filter.is_source_live(source_id) {
+                continue;
+            }
+            if let Err(err) = self.check_source(now, source_id, &order_ids, &stored_orders).await {
+                error!("{} check failed, source={}, err={}", self, source_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    // 从新到旧找source当前在存储的order: 取消的order跳过, 最新的order还在prepare时说明续约正在进行
+    async fn check_source(&self, now: u64, source_id: u64, order_ids: &[u64], stored_orders: &HashSet<u64>) -> DmcResult<()> {
+        for order_id in order_ids.iter().copied() {
+            let order = match self.0.client.get_order_by_id(order_id).await? {
+                Some(order) => order,
+                None => {
+                    warn!("{} check, source={}, order={}, err=order not found", self, source_id, order_id);
+                    continue;
+                }
+            };
+            match &order.state {
+                DmcOrderState::Canceled => continue,
+                DmcOrderState::Preparing { .. } => {
+                    debug!("{} check, source={}, order={}, preparing", self, source_id, order_id);
+                    return Ok(());
+                },
+                DmcOrderState::Storing(_) => {
+                    if !stored_orders.contains(&order_id) {
+                        let event = JournalEvent {
+                            source_id,
+                            order_id: Some(order_id),
+                            event_type: JournalEventType::OrderStored,
+                            event_params: None
+                        };
+                        let _ = self.0.journal.append(event).await?;
+                    }
+                    return self.check_order(now, source_id, &order).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_order(&self, now: u64, source_id: u64, order: &DmcOrder) -> DmcResult<()> {
+        let order_id = order.order_id;
+        let expire_at = order.expire_at();
+        match order_renewal_state(expire_at, now, self.0.options.lead_time) {
+            OrderRenewalState::Live => {},
+            OrderRenewalState::Expired => {
+                error!("{} check, source={}, order={}, err=expired at {} before renewal", self, source_id, order_id, expire_at);
+            },
+            OrderRenewalState::Due => {
+                if let Err(err) = self.renew(source_id, order).await {
+                    error!("{} renew failed, source={}, order={}, expire_at={}, err={}", self, source_id, order_id, expire_at, err);
+                    let mut event = JournalEvent {
+                        source_id,
+                        order_id: Some(order_id),
+                        event_type: JournalEventType::OrderRenewFailed,
+                        event_params: None
+                    };
+                    event.set_params(&OrderRenewFailedParams { expire_at, error: err.to_string() })?;
+                    let _ = self.0.journal.append(event).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn renew(&self, source_id: u64, order: &DmcOrder) -> DmcResult<u64> {
+        info!("{} renew, source={}, order={}, miner={}", self, source_id, order.order_id, order.miner);
+        let source = self.0.source_server.get(SourceFilter::from_source_id(source_id), SourceNavigator { page_size: 1, page_index: 0 }).await?
+            .pop().ok_or_else(|| dmc_err!(DmcErrorCode::NotFound, "{} renew, source={}, err=source not found", self, source_id))?;
+        let merkle_stub = source.merkle_stub.clone()
+            .ok_or_else(|| dmc_err!(DmcErrorCode::ErrorState, "{} renew, source={}, err=merkle not prepared", self, source_id))?;
+
+        let bills = self.0.client.get_bills(DmcBillFilter {
+            min_asset: dmc_asset_of_length(source.length, 1),
+            max_price: self.0.options.policy.max_price
+        }).await?;
+        let reputations = self.0.reputations.read().await.clone();
+        let same_miner: Vec<DmcBill> = bills.iter().filter(|bill| bill.miner == order.miner).cloned().collect();
+        let terms = select_dmc_bill(&same_miner, source.length, self.0.options.duration, &self.0.options.policy, &reputations)
+            .map_err(|err| dmc_err!(err.code(), "{} renew, source={}, miner={}, err=no acceptable bill from the current miner, data must be re-uploaded to another miner: {}",
+                self, source_id, order.miner, err))?;
+
+        let pending = self.0.client.create_order(terms.order_options()).await?;
+        let new_order = pending.wait().await?.result
+            .map_err(|err| dmc_err!(DmcErrorCode::Failed, "{} renew, source={}, terms={:?}, err=create order {}", self, source_id, terms, err))?;
+        self.0.client.prepare_order(DmcPrepareOrderOptions { order_id: new_order.order_id, merkle_stub }).await??;
+
+        let mut event = JournalEvent {
+            source_id,
+            order_id: Some(new_order.order_id),
+            event_type: JournalEventType::OrderRenewed,
+            event_params: None
+        };
+        event.set_params(&OrderRenewedParams { old_order_id: order.order_id, terms })?;
+        let _ = self.0.journal.append(event).await?;
+        info!("{} renew, source={}, order={}, finished, new_order={}", self, source_id, order.order_id, new_order.order_id);
+        Ok(new_order.order_id)
+    }
+}
+
+#[test]
+fn test_order_renewal_state() {
+    let order = DmcOrder {
+        order_id: 1,
+        bill_id: 1,
+        user: "user".to_owned(),
+        miner: "miner".to_owned(),
+        asset: 1,
+        duration: 2,
+        price: 1,
+        pledge_rate: 0,
+        state: DmcOrderState::Canceled,
+        start_at: 1000
+    };
+    let expire_at = order.expire_at();
+    assert_eq!(expire_at, 1000 + 2 * DMC_ORDER_DURATION_UNIT_SECS);
+    assert_eq!(order_renewal_state(expire_at, 1000, 3600), OrderRenewalState::Live);
+    assert_eq!(order_renewal_state(expire_at, expire_at - 3600, 3600), OrderRenewalState::Due);
+    assert_eq!(order_renewal_state(expire_at, expire_at, 3600), OrderRenewalState::Expired);
+}
@@ -0,0 +1,67 @@
+use log::*;
+use std::collections::HashMap;
+use dmc_tools_common::*;
+use crate::{
+    journal::*,
+    source::*
+};
+
+// 封装sector之前为source(一个checkpoint的数据)选择订单条款: 按plan的策略查询报价并选择miner,
+// 选中的条款作为OrderTermsSelected记录到source的journal中, 之后按条款下单
+pub async fn select_order_terms<C: DmcChainClient>(
+    client: &C,
+    journal: &JournalServer,
+    source: &DataSource,
+    duration: u32,
+    policy: &DmcOrderPolicy,
+    reputations: &HashMap<String, DmcMinerReputation>
+) -> DmcResult<DmcOrderTerms> {
+    info!("select order terms, source={}, length={}, duration={}, policy={:?}", source.source_id, source.length, duration, policy);
+    let filter = DmcBillFilter {
+        min_asset: dmc_asset_of_length(source.length, 1),
+        max_price: policy.max_price
+    };
+    let bills = client.get_bills(filter).await
+        .map_err(|err| dmc_err!(err.code(), "select order terms, source={}, err=get bills {}", source.source_id, err))?;
+    let terms = select_dmc_bill(&bills, source.length, duration, policy, reputations)
+        .map_err(|err| dmc_err!(err.code(), "select order terms, source={}, bills={}, err={}", source.source_id, bills.len(), err))?;
+
+    let mut event = JournalEvent {
+        source_id: source.source_id,
+        order_id: None,
+        event_type: JournalEventType::OrderTermsSelected,
+        event_params: None
+    };
+    event.set_params(&terms)?;
+    let _ = journal.append(event).await?;
+    info!("select order terms, source={}, finished, terms={:?}", source.source_id, terms);
+    Ok(terms)
+}
+
+// 分页读取filter匹配的所有journal
+pub async fn load_journal_logs(journal: &JournalServer, filter: JournalFilter) -> DmcResult<Vec<JournalLog>> {
+    let mut navigator = JournalNavigator { from_id: None, page_size: 64 };
+    let mut all_logs = vec![];
+    loop {
+        let logs = journal.get(filter.clone(), navigator.clone()).await?;
+        if logs.is_empty() {
+            break;
+        }
+        navigator.from_id = Some(logs.last().unwrap().log_id);
+        all_logs.extend(logs);
+    }
+    Ok(all_logs)
+}
+
+// source最后一次选中的订单条款
+pub async fn get_order_terms(journal: &JournalServer, source_id: u64) -> DmcResult<Option<DmcOrderTerms>> {
+    let filter = JournalFilter {
+        event_type: Some(vec![JournalEventType::OrderTermsSelected]),
+        source_id: Some(source_id),
+        order_id: None
+    };
+    match load_journal_logs(journal, filter).await?.pop() {
+        Some(log) => Ok(Some(log.event.get_params()?)),
+        None => Ok(None)
+    }
+}
@@ -208,6 +208,35 @@ impl SourceServer {
         });
 
 
+        http_server.at("/source/proof").get(|req: Request<T>| async move {
+            let mut source_id = None;
+            let mut offset = None;
+            let mut length = None;
+            for (key, value) in req.url().query_pairs() {
+                match &*key {
+                    "source_id" => {
+                        source_id = Some(u64::from_str_radix(&*value, 10)?);
+                    }, 
+                    "offset" => {
+                        offset = Some(u64::from_str_radix(&*value, 10)?);
+                    }, 
+                    "length" => {
+                        length = Some(u64::from_str_radix(&*value, 10)?);
+                    }, 
+                    _ => {}
+                }
+            }
+            match (source_id, offset, length) {
+                (Some(source_id), Some(offset), Some(length)) => {
+                    let proof = req.state().source_server().merkle_proof(source_id, offset, length).await?;
+                    let mut resp = Response::new(200);
+                    resp.set_body(Body::from_json(&proof)?);
+                    Ok(resp)
+                }, 
+                _ => Ok(Response::new(400))
+            }
+        });
+
         // http_server.at("/source/detail").get(|req: Request<T>| async move {
         //     let param = req.query::<QuerySource>()?;
 
@@ -635,6 +664,55 @@ impl SourceServer {
         }
     }
 
+    // 第三方审计用: 返回source中一段数据的每个piece到merkle root的路径, 验证时使用链上order的DmcMerkleStub,
+    // 见SourceMerkleProof::verify
+    pub async fn merkle_proof(&self, source_id: u64, offset: u64, length: u64) -> DmcResult<SourceMerkleProof> {
+        info!("{} merkle proof, source={}, offset={}, length={}", self, source_id, offset, length);
+        let row: SourceStateRow = sqlx::query_as("SELECT * FROM source_state WHERE source_id=?")
+            .bind(source_id as sqlx_types::U64).fetch_optional(self.sql_pool()).await
+            .map_err(|err| dmc_err!(DmcErrorCode::Failed, "{} merkle proof, source={}, err={}", self, source_id, err))?
+            .ok_or_else(|| dmc_err!(DmcErrorCode::NotFound, "{} merkle proof, source={}, err=not found", self, source_id))?;
+        let options: CreateSourceOptions = (&row).try_into()?;
+        let source: DataSource = row.try_into()?;
+        let merkle_stub = source.merkle_stub.clone()
+            .ok_or_else(|| dmc_err!(DmcErrorCode::ErrorState, "{} merkle proof, source={}, err=merkle not prepared", self, source_id))?;
+        if length == 0 || offset.checked_add(length).map_or(true, |end| end > source.length) {
+            return Err(dmc_err!(DmcErrorCode::InvalidParam, "{} merkle proof, source={}, err=range {}+{} out of length {}", self, source_id, offset, length, source.length));
+        }
+
+        let piece_size = options.merkle.piece_size as u64;
+        let pieces = offset / piece_size..(offset + length - 1) / piece_size + 1;
+        if pieces.end - pieces.start > SOURCE_MERKLE_PROOF_MAX_PIECES {
+            return Err(dmc_err!(DmcErrorCode::OutOfLimit, "{} merkle proof, source={}, err={} pieces exceed limit {}", self, source_id, pieces.end - pieces.start, SOURCE_MERKLE_PROOF_MAX_PIECES));
+        }
+
+        let mut row_stream = sqlx::query_as::<_, MerkleBlockStubRow>("SELECT * FROM merkle_stub WHERE source_id=? ORDER BY `index`")
+            .bind(source_id as sqlx_types::U64).fetch(self.sql_pool());
+        let mut block_roots = vec![];
+        while let Some(stub) = row_stream.next().await {
+            let stub: MerkleBlockStub = stub.map(|row| row.into()).map_err(|err| dmc_err!(DmcErrorCode::Failed, "{} merkle proof, source={}, err={}", self, source_id, err))?;
+            block_roots.push(HashValue::try_from(stub.content)?);
+        }
+
+        let (source_reader, source_length) = self.parse_source_url(&source.source_url).await?;
+        let merkle_proc = MerkleProc::new(source_length, options.merkle.piece_size, options.merkle.pieces_per_block, true);
+        if merkle_proc.leaves() != merkle_stub.leaves {
+            return Err(dmc_err!(DmcErrorCode::InvalidData, "{} merkle proof, source={}, err=source {} changed after prepare", self, source_id, source.source_url));
+        }
+        let mut source_reader = merkle_proc.wrap_reader(source_reader);
+        let pieces = merkle_proc.proof_of_pieces::<_, MerkleStubSha256>(pieces, &block_roots, &mut source_reader).await
+            .map_err(|err| dmc_err!(err.code(), "{} merkle proof, source={}, err={}", self, source_id, err))?;
+
+        Ok(SourceMerkleProof {
+            source_id, 
+            offset, 
+            length, 
+            merkle_stub, 
+            pieces_per_block: options.merkle.pieces_per_block, 
+            pieces
+        })
+    }
+
     pub async fn random_stub(&self, source_id: u64) -> DmcResult<SourceStub> {
         // let row: SourceStateRow = sqlx::query_as("SELECT * FROM source_state WHERE source_id=?")
         //     .bind(source_id as sqlx_types::U64).fetch_one(self.sql_pool()).await.map_err(|err| dmc_err!(DmcErrorCode::Failed, "{} random stub, source={}, err={}", self, source_id, err))?;
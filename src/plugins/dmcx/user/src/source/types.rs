@@ -72,6 +72,53 @@ pub struct MerkleBlockStub {
     pub content: Vec<u8>
 }
 
+// 一次证明最多包含的piece数, 更大的范围分多次请求
+pub const SOURCE_MERKLE_PROOF_MAX_PIECES: u64 = 1024;
+
+// source中[offset, offset + length)这段数据(比如checkpoint的一个chunk)到source merkle root的证明
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SourceMerkleProof {
+    pub source_id: u64, 
+    pub offset: u64, 
+    pub length: u64, 
+    pub merkle_stub: DmcMerkleStub, 
+    pub pieces_per_block: u16, 
+    pub pieces: Vec<MerklePieceProof>
+}
+
+impl SourceMerkleProof {
+    // onchain_stub是链上Storing状态的order中的DmcMerkleStub, 不使用证明中服务端给出的stub;
+    // 验证通过时返回这段数据, 审计方可以再和chunk的hash比对
+    pub fn verify(&self, onchain_stub: &DmcMerkleStub) -> DmcResult<Vec<u8>> {
+        if self.merkle_stub != *onchain_stub {
+            return Err(DmcError::new(DmcErrorCode::InvalidData, format!("merkle stub {:?} mismatch onchain stub {:?}", self.merkle_stub, onchain_stub)));
+        }
+        let piece_size = onchain_stub.piece_size as u64;
+        if self.length == 0 || piece_size == 0 {
+            return Err(DmcError::new(DmcErrorCode::InvalidData, "empty proof"));
+        }
+        let first_piece = self.offset / piece_size;
+        let last_piece = (self.offset + self.length - 1) / piece_size;
+        if self.pieces.len() as u64 != last_piece - first_piece + 1 {
+            return Err(DmcError::new(DmcErrorCode::InvalidData, format!("expect {} pieces, got {}", last_piece - first_piece + 1, self.pieces.len())));
+        }
+
+        let verifier = MerkleProc::verifier(onchain_stub.leaves, onchain_stub.piece_size, self.pieces_per_block);
+        let mut content = Vec::with_capacity(self.pieces.len() * piece_size as usize);
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if piece.piece_index != first_piece + i as u64 {
+                return Err(DmcError::new(DmcErrorCode::InvalidData, format!("expect piece {}, got {}", first_piece + i as u64, piece.piece_index)));
+            }
+            if !verifier.verify_piece_proof::<MerkleStubSha256>(&onchain_stub.root, piece) {
+                return Err(DmcError::new(DmcErrorCode::Unmatch, format!("piece {} proof verify failed", piece.piece_index)));
+            }
+            content.extend_from_slice(piece.piece_content.as_slice());
+        }
+        let start = (self.offset - first_piece * piece_size) as usize;
+        Ok(content[start..start + self.length as usize].to_vec())
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct DataSource {
     pub source_id: u64,  
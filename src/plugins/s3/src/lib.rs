@@ -1,7 +1,9 @@
 #![allow(dead_code)]
 use async_trait::async_trait;
 use aws_sdk_s3::error::SdkError;
-use buckyos_backup_lib::{IBackupChunkTargetProvider, BackupResult, BuckyBackupError};
+use buckyos_backup_lib::{IBackupChunkTargetProvider, BackupResult, BuckyBackupError, TargetCredential, TARGET_LAYOUT_MARKER_NAME};
+use buckyos_backup_lib::{TargetUrl, TargetLocation, ChunkKeyNamer, ChunkKeyMapManifest, CHUNK_KEY_MAP_NAME};
+use buckyos_backup_lib::{BackupProviderRegistry, BackupProviderDesc, BackupProviderKind, BackupProviderAbilities, BackupChunkTargetProvider, TargetProviderFuture};
 use ndn_lib::{ChunkId, ChunkReader, ChunkWriter};
 use anyhow::{Result, anyhow};
 use aws_sdk_s3::{Client, Config};
@@ -13,7 +15,9 @@ use std::future::Future;
 use std::task::{Context, Poll};
 use std::{collections::HashMap, pin::Pin};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, MetadataDirective};
+use aws_sdk_s3::primitives::ByteStream;
 use serde::{Serialize, Deserialize};
 use tokio::io::AsyncWrite;
 use futures::FutureExt;  
@@ -78,6 +82,40 @@ pub struct S3ChunkTarget {
     bucket: String,
     upload_states: Mutex<HashMap<String, MultipartUploadState>>, 
     url: String,
+    key_namer: Option<ChunkKeyNamer>,//url中chunk_naming=hmac时对象名是chunk_id的HMAC
+    key_map_written: AtomicBool,//bucket中已经有和当前命名方式一致的key map清单
+}
+
+//把s3 target注册到engine的provider registry
+pub fn register_s3_provider(registry: &mut BackupProviderRegistry) {
+    registry.register_target_provider(
+        BackupProviderDesc {
+            name: "s3_chunk_target".to_string(),
+            scheme: "s3".to_string(),
+            kind: BackupProviderKind::Target,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            plugin: None,
+            abilities: BackupProviderAbilities {
+                //multipart upload的状态只保存在内存中,中断后只能重新上传
+                supports_resume: false,
+                supports_ranged_read: true,
+                supports_link: true,
+                supports_checkpoint_status: false,
+                //multipart upload最多10000个part
+                max_chunk_size: Some(S3ChunkTarget::part_size() as u64 * 10000),
+                supports_chunk_naming: true,
+            },
+        },
+        std::sync::Arc::new(|target_url: String, credential: Option<TargetCredential>| Box::pin(async move {
+            let url = Url::parse(target_url.as_str())?;
+            let target = match credential {
+                Some(credential) => S3ChunkTarget::with_credential(url, &credential).await?,
+                None => S3ChunkTarget::with_url(url).await?,
+            };
+            target.check_chunk_key_map().await?;
+            Ok(Box::new(target) as BackupChunkTargetProvider)
+        }) as TargetProviderFuture),
+    );
 }
 
 impl S3ChunkTarget {
@@ -85,11 +123,18 @@ impl S3ChunkTarget {
         5 * 1024 * 1024
     }
 
+    //bucket名和region通过TargetUrl解析并检查
+    fn parse_s3_url(url: &Url) -> Result<(String, Option<String>)> {
+        let target_url = TargetUrl::parse(url.as_str())?;
+        match target_url.location() {
+            TargetLocation::S3 { bucket, region } => Ok((bucket.clone(), region.clone())),
+            _ => Err(anyhow!("{} is not an s3 url", target_url.redacted())),
+        }
+    }
+
     pub async fn with_url(url:Url) -> Result<Self> {
-        info!("new s3 chunk target, url: {}", url);
         // s3://bucket-name?region=region-name&access_key=xxx&secret_key=yyy
-        let bucket = url.host_str().unwrap_or_default().to_string();
-        let region = url.query_pairs().find(|(k, _)| k == "region").map(|(_, v)| v.to_string());
+        let (bucket, region) = Self::parse_s3_url(&url)?;
         let access_key = url.query_pairs().find(|(k, _)| k == "access_key").map(|(_, v)| v.to_string());
         let secret_key = url.query_pairs().find(|(k, _)| k == "secret_key").map(|(_, v)| v.to_string());
         let session_token = url.query_pairs().find(|(k, _)| k == "session_token").map(|(_, v)| v.to_string());
@@ -102,7 +147,33 @@ impl S3ChunkTarget {
                 session_token,
             }
         };
-        Self::with_session(bucket, region, account).await
+        let mut target = Self::with_session(bucket, region, account).await?;
+        target.key_namer = ChunkKeyNamer::from_url(url.as_str(), None)?;
+        Ok(target)
+    }
+
+    // s3://bucket-name?region=region-name&credential_id=xxx, 密钥从凭证库中解析,url中不包含密钥
+    pub async fn with_credential(url:Url, credential:&TargetCredential) -> Result<Self> {
+        let (bucket, region) = Self::parse_s3_url(&url)?;
+        let access_key = credential.get_secret("access_key");
+        let secret_key = credential.get_secret("secret_key");
+        let key_namer = ChunkKeyNamer::from_url(url.as_str(), Some(credential))?;
+        //凭证中只有chunk_naming_key时使用环境中的aws账号
+        let account = if access_key.is_none() && secret_key.is_none() && key_namer.is_some() {
+            S3AccountSession::Environment
+        } else if access_key.is_none() || secret_key.is_none() {
+            return Err(anyhow!("credential for {} has no access_key/secret_key", url));
+        } else {
+            S3AccountSession::AccessKey {
+                access_key_id: access_key.unwrap().clone(),
+                secret_access_key: secret_key.unwrap().clone(),
+                session_token: credential.get_secret("session_token").cloned(),
+            }
+        };
+        let mut target = Self::with_session(bucket, region, account).await?;
+        target.url = url.to_string();
+        target.key_namer = key_namer;
+        Ok(target)
     }
 
     pub async fn with_session(
@@ -166,8 +237,79 @@ impl S3ChunkTarget {
             upload_states: Mutex::new(HashMap::new()), 
             url: Url::parse_with_params(&format!("s3://{}", bucket), params).unwrap().to_string(),
             bucket,
+            key_namer: None,
+            key_map_written: AtomicBool::new(false),
         })
     }
+
+    //chunk在bucket中的对象名
+    fn object_key(&self, chunk_id: &ChunkId) -> String {
+        match self.key_namer.as_ref() {
+            Some(key_namer) => key_namer.object_key(chunk_id),
+            None => chunk_id.to_string(),
+        }
+    }
+
+    //link目标保存在对象的metadata中,hmac命名时加密保存
+    fn encode_link_target(&self, chunk_id: &ChunkId) -> String {
+        match self.key_namer.as_ref() {
+            Some(key_namer) => key_namer.seal_chunk_id(chunk_id),
+            None => chunk_id.to_string(),
+        }
+    }
+
+    fn decode_link_target(&self, link_target: &str) -> BackupResult<ChunkId> {
+        match self.key_namer.as_ref() {
+            Some(key_namer) => key_namer.open_chunk_id(link_target),
+            None => ChunkId::new(link_target)
+                .map_err(|e| BuckyBackupError::Failed(format!("invalid link target {}: {}", link_target, e))),
+        }
+    }
+
+    //bucket中已有key map清单时检查命名方式和密钥是否一致,没有时在第一次写入chunk前创建
+    async fn check_chunk_key_map(&self) -> Result<()> {
+        let response = match self.client.get_object()
+            .bucket(&self.bucket)
+            .key(CHUNK_KEY_MAP_NAME)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                if let SdkError::ServiceError(service_err) = &err {
+                    if service_err.raw().status().as_u16() == 404 {
+                        return Ok(());
+                    }
+                }
+                return Err(anyhow!("Failed to get chunk key map: {}", err));
+            }
+        };
+        let body = response.body.collect().await
+            .map_err(|e| anyhow!("Failed to read chunk key map: {}", e))?;
+        let manifest = String::from_utf8(body.into_bytes().to_vec())
+            .map_err(|e| anyhow!("Invalid chunk key map: {}", e))?;
+        ChunkKeyMapManifest::from_json_str(manifest.as_str())?
+            .check(format!("s3://{}", self.bucket).as_str(), self.key_namer.as_ref())?;
+        self.key_map_written.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn ensure_chunk_key_map(&self) -> BackupResult<()> {
+        if self.key_map_written.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let manifest = ChunkKeyMapManifest::new(self.key_namer.as_ref(), now);
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(CHUNK_KEY_MAP_NAME)
+            .body(ByteStream::from(manifest.to_json_string().into_bytes()))
+            .send()
+            .await
+            .map_err(|e| BuckyBackupError::TryLater(format!("Failed to put chunk key map: {}", e)))?;
+        self.key_map_written.store(true, Ordering::SeqCst);
+        Ok(())
+    }
 }
 
 
@@ -457,7 +599,7 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
     }
 
     async fn is_chunk_exist(&self, chunk_id: &ChunkId) -> Result<(bool, u64)> {
-        let key = chunk_id.to_string();
+        let key = self.object_key(chunk_id);
         
         match self.client.head_object()
             .bucket(&self.bucket)
@@ -485,8 +627,9 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
 
     async fn link_chunkid(&self, target_chunk_id: &ChunkId, new_chunk_id: &ChunkId) -> BackupResult<()> {
         info!("link chunkid, target_chunk_id: {}, new_chunk_id: {}", target_chunk_id.to_string(), new_chunk_id.to_string());
-        let target_key = target_chunk_id.to_string();
-        let new_key = new_chunk_id.to_string();
+        self.ensure_chunk_key_map().await?;
+        let target_key = self.object_key(target_chunk_id);
+        let new_key = self.object_key(new_chunk_id);
 
         // 先获取源对象的元数据
         let head = self.client
@@ -500,7 +643,7 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
         // 构建新的元数据
         let metadata = head.metadata().cloned().unwrap_or_default();
         let mut target_metadata = metadata.clone();
-        target_metadata.insert("link_target".to_string(), new_key.clone());
+        target_metadata.insert("link_target".to_string(), self.encode_link_target(new_chunk_id));
 
         // 更新源对象的元数据
         self.client
@@ -516,7 +659,7 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
 
 
         let mut new_metadata = metadata;
-        new_metadata.insert("link_target".to_string(), target_key.clone());
+        new_metadata.insert("link_target".to_string(), self.encode_link_target(target_chunk_id));
         // 复制对象并创建新的链接
         self.client
             .copy_object()
@@ -533,7 +676,7 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
     }
 
     async fn query_link_target(&self, source_chunk_id: &ChunkId)->BackupResult<Option<ChunkId>> {
-        let key = source_chunk_id.to_string();
+        let key = self.object_key(source_chunk_id);
         let head = self.client
             .head_object()
             .bucket(&self.bucket)
@@ -541,13 +684,15 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
             .send()
             .await
             .map_err(|e| BuckyBackupError::Failed(format!("Failed to get object head: {}", e)))?;
-        Ok(head.metadata().and_then(|metadata| metadata.get("link_target"))
-            .map(|target_key| ChunkId::new(target_key).unwrap()))
+        match head.metadata().and_then(|metadata| metadata.get("link_target")) {
+            Some(link_target) => Ok(Some(self.decode_link_target(link_target.as_str())?)),
+            None => Ok(None),
+        }
     }
 
     async fn open_chunk_reader_for_restore(&self, chunk_id: &ChunkId, offset:u64) -> BackupResult<ChunkReader> {
         info!("open chunk reader for restore, chunk_id: {}, offset: {}", chunk_id.to_string(), offset);
-        let key = chunk_id.to_string();
+        let key = self.object_key(chunk_id);
         
         let head = self.client
             .head_object()
@@ -582,7 +727,8 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
 
     async fn open_chunk_writer(&self, chunk_id: &ChunkId, _offset: u64, size: u64) -> BackupResult<(ChunkWriter,u64)> {
         info!("open chunk writer, chunk_id: {}, offset: {}, size: {}", chunk_id.to_string(), _offset, size);
-        let key = chunk_id.to_string();
+        self.ensure_chunk_key_map().await?;
+        let key = self.object_key(chunk_id);
         
         {
             // 先检查是否已有进行中的上传
@@ -723,7 +869,7 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
 
     async fn complete_chunk_writer(&self, chunk_id: &ChunkId) -> BackupResult<()> {
         info!("complete chunk writer, chunk_id: {}", chunk_id.to_string());
-        let key = chunk_id.to_string();
+        let key = self.object_key(chunk_id);
 
         // get and remove upload id in states
         if let Some(upload_id) = {
@@ -781,4 +927,52 @@ impl IBackupChunkTargetProvider for S3ChunkTarget {
             return Err(BuckyBackupError::Failed("No upload ID found".to_string()));
         }
     }
+
+    //link_chunkid复制了对象,两个chunk的对象可以分别删除;delete_object对不存在的key也返回成功
+    async fn remove_chunk(&self, chunk_id: &ChunkId) -> BackupResult<()> {
+        let key = self.object_key(chunk_id);
+        self.client.delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| BuckyBackupError::TryLater(format!("Failed to delete object {}: {}", key, e)))?;
+        Ok(())
+    }
+
+    //标记保存在bucket根目录的对象中
+    async fn read_layout_marker(&self) -> BackupResult<Option<String>> {
+        let response = match self.client.get_object()
+            .bucket(&self.bucket)
+            .key(TARGET_LAYOUT_MARKER_NAME)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                if let SdkError::ServiceError(service_err) = &err {
+                    if service_err.raw().status().as_u16() == 404 {
+                        return Ok(None);
+                    }
+                }
+                return Err(BuckyBackupError::TryLater(format!("Failed to get layout marker: {}", err)));
+            }
+        };
+        let body = response.body.collect().await
+            .map_err(|e| BuckyBackupError::TryLater(format!("Failed to read layout marker: {}", e)))?;
+        let marker = String::from_utf8(body.into_bytes().to_vec())
+            .map_err(|e| BuckyBackupError::Failed(format!("Invalid layout marker: {}", e)))?;
+        Ok(Some(marker))
+    }
+
+    async fn write_layout_marker(&self, marker: &str) -> BackupResult<()> {
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(TARGET_LAYOUT_MARKER_NAME)
+            .body(ByteStream::from(marker.as_bytes().to_vec()))
+            .send()
+            .await
+            .map_err(|e| BuckyBackupError::Failed(format!("Failed to put layout marker: {}", e)))?;
+        Ok(())
+    }
 } 
\ No newline at end of file
@@ -47,7 +47,7 @@ export class BSTaskPanel extends LitElement {
             case 'RUNNING':
                 this.task_state = "upload";
                 break;
-            case 'PENDING':
+            case 'QUEUED':
                 this.task_state = "hourglass";
                 break;
             case 'PAUSED':
@@ -59,6 +59,9 @@ export class BSTaskPanel extends LitElement {
             case 'FAILED':
                 this.task_state = "x-circle";
                 break;
+            case 'CANCELLED':
+                this.task_state = "slash-circle";
+                break;
         }
         let now = Date.now();
         if(this.last_update_task_info) {
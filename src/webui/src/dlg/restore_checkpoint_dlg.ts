@@ -100,11 +100,16 @@ class CreatePlanDlg extends LitElement {
                 checkpointCombo.innerHTML = '';
                 let task_ids = await taskManager.listBackupTasks('done') as string[];
                 let tasks = await Promise.all(task_ids.map(task_id => taskManager.getTaskInfo(task_id)));
+                let pinned_checkpoints = await taskManager.listPinnedCheckpoints();
                 tasks.filter(task => task.state == "DONE" && task.owner_plan_id == plan_id).forEach(task => {
                     console.log("checkpoint_id:", task.checkpoint_id, "task:", task);
                     const option = document.createElement('sl-option');
                     option.value = task.checkpoint_id;
                     option.textContent = new Date(task.update_time).toLocaleString();
+                    const pinned = pinned_checkpoints.find(checkpoint => checkpoint.checkpoint_id == task.checkpoint_id);
+                    if (pinned) {
+                        option.textContent += pinned.pin_reason ? ` (pinned: ${pinned.pin_reason})` : " (pinned)";
+                    }
                     checkpointCombo.appendChild(option);
                 });
             } catch (error) {
@@ -7,7 +7,7 @@ export interface TaskInfo {
     checkpoint_id: string;
     total_size: number;
     completed_size: number;
-    state: 'RUNNING' | 'PENDING' | 'PAUSED' | 'DONE' | 'FAILED';
+    state: 'RUNNING' | 'QUEUED' | 'PAUSED' | 'DONE' | 'FAILED' | 'CANCELLED';
     create_time: number;//unix timestamp 
     update_time: number;//unix timestamp 
     item_count: number;
@@ -26,7 +26,77 @@ export interface BackupPlanInfo {
     target: string;
 }
 
-export type TaskFilter = "all" | "running" | "paused" | "failed" | "done";
+//恢复路径上已经存在同名文件时的处理策略
+export type RestoreConflictPolicy = "overwrite" | "skip" | "keep_both" | "only_newer";
+
+export interface RestoreConflictDecision {
+    item_id: string;
+    restore_item_id: string | null;
+    action: "overwrite" | "skip" | "keep_both";
+    reason: string;
+}
+
+//被pin的checkpoint不会被prune/保留策略删除
+export interface PinnedCheckpointInfo {
+    checkpoint_id: string;
+    plan_id: string;
+    checkpoint_index: number;
+    create_time: number;//unix timestamp
+    pin_reason: string | null;
+}
+
+//checkpoint被保留的原因,rules为空的checkpoint会在下次备份完成后删除
+export type RetentionRule = "last" | "daily" | "weekly" | "monthly" | "yearly" | "pinned" | "dependency";
+
+export interface CheckpointRetentionInfo {
+    checkpoint_id: string;
+    rules: RetentionRule[];
+}
+
+export interface CheckpointReconcileReport {
+    checkpoint_id: string;
+    old_state: string;
+    new_state: string;
+    checked_chunk_count: number;
+    requeued_items: string[];//target上缺少chunk,需要重新上传的item
+    completed_items: string[];//target上已经有chunk,直接完成的item
+    unverified_items: string[];//没有chunk_id,无法和target比较的item
+}
+
+//备份前的预估,upload_size是去重后预计需要上传的大小
+export interface BackupEstimate {
+    item_count: number;
+    total_size: number;
+    changed_item_count: number;
+    upload_size: number;
+    base_checkpoint_id: string | null;
+    speed: number;//bytes/s
+    is_speed_measured: boolean;
+    estimated_duration_secs: number;
+}
+
+export interface ChangeHeatmapEntry {
+    dir: string;//根目录为""
+    changed_size: number;
+    changed_item_count: number;
+    deleted_item_count: number;
+    checkpoint_count: number;
+}
+
+export interface DedupStat {
+    logical_size: number;//不去重时需要存储的大小
+    stored_size: number;//实际写入target的大小
+    saved_size: number;
+    dedup_ratio: number;
+    checkpoint_count: number;
+}
+
+export interface DedupStats {
+    plans: { [planId: string]: DedupStat };
+    targets: { [targetUrl: string]: DedupStat };
+}
+
+export type TaskFilter = "all" | "running" | "queued" | "paused" | "failed" | "cancelled" | "done";
 
 export class BackupTaskManager {
     private rpc_client: any;
@@ -90,6 +160,37 @@ export class BackupTaskManager {
         return result;
     }
 
+    async estimateBackup(planId: string): Promise<BackupEstimate> {
+        const result = await this.rpc_client.call("estimate_backup", {
+            plan_id: planId
+        });
+        return result.estimate;
+    }
+
+    async getChangeHeatmap(planId: string, sinceTime?: number, depth?: number): Promise<ChangeHeatmapEntry[]> {
+        const params: any = { plan_id: planId };
+        if (sinceTime !== undefined) {
+            params.since_time = sinceTime;
+        }
+        if (depth !== undefined) {
+            params.depth = depth;
+        }
+        const result = await this.rpc_client.call("get_change_heatmap", params);
+        return result.heatmap;
+    }
+
+    async getDedupStats(planId?: string, targetUrl?: string): Promise<DedupStats> {
+        const params: any = {};
+        if (planId) {
+            params.plan_id = planId;
+        }
+        if (targetUrl) {
+            params.target = targetUrl;
+        }
+        const result = await this.rpc_client.call("get_dedup_stats", params);
+        return result.dedup_stats;
+    }
+
     async createBackupTask(planId: string, parentCheckpointId: string | null) {
         const params: any = { plan_id: planId };
         if (parentCheckpointId) {
@@ -99,16 +200,56 @@ export class BackupTaskManager {
         return result;
     }
 
-    async createRestoreTask(planId: string, checkpointId: string, targetLocationUrl: string, is_clean_folder?: boolean) {
+    async createRestoreTask(planId: string, checkpointId: string, targetLocationUrl: string, is_clean_folder?: boolean,
+        conflict_policy?: RestoreConflictPolicy) {
         const params: any = { plan_id: planId, checkpoint_id: checkpointId, cfg: {
             restore_location_url: targetLocationUrl,
-            is_clean_restore: is_clean_folder
+            is_clean_restore: is_clean_folder,
+            conflict_policy: conflict_policy
         } };
 
         const result = await this.rpc_client.call("create_restore_task", params);
         return result;
     }
 
+    //恢复到plan的source目录,没有指定冲突策略时保留已有的文件
+    async createRestoreInPlaceTask(planId: string, checkpointId: string, conflict_policy?: RestoreConflictPolicy) {
+        const params: any = { plan_id: planId, checkpoint_id: checkpointId, cfg: {
+            restore_location_url: "",
+            is_clean_restore: false,
+            restore_in_place: true,
+            conflict_policy: conflict_policy
+        } };
+
+        const result = await this.rpc_client.call("create_restore_task", params);
+        return result;
+    }
+
+    //把checkpoint中的一个文件恢复到dest目录,返回恢复后的文件路径
+    async restoreSingleItem(checkpointId: string, itemPath: string, dest: string): Promise<string> {
+        const result = await this.rpc_client.call("restore_single_item", {
+            checkpoint_id: checkpointId,
+            item_path: itemPath,
+            dest: dest
+        });
+        return result.path;
+    }
+
+    //直接下载checkpoint中的一个文件
+    async downloadSingleItem(checkpointId: string, itemPath: string): Promise<Blob> {
+        const result = await this.rpc_client.call("restore_single_item", {
+            checkpoint_id: checkpointId,
+            item_path: itemPath,
+            download: true
+        });
+        const binary = atob(result.content);
+        const bytes = new Uint8Array(binary.length);
+        for (let i = 0; i < binary.length; i++) {
+            bytes[i] = binary.charCodeAt(i);
+        }
+        return new Blob([bytes]);
+    }
+
     async listBackupTasks(filter: TaskFilter = "all") {
         const result = await this.rpc_client.call("list_backup_task", {
             filter: filter
@@ -138,6 +279,48 @@ export class BackupTaskManager {
         return result.result === "success";
     }
 
+    async cancelBackupTask(taskId: string) {
+        const result = await this.rpc_client.call("cancel_backup_task", {
+            taskid: taskId
+        });
+        return result.result === "success";
+    }
+
+    async getRestoreConflicts(taskId: string): Promise<RestoreConflictDecision[]> {
+        const result = await this.rpc_client.call("get_restore_conflicts", {
+            taskid: taskId
+        });
+        return result.conflicts;
+    }
+
+    async reconcileCheckpoint(checkpointId: string): Promise<CheckpointReconcileReport> {
+        const result = await this.rpc_client.call("reconcile_checkpoint", {
+            checkpoint_id: checkpointId
+        });
+        return result.report;
+    }
+
+    async pinCheckpoint(checkpointId: string, pinned: boolean, reason?: string) {
+        const result = await this.rpc_client.call("pin_checkpoint", {
+            checkpoint_id: checkpointId,
+            pinned: pinned,
+            reason: reason
+        });
+        return result;
+    }
+
+    async listPinnedCheckpoints(): Promise<PinnedCheckpointInfo[]> {
+        const result = await this.rpc_client.call("list_pinned_checkpoints", {});
+        return result.checkpoints;
+    }
+
+    async getPlanRetention(planId: string): Promise<CheckpointRetentionInfo[]> {
+        const result = await this.rpc_client.call("get_plan_retention", {
+            plan_id: planId
+        });
+        return result.checkpoints;
+    }
+
     async validatePath(path: string) {
         const result = await this.rpc_client.call("validate_path", {
             path: path